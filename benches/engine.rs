@@ -0,0 +1,73 @@
+//! Throughput of the engine on a synthetic input. Compare the hashers by running it with and
+//! without the `fast-hash` feature:
+//!
+//! ```text
+//! cargo bench --bench engine -- --save-baseline siphash
+//! cargo bench --bench engine --features fast-hash -- --baseline siphash
+//! ```
+
+use criterion::{ criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput };
+use rust_decimal::Decimal;
+use transaction_engine::{ ClientId, Engine, MemoryStore, Transaction, TransactionType };
+
+const CLIENTS: u32 = 10_000;
+
+fn client_of(tx_id: u32) -> ClientId {
+    (tx_id % CLIENTS) as ClientId
+}
+
+// Deposits spread over the clients, one in ten transactions a withdrawal and one in ten a dispute
+// of the deposit just before it
+fn input(txs: u32) -> Vec<Transaction> {
+    (1..=txs)
+        .map(|tx_id| match tx_id % 10 {
+            3 => Transaction::new(client_of(tx_id), tx_id, TransactionType::Withdrawal(Decimal::ONE)),
+            7 => Transaction::new(client_of(tx_id - 1), tx_id - 1, TransactionType::Dispute),
+            _ => Transaction::new(client_of(tx_id), tx_id, TransactionType::Deposit(Decimal::new(1050, 2))),
+        })
+        .collect()
+}
+
+fn run(mut engine: Engine, input: Vec<Transaction>) -> Engine {
+    for tx in input {
+        let _ = engine.add_transaction(tx);
+    }
+
+    engine
+}
+
+fn bench_engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine");
+    group.sample_size(10);
+
+    for txs in [100_000, 1_000_000] {
+        let input = input(txs);
+
+        group.throughput(Throughput::Elements(txs as u64));
+
+        group.bench_with_input(BenchmarkId::new("grow", txs), &input, |b, input| {
+            b.iter_batched(
+                || input.clone(),
+                |input| run(Engine::new(), input),
+                BatchSize::LargeInput
+            );
+        });
+
+        group.bench_with_input(BenchmarkId::new("presized", txs), &input, |b, input| {
+            b.iter_batched(
+                || input.clone(),
+                |input| {
+                    let store = MemoryStore::with_capacity(CLIENTS as usize, txs as usize);
+
+                    run(Engine::builder().build_with_store(store), input)
+                },
+                BatchSize::LargeInput
+            );
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_engine);
+criterion_main!(benches);
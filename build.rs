@@ -0,0 +1,33 @@
+fn main() {
+    // The service is described in Rust rather than compiled from proto/engine.proto, so building
+    // doesn't need protoc. The messages are declared in src/grpc.rs.
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{ Builder, Method, Service };
+
+        let method = |name: &str, route: &str, input: &str, output: &str| {
+            Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("crate::grpc::{}", input))
+                .output_type(format!("crate::grpc::{}", output))
+                .codec_path("tonic_prost::ProstCodec")
+        };
+
+        let service = Service::builder()
+            .name("Engine")
+            .package("transaction_engine")
+            .method(
+                method("submit_transactions", "SubmitTransactions", "TransactionRequest", "SubmitSummary")
+                    .client_streaming()
+                    .build()
+            )
+            .method(
+                method("submit_transaction", "SubmitTransaction", "TransactionRequest", "TransactionReceipt").build()
+            )
+            .method(method("get_account", "GetAccount", "GetAccountRequest", "AccountReply").build())
+            .build();
+
+        Builder::new().compile(&[service]);
+    }
+}
@@ -0,0 +1,318 @@
+use std::{ collections::{ HashMap, HashSet, VecDeque }, fmt, io, str::FromStr };
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{ observer::LifecycleEvent, types::{ custom_serde, Account, ClientId } };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertRule {
+    HeldAbove(Decimal),
+    AvailableBelow(Decimal),
+    TotalAbove(Decimal),
+    Locked,
+    /// The total moved up or down by more than the amount within the window, in seconds of the
+    /// engine clock
+    TotalChange { above: Decimal, window: u64 },
+}
+
+impl AlertRule {
+    /// Whether the account matches the rule, `totals` being the totals it had by engine clock time
+    /// within the largest window of the rules, after the one it had when that window started, and
+    /// the current one last.
+    pub fn matches(&self, account: &Account, totals: &VecDeque<(u64, Decimal)>) -> bool {
+        match self {
+            AlertRule::HeldAbove(threshold) => account.held > *threshold,
+            AlertRule::AvailableBelow(threshold) => account.available < *threshold,
+            AlertRule::TotalAbove(threshold) => account.total > *threshold,
+            AlertRule::Locked => account.locked,
+            AlertRule::TotalChange { above, window } => {
+                let Some((now, _)) = totals.back() else {
+                    return false;
+                };
+
+                let start = now.saturating_sub(*window);
+                let first = totals.iter().rposition(|(at, _)| *at <= start).unwrap_or(0);
+
+                totals.range(first..).any(|(_, total)| (account.total - total).abs() > *above)
+            }
+        }
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertRule::HeldAbove(threshold) => write!(f, "held>{}", threshold),
+            AlertRule::AvailableBelow(threshold) => write!(f, "available<{}", threshold),
+            AlertRule::TotalAbove(threshold) => write!(f, "total>{}", threshold),
+            AlertRule::Locked => f.write_str("locked"),
+            AlertRule::TotalChange { above, window } => write!(f, "change>{}/{}", above, window),
+        }
+    }
+}
+
+impl FromStr for AlertRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let threshold = |value: &str| {
+            value
+                .trim()
+                .parse::<Decimal>()
+                .map_err(|err| format!("invalid threshold {}: {}", value, err))
+        };
+
+        match s.trim() {
+            "locked" => Ok(AlertRule::Locked),
+            rule => {
+                if let Some(value) = rule.strip_prefix("held>") {
+                    Ok(AlertRule::HeldAbove(threshold(value)?))
+                } else if let Some(value) = rule.strip_prefix("available<") {
+                    Ok(AlertRule::AvailableBelow(threshold(value)?))
+                } else if let Some(value) = rule.strip_prefix("total>") {
+                    Ok(AlertRule::TotalAbove(threshold(value)?))
+                } else if let Some(value) = rule.strip_prefix("change>") {
+                    let (above, window) = value
+                        .split_once('/')
+                        .ok_or_else(|| format!("invalid alert rule {}, expected change>AMOUNT/SECONDS", s))?;
+                    let window = window
+                        .trim()
+                        .parse::<u64>()
+                        .map_err(|err| format!("invalid window {}: {}", window, err))?;
+
+                    Ok(AlertRule::TotalChange { above: threshold(above)?, window })
+                } else {
+                    Err(
+                        format!(
+                            "unknown alert rule {}, expected held>AMOUNT, available<AMOUNT, total>AMOUNT, \
+                             change>AMOUNT/SECONDS or locked",
+                            s
+                        )
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// An alert raised during a run, a row of the alerts report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RaisedAlert {
+    pub client: ClientId,
+    pub tx: u32,
+    pub rule: String,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub available: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub held: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub total: Decimal,
+    /// The engine clock when it was raised, when it had a time
+    pub raised_at: Option<u64>,
+    /// Whether the account still matched the rule at the end of the run
+    pub active: bool,
+}
+
+// Raises an alert when an account starts matching a rule. It's raised again only after the
+// account stopped matching it in between.
+#[derive(Debug, Clone, Default)]
+pub struct AlertMonitor {
+    rules: Vec<AlertRule>,
+    active: HashSet<(usize, ClientId)>,
+    // The totals of every client within the largest change window, only kept with a change rule
+    totals: HashMap<ClientId, VecDeque<(u64, Decimal)>>,
+    // Every alert raised, with the index of its rule
+    raised: Vec<(usize, RaisedAlert)>,
+}
+
+impl AlertMonitor {
+    pub fn add_rule(&mut self, rule: AlertRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // The largest change window of the rules, `None` without any
+    fn window(&self) -> Option<u64> {
+        self.rules
+            .iter()
+            .filter_map(|rule| match rule {
+                AlertRule::TotalChange { window, .. } => Some(*window),
+                _ => None,
+            })
+            .max()
+    }
+
+    /// The alerts the account raised once a transaction changed it. Without a clock time there's
+    /// no window to measure, the change rules don't match. A change is measured from the totals
+    /// the monitor saw, the total of an account before its first transaction of the run isn't known.
+    pub fn check(&mut self, account: &Account, tx_id: u32, now: Option<u64>) -> Vec<LifecycleEvent> {
+        let empty = VecDeque::new();
+        let totals = match self.window().zip(now) {
+            Some((window, now)) => {
+                let totals = self.totals.entry(account.client_id).or_default();
+                // A clock going back, with late timestamps, doesn't reorder the totals
+                let now = totals.back().map_or(now, |(at, _)| now.max(*at));
+                let start = now.saturating_sub(window);
+
+                // Keeps the last total before the window, the one the account had when it started
+                while totals.len() > 1 && totals[1].0 <= start {
+                    totals.pop_front();
+                }
+                totals.push_back((now, account.total));
+
+                &*totals
+            }
+            None => &empty,
+        };
+
+        let mut events = vec![];
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            let key = (index, account.client_id);
+
+            if !rule.matches(account, totals) {
+                self.active.remove(&key);
+            } else if self.active.insert(key) {
+                events.push(LifecycleEvent::Alert {
+                    client_id: account.client_id,
+                    tx_id,
+                    rule: rule.to_string(),
+                    available: account.available,
+                    held: account.held,
+                });
+                self.raised.push((index, RaisedAlert {
+                    client: account.client_id,
+                    tx: tx_id,
+                    rule: rule.to_string(),
+                    available: account.available,
+                    held: account.held,
+                    total: account.total,
+                    raised_at: now,
+                    active: true,
+                }));
+            }
+        }
+
+        events
+    }
+
+    pub fn clear(&mut self, client_id: ClientId) {
+        self.active.retain(|(_, active)| *active != client_id);
+        self.totals.remove(&client_id);
+    }
+
+    /// Every alert raised so far in order. The last one of a rule and client is active when the
+    /// account still matches the rule, the ones before it ended already.
+    pub fn report(&self) -> Vec<RaisedAlert> {
+        let mut latest = HashSet::new();
+
+        let mut report: Vec<RaisedAlert> = self.raised
+            .iter()
+            .rev()
+            .map(|(index, alert)| {
+                let key = (*index, alert.client);
+
+                RaisedAlert { active: latest.insert(key) && self.active.contains(&key), ..alert.clone() }
+            })
+            .collect();
+        report.reverse();
+
+        report
+    }
+}
+
+pub fn write_report<W: io::Write>(alerts: &[RaisedAlert], writer: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for alert in alerts {
+        writer.serialize(alert)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!("held>100".parse(), Ok(AlertRule::HeldAbove(dec!(100))));
+        assert_eq!("available<0".parse(), Ok(AlertRule::AvailableBelow(dec!(0))));
+        assert_eq!("total>1.5".parse(), Ok(AlertRule::TotalAbove(dec!(1.5))));
+        assert_eq!("locked".parse(), Ok(AlertRule::Locked));
+        assert!("held<1".parse::<AlertRule>().is_err());
+        assert!("held>x".parse::<AlertRule>().is_err());
+        assert_eq!("change>1000/3600".parse(), Ok(AlertRule::TotalChange { above: dec!(1000), window: 3600 }));
+        assert!("change>1000".parse::<AlertRule>().is_err());
+        assert!("change>1000/1h".parse::<AlertRule>().is_err());
+
+        assert_eq!(AlertRule::AvailableBelow(dec!(-5)).to_string(), "available<-5");
+        assert_eq!(AlertRule::TotalChange { above: dec!(10), window: 60 }.to_string(), "change>10/60");
+    }
+
+    #[test]
+    fn test_alert_on_crossing_only() {
+        let mut monitor = AlertMonitor::default();
+        monitor.add_rule(AlertRule::HeldAbove(dec!(10)));
+
+        let mut account = Account::new(1);
+        assert!(monitor.check(&account, 1, None).is_empty());
+
+        account.held = dec!(11);
+        assert_eq!(monitor.check(&account, 2, None), vec![LifecycleEvent::Alert {
+            client_id: 1,
+            tx_id: 2,
+            rule: "held>10".to_string(),
+            available: dec!(0),
+            held: dec!(11),
+        }]);
+        assert!(monitor.check(&account, 3, None).is_empty());
+
+        account.held = dec!(5);
+        assert!(monitor.check(&account, 4, None).is_empty());
+
+        account.held = dec!(20);
+        assert_eq!(monitor.check(&account, 5, None).len(), 1);
+    }
+
+    #[test]
+    fn test_total_change() {
+        let mut monitor = AlertMonitor::default();
+        monitor.add_rule(AlertRule::TotalChange { above: dec!(10), window: 60 });
+
+        let account = |total| Account { available: total, total, ..Account::new(1) };
+
+        assert!(monitor.check(&account(dec!(5)), 1, Some(0)).is_empty());
+        // Without a clock time there's no window
+        assert!(monitor.check(&account(dec!(50)), 2, None).is_empty());
+        assert!(monitor.check(&account(dec!(14)), 3, Some(30)).is_empty());
+        // A drop counts as well
+        assert_eq!(monitor.check(&account(dec!(-6)), 4, Some(50)).len(), 1);
+        // Still moved by more than 10 within the last minute, not raised again
+        assert!(monitor.check(&account(dec!(-6)), 5, Some(80)).is_empty());
+        // Of the totals before the window, only the one in effect when it started is kept
+        assert!(monitor.check(&account(dec!(-6)), 6, Some(200)).is_empty());
+        assert_eq!(monitor.check(&account(dec!(10)), 7, Some(210)).len(), 1);
+
+        let mut output = vec![];
+        write_report(&monitor.report(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "client,tx,rule,available,held,total,raised_at,active\n",
+                "1,4,change>10/60,-6,0,-6,50,false\n",
+                "1,7,change>10/60,10,0,10,210,true\n"
+            )
+        );
+    }
+}
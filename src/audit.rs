@@ -0,0 +1,218 @@
+use std::{
+    fs::{ self, File },
+    io::{ self, BufWriter, Write },
+    path::{ Path, PathBuf },
+};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{ reason::ReasonCode, types::{ custom_serde, Account, ClientId, Transaction } };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Applied,
+    Rejected,
+}
+
+/// What happened to a transaction, with the balances of its client right after it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+    pub tx: u32,
+    pub client: ClientId,
+    #[serde(rename = "type")]
+    pub tx_type: &'static str,
+    #[serde(serialize_with = "custom_serde::serialize_optional_decimal")]
+    pub amount: Option<Decimal>,
+    pub outcome: Outcome,
+    pub reason: Option<ReasonCode>,
+    #[serde(serialize_with = "custom_serde::serialize_optional_decimal")]
+    pub available: Option<Decimal>,
+    #[serde(serialize_with = "custom_serde::serialize_optional_decimal")]
+    pub held: Option<Decimal>,
+    #[serde(serialize_with = "custom_serde::serialize_optional_decimal")]
+    pub total: Option<Decimal>,
+    pub locked: Option<bool>,
+    /// The timestamp of the transaction
+    pub timestamp: Option<u64>,
+    /// The time of the engine clock once the transaction was done
+    pub recorded_at: Option<u64>,
+    /// The rules of the alerts the transaction raised
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<String>,
+}
+
+impl AuditRecord {
+    /// The balances are left out when the client has no account, e.g. its first deposit was rejected.
+    pub fn new(tx: &Transaction, reason: Option<ReasonCode>, account: Option<&Account>, now: Option<u64>) -> Self {
+        AuditRecord {
+            tx: tx.tx_id,
+            client: tx.client_id,
+            tx_type: tx.tx_type.name(),
+            amount: tx.tx_type.amount(),
+            outcome: match reason {
+                Some(_) => Outcome::Rejected,
+                None => Outcome::Applied,
+            },
+            reason,
+            available: account.map(|account| account.available),
+            held: account.map(|account| account.held),
+            total: account.map(|account| account.total),
+            locked: account.map(|account| account.locked),
+            timestamp: tx.timestamp,
+            recorded_at: now,
+            alerts: vec![],
+        }
+    }
+}
+
+/// Receives a record for every transaction given to an engine, applied or rejected, in order.
+pub trait AuditSink: Send {
+    fn record(&mut self, record: &AuditRecord);
+}
+
+/// Writes every record as a line of JSON, in a single write.
+pub struct AuditWriter<W: io::Write + Send> {
+    writer: W,
+}
+
+impl<W: io::Write + Send> AuditWriter<W> {
+    pub fn new(writer: W) -> Self {
+        AuditWriter { writer }
+    }
+}
+
+impl<W: io::Write + Send> AuditSink for AuditWriter<W> {
+    fn record(&mut self, record: &AuditRecord) {
+        let result = serde_json::to_vec(record)
+            .map_err(io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                self.writer.write_all(&line)
+            });
+
+        if let Err(err) = result {
+            log::error!("Failed to write the audit record of transaction {}: {}", record.tx, err);
+        }
+    }
+}
+
+/// A file that's rotated before it grows over a size: `audit.jsonl` is renamed to `audit.jsonl.1`,
+/// the older ones shift to `.2` and so on up to `keep` of them, and the writes go on in a new
+/// `audit.jsonl`. A write is never split across files, so neither is a line written at once.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Appends to the file when it exists.
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::options().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile { path, max_bytes, keep, file: BufWriter::new(file), size })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.keep > 0 {
+            for index in (1..self.keep).rev() {
+                match fs::rename(self.rotated(index), self.rotated(index + 1)) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl io::Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::types::TransactionType;
+
+    #[test]
+    fn test_records() {
+        let mut output = vec![];
+        let mut writer = AuditWriter::new(&mut output);
+
+        let deposit = Transaction::new(1, 2, TransactionType::Deposit(dec!(1.5))).with_timestamp(10);
+        let account = Account { available: dec!(1.5), total: dec!(1.5), ..Account::new(1) };
+        writer.record(&AuditRecord::new(&deposit, None, Some(&account), Some(10)));
+
+        let dispute = Transaction::new(3, 4, TransactionType::Dispute);
+        writer.record(&AuditRecord::new(&dispute, Some(ReasonCode::UnknownTx), None, Some(10)));
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"{"tx":2,"client":1,"type":"deposit","amount":"1.5","outcome":"applied","reason":null,"#,
+                r#""available":"1.5","held":"0","total":"1.5","locked":false,"timestamp":10,"recorded_at":10}"#,
+                "\n",
+                r#"{"tx":4,"client":3,"type":"dispute","amount":null,"outcome":"rejected","reason":"UNKNOWN_TX","#,
+                r#""available":null,"held":null,"total":null,"locked":null,"timestamp":null,"recorded_at":10}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join("transaction-engine-audit-rotation");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(dir.join("audit.jsonl.1")).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(dir.join("audit.jsonl.2")).unwrap(), "second\n");
+        assert!(!dir.join("audit.jsonl.3").exists());
+    }
+}
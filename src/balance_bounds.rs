@@ -0,0 +1,150 @@
+use std::{ collections::HashMap, fs, io, path::Path };
+
+use rust_decimal::Decimal;
+use serde::{ Deserialize, Serialize };
+
+use crate::types::{ custom_serde, ClientId };
+
+/// The balance an account of a class must stay within.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bounds {
+    /// Debits can't take the available balance under it
+    #[serde(default)]
+    pub floor: Option<Decimal>,
+    /// Deposits can't take the total balance over it
+    #[serde(default)]
+    pub ceiling: Option<Decimal>,
+}
+
+/// Balance bounds per account class, e.g. prepaid wallets capped at 10000. Loaded from a JSON
+/// file, e.g. `{"classes": {"prepaid": {"ceiling": "10000"}}, "accounts": {"17": "prepaid"}}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalanceBounds {
+    #[serde(default)]
+    pub classes: HashMap<String, Bounds>,
+    /// The class of each client
+    #[serde(default)]
+    pub accounts: HashMap<ClientId, String>,
+    /// The class of the clients not listed, they are unbounded without one
+    #[serde(default)]
+    pub default_class: Option<String>,
+}
+
+impl BalanceBounds {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bounds: BalanceBounds = serde_json::from_slice(&fs::read(path)?)?;
+
+        let unknown = bounds.accounts
+            .values()
+            .chain(&bounds.default_class)
+            .find(|class| !bounds.classes.contains_key(*class));
+
+        if let Some(class) = unknown {
+            let err = format!("unknown account class {}", class);
+
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+
+        let inverted = bounds.classes
+            .iter()
+            .find(|(_, class)| {
+                class.floor.zip(class.ceiling).is_some_and(|(floor, ceiling)| floor > ceiling)
+            });
+
+        if let Some((name, _)) = inverted {
+            let err = format!("the floor of account class {} is above its ceiling", name);
+
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+
+        Ok(bounds)
+    }
+
+    /// The class of the client and its bounds.
+    pub fn of(&self, client_id: ClientId) -> Option<(&str, &Bounds)> {
+        let class = self.accounts.get(&client_id).or(self.default_class.as_ref())?;
+
+        self.classes.get(class).map(|bounds| (class.as_str(), bounds))
+    }
+}
+
+/// A deposit credited up to the ceiling of its account, the excess wasn't credited.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Overflow {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: u32,
+    pub class: String,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub amount: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub credited: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub excess: Decimal,
+}
+
+pub fn write_report<W: io::Write>(overflows: &[Overflow], writer: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for overflow in overflows {
+        writer.serialize(overflow)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join("transaction-engine-balance-bounds.json");
+
+        let json = r#"{
+            "classes": {"prepaid": {"ceiling": "10000"}, "basic": {"floor": "10"}},
+            "accounts": {"17": "prepaid"},
+            "default_class": "basic"
+        }"#;
+        fs::write(&path, json).unwrap();
+        let bounds = BalanceBounds::load(&path).unwrap();
+
+        assert_eq!(bounds.of(17), Some(("prepaid", &Bounds { floor: None, ceiling: Some(dec!(10000)) })));
+        assert_eq!(bounds.of(2).map(|(class, _)| class), Some("basic"));
+
+        fs::write(&path, r#"{"classes": {}, "accounts": {"17": "prepaid"}}"#).unwrap();
+        assert!(BalanceBounds::load(&path).is_err());
+
+        fs::write(&path, r#"{"classes": {"prepaid": {"floor": "5", "ceiling": "1"}}}"#).unwrap();
+        assert!(BalanceBounds::load(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_report() {
+        let overflows = vec![Overflow {
+            client_id: 17,
+            tx_id: 3,
+            class: "prepaid".to_string(),
+            amount: dec!(2500),
+            credited: dec!(1000.50),
+            excess: dec!(1499.50),
+        }];
+
+        let mut output = vec![];
+        write_report(&overflows, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,class,amount,credited,excess\n17,3,prepaid,2500,1000.50,1499.50\n"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::{ fs::{ self, File }, io::{ self, BufReader, BufWriter, Write }, path::Path };
+
+use rust_decimal::Decimal;
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+    engine::Engine,
+    provenance::Provenance,
+    snapshot::{ self, Snapshot, SnapshotError },
+    store::{ HistoryEntry, KeptTransaction, StateStore, TransactionInfo },
+    types,
+};
+
+const FILE_NAME: &str = "checkpoint.bin";
+
+/// The version of the checkpoints written by this release, with the layout of their position and
+/// history. The snapshot inside has its own version, see [`SNAPSHOT_VERSION`](snapshot::SNAPSHOT_VERSION).
+pub const CHECKPOINT_VERSION: u16 = 3;
+
+const MAGIC: &[u8; 8] = b"TXCHKPNT";
+
+/// The state of a run right after one of its records: the engine, as a snapshot and its history,
+/// and where the record was read from. A run interrupted after it resumes from there instead of
+/// starting over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub position: Provenance,
+    pub snapshot: Snapshot,
+    pub history: Vec<KeptTransaction>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedPosition {
+    source: String,
+    line: u64,
+    offset: u64,
+}
+
+// Written after the position, the snapshot follows in its own binary form. Version 1, written
+// before checkpoints had a header, has the layout of version 2, which has the one of version 3
+// with 16 bit client ids.
+#[derive(Serialize, Deserialize)]
+struct EncodedTransaction<C = u64> {
+    tx_id: u32,
+    info: TransactionInfo,
+    client_id: C,
+    amount: [u8; 16],
+    timestamp: Option<u64>,
+    sender: Option<C>,
+    redisputes: u32,
+}
+
+impl EncodedTransaction<u16> {
+    fn widen(self) -> EncodedTransaction {
+        EncodedTransaction {
+            tx_id: self.tx_id,
+            info: self.info,
+            client_id: self.client_id.into(),
+            amount: self.amount,
+            timestamp: self.timestamp,
+            sender: self.sender.map(u64::from),
+            redisputes: self.redisputes,
+        }
+    }
+}
+
+impl Checkpoint {
+    /// Captures the engine once the record at `position` is applied.
+    pub fn capture<S: StateStore>(engine: &Engine<S>, position: Provenance) -> Self {
+        Checkpoint {
+            position,
+            snapshot: Snapshot::capture(engine),
+            history: engine.history().collect(),
+        }
+    }
+
+    pub fn write<W: io::Write>(&self, mut writer: W) -> Result<(), SnapshotError> {
+        let position = EncodedPosition {
+            source: self.position.source.to_string(),
+            line: self.position.line,
+            offset: self.position.offset,
+        };
+
+        let history: Vec<EncodedTransaction> = self.history
+            .iter()
+            .map(|kept| EncodedTransaction {
+                tx_id: kept.tx_id,
+                info: kept.entry.info,
+                client_id: kept.entry.client_id.into(),
+                amount: kept.entry.amount.serialize(),
+                timestamp: kept.timestamp,
+                sender: kept.sender.map(u64::from),
+                redisputes: kept.redisputes,
+            })
+            .collect();
+
+        snapshot::write_header(&mut writer, MAGIC, CHECKPOINT_VERSION)?;
+        bincode::serialize_into(&mut writer, &position)?;
+        bincode::serialize_into(&mut writer, &history)?;
+
+        self.snapshot.write(writer)
+    }
+
+    /// Reads a checkpoint of any version up to [`CHECKPOINT_VERSION`], migrating the older ones.
+    pub fn read<R: io::Read>(reader: R) -> Result<Self, SnapshotError> {
+        let (version, mut reader) = snapshot::read_header(reader, MAGIC)?;
+
+        if !(1..=CHECKPOINT_VERSION).contains(&version) {
+            return Err(SnapshotError::UnsupportedVersion { found: version, supported: CHECKPOINT_VERSION });
+        }
+
+        let position: EncodedPosition = bincode::deserialize_from(&mut reader)?;
+        let history: Vec<EncodedTransaction> = match version {
+            1 | 2 => {
+                let history: Vec<EncodedTransaction<u16>> = bincode::deserialize_from(&mut reader)?;
+
+                history.into_iter().map(EncodedTransaction::widen).collect()
+            }
+            _ => bincode::deserialize_from(&mut reader)?,
+        };
+
+        Ok(Checkpoint {
+            position: Provenance {
+                source: position.source.into(),
+                line: position.line,
+                offset: position.offset,
+            },
+            snapshot: Snapshot::read(reader)?,
+            history: history
+                .into_iter()
+                .map(|kept| {
+                    Ok(KeptTransaction {
+                        tx_id: kept.tx_id,
+                        entry: HistoryEntry {
+                            info: kept.info,
+                            client_id: types::client_id(kept.client_id)?,
+                            amount: Decimal::deserialize(kept.amount),
+                        },
+                        timestamp: kept.timestamp,
+                        sender: kept.sender.map(types::client_id).transpose()?,
+                        redisputes: kept.redisputes,
+                    })
+                })
+                .collect::<Result<_, SnapshotError>>()?,
+        })
+    }
+
+    /// Writes the checkpoint to `dir`, created when missing. It's written aside and renamed over
+    /// the previous one, so a run killed while writing leaves the previous checkpoint whole.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<(), SnapshotError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let partial = dir.join(format!("{}.partial", FILE_NAME));
+        let mut writer = BufWriter::new(File::create(&partial)?);
+
+        self.write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        fs::rename(partial, dir.join(FILE_NAME))?;
+
+        Ok(())
+    }
+
+    /// Whether `dir` holds a checkpoint to load.
+    pub fn exists<P: AsRef<Path>>(dir: P) -> bool {
+        dir.as_ref().join(FILE_NAME).exists()
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, SnapshotError> {
+        Checkpoint::read(BufReader::new(File::open(dir.as_ref().join(FILE_NAME))?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::types::{ Transaction, TransactionType };
+
+    #[test]
+    fn test_save_load() {
+        let mut engine = Engine::new();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(2)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::WithdrawHold(dec!(1)))).unwrap();
+
+        let position = Provenance { source: "input.csv".into(), line: 5, offset: 120 };
+        let checkpoint = Checkpoint::capture(&engine, position);
+
+        assert_eq!(checkpoint.history.len(), 3);
+
+        let dir = env::temp_dir().join("transaction-engine-checkpoint");
+        checkpoint.save(&dir).unwrap();
+
+        let loaded = Checkpoint::load(&dir).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert!(!dir.join("checkpoint.bin.partial").exists());
+
+        // The resumed engine carries on where the first one stopped
+        let mut resumed = Engine::builder()
+            .opening_balances(loaded.snapshot.accounts)
+            .scheduled_releases(loaded.snapshot.scheduled)
+            .history(loaded.history)
+            .build();
+
+        for engine in [&mut engine, &mut resumed] {
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Chargeback)).unwrap();
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::WithdrawCommit)).unwrap();
+            assert!(engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(1)))).is_err());
+        }
+
+        assert_eq!(resumed.get_account(1), engine.get_account(1));
+        assert_eq!(resumed.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_read_v1() {
+        let mut engine = Engine::new();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+
+        let checkpoint = Checkpoint::capture(&engine, Provenance { source: "input.csv".into(), line: 3, offset: 40 });
+
+        // Before the header, the checkpoint started right with its position, and the history had
+        // 16 bit client ids. The snapshot inside is read by its own version.
+        let mut v1 = bincode::serialize(&EncodedPosition { source: "input.csv".to_string(), line: 3, offset: 40 }).unwrap();
+        bincode::serialize_into(&mut v1, &vec![EncodedTransaction::<u16> {
+            tx_id: 1,
+            info: TransactionInfo::UnderDispute,
+            client_id: 1,
+            amount: dec!(10).serialize(),
+            timestamp: None,
+            sender: None,
+            redisputes: 0,
+        }]).unwrap();
+        checkpoint.snapshot.write(&mut v1).unwrap();
+
+        assert_eq!(Checkpoint::read(v1.as_slice()).unwrap(), checkpoint);
+
+        let mut bytes = vec![];
+        checkpoint.write(&mut bytes).unwrap();
+
+        bytes[MAGIC.len()] = 9;
+        assert!(matches!(
+            Checkpoint::read(bytes.as_slice()),
+            Err(SnapshotError::UnsupportedVersion { found: 9, supported: CHECKPOINT_VERSION })
+        ));
+    }
+}
@@ -0,0 +1,932 @@
+use std::path::PathBuf;
+
+use clap::{ ArgGroup, Args, Parser, Subcommand, ValueEnum };
+use rust_decimal::Decimal;
+
+use transaction_engine::{
+    alert::AlertRule,
+    ingest::{ Compression, InputFormat, Overflow, Tuning },
+    listener::ListenAddress,
+    output::{ AccountFilter, ClientRange, OutputFormat },
+    policy::{ AccountLimit, RiskTier },
+    risk::RiskThreshold,
+    throttle::{ RateLimits, ReplaySpeed },
+    throughput::Bucket,
+    types::custom_serde::{ RoundingMode, DEFAULT_PRECISION },
+    validation::DEFAULT_MAX_SCALE,
+    ClientId,
+};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Processes a CSV of transactions and outputs the client accounts",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    group(ArgGroup::new("shadow").args(["shadow_opening_balances", "shadow_config"]).multiple(true))
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML file with the settings of the run, every key the long name of a flag without its dashes, e.g. `workers = 4`, `allow_negative_balance = true` or `alert = ["locked"]`. The flags given on the command line take precedence over the file
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// CSV files with the transactions to process, in order (glob patterns are expanded), read from stdin when it's `-` or missing
+    pub files: Vec<PathBuf>,
+
+    /// Format of the input files, `csv` or `json` (JSON Lines), detected from the extension by default (.json, .jsonl and .ndjson are JSON) and CSV for stdin
+    #[arg(long, value_name = "FORMAT")]
+    pub input_format: Option<InputFormat>,
+
+    /// Compression of the input files, `none`, `gzip` or `zstd`, detected from the extension by default (.gz and .zst) and none for stdin. Compressed files are read by a single reader
+    #[arg(long, value_name = "COMPRESSION")]
+    pub compression: Option<Compression>,
+
+    /// Map the fields of every input record before it's parsed, as configured in this JSON file: `types` renames transaction types, `amount_multiplier` scales amounts and `clients` remaps client ids
+    #[arg(long, value_name = "FILE")]
+    pub transform: Option<PathBuf>,
+
+    /// Reject JSON records whose amount is a number rather than a string as malformed, as the number may have gone through a binary float before it was written. Amounts are always read from their text as written
+    #[arg(long)]
+    pub strict_amounts: bool,
+
+    /// Stop the run on the first record that can't be parsed, with the input's exit code, instead of skipping it
+    #[arg(long, conflicts_with = "max_errors")]
+    pub strict: bool,
+
+    /// Stop the run once more than this many records couldn't be parsed, with the input's exit code
+    #[arg(long, value_name = "COUNT")]
+    pub max_errors: Option<u64>,
+
+    /// Exit with code 3 when a complete run rejected transactions and 6 when it skipped rows it couldn't parse, instead of 0
+    #[arg(long)]
+    pub detailed_exit_codes: bool,
+
+    /// The accounts to start from, e.g. the output of yesterday's run: CSV (client,available,held,total,locked), or JSON with a `.json`, `.ndjson` or `.jsonl` extension. The extra columns and sections of an output are ignored
+    #[arg(long, value_name = "FILE", visible_alias = "initial-accounts")]
+    pub opening_balances: Option<PathBuf>,
+
+    /// Keep the ordered log of applied transactions and derive the balances from it
+    #[arg(long)]
+    pub event_sourcing: bool,
+
+    /// Write per-client per-day activity totals to this file (CSV, or Parquet with a `.parquet` extension)
+    #[arg(long, value_name = "FILE", requires = "event_sourcing")]
+    pub daily_rollup: Option<PathBuf>,
+
+    /// Post account lifecycle events as JSON to this http:// URL (can be repeated)
+    #[arg(long, value_name = "URL")]
+    pub lifecycle_webhook: Vec<String>,
+
+    /// Cache the account balances in redis under account:<client> and publish every update to the accounts channel, e.g. redis://localhost:6379
+    #[arg(long, value_name = "URL")]
+    pub redis_url: Option<String>,
+
+    /// Write an event for every change to an account (deposited, withdrawn, dispute_opened, dispute_resolved, charged_back, account_locked), every rejected transaction and every alert raised to this file as JSON Lines, in the order they happen
+    #[arg(long, value_name = "FILE")]
+    pub events: Option<PathBuf>,
+
+    /// Write an audit record of every transaction (tx, client, type, amount, outcome, reason, the balances of the client after it, timestamp) to this file as JSON Lines, or to stdout when it's `-` with `--output`
+    #[arg(long, value_name = "FILE")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Rotate the audit log before it grows over this many bytes, it's appended to instead of replaced
+    #[arg(long, value_name = "BYTES", requires = "audit_log")]
+    pub audit_max_bytes: Option<u64>,
+
+    /// Rotated audit logs to keep (audit.jsonl.1, audit.jsonl.2, ...), the oldest one is dropped
+    #[arg(long, value_name = "COUNT", default_value_t = 5)]
+    pub audit_keep: usize,
+
+    /// Raise an alert event when an account crosses this rule: held>AMOUNT, available<AMOUNT, total>AMOUNT, change>AMOUNT/SECONDS (the total moved by more than AMOUNT within that many seconds of the engine clock) or locked (can be repeated)
+    #[arg(long, value_name = "RULE")]
+    pub alert: Vec<AlertRule>,
+
+    /// Flag accounts without activity for this many days (based on the transaction timestamps) as dormant
+    #[arg(long, value_name = "DAYS")]
+    pub dormant_after: Option<u64>,
+
+    /// Add the timestamps of the first and last transactions of each account to the output as `first_activity` and `last_activity` columns, empty for accounts without timestamped transactions
+    #[arg(long)]
+    pub activity_columns: bool,
+
+    /// Write the dormant accounts to this CSV file
+    #[arg(long, value_name = "FILE", requires = "dormant_after")]
+    pub dormancy_report: Option<PathBuf>,
+
+    /// Run a candidate engine seeded with these opening balances alongside the primary one and report divergences
+    #[arg(long, value_name = "FILE")]
+    pub shadow_opening_balances: Option<PathBuf>,
+
+    /// Run a candidate engine configured with the settings of this TOML file alongside the primary one and report divergences. The keys are the engine flags (fees, limits, tier_limit, rounding, dispute_window_days, ...) the candidate takes in place of the run's, the other flags carry over
+    #[arg(long, value_name = "FILE")]
+    pub shadow_config: Option<PathBuf>,
+
+    /// Write the divergences between the primary and the shadow engine to this CSV file
+    #[arg(long, value_name = "FILE", requires = "shadow")]
+    pub shadow_report: Option<PathBuf>,
+
+    /// Allow disputes to hold more than the available funds, leaving the account in deficit
+    #[arg(long)]
+    pub allow_negative_balance: bool,
+
+    /// Treat exact duplicates of earlier deposits and withdrawals (same id, client, type and amount) as no-ops, so replaying a file is safe
+    #[arg(long)]
+    pub idempotent: bool,
+
+    /// Reject with OUT_OF_SEQUENCE the records whose `seq` repeats or goes back for their client, gaps and conflicts are only reported otherwise
+    #[arg(long)]
+    pub reject_out_of_sequence: bool,
+
+    /// Reject with LATE_TIMESTAMP the records whose timestamp is older than the latest one of their client, see `--reorder-window` to put them back in order first
+    #[arg(long)]
+    pub reject_late_timestamps: bool,
+
+    /// Verify the state after every transaction: totals are available plus held, held balances aren't negative and add up to the open disputes and holds, and locked accounts don't lose available funds nor hold more. The run stops at the first violation with exit code 3, without writing the accounts. Slow, for tests and investigations
+    #[arg(long)]
+    pub check_invariants: bool,
+
+    /// Keep accepting deposits into accounts locked by a chargeback, every other transaction is rejected
+    #[arg(long)]
+    pub allow_locked_deposits: bool,
+
+    /// Accept adjustment rows (signed amount and reason column) correcting available balances, they are rejected with NOT_ALLOWED otherwise
+    #[arg(long)]
+    pub allow_adjustments: bool,
+
+    /// Accept unlock rows reopening accounts locked by a chargeback, they are rejected with NOT_ALLOWED otherwise
+    #[arg(long)]
+    pub allow_unlocks: bool,
+
+    /// Unlock the account when a representment row reverses one of its chargebacks
+    #[arg(long)]
+    pub unlock_on_representment: bool,
+
+    /// Accept delete rows hiding an account from the output while keeping its state, and restore rows bringing it back, they are rejected with NOT_ALLOWED otherwise
+    #[arg(long)]
+    pub allow_deletes: bool,
+
+    /// Days after which a withdraw_hold that wasn't committed gives its funds back to available
+    #[arg(long, value_name = "DAYS", default_value_t = 7)]
+    pub hold_expiry_days: u64,
+
+    /// Keep deposits held for this many days before their funds become available
+    #[arg(long, value_name = "DAYS")]
+    pub deposit_hold_days: Option<u64>,
+
+    /// Resolve disputes that weren't resolved or charged back after this many days
+    #[arg(long, value_name = "DAYS")]
+    pub dispute_expiry_days: Option<u64>,
+
+    /// Reject disputes filed more than this many days after their transaction with DISPUTE_WINDOW_CLOSED, by the `timestamp` column
+    #[arg(long, value_name = "DAYS")]
+    pub dispute_window_days: Option<u64>,
+
+    /// Raise the risk tier of a client to high, with an escalated lifecycle event, once it disputed transactions already under dispute more than this many times (they are rejected with ALREADY_DISPUTED either way)
+    #[arg(long, value_name = "COUNT")]
+    pub escalate_duplicate_disputes: Option<u32>,
+
+    /// Let a resolved transaction be disputed again up to this many times, like a card network's second presentment, rejecting the disputes past it with REDISPUTE_LIMIT
+    #[arg(long, value_name = "COUNT")]
+    pub allow_redisputes: Option<u32>,
+
+    /// Pay interest at this daily rate (e.g. 0.0001) on the positive available balance of every unlocked account at the end of each day of the `timestamp` column, as an `interest` adjustment
+    #[arg(long, value_name = "RATE")]
+    pub daily_interest: Option<Decimal>,
+
+    /// Sweep the available funds over this amount out of every unlocked account at the end of each day of the `timestamp` column, as a `sweep` adjustment. Runs after the interest
+    #[arg(long, value_name = "AMOUNT")]
+    pub sweep_above: Option<Decimal>,
+
+    /// Write the risk signals of every client (deposits, disputes, chargebacks, dispute rate, rapid deposit and withdrawal cycles, velocity) and the thresholds they're over to this CSV file
+    #[arg(long, value_name = "FILE")]
+    pub risk_report: Option<PathBuf>,
+
+    /// Flag the clients with a risk signal over this threshold: chargebacks>COUNT, dispute_rate>RATE, cycles>COUNT or velocity>COUNT (can be repeated)
+    #[arg(long, value_name = "RULE")]
+    pub risk_threshold: Vec<RiskThreshold>,
+
+    /// Lock the accounts of the clients flagged by a --risk-threshold, their later transactions are rejected with ACCOUNT_LOCKED
+    #[arg(long, requires = "risk_threshold")]
+    pub risk_lock: bool,
+
+    /// The sliding window of the cycle and velocity risk signals, by the `timestamp` column: a withdrawal within it after a deposit is a cycle, and the velocity is the most deposits, withdrawals and transfers within one
+    #[arg(long, value_name = "MINUTES", default_value_t = 60)]
+    pub risk_window_minutes: u64,
+
+    /// Reject amounts with more decimal places than this with INVALID_AMOUNT
+    #[arg(long, value_name = "PLACES", default_value_t = DEFAULT_MAX_SCALE)]
+    pub max_amount_scale: u32,
+
+    /// Decimal places of the balances read (opening balances) and written (outputs and reports)
+    #[arg(long, value_name = "PLACES", default_value_t = DEFAULT_PRECISION)]
+    pub precision: u32,
+
+    /// How balances are rounded to `--precision`: half-up, half-even (banker's rounding) or truncate
+    #[arg(long, value_name = "MODE", default_value_t = RoundingMode::HalfEven)]
+    pub rounding: RoundingMode,
+
+    /// Write the balances without trailing zeros (`1.5` rather than `1.50`), whatever scale the amounts they come from had
+    #[arg(long)]
+    pub normalize_amounts: bool,
+
+    /// Write the accounts with a negative available balance to this CSV file
+    #[arg(long, value_name = "FILE")]
+    pub deficit_report: Option<PathBuf>,
+
+    /// Write every alert raised to this CSV file (client, tx, rule, the balances then, when it was raised and whether the account still matches the rule), in the order they were raised
+    #[arg(long, value_name = "FILE", requires = "alert")]
+    pub alerts_report: Option<PathBuf>,
+
+    /// Write every transaction under dispute to this CSV file (tx, client, amount, when the dispute was opened and its age in days), the oldest dispute first
+    #[arg(long, value_name = "FILE")]
+    pub open_disputes: Option<PathBuf>,
+
+    /// Write a statement of every client to this directory, client-<id>.csv with its applied transactions, the available and held balances after each one and where their disputes stand. The transactions of the whole run are kept in memory for it
+    #[arg(long, value_name = "DIR")]
+    pub statements: Option<PathBuf>,
+
+    /// Write treasury's position (client funds, held funds, deficits, system accounts and total exposure) in the base currency to this CSV file
+    #[arg(long, value_name = "FILE", requires = "base_currency")]
+    pub exposure_report: Option<PathBuf>,
+
+    /// The currency the balances are kept in, e.g. EUR
+    #[arg(long, value_name = "CODE")]
+    pub base_currency: Option<String>,
+
+    /// Keep the balances of each client per currency, from the `currency` column of the input: transactions without one are in the `--base-currency`, and the output gets a currency column. The currencies are independent, e.g. a chargeback only locks the account of its currency
+    #[arg(
+        long,
+        requires = "base_currency",
+        conflicts_with_all = [
+            "workers",
+            "event_sourcing",
+            "shadow",
+            "quarantine_above",
+            "pending",
+            "lifecycle_webhook",
+            "redis_url",
+            "events",
+            "audit_log",
+            "alert",
+            "alerts_report",
+            "dormant_after",
+            "deficit_report",
+            "open_disputes",
+            "statements",
+            "overflow_report",
+            "exposure_report",
+            "risk_tiers",
+            "system_accounts",
+            "fee_summary",
+            "limits",
+            "risk_report",
+            "risk_threshold",
+            "reject_out_of_sequence",
+            "reject_late_timestamps",
+            "activity_columns",
+            "check_invariants",
+            "retain_days",
+            "retain_max",
+            "retain_per_client",
+            "restore",
+            "snapshot",
+            "stream_output",
+        ]
+    )]
+    pub multi_currency: bool,
+
+    /// CSV (`from,to,rate,spread`) or JSON file with the exchange rates of the `convert` transactions, the spread is the fraction of the converted amount kept as a fee. Conversions without a rate are rejected with NO_RATE
+    #[arg(long, value_name = "FILE", requires = "multi_currency")]
+    pub rates: Option<PathBuf>,
+
+    /// Keep the accounts and history of each tenant apart, from the `tenant` column of the input: client and transaction ids only have to be unique within a tenant, transactions without one are in the `--default-tenant`, and the output gets a tenant column
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "workers",
+            "event_sourcing",
+            "shadow",
+            "quarantine_above",
+            "pending",
+            "lifecycle_webhook",
+            "redis_url",
+            "events",
+            "audit_log",
+            "alert",
+            "alerts_report",
+            "dormant_after",
+            "deficit_report",
+            "open_disputes",
+            "statements",
+            "overflow_report",
+            "exposure_report",
+            "risk_tiers",
+            "system_accounts",
+            "fee_summary",
+            "limits",
+            "risk_report",
+            "risk_threshold",
+            "reject_out_of_sequence",
+            "reject_late_timestamps",
+            "activity_columns",
+            "check_invariants",
+            "retain_days",
+            "retain_max",
+            "retain_per_client",
+            "restore",
+            "snapshot",
+            "stream_output",
+            "multi_currency",
+            "checkpoint",
+            "resume",
+        ]
+    )]
+    pub multi_tenant: bool,
+
+    /// The tenant of the transactions without one, the opening balances are in it
+    #[arg(long, value_name = "NAME", default_value = "default", requires = "multi_tenant")]
+    pub default_tenant: String,
+
+    /// Only output the accounts of this tenant. When repeated, the accounts of any of them are output
+    #[arg(long, value_name = "NAME", requires = "multi_tenant")]
+    pub tenant: Vec<String>,
+
+    /// Also write the output accounts of every tenant to this directory, <tenant>.csv with the standard account columns
+    #[arg(long, value_name = "DIR", requires = "multi_tenant")]
+    pub split_tenants: Option<PathBuf>,
+
+    /// Apply at most this many transactions per second, to protect slow downstream sinks during a backfill
+    #[arg(long, value_name = "TPS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_apply_rate: Option<u32>,
+
+    /// Pace the transactions by their timestamps, at the original pace (`realtime`) or faster or slower (e.g. `10x`)
+    #[arg(long, value_name = "SPEED")]
+    pub replay_speed: Option<ReplaySpeed>,
+
+    /// Write the rows with an unknown transaction type to this CSV file (source, line and the raw row) instead of only counting them as rejected
+    #[arg(long, value_name = "FILE")]
+    pub unknown_types: Option<PathBuf>,
+
+    /// Write every rejected row to this CSV file with its source, line, reason code and the transaction or raw row
+    #[arg(long, value_name = "FILE")]
+    pub rejects: Option<PathBuf>,
+
+    /// Write every applied transaction to this file with the canonical columns and amounts (no trailing zeros), as JSON Lines for a .json, .jsonl or .ndjson extension and CSV otherwise, to feed other tools
+    #[arg(long, value_name = "FILE")]
+    pub emit_normalized: Option<PathBuf>,
+
+    /// Report the rows read, applied and rejected, the rows per second and the ETA of the input files to stderr every second
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Write per-source counters of applied, rejected and malformed transactions to this CSV file
+    #[arg(long, value_name = "FILE")]
+    pub source_stats: Option<PathBuf>,
+
+    /// Write the applied and rejected transactions per timestamp bucket and type to this CSV file
+    #[arg(long, value_name = "FILE")]
+    pub throughput_report: Option<PathBuf>,
+
+    /// Size of the throughput report buckets, `hour` or `day`
+    #[arg(long, value_name = "BUCKET", default_value = "hour", requires = "throughput_report")]
+    pub throughput_bucket: Bucket,
+
+    /// Log at warn level, with the transaction and the engine state, every transaction that takes longer than this many microseconds to apply
+    #[arg(long, value_name = "MICROS")]
+    pub slow_apply_micros: Option<u64>,
+
+    /// Write the engine metrics in the Prometheus text format to this file at the end of the run (e.g. for the node exporter textfile collector)
+    #[arg(long, value_name = "FILE")]
+    pub metrics: Option<PathBuf>,
+
+    /// Quarantine deposits and withdrawals above this amount until an `approve` or `decline` row releases them
+    #[arg(long, value_name = "AMOUNT")]
+    pub quarantine_above: Option<Decimal>,
+
+    /// JSON approval queue: pending transactions are restored from it and the reviewed ones released, then it's updated
+    #[arg(long, value_name = "FILE", requires = "quarantine_above")]
+    pub pending: Option<PathBuf>,
+
+    /// Cap withdrawals of clients in a risk tier, e.g. `high=500` (can be repeated). Clients move to the high tier after a chargeback
+    #[arg(long, value_name = "TIER=AMOUNT", value_parser = parse_tier_limit)]
+    pub tier_limit: Vec<(RiskTier, Decimal)>,
+
+    /// Cap the transactions or turnover of every account per period, `[TIER:]count=N/PERIOD` or `[TIER:]turnover=AMOUNT/PERIOD` with an hour, day or week period, e.g. `high:turnover=5000/day` (can be repeated)
+    #[arg(long, value_name = "LIMIT")]
+    pub account_limit: Vec<AccountLimit>,
+
+    /// JSON file with the balance floor and ceiling of account classes and the class of each client, e.g. `{"classes": {"prepaid": {"ceiling": "10000"}}, "accounts": {"17": "prepaid"}}`. Deposits over the ceiling are rejected with ABOVE_CEILING and debits under the floor with BELOW_FLOOR
+    #[arg(long, value_name = "FILE")]
+    pub balance_bounds: Option<PathBuf>,
+
+    /// Credit deposits over the ceiling up to it instead of rejecting them, and write what wasn't credited to this CSV file
+    #[arg(long, value_name = "FILE", requires = "balance_bounds")]
+    pub overflow_report: Option<PathBuf>,
+
+    /// JSON file with the fees of deposits and withdrawals, a flat amount plus a percentage with per-client overrides, e.g. `{"withdrawal": {"flat": "0.5", "percent": "1"}, "clients": {"17": {"withdrawal": {}}}}`. Deposits are credited net of their fee and withdrawals debit it on top, a deposit not covering its fee is rejected with INVALID_AMOUNT
+    #[arg(long, value_name = "FILE")]
+    pub fees: Option<PathBuf>,
+
+    /// CSV file (`client,limit`) with the agreed overdraft of clients: their withdrawals, withdrawal holds and transfers can take the available balance under zero down to the limit. Adds `credit_limit` and `credit_used` columns to the output
+    #[arg(long, value_name = "FILE")]
+    pub limits: Option<PathBuf>,
+
+    /// Append the fees charged by transaction type to the output as another CSV section
+    #[arg(long, requires = "fees")]
+    pub fee_summary: bool,
+
+    /// Add the client risk tier to the output as a `tier` column
+    #[arg(long)]
+    pub risk_tiers: bool,
+
+    /// Append the system accounts (fees, chargeback losses, escrow) to the output as a second CSV section
+    #[arg(long)]
+    pub system_accounts: bool,
+
+    /// Forget transactions older than this many days, they can't be disputed anymore (open disputes are always kept)
+    #[arg(long, value_name = "DAYS")]
+    pub retain_days: Option<u64>,
+
+    /// Keep at most this many disputable transactions in memory, forgetting the oldest ones first
+    #[arg(long, value_name = "COUNT")]
+    pub retain_max: Option<usize>,
+
+    /// Keep at most this many disputable transactions of each client in memory, forgetting its oldest ones first
+    #[arg(long, value_name = "COUNT")]
+    pub retain_per_client: Option<usize>,
+
+    /// Where time-based features (dormancy, deficit age) take the current time from
+    #[arg(long, value_enum, default_value_t = ClockSource::Event)]
+    pub clock: ClockSource,
+
+    /// Start from the balances and risk tiers of this snapshot file (binary, or JSON with a `.json` extension)
+    #[arg(long, value_name = "FILE", conflicts_with = "opening_balances")]
+    pub restore: Option<PathBuf>,
+
+    /// Write the balances and risk tiers at the end of the run to this snapshot file (JSON with a `.json` extension, binary otherwise), see `serve-snapshot`
+    #[arg(long, value_name = "FILE")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Save a checkpoint of the run to this directory every `--checkpoint-every` records and when interrupted: the engine and the last record it applied. A run interrupted or crashed since can carry on from it with `--resume`. Needs the records applied in the input order, on a single worker and shard
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["multi_currency", "multi_tenant", "reorder_window", "shadow"]
+    )]
+    pub checkpoint: Option<PathBuf>,
+
+    /// How many records between two checkpoints, a checkpoint is taken at the end of a batch
+    #[arg(long, value_name = "RECORDS", default_value_t = 100_000, value_parser = positive, requires = "checkpoint")]
+    pub checkpoint_every: usize,
+
+    /// Carry on from the checkpoint in this directory, starting right after the last record it applied. Give the inputs and flags of the run that saved it
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = [
+            "restore",
+            "opening_balances",
+            "multi_currency",
+            "multi_tenant",
+            "reorder_window",
+            "shadow",
+        ]
+    )]
+    pub resume: Option<PathBuf>,
+
+    /// Write the output accounts to this file instead of stdout, it's only replaced once the whole output is written
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Write a JSON manifest of the run to this file at the end: the engine version, the arguments, the inputs and outputs with their size and SHA-256, and the row counts
+    #[arg(long, value_name = "FILE")]
+    pub manifest: Option<PathBuf>,
+
+    /// Format of the output accounts: csv, json (an array of objects), ndjson (an object per line) or parquet (a build with `--features parquet`)
+    #[arg(long, value_name = "FORMAT", default_value_t = OutputFormat::Csv)]
+    pub output_format: OutputFormat,
+
+    /// Only output the accounts needing a review: locked, disputed (holding funds) or negative (a negative available or total balance). When repeated, an account matching any of them is output
+    #[arg(long, value_name = "FILTER")]
+    pub only: Vec<AccountFilter>,
+
+    /// Only output the accounts of these clients, e.g. `1,5,9`. With `--client-range`, an account matching either is output
+    #[arg(long, value_name = "IDS", value_delimiter = ',')]
+    pub clients: Vec<ClientId>,
+
+    /// Only output the accounts of the clients in this range, e.g. `100-200` with both ends included (can be repeated)
+    #[arg(long, value_name = "FIRST-LAST")]
+    pub client_range: Vec<ClientRange>,
+
+    /// Output the accounts in the order they're stored instead of sorted by client id, which saves the sort with huge account counts. The order can change from one run to the next
+    #[arg(long)]
+    pub unsorted: bool,
+
+    /// Format the output accounts on this many threads, the output is the same whatever the count
+    #[arg(long, value_name = "COUNT", default_value_t = 1, value_parser = positive)]
+    pub writer_threads: usize,
+
+    /// Write the output accounts as they're formatted, a chunk at a time, instead of formatting the whole output in memory first. The output is the same, and a `--output` file is still only replaced once it's complete
+    #[arg(long, conflicts_with = "writer_threads")]
+    pub stream_output: bool,
+
+    /// Keep the history of transactions in a sled database in this directory instead of in memory, for inputs whose history doesn't fit in memory. The directory only holds the state of the run, what it held before is dropped
+    #[cfg(feature = "sled")]
+    #[arg(long, value_name = "DIR", conflicts_with = "workers")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Size the in-memory accounts map for about this many clients up front, split evenly between the workers. A hint, more clients still fit
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub expected_clients: usize,
+
+    /// Size the in-memory history for about this many transactions up front, split evenly between the workers. A hint, more transactions still fit
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub expected_txs: usize,
+
+    /// Apply the transactions on this many workers, each one owning the accounts of a share of the clients. The clients of a transfer or a merge are moved to one worker first
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 1,
+        value_parser = positive,
+        conflicts_with_all = [
+            "event_sourcing",
+            "shadow",
+            "quarantine_above",
+            "lifecycle_webhook",
+            "redis_url",
+            "events",
+            "audit_log",
+            "alert",
+            "alerts_report",
+            "dormant_after",
+            "deficit_report",
+            "open_disputes",
+            "statements",
+            "overflow_report",
+            "exposure_report",
+            "risk_tiers",
+            "system_accounts",
+            "fee_summary",
+            "risk_report",
+            "risk_threshold",
+            "reject_out_of_sequence",
+            "activity_columns",
+            "check_invariants",
+            "retain_days",
+            "retain_max",
+            "retain_per_client",
+        ]
+    )]
+    pub workers: usize,
+
+    #[command(flatten)]
+    pub tuning: TuningArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TuningArgs {
+    /// Read the file with this many threads, each one reading a range of lines (records can't span lines)
+    #[arg(long, value_name = "COUNT", default_value_t = 1, value_parser = positive)]
+    pub reader_threads: usize,
+
+    /// Parse batches of records on this many threads, the parsed batches are handed on in the file order
+    #[arg(long, value_name = "COUNT", default_value_t = 1, value_parser = positive)]
+    pub parser_threads: usize,
+
+    /// Split each file into chunks read and parsed from start to end by a pool of this many threads, in place of the readers and parsers. The chunks are handed on in the file order, so each client's transactions keep theirs
+    #[arg(long, value_name = "COUNT", value_parser = positive, conflicts_with_all = ["reader_threads", "parser_threads", "overflow"])]
+    pub parse_threads: Option<usize>,
+
+    /// Split the parsed transactions into this many streams by client. The order of each client's transactions is kept, and transfers, merges and transactions reusing or referring to an id of another stream keep their place in the input
+    #[arg(long, value_name = "COUNT", default_value_t = 1, value_parser = positive)]
+    pub shards: usize,
+
+    /// Number of records read, parsed and sent to the engine at a time
+    #[arg(long, value_name = "COUNT", default_value_t = 100, value_parser = positive)]
+    pub batch_size: usize,
+
+    /// Number of batches each stage of the pipeline holds before the next one takes them
+    #[arg(long, value_name = "COUNT", default_value_t = 100, value_parser = positive)]
+    pub buffer_size: usize,
+
+    /// What to do with the batches read while the pipeline is full: block the readers, drop them (counted in the metrics) or spill them to a temporary file
+    #[arg(long, value_name = "STRATEGY", default_value_t = Overflow::Block)]
+    pub overflow: Overflow,
+
+    /// Hold the records back until the timestamps are this many seconds past theirs and apply them in timestamp order, so records arriving up to this late are applied in time order. Sequence numbers follow the new order
+    #[arg(long, value_name = "SECONDS")]
+    pub reorder_window: Option<u64>,
+}
+
+/// Rate limits of the services, enforced before the engine
+#[derive(Debug, Args)]
+pub struct RateLimitArgs {
+    /// Take at most this many transactions per second from all the sources together
+    #[arg(long, value_name = "TPS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_rate: Option<u32>,
+
+    /// Take at most this many transactions per second for each client, so one noisy client can't starve the others. Its transactions over the limit don't count towards `--max-rate`
+    #[arg(long, value_name = "TPS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_client_rate: Option<u32>,
+}
+
+#[derive(Debug, Args)]
+pub struct DeadLetterArgs {
+    /// Append the records that can't be parsed or that the engine rejected to this file, a JSON object per line with their source, reason code, error and raw payload
+    #[arg(long, value_name = "FILE")]
+    pub dead_letter_file: Option<PathBuf>,
+
+    /// Also produce the dead letters to this Kafka topic, keyed by their source
+    #[cfg(feature = "kafka")]
+    #[arg(long, value_name = "TOPIC")]
+    pub dead_letter_topic: Option<String>,
+
+    /// Kafka bootstrap servers of the dead letter topic, the ones of `--brokers` by default with `consume` and localhost:9092 otherwise
+    #[cfg(feature = "kafka")]
+    #[arg(long, value_name = "HOSTS", requires = "dead_letter_topic")]
+    pub dead_letter_brokers: Option<String>,
+}
+
+impl From<RateLimitArgs> for RateLimits {
+    fn from(args: RateLimitArgs) -> Self {
+        RateLimits { global: args.max_rate, per_client: args.max_client_rate }
+    }
+}
+
+impl From<TuningArgs> for Tuning {
+    fn from(args: TuningArgs) -> Self {
+        Tuning {
+            reader_threads: args.reader_threads,
+            parser_threads: args.parser_threads,
+            parse_threads: args.parse_threads,
+            shards: args.shards,
+            batch_size: args.batch_size,
+            buffer_size: args.buffer_size,
+            overflow: args.overflow,
+            reorder_window: args.reorder_window,
+        }
+    }
+}
+
+fn positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>().map_err(|err| err.to_string())? {
+        0 => Err("must be at least 1".to_string()),
+        value => Ok(value),
+    }
+}
+
+fn parse_tier_limit(s: &str) -> Result<(RiskTier, Decimal), String> {
+    let (tier, amount) = s.split_once('=').ok_or("expected TIER=AMOUNT")?;
+    let amount = amount.parse::<Decimal>().map_err(|err| err.to_string())?;
+
+    Ok((tier.parse()?, amount))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ClockSource {
+    /// The latest transaction timestamp seen, so replays are deterministic
+    Event,
+    /// The system time
+    System,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Review the transactions waiting in an approval queue
+    Pending {
+        #[command(subcommand)]
+        action: PendingAction,
+    },
+    /// Serve a read-only query API (accounts and risk tiers) over a snapshot, without ingesting transactions
+    ServeSnapshot {
+        /// Snapshot file written by a run with `--snapshot`
+        snapshot: PathBuf,
+
+        /// Address to listen on
+        #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Run the engine as a service, transactions are posted to an HTTP API instead of read from files
+    Serve(ServeArgs),
+    /// Apply the transactions of a Kafka topic as they arrive, one JSON transaction per message
+    #[cfg(feature = "kafka")]
+    Consume(ConsumeArgs),
+    /// Apply newline-delimited transactions sent over TCP or a Unix socket, each line is answered with `ok` or why it was rejected
+    Listen(ListenArgs),
+    /// Rebuild the accounts from the journal of serve or listen and print them as CSV
+    Replay {
+        /// The journal
+        journal: PathBuf,
+
+        /// CSV file with the opening balances the journaling process started from
+        #[arg(long, value_name = "FILE")]
+        opening_balances: Option<PathBuf>,
+
+        /// Also write a snapshot of the rebuilt state
+        #[arg(long, value_name = "FILE")]
+        snapshot: Option<PathBuf>,
+    },
+    /// Run a previous release and this one on the same input and print the differences between their outputs, exits with 1 when they differ
+    Regress {
+        /// The binary of the previous release
+        #[arg(long, value_name = "PATH")]
+        baseline_bin: PathBuf,
+
+        /// The binary to compare with the baseline, this one by default
+        #[arg(long, value_name = "PATH")]
+        candidate_bin: Option<PathBuf>,
+
+        /// Transactions file given to both binaries
+        #[arg(long, value_name = "FILE")]
+        input: PathBuf,
+
+        /// Flags given to both binaries, after `--`
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Compare two account reports client by client and print the balances and locks that differ, exits with 1 when they differ
+    Reconcile {
+        /// The account report to compare against
+        old: PathBuf,
+
+        /// The account report to check
+        new: PathBuf,
+    },
+    /// Run a scenario file (`deposit 1 1 10`, `dispute 1 1`, `expect 1 available=0 held=10`, ...) on a new engine, print the accounts at the end and exit with 1 when an expectation doesn't hold
+    Scenario {
+        /// The scenario, one statement per line
+        file: PathBuf,
+    },
+    /// Run random transactions through a new engine and a reference model of it, and exit with 1 at the first transaction they disagree on or the first broken invariant
+    Simulate {
+        /// Seed of the generated transactions, the same seed generates the same ones
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Number of transactions to generate
+        #[arg(long, value_name = "COUNT", default_value_t = 100_000)]
+        transactions: usize,
+
+        /// Number of clients the transactions are spread over
+        #[arg(long, value_name = "COUNT", default_value_t = 100)]
+        clients: ClientId,
+    },
+    /// Try the ingestion tuning flags on a sample of the file and print the fastest combination
+    Tune {
+        /// CSV file with the transactions to sample
+        file: PathBuf,
+
+        /// Number of records to sample from the start of the file
+        #[arg(long, value_name = "COUNT", default_value_t = 100_000)]
+        sample: usize,
+    },
+}
+
+/// The flags of `serve`
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:8080")]
+    pub listen: String,
+
+    /// Also serve the gRPC API (proto/engine.proto) on this address, over the same engine
+    #[cfg(feature = "grpc")]
+    #[arg(long, value_name = "ADDRESS")]
+    pub grpc_listen: Option<std::net::SocketAddr>,
+
+    /// CSV file with the opening balances of the accounts (client,available,held,total,locked)
+    #[arg(long, value_name = "FILE")]
+    pub opening_balances: Option<PathBuf>,
+
+    /// Journal every transaction to this file before applying it, the transactions already in it are applied at start
+    #[arg(long, value_name = "FILE")]
+    pub journal: Option<PathBuf>,
+
+    /// On SIGINT or SIGTERM, also save the final state to this snapshot file before printing the accounts
+    #[arg(long, value_name = "FILE")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Quarantine deposits and withdrawals above this amount until they're approved or rejected through `/pending`
+    #[arg(long, value_name = "AMOUNT")]
+    pub quarantine_above: Option<Decimal>,
+
+    /// Reject the transactions whose JSON amount is a number rather than a string as malformed, as the number may have gone through a binary float before it was written
+    #[arg(long)]
+    pub strict_amounts: bool,
+
+    /// Apply the transactions on this many engines, each one owning the accounts of a share of the clients, so different clients are applied concurrently. With more than one engine transfers and merges are rejected with NOT_ALLOWED
+    #[arg(long, value_name = "COUNT", default_value_t = 1, value_parser = positive)]
+    pub workers: usize,
+
+    /// Transactions over the limits are answered with 429 over HTTP, and wait for their turn over gRPC
+    #[command(flatten)]
+    pub rate_limits: RateLimitArgs,
+
+    /// Where the posted transactions that aren't applied go
+    #[command(flatten)]
+    pub dead_letters: DeadLetterArgs,
+}
+
+/// The flags of `consume`
+#[cfg(feature = "kafka")]
+#[derive(Debug, Args)]
+pub struct ConsumeArgs {
+    /// Kafka bootstrap servers, comma separated
+    #[arg(long, value_name = "HOSTS", default_value = "localhost:9092")]
+    pub brokers: String,
+
+    /// Topic with the transactions
+    #[arg(long)]
+    pub topic: String,
+
+    /// Consumer group, its committed offsets say where to resume
+    #[arg(long, value_name = "GROUP", default_value = "transaction-engine")]
+    pub group: String,
+
+    /// Directory of the checkpoint (the accounts and the transaction history) restored at start when it holds one and written periodically, the offsets are committed with it
+    #[arg(long, value_name = "DIR")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoints
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    pub checkpoint_interval: u64,
+
+    /// Reject the transactions whose JSON amount is a number rather than a string as malformed, as the number may have gone through a binary float before it was written
+    #[arg(long)]
+    pub strict_amounts: bool,
+
+    /// Write the metrics (throttled messages) in the Prometheus text format to this file every checkpoint interval
+    #[arg(long, value_name = "FILE")]
+    pub metrics: Option<PathBuf>,
+
+    /// Messages over the limits wait for their turn
+    #[command(flatten)]
+    pub rate_limits: RateLimitArgs,
+
+    /// Where the messages that aren't applied go
+    #[command(flatten)]
+    pub dead_letters: DeadLetterArgs,
+}
+
+/// The flags of `listen`
+#[derive(Debug, Args)]
+pub struct ListenArgs {
+    /// TCP address, or `unix:PATH` for a Unix domain socket
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:7070")]
+    pub listen: ListenAddress,
+
+    /// Format of the lines, a CSV connection starts with its header line
+    #[arg(long, value_name = "FORMAT", default_value = "csv")]
+    pub format: InputFormat,
+
+    /// Reject the transactions whose JSON amount is a number rather than a string as malformed, as the number may have gone through a binary float before it was written
+    #[arg(long)]
+    pub strict_amounts: bool,
+
+    /// CSV file with the opening balances of the accounts (client,available,held,total,locked)
+    #[arg(long, value_name = "FILE")]
+    pub opening_balances: Option<PathBuf>,
+
+    /// Journal every transaction to this file before applying it, the transactions already in it are applied at start
+    #[arg(long, value_name = "FILE")]
+    pub journal: Option<PathBuf>,
+
+    /// On SIGINT or SIGTERM, also save the final state to this snapshot file before printing the accounts
+    #[arg(long, value_name = "FILE")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Where the lines that aren't applied go
+    #[command(flatten)]
+    pub dead_letters: DeadLetterArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PendingAction {
+    /// List the pending transactions as CSV
+    List {
+        /// JSON approval queue
+        queue: PathBuf,
+    },
+    /// Approve a pending transaction, it's applied by the next run
+    Approve {
+        /// JSON approval queue
+        queue: PathBuf,
+        tx: u32,
+        /// Who reviewed the transaction, recorded in the audit entries
+        #[arg(long)]
+        reviewer: Option<String>,
+    },
+    /// Reject a pending transaction, its held funds are released by the next run
+    Reject {
+        /// JSON approval queue
+        queue: PathBuf,
+        tx: u32,
+        /// Who reviewed the transaction, recorded in the audit entries
+        #[arg(long)]
+        reviewer: Option<String>,
+    },
+}
@@ -0,0 +1,99 @@
+use std::{
+    sync::{ atomic::{ AtomicU64, Ordering }, Arc },
+    time::{ SystemTime, UNIX_EPOCH },
+};
+
+// Time-based behaviors ask the clock for "now" (unix seconds) instead of reading the system time,
+// so they stay deterministic on replays and can be driven by hand in tests.
+pub trait Clock: Send {
+    fn now(&self) -> Option<u64>;
+
+    /// Called with the timestamp of every applied transaction
+    fn observe(&mut self, _timestamp: u64) {}
+
+    /// Forgets the timestamps observed, the engine replays them when it rebuilds its state
+    fn reset(&mut self) {}
+}
+
+/// Follows the latest transaction timestamp seen, the default
+#[derive(Default)]
+pub struct EventClock {
+    latest: Option<u64>,
+}
+
+impl Clock for EventClock {
+    fn now(&self) -> Option<u64> {
+        self.latest
+    }
+
+    fn observe(&mut self, timestamp: u64) {
+        self.latest = self.latest.max(Some(timestamp));
+    }
+
+    fn reset(&mut self) {
+        self.latest = None;
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Option<u64> {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs())
+    }
+}
+
+/// Only moves when advanced by hand, clones share the same time
+#[derive(Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    pub fn at(now: u64) -> Self {
+        ManualClock(Arc::new(AtomicU64::new(now)))
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.0.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Option<u64> {
+        Some(self.0.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_clock() {
+        let mut clock = EventClock::default();
+        assert_eq!(clock.now(), None);
+
+        clock.observe(20);
+        clock.observe(10);
+        assert_eq!(clock.now(), Some(20));
+
+        clock.reset();
+        assert_eq!(clock.now(), None);
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::at(100);
+        let handle = clock.clone();
+
+        handle.advance(50);
+        assert_eq!(clock.now(), Some(150));
+    }
+
+    #[test]
+    fn test_system_clock() {
+        assert!(SystemClock.now().unwrap() > 1_600_000_000);
+    }
+}
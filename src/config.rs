@@ -0,0 +1,114 @@
+use std::{ fs, io, path::Path };
+
+use toml::{ Table, Value };
+
+/// The settings of a run read from a TOML file. Every key is the long name of a command line flag
+/// without its dashes, e.g. `workers = 4`, `hold_expiry_days = 3`, `allow_negative_balance = true`
+/// or `alert = ["locked", "held>100"]` for a flag that can be repeated.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    settings: Vec<(String, Value)>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let table: Table = text.parse().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut settings = vec![];
+
+        for (key, value) in table {
+            let values = match &value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+
+            if let Some(value) = values.into_iter().find(|value| !is_scalar(value)) {
+                let err = format!("{} must be a string, a number, a boolean or an array of them, not a {}", key, value.type_str());
+
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+
+            settings.push((key.replace('-', "_"), value));
+        }
+
+        Ok(Config { settings })
+    }
+
+    /// The keys in the file, with `_` in place of `-`.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.settings.iter().map(|(key, _)| key.as_str())
+    }
+
+    /// The settings as command line arguments, leaving out the keys `skip` returns true for. A
+    /// `false` boolean leaves its flag out and an array repeats it.
+    pub fn args(&self, skip: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut args = vec![];
+
+        for (key, value) in self.settings.iter().filter(|(key, _)| !skip(key)) {
+            let flag = format!("--{}", key.replace('_', "-"));
+
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+
+            for value in values {
+                match value {
+                    Value::Boolean(true) => args.push(flag.clone()),
+                    Value::Boolean(false) => {}
+                    Value::String(text) => args.extend([flag.clone(), text.clone()]),
+                    value => args.extend([flag.clone(), value.to_string()]),
+                }
+            }
+        }
+
+        args
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    matches!(value, Value::String(_) | Value::Integer(_) | Value::Float(_) | Value::Boolean(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        let config = Config::parse(
+            "workers = 4\n\
+             allow-negative-balance = true\n\
+             idempotent = false\n\
+             fees = \"fees.json\"\n\
+             alert = [\"locked\", \"held>100\"]\n"
+        ).unwrap();
+
+        assert_eq!(config.keys().collect::<Vec<_>>(), vec![
+            "alert",
+            "allow_negative_balance",
+            "fees",
+            "idempotent",
+            "workers"
+        ]);
+        assert_eq!(config.args(|key| key == "fees"), vec![
+            "--alert",
+            "locked",
+            "--alert",
+            "held>100",
+            "--allow-negative-balance",
+            "--workers",
+            "4"
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(Config::parse("workers = ").is_err());
+        assert!(Config::parse("[disputes]\nwindow_days = 30\n").is_err());
+        assert!(Config::parse("alert = [[\"locked\"]]\n").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::{ collections::HashMap, fs::File, io, path::Path };
+
+use csv::{ ReaderBuilder, Trim };
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::types::ClientId;
+
+#[derive(Debug, Deserialize)]
+struct Row {
+    client: ClientId,
+    limit: Decimal,
+}
+
+/// The agreed overdraft of clients, how far under zero their available balance can go. Loaded from
+/// a `client,limit` CSV file, the clients not listed have no overdraft.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CreditLimits {
+    limits: HashMap<ClientId, Decimal>,
+}
+
+impl CreditLimits {
+    pub fn new(limits: impl IntoIterator<Item = (ClientId, Decimal)>) -> Self {
+        CreditLimits { limits: limits.into_iter().collect() }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        CreditLimits::from_csv(File::open(path)?)
+    }
+
+    pub fn from_csv<R: io::Read>(rdr: R) -> io::Result<Self> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(rdr);
+        let mut limits = HashMap::new();
+
+        for row in reader.deserialize::<Row>() {
+            let Row { client, limit } = row?;
+
+            let err = match limits.insert(client, limit) {
+                Some(_) => format!("client {} appears more than once", client),
+                None if limit < Decimal::ZERO => format!("client {} has a negative credit limit", client),
+                None => continue,
+            };
+
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+
+        Ok(CreditLimits { limits })
+    }
+
+    pub fn limit(&self, client_id: ClientId) -> Decimal {
+        self.limits.get(&client_id).copied().unwrap_or_default()
+    }
+}
+
+/// How much of its credit limit an account uses, the part of its available balance under zero.
+pub fn used(available: Decimal) -> Decimal {
+    (-available).max(Decimal::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let limits = CreditLimits::from_csv("client,limit\n1, 100\n2,0.5\n".as_bytes()).unwrap();
+
+        assert_eq!(limits.limit(1), dec!(100));
+        assert_eq!(limits.limit(2), dec!(0.5));
+        assert_eq!(limits.limit(3), dec!(0));
+
+        assert!(CreditLimits::from_csv("client,limit\n1,-1\n".as_bytes()).is_err());
+        assert!(CreditLimits::from_csv("client,limit\n1,1\n1,2\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_used() {
+        assert_eq!(used(dec!(-30)), dec!(30));
+        assert_eq!(used(dec!(5)), dec!(0));
+    }
+}
@@ -0,0 +1,289 @@
+pub mod conversion;
+
+use std::collections::{ BTreeMap, HashMap };
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    engine::Engine,
+    error::EngineError,
+    types::{ custom_serde, Account, ClientId, Transaction, TransactionType },
+};
+
+use conversion::ConversionTable;
+
+/// An account of the output of a multi-currency run, the balances of a client in one currency.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CurrencyAccount {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub currency: String,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub available: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub held: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl CurrencyAccount {
+    pub fn new(currency: &str, account: &Account) -> Self {
+        CurrencyAccount {
+            client_id: account.client_id,
+            currency: currency.to_string(),
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+        }
+    }
+
+    pub fn account(&self) -> Account {
+        Account {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            ..Account::new(self.client_id)
+        }
+    }
+}
+
+/// Keeps one account per client and currency, with an [`Engine`] per currency created the first
+/// time a transaction in it is seen. A transaction goes to the engine of its `currency`, or of the
+/// currency of the transaction it refers to (a dispute, a resolve, ...), or of the base currency.
+/// The currencies are independent: a chargeback only locks the account of its currency and a
+/// transfer moves funds between the accounts of one currency. Transaction ids are unique across
+/// currencies. A `convert` moves funds between the currencies of a client at the rate of the
+/// [`ConversionTable`].
+pub struct MultiCurrency<F> {
+    base: String,
+    engines: BTreeMap<String, Engine>,
+    // The currency of every transaction with its own amount, by id
+    tx_currencies: HashMap<u32, String>,
+    rates: ConversionTable,
+    build: F,
+}
+
+impl<F: FnMut(&str) -> Engine> MultiCurrency<F> {
+    /// `build` creates the engine of a currency from its code.
+    pub fn new(base: impl Into<String>, build: F) -> Self {
+        MultiCurrency {
+            base: base.into(),
+            engines: BTreeMap::new(),
+            tx_currencies: HashMap::new(),
+            rates: ConversionTable::default(),
+            build,
+        }
+    }
+
+    /// The rates of the conversions, without them every conversion is rejected with `NO_RATE`.
+    pub fn set_rates(&mut self, rates: ConversionTable) {
+        self.rates = rates;
+    }
+
+    pub fn add_transaction(&mut self, tx: Transaction) -> Result<(), EngineError> {
+        let owned = tx.tx_type.amount().is_some();
+        let known = self.tx_currencies.get(&tx.tx_id);
+        let given = tx.currency.as_deref().map(str::trim).filter(|currency| !currency.is_empty());
+
+        let currency = match (given, known) {
+            (Some(currency), _) => currency.to_string(),
+            (None, Some(known)) if !owned => known.clone(),
+            _ => self.base.clone(),
+        };
+
+        // The engine of another currency doesn't know the id
+        if owned && known.is_some_and(|known| *known != currency) {
+            return Err(EngineError::DuplicateTxId(tx.tx_id));
+        }
+
+        let tx_id = tx.tx_id;
+
+        let result = match &tx.tx_type {
+            TransactionType::Convert { to, amount } => self.convert(&tx, &currency, to, *amount),
+            _ => self.engine_mut(&currency).add_transaction(tx),
+        };
+
+        if owned && result.is_ok() {
+            self.tx_currencies.insert(tx_id, currency);
+        }
+
+        result
+    }
+
+    // Withdraws the amount from the balance of one currency and deposits it converted in the other
+    // under the same id. A deposit the other engine rejects gives the withdrawn funds back.
+    fn convert(&mut self, tx: &Transaction, from: &str, to: &str, amount: Decimal) -> Result<(), EngineError> {
+        let converted = self.rates.convert(from, to, amount).ok_or_else(|| EngineError::NoRate {
+            from: from.to_string(),
+            to: to.to_string(),
+        })?;
+
+        let source = self.engine_mut(from);
+        let before = source.get_account(tx.client_id).cloned();
+
+        source.add_transaction(Transaction { tx_type: TransactionType::Withdrawal(amount), ..tx.clone() })?;
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit(converted),
+            currency: Some(to.to_string()),
+            ..tx.clone()
+        };
+
+        let result = self.engine_mut(to).add_transaction(deposit);
+
+        if let (Err(err), Some(before)) = (&result, before) {
+            log::debug!("Giving back conversion {} after its deposit was rejected: {}", tx.tx_id, err);
+
+            self.engine_mut(from).seed_account(before);
+        }
+
+        result
+    }
+
+    fn engine_mut(&mut self, currency: &str) -> &mut Engine {
+        self.engines
+            .entry(currency.to_string())
+            .or_insert_with(|| (self.build)(currency))
+    }
+
+    pub fn engine(&self, currency: &str) -> Option<&Engine> {
+        self.engines.get(currency)
+    }
+
+    /// Consumes the engines and returns the accounts sorted by client id, then currency.
+    pub fn get_accounts(self) -> Vec<CurrencyAccount> {
+        let mut accounts = self.into_accounts();
+
+        accounts.sort_by(|a, b| (a.client_id, &a.currency).cmp(&(b.client_id, &b.currency)));
+
+        accounts
+    }
+
+    /// Consumes the engines and returns the accounts by currency, in no particular order within one.
+    pub fn into_accounts(self) -> Vec<CurrencyAccount> {
+        self.engines
+            .into_iter()
+            .flat_map(|(currency, engine)| {
+                engine
+                    .into_accounts()
+                    .into_iter()
+                    .map(move |account| CurrencyAccount::new(&currency, &account))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::types::TransactionType;
+
+    fn tx(client_id: ClientId, tx_id: u32, tx_type: TransactionType, currency: Option<&str>) -> Transaction {
+        Transaction { currency: currency.map(String::from), ..Transaction::new(client_id, tx_id, tx_type) }
+    }
+
+    #[test]
+    fn test_accounts_per_currency() {
+        let mut engines = MultiCurrency::new("USD", |_: &str| Engine::new());
+
+        engines.add_transaction(tx(1, 1, TransactionType::Deposit(dec!(10)), None)).unwrap();
+        engines.add_transaction(tx(1, 2, TransactionType::Deposit(dec!(5)), Some("EUR"))).unwrap();
+        engines.add_transaction(tx(2, 3, TransactionType::Deposit(dec!(1)), Some("EUR"))).unwrap();
+
+        // The funds of one currency can't pay for another
+        assert_eq!(
+            engines.add_transaction(tx(1, 4, TransactionType::Withdrawal(dec!(6)), Some("EUR"))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(5), required: dec!(6) })
+        );
+
+        // A dispute without a currency goes to the currency of the deposit
+        engines.add_transaction(tx(1, 2, TransactionType::Dispute, None)).unwrap();
+        engines.add_transaction(tx(1, 2, TransactionType::Chargeback, None)).unwrap();
+
+        // Ids are unique across currencies
+        assert_eq!(
+            engines.add_transaction(tx(2, 3, TransactionType::Deposit(dec!(1)), Some("USD"))),
+            Err(EngineError::DuplicateTxId(3))
+        );
+
+        assert_eq!(
+            engines.get_accounts(),
+            vec![
+                CurrencyAccount::new("EUR", &Account { locked: true, ..Account::new(1) }),
+                CurrencyAccount::new(
+                    "USD",
+                    &Account { available: dec!(10), total: dec!(10), ..Account::new(1) }
+                ),
+                CurrencyAccount::new("EUR", &Account { available: dec!(1), total: dec!(1), ..Account::new(2) })
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let convert = |tx_id, amount, to: &str| {
+            tx(1, tx_id, TransactionType::Convert { to: to.to_string(), amount }, Some("EUR"))
+        };
+
+        let mut engines = MultiCurrency::new("EUR", |_: &str| Engine::new());
+        engines.set_rates(ConversionTable::from_csv("from,to,rate,spread\nEUR,USD,1.5,0.1\n".as_bytes()).unwrap());
+
+        engines.add_transaction(tx(1, 1, TransactionType::Deposit(dec!(10)), None)).unwrap();
+        engines.add_transaction(convert(2, dec!(4), "USD")).unwrap();
+
+        assert_eq!(engines.add_transaction(convert(3, dec!(1), "GBP")), Err(EngineError::NoRate {
+            from: "EUR".to_string(),
+            to: "GBP".to_string(),
+        }));
+        assert_eq!(
+            engines.add_transaction(convert(4, dec!(7), "USD")),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(6), required: dec!(7) })
+        );
+        assert_eq!(engines.add_transaction(convert(2, dec!(1), "USD")), Err(EngineError::DuplicateTxId(2)));
+
+        assert_eq!(
+            engines.get_accounts(),
+            vec![
+                CurrencyAccount::new("EUR", &Account { available: dec!(6), total: dec!(6), ..Account::new(1) }),
+                CurrencyAccount::new("USD", &Account { available: dec!(5.4), total: dec!(5.4), ..Account::new(1) })
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert_gives_back_rejected_deposits() {
+        let mut engines = MultiCurrency::new("EUR", |_: &str| Engine::new());
+        engines.set_rates(ConversionTable::from_csv("from,to,rate\nEUR,USD,2\n".as_bytes()).unwrap());
+
+        engines.add_transaction(tx(1, 1, TransactionType::Deposit(dec!(10)), Some("USD"))).unwrap();
+        engines.add_transaction(tx(1, 1, TransactionType::Dispute, None)).unwrap();
+        engines.add_transaction(tx(1, 1, TransactionType::Chargeback, None)).unwrap();
+        engines.add_transaction(tx(1, 2, TransactionType::Deposit(dec!(3)), Some("EUR"))).unwrap();
+
+        // The USD account is locked
+        assert_eq!(
+            engines.add_transaction(tx(1, 3, TransactionType::Convert { to: "USD".to_string(), amount: dec!(1) }, None)),
+            Err(EngineError::AccountLocked(1))
+        );
+        assert_eq!(engines.engine("EUR").unwrap().get_account(1).unwrap().available, dec!(3));
+    }
+
+    #[test]
+    fn test_write_currency_column() {
+        let account = CurrencyAccount::new("EUR", &Account { available: dec!(1.5), total: dec!(1.5), ..Account::new(3) });
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&account).unwrap();
+
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "client,currency,available,held,total,locked\n3,EUR,1.5,0,1.5,false\n"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::{ collections::HashMap, fs::File, io, path::Path };
+
+use csv::{ ReaderBuilder, Trim };
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::types::custom_serde;
+
+/// A row of the rates file, `from,to,rate,spread` in CSV or an array of objects in JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    pub from: String,
+    pub to: String,
+    /// Units of `to` for one unit of `from`
+    pub rate: Decimal,
+    /// Fraction of the converted amount kept as a fee, e.g. 0.01 for 1%
+    #[serde(default, deserialize_with = "deserialize_spread")]
+    pub spread: Option<Decimal>,
+}
+
+/// The exchange rates of the `convert` transactions. Only the pairs in the table convert, the
+/// inverse of a pair isn't derived from it since the rates usually differ both ways.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversionTable {
+    rates: HashMap<(String, String), (Decimal, Decimal)>,
+}
+
+impl ConversionTable {
+    pub fn new(rates: impl IntoIterator<Item = Rate>) -> io::Result<Self> {
+        let mut table = ConversionTable::default();
+
+        for Rate { from, to, rate, spread } in rates {
+            let spread = spread.unwrap_or_default();
+
+            let err = if from == to {
+                format!("the rate from {} to itself", from)
+            } else if rate <= Decimal::ZERO {
+                format!("the rate from {} to {} isn't positive", from, to)
+            } else if spread < Decimal::ZERO || spread >= Decimal::ONE {
+                format!("the spread from {} to {} isn't between 0 and 1", from, to)
+            } else if table.rates.contains_key(&(from.clone(), to.clone())) {
+                format!("the rate from {} to {} appears more than once", from, to)
+            } else {
+                table.rates.insert((from, to), (rate, spread));
+                continue;
+            };
+
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+
+        Ok(table)
+    }
+
+    /// Loads a JSON file when its extension is `.json`, CSV otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(&path)?;
+
+        match path.as_ref().extension().is_some_and(|extension| extension == "json") {
+            true => ConversionTable::new(serde_json::from_reader::<_, Vec<Rate>>(io::BufReader::new(file))?),
+            false => ConversionTable::from_csv(file),
+        }
+    }
+
+    pub fn from_csv<R: io::Read>(rdr: R) -> io::Result<Self> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(rdr);
+        let rates = reader.deserialize().collect::<Result<Vec<Rate>, _>>()?;
+
+        ConversionTable::new(rates)
+    }
+
+    /// The amount credited in `to` for `amount` debited in `from`, net of the spread and rounded
+    /// like the balances, `None` without a rate for the pair.
+    pub fn convert(&self, from: &str, to: &str, amount: Decimal) -> Option<Decimal> {
+        let (rate, spread) = self.rates.get(&(from.to_string(), to.to_string()))?;
+
+        Some(custom_serde::rounding().round(amount * rate * (Decimal::ONE - spread)))
+    }
+}
+
+// An empty spread column is no spread
+fn deserialize_spread<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) if !text.trim().is_empty() => {
+            custom_serde::parse_decimal(&text)
+                .map(Some)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid spread {}", text)))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let table = ConversionTable::from_csv(
+            "from,to,rate,spread\nEUR,USD,1.0837,0.01\nUSD,EUR,0.92,\n".as_bytes()
+        ).unwrap();
+
+        assert_eq!(table.convert("EUR", "USD", dec!(100)), Some(dec!(107.2863)));
+        assert_eq!(table.convert("USD", "EUR", dec!(10)), Some(dec!(9.2)));
+        assert_eq!(table.convert("EUR", "GBP", dec!(10)), None);
+    }
+
+    #[test]
+    fn test_invalid_rates() {
+        let table = |csv: &str| ConversionTable::from_csv(format!("from,to,rate,spread\n{}", csv).as_bytes());
+
+        assert!(table("EUR,EUR,1,\n").is_err());
+        assert!(table("EUR,USD,0,\n").is_err());
+        assert!(table("EUR,USD,1.1,1\n").is_err());
+        assert!(table("EUR,USD,1.1,\nEUR,USD,1.2,\n").is_err());
+        assert!(table("EUR,USD,abc,\n").is_err());
+    }
+
+    #[test]
+    fn test_load_json() {
+        let path = std::env::temp_dir().join("transaction-engine-rates.json");
+        std::fs::write(&path, r#"[{"from": "EUR", "to": "USD", "rate": "1.1", "spread": "0.5"}]"#).unwrap();
+
+        let table = ConversionTable::load(&path).unwrap();
+
+        assert_eq!(table.convert("EUR", "USD", dec!(2)), Some(dec!(1.1)));
+    }
+}
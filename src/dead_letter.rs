@@ -0,0 +1,140 @@
+use std::{ fmt, fs::File, io::{ self, Write }, path::Path, sync::{ Arc, Mutex } };
+
+use serde::Serialize;
+
+use crate::reason::ReasonCode;
+
+/// A record a streaming source couldn't apply, because it couldn't be parsed or the engine rejected
+/// it, with its payload as received so it can be fixed and sent again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeadLetter {
+    /// Where the record came from, e.g. `transactions:0@42` for a Kafka message or
+    /// `127.0.0.1:50122 line 3` for a line of a connection
+    pub source: String,
+    pub reason: ReasonCode,
+    pub error: String,
+    /// The raw record, invalid UTF-8 replaced
+    pub payload: String,
+}
+
+impl DeadLetter {
+    pub fn new(source: impl Into<String>, reason: ReasonCode, error: impl fmt::Display, payload: &[u8]) -> Self {
+        DeadLetter {
+            source: source.into(),
+            reason,
+            error: error.to_string(),
+            payload: String::from_utf8_lossy(payload).into_owned(),
+        }
+    }
+}
+
+/// Receives the dead letters of every source of a running engine, from any of their tasks.
+pub trait DeadLetterSink: Send + Sync {
+    fn send(&self, letter: &DeadLetter);
+}
+
+/// Appends every dead letter to a file as a line of JSON, written at once so the lines of
+/// concurrent connections don't interleave.
+pub struct DeadLetterFile {
+    file: Mutex<File>,
+}
+
+impl DeadLetterFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+
+        Ok(DeadLetterFile { file: Mutex::new(file) })
+    }
+}
+
+impl DeadLetterSink for DeadLetterFile {
+    fn send(&self, letter: &DeadLetter) {
+        let result = serde_json::to_vec(letter)
+            .map_err(io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                self.file.lock().expect("dead letter file poisoned").write_all(&line)
+            });
+
+        if let Err(err) = result {
+            log::error!("Failed to write the dead letter from {}: {}", letter.source, err);
+        }
+    }
+}
+
+/// The sinks the dead letters of a running engine go to, shared by its sources. Without any, the
+/// records are only answered to their sender or logged.
+#[derive(Clone, Default)]
+pub struct DeadLetterQueue {
+    sinks: Arc<[Box<dyn DeadLetterSink>]>,
+}
+
+impl DeadLetterQueue {
+    pub fn new(sinks: Vec<Box<dyn DeadLetterSink>>) -> Self {
+        DeadLetterQueue { sinks: sinks.into() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub fn send(&self, letter: DeadLetter) {
+        for sink in self.sinks.iter() {
+            sink.send(&letter);
+        }
+    }
+}
+
+impl fmt::Debug for DeadLetterQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadLetterQueue").field("sinks", &self.sinks.len()).finish()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Keeps the dead letters sent to it, for the tests of the sources
+    #[derive(Clone, Default)]
+    pub struct Recorder {
+        pub letters: Arc<Mutex<Vec<DeadLetter>>>,
+    }
+
+    impl DeadLetterSink for Recorder {
+        fn send(&self, letter: &DeadLetter) {
+            self.letters.lock().unwrap().push(letter.clone());
+        }
+    }
+
+    #[test]
+    fn test_dead_letter_file() {
+        let dir = std::env::temp_dir().join("transaction-engine-dead-letters");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dead.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let recorder = Recorder::default();
+        let queue = DeadLetterQueue::new(vec![
+            Box::new(DeadLetterFile::open(&path).unwrap()),
+            Box::new(recorder.clone()),
+        ]);
+
+        queue.send(DeadLetter::new("a:0@1", ReasonCode::Malformed, "missing field `tx`", b"{\"type\":\"deposit\"}"));
+        queue.send(DeadLetter::new("a:0@2", ReasonCode::UnknownType, "unknown transaction type refund", b"\xff"));
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            concat!(
+                "{\"source\":\"a:0@1\",\"reason\":\"MALFORMED\",\"error\":\"missing field `tx`\",",
+                "\"payload\":\"{\\\"type\\\":\\\"deposit\\\"}\"}\n",
+                "{\"source\":\"a:0@2\",\"reason\":\"UNKNOWN_TYPE\",\"error\":\"unknown transaction type refund\",",
+                "\"payload\":\"\u{fffd}\"}\n"
+            )
+        );
+        assert_eq!(recorder.letters.lock().unwrap().len(), 2);
+        assert!(DeadLetterQueue::default().is_empty());
+    }
+}
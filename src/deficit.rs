@@ -0,0 +1,58 @@
+use std::io;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::types::{ custom_serde, ClientId };
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeficitAccount {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub deficit: Decimal,
+    pub since_tx: Option<u32>,
+    pub since: Option<u64>,
+    pub age_days: Option<u64>,
+}
+
+pub fn write_report<W: io::Write>(accounts: &[DeficitAccount], writer: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for account in accounts {
+        writer.serialize(account)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_write_report() {
+        let accounts = vec![
+            DeficitAccount {
+                client_id: 1,
+                deficit: dec!(2.50),
+                since_tx: Some(7),
+                since: Some(100),
+                age_days: Some(3),
+            },
+            DeficitAccount { client_id: 4, deficit: dec!(1), since_tx: None, since: None, age_days: None }
+        ];
+
+        let mut output = vec![];
+        write_report(&accounts, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,deficit,since_tx,since,age_days\n1,2.50,7,100,3\n4,1,,,\n"
+        );
+    }
+}
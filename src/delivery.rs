@@ -0,0 +1,183 @@
+use std::{
+    io,
+    net::{ TcpStream, ToSocketAddrs },
+    sync::mpsc::{ self, Receiver, RecvTimeoutError, SyncSender, TrySendError },
+    thread::{ self, JoinHandle },
+    time::Duration,
+};
+
+/// How long the observers sending updates to another service (webhooks, redis) may wait on it, so
+/// a service that hangs can't hold the engine up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryLimits {
+    pub connect_timeout: Duration,
+    /// Of every read and write on the connection
+    pub io_timeout: Duration,
+    /// Updates waiting to be sent, the ones coming while it's full are dropped
+    pub queue: usize,
+    /// How long the end of the run waits for the waiting updates to be sent
+    pub shutdown: Duration,
+}
+
+impl Default for DeliveryLimits {
+    fn default() -> Self {
+        DeliveryLimits {
+            connect_timeout: Duration::from_secs(5),
+            io_timeout: Duration::from_secs(10),
+            queue: 10_000,
+            shutdown: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Connects to the first address of the host that answers in time, reads and writes on the
+/// connection time out too.
+pub fn connect(host: &str, port: u16, limits: &DeliveryLimits) -> io::Result<TcpStream> {
+    let mut failed = io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", host));
+
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, limits.connect_timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(limits.io_timeout))?;
+                stream.set_write_timeout(Some(limits.io_timeout))?;
+
+                return Ok(stream);
+            }
+            Err(err) => failed = err,
+        }
+    }
+
+    Err(failed)
+}
+
+/// Sends updates from a worker thread, over a queue bounded by [`DeliveryLimits::queue`]. An
+/// update coming while the queue is full is dropped and counted rather than blocking the engine,
+/// and dropping the worker waits for the queue to drain until [`DeliveryLimits::shutdown`], the
+/// updates still waiting then are lost.
+pub struct DeliveryWorker<T> {
+    name: &'static str,
+    sender: Option<SyncSender<T>>,
+    worker: Option<JoinHandle<()>>,
+    // Nothing is sent on it, it's disconnected once the worker is done
+    done: Receiver<()>,
+    shutdown: Duration,
+    dropped: u64,
+}
+
+impl<T: Send + 'static> DeliveryWorker<T> {
+    /// Starts the worker, `deliver` sends an update and logs why it couldn't.
+    pub fn spawn(name: &'static str, limits: &DeliveryLimits, mut deliver: impl FnMut(T) + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(limits.queue);
+        let (finished, done) = mpsc::channel::<()>();
+
+        let worker = thread::spawn(move || {
+            let _finished = finished;
+
+            for update in receiver {
+                deliver(update);
+            }
+        });
+
+        DeliveryWorker {
+            name,
+            sender: Some(sender),
+            worker: Some(worker),
+            done,
+            shutdown: limits.shutdown,
+            dropped: 0,
+        }
+    }
+
+    pub fn send(&mut self, update: T) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        if let Err(TrySendError::Full(_)) = sender.try_send(update) {
+            if self.dropped == 0 {
+                log::warn!("The {} queue is full, dropping updates until it has room", self.name);
+            }
+
+            self.dropped += 1;
+        }
+    }
+
+    /// The updates dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl<T> Drop for DeliveryWorker<T> {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if self.dropped > 0 {
+            log::warn!("Dropped {} {} updates while the queue was full", self.dropped, self.name);
+        }
+
+        match self.done.recv_timeout(self.shutdown) {
+            Err(RecvTimeoutError::Timeout) => {
+                log::error!(
+                    "The {} worker didn't send its waiting updates within {:?}, they are lost",
+                    self.name,
+                    self.shutdown
+                );
+            }
+            _ => {
+                if let Some(worker) = self.worker.take() {
+                    let _ = worker.join();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ sync::{ Arc, Mutex }, time::Instant };
+
+    use super::*;
+
+    fn limits(queue: usize, shutdown: Duration) -> DeliveryLimits {
+        DeliveryLimits { queue, shutdown, ..DeliveryLimits::default() }
+    }
+
+    #[test]
+    fn test_delivery() {
+        let delivered = Arc::new(Mutex::new(vec![]));
+        let sent = delivered.clone();
+
+        let mut worker = DeliveryWorker::spawn("test", &limits(10, Duration::from_secs(5)), move |update| {
+            sent.lock().unwrap().push(update);
+        });
+
+        for update in 0..5 {
+            worker.send(update);
+        }
+
+        drop(worker);
+
+        assert_eq!(*delivered.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_full_queue_and_shutdown_deadline() {
+        // The service never answers
+        let mut worker = DeliveryWorker::spawn("test", &limits(2, Duration::from_millis(200)), |_: u32| {
+            thread::sleep(Duration::from_secs(60));
+        });
+
+        for update in 0..10 {
+            worker.send(update);
+        }
+
+        // One taken by the worker, at most two waiting
+        assert!(worker.dropped() >= 7);
+
+        let start = Instant::now();
+        drop(worker);
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
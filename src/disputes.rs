@@ -0,0 +1,53 @@
+use std::io;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::types::{ custom_serde, ClientId };
+
+/// A transaction under dispute, its funds held until it's resolved or charged back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OpenDispute {
+    pub tx: u32,
+    pub client: ClientId,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub amount: Decimal,
+    /// The engine clock when the dispute was opened, when it had a time
+    pub disputed_at: Option<u64>,
+    pub age_days: Option<u64>,
+}
+
+pub fn write_report<W: io::Write>(disputes: &[OpenDispute], writer: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for dispute in disputes {
+        writer.serialize(dispute)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_write_report() {
+        let disputes = vec![
+            OpenDispute { tx: 3, client: 1, amount: dec!(2.50), disputed_at: Some(100), age_days: Some(3) },
+            OpenDispute { tx: 1, client: 2, amount: dec!(1), disputed_at: None, age_days: None }
+        ];
+
+        let mut output = vec![];
+        write_report(&disputes, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tx,client,amount,disputed_at,age_days\n3,1,2.50,100,3\n1,2,1,,\n"
+        );
+    }
+}
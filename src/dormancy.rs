@@ -0,0 +1,56 @@
+use std::io;
+
+use serde::Serialize;
+
+use crate::types::ClientId;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DormantAccount {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub last_activity: u64,
+    pub idle_days: u64,
+}
+
+pub fn status(dormant: bool) -> &'static str {
+    if dormant { "dormant" } else { "active" }
+}
+
+pub fn write_report<W: io::Write>(accounts: &[DormantAccount], writer: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for account in accounts {
+        writer.serialize(account)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_report() {
+        let accounts = vec![
+            DormantAccount { client_id: 1, last_activity: 100, idle_days: 30 },
+            DormantAccount { client_id: 4, last_activity: 200, idle_days: 31 }
+        ];
+
+        let mut output = vec![];
+        write_report(&accounts, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,last_activity,idle_days\n1,100,30\n4,200,31\n"
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(status(true), "dormant");
+        assert_eq!(status(false), "active");
+    }
+}
@@ -1,534 +1,6410 @@
-use std::collections::HashMap;
+use std::{
+    any::Any,
+    collections::{ BTreeMap, HashMap, HashSet },
+    iter,
+    time::{ Duration, Instant },
+};
 
 use rust_decimal::Decimal;
 
-use crate::types::{ Account, Transaction, TransactionType };
+use crate::{
+    alert::{ AlertMonitor, AlertRule, RaisedAlert },
+    audit::{ AuditRecord, AuditSink },
+    balance_bounds::{ BalanceBounds, Overflow },
+    clock::{ Clock, EventClock },
+    deficit::DeficitAccount,
+    disputes::OpenDispute,
+    dormancy::DormantAccount,
+    error::EngineError,
+    event_log::{ EventLog, Projection },
+    events::{ EngineEvent, EventHandler },
+    credit::CreditLimits,
+    fees::{ FeeSchedule, FeeSummary },
+    hooks::EngineHooks,
+    invariants::{ InvariantViolation, Violation },
+    metrics::{ MetricsRecorder, NoopRecorder },
+    observer::{ AccountObserver, LifecycleEvent },
+    ordering::{ SequenceConflict, SequenceSummary, UpstreamSequence },
+    policy::{ Decision, Policy, RiskTier },
+    reason::ReasonCode,
+    retention::Retention,
+    risk::RiskScoring,
+    scheduler::{ DailyOperations, Operation, ReleaseKind, ScheduledRelease, Scheduler },
+    snapshot::Snapshot,
+    statement::Ledger,
+    store::{ HistoryEntry, KeptTransaction, MemoryStore, StateStore, TransactionInfo },
+    system::{ SystemAccount, SystemLedger },
+    types::{ custom_serde::{ self, Rounding }, Account, ClientId, Transaction, TransactionType },
+    validation::{ AmountValidator, Validator },
+};
 
-enum TransactionInfo {
-    Regular,
+#[derive(Debug, PartialEq, Eq)]
+enum Admission {
+    Apply,
+    /// An exact duplicate in idempotent mode, nothing to apply
+    Duplicate,
+}
+
+/// How an engine holds a transaction id, see [`Engine::holding`]. The workers of a
+/// [`ShardedEngine`](crate::shard::ShardedEngine) tell each other, so an id held by one of them is
+/// rejected by another one like a single engine would.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Holding {
+    /// In the history
+    Applied,
+    Quarantined,
+}
+
+#[derive(Default)]
+struct AccountActivity {
+    deposited: bool,
+    first_activity: Option<u64>,
+    last_activity: Option<u64>,
+}
+
+impl AccountActivity {
+    fn observe(&mut self, timestamp: u64) {
+        self.first_activity = Some(self.first_activity.map_or(timestamp, |first| first.min(timestamp)));
+        self.last_activity = self.last_activity.max(Some(timestamp));
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct DeficitStart {
+    tx_id: Option<u32>,
+    timestamp: Option<u64>,
+}
+
+/// Clients taken out of an engine by [`Engine::take_clients`], with everything it kept about them,
+/// to be put in another engine by [`Engine::put_clients`].
+pub struct MovedClients {
+    accounts: Vec<Account>,
+    deleted: Vec<ClientId>,
+    tiers: Vec<(ClientId, RiskTier)>,
+    merged: Vec<(ClientId, ClientId)>,
+    activity: Vec<(ClientId, AccountActivity)>,
+    deficits: Vec<(ClientId, DeficitStart)>,
+    duplicate_disputes: Vec<(ClientId, u32)>,
+    latest_timestamps: Vec<(ClientId, u64)>,
+    upstream: Vec<(ClientId, u64)>,
+    history: Vec<MovedTx>,
+    releases: Vec<ScheduledRelease>,
+    // The state the policies keep for the clients, by policy index
+    policies: Vec<(usize, Box<dyn Any + Send>)>,
+    // Held funds of the opening balances not backed by a hold of the moved history
+    opening_held: Decimal,
+}
+
+impl MovedClients {
+    /// The ids of the moved history.
+    pub fn tx_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.history.iter().map(|moved| moved.kept.tx_id)
+    }
+}
+
+struct MovedTx {
+    kept: KeptTransaction,
+    tx_time: Option<u64>,
+    dispute_time: Option<u64>,
+}
+
+/// Where a deposit, withdrawal or any other transaction with its own amount stands, see
+/// [`Engine::tx_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    Applied,
     UnderDispute,
+    /// Resolved, or its dispute expired
+    Resolved,
+    ChargedBack,
+    /// Charged back, then won back by a representment
+    Represented,
+    Rejected(ReasonCode),
+}
+
+impl TxStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TxStatus::Applied => "applied",
+            TxStatus::UnderDispute => "under_dispute",
+            TxStatus::Resolved => "resolved",
+            TxStatus::ChargedBack => "charged_back",
+            TxStatus::Represented => "represented",
+            TxStatus::Rejected(_) => "rejected",
+        }
+    }
+
+    pub fn reason(&self) -> Option<ReasonCode> {
+        match self {
+            TxStatus::Rejected(reason) => Some(*reason),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransactionInfo> for TxStatus {
+    fn from(info: TransactionInfo) -> Self {
+        match info {
+            TransactionInfo::UnderDispute => TxStatus::UnderDispute,
+            TransactionInfo::Resolved => TxStatus::Resolved,
+            TransactionInfo::ChargedBack => TxStatus::ChargedBack,
+            TransactionInfo::Represented => TxStatus::Represented,
+            TransactionInfo::Regular |
+            TransactionInfo::Withdrawal |
+            TransactionInfo::Adjustment |
+            TransactionInfo::Hold |
+            TransactionInfo::Expired |
+            TransactionInfo::OnHold => TxStatus::Applied,
+        }
+    }
+}
+
+/// What a submitted transaction did, with the balances of its client right after it was applied
+/// or rejected, so the submitter can confirm its effect without reading the account again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Receipt {
+    pub tx_id: u32,
+    /// The client whose balances are given, the surviving one after a merge
+    pub client_id: ClientId,
+    pub outcome: Result<(), EngineError>,
+    /// Zero for a client without an account, e.g. after a rejected first transaction
+    pub available_after: Decimal,
+    pub held_after: Decimal,
+    pub locked: bool,
+}
+
+impl Receipt {
+    pub fn new(tx_id: u32, outcome: Result<(), EngineError>, account: &Account) -> Self {
+        Receipt {
+            tx_id,
+            client_id: account.client_id,
+            outcome,
+            available_after: account.available,
+            held_after: account.held,
+            locked: account.locked,
+        }
+    }
+}
+
+// The state a transaction must still be in for its release to apply
+fn expected_info(kind: ReleaseKind) -> TransactionInfo {
+    match kind {
+        ReleaseKind::DepositHold => TransactionInfo::OnHold,
+        ReleaseKind::DisputeExpiry => TransactionInfo::UnderDispute,
+        ReleaseKind::WithdrawalHold => TransactionInfo::Hold,
+    }
+}
+
+// Without a clock time there's nothing to count from, the obligation never comes due
+fn release_time(after: Option<Duration>, now: Option<u64>) -> Option<u64> {
+    after.zip(now).map(|(after, now)| now + after.as_secs())
+}
+
+// The entries of the clients, removed from the map
+fn take<V>(map: &mut HashMap<ClientId, V>, clients: &HashSet<ClientId>) -> Vec<(ClientId, V)> {
+    clients
+        .iter()
+        .filter_map(|client_id| map.remove(client_id).map(|value| (*client_id, value)))
+        .collect()
+}
+
+// The funds a transaction of the history keeps in the held balance of its client
+fn held_funds(entry: HistoryEntry) -> Decimal {
+    match entry.info {
+        TransactionInfo::UnderDispute | TransactionInfo::Hold | TransactionInfo::OnHold => entry.amount,
+        _ => Decimal::ZERO,
+    }
+}
+
+fn check_account(account: &Account) -> Result<(), Violation> {
+    if account.total != account.available + account.held {
+        return Err(Violation::Total(account.clone()));
+    }
+
+    if account.held < Decimal::ZERO {
+        return Err(Violation::NegativeHeld(account.clone()));
+    }
+
+    Ok(())
+}
+
+pub const SECONDS_PER_DAY: u64 = 86400;
+
+pub const DEFAULT_HOLD_EXPIRY: Duration = Duration::from_secs(7 * SECONDS_PER_DAY);
+
+// Transactions between the prunings of the history, with a retention
+const PRUNE_INTERVAL: usize = 10_000;
+
+/// What the services running an engine need from it, so they work with other implementations than
+/// [`Engine`]. Accounts are returned by value, an implementation doesn't have to keep them in memory.
+pub trait EngineCore {
+    fn apply(&mut self, tx: Transaction) -> Result<(), EngineError>;
+
+    /// Applies a transaction like [`EngineCore::apply`], returning the balances of its client
+    /// afterwards along with the outcome.
+    fn submit(&mut self, tx: Transaction) -> Receipt {
+        let (tx_id, client_id) = (tx.tx_id, tx.client_id);
+        let outcome = self.apply(tx);
+        let account = self.account(client_id).unwrap_or_else(|| Account::new(client_id));
+
+        Receipt::new(tx_id, outcome, &account)
+    }
+
+    fn account(&self, client_id: ClientId) -> Option<Account>;
+
+    /// The accounts in no particular order
+    fn accounts_iter(&self) -> Box<dyn Iterator<Item = Account> + '_>;
+
+    fn snapshot(&self) -> Snapshot;
+
+    fn risk_tier(&self, _client_id: ClientId) -> RiskTier {
+        RiskTier::default()
+    }
+
+    fn tx_status(&self, _tx_id: u32) -> Option<TxStatus> {
+        None
+    }
+
+    /// The clients whose accounts the transaction changed once it was applied
+    fn changed_clients(&self, tx: &Transaction) -> Vec<ClientId> {
+        vec![tx.client_id]
+    }
+
+    /// The transactions quarantined for a review, by id
+    fn pending(&self) -> Vec<Transaction> {
+        vec![]
+    }
+}
+
+/// Applies transactions to client accounts, see the crate documentation for an example. The
+/// accounts and the history are kept in `S`, in memory by default.
+pub struct Engine<S = MemoryStore> {
+    store: S,
+    // The sending client of every transfer in the history, a chargeback gives the funds back to it
+    transfer_sources: HashMap<u32, ClientId>,
+    // Withdrawal hold expiries, deposit holds and dispute expiries by release time
+    scheduler: Scheduler,
+    operations: DailyOperations,
+    // The adjustments of the daily operations run by the last transaction, with the account right
+    // after each one, for the audit log and the statements
+    operation_txs: Vec<(Transaction, Account)>,
+    hold_expiry: Duration,
+    deposit_hold: Option<Duration>,
+    dispute_expiry: Option<Duration>,
+    dispute_window: Option<Duration>,
+    // The time of the disputable transactions, with a dispute window
+    tx_times: HashMap<u32, u64>,
+    // When the open disputes were opened, by the engine clock
+    dispute_times: HashMap<u32, u64>,
+    // Seeded releases, a rebuild starts from them
+    opening_releases: Vec<ScheduledRelease>,
+    // Seeded history, a rebuild starts from it
+    opening_history: Vec<KeptTransaction>,
+    activity: HashMap<ClientId, AccountActivity>,
+    merged: HashMap<ClientId, ClientId>,
+    deficits: HashMap<ClientId, DeficitStart>,
+    allow_negative: bool,
+    locked_deposits: bool,
+    idempotent: bool,
+    adjustments: bool,
+    unlocks: bool,
+    deletes: bool,
+    // Soft-deleted clients, their accounts are kept but hidden from the output and queries
+    deleted: HashSet<ClientId>,
+    // Rejected transactions with their own amount whose id isn't in the history
+    rejected: HashMap<u32, ReasonCode>,
+    // Seeded deleted clients, a rebuild starts from them
+    opening_deleted: HashSet<ClientId>,
+    amounts: AmountValidator,
+    validators: Vec<Box<dyn Validator>>,
+    policies: Vec<Box<dyn Policy>>,
+    hooks: Vec<Box<dyn EngineHooks>>,
+    tiers: HashMap<ClientId, RiskTier>,
+    // Seeded tiers, a rebuild starts from them
+    opening_tiers: HashMap<ClientId, RiskTier>,
+    // Disputes of transactions already under dispute, by client
+    duplicate_disputes: HashMap<ClientId, u32>,
+    duplicate_dispute_limit: Option<u32>,
+    // Times each resolved transaction was disputed again
+    redisputes: HashMap<u32, u32>,
+    redispute_limit: Option<u32>,
+    representment_unlocks: bool,
+    balance_bounds: Option<BalanceBounds>,
+    route_overflow: bool,
+    // Deposits credited up to the ceiling of their account, with routing
+    overflows: Vec<Overflow>,
+    fees: Option<FeeSchedule>,
+    fee_summary: FeeSummary,
+    credit_limits: Option<CreditLimits>,
+    risk: Option<RiskScoring>,
+    risk_lock: bool,
+    quarantined: BTreeMap<u32, Transaction>,
+    system: SystemLedger,
+    clock: Box<dyn Clock>,
+    event_log: Option<EventLog>,
+    observers: Vec<Box<dyn AccountObserver>>,
+    lifecycle_events: Vec<LifecycleEvent>,
+    event_handlers: Vec<Box<dyn EventHandler>>,
+    engine_events: Vec<EngineEvent>,
+    audit: Vec<Box<dyn AuditSink>>,
+    alerts: AlertMonitor,
+    metrics: Box<dyn MetricsRecorder>,
+    slow_apply: Option<Duration>,
+    upstream: UpstreamSequence,
+    reject_out_of_sequence: bool,
+    /// The latest timestamp of every client, kept with `reject_late_timestamps`
+    latest_timestamps: Option<HashMap<ClientId, u64>>,
+    ledger: Option<Ledger>,
+    // Held funds of the opening balances not backed by a restored hold or quarantined withdrawal
+    opening_held: Decimal,
+    invariants: Option<InvariantCheck>,
+    // How fees and interest are rounded
+    rounding: Rounding,
+    retention: Retention,
+    // Transactions given to the engine, to prune the history every `PRUNE_INTERVAL` of them
+    processed: usize,
+    // The id of the transaction being applied when another engine holds it
+    foreign_holding: Option<(u32, Holding)>,
+}
+
+// The state of the invariant check between transactions
+#[derive(Default)]
+struct InvariantCheck {
+    // The accounts and history entries written since the last check, as they were before
+    accounts: HashMap<ClientId, Option<Account>>,
+    entries: HashMap<u32, Option<HistoryEntry>>,
+    // The clients whose releases ran since the last check
+    released: HashSet<ClientId>,
+    // The held balances and the held funds of the history, kept since the first check passed over
+    // the whole state
+    totals: Option<(Decimal, Decimal)>,
+    violation: Option<InvariantViolation>,
+}
+
+/// Configures an [`Engine`] before it processes any transaction.
+#[derive(Default)]
+pub struct EngineBuilder {
+    event_sourcing: bool,
+    allow_negative: bool,
+    locked_deposits: bool,
+    idempotent: bool,
+    adjustments: bool,
+    unlocks: bool,
+    deletes: bool,
+    hold_expiry: Option<Duration>,
+    deposit_hold: Option<Duration>,
+    dispute_expiry: Option<Duration>,
+    dispute_window: Option<Duration>,
+    max_amount_scale: Option<u32>,
+    duplicate_dispute_limit: Option<u32>,
+    redispute_limit: Option<u32>,
+    representment_unlocks: bool,
+    balance_bounds: Option<BalanceBounds>,
+    route_overflow: bool,
+    fees: Option<FeeSchedule>,
+    credit_limits: Option<CreditLimits>,
+    risk: Option<RiskScoring>,
+    risk_lock: bool,
+    clock: Option<Box<dyn Clock>>,
+    validators: Vec<Box<dyn Validator>>,
+    policies: Vec<Box<dyn Policy>>,
+    hooks: Vec<Box<dyn EngineHooks>>,
+    observers: Vec<Box<dyn AccountObserver>>,
+    event_handlers: Vec<Box<dyn EventHandler>>,
+    audit: Vec<Box<dyn AuditSink>>,
+    opening_balances: Vec<Account>,
+    deleted_accounts: Vec<Account>,
+    opening_tiers: Vec<(ClientId, RiskTier)>,
+    releases: Vec<ScheduledRelease>,
+    history: Vec<KeptTransaction>,
+    projections: Vec<Box<dyn Projection>>,
+    alerts: Vec<AlertRule>,
+    metrics: Option<Box<dyn MetricsRecorder>>,
+    slow_apply: Option<Duration>,
+    reject_out_of_sequence: bool,
+    reject_late_timestamps: bool,
+    statements: bool,
+    check_invariants: bool,
+    operations: Vec<Operation>,
+    rounding: Option<Rounding>,
+    retention: Retention,
+}
+
+impl EngineBuilder {
+    /// Keeps the log of applied transactions, see [`Engine::rebuild`].
+    pub fn event_sourcing(mut self) -> Self {
+        self.event_sourcing = true;
+        self
+    }
+
+    /// Lets disputes hold more than the available funds, leaving the account in deficit.
+    pub fn allow_negative_balances(mut self) -> Self {
+        self.allow_negative = true;
+        self
+    }
+
+    /// Keeps accepting deposits into locked accounts, every other transaction is still rejected.
+    pub fn allow_locked_deposits(mut self) -> Self {
+        self.locked_deposits = true;
+        self
+    }
+
+    /// Accepts exact duplicates of earlier deposits and withdrawals (same id, client, type and
+    /// amount) without applying them again, so replaying a file is safe. Other transactions reusing
+    /// an id are still rejected.
+    pub fn idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+
+    /// Accepts `adjustment` transactions, they are rejected with `NOT_ALLOWED` otherwise.
+    pub fn allow_adjustments(mut self) -> Self {
+        self.adjustments = true;
+        self
+    }
+
+    /// Accepts `unlock` transactions reopening locked accounts, they are rejected with
+    /// `NOT_ALLOWED` otherwise.
+    pub fn allow_unlocks(mut self) -> Self {
+        self.unlocks = true;
+        self
+    }
+
+    /// Accepts `delete` and `restore` transactions hiding accounts and bringing them back, they are
+    /// rejected with `NOT_ALLOWED` otherwise.
+    pub fn allow_deletes(mut self) -> Self {
+        self.deletes = true;
+        self
+    }
+
+    /// How long a withdrawal hold lasts before its funds go back to available, 7 days by default.
+    pub fn hold_expiry(mut self, expiry: Duration) -> Self {
+        self.hold_expiry = Some(expiry);
+        self
+    }
+
+    /// Holds the funds of deposits for this long before they become available, they can't be
+    /// withdrawn meanwhile.
+    pub fn deposit_hold(mut self, hold: Duration) -> Self {
+        self.deposit_hold = Some(hold);
+        self
+    }
+
+    /// Resolves disputes still open after this long, they last until resolved or charged back
+    /// otherwise.
+    pub fn dispute_expiry(mut self, expiry: Duration) -> Self {
+        self.dispute_expiry = Some(expiry);
+        self
+    }
+
+    /// Rejects disputes filed more than this long after the transaction with
+    /// `DISPUTE_WINDOW_CLOSED`. Transactions or disputes without a time are always disputable.
+    pub fn dispute_window(mut self, window: Duration) -> Self {
+        self.dispute_window = Some(window);
+        self
+    }
+
+    /// The most decimal places an amount can have, 4 by default.
+    pub fn max_amount_scale(mut self, scale: u32) -> Self {
+        self.max_amount_scale = Some(scale);
+        self
+    }
+
+    /// Raises the risk tier of a client to high once it disputed transactions already under
+    /// dispute more than `limit` times. Those disputes are rejected either way.
+    pub fn escalate_duplicate_disputes(mut self, limit: u32) -> Self {
+        self.duplicate_dispute_limit = Some(limit);
+        self
+    }
+
+    /// Lets a resolved transaction be disputed again, up to `limit` times, like a second
+    /// presentment. Disputes past the limit are rejected with `REDISPUTE_LIMIT`, without this they
+    /// are rejected with `UNKNOWN_TX` like disputes of a charged back transaction.
+    pub fn allow_redisputes(mut self, limit: u32) -> Self {
+        self.redispute_limit = Some(limit);
+        self
+    }
+
+    /// Unlocks the account when a representment reverses one of its chargebacks, it stays locked
+    /// otherwise.
+    pub fn unlock_on_representment(mut self) -> Self {
+        self.representment_unlocks = true;
+        self
+    }
+
+    /// Keeps the balance of every account within the bounds of its class: deposits taking it over
+    /// the ceiling are rejected with `ABOVE_CEILING`, debits taking it under the floor with
+    /// `BELOW_FLOOR`.
+    pub fn balance_bounds(mut self, bounds: BalanceBounds) -> Self {
+        self.balance_bounds = Some(bounds);
+        self
+    }
+
+    /// Credits deposits over the ceiling up to it instead of rejecting them, see
+    /// [`Engine::overflows`]. A deposit into an account already at its ceiling is still rejected.
+    pub fn route_overflow(mut self) -> Self {
+        self.route_overflow = true;
+        self
+    }
+
+    /// Charges the fees of the schedule: deposits are credited net of their fee and withdrawals
+    /// debit their fee on top, see [`Engine::fee_summary`].
+    pub fn fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = Some(fees);
+        self
+    }
+
+    /// Lets withdrawals, withdrawal holds and transfers take the available balance of a client
+    /// under zero down to its credit limit.
+    pub fn credit_limits(mut self, limits: CreditLimits) -> Self {
+        self.credit_limits = Some(limits);
+        self
+    }
+
+    /// Computes the risk signals of every client, see [`Engine::risk_scoring`].
+    pub fn risk_scoring(mut self, scoring: RiskScoring) -> Self {
+        self.risk = Some(scoring);
+        self
+    }
+
+    /// Locks the account of a client once its risk signals go over a threshold of the scoring.
+    pub fn lock_risky_accounts(mut self) -> Self {
+        self.risk_lock = true;
+        self
+    }
+
+    /// Validates every transaction with this validator too, after the amount checks.
+    pub fn validator(mut self, validator: Box<dyn Validator>) -> Self {
+        self.validators.push(validator);
+        self
+    }
+
+    /// Where time-based features take the current time from, the latest transaction timestamp by
+    /// default.
+    pub fn clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Evaluates every transaction against this policy before applying it.
+    pub fn policy(mut self, policy: Box<dyn Policy>) -> Self {
+        self.policies.push(policy);
+        self
+    }
+
+    /// Runs the hooks around every transaction, in the order they were added and before the
+    /// policies.
+    pub fn hooks(mut self, hooks: Box<dyn EngineHooks>) -> Self {
+        self.hooks.push(hooks);
+        self
+    }
+
+    /// Notifies the observer of account lifecycle events.
+    pub fn observer(mut self, observer: Box<dyn AccountObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Sends the handler an event for every change to an account and every rejected transaction.
+    pub fn event_handler(mut self, handler: Box<dyn EventHandler>) -> Self {
+        self.event_handlers.push(handler);
+        self
+    }
+
+    /// Records every transaction, applied or rejected, with the balances of its client after it.
+    pub fn audit(mut self, sink: Box<dyn AuditSink>) -> Self {
+        self.audit.push(sink);
+        self
+    }
+
+    /// Seeds the accounts with the closing balances of a previous run.
+    pub fn opening_balances(mut self, accounts: impl IntoIterator<Item = Account>) -> Self {
+        self.opening_balances.extend(accounts);
+        self
+    }
+
+    /// Seeds the accounts deleted in a previous run, they stay hidden until restored.
+    pub fn deleted_accounts(mut self, accounts: impl IntoIterator<Item = Account>) -> Self {
+        self.deleted_accounts.extend(accounts);
+        self
+    }
+
+    /// Seeds the risk tiers of a previous run, e.g. from a snapshot.
+    /// Pending releases of a previous run, e.g. from a snapshot. The balances must already
+    /// account for them.
+    pub fn scheduled_releases(mut self, releases: impl IntoIterator<Item = ScheduledRelease>) -> Self {
+        self.releases.extend(releases);
+        self
+    }
+
+    pub fn risk_tiers(mut self, tiers: impl IntoIterator<Item = (ClientId, RiskTier)>) -> Self {
+        self.opening_tiers.extend(tiers);
+        self
+    }
+
+    /// The history of a previous run, e.g. from a checkpoint, seeded after the pending releases.
+    /// The balances must already account for its held funds.
+    pub fn history(mut self, history: impl IntoIterator<Item = KeptTransaction>) -> Self {
+        self.history.extend(history);
+        self
+    }
+
+    /// Registers a projection of the event log, only with event sourcing.
+    pub fn projection(mut self, projection: Box<dyn Projection>) -> Self {
+        self.projections.push(projection);
+        self
+    }
+
+    /// Raises a lifecycle event when an account starts matching the rule.
+    pub fn alert(mut self, rule: AlertRule) -> Self {
+        self.alerts.push(rule);
+        self
+    }
+
+    /// Records the engine metrics (transactions, rejections, apply time, ...) with this recorder.
+    pub fn metrics(mut self, metrics: Box<dyn MetricsRecorder>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Rejects transactions whose upstream `seq` repeats or goes back for their client, gaps are
+    /// only reported.
+    pub fn reject_out_of_sequence(mut self) -> Self {
+        self.reject_out_of_sequence = true;
+        self
+    }
+
+    /// Rejects transactions whose timestamp is older than the latest one of their client, so
+    /// every client's history is applied in time order.
+    pub fn reject_late_timestamps(mut self) -> Self {
+        self.reject_late_timestamps = true;
+        self
+    }
+
+    /// Verifies the state after every transaction, see [`Engine::verify`], and keeps the first
+    /// violation for [`Engine::invariant_violation`]. The first check passes over the whole state,
+    /// the next ones only over the accounts and history entries the transaction wrote. Locked
+    /// accounts are also checked to stay unchanged, except by their releases, the transactions of
+    /// their client they don't refuse, which may only add to their available funds or release
+    /// held ones, and admin transactions (adjustments, merges, unlocks, deletes and restores).
+    pub fn check_invariants(mut self) -> Self {
+        self.check_invariants = true;
+        self
+    }
+
+    /// Logs every transaction that takes longer than this to apply, with the state around it.
+    pub fn slow_apply_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_apply = Some(threshold);
+        self
+    }
+
+    /// Keeps the applied transactions of every client with its balances after each one, see
+    /// [`Engine::ledger`].
+    pub fn statements(mut self) -> Self {
+        self.statements = true;
+        self
+    }
+
+    /// Runs this operation on the accounts at the end of every day of the engine clock, see
+    /// [`Operation`]. Its changes are adjustments with the operation's name as the reason.
+    pub fn daily_operation(mut self, operation: Operation) -> Self {
+        self.operations.push(operation);
+        self
+    }
+
+    /// Rounds fees and interest this way. Without it they're rounded like the balances, with the
+    /// rounding set for the process by [`custom_serde::set_rounding`] when the engine is built.
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = Some(rounding);
+        self
+    }
+
+    /// Prunes the history every 10000 transactions down to this retention, see
+    /// [`Engine::prune_history`].
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn build(self) -> Engine {
+        self.build_with_store(MemoryStore::new())
+    }
+
+    /// Builds an engine keeping its state in this store, which should start empty.
+    pub fn build_with_store<S: StateStore>(self, store: S) -> Engine<S> {
+        let mut engine = Engine::with_store(store);
+
+        if self.event_sourcing {
+            engine.event_log = Some(EventLog::new());
+        }
+
+        engine.allow_negative = self.allow_negative;
+        engine.locked_deposits = self.locked_deposits;
+        engine.idempotent = self.idempotent;
+        engine.adjustments = self.adjustments;
+        engine.unlocks = self.unlocks;
+        engine.deletes = self.deletes;
+
+        if let Some(expiry) = self.hold_expiry {
+            engine.hold_expiry = expiry;
+        }
+
+        engine.deposit_hold = self.deposit_hold;
+        engine.dispute_expiry = self.dispute_expiry;
+        engine.dispute_window = self.dispute_window;
+        engine.duplicate_dispute_limit = self.duplicate_dispute_limit;
+        engine.redispute_limit = self.redispute_limit;
+        engine.representment_unlocks = self.representment_unlocks;
+        engine.balance_bounds = self.balance_bounds;
+        engine.route_overflow = self.route_overflow;
+        engine.fees = self.fees;
+        engine.credit_limits = self.credit_limits;
+        engine.risk = self.risk;
+        engine.risk_lock = self.risk_lock;
+
+        if let Some(scale) = self.max_amount_scale {
+            engine.set_max_amount_scale(scale);
+        }
+
+        if let Some(clock) = self.clock {
+            engine.set_clock(clock);
+        }
+
+        for validator in self.validators {
+            engine.add_validator(validator);
+        }
+
+        for policy in self.policies {
+            engine.add_policy(policy);
+        }
+
+        for hooks in self.hooks {
+            engine.add_hooks(hooks);
+        }
+
+        for observer in self.observers {
+            engine.add_observer(observer);
+        }
+
+        for handler in self.event_handlers {
+            engine.add_event_handler(handler);
+        }
+
+        for sink in self.audit {
+            engine.add_audit_sink(sink);
+        }
+
+        for account in self.opening_balances {
+            engine.seed_account(account);
+        }
+
+        for account in self.deleted_accounts {
+            engine.seed_deleted_account(account);
+        }
+
+        for (client_id, tier) in self.opening_tiers {
+            engine.seed_risk_tier(client_id, tier);
+        }
+
+        for release in self.releases {
+            engine.seed_release(release);
+        }
+
+        for kept in self.history {
+            engine.seed_history(kept);
+        }
+
+        for projection in self.projections {
+            engine.add_projection(projection);
+        }
+
+        for rule in self.alerts {
+            engine.add_alert(rule);
+        }
+
+        if let Some(metrics) = self.metrics {
+            engine.set_metrics(metrics);
+        }
+
+        engine.slow_apply = self.slow_apply;
+        engine.reject_out_of_sequence = self.reject_out_of_sequence;
+
+        if self.reject_late_timestamps {
+            engine.latest_timestamps = Some(HashMap::new());
+        }
+
+        if self.check_invariants {
+            engine.invariants = Some(InvariantCheck::default());
+        }
+
+        if self.statements {
+            engine.ledger = Some(Ledger::new());
+        }
+
+        engine.operations = DailyOperations::new(self.operations);
+
+        if let Some(rounding) = self.rounding {
+            engine.rounding = rounding;
+        }
+
+        engine.retention = self.retention;
+
+        engine
+    }
 }
 
-pub struct Engine {
-    accounts: HashMap<u16, Account>,
-    history: HashMap<u32, (TransactionInfo, Decimal)>,
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
 }
 
 impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
     pub fn new() -> Self {
-        Engine { accounts: HashMap::new(), history: HashMap::new() }
+        Engine::with_store(MemoryStore::new())
     }
 
-    pub fn add_transaction(&mut self, tx: Transaction) {
-        let account = self.accounts.entry(tx.client_id).or_insert(Account::new(tx.client_id));
+    pub fn event_sourced() -> Self {
+        Engine { event_log: Some(EventLog::new()), ..Engine::new() }
+    }
+}
 
-        log::info!("{:?}", tx);
+impl<S: StateStore> Engine<S> {
+    pub fn with_store(store: S) -> Self {
+        Engine {
+            store,
+            transfer_sources: HashMap::new(),
+            scheduler: Scheduler::new(),
+            operations: DailyOperations::default(),
+            operation_txs: vec![],
+            hold_expiry: DEFAULT_HOLD_EXPIRY,
+            deposit_hold: None,
+            dispute_expiry: None,
+            dispute_window: None,
+            tx_times: HashMap::new(),
+            dispute_times: HashMap::new(),
+            opening_releases: vec![],
+            opening_history: vec![],
+            activity: HashMap::new(),
+            merged: HashMap::new(),
+            deficits: HashMap::new(),
+            allow_negative: false,
+            locked_deposits: false,
+            idempotent: false,
+            adjustments: false,
+            unlocks: false,
+            deletes: false,
+            deleted: HashSet::new(),
+            rejected: HashMap::new(),
+            opening_deleted: HashSet::new(),
+            amounts: AmountValidator::default(),
+            validators: vec![],
+            policies: vec![],
+            hooks: vec![],
+            tiers: HashMap::new(),
+            opening_tiers: HashMap::new(),
+            duplicate_disputes: HashMap::new(),
+            duplicate_dispute_limit: None,
+            redisputes: HashMap::new(),
+            redispute_limit: None,
+            representment_unlocks: false,
+            balance_bounds: None,
+            route_overflow: false,
+            overflows: vec![],
+            fees: None,
+            fee_summary: FeeSummary::default(),
+            credit_limits: None,
+            risk: None,
+            risk_lock: false,
+            quarantined: BTreeMap::new(),
+            system: SystemLedger::new(),
+            clock: Box::new(EventClock::default()),
+            event_log: None,
+            observers: vec![],
+            lifecycle_events: vec![],
+            event_handlers: vec![],
+            engine_events: vec![],
+            audit: vec![],
+            alerts: AlertMonitor::default(),
+            metrics: Box::new(NoopRecorder),
+            slow_apply: None,
+            upstream: UpstreamSequence::new(),
+            reject_out_of_sequence: false,
+            latest_timestamps: None,
+            ledger: None,
+            opening_held: Decimal::ZERO,
+            invariants: None,
+            rounding: custom_serde::rounding(),
+            retention: Retention::default(),
+            processed: 0,
+            foreign_holding: None,
+        }
+    }
 
-        match tx.tx_type {
-            TransactionType::Deposit(amount) => {
-                account.available += amount;
-                self.history.insert(tx.tx_id, (TransactionInfo::Regular, amount));
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
 
-                log::debug!("Successfull deposit of {}", amount);
-            }
-            TransactionType::Withdrawal(amount) => {
-                if account.available >= amount {
-                    account.available -= amount;
+    pub fn allow_negative_balances(&mut self) {
+        self.allow_negative = true;
+    }
 
-                    log::debug!("Successfull withdraw of {}", amount);
-                }
-            }
-            TransactionType::Dispute => {
-                if let Some((TransactionInfo::Regular, amount)) = self.history.get(&tx.tx_id) {
-                    if account.available >= *amount {
-                        account.available -= *amount;
-                        account.held += *amount;
+    pub fn allow_locked_deposits(&mut self) {
+        self.locked_deposits = true;
+    }
 
-                        log::debug!("Successfull dispute of {} {}", tx.tx_id, *amount);
+    pub fn allow_adjustments(&mut self) {
+        self.adjustments = true;
+    }
 
-                        self.history.insert(tx.tx_id, (TransactionInfo::UnderDispute, *amount));
-                    }
-                }
-            }
-            TransactionType::Resolve => {
-                if let Some((TransactionInfo::UnderDispute, amount)) = self.history.get(&tx.tx_id) {
-                    account.available += *amount;
-                    account.held -= *amount;
+    pub fn allow_unlocks(&mut self) {
+        self.unlocks = true;
+    }
 
-                    log::debug!("Successfull resolve of {} {}", tx.tx_id, *amount);
+    pub fn allow_deletes(&mut self) {
+        self.deletes = true;
+    }
 
-                    self.history.remove(&tx.tx_id);
-                }
-            }
-            TransactionType::Chargeback => {
-                if let Some((TransactionInfo::UnderDispute, amount)) = self.history.get(&tx.tx_id) {
-                    account.held -= *amount;
-                    account.locked = true;
+    pub fn set_max_amount_scale(&mut self, scale: u32) {
+        self.amounts.max_scale = scale;
+    }
 
-                    log::debug!("Successfull chargeback of {} {}", tx.tx_id, *amount);
+    pub fn add_validator(&mut self, validator: Box<dyn Validator>) {
+        self.validators.push(validator);
+    }
 
-                    self.history.remove(&tx.tx_id);
-                }
-            }
-        }
+    pub fn add_policy(&mut self, policy: Box<dyn Policy>) {
+        self.policies.push(policy);
+    }
+
+    pub fn add_hooks(&mut self, hooks: Box<dyn EngineHooks>) {
+        self.hooks.push(hooks);
+    }
+
+    /// The conflicts found in the upstream `seq` of the transactions so far.
+    pub fn sequence_summary(&self) -> SequenceSummary {
+        self.upstream.summary()
+    }
+
+    /// The current time according to the engine clock, if it knows it.
+    pub fn now(&self) -> Option<u64> {
+        self.clock.now()
+    }
+
+    /// The risk tier of the client, `Standard` until a policy changes it.
+    pub fn risk_tier(&self, client_id: ClientId) -> RiskTier {
+        self.tiers.get(&client_id).copied().unwrap_or_default()
+    }
+
+    /// Where a transaction stands, `None` if its id was never seen, was pruned from the history or
+    /// is quarantined. Only transactions with their own amount (deposits, withdrawals, transfers,
+    /// ...) have a status, a dispute changes the status of the transaction it refers to.
+    pub fn tx_status(&self, tx_id: u32) -> Option<TxStatus> {
+        match self.store.get_tx(tx_id) {
+            Some(entry) => Some(entry.info.into()),
+            None => self.rejected.get(&tx_id).copied().map(TxStatus::Rejected),
+        }
+    }
+
+    /// Whether the transaction id is in the history or quarantined, pruned ids aren't held.
+    pub fn holding(&self, tx_id: u32) -> Option<Holding> {
+        match (self.store.has_tx(tx_id), self.quarantined.contains_key(&tx_id)) {
+            (true, _) => Some(Holding::Applied),
+            (_, true) => Some(Holding::Quarantined),
+            _ => None,
+        }
+    }
+
+    /// How many times each client disputed a transaction already under dispute.
+    pub fn duplicate_disputes(&self) -> impl Iterator<Item = (ClientId, u32)> + '_ {
+        self.duplicate_disputes.iter().map(|(client_id, count)| (*client_id, *count))
+    }
+
+    /// The fees charged so far by transaction type, with [`EngineBuilder::fees`].
+    pub fn fee_summary(&self) -> &FeeSummary {
+        &self.fee_summary
+    }
+
+    /// The risk signals of the clients, with [`EngineBuilder::risk_scoring`].
+    pub fn risk_scoring(&self) -> Option<&RiskScoring> {
+        self.risk.as_ref()
+    }
+
+    /// How far under zero the available balance of the client can go, zero without an overdraft.
+    pub fn credit_limit(&self, client_id: ClientId) -> Decimal {
+        self.credit_limits.as_ref().map_or(Decimal::ZERO, |limits| limits.limit(client_id))
+    }
+
+    /// The statements of the clients, only when built with [`EngineBuilder::statements`].
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    /// The deposits credited up to the ceiling of their account, in the order they were applied.
+    pub fn overflows(&self) -> &[Overflow] {
+        &self.overflows
+    }
+
+    /// The transactions waiting for an `approve` or `decline` row, by transaction id.
+    pub fn quarantined(&self) -> impl Iterator<Item = &Transaction> {
+        self.quarantined.values()
+    }
+
+    // Parks a transaction quarantined in a previous run, its held funds are already part of the
+    // seeded balances
+    pub fn restore_quarantined(&mut self, tx: Transaction) {
+        if let Some(event_log) = &mut self.event_log {
+            event_log.seed_quarantined(tx.clone());
+        }
+
+        match tx.tx_type {
+            TransactionType::Deposit(amount) => self.system.credit(SystemAccount::Escrow, amount),
+            // Held in the opening balances already
+            TransactionType::Withdrawal(amount) => self.opening_held -= amount,
+            _ => {}
+        }
+
+        self.quarantined.insert(tx.tx_id, tx);
+    }
+
+    /// The internal accounts on the other side of chargebacks, quarantined deposits and fees.
+    pub fn system_accounts(&self) -> &SystemLedger {
+        &self.system
+    }
+
+    /// Sets the opening balance of an account.
+    pub fn seed_account(&mut self, account: Account) {
+        log::debug!("Seeding account {:?}", account);
+
+        if account.available < Decimal::ZERO {
+            self.deficits.insert(account.client_id, DeficitStart::default());
+        }
+
+        if let Some(event_log) = &mut self.event_log {
+            event_log.seed(account.clone());
+        }
+
+        self.opening_held += account.held;
+        self.activity.insert(account.client_id, AccountActivity {
+            deposited: true,
+            ..Default::default()
+        });
+        if !self.deleted.contains(&account.client_id) {
+            for observer in self.observers.iter_mut() {
+                observer.on_balance_change(&account);
+            }
+        }
+
+        self.write_account(account);
+    }
+
+    /// Sets the opening balance of an account deleted in a previous run.
+    pub fn seed_deleted_account(&mut self, account: Account) {
+        self.opening_deleted.insert(account.client_id);
+        self.deleted.insert(account.client_id);
+        self.seed_account(account);
+    }
+
+    /// Sets the risk tier of a client before any transaction.
+    pub fn seed_risk_tier(&mut self, client_id: ClientId, tier: RiskTier) {
+        self.opening_tiers.insert(client_id, tier);
+        self.tiers.insert(client_id, tier);
+    }
+
+    /// Restores a pending release and the transaction it applies to, so the transaction can
+    /// still be committed, resolved or charged back. Its funds must be in the held balance.
+    pub fn seed_release(&mut self, release: ScheduledRelease) {
+        self.opening_held -= release.amount;
+        self.opening_releases.push(release.clone());
+        self.restore_release(release);
+    }
+
+    fn restore_release(&mut self, release: ScheduledRelease) {
+        if self.store.has_tx(release.tx_id) {
+            log::warn!("Ignoring release of transaction {}, its id is already used", release.tx_id);
+            return;
+        }
+
+        self.write_tx(release.tx_id, HistoryEntry {
+            info: expected_info(release.kind),
+            client_id: release.client_id,
+            amount: release.amount,
+        });
+        self.store.push_order(release.tx_id, None);
+        self.scheduler.schedule(release);
+    }
+
+    /// Restores a transaction of the history of a previous run, so it can still be disputed,
+    /// resolved or charged back. The funds it holds must be in the held balance. A transaction
+    /// whose pending release was seeded only gets what the release doesn't carry.
+    pub fn seed_history(&mut self, kept: KeptTransaction) {
+        let holds = matches!(
+            kept.entry.info,
+            TransactionInfo::UnderDispute | TransactionInfo::Hold | TransactionInfo::OnHold
+        );
+
+        if holds && !self.store.has_tx(kept.tx_id) {
+            self.opening_held -= kept.entry.amount;
+        }
+
+        self.opening_history.push(kept);
+        self.restore_history(kept);
+    }
+
+    fn restore_history(&mut self, kept: KeptTransaction) {
+        if !self.store.has_tx(kept.tx_id) {
+            self.write_tx(kept.tx_id, kept.entry);
+            self.store.push_order(kept.tx_id, kept.timestamp);
+        }
+
+        let disputable = matches!(
+            kept.entry.info,
+            TransactionInfo::Regular |
+                TransactionInfo::UnderDispute |
+                TransactionInfo::Resolved |
+                TransactionInfo::ChargedBack |
+                TransactionInfo::Represented |
+                TransactionInfo::OnHold
+        );
+
+        if let Some(timestamp) = kept.timestamp.filter(|_| disputable && self.dispute_window.is_some()) {
+            self.tx_times.insert(kept.tx_id, timestamp);
+        }
+
+        if let Some(sender) = kept.sender {
+            self.transfer_sources.insert(kept.tx_id, sender);
+        }
+
+        if kept.redisputes > 0 {
+            self.redisputes.insert(kept.tx_id, kept.redisputes);
+        }
+    }
+
+    /// The transactions of the history in the order they were added, with what
+    /// [`Engine::seed_history`] needs to carry them over to another engine.
+    pub fn history(&self) -> impl Iterator<Item = KeptTransaction> + '_ {
+        let mut seen = HashSet::new();
+
+        self.store
+            .order()
+            .filter(move |(tx_id, _)| seen.insert(*tx_id))
+            .filter_map(|(tx_id, timestamp)| {
+                self.store.get_tx(tx_id).map(|entry| KeptTransaction {
+                    tx_id,
+                    entry,
+                    timestamp,
+                    sender: self.transfer_sources.get(&tx_id).copied(),
+                    redisputes: self.redisputes.get(&tx_id).copied().unwrap_or_default(),
+                })
+            })
+    }
+
+    /// Takes the clients out of the engine with their accounts, history and pending releases, to
+    /// put them in another engine with [`Engine::put_clients`]. A transaction of the history goes
+    /// with the client of its entry, so the clients of a transfer or a merge have to move together.
+    /// Quarantined transactions and the internal accounts stay in this engine.
+    pub fn take_clients(&mut self, clients: &HashSet<ClientId>) -> MovedClients {
+        let kept: Vec<KeptTransaction> = self
+            .history()
+            .filter(|kept| clients.contains(&kept.entry.client_id))
+            .collect();
+
+        let mut holds = Decimal::ZERO;
+        let mut history = Vec::with_capacity(kept.len());
+
+        for kept in kept {
+            if
+                matches!(
+                    kept.entry.info,
+                    TransactionInfo::UnderDispute | TransactionInfo::Hold | TransactionInfo::OnHold
+                )
+            {
+                holds += kept.entry.amount;
+            }
+
+            self.delete_tx(kept.tx_id);
+            self.transfer_sources.remove(&kept.tx_id);
+            self.redisputes.remove(&kept.tx_id);
+
+            history.push(MovedTx {
+                kept,
+                tx_time: self.tx_times.remove(&kept.tx_id),
+                dispute_time: self.dispute_times.remove(&kept.tx_id),
+            });
+        }
+
+        self.store.compact_order();
+
+        let accounts: Vec<Account> = clients
+            .iter()
+            .filter_map(|client_id| self.delete_account(*client_id))
+            .collect();
+        let opening_held = accounts.iter().map(|account| account.held).sum::<Decimal>() - holds;
+
+        self.opening_held -= opening_held;
+
+        let merged = clients
+            .iter()
+            .filter_map(|from| self.merged.remove(from).map(|into| (*from, into)))
+            .collect();
+        let latest_timestamps = match &mut self.latest_timestamps {
+            Some(latest) => clients
+                .iter()
+                .filter_map(|client_id| latest.remove(client_id).map(|timestamp| (*client_id, timestamp)))
+                .collect(),
+            None => vec![],
+        };
+        let mut policies = vec![];
+
+        for (index, policy) in self.policies.iter_mut().enumerate() {
+            for client_id in clients {
+                if let Some(state) = policy.take_client(*client_id) {
+                    policies.push((index, state));
+                }
+            }
+        }
+
+        MovedClients {
+            accounts,
+            deleted: clients.iter().copied().filter(|client_id| self.deleted.remove(client_id)).collect(),
+            tiers: take(&mut self.tiers, clients),
+            merged,
+            activity: take(&mut self.activity, clients),
+            deficits: take(&mut self.deficits, clients),
+            duplicate_disputes: take(&mut self.duplicate_disputes, clients),
+            latest_timestamps,
+            upstream: clients
+                .iter()
+                .filter_map(|client_id| self.upstream.take_client(*client_id).map(|last| (*client_id, last)))
+                .collect(),
+            history,
+            releases: self.scheduler.take_clients(clients),
+            policies,
+            opening_held,
+        }
+    }
+
+    /// Puts the clients taken out of another engine, built the same way, by [`Engine::take_clients`].
+    /// Their transaction ids must not be in the history of this engine.
+    pub fn put_clients(&mut self, moved: MovedClients) {
+        for account in moved.accounts {
+            self.write_account(account);
+        }
+
+        self.opening_held += moved.opening_held;
+        self.deleted.extend(moved.deleted);
+        self.tiers.extend(moved.tiers);
+        self.merged.extend(moved.merged);
+        self.activity.extend(moved.activity);
+        self.deficits.extend(moved.deficits);
+        self.duplicate_disputes.extend(moved.duplicate_disputes);
+
+        if let Some(latest) = &mut self.latest_timestamps {
+            latest.extend(moved.latest_timestamps);
+        }
+
+        for (client_id, last) in moved.upstream {
+            self.upstream.put_client(client_id, last);
+        }
+
+        for MovedTx { kept, tx_time, dispute_time } in moved.history {
+            self.write_tx(kept.tx_id, kept.entry);
+            self.store.push_order(kept.tx_id, kept.timestamp);
+
+            if let Some(sender) = kept.sender {
+                self.transfer_sources.insert(kept.tx_id, sender);
+            }
+
+            if kept.redisputes > 0 {
+                self.redisputes.insert(kept.tx_id, kept.redisputes);
+            }
+
+            if let Some(time) = tx_time {
+                self.tx_times.insert(kept.tx_id, time);
+            }
+
+            if let Some(time) = dispute_time {
+                self.dispute_times.insert(kept.tx_id, time);
+            }
+        }
+
+        for release in moved.releases {
+            self.scheduler.schedule(release);
+        }
+
+        for (index, state) in moved.policies {
+            self.policies[index].put_client(state);
+        }
+    }
+
+    /// The releases still to come, in time order.
+    pub fn scheduled_releases(&self) -> impl Iterator<Item = &ScheduledRelease> {
+        self.scheduler.iter().filter(|release| {
+            self.store
+                .get_tx(release.tx_id)
+                .is_some_and(|entry| entry.info == expected_info(release.kind))
+        })
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn AccountObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn add_event_handler(&mut self, handler: Box<dyn EventHandler>) {
+        self.event_handlers.push(handler);
+    }
+
+    pub fn add_audit_sink(&mut self, sink: Box<dyn AuditSink>) {
+        self.audit.push(sink);
+    }
+
+    pub fn set_metrics(&mut self, metrics: Box<dyn MetricsRecorder>) {
+        self.metrics = metrics;
+    }
+
+    pub fn add_alert(&mut self, rule: AlertRule) {
+        self.alerts.add_rule(rule);
+    }
+
+    /// Every alert raised so far, see [`AlertMonitor::report`].
+    pub fn alerts_report(&self) -> Vec<RaisedAlert> {
+        self.alerts.report()
+    }
+
+    /// Applies a transaction, or returns why it was rejected without changing any balance.
+    pub fn add_transaction(&mut self, tx: Transaction) -> Result<(), EngineError> {
+        let result = self.process(tx);
+
+        self.record_gauges();
+
+        result
+    }
+
+    /// Applies a transaction like [`Engine::add_transaction`], its id held by another engine
+    /// owning other clients. A transaction reusing the id is a duplicate, one referring to it
+    /// refers to the transaction of another client.
+    pub fn add_transaction_held_elsewhere(&mut self, tx: Transaction, holding: Holding) -> Result<(), EngineError> {
+        self.foreign_holding = Some((tx.tx_id, holding));
+
+        let result = self.add_transaction(tx);
+
+        self.foreign_holding = None;
+
+        result
+    }
+
+    /// Applies a transaction like [`Engine::add_transaction`], returning a receipt with the
+    /// balances of its client afterwards.
+    pub fn submit(&mut self, tx: Transaction) -> Receipt {
+        let (tx_id, client_id) = (tx.tx_id, tx.client_id);
+        let outcome = self.add_transaction(tx);
+        let client_id = self.resolve_client(client_id);
+
+        match self.get_account(client_id) {
+            Some(account) => Receipt::new(tx_id, outcome, account),
+            None => Receipt::new(tx_id, outcome, &Account::new(client_id)),
+        }
+    }
+
+    /// Applies transactions in order like [`Engine::add_transaction`], returning the result of
+    /// each one, e.g. for the records of a Kafka poll. The gauges of the metrics recorder are only
+    /// updated once, after the last one.
+    pub fn add_transactions(
+        &mut self,
+        txs: impl IntoIterator<Item = Transaction>
+    ) -> Vec<Result<(), EngineError>> {
+        let results = txs.into_iter().map(|tx| self.process(tx)).collect();
+
+        self.record_gauges();
+
+        results
+    }
+
+    // Applies a transaction, the gauges are left for the caller to update
+    fn process(&mut self, mut tx: Transaction) -> Result<(), EngineError> {
+        log::info!("{:?}", tx);
+
+        self.processed += 1;
+
+        if self.retention.is_enabled() && self.processed.is_multiple_of(PRUNE_INTERVAL) {
+            let retention = self.retention;
+            self.prune_history(&retention);
+        }
+
+        if !matches!(tx.tx_type, TransactionType::Merge(_)) {
+            tx.client_id = self.resolve_client(tx.client_id);
+        }
+
+        let start = Instant::now();
+        let result = match self.store.failure() {
+            Some(failure) => Err(failure),
+            None => self.apply(&tx),
+        };
+        let took = start.elapsed();
+        // A store failing while the transaction is applied leaves its state untrusted, whatever
+        // the outcome
+        let result = self.store.failure().map_or(result, Err);
+
+        self.record_operations();
+
+        if self.invariants.is_some() {
+            self.check_invariants(&tx);
+        }
+
+        self.record_metrics(&tx, &result, took);
+
+        if self.slow_apply.is_some_and(|threshold| took > threshold) {
+            self.report_slow_apply(&tx, &result, took);
+        }
+
+        let alerts = match result.is_ok() {
+            true => self.check_alerts(&tx),
+            false => vec![],
+        };
+
+        self.record_audit(&tx, &result, alerts);
+
+        if let Err(err) = &result {
+            self.engine_events.push(EngineEvent::TransactionRejected {
+                client_id: tx.client_id,
+                tx_id: tx.tx_id,
+                reason: err.reason(),
+            });
+        }
+
+        self.notify_observers();
+        self.notify_event_handlers();
+
+        if tx.tx_type.amount().is_some() {
+            match &result {
+                Ok(()) => {
+                    self.rejected.remove(&tx.tx_id);
+                }
+                // A reused id is rejected too, the transaction in the history keeps its status
+                Err(err) if self.store.get_tx(tx.tx_id).is_none() => {
+                    self.rejected.insert(tx.tx_id, err.reason());
+                }
+                Err(_) => {}
+            }
+        }
+
+        match &result {
+            Ok(()) => {
+                for client_id in self.affected_clients(&tx) {
+                    self.notify_balance_change(client_id);
+                    self.run_after_hooks(&tx, client_id);
+                    self.record_statement(&tx, client_id);
+                }
+
+                if let Some(event_log) = &mut self.event_log {
+                    event_log.append(tx);
+                }
+            }
+            Err(err) => {
+                log::debug!("Rejected transaction {}: {}", tx.tx_id, err);
+            }
+        }
+
+        result
+    }
+
+    /// Checks that every account's total is its available plus held balance, that no held balance
+    /// is negative and that the held balances add up to the open disputes, withdrawal holds,
+    /// deposits on hold and quarantined withdrawals, plus the held funds of the opening balances.
+    pub fn verify(&self) -> Result<(), Violation> {
+        self.verify_totals().map(|_| ())
+    }
+
+    // Verifies the whole state, returning the held balances and the held funds of the history
+    fn verify_totals(&self) -> Result<(Decimal, Decimal), Violation> {
+        let mut held = Decimal::ZERO;
+
+        for account in self.store.accounts() {
+            check_account(account)?;
+
+            held += account.held;
+        }
+
+        let mut seen = HashSet::new();
+        let mut history = Decimal::ZERO;
+
+        for (tx_id, _) in self.store.order() {
+            if !seen.insert(tx_id) {
+                continue;
+            }
+
+            history += self.store.get_tx(tx_id).map_or(Decimal::ZERO, held_funds);
+        }
+
+        self.check_held(held, history)?;
+
+        Ok((held, history))
+    }
+
+    // The held funds of the history must be the held balances, less the funds held by
+    // quarantined withdrawals and the opening balances
+    fn check_held(&self, held: Decimal, history: Decimal) -> Result<(), Violation> {
+        let mut holds = history + self.opening_held;
+
+        for tx in self.quarantined.values() {
+            if let TransactionType::Withdrawal(amount) = tx.tx_type {
+                holds += amount;
+            }
+        }
+
+        match held == holds {
+            true => Ok(()),
+            false => Err(Violation::Held { held, holds }),
+        }
+    }
+
+    /// The first failure of the state store, every transaction is rejected with it from then on.
+    pub fn store_failure(&self) -> Option<EngineError> {
+        self.store.failure()
+    }
+
+    /// The first invariant violation, with [`EngineBuilder::check_invariants`].
+    pub fn invariant_violation(&self) -> Option<&InvariantViolation> {
+        self.invariants.as_ref()?.violation.as_ref()
+    }
+
+    fn check_invariants(&mut self, tx: &Transaction) {
+        let Some(check) = &mut self.invariants else {
+            return;
+        };
+
+        let accounts = std::mem::take(&mut check.accounts);
+        let entries = std::mem::take(&mut check.entries);
+        let released = std::mem::take(&mut check.released);
+
+        if check.violation.is_some() {
+            return;
+        }
+
+        let result = match check.totals {
+            Some(totals) => self.verify_changes(tx, totals, &accounts, &entries, &released),
+            None => self.verify_totals(),
+        };
+
+        let Some(check) = &mut self.invariants else {
+            return;
+        };
+
+        match result {
+            Ok(totals) => {
+                check.totals = Some(totals);
+            }
+            Err(violation) => {
+                let violation = InvariantViolation { tx: tx.clone(), violation };
+
+                log::error!("Invariant violated {}", violation);
+
+                check.violation = Some(violation);
+            }
+        }
+    }
+
+    // Verifies the accounts and history entries written by a transaction, given what they were
+    // before it, and carries the held totals of the previous check forward
+    fn verify_changes(
+        &self,
+        tx: &Transaction,
+        (mut held, mut history): (Decimal, Decimal),
+        accounts: &HashMap<ClientId, Option<Account>>,
+        entries: &HashMap<u32, Option<HistoryEntry>>,
+        released: &HashSet<ClientId>
+    ) -> Result<(Decimal, Decimal), Violation> {
+        // Admin operations, and representments taking the refund of a transfer back from a
+        // locked sender
+        let admin = matches!(
+            tx.tx_type,
+            TransactionType::Adjustment { .. } |
+                TransactionType::Merge(_) |
+                TransactionType::Unlock |
+                TransactionType::Delete |
+                TransactionType::Restore |
+                TransactionType::Representment
+        );
+        // The transactions a locked account refuses, see `check_locked`
+        let refused = match tx.tx_type {
+            TransactionType::Deposit(_) => !self.locked_deposits,
+            TransactionType::Withdrawal(_) |
+            TransactionType::Transfer { .. } |
+            TransactionType::WithdrawHold(_) |
+            TransactionType::Dispute => true,
+            _ => false,
+        };
+        let affected = self.affected_clients(tx);
+
+        let mut clients: Vec<&ClientId> = accounts.keys().collect();
+        clients.sort_unstable();
+
+        for client_id in clients {
+            let before = accounts[client_id].as_ref();
+            let after = self.store.get_account(*client_id);
+
+            if let Some(after) = after {
+                check_account(after)?;
+            }
+
+            held += after.map_or(Decimal::ZERO, |account| account.held);
+            held -= before.map_or(Decimal::ZERO, |account| account.held);
+
+            let Some(before) = before.filter(|before| before.locked && !admin) else {
+                continue;
+            };
+
+            let after = after.cloned().unwrap_or(Account::new(*client_id));
+
+            if after == *before {
+                continue;
+            }
+
+            // Only the releases of a locked account and the transactions of its client it doesn't
+            // refuse, e.g. the resolve of a dispute from before the lock, may change it, and it
+            // stays locked without losing available funds nor holding more
+            let allowed = released.contains(client_id) || (!refused && affected.contains(client_id));
+
+            if !allowed || !after.locked || after.available < before.available || after.held > before.held {
+                return Err(Violation::LockedChanged { before: before.clone(), after });
+            }
+        }
+
+        for (tx_id, before) in entries {
+            history += self.store.get_tx(*tx_id).map_or(Decimal::ZERO, held_funds);
+            history -= before.map_or(Decimal::ZERO, held_funds);
+        }
+
+        self.check_held(held, history)?;
+
+        Ok((held, history))
+    }
+
+    /// Runs the releases due by the engine clock that no transaction triggered yet, e.g. the disputes
+    /// expired since the last one with a system clock.
+    pub fn finalize(&mut self) {
+        self.run_releases(self.clock.now());
+        self.run_operations(self.clock.now());
+        self.record_operations();
+        self.notify_observers();
+        self.notify_event_handlers();
+    }
+
+    /// The timestamps of the first and last transactions that moved the client's balances, when
+    /// they had one.
+    pub fn activity_span(&self, client_id: ClientId) -> Option<(u64, u64)> {
+        let activity = self.activity.get(&client_id)?;
+
+        activity.first_activity.zip(activity.last_activity)
+    }
+
+    /// Returns the accounts without activity for at least this many days and notifies the
+    /// observers about them.
+    pub fn detect_dormant(&mut self, days: u64) -> Vec<DormantAccount> {
+        let Some(now) = self.clock.now() else {
+            return vec![];
+        };
+
+        let mut dormant: Vec<DormantAccount> = self.activity
+            .iter()
+            .filter(|(client_id, _)| !self.deleted.contains(client_id))
+            .filter_map(|(client_id, activity)| {
+                let last_activity = activity.last_activity?;
+                let idle_days = now.saturating_sub(last_activity) / SECONDS_PER_DAY;
+
+                (idle_days >= days).then_some(DormantAccount {
+                    client_id: *client_id,
+                    last_activity,
+                    idle_days,
+                })
+            })
+            .collect();
+
+        dormant.sort_by_key(|account| account.client_id);
+
+        for account in dormant.iter() {
+            log::debug!("Account {} dormant for {} days", account.client_id, account.idle_days);
+
+            self.lifecycle_events.push(LifecycleEvent::Dormant {
+                client_id: account.client_id,
+                last_activity: account.last_activity,
+                days: account.idle_days,
+            });
+        }
+
+        self.notify_observers();
+
+        dormant
+    }
+
+    /// Returns the accounts with a negative available balance.
+    pub fn deficits(&self) -> Vec<DeficitAccount> {
+        let mut deficits: Vec<DeficitAccount> = self.deficits
+            .iter()
+            .filter(|(client_id, _)| !self.deleted.contains(client_id))
+            .filter_map(|(client_id, start)| {
+                let account = self.store.get_account(*client_id)?;
+
+                Some(DeficitAccount {
+                    client_id: *client_id,
+                    deficit: -account.available,
+                    since_tx: start.tx_id,
+                    since: start.timestamp,
+                    age_days: start.timestamp
+                        .zip(self.clock.now())
+                        .map(|(since, now)| now.saturating_sub(since) / SECONDS_PER_DAY),
+                })
+            })
+            .collect();
+
+        deficits.sort_by_key(|account| account.client_id);
+
+        deficits
+    }
+
+    /// The transactions under dispute, the oldest dispute first. Their age is only known for the
+    /// disputes opened once the engine clock had a time.
+    pub fn open_disputes(&self) -> Vec<OpenDispute> {
+        let mut disputes: Vec<OpenDispute> = self
+            .history()
+            .filter(|kept| kept.entry.info == TransactionInfo::UnderDispute)
+            .map(|kept| {
+                let disputed_at = self.dispute_times.get(&kept.tx_id).copied();
+
+                OpenDispute {
+                    tx: kept.tx_id,
+                    client: self.resolve_client(kept.entry.client_id),
+                    amount: kept.entry.amount,
+                    disputed_at,
+                    age_days: disputed_at
+                        .zip(self.clock.now())
+                        .map(|(since, now)| now.saturating_sub(since) / SECONDS_PER_DAY),
+                }
+            })
+            .collect();
+
+        disputes.sort_by_key(|dispute| (dispute.disputed_at.is_none(), dispute.disputed_at, dispute.tx));
+
+        disputes
+    }
+
+    // Drops the oldest transactions past the retention except the ones under dispute, returns how
+    // many were dropped. A dropped transaction can't be disputed anymore and its id can be reused.
+    pub fn prune_history(&mut self, retention: &Retention) -> usize {
+        let cutoff = retention.cutoff(self.clock.now());
+        let mut excess = retention.max_entries
+            .map(|max_entries| self.store.tx_count().saturating_sub(max_entries))
+            .unwrap_or(0);
+        // How many transactions each client has over its own limit
+        let mut client_excess: HashMap<ClientId, usize> = HashMap::new();
+
+        if let Some(max_per_client) = retention.max_per_client {
+            for (tx_id, _) in self.store.order() {
+                if let Some(entry) = self.store.get_tx(tx_id) {
+                    *client_excess.entry(entry.client_id).or_default() += 1;
+                }
+            }
+
+            for excess in client_excess.values_mut() {
+                *excess = excess.saturating_sub(max_per_client);
+            }
+        }
+
+        // The order is read from the store, the transactions are removed once it's been through
+        let mut pruned = HashSet::new();
+
+        for (tx_id, timestamp) in self.store.order() {
+            if pruned.contains(&tx_id) {
+                continue;
+            }
+
+            match self.store.get_tx(tx_id) {
+                Some(HistoryEntry {
+                    info: TransactionInfo::UnderDispute | TransactionInfo::Hold | TransactionInfo::OnHold,
+                    ..
+                }) => {}
+                Some(HistoryEntry { client_id, .. }) => {
+                    let expired = cutoff.zip(timestamp).is_some_and(|(cutoff, ts)| ts < cutoff);
+                    let over_client = client_excess.get(&client_id).is_some_and(|excess| *excess > 0);
+
+                    if expired || excess > 0 || over_client {
+                        pruned.insert(tx_id);
+                        excess = excess.saturating_sub(1);
+
+                        if let Some(excess) = client_excess.get_mut(&client_id) {
+                            *excess = excess.saturating_sub(1);
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+
+        for tx_id in &pruned {
+            self.delete_tx(*tx_id);
+            self.transfer_sources.remove(tx_id);
+            self.tx_times.remove(tx_id);
+            self.redisputes.remove(tx_id);
+        }
+
+        self.store.compact_order();
+
+        let pruned = pruned.len();
+
+        log::debug!("Pruned {} transactions from the history", pruned);
+
+        self.metrics.increment_counter("engine_history_pruned_total", &[], pruned as u64);
+
+        pruned
+    }
+
+    fn record_audit(&mut self, tx: &Transaction, result: &Result<(), EngineError>, alerts: Vec<String>) {
+        if self.audit.is_empty() {
+            return;
+        }
+
+        let reason = result.as_ref().err().map(EngineError::reason);
+        let record = AuditRecord {
+            alerts,
+            ..AuditRecord::new(tx, reason, self.store.get_account(tx.client_id), self.clock.now())
+        };
+
+        for sink in self.audit.iter_mut() {
+            sink.record(&record);
+        }
+    }
+
+    // Returns the rules of the alerts the transaction raised, for its audit record
+    fn check_alerts(&mut self, tx: &Transaction) -> Vec<String> {
+        if self.alerts.is_empty() {
+            return vec![];
+        }
+
+        if let TransactionType::Merge(_) = tx.tx_type {
+            self.alerts.clear(tx.client_id);
+        }
+
+        let now = self.clock.now();
+        let mut rules = vec![];
+
+        for client_id in self.affected_clients(tx) {
+            if let Some(account) = self.store.get_account(client_id) {
+                for event in self.alerts.check(account, tx.tx_id, now) {
+                    if let LifecycleEvent::Alert { client_id, tx_id, rule, available, held } = &event {
+                        self.engine_events.push(EngineEvent::AlertRaised {
+                            client_id: *client_id,
+                            tx_id: *tx_id,
+                            rule: rule.clone(),
+                            available: *available,
+                            held: *held,
+                        });
+                        rules.push(rule.clone());
+                    }
+
+                    self.lifecycle_events.push(event);
+                }
+            }
+        }
+
+        rules
+    }
+
+    // The accounts a transaction changed. A merge changes the account it was merged into, a
+    // transfer and the chargeback or representment of one change the sender too.
+    fn affected_clients(&self, tx: &Transaction) -> Vec<ClientId> {
+        match tx.tx_type {
+            TransactionType::Merge(into) => vec![self.resolve_client(into)],
+            TransactionType::Transfer { to, .. } => vec![tx.client_id, self.resolve_client(to)],
+            TransactionType::Chargeback | TransactionType::Representment => {
+                match self.transfer_sources.get(&tx.tx_id) {
+                    Some(source) => vec![tx.client_id, self.resolve_client(*source)],
+                    None => vec![tx.client_id],
+                }
+            }
+            _ => vec![tx.client_id],
+        }
+    }
+
+    fn notify_balance_change(&mut self, client_id: ClientId) {
+        if self.deleted.contains(&client_id) {
+            return;
+        }
+
+        if let Some(account) = self.store.get_account(client_id) {
+            for observer in self.observers.iter_mut() {
+                observer.on_balance_change(account);
+            }
+        }
+    }
+
+    fn record_statement(&mut self, tx: &Transaction, client_id: ClientId) {
+        if let (Some(ledger), Some(account)) = (&mut self.ledger, self.store.get_account(client_id)) {
+            ledger.record(tx, account);
+        }
+    }
+
+    fn run_after_hooks(&mut self, tx: &Transaction, client_id: ClientId) {
+        if self.deleted.contains(&client_id) {
+            return;
+        }
+
+        if let Some(account) = self.store.get_account(client_id) {
+            for hooks in self.hooks.iter_mut() {
+                hooks.after_apply(tx, account);
+            }
+        }
+    }
+
+    fn record_metrics(&mut self, tx: &Transaction, result: &Result<(), EngineError>, took: Duration) {
+        let tx_type = tx.tx_type.name();
+
+        match result {
+            Ok(()) => {
+                self.metrics.increment_counter(
+                    "engine_transactions_applied_total",
+                    &[("type", tx_type)],
+                    1
+                );
+            }
+            Err(err) => {
+                self.metrics.increment_counter(
+                    "engine_transactions_rejected_total",
+                    &[("type", tx_type), ("reason", err.reason().code())],
+                    1
+                );
+            }
+        }
+
+        self.metrics.record_histogram("engine_apply_seconds", &[], took.as_secs_f64());
+    }
+
+    fn record_gauges(&mut self) {
+        self.metrics.set_gauge("engine_accounts", &[], self.store.account_count() as f64);
+        self.metrics.set_gauge("engine_history_entries", &[], self.store.tx_count() as f64);
+        self.metrics.set_gauge("engine_quarantined", &[], self.quarantined.len() as f64);
+    }
+
+    // Enough to tell a slow lookup from a large state when chasing tail latencies
+    fn report_slow_apply(&mut self, tx: &Transaction, result: &Result<(), EngineError>, took: Duration) {
+        log::warn!(
+            "Applying transaction {} took {:?}: {:?}, result {:?}, account {:?}, history entry {:?}, \
+             {} accounts, {} history entries, {} quarantined",
+            tx.tx_id,
+            took,
+            tx,
+            result,
+            self.store.get_account(tx.client_id),
+            self.store.get_tx(tx.tx_id),
+            self.store.account_count(),
+            self.store.tx_count(),
+            self.quarantined.len()
+        );
+
+        self.metrics.increment_counter("engine_slow_applies_total", &[("type", tx.tx_type.name())], 1);
+    }
+
+    fn notify_event_handlers(&mut self) {
+        for event in self.engine_events.drain(..) {
+            for handler in self.event_handlers.iter_mut() {
+                handler.on_event(&event);
+            }
+        }
+    }
+
+    fn notify_observers(&mut self) {
+        for event in self.lifecycle_events.drain(..) {
+            self.metrics.increment_counter(
+                "engine_lifecycle_events_total",
+                &[("event", event.name())],
+                1
+            );
+
+            for observer in self.observers.iter_mut() {
+                observer.on_lifecycle_event(&event);
+            }
+
+            // Releases happen between transactions, no transaction reports their balance change
+            if let
+                | LifecycleEvent::HoldExpired { client_id, .. }
+                | LifecycleEvent::DepositReleased { client_id, .. }
+                | LifecycleEvent::DisputeExpired { client_id, .. } = event
+            {
+                if self.deleted.contains(&client_id) {
+                    continue;
+                }
+
+                if let Some(account) = self.store.get_account(client_id) {
+                    for observer in self.observers.iter_mut() {
+                        observer.on_balance_change(account);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn add_projection(&mut self, projection: Box<dyn Projection>) {
+        if let Some(event_log) = &mut self.event_log {
+            event_log.add_projection(projection);
+        }
+    }
+
+    pub fn projection<P: Projection>(&self) -> Option<&P> {
+        self.event_log.as_ref()?.projection::<P>()
+    }
+
+    pub fn events(&self) -> &[Transaction] {
+        self.event_log
+            .as_ref()
+            .map(|event_log| event_log.events())
+            .unwrap_or_default()
+    }
+
+    /// Rebuilds the balances and projections by replaying the event log, only with event sourcing.
+    pub fn rebuild(&mut self) {
+        let Some(mut event_log) = self.event_log.take() else {
+            return;
+        };
+
+        log::debug!("Rebuilding from {} events", event_log.events().len());
+
+        self.store.clear();
+
+        // The next check passes over the whole state again
+        if let Some(check) = &mut self.invariants {
+            check.totals = None;
+        }
+
+        for account in event_log.opening_balances() {
+            self.store.upsert_account(account.clone());
+        }
+
+        self.transfer_sources.clear();
+        self.tx_times.clear();
+        self.dispute_times.clear();
+        self.redisputes.clear();
+        self.scheduler.clear();
+        self.activity = self.store
+            .accounts()
+            .map(|account| (account.client_id, AccountActivity { deposited: true, ..Default::default() }))
+            .collect();
+        self.merged.clear();
+        self.quarantined.clear();
+        self.clock.reset();
+
+        // The replayed sequence numbers are checked again from the start
+        let sequence_summary = self.upstream.summary();
+        self.upstream = UpstreamSequence::new();
+
+        // The replayed timestamps were already checked, they'd all be late against themselves
+        if let Some(latest_timestamps) = &mut self.latest_timestamps {
+            latest_timestamps.clear();
+        }
+
+        self.tiers = self.opening_tiers.clone();
+        self.deleted = self.opening_deleted.clone();
+
+        // Duplicate disputes are rejected, the log doesn't replay them
+        for client_id in self.escalated_clients() {
+            self.tiers.insert(client_id, RiskTier::High);
+        }
+
+        self.system = SystemLedger::new();
+        self.overflows.clear();
+        self.fee_summary.clear();
+
+        if let Some(risk) = &mut self.risk {
+            risk.reset();
+        }
+
+        for policy in self.policies.iter_mut() {
+            policy.reset();
+        }
+
+        for release in self.opening_releases.clone() {
+            self.restore_release(release);
+        }
+
+        for kept in self.opening_history.clone() {
+            self.restore_history(kept);
+        }
+
+        for tx in event_log.opening_quarantined() {
+            if let TransactionType::Deposit(amount) = tx.tx_type {
+                self.system.credit(SystemAccount::Escrow, amount);
+            }
+
+            self.quarantined.insert(tx.tx_id, tx.clone());
+        }
+        self.deficits = self.store
+            .accounts()
+            .filter(|account| account.available < Decimal::ZERO)
+            .map(|account| (account.client_id, DeficitStart::default()))
+            .collect();
+
+        for tx in event_log.events() {
+            // The adjustments of the daily operations are in the log, the day ends aren't run again
+            let _ = match self.operations.made(tx) {
+                true => self.execute_net(tx),
+                false => self.apply(tx),
+            };
+        }
+
+        self.lifecycle_events.clear();
+        self.engine_events.clear();
+        self.operation_txs.clear();
+        self.upstream.restore_summary(sequence_summary);
+
+        event_log.rebuild_projections();
+
+        self.event_log = Some(event_log);
+    }
+
+    // Deposits and withdrawals can't reuse the id of a transaction in the history or quarantined,
+    // pruned ids are forgotten. Locked accounts can't move funds nor open disputes, but disputes
+    // opened before the lock can still be resolved or charged back.
+    fn admit(&self, tx: &Transaction) -> Result<Admission, EngineError> {
+        match tx.tx_type {
+            // Conversions are between the engines of two currencies, see `MultiCurrency`
+            TransactionType::Convert { .. } => {
+                Err(EngineError::NotAllowed { tx_id: tx.tx_id, tx_type: tx.tx_type.name() })
+            }
+            TransactionType::Adjustment { .. } if !self.adjustments => {
+                Err(EngineError::NotAllowed { tx_id: tx.tx_id, tx_type: tx.tx_type.name() })
+            }
+            TransactionType::Deposit(_) |
+            TransactionType::Withdrawal(_) |
+            TransactionType::Adjustment { .. } |
+            TransactionType::Transfer { .. } |
+            TransactionType::WithdrawHold(_) if self.is_known(tx.tx_id) => {
+                match self.idempotent && self.is_exact_duplicate(tx) {
+                    true => Ok(Admission::Duplicate),
+                    false => Err(EngineError::DuplicateTxId(tx.tx_id)),
+                }
+            }
+            TransactionType::Dispute |
+            TransactionType::Resolve |
+            TransactionType::Chargeback |
+            TransactionType::Representment |
+            TransactionType::WithdrawCommit |
+            TransactionType::WithdrawVoid if !self.is_owner(tx.client_id, tx.tx_id) => {
+                Err(EngineError::ClientMismatch { tx_id: tx.tx_id, client_id: tx.client_id })
+            }
+            TransactionType::Dispute if self.outside_dispute_window(tx) => {
+                Err(EngineError::DisputeWindowClosed(tx.tx_id))
+            }
+            _ => self.check_locked(tx).map(|()| Admission::Apply),
+        }
+    }
+
+    fn check_locked(&self, tx: &Transaction) -> Result<(), EngineError> {
+        let locked = self.store.get_account(tx.client_id).is_some_and(|account| account.locked);
+
+        match tx.tx_type {
+            TransactionType::Deposit(_) if locked && self.locked_deposits => Ok(()),
+            TransactionType::Deposit(_) |
+            TransactionType::Withdrawal(_) |
+            TransactionType::Transfer { .. } |
+            TransactionType::WithdrawHold(_) |
+            TransactionType::Dispute if locked => {
+                Err(EngineError::AccountLocked(tx.client_id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Filed after the window by the time of the dispute, or by the engine clock without one
+    fn outside_dispute_window(&self, tx: &Transaction) -> bool {
+        let Some(window) = self.dispute_window else {
+            return false;
+        };
+
+        let filed = tx.timestamp.or(self.clock.now());
+
+        filed
+            .zip(self.tx_times.get(&tx.tx_id))
+            .is_some_and(|(filed, at)| filed > at + window.as_secs())
+    }
+
+    fn is_known(&self, tx_id: u32) -> bool {
+        self.holding(tx_id).is_some() || self.held_elsewhere(tx_id).is_some()
+    }
+
+    // Unknown transactions are left to the dispute, resolve and chargeback to reject.
+    fn is_owner(&self, client_id: ClientId, tx_id: u32) -> bool {
+        match self.store.get_tx(tx_id) {
+            Some(entry) => self.resolve_client(entry.client_id) == client_id,
+            None => self.held_elsewhere(tx_id) != Some(Holding::Applied),
+        }
+    }
+
+    fn held_elsewhere(&self, tx_id: u32) -> Option<Holding> {
+        self.foreign_holding.filter(|(held, _)| *held == tx_id).map(|(_, holding)| holding)
+    }
+
+    // Same id, client, type and amount as a transaction already applied or quarantined. The client
+    // may have been merged since.
+    fn is_exact_duplicate(&self, tx: &Transaction) -> bool {
+        if let Some(original) = self.quarantined.get(&tx.tx_id) {
+            return self.resolve_client(original.client_id) == tx.client_id &&
+                original.tx_type == tx.tx_type;
+        }
+
+        let Some(entry) = self.store.get_tx(tx.tx_id) else {
+            return false;
+        };
+
+        if let TransactionType::Transfer { to, amount } = tx.tx_type {
+            return self.transfer_sources
+                .get(&tx.tx_id)
+                .is_some_and(|source| self.resolve_client(*source) == tx.client_id) &&
+                self.resolve_client(entry.client_id) == self.resolve_client(to) &&
+                entry.amount == amount;
+        }
+
+        let same_type = match tx.tx_type {
+            TransactionType::Deposit(amount) => {
+                let deposit = matches!(
+                    entry.info,
+                    TransactionInfo::Regular |
+                        TransactionInfo::OnHold |
+                        TransactionInfo::UnderDispute |
+                        TransactionInfo::Resolved |
+                        TransactionInfo::ChargedBack |
+                        TransactionInfo::Represented
+                );
+
+                deposit && !self.transfer_sources.contains_key(&tx.tx_id) && entry.amount == amount
+            }
+            TransactionType::Withdrawal(amount) => {
+                entry.info == TransactionInfo::Withdrawal && entry.amount == amount
+            }
+            TransactionType::Adjustment { amount, .. } => {
+                entry.info == TransactionInfo::Adjustment && entry.amount == amount
+            }
+            TransactionType::WithdrawHold(amount) => {
+                let hold = matches!(
+                    entry.info,
+                    TransactionInfo::Hold | TransactionInfo::Withdrawal | TransactionInfo::Expired
+                );
+
+                hold && entry.amount == amount
+            }
+            _ => false,
+        };
+
+        same_type && self.resolve_client(entry.client_id) == tx.client_id
+    }
+
+    fn resolve_client(&self, client_id: ClientId) -> ClientId {
+        self.merged.get(&client_id).copied().unwrap_or(client_id)
+    }
+
+    fn merge(&mut self, tx: &Transaction, into: ClientId) -> Result<(), EngineError> {
+        let from = tx.client_id;
+        let into = self.resolve_client(into);
+
+        if from == into {
+            return Err(EngineError::UnknownClient(from));
+        }
+
+        let Some(source) = self.delete_account(from) else {
+            return Err(EngineError::UnknownClient(from));
+        };
+        let source_activity = self.activity.remove(&from).unwrap_or_default();
+        let source_deficit = self.deficits.remove(&from);
+        let source_tier = self.tiers.remove(&from).unwrap_or_default();
+
+        let mut target = self.open_account(into);
+
+        target.available += source.available;
+        target.held += source.held;
+        target.total = target.available + target.held;
+
+        if target.available < Decimal::ZERO {
+            let start = source_deficit.unwrap_or(DeficitStart {
+                tx_id: Some(tx.tx_id),
+                timestamp: tx.timestamp,
+            });
+
+            self.deficits.entry(into).or_insert(start);
+        } else {
+            self.deficits.remove(&into);
+        }
+
+        if source.locked && !target.locked {
+            target.locked = true;
+
+            self.lifecycle_events.push(LifecycleEvent::Locked { client_id: into, tx_id: tx.tx_id });
+            self.engine_events.push(EngineEvent::AccountLocked { client_id: into, tx_id: tx.tx_id });
+        }
+
+        self.write_account(target);
+
+        if source_tier > self.risk_tier(into) {
+            self.tiers.insert(into, source_tier);
+        }
+
+        let activity = self.activity.entry(into).or_default();
+        activity.deposited |= source_activity.deposited;
+
+        let timestamps = [source_activity.first_activity, source_activity.last_activity, tx.timestamp];
+
+        for timestamp in timestamps.into_iter().flatten() {
+            activity.observe(timestamp);
+        }
+
+        if let Some(timestamp) = tx.timestamp {
+            self.clock.observe(timestamp);
+        }
+
+        for target in self.merged.values_mut().filter(|target| **target == from) {
+            *target = into;
+        }
+        self.merged.insert(from, into);
+
+        self.lifecycle_events.push(LifecycleEvent::Closed { client_id: from });
+
+        log::debug!("Successfull merge of client {} into {}", from, into);
+
+        Ok(())
+    }
+
+    // Every record moves its client's sequence, whether it's applied or not
+    fn check_sequence(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        let Some(conflict) = tx.seq.and_then(|seq| self.upstream.check(tx.client_id, seq)) else {
+            return Ok(());
+        };
+
+        log::warn!("Upstream sequence conflict at transaction {}: {}", tx.tx_id, conflict);
+
+        self.metrics.increment_counter("engine_sequence_conflicts_total", &[("kind", conflict.name())], 1);
+
+        match conflict {
+            SequenceConflict::Duplicate { client_id, seq } if self.reject_out_of_sequence => {
+                Err(EngineError::OutOfSequence { client_id, last: seq, received: seq })
+            }
+            SequenceConflict::Late { client_id, last, received } if self.reject_out_of_sequence => {
+                Err(EngineError::OutOfSequence { client_id, last, received })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Records without a timestamp are never late
+    fn check_timestamp(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        let (Some(latest_timestamps), Some(received)) = (&mut self.latest_timestamps, tx.timestamp) else {
+            return Ok(());
+        };
+
+        let last = latest_timestamps.entry(tx.client_id).or_insert(received);
+
+        if received < *last {
+            return Err(EngineError::LateTimestamp { client_id: tx.client_id, last: *last, received });
+        }
+
+        *last = received;
+
+        Ok(())
+    }
+
+    fn validate(&self, tx: &Transaction) -> Result<(), EngineError> {
+        for client_id in self.affected_clients(tx) {
+            let new_account = Account::new(client_id);
+            let account = self.store.get_account(client_id).unwrap_or(&new_account);
+
+            self.amounts.validate(tx, account)?;
+
+            for validator in self.validators.iter() {
+                validator.validate(tx, account)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate_policies(&self, tx: &Transaction) -> Decision {
+        let new_account = Account::new(tx.client_id);
+        let account = self.store.get_account(tx.client_id).unwrap_or(&new_account);
+        let tier = self.risk_tier(tx.client_id);
+
+        let hooks = self.hooks.iter().map(|hooks| hooks.before_apply(tx));
+        let policies = self.policies.iter().map(|policy| policy.evaluate(tx, account, tier));
+
+        hooks
+            .chain(policies)
+            .find(|decision| *decision != Decision::Allow)
+            .unwrap_or(Decision::Allow)
+    }
+
+    fn quarantine(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        if let TransactionType::Withdrawal(amount) = tx.tx_type {
+            let mut account = self.open_account(tx.client_id);
+            let spendable = account.available + self.credit_limit(tx.client_id);
+
+            if spendable < amount {
+                return Err(EngineError::InsufficientFunds {
+                    client_id: tx.client_id,
+                    available: spendable,
+                    required: amount,
+                });
+            }
+
+            account.available -= amount;
+            account.held += amount;
+
+            self.write_account(account);
+        }
+
+        if let TransactionType::Deposit(amount) = tx.tx_type {
+            self.system.credit(SystemAccount::Escrow, amount);
+        }
+
+        log::debug!("Quarantined transaction {}", tx.tx_id);
+
+        self.quarantined.insert(tx.tx_id, tx.clone());
+
+        Ok(())
+    }
+
+    // The quarantine entry, and the escrow of a deposit, are only released once the transaction
+    // went through, a rejected approval leaves the transaction quarantined
+    fn release(&mut self, tx: &Transaction, approved: bool) -> Result<(), EngineError> {
+        let Some(original) = self.quarantined.get(&tx.tx_id) else {
+            return match self.held_elsewhere(tx.tx_id) {
+                Some(Holding::Quarantined) => {
+                    Err(EngineError::ClientMismatch { tx_id: tx.tx_id, client_id: tx.client_id })
+                }
+                _ => Err(EngineError::UnknownTransaction(tx.tx_id)),
+            };
+        };
+
+        let client_id = self.resolve_client(original.client_id);
+
+        if client_id != tx.client_id {
+            return Err(EngineError::ClientMismatch { tx_id: tx.tx_id, client_id: tx.client_id });
+        }
+
+        let original = Transaction { client_id, ..original.clone() };
+
+        match (&original.tx_type, approved) {
+            (TransactionType::Withdrawal(amount), _) => {
+                let mut account = self.store
+                    .get_account(client_id)
+                    .cloned()
+                    .unwrap_or(Account::new(client_id));
+
+                account.held -= *amount;
+
+                match approved {
+                    true => {
+                        self.write_tx(tx.tx_id, HistoryEntry {
+                            info: TransactionInfo::Withdrawal,
+                            client_id,
+                            amount: *amount,
+                        });
+                        self.store.push_order(tx.tx_id, original.timestamp);
+                        self.engine_events.push(EngineEvent::Withdrawn {
+                            client_id,
+                            tx_id: tx.tx_id,
+                            amount: *amount,
+                        });
+                    }
+                    false => account.available += *amount,
+                }
+
+                account.total = account.available + account.held;
+
+                self.write_account(account);
+                self.track_deficit(client_id, tx);
+            }
+            (_, true) => {
+                // The account may have been locked while the transaction was quarantined
+                self.check_locked(&original)?;
+                self.execute(&original)?;
+                self.adjust_tier(&original);
+            }
+            (_, false) => {}
+        }
+
+        self.quarantined.remove(&tx.tx_id);
+
+        if let TransactionType::Deposit(amount) = original.tx_type {
+            self.system.debit(SystemAccount::Escrow, amount);
+        }
+
+        log::debug!("Released quarantined transaction {} approved {}", tx.tx_id, approved);
+
+        Ok(())
+    }
+
+    // Runs the releases due at `now`, each one moves the funds of its transaction from held to
+    // available. Obligations taken before the engine knew the time are never released.
+    fn run_releases(&mut self, now: Option<u64>) {
+        let Some(now) = now else {
+            return;
+        };
+
+        while let Some(release) = self.scheduler.pop_due(now) {
+            let Some(mut entry) = self.store.get_tx(release.tx_id) else {
+                continue;
+            };
+
+            // The transaction moved on, e.g. the hold was committed or the dispute resolved
+            if entry.info != expected_info(release.kind) {
+                continue;
+            }
+
+            entry.info = match release.kind {
+                ReleaseKind::DepositHold => TransactionInfo::Regular,
+                ReleaseKind::DisputeExpiry => TransactionInfo::Resolved,
+                ReleaseKind::WithdrawalHold => TransactionInfo::Expired,
+            };
+
+            self.write_tx(release.tx_id, entry);
+            self.dispute_times.remove(&release.tx_id);
+
+            let HistoryEntry { client_id, amount, .. } = entry;
+            let client_id = self.resolve_client(client_id);
+            let tx_id = release.tx_id;
+
+            if let Some(check) = &mut self.invariants {
+                check.released.insert(client_id);
+            }
+
+            self.update_account(client_id, |account| {
+                account.available += amount;
+                account.held -= amount;
+            });
+
+            if release.kind == ReleaseKind::DisputeExpiry {
+                self.engine_events.push(EngineEvent::DisputeResolved { client_id, tx_id, amount });
+            }
+
+            self.lifecycle_events.push(match release.kind {
+                ReleaseKind::DepositHold => LifecycleEvent::DepositReleased { client_id, tx_id, amount },
+                ReleaseKind::DisputeExpiry => LifecycleEvent::DisputeExpired { client_id, tx_id, amount },
+                ReleaseKind::WithdrawalHold => LifecycleEvent::HoldExpired { client_id, tx_id, amount },
+            });
+
+            log::debug!("Released {} of transaction {} ({:?})", amount, tx_id, release.kind);
+        }
+    }
+
+    // Runs the daily operations of the day ends passed by `now` on the unlocked accounts, in the
+    // order of the client ids
+    fn run_operations(&mut self, now: Option<u64>) {
+        let Some(now) = now.filter(|_| !self.operations.is_empty()) else {
+            return;
+        };
+
+        for day_end in self.operations.due(now) {
+            for operation in self.operations.operations().to_vec() {
+                let mut clients: Vec<(ClientId, Decimal)> = self
+                    .accounts_iter()
+                    .filter(|account| !account.locked)
+                    .map(|account| (account.client_id, account.available))
+                    .collect();
+
+                clients.sort_unstable_by_key(|(client_id, _)| *client_id);
+
+                for (client_id, available) in clients {
+                    let Some(amount) = operation.amount(available, self.rounding) else {
+                        continue;
+                    };
+
+                    let (store, quarantined) = (&self.store, &self.quarantined);
+                    let tx_id = self.operations.next_id(|tx_id| store.has_tx(tx_id) || quarantined.contains_key(&tx_id));
+                    let tx_type = TransactionType::Adjustment { amount, reason: operation.name().to_string() };
+                    let tx = Transaction::new(client_id, tx_id, tx_type).with_timestamp(day_end);
+
+                    match self.execute_net(&tx) {
+                        Ok(()) => {
+                            if let Some(account) = self.store.get_account(client_id) {
+                                self.operation_txs.push((tx, account.clone()));
+                            }
+                        }
+                        Err(err) => log::warn!("Could not run the {} of client {}: {}", operation.name(), client_id, err),
+                    }
+                }
+            }
+        }
+    }
+
+    // Audits the adjustments of the daily operations, adds them to the statements and logs them
+    // before the transaction that ran them
+    fn record_operations(&mut self) {
+        for (tx, account) in std::mem::take(&mut self.operation_txs) {
+            if !self.audit.is_empty() {
+                let record = AuditRecord::new(&tx, None, Some(&account), tx.timestamp);
+
+                for sink in self.audit.iter_mut() {
+                    sink.record(&record);
+                }
+            }
+
+            if let Some(ledger) = &mut self.ledger {
+                ledger.record(&tx, &account);
+            }
+
+            self.notify_balance_change(tx.client_id);
+
+            if let Some(event_log) = &mut self.event_log {
+                event_log.append(tx);
+            }
+        }
+    }
+
+    fn unlock(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        if !self.unlocks {
+            return Err(EngineError::NotAllowed { tx_id: tx.tx_id, tx_type: tx.tx_type.name() });
+        }
+
+        let Some(account) = self.store.get_account(tx.client_id) else {
+            return Err(EngineError::UnknownClient(tx.client_id));
+        };
+
+        if !account.locked {
+            return Err(EngineError::NotLocked(tx.client_id));
+        }
+
+        self.write_account(Account { locked: false, ..account.clone() });
+
+        self.lifecycle_events.push(LifecycleEvent::Unlocked { client_id: tx.client_id, tx_id: tx.tx_id });
+
+        log::debug!("Unlocked account {}", tx.client_id);
+
+        Ok(())
+    }
+
+    // Deleting keeps the account, its history and pending releases, it's only hidden
+    fn delete(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        if !self.deletes {
+            return Err(EngineError::NotAllowed { tx_id: tx.tx_id, tx_type: tx.tx_type.name() });
+        }
+
+        if self.store.get_account(tx.client_id).is_none() {
+            return Err(EngineError::UnknownClient(tx.client_id));
+        }
+
+        self.deleted.insert(tx.client_id);
+
+        self.lifecycle_events.push(LifecycleEvent::Deleted { client_id: tx.client_id, tx_id: tx.tx_id });
+
+        log::debug!("Deleted account {}", tx.client_id);
+
+        Ok(())
+    }
+
+    fn restore(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        if !self.deletes {
+            return Err(EngineError::NotAllowed { tx_id: tx.tx_id, tx_type: tx.tx_type.name() });
+        }
+
+        if !self.deleted.remove(&tx.client_id) {
+            return Err(EngineError::NotDeleted(tx.client_id));
+        }
+
+        self.lifecycle_events.push(LifecycleEvent::Restored { client_id: tx.client_id, tx_id: tx.tx_id });
+
+        log::debug!("Restored account {}", tx.client_id);
+
+        Ok(())
+    }
+
+    // Only a restore applies to a deleted account, transfers and merges into one are rejected too
+    fn check_deleted(&self, tx: &Transaction) -> Result<(), EngineError> {
+        let other = match tx.tx_type {
+            TransactionType::Restore => {
+                return Ok(());
+            }
+            TransactionType::Transfer { to, .. } => Some(to),
+            TransactionType::Merge(into) => Some(self.resolve_client(into)),
+            _ => None,
+        };
+
+        match iter::once(tx.client_id).chain(other).find(|client_id| self.deleted.contains(client_id)) {
+            Some(client_id) => Err(EngineError::AccountDeleted(client_id)),
+            None => Ok(()),
+        }
+    }
+
+    fn record_duplicate_dispute(&mut self, tx: &Transaction) {
+        let count = self.duplicate_disputes.entry(tx.client_id).or_default();
+        *count += 1;
+
+        if self.duplicate_dispute_limit.is_some_and(|limit| *count == limit.saturating_add(1)) {
+            log::debug!(
+                "Client {} risk tier raised to high after {} duplicate disputes",
+                tx.client_id,
+                count
+            );
+
+            self.lifecycle_events.push(LifecycleEvent::Escalated {
+                client_id: tx.client_id,
+                tx_id: tx.tx_id,
+                duplicate_disputes: *count,
+            });
+            self.tiers.insert(tx.client_id, RiskTier::High);
+        }
+    }
+
+    // Records the transaction in the risk signals of its client, then locks the account if they went
+    // over a threshold with `lock_risky_accounts`
+    fn score_risk(&mut self, tx: &Transaction) {
+        let Some(risk) = &mut self.risk else {
+            return;
+        };
+
+        risk.record(tx);
+
+        let flags = risk.flags(tx.client_id);
+
+        if !self.risk_lock || flags.is_empty() {
+            return;
+        }
+
+        let Some(mut account) = self.store.get_account(tx.client_id).cloned() else {
+            return;
+        };
+
+        if account.locked {
+            return;
+        }
+
+        let flags: Vec<String> = flags.iter().map(ToString::to_string).collect();
+        log::warn!("Locking client {}, its risk signals are over {}", tx.client_id, flags.join(", "));
+
+        account.locked = true;
+        self.write_account(account);
+
+        self.lifecycle_events.push(LifecycleEvent::Locked { client_id: tx.client_id, tx_id: tx.tx_id });
+        self.engine_events.push(EngineEvent::AccountLocked { client_id: tx.client_id, tx_id: tx.tx_id });
+    }
+
+    fn escalated_clients(&self) -> Vec<ClientId> {
+        let Some(limit) = self.duplicate_dispute_limit else {
+            return vec![];
+        };
+
+        self.duplicate_disputes
+            .iter()
+            .filter(|(_, count)| **count > limit)
+            .map(|(client_id, _)| *client_id)
+            .collect()
+    }
+
+    fn adjust_tier(&mut self, tx: &Transaction) {
+        for policy in self.policies.iter() {
+            let tier = self.tiers.get(&tx.client_id).copied().unwrap_or_default();
+
+            if let Some(new_tier) = policy.adjust_tier(tx, tier) {
+                log::debug!("Client {} risk tier changed from {} to {}", tx.client_id, tier, new_tier);
+
+                self.tiers.insert(tx.client_id, new_tier);
+            }
+        }
+    }
+
+    // The account of the client, created when it has none yet
+    fn open_account(&mut self, client_id: ClientId) -> Account {
+        if let Some(account) = self.store.get_account(client_id) {
+            return account.clone();
+        }
+
+        self.lifecycle_events.push(LifecycleEvent::Created { client_id });
+
+        let account = Account::new(client_id);
+        self.write_account(account.clone());
+
+        account
+    }
+
+    // Changes the account of the client if it has one, returns whether it had
+    // The writes to the store go through these, so the invariant check only looks at what changed
+    fn write_account(&mut self, account: Account) {
+        self.touch_account(account.client_id);
+        self.store.upsert_account(account);
+    }
+
+    fn delete_account(&mut self, client_id: ClientId) -> Option<Account> {
+        self.touch_account(client_id);
+        self.store.remove_account(client_id)
+    }
+
+    fn write_tx(&mut self, tx_id: u32, entry: HistoryEntry) {
+        self.touch_tx(tx_id);
+        self.store.put_tx(tx_id, entry);
+    }
+
+    fn delete_tx(&mut self, tx_id: u32) {
+        self.touch_tx(tx_id);
+        self.store.remove_tx(tx_id);
+    }
+
+    fn touch_account(&mut self, client_id: ClientId) {
+        if let Some(check) = &mut self.invariants {
+            check.accounts.entry(client_id).or_insert_with(|| self.store.get_account(client_id).cloned());
+        }
+    }
+
+    fn touch_tx(&mut self, tx_id: u32) {
+        if let Some(check) = &mut self.invariants {
+            check.entries.entry(tx_id).or_insert_with(|| self.store.get_tx(tx_id));
+        }
+    }
+
+    fn update_account(&mut self, client_id: ClientId, update: impl FnOnce(&mut Account)) -> bool {
+        let Some(mut account) = self.store.get_account(client_id).cloned() else {
+            return false;
+        };
+
+        update(&mut account);
+        self.write_account(account);
+
+        true
+    }
+
+    fn move_to(&mut self, tx_id: u32, entry: HistoryEntry, info: TransactionInfo) {
+        self.write_tx(tx_id, HistoryEntry { info, ..entry });
+
+        match (info, self.clock.now()) {
+            (TransactionInfo::UnderDispute, Some(now)) => {
+                self.dispute_times.insert(tx_id, now);
+            }
+            _ => {
+                self.dispute_times.remove(&tx_id);
+            }
+        }
+    }
+
+    fn track_deficit(&mut self, client_id: ClientId, tx: &Transaction) {
+        let in_deficit = self.store
+            .get_account(client_id)
+            .is_some_and(|account| account.available < Decimal::ZERO);
+
+        if in_deficit {
+            self.deficits.entry(client_id).or_insert(DeficitStart {
+                tx_id: Some(tx.tx_id),
+                timestamp: tx.timestamp,
+            });
+        } else {
+            self.deficits.remove(&client_id);
+        }
+    }
+
+    fn apply(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        self.check_sequence(tx)?;
+        self.check_timestamp(tx)?;
+        self.run_releases(self.clock.now().max(tx.timestamp));
+        self.run_operations(self.clock.now().max(tx.timestamp));
+        self.check_deleted(tx)?;
+
+        match tx.tx_type {
+            TransactionType::Merge(into) => {
+                return self.merge(tx, into);
+            }
+            TransactionType::Approve => {
+                return self.release(tx, true);
+            }
+            TransactionType::Decline => {
+                return self.release(tx, false);
+            }
+            TransactionType::Unlock => {
+                return self.unlock(tx);
+            }
+            TransactionType::Delete => {
+                return self.delete(tx);
+            }
+            TransactionType::Restore => {
+                return self.restore(tx);
+            }
+            _ => {}
+        }
+
+        self.validate(tx)?;
+
+        if self.admit(tx)? == Admission::Duplicate {
+            log::info!("Ignoring transaction {}, it's a duplicate of an earlier one", tx.tx_id);
+            return Ok(());
+        }
+
+        match self.evaluate_policies(tx) {
+            Decision::Allow => {
+                let result = self.execute(tx);
+
+                if let Err(EngineError::AlreadyDisputed(_)) = result {
+                    self.record_duplicate_dispute(tx);
+                }
+
+                if result.is_ok() {
+                    self.adjust_tier(tx);
+
+                    for policy in self.policies.iter_mut() {
+                        policy.record(tx);
+                    }
+
+                    self.score_risk(tx);
+                }
+
+                result
+            }
+            Decision::Reject(reason) => Err(EngineError::Policy(reason)),
+            Decision::Quarantine => self.quarantine(tx),
+        }
+    }
+
+    fn execute(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        self.charge(tx)?;
+
+        let disputable = matches!(tx.tx_type, TransactionType::Deposit(_) | TransactionType::Transfer { .. });
+
+        if let Some(timestamp) = tx.timestamp.filter(|_| disputable && self.dispute_window.is_some()) {
+            self.tx_times.insert(tx.tx_id, timestamp);
+        }
+
+        Ok(())
+    }
+
+    // Deposits are credited net of their fee and withdrawals debit the fee on top of their amount,
+    // the fees go to the fees system account once the transaction is applied
+    fn charge(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        let fee = self.fees.as_ref().map_or(Decimal::ZERO, |fees| fees.fee(tx.client_id, &tx.tx_type, self.rounding));
+
+        if fee.is_zero() {
+            return self.execute_net(tx);
+        }
+
+        let tx_type = match tx.tx_type {
+            TransactionType::Deposit(amount) if fee >= amount => return Err(EngineError::InvalidAmount(amount)),
+            TransactionType::Deposit(amount) => TransactionType::Deposit(amount - fee),
+            TransactionType::Withdrawal(amount) => TransactionType::Withdrawal(amount + fee),
+            _ => return self.execute_net(tx),
+        };
+
+        self.execute_net(&Transaction { tx_type, ..tx.clone() })?;
+
+        log::debug!("Charged a fee of {} on transaction {}", fee, tx.tx_id);
+
+        self.system.credit(SystemAccount::Fees, fee);
+        self.fee_summary.record(tx.tx_type.name(), fee);
+
+        Ok(())
+    }
+
+    fn execute_net(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        let bounded = self.check_bounds(tx)?;
+        let tx = bounded.as_ref().unwrap_or(tx);
+
+        if let TransactionType::Transfer { to, amount } = tx.tx_type {
+            return self.transfer(tx, to, amount);
+        }
+
+        let mut account = self.open_account(tx.client_id);
+        let spendable = account.available + self.credit_limit(tx.client_id);
+        let activity = self.activity.entry(tx.client_id).or_default();
+
+        if let Some(timestamp) = tx.timestamp {
+            activity.observe(timestamp);
+            self.clock.observe(timestamp);
+        }
+
+        let mut refund = None;
+
+        let result = match tx.tx_type {
+            TransactionType::Deposit(amount) => {
+                let release_at = release_time(self.deposit_hold, self.clock.now());
+
+                let info = match release_at {
+                    Some(at) => {
+                        account.held += amount;
+
+                        self.scheduler.schedule(ScheduledRelease {
+                            at,
+                            kind: ReleaseKind::DepositHold,
+                            tx_id: tx.tx_id,
+                            client_id: tx.client_id,
+                            amount,
+                        });
+
+                        TransactionInfo::OnHold
+                    }
+                    None => {
+                        account.available += amount;
+
+                        TransactionInfo::Regular
+                    }
+                };
+
+                self.write_tx(tx.tx_id, HistoryEntry { info, client_id: tx.client_id, amount });
+                self.store.push_order(tx.tx_id, tx.timestamp);
+                self.engine_events.push(EngineEvent::Deposited {
+                    client_id: tx.client_id,
+                    tx_id: tx.tx_id,
+                    amount,
+                });
+
+                let activity = self.activity.entry(tx.client_id).or_default();
+
+                if !activity.deposited {
+                    activity.deposited = true;
+
+                    self.lifecycle_events.push(LifecycleEvent::FirstDeposit {
+                        client_id: tx.client_id,
+                        tx_id: tx.tx_id,
+                        amount,
+                    });
+                }
+
+                log::debug!("Successfull deposit of {}", amount);
+
+                Ok(())
+            }
+            TransactionType::Withdrawal(amount) => {
+                if spendable >= amount {
+                    account.available -= amount;
+
+                    self.write_tx(tx.tx_id, HistoryEntry {
+                        info: TransactionInfo::Withdrawal,
+                        client_id: tx.client_id,
+                        amount,
+                    });
+                    self.store.push_order(tx.tx_id, tx.timestamp);
+                    self.engine_events.push(EngineEvent::Withdrawn {
+                        client_id: tx.client_id,
+                        tx_id: tx.tx_id,
+                        amount,
+                    });
+
+                    log::debug!("Successfull withdraw of {}", amount);
+
+                    Ok(())
+                } else {
+                    Err(EngineError::InsufficientFunds {
+                        client_id: tx.client_id,
+                        available: spendable,
+                        required: amount,
+                    })
+                }
+            }
+            TransactionType::WithdrawHold(amount) => {
+                if spendable >= amount {
+                    account.available -= amount;
+                    account.held += amount;
+
+                    self.write_tx(tx.tx_id, HistoryEntry {
+                        info: TransactionInfo::Hold,
+                        client_id: tx.client_id,
+                        amount,
+                    });
+                    self.store.push_order(tx.tx_id, tx.timestamp);
+
+                    if let Some(now) = self.clock.now() {
+                        self.scheduler.schedule(ScheduledRelease {
+                            at: now + self.hold_expiry.as_secs(),
+                            kind: ReleaseKind::WithdrawalHold,
+                            tx_id: tx.tx_id,
+                            client_id: tx.client_id,
+                            amount,
+                        });
+                    }
+
+                    log::debug!("Successfull withdrawal hold of {}", amount);
+
+                    Ok(())
+                } else {
+                    Err(EngineError::InsufficientFunds {
+                        client_id: tx.client_id,
+                        available: spendable,
+                        required: amount,
+                    })
+                }
+            }
+            TransactionType::WithdrawCommit => {
+                match self.store.get_tx(tx.tx_id) {
+                    Some(entry) if entry.info == TransactionInfo::Hold => {
+                        account.held -= entry.amount;
+                        self.move_to(tx.tx_id, entry, TransactionInfo::Withdrawal);
+                        self.engine_events.push(EngineEvent::Withdrawn {
+                            client_id: tx.client_id,
+                            tx_id: tx.tx_id,
+                            amount: entry.amount,
+                        });
+
+                        log::debug!("Successfull withdrawal commit of {} {}", tx.tx_id, entry.amount);
+
+                        Ok(())
+                    }
+                    _ => Err(EngineError::UnknownTransaction(tx.tx_id)),
+                }
+            }
+            TransactionType::WithdrawVoid => {
+                match self.store.get_tx(tx.tx_id) {
+                    Some(entry) if entry.info == TransactionInfo::Hold => {
+                        account.available += entry.amount;
+                        account.held -= entry.amount;
+                        self.move_to(tx.tx_id, entry, TransactionInfo::Expired);
+
+                        log::debug!("Successfull withdrawal void of {} {}", tx.tx_id, entry.amount);
+
+                        Ok(())
+                    }
+                    _ => Err(EngineError::UnknownTransaction(tx.tx_id)),
+                }
+            }
+            TransactionType::Dispute => {
+                let expires_at = release_time(self.dispute_expiry, self.clock.now());
+
+                let result = match self.store.get_tx(tx.tx_id) {
+                    // The funds of a deposit on hold are held already, the dispute takes over the hold
+                    Some(entry) if entry.info == TransactionInfo::OnHold => {
+                        log::debug!("Successfull dispute of {} {} on hold", tx.tx_id, entry.amount);
+
+                        Ok(entry)
+                    }
+                    Some(entry) if
+                        entry.info == TransactionInfo::Regular &&
+                        (self.allow_negative || account.available >= entry.amount)
+                    => {
+                        account.available -= entry.amount;
+                        account.held += entry.amount;
+
+                        log::debug!("Successfull dispute of {} {}", tx.tx_id, entry.amount);
+
+                        Ok(entry)
+                    }
+                    // A second presentment, when allowed
+                    Some(entry) if entry.info == TransactionInfo::Resolved && self.redispute_limit.is_some() => {
+                        let redisputes = self.redisputes.get(&tx.tx_id).copied().unwrap_or_default();
+
+                        if self.redispute_limit.is_some_and(|limit| redisputes >= limit) {
+                            Err(EngineError::RedisputeLimit(tx.tx_id))
+                        } else if self.allow_negative || account.available >= entry.amount {
+                            account.available -= entry.amount;
+                            account.held += entry.amount;
+                            self.redisputes.insert(tx.tx_id, redisputes + 1);
+
+                            log::debug!("Successfull re-dispute of {} {}", tx.tx_id, entry.amount);
+
+                            Ok(entry)
+                        } else {
+                            Err(EngineError::InsufficientFunds {
+                                client_id: tx.client_id,
+                                available: account.available,
+                                required: entry.amount,
+                            })
+                        }
+                    }
+                    Some(HistoryEntry { info: TransactionInfo::Regular, amount, .. }) => {
+                        Err(EngineError::InsufficientFunds {
+                            client_id: tx.client_id,
+                            available: account.available,
+                            required: amount,
+                        })
+                    }
+                    Some(HistoryEntry { info: TransactionInfo::UnderDispute, .. }) => {
+                        Err(EngineError::AlreadyDisputed(tx.tx_id))
+                    }
+                    _ => Err(EngineError::UnknownTransaction(tx.tx_id)),
+                };
+
+                result.map(|entry| {
+                    self.move_to(tx.tx_id, entry, TransactionInfo::UnderDispute);
+                    self.engine_events.push(EngineEvent::DisputeOpened {
+                        client_id: tx.client_id,
+                        tx_id: tx.tx_id,
+                        amount: entry.amount,
+                    });
+
+                    if let Some(at) = expires_at {
+                        self.scheduler.schedule(ScheduledRelease {
+                            at,
+                            kind: ReleaseKind::DisputeExpiry,
+                            tx_id: tx.tx_id,
+                            client_id: entry.client_id,
+                            amount: entry.amount,
+                        });
+                    }
+                })
+            }
+            TransactionType::Resolve => {
+                match self.store.get_tx(tx.tx_id) {
+                    Some(entry) if entry.info == TransactionInfo::UnderDispute => {
+                        account.available += entry.amount;
+                        account.held -= entry.amount;
+                        self.move_to(tx.tx_id, entry, TransactionInfo::Resolved);
+                        self.engine_events.push(EngineEvent::DisputeResolved {
+                            client_id: tx.client_id,
+                            tx_id: tx.tx_id,
+                            amount: entry.amount,
+                        });
+
+                        log::debug!("Successfull resolve of {} {}", tx.tx_id, entry.amount);
+
+                        Ok(())
+                    }
+                    Some(HistoryEntry { info: TransactionInfo::Regular | TransactionInfo::OnHold, .. }) =>
+                        Err(EngineError::NotUnderDispute(tx.tx_id)),
+                    _ => Err(EngineError::UnknownTransaction(tx.tx_id)),
+                }
+            }
+            TransactionType::Chargeback => {
+                match self.store.get_tx(tx.tx_id) {
+                    Some(entry) if entry.info == TransactionInfo::UnderDispute => {
+                        let amount = entry.amount;
+
+                        account.held -= amount;
+                        self.move_to(tx.tx_id, entry, TransactionInfo::ChargedBack);
+                        self.engine_events.push(EngineEvent::ChargedBack {
+                            client_id: tx.client_id,
+                            tx_id: tx.tx_id,
+                            amount,
+                        });
+
+                        match self.transfer_sources.get(&tx.tx_id) {
+                            Some(source) => refund = Some((*source, amount)),
+                            None => self.system.credit(SystemAccount::ChargebackLosses, amount),
+                        }
+
+                        if !account.locked {
+                            account.locked = true;
+
+                            self.lifecycle_events.push(LifecycleEvent::Locked {
+                                client_id: tx.client_id,
+                                tx_id: tx.tx_id,
+                            });
+                            self.engine_events.push(EngineEvent::AccountLocked {
+                                client_id: tx.client_id,
+                                tx_id: tx.tx_id,
+                            });
+                        }
+
+                        log::debug!("Successfull chargeback of {} {}", tx.tx_id, amount);
+
+                        Ok(())
+                    }
+                    Some(HistoryEntry { info: TransactionInfo::Regular | TransactionInfo::OnHold, .. }) =>
+                        Err(EngineError::NotUnderDispute(tx.tx_id)),
+                    _ => Err(EngineError::UnknownTransaction(tx.tx_id)),
+                }
+            }
+            TransactionType::Representment => {
+                match self.store.get_tx(tx.tx_id) {
+                    Some(entry) if entry.info == TransactionInfo::ChargedBack => {
+                        let amount = entry.amount;
+
+                        account.available += amount;
+                        self.move_to(tx.tx_id, entry, TransactionInfo::Represented);
+                        self.engine_events.push(EngineEvent::Represented {
+                            client_id: tx.client_id,
+                            tx_id: tx.tx_id,
+                            amount,
+                        });
+
+                        // The sender of a transfer gives back what its chargeback returned
+                        match self.transfer_sources.get(&tx.tx_id) {
+                            Some(source) => refund = Some((*source, -amount)),
+                            None => self.system.debit(SystemAccount::ChargebackLosses, amount),
+                        }
+
+                        if account.locked && self.representment_unlocks {
+                            account.locked = false;
+
+                            self.lifecycle_events.push(LifecycleEvent::Unlocked {
+                                client_id: tx.client_id,
+                                tx_id: tx.tx_id,
+                            });
+                        }
+
+                        log::debug!("Successfull representment of {} {}", tx.tx_id, amount);
+
+                        Ok(())
+                    }
+                    Some(HistoryEntry {
+                        info: TransactionInfo::Regular |
+                        TransactionInfo::OnHold |
+                        TransactionInfo::UnderDispute |
+                        TransactionInfo::Resolved,
+                        ..
+                    }) => Err(EngineError::NotChargedBack(tx.tx_id)),
+                    _ => Err(EngineError::UnknownTransaction(tx.tx_id)),
+                }
+            }
+            TransactionType::Recovery(amount) => {
+                if account.available >= Decimal::ZERO {
+                    Err(EngineError::NotInDeficit(tx.client_id))
+                } else if amount <= Decimal::ZERO || amount > -account.available {
+                    Err(EngineError::InvalidAmount(amount))
+                } else {
+                    account.available += amount;
+
+                    log::debug!("Successfull recovery of {}", amount);
+
+                    Ok(())
+                }
+            }
+            TransactionType::Adjustment { amount, ref reason } => {
+                if amount.is_zero() {
+                    Err(EngineError::InvalidAmount(amount))
+                } else if amount < Decimal::ZERO && account.available < -amount && !self.allow_negative {
+                    Err(EngineError::InsufficientFunds {
+                        client_id: tx.client_id,
+                        available: account.available,
+                        required: -amount,
+                    })
+                } else {
+                    account.available += amount;
+
+                    self.write_tx(tx.tx_id, HistoryEntry {
+                        info: TransactionInfo::Adjustment,
+                        client_id: tx.client_id,
+                        amount,
+                    });
+                    self.store.push_order(tx.tx_id, tx.timestamp);
+                    self.system.debit(SystemAccount::Adjustments, amount);
+
+                    self.lifecycle_events.push(LifecycleEvent::Adjusted {
+                        client_id: tx.client_id,
+                        tx_id: tx.tx_id,
+                        amount,
+                        reason: reason.clone(),
+                    });
+
+                    log::debug!("Successfull adjustment of {} ({})", amount, reason);
+
+                    Ok(())
+                }
+            }
+            TransactionType::Merge(_) |
+            TransactionType::Approve |
+            TransactionType::Decline |
+            TransactionType::Unlock |
+            TransactionType::Delete |
+            TransactionType::Restore |
+            TransactionType::Transfer { .. } |
+            TransactionType::Convert { .. } => {
+                unreachable!()
+            }
+        };
+
+        account.total = account.available + account.held;
+
+        self.write_account(account);
+        self.track_deficit(tx.client_id, tx);
+
+        if let Some((source, amount)) = refund {
+            let source = self.resolve_client(source);
+
+            let returned = self.update_account(source, |account| {
+                account.available += amount;
+                account.total = account.available + account.held;
+            });
+
+            if returned {
+                log::debug!("Returned {} of charged back transfer {} to {}", amount, tx.tx_id, source);
+            }
+
+            self.track_deficit(source, tx);
+        }
+
+        result
+    }
+
+    // Returns the deposit to apply instead when it's credited up to the ceiling of the account
+    fn check_bounds(&mut self, tx: &Transaction) -> Result<Option<Transaction>, EngineError> {
+        let bounds = self.balance_bounds.as_ref().and_then(|bounds| bounds.of(tx.client_id));
+
+        let Some((class, bounds)) = bounds else {
+            return Ok(None);
+        };
+
+        let (available, total) = self.store
+            .get_account(tx.client_id)
+            .map(|account| (account.available, account.total))
+            .unwrap_or_default();
+
+        match tx.tx_type {
+            TransactionType::Deposit(amount) => {
+                let Some(ceiling) = bounds.ceiling.filter(|ceiling| total + amount > *ceiling) else {
+                    return Ok(None);
+                };
+
+                let credited = ceiling - total;
+
+                if !self.route_overflow || credited <= Decimal::ZERO {
+                    return Err(EngineError::AboveCeiling {
+                        client_id: tx.client_id,
+                        total: total + amount,
+                        ceiling,
+                    });
+                }
+
+                log::debug!("Crediting {} of deposit {} up to the ceiling", credited, tx.tx_id);
+
+                self.overflows.push(Overflow {
+                    client_id: tx.client_id,
+                    tx_id: tx.tx_id,
+                    class: class.to_string(),
+                    amount,
+                    credited,
+                    excess: amount - credited,
+                });
+
+                Ok(Some(Transaction { tx_type: TransactionType::Deposit(credited), ..tx.clone() }))
+            }
+            TransactionType::Withdrawal(amount) |
+            TransactionType::WithdrawHold(amount) |
+            TransactionType::Transfer { amount, .. } => {
+                match bounds.floor {
+                    Some(floor) if available - amount < floor => {
+                        Err(EngineError::BelowFloor {
+                            client_id: tx.client_id,
+                            available: available - amount,
+                            floor,
+                        })
+                    }
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Debits the sender and credits the receiver, or changes neither. The receiver can dispute it
+    // like a deposit.
+    fn transfer(&mut self, tx: &Transaction, to: ClientId, amount: Decimal) -> Result<(), EngineError> {
+        let to = self.resolve_client(to);
+        let available = self.store
+            .get_account(tx.client_id)
+            .map(|account| account.available)
+            .unwrap_or_default() + self.credit_limit(tx.client_id);
+
+        if to == tx.client_id {
+            return Err(EngineError::UnknownClient(to));
+        }
+
+        if amount <= Decimal::ZERO {
+            return Err(EngineError::InvalidAmount(amount));
+        }
+
+        if self.store.get_account(to).is_some_and(|account| account.locked) {
+            return Err(EngineError::AccountLocked(to));
+        }
+
+        if available < amount {
+            return Err(EngineError::InsufficientFunds {
+                client_id: tx.client_id,
+                available,
+                required: amount,
+            });
+        }
+
+        for (client_id, change) in [(tx.client_id, -amount), (to, amount)] {
+            let mut account = self.open_account(client_id);
+
+            account.available += change;
+            account.total = account.available + account.held;
+
+            self.write_account(account);
+
+            if let Some(timestamp) = tx.timestamp {
+                self.activity.entry(client_id).or_default().observe(timestamp);
+            }
+        }
+
+        if let Some(timestamp) = tx.timestamp {
+            self.clock.observe(timestamp);
+        }
+
+        self.write_tx(tx.tx_id, HistoryEntry {
+            info: TransactionInfo::Regular,
+            client_id: to,
+            amount,
+        });
+        self.store.push_order(tx.tx_id, tx.timestamp);
+        self.transfer_sources.insert(tx.tx_id, tx.client_id);
+
+        self.track_deficit(to, tx);
+
+        log::debug!("Successfull transfer of {} from {} to {}", amount, tx.client_id, to);
+
+        Ok(())
+    }
+
+    /// The current balance of a client, if it has an account.
+    pub fn get_account(&self, client_id: ClientId) -> Option<&Account> {
+        match self.deleted.contains(&client_id) {
+            true => None,
+            false => self.store.get_account(client_id),
+        }
+    }
+
+    /// The accounts in no particular order, without consuming the engine so it can keep
+    /// processing transactions.
+    pub fn accounts_iter(&self) -> impl Iterator<Item = &Account> {
+        self.store.accounts().filter(|account| !self.deleted.contains(&account.client_id))
+    }
+
+    /// The number of accounts, soft-deleted ones excluded.
+    pub fn account_count(&self) -> usize {
+        // Deleted clients always have an account in the store, it's kept while they are hidden
+        self.store.account_count().saturating_sub(self.deleted.len())
+    }
+
+    pub fn client_ids(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.store
+            .accounts()
+            .map(|account| account.client_id)
+            .filter(|client_id| !self.deleted.contains(client_id))
+    }
+
+    /// The soft-deleted accounts, in no particular order.
+    pub fn deleted_accounts(&self) -> impl Iterator<Item = &Account> {
+        self.deleted.iter().filter_map(|client_id| self.store.get_account(*client_id))
+    }
+
+    /// Consumes the engine and returns the accounts sorted by client id.
+    pub fn get_accounts(self) -> Vec<Account> {
+        let mut accounts = self.into_accounts();
+
+        accounts.sort_unstable_by_key(|account| account.client_id);
+
+        accounts
+    }
+
+    /// Consumes the engine and returns the accounts in the order of the store, skipping the sort
+    /// of [`Engine::get_accounts`] when the order doesn't matter. They're finalized first.
+    pub fn into_accounts(mut self) -> Vec<Account> {
+        self.finalize();
+
+        self.accounts_iter().cloned().collect()
+    }
+}
+
+impl<S: StateStore> EngineCore for Engine<S> {
+    fn apply(&mut self, tx: Transaction) -> Result<(), EngineError> {
+        self.add_transaction(tx)
+    }
+
+    fn submit(&mut self, tx: Transaction) -> Receipt {
+        Engine::submit(self, tx)
+    }
+
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        self.get_account(client_id).cloned()
+    }
+
+    fn accounts_iter(&self) -> Box<dyn Iterator<Item = Account> + '_> {
+        Box::new(Engine::accounts_iter(self).cloned())
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(self)
+    }
+
+    fn risk_tier(&self, client_id: ClientId) -> RiskTier {
+        Engine::risk_tier(self, client_id)
+    }
+
+    fn tx_status(&self, tx_id: u32) -> Option<TxStatus> {
+        Engine::tx_status(self, tx_id)
+    }
+
+    fn changed_clients(&self, tx: &Transaction) -> Vec<ClientId> {
+        self.affected_clients(tx)
+    }
+
+    fn pending(&self) -> Vec<Transaction> {
+        self.quarantined().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{ Arc, Mutex };
+
+    use super::*;
+    use rust_decimal_macros::dec;
+    use crate::{
+        balance_bounds::Bounds,
+        clock::ManualClock,
+        credit::CreditLimits,
+        fees::Fee,
+        metrics::PrometheusRecorder,
+        policy::{ AccountLimits, QuarantineAbove, TierPolicy },
+        reason::ReasonCode,
+        risk::RiskThreshold,
+        scheduler::{ ReleaseKind, ScheduledRelease },
+        types::TransactionType,
+    };
+
+    #[test]
+    fn test_example() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(1.0))));
+
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(2.0))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(2.0))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(1.5))));
+
+        let _ = engine.add_transaction(Transaction::new(2, 5, TransactionType::Withdrawal(dec!(3.0))));
+
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by(|a, b| {
+            match a.client_id < b.client_id {
+                true => std::cmp::Ordering::Less,
+                false => std::cmp::Ordering::Greater,
+            }
+        });
+
+        assert_eq!(accounts.len(), 2);
+
+        assert_eq!(accounts[0], Account {
+            client_id: 1,
+            available: dec!(1.5),
+            held: dec!(0),
+            total: dec!(1.5),
+            locked: false,
+        });
+
+        assert_eq!(accounts[1], Account {
+            client_id: 2,
+            available: dec!(2.0),
+            held: dec!(0),
+            total: dec!(2.0),
+            locked: false,
+        });
+    }
+
+    #[test]
+    fn test_builder() {
+        let clock = ManualClock::at(10 * SECONDS_PER_DAY);
+
+        let mut engine = Engine::builder()
+            .event_sourcing()
+            .clock(Box::new(clock.clone()))
+            .policy(Box::new(QuarantineAbove(dec!(100))))
+            .opening_balances([Account { available: dec!(5), total: dec!(5), ..Account::new(1) }])
+            .build();
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(500)))),
+            Ok(())
+        );
+        assert_eq!(engine.quarantined().count(), 1);
+
+        let _ = engine.add_transaction(
+            Transaction::new(1, 2, TransactionType::Withdrawal(dec!(2))).with_timestamp(0)
+        );
+        assert_eq!(engine.detect_dormant(10).len(), 1);
+
+        engine.rebuild();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(3));
+    }
+
+    #[test]
+    fn test_deposit() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(10));
+    }
+
+    #[test]
+    fn test_withdrawal() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(5))));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(5));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(5));
+    }
+
+    #[test]
+    fn test_withdrawal_not_enough_funds() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(15))));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(10));
+    }
+
+    #[test]
+    fn test_dispute() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(5));
+        assert_eq!(account.total, dec!(15));
+    }
+
+    #[test]
+    fn test_dispute_not_enough_funds() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(5));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(5));
+    }
+
+    #[test]
+    fn test_dispute_unknown() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Dispute));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(15));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(15));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Resolve));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(15));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(15));
+    }
+
+    #[test]
+    fn test_resolve_not_under_dispute() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Resolve));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(5));
+        assert_eq!(account.total, dec!(15));
+    }
+
+    #[test]
+    fn test_resolve_unknown() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Resolve));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(5));
+        assert_eq!(account.total, dec!(15));
+    }
+
+    #[test]
+    fn test_chargeback() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Chargeback));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(10));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_representment() {
+        let mut engine = Engine::new();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Representment)),
+            Err(EngineError::NotChargedBack(2))
+        );
+
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Chargeback)).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Representment)).unwrap();
+
+        let account = engine.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(15));
+        assert_eq!(account.total, dec!(15));
+        assert!(account.locked);
+        assert_eq!(engine.tx_status(2), Some(TxStatus::Represented));
+        assert_eq!(engine.system_accounts().balance(SystemAccount::ChargebackLosses), dec!(0));
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Representment)),
+            Err(EngineError::UnknownTransaction(2))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)),
+            Err(EngineError::AccountLocked(1))
+        );
+
+        // The sender of a charged back transfer gives its refund back, the receiver is unlocked
+        let recorder = Recorder::default();
+        let mut engine = Engine::builder()
+            .unlock_on_representment()
+            .observer(Box::new(recorder.clone()))
+            .build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Transfer { to: 2, amount: dec!(4) })).unwrap();
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Chargeback)).unwrap();
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Representment)).unwrap();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(6));
+        assert_eq!(engine.get_account(2).unwrap().available, dec!(4));
+        assert!(!engine.get_account(2).unwrap().locked);
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&LifecycleEvent::Unlocked { client_id: 2, tx_id: 2 })
+        );
+    }
+
+    #[test]
+    fn test_chargeback_not_under_dispute() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(5));
+        assert_eq!(account.total, dec!(15));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_chargeback_unknown() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Chargeback));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(5));
+        assert_eq!(account.total, dec!(15));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_tx_status() {
+        let mut engine = Engine::new();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(1)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Resolve)).unwrap();
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(20))));
+
+        assert_eq!(engine.tx_status(1), Some(TxStatus::Applied));
+        assert_eq!(engine.tx_status(2), Some(TxStatus::UnderDispute));
+        assert_eq!(engine.tx_status(3), Some(TxStatus::Resolved));
+        assert_eq!(engine.tx_status(4), Some(TxStatus::Rejected(ReasonCode::InsufficientFunds)));
+        assert_eq!(engine.tx_status(5), None);
+
+        // Reusing an id doesn't change the status of the original
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Withdrawal(dec!(1))));
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Chargeback)).unwrap();
+        engine.add_transaction(Transaction::new(1, 4, TransactionType::Deposit(dec!(1)))).unwrap_err();
+
+        assert_eq!(engine.tx_status(1), Some(TxStatus::Applied));
+        assert_eq!(engine.tx_status(2), Some(TxStatus::ChargedBack));
+        assert_eq!(engine.tx_status(4), Some(TxStatus::Rejected(ReasonCode::AccountLocked)));
+    }
+
+    #[test]
+    fn test_reject_reasons() {
+        let mut engine = Engine::new();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(12)))).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(5)))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(3), required: dec!(5) })
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(3), required: dec!(10) })
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 9, TransactionType::Dispute)),
+            Err(EngineError::UnknownTransaction(9))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Resolve)),
+            Err(EngineError::NotUnderDispute(2))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Chargeback)),
+            Err(EngineError::NotUnderDispute(2))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 9, TransactionType::Resolve)),
+            Err(EngineError::UnknownTransaction(9))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 9, TransactionType::Chargeback)),
+            Err(EngineError::UnknownTransaction(9))
+        );
+
+        engine.add_transaction(Transaction::new(1, 5, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)),
+            Err(EngineError::AlreadyDisputed(2))
+        );
+    }
+
+    #[test]
+    fn test_dispute_client_mismatch() {
+        let mut engine = Engine::new();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(10)))).unwrap();
+
+        for tx_type in [TransactionType::Dispute, TransactionType::Resolve, TransactionType::Chargeback] {
+            assert_eq!(
+                engine.add_transaction(Transaction::new(2, 1, tx_type)),
+                Err(EngineError::ClientMismatch { tx_id: 1, client_id: 2 })
+            );
+        }
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 1, TransactionType::Chargeback)),
+            Err(EngineError::ClientMismatch { tx_id: 1, client_id: 2 })
+        );
+
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(10));
+        assert_eq!(engine.get_account(2).unwrap().available, dec!(10));
+        assert_eq!(engine.get_account(2).unwrap().held, dec!(0));
+
+        // A merged client disputes with its new id
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Merge(2))).unwrap();
+        engine.add_transaction(Transaction::new(2, 1, TransactionType::Resolve)).unwrap();
+        assert_eq!(engine.get_account(2).unwrap().available, dec!(20));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let metrics = PrometheusRecorder::new();
+        let mut engine = Engine::builder().metrics(Box::new(metrics.clone())).build();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(20))));
+        let _ = engine.add_transaction(Transaction::new(2, 3, TransactionType::Deposit(dec!(1))));
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("engine_transactions_applied_total{type=\"deposit\"} 2\n"));
+        assert!(
+            rendered.contains(
+                "engine_transactions_rejected_total{type=\"withdrawal\",reason=\"INSUFFICIENT_FUNDS\"} 1\n"
+            )
+        );
+        assert!(rendered.contains("engine_lifecycle_events_total{event=\"created\"} 2\n"));
+        assert!(rendered.contains("engine_accounts 2\n"));
+        assert!(rendered.contains("engine_apply_seconds_count 3\n"));
+        assert!(!rendered.contains("engine_slow_applies_total"));
+    }
+
+    #[test]
+    fn test_add_transactions() {
+        let metrics = PrometheusRecorder::new();
+        let mut engine = Engine::builder().metrics(Box::new(metrics.clone())).build();
+
+        let results = engine.add_transactions([
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))),
+            Transaction::new(1, 2, TransactionType::Withdrawal(dec!(20))),
+            Transaction::new(2, 3, TransactionType::Deposit(dec!(1))),
+        ]);
+
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(10), required: dec!(20) }),
+                Ok(())
+            ]
+        );
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+        assert!(engine.add_transactions([]).is_empty());
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("engine_accounts 2\n"));
+        assert!(rendered.contains("engine_apply_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn test_statements() {
+        let mut engine = Engine::builder().statements().build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Transfer { to: 2, amount: dec!(2) })).unwrap();
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(9))));
+
+        let ledger = engine.ledger().unwrap();
+
+        assert_eq!(ledger.client_ids(), vec![1, 2]);
+        assert_eq!(ledger.statement(1).len(), 2);
+        assert_eq!(ledger.statement(1)[1].available, dec!(3));
+        assert_eq!(ledger.statement(2)[0].available, dec!(2));
+        assert!(Engine::new().ledger().is_none());
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut engine = Engine::builder()
+            .check_invariants()
+            .opening_balances([Account { available: dec!(1), held: dec!(2), total: dec!(3), ..Account::new(9) }])
+            .policy(Box::new(QuarantineAbove(dec!(100))))
+            .build();
+
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))),
+            Transaction::new(1, 2, TransactionType::Deposit(dec!(5))),
+            Transaction::new(1, 3, TransactionType::WithdrawHold(dec!(2))),
+            Transaction::new(1, 1, TransactionType::Dispute),
+            Transaction::new(1, 4, TransactionType::Withdrawal(dec!(1))),
+            Transaction::new(1, 5, TransactionType::Withdrawal(dec!(50))),
+            Transaction::new(1, 1, TransactionType::Resolve),
+            Transaction::new(2, 6, TransactionType::Deposit(dec!(150))),
+            Transaction::new(2, 6, TransactionType::Approve),
+            Transaction::new(2, 7, TransactionType::Withdrawal(dec!(120))),
+            Transaction::new(1, 2, TransactionType::Dispute),
+            Transaction::new(1, 2, TransactionType::Chargeback),
+            Transaction::new(1, 3, TransactionType::WithdrawCommit),
+        ];
+
+        for tx in transactions {
+            let _ = engine.add_transaction(tx);
+
+            assert_eq!(engine.verify(), Ok(()));
+        }
+
+        assert!(engine.get_account(1).unwrap().locked);
+        assert!(engine.invariant_violation().is_none());
+
+        // Funds leaving a locked account in the middle of another client's transaction
+        let locked = engine.get_account(1).unwrap().clone();
+        engine.write_account(Account { available: dec!(0), total: locked.held, ..locked.clone() });
+        let _ = engine.add_transaction(Transaction::new(3, 8, TransactionType::Deposit(dec!(1))));
+
+        let violation = engine.invariant_violation().unwrap();
+
+        assert_eq!(violation.tx.tx_id, 8);
+        assert_eq!(violation.violation, Violation::LockedChanged {
+            before: locked.clone(),
+            after: Account { available: dec!(0), total: locked.held, ..locked },
+        });
+        assert_eq!(
+            violation.to_string(),
+            "after transaction 8 (deposit of client 3): locked client 1 went from 7 available and 0 held to 0 available and 0 held"
+        );
+
+        engine.store.upsert_account(Account { total: dec!(1), ..Account::new(4) });
+        assert_eq!(engine.verify(), Err(Violation::Total(Account { total: dec!(1), ..Account::new(4) })));
+
+        engine.store.upsert_account(Account { held: dec!(1), total: dec!(1), ..Account::new(4) });
+        assert_eq!(engine.verify(), Err(Violation::Held { held: dec!(2) + dec!(120) + dec!(1), holds: dec!(122) }));
+
+        // Only the written accounts are checked, the held totals are carried between transactions
+        let mut engine = Engine::builder().check_invariants().build();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+
+        let account = engine.get_account(1).unwrap().clone();
+        engine.write_account(Account { held: dec!(1), total: dec!(11), ..account });
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(1)))).unwrap();
+
+        assert_eq!(engine.invariant_violation().unwrap().violation, Violation::Held {
+            held: dec!(1),
+            holds: dec!(0),
+        });
+    }
+
+    #[test]
+    fn test_move_clients() {
+        let build = || {
+            Engine::builder()
+                .opening_balances([Account { available: dec!(1), held: dec!(2), total: dec!(3), ..Account::new(9) }])
+                .build()
+        };
+        let before = [
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(4))),
+            Transaction::new(2, 2, TransactionType::Deposit(dec!(5))),
+            Transaction::new(1, 5, TransactionType::Deposit(dec!(10))),
+            Transaction::new(1, 3, TransactionType::Transfer { to: 3, amount: dec!(4) }),
+            Transaction::new(1, 1, TransactionType::Dispute),
+            Transaction::new(9, 4, TransactionType::Merge(3)),
+        ];
+        let after = [
+            Transaction::new(1, 1, TransactionType::Resolve),
+            Transaction::new(9, 6, TransactionType::Deposit(dec!(2))),
+            Transaction::new(3, 3, TransactionType::Dispute),
+            Transaction::new(3, 3, TransactionType::Chargeback),
+        ];
+
+        let mut single = build();
+        let mut from = build();
+        let mut to = Engine::new();
+
+        for tx in before.iter().chain(after.iter()) {
+            let _ = single.add_transaction(tx.clone());
+        }
+
+        for tx in before {
+            from.add_transaction(tx).unwrap();
+        }
+
+        let moved = from.take_clients(&[1, 3, 9].into_iter().collect());
+        assert_eq!(moved.tx_ids().collect::<Vec<_>>(), vec![1, 5, 3]);
+        to.put_clients(moved);
+
+        for tx in after {
+            to.add_transaction(tx).unwrap();
+        }
+
+        assert_eq!(from.verify(), Ok(()));
+        assert_eq!(to.verify(), Ok(()));
+        assert_eq!(to.get_account(1).unwrap().available, dec!(14));
+        assert_eq!(from.get_accounts(), vec![single.get_account(2).unwrap().clone()]);
+        assert_eq!(
+            to.get_accounts(),
+            single.get_accounts().into_iter().filter(|account| account.client_id != 2).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_late_timestamps() {
+        let deposit = |tx_id, timestamp| {
+            Transaction { timestamp: Some(timestamp), ..Transaction::new(1, tx_id, TransactionType::Deposit(dec!(1))) }
+        };
+
+        let mut engine = Engine::builder().reject_late_timestamps().build();
+
+        assert_eq!(engine.add_transaction(deposit(1, 100)), Ok(()));
+        assert_eq!(engine.add_transaction(deposit(2, 100)), Ok(()));
+        assert_eq!(
+            engine.add_transaction(deposit(3, 50)),
+            Err(EngineError::LateTimestamp { client_id: 1, last: 100, received: 50 })
+        );
+        assert_eq!(engine.add_transaction(Transaction::new(1, 4, TransactionType::Deposit(dec!(1)))), Ok(()));
+        assert_eq!(engine.add_transaction(deposit(5, 200)), Ok(()));
+        assert_eq!(engine.add_transaction(Transaction { client_id: 2, ..deposit(6, 50) }), Ok(()));
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(4));
+        assert_eq!(engine.activity_span(1), Some((100, 200)));
+        assert_eq!(engine.activity_span(2), Some((50, 50)));
+        assert_eq!(engine.activity_span(3), None);
+
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.add_transaction(deposit(1, 100)), Ok(()));
+        assert_eq!(engine.add_transaction(deposit(2, 50)), Ok(()));
+        assert_eq!(engine.activity_span(1), Some((50, 100)));
+    }
+
+    #[test]
+    fn test_rebuild_with_late_timestamps() {
+        let deposit = |tx_id, amount, timestamp| Transaction {
+            timestamp: Some(timestamp),
+            ..Transaction::new(1, tx_id, TransactionType::Deposit(amount))
+        };
+
+        let mut engine = Engine::builder().event_sourcing().reject_late_timestamps().build();
+
+        assert_eq!(engine.add_transaction(deposit(1, dec!(10), 100)), Ok(()));
+        assert_eq!(engine.add_transaction(deposit(2, dec!(5), 200)), Ok(()));
+
+        engine.rebuild();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(15));
+        assert_eq!(engine.now(), Some(200));
+        assert_eq!(
+            engine.add_transaction(deposit(3, dec!(1), 150)),
+            Err(EngineError::LateTimestamp { client_id: 1, last: 200, received: 150 })
+        );
+    }
+
+    #[test]
+    fn test_upstream_sequence() {
+        let deposit = |tx_id, seq| {
+            Transaction { seq: Some(seq), ..Transaction::new(1, tx_id, TransactionType::Deposit(dec!(1))) }
+        };
+
+        for reject in [false, true] {
+            let mut engine = match reject {
+                true => Engine::builder().reject_out_of_sequence().build(),
+                false => Engine::new(),
+            };
+
+            assert_eq!(engine.add_transaction(deposit(1, 5)), Ok(()));
+            assert_eq!(engine.add_transaction(deposit(2, 8)), Ok(()));
+
+            let duplicate = engine.add_transaction(deposit(3, 8));
+            let late = engine.add_transaction(deposit(4, 6));
+
+            match reject {
+                true => {
+                    assert_eq!(
+                        duplicate,
+                        Err(EngineError::OutOfSequence { client_id: 1, last: 8, received: 8 })
+                    );
+                    assert_eq!(late, Err(EngineError::OutOfSequence { client_id: 1, last: 8, received: 6 }));
+                }
+                false => {
+                    assert_eq!((duplicate, late), (Ok(()), Ok(())));
+                }
+            }
+
+            // Transactions without a sequence number aren't checked
+            assert_eq!(
+                engine.add_transaction(Transaction::new(1, 5, TransactionType::Deposit(dec!(1)))),
+                Ok(())
+            );
+            assert_eq!(
+                engine.sequence_summary(),
+                SequenceSummary { gaps: 1, missing: 2, duplicates: 1, late: 1 }
+            );
+        }
+    }
+
+    #[test]
+    fn test_slow_apply() {
+        let metrics = PrometheusRecorder::new();
+        let mut engine = Engine::builder()
+            .metrics(Box::new(metrics.clone()))
+            .slow_apply_threshold(Duration::ZERO)
+            .build();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("engine_slow_applies_total{type=\"deposit\"} 1\n"));
+        assert!(rendered.contains("engine_slow_applies_total{type=\"dispute\"} 1\n"));
+    }
+
+    #[test]
+    fn test_locked_account() {
+        for locked_deposits in [false, true] {
+            let mut engine = match locked_deposits {
+                true => Engine::builder().allow_locked_deposits().build(),
+                false => Engine::new(),
+            };
+
+            let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+            let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+            let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(1))));
+            let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+            let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+            let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback));
+
+            assert_eq!(
+                engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(1)))),
+                Err(EngineError::AccountLocked(1))
+            );
+            assert_eq!(
+                engine.add_transaction(Transaction::new(1, 3, TransactionType::Dispute)),
+                Err(EngineError::AccountLocked(1))
+            );
+            assert_eq!(
+                engine.add_transaction(Transaction::new(1, 2, TransactionType::Resolve)),
+                Ok(())
+            );
+
+            let deposit = engine.add_transaction(
+                Transaction::new(1, 5, TransactionType::Deposit(dec!(2)))
+            );
+
+            match locked_deposits {
+                true => assert_eq!(deposit, Ok(())),
+                false => assert_eq!(deposit, Err(EngineError::AccountLocked(1))),
+            }
+        }
+    }
+
+    #[test]
+    fn test_duplicate_tx_id() {
+        let mut engine = quarantining_engine();
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(500))));
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 1, TransactionType::Deposit(dec!(5)))),
+            Err(EngineError::DuplicateTxId(1))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(5)))),
+            Err(EngineError::DuplicateTxId(2))
+        );
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(100));
+        assert_eq!(engine.get_account(2), None);
+    }
+
+    #[test]
+    fn test_duplicate_of_withdrawal_and_settled_deposit() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Resolve));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(3))));
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(3)))),
+            Err(EngineError::DuplicateTxId(2))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))),
+            Err(EngineError::DuplicateTxId(1))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)),
+            Err(EngineError::UnknownTransaction(2))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)),
+            Err(EngineError::UnknownTransaction(1))
+        );
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(7));
+    }
+
+    #[test]
+    fn test_idempotent_replay() {
+        let mut engine = Engine::builder().idempotent().build();
+
+        let replay = [
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))),
+            Transaction::new(1, 2, TransactionType::Withdrawal(dec!(3))),
+            Transaction::new(1, 3, TransactionType::Deposit(dec!(5))),
+            Transaction::new(1, 3, TransactionType::Dispute),
+        ];
+
+        for tx in replay.iter().chain(replay.iter().take(3)) {
+            let _ = engine.add_transaction(tx.clone());
+        }
+
+        let account = engine.get_account(1).unwrap();
+        assert_eq!((account.available, account.held), (dec!(7), dec!(5)));
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))),
+            Ok(())
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(11)))),
+            Err(EngineError::DuplicateTxId(1))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 2, TransactionType::Withdrawal(dec!(3)))),
+            Err(EngineError::DuplicateTxId(2))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(3)))),
+            Err(EngineError::DuplicateTxId(2))
+        );
+    }
+
+    #[test]
+    fn test_seed_account() {
+        let mut engine = Engine::new();
+
+        engine.seed_account(Account {
+            client_id: 1,
+            available: dec!(10),
+            held: dec!(5),
+            total: dec!(15),
+            locked: false,
+        });
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Withdrawal(dec!(7))));
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(3));
+        assert_eq!(account.held, dec!(5));
+        assert_eq!(account.total, dec!(8));
+    }
+
+    #[test]
+    fn test_event_sourced_logs_applied_transactions() {
+        let mut engine = Engine::event_sourced();
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))),
+            Ok(())
+        );
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(15)))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(10), required: dec!(15) })
+        );
+
+        assert_eq!(engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)), Ok(()));
+
+        let tx_ids: Vec<u32> = engine
+            .events()
+            .iter()
+            .map(|tx| tx.tx_id)
+            .collect();
+        assert_eq!(tx_ids, vec![1, 1]);
+    }
+
+    #[test]
+    fn test_rebuild_with_upstream_sequence() {
+        let deposit = |tx_id, seq| {
+            Transaction { seq: Some(seq), ..Transaction::new(1, tx_id, TransactionType::Deposit(dec!(1))) }
+        };
+
+        let mut engine = Engine::builder().event_sourcing().reject_out_of_sequence().build();
+
+        assert_eq!(engine.add_transaction(deposit(1, 1)), Ok(()));
+        assert_eq!(engine.add_transaction(deposit(2, 2)), Ok(()));
+        assert!(engine.add_transaction(deposit(3, 2)).is_err());
+
+        engine.rebuild();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(2));
+        assert_eq!(engine.sequence_summary(), SequenceSummary { duplicates: 1, ..SequenceSummary::default() });
+        assert_eq!(
+            engine.add_transaction(deposit(4, 2)),
+            Err(EngineError::OutOfSequence { client_id: 1, last: 2, received: 2 })
+        );
+        assert_eq!(engine.add_transaction(deposit(5, 3)), Ok(()));
+    }
+
+    #[test]
+    fn test_event_sourced_rebuild() {
+        let mut engine = Engine::event_sourced();
+
+        engine.seed_account(Account {
+            client_id: 1,
+            available: dec!(3),
+            held: dec!(0),
+            total: dec!(3),
+            locked: false,
+        });
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+
+        engine.store.clear();
+        engine.rebuild();
+
+        let account = engine.store.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(3));
+        assert_eq!(account.held, dec!(10));
+        assert_eq!(account.total, dec!(13));
+        assert!(engine.store.has_tx(1));
+    }
+
+    #[test]
+    fn test_not_event_sourced() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        assert!(engine.events().is_empty());
+    }
+
+    // Counts the writes, to check the engine writes every change back to its store
+    #[derive(Default)]
+    struct CountingStore {
+        inner: MemoryStore,
+        writes: usize,
+    }
+
+    impl StateStore for CountingStore {
+        fn get_account(&self, client_id: ClientId) -> Option<&Account> {
+            self.inner.get_account(client_id)
+        }
+
+        fn upsert_account(&mut self, account: Account) {
+            self.writes += 1;
+            self.inner.upsert_account(account);
+        }
+
+        fn remove_account(&mut self, client_id: ClientId) -> Option<Account> {
+            self.inner.remove_account(client_id)
+        }
+
+        fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+            self.inner.accounts()
+        }
+
+        fn account_count(&self) -> usize {
+            self.inner.account_count()
+        }
+
+        fn get_tx(&self, tx_id: u32) -> Option<HistoryEntry> {
+            self.inner.get_tx(tx_id)
+        }
+
+        fn put_tx(&mut self, tx_id: u32, entry: HistoryEntry) {
+            self.writes += 1;
+            self.inner.put_tx(tx_id, entry);
+        }
+
+        fn remove_tx(&mut self, tx_id: u32) {
+            self.inner.remove_tx(tx_id);
+        }
+
+        fn tx_count(&self) -> usize {
+            self.inner.tx_count()
+        }
+
+        fn push_order(&mut self, tx_id: u32, timestamp: Option<u64>) {
+            self.inner.push_order(tx_id, timestamp);
+        }
+
+        fn order(&self) -> Box<dyn Iterator<Item = (u32, Option<u64>)> + '_> {
+            self.inner.order()
+        }
+
+        fn compact_order(&mut self) {
+            self.inner.compact_order();
+        }
+
+        fn clear(&mut self) {
+            self.inner.clear();
+        }
+    }
+
+    #[test]
+    fn test_custom_store() {
+        let mut engine = Engine::builder().build_with_store(CountingStore::default());
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Resolve)).unwrap();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+        assert_eq!(engine.store.get_tx(1).unwrap().info, TransactionInfo::Resolved);
+        assert!(engine.store.writes >= 6);
+        assert_eq!(Snapshot::capture(&engine).accounts.len(), 1);
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<LifecycleEvent>>>,
+        balances: Arc<Mutex<Vec<(ClientId, Decimal)>>>,
+    }
+
+    impl AccountObserver for Recorder {
+        fn on_lifecycle_event(&mut self, event: &LifecycleEvent) {
+            self.events.lock().unwrap().push(event.clone());
+        }
+
+        fn on_balance_change(&mut self, account: &Account) {
+            self.balances.lock().unwrap().push((account.client_id, account.available));
+        }
+    }
+
+    #[test]
+    fn test_balance_changes() {
+        let mut engine = Engine::new();
+        let recorder = Recorder::default();
+        engine.add_observer(Box::new(recorder.clone()));
+
+        engine.seed_account(Account { available: dec!(2), total: dec!(2), ..Account::new(2) });
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(20))));
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Merge(2)));
+
+        assert_eq!(*recorder.balances.lock().unwrap(), vec![(2, dec!(2)), (1, dec!(10)), (2, dec!(12))]);
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let mut engine = Engine::new();
+        let recorder = Recorder::default();
+        engine.add_observer(Box::new(recorder.clone()));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Withdrawal(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(5))));
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Chargeback));
+
+        assert_eq!(*recorder.events.lock().unwrap(), vec![
+            LifecycleEvent::Created { client_id: 1 },
+            LifecycleEvent::FirstDeposit { client_id: 1, tx_id: 2, amount: dec!(10) },
+            LifecycleEvent::Locked { client_id: 1, tx_id: 3 }
+        ]);
+    }
+
+    impl AuditSink for Arc<Mutex<Vec<AuditRecord>>> {
+        fn record(&mut self, record: &AuditRecord) {
+            self.lock().unwrap().push(record.clone());
+        }
+    }
+
+    #[test]
+    fn test_audit() {
+        let records = Arc::new(Mutex::new(vec![]));
+        let mut engine = Engine::builder().event_sourcing().audit(Box::new(records.clone())).build();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))).with_timestamp(5));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(20))));
+        let _ = engine.add_transaction(Transaction::new(2, 3, TransactionType::Dispute));
+
+        // Replays aren't audited again
+        engine.rebuild();
+
+        let account = Account { available: dec!(10), total: dec!(10), ..Account::new(1) };
+        let deposit = Transaction::new(1, 1, TransactionType::Deposit(dec!(10))).with_timestamp(5);
+        let withdrawal = Transaction::new(1, 2, TransactionType::Withdrawal(dec!(20)));
+        let dispute = Transaction::new(2, 3, TransactionType::Dispute);
+
+        assert_eq!(*records.lock().unwrap(), vec![
+            AuditRecord::new(&deposit, None, Some(&account), Some(5)),
+            AuditRecord::new(&withdrawal, Some(ReasonCode::InsufficientFunds), Some(&account), Some(5)),
+            AuditRecord::new(&dispute, Some(ReasonCode::UnknownTx), Some(&Account::new(2)), Some(5))
+        ]);
+    }
+
+    #[test]
+    fn test_daily_operations() {
+        let records = Arc::new(Mutex::new(vec![]));
+        let mut engine = Engine::builder()
+            .event_sourcing()
+            .statements()
+            .daily_operation(Operation::Interest(dec!(0.01)))
+            .daily_operation(Operation::Sweep(dec!(100)))
+            .audit(Box::new(records.clone()))
+            .build();
+
+        let deposit = |client_id, tx_id, amount, timestamp| {
+            Transaction::new(client_id, tx_id, TransactionType::Deposit(amount)).with_timestamp(timestamp)
+        };
+
+        engine.add_transaction(deposit(1, 1, dec!(50), 10)).unwrap();
+        engine.add_transaction(deposit(2, 2, dec!(200), 20)).unwrap();
+        engine.add_transaction(deposit(3, 3, dec!(5), 30)).unwrap();
+        engine.add_transaction(Transaction::new(3, 3, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(3, 3, TransactionType::Chargeback)).unwrap();
+
+        // Goes past the end of the first day, the operations run before the deposit
+        engine.add_transaction(deposit(1, 4, dec!(1), SECONDS_PER_DAY + 5)).unwrap();
+
+        let accounts = |engine: &Engine| {
+            [1, 2].map(|client_id| engine.get_account(client_id).unwrap().available)
+        };
+
+        assert_eq!(accounts(&engine), [dec!(51.5), dec!(100)]);
+
+        let adjustment = |client_id, tx_id, amount, reason: &str| {
+            Transaction::new(client_id, tx_id, TransactionType::Adjustment { amount, reason: reason.to_string() })
+                .with_timestamp(SECONDS_PER_DAY)
+        };
+        let interest = adjustment(2, u32::MAX - 1, dec!(2), "interest");
+        let account = Account { available: dec!(202), total: dec!(202), ..Account::new(2) };
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 9);
+        assert_eq!(records[6], AuditRecord::new(&interest, None, Some(&account), Some(SECONDS_PER_DAY)));
+        assert_eq!(records[7].tx, u32::MAX - 2);
+        assert_eq!(records[7].available, Some(dec!(100)));
+        drop(records);
+
+        let statement = engine.ledger().unwrap().statement(1);
+        assert_eq!(statement[1].tx_type, "adjustment");
+        assert_eq!(statement[1].amount, Some(dec!(0.5)));
+
+        assert_eq!(engine.system_accounts().balance(SystemAccount::Adjustments), dec!(99.5));
+
+        // They're run again with the transaction that ran them
+        engine.rebuild();
+
+        assert_eq!(accounts(&engine), [dec!(51.5), dec!(100)]);
+    }
+
+    #[test]
+    fn test_escalate_duplicate_disputes() {
+        let recorder = Recorder::default();
+        let mut engine = Engine::builder()
+            .event_sourcing()
+            .escalate_duplicate_disputes(1)
+            .observer(Box::new(recorder.clone()))
+            .build();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+
+        for _ in 0..3 {
+            assert_eq!(
+                engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)),
+                Err(EngineError::AlreadyDisputed(1))
+            );
+        }
+
+        assert_eq!(engine.duplicate_disputes().collect::<Vec<_>>(), vec![(1, 3)]);
+        assert_eq!(engine.risk_tier(1), RiskTier::High);
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&LifecycleEvent::Escalated { client_id: 1, tx_id: 1, duplicate_disputes: 2 })
+        );
+
+        engine.rebuild();
+        assert_eq!(engine.risk_tier(1), RiskTier::High);
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(10));
+    }
+
+    fn prepaid_bounds() -> BalanceBounds {
+        BalanceBounds {
+            classes: HashMap::from([
+                ("prepaid".to_string(), Bounds { floor: Some(dec!(5)), ceiling: Some(dec!(100)) }),
+            ]),
+            accounts: HashMap::from([(1, "prepaid".to_string())]),
+            default_class: None,
+        }
+    }
+
+    #[test]
+    fn test_balance_bounds() {
+        let mut engine = Engine::builder().balance_bounds(prepaid_bounds()).build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(60)))).unwrap();
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(50)))),
+            Err(EngineError::AboveCeiling { client_id: 1, total: dec!(110), ceiling: dec!(100) })
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(56)))),
+            Err(EngineError::BelowFloor { client_id: 1, available: dec!(4), floor: dec!(5) })
+        );
+        engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(55)))).unwrap();
+
+        // Clients without a class are unbounded
+        engine.add_transaction(Transaction::new(2, 5, TransactionType::Deposit(dec!(500)))).unwrap();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(5));
+        assert!(engine.overflows().is_empty());
+    }
+
+    #[test]
+    fn test_route_overflow() {
+        let mut engine = Engine::builder()
+            .event_sourcing()
+            .balance_bounds(prepaid_bounds())
+            .route_overflow()
+            .build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(60)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(50)))).unwrap();
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(1)))),
+            Err(EngineError::AboveCeiling { client_id: 1, total: dec!(101), ceiling: dec!(100) })
+        );
+
+        let overflow = Overflow {
+            client_id: 1,
+            tx_id: 2,
+            class: "prepaid".to_string(),
+            amount: dec!(50),
+            credited: dec!(40),
+            excess: dec!(10),
+        };
+        assert_eq!(engine.overflows().len(), 1);
+        assert_eq!(engine.overflows()[0], overflow);
+        assert_eq!(engine.get_account(1).unwrap().total, dec!(100));
+
+        // Only the credited part is held by a dispute
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(40));
+
+        engine.rebuild();
+        assert_eq!(engine.overflows(), [overflow]);
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(40));
+    }
+
+    #[test]
+    fn test_builder_rounding_and_retention() {
+        let fees = FeeSchedule {
+            withdrawal: Some(Fee { flat: dec!(0), percent: dec!(1) }),
+            ..Default::default()
+        };
+        let mut engine = Engine::builder()
+            .fees(fees)
+            .rounding(Rounding { precision: 2, mode: "truncate".parse().unwrap() })
+            .retention(Retention { max_entries: Some(100), ..Default::default() })
+            .build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(100)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(12.99)))).unwrap();
+
+        // 1% of 12.99 truncated to 2 places
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(100) - dec!(12.99) - dec!(0.12));
+
+        for tx_id in 3..=PRUNE_INTERVAL as u32 {
+            engine.add_transaction(Transaction::new(2, tx_id, TransactionType::Deposit(dec!(1)))).unwrap();
+        }
+
+        // Pruned before the last transaction was applied
+        assert_eq!(engine.history().count(), 101);
+    }
+
+    #[test]
+    fn test_fees() {
+        let fees = FeeSchedule {
+            deposit: Some(Fee { flat: dec!(1), percent: dec!(0) }),
+            withdrawal: Some(Fee { flat: dec!(0.5), percent: dec!(10) }),
+            ..Default::default()
+        };
+        let mut engine = Engine::builder().event_sourcing().fees(fees).build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(20)))).unwrap();
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(1)))),
+            Err(EngineError::InvalidAmount(dec!(1)))
+        );
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(10)))).unwrap();
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(7)))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(7.5), required: dec!(8.2) })
+        );
+
+        // A dispute holds what was credited
+        engine.add_transaction(Transaction::new(1, 5, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 5, TransactionType::Dispute)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(4));
+        assert_eq!(engine.get_account(1).unwrap().total, dec!(11.5));
+
+        assert_eq!(engine.fee_summary().total(), dec!(3.5));
+        assert_eq!(engine.system_accounts().balance(SystemAccount::Fees), dec!(3.5));
+
+        engine.rebuild();
+        assert_eq!(engine.fee_summary().total(), dec!(3.5));
+        assert_eq!(engine.system_accounts().balance(SystemAccount::Fees), dec!(3.5));
+    }
+
+    #[test]
+    fn test_credit_limits() {
+        let limits = CreditLimits::new([(1, dec!(50))]);
+        let mut engine = Engine::builder().credit_limits(limits).build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(40)))).unwrap();
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::WithdrawHold(dec!(21)))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(20), required: dec!(21) })
+        );
+        engine.add_transaction(Transaction::new(1, 5, TransactionType::Transfer { to: 2, amount: dec!(20) })).unwrap();
+
+        // Clients without a limit have no overdraft
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 6, TransactionType::Withdrawal(dec!(31)))),
+            Err(EngineError::InsufficientFunds { client_id: 2, available: dec!(30), required: dec!(31) })
+        );
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(-50));
+        assert_eq!(engine.credit_limit(1), dec!(50));
+        assert_eq!(engine.credit_limit(2), dec!(0));
+    }
+
+    #[test]
+    fn test_engine_events() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = Engine::builder().event_handler(Box::new(sender)).build();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(3))));
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(30))));
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Deposit(dec!(2))));
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Resolve));
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 5, TransactionType::Deposit(dec!(1))));
+        let _ = engine.add_transaction(Transaction::new(1, 5, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 5, TransactionType::Chargeback));
+
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![
+            EngineEvent::Deposited { client_id: 1, tx_id: 1, amount: dec!(10) },
+            EngineEvent::Withdrawn { client_id: 1, tx_id: 2, amount: dec!(3) },
+            EngineEvent::TransactionRejected {
+                client_id: 1,
+                tx_id: 3,
+                reason: ReasonCode::InsufficientFunds,
+            },
+            EngineEvent::Deposited { client_id: 1, tx_id: 4, amount: dec!(2) },
+            EngineEvent::DisputeOpened { client_id: 1, tx_id: 4, amount: dec!(2) },
+            EngineEvent::DisputeResolved { client_id: 1, tx_id: 4, amount: dec!(2) },
+            EngineEvent::TransactionRejected { client_id: 1, tx_id: 4, reason: ReasonCode::UnknownTx },
+            EngineEvent::Deposited { client_id: 1, tx_id: 5, amount: dec!(1) },
+            EngineEvent::DisputeOpened { client_id: 1, tx_id: 5, amount: dec!(1) },
+            EngineEvent::ChargedBack { client_id: 1, tx_id: 5, amount: dec!(1) },
+            EngineEvent::AccountLocked { client_id: 1, tx_id: 5 },
+        ]);
+    }
+
+    #[test]
+    fn test_query_accounts_mid_stream() {
+        let mut engine = Engine::builder().allow_deletes().build();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(5))));
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+        assert_eq!(engine.account_count(), 2);
+
+        let _ = engine.add_transaction(Transaction::new(2, 3, TransactionType::Delete));
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(4))));
+
+        let accounts: Vec<(ClientId, Decimal)> = engine
+            .accounts_iter()
+            .map(|account| (account.client_id, account.available))
+            .collect();
+        assert_eq!(accounts, vec![(1, dec!(6))]);
+        assert_eq!(engine.account_count(), 1);
+        assert!(engine.get_account(2).is_none());
+    }
+
+    #[derive(Clone, Default)]
+    struct WithdrawalLimit {
+        applied: Arc<Mutex<Vec<(u32, ClientId, Decimal)>>>,
+    }
+
+    impl EngineHooks for WithdrawalLimit {
+        fn before_apply(&self, tx: &Transaction) -> Decision {
+            match tx.tx_type {
+                TransactionType::Withdrawal(amount) if amount > dec!(5) => {
+                    Decision::Reject(ReasonCode::LimitExceeded)
+                }
+                _ => Decision::Allow,
+            }
+        }
+
+        fn after_apply(&mut self, tx: &Transaction, account: &Account) {
+            self.applied.lock().unwrap().push((tx.tx_id, account.client_id, account.available));
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let hooks = WithdrawalLimit::default();
+        let mut engine = Engine::builder().hooks(Box::new(hooks.clone())).build();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(
+            Transaction::new(1, 2, TransactionType::Transfer { to: 2, amount: dec!(4) })
+        );
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(6)))),
+            Err(EngineError::Policy(ReasonCode::LimitExceeded))
+        );
+        assert!(engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(5)))).is_ok());
+
+        assert_eq!(*hooks.applied.lock().unwrap(), vec![
+            (1, 1, dec!(10)),
+            (2, 1, dec!(6)),
+            (2, 2, dec!(4)),
+            (4, 1, dec!(1))
+        ]);
+    }
+
+    #[test]
+    fn test_lifecycle_events_seeded_account() {
+        let mut engine = Engine::new();
+        let recorder = Recorder::default();
+        engine.add_observer(Box::new(recorder.clone()));
+
+        engine.seed_account(Account::new(1));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        assert!(recorder.events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lifecycle_events_not_repeated_on_rebuild() {
+        let mut engine = Engine::event_sourced();
+        let recorder = Recorder::default();
+        engine.add_observer(Box::new(recorder.clone()));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        engine.rebuild();
+
+        assert_eq!(recorder.events.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_alerts() {
+        let recorder = Recorder::default();
+        let mut engine = Engine::builder()
+            .observer(Box::new(recorder.clone()))
+            .alert(AlertRule::HeldAbove(dec!(5)))
+            .alert(AlertRule::Locked)
+            .build();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(1))));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback));
+
+        let alerts: Vec<LifecycleEvent> = recorder.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| matches!(event, LifecycleEvent::Alert { .. }))
+            .cloned()
+            .collect();
+
+        assert_eq!(alerts, vec![
+            LifecycleEvent::Alert {
+                client_id: 1,
+                tx_id: 1,
+                rule: "held>5".to_string(),
+                available: dec!(0),
+                held: dec!(10),
+            },
+            LifecycleEvent::Alert {
+                client_id: 1,
+                tx_id: 1,
+                rule: "locked".to_string(),
+                available: dec!(1),
+                held: dec!(0),
+            }
+        ]);
+    }
+
+    #[test]
+    fn test_alert_events_and_report() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let audit = Arc::new(Mutex::new(vec![]));
+        let mut engine = Engine::builder()
+            .event_handler(Box::new(sender))
+            .audit(Box::new(audit.clone()))
+            .alert(AlertRule::TotalChange { above: dec!(100), window: 60 })
+            .alert(AlertRule::AvailableBelow(dec!(5)))
+            .build();
+
+        let deposit = |tx_id, amount, at| Transaction::new(1, tx_id, TransactionType::Deposit(amount)).with_timestamp(at);
+
+        engine.add_transaction(deposit(1, dec!(80), 0)).unwrap();
+        engine.add_transaction(deposit(2, dec!(50), 30)).unwrap();
+        // 110 more than a minute ago
+        engine.add_transaction(deposit(3, dec!(60), 50)).unwrap();
+        // The total a minute ago was the one after the last deposit
+        engine.add_transaction(deposit(4, dec!(1), 150)).unwrap();
+        engine.add_transaction(
+            Transaction::new(1, 5, TransactionType::Withdrawal(dec!(188))).with_timestamp(200)
+        ).unwrap();
+
+        let events: Vec<EngineEvent> = receiver
+            .try_iter()
+            .filter(|event| matches!(event, EngineEvent::AlertRaised { .. }))
+            .collect();
+
+        assert_eq!(events, vec![
+            EngineEvent::AlertRaised {
+                client_id: 1,
+                tx_id: 3,
+                rule: "change>100/60".to_string(),
+                available: dec!(190),
+                held: dec!(0),
+            },
+            EngineEvent::AlertRaised {
+                client_id: 1,
+                tx_id: 5,
+                rule: "change>100/60".to_string(),
+                available: dec!(3),
+                held: dec!(0),
+            },
+            EngineEvent::AlertRaised {
+                client_id: 1,
+                tx_id: 5,
+                rule: "available<5".to_string(),
+                available: dec!(3),
+                held: dec!(0),
+            }
+        ]);
+
+        let audit = audit.lock().unwrap();
+        assert!(audit[1].alerts.is_empty());
+        assert_eq!(audit[2].alerts, vec!["change>100/60".to_string()]);
+        assert_eq!(audit[4].alerts.len(), 2);
+
+        let report = engine.alerts_report();
+        assert_eq!(
+            report.iter().map(|alert| (alert.tx, alert.raised_at, alert.active)).collect::<Vec<_>>(),
+            vec![(3, Some(50), false), (5, Some(200), true), (5, Some(200), true)]
+        );
+        assert_eq!(report[0].total, dec!(190));
+    }
+
+    #[test]
+    fn test_detect_dormant() {
+        let mut engine = Engine::new();
+        let recorder = Recorder::default();
+
+        let _ = engine.add_transaction(
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))).with_timestamp(0)
+        );
+        let _ = engine.add_transaction(
+            Transaction::new(2, 2, TransactionType::Deposit(dec!(10))).with_timestamp(0)
+        );
+        let _ = engine.add_transaction(Transaction::new(3, 3, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(
+            Transaction::new(2, 4, TransactionType::Withdrawal(dec!(1))).with_timestamp(
+                SECONDS_PER_DAY * 20
+            )
+        );
+        let _ = engine.add_transaction(
+            Transaction::new(4, 5, TransactionType::Deposit(dec!(10))).with_timestamp(
+                SECONDS_PER_DAY * 30
+            )
+        );
+
+        engine.add_observer(Box::new(recorder.clone()));
+
+        let dormant = engine.detect_dormant(10);
+        assert_eq!(dormant, vec![
+            DormantAccount { client_id: 1, last_activity: 0, idle_days: 30 },
+            DormantAccount { client_id: 2, last_activity: SECONDS_PER_DAY * 20, idle_days: 10 }
+        ]);
+
+        assert_eq!(*recorder.events.lock().unwrap(), vec![
+            LifecycleEvent::Dormant { client_id: 1, last_activity: 0, days: 30 },
+            LifecycleEvent::Dormant { client_id: 2, last_activity: SECONDS_PER_DAY * 20, days: 10 }
+        ]);
+    }
+
+    #[test]
+    fn test_detect_dormant_without_timestamps() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        assert!(engine.detect_dormant(0).is_empty());
+    }
+
+    #[test]
+    fn test_get_accounts() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute));
+
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Chargeback));
+
+        let account = engine.get_accounts().pop().unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(10));
+    }
+
+    #[test]
+    fn test_get_accounts_sorted() {
+        let transactions: Vec<Transaction> = (0..1000u32)
+            .map(|tx_id| {
+                let client_id = ((tx_id * 7919) % 500) as ClientId;
+                Transaction::new(client_id, tx_id, TransactionType::Deposit(dec!(1.5)))
+            })
+            .collect();
+
+        let run = || {
+            let mut engine = Engine::new();
+
+            for tx in transactions.iter() {
+                let _ = engine.add_transaction(tx.clone());
+            }
+
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for account in engine.get_accounts() {
+                writer.serialize(account).unwrap();
+            }
+            writer.into_inner().unwrap()
+        };
+
+        let output = run();
+        assert_eq!(output, run());
+
+        let clients: Vec<ClientId> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(clients, (0..500).collect::<Vec<ClientId>>());
+    }
+
+    #[test]
+    fn test_submit_receipt() {
+        let mut engine = Engine::new();
+
+        let receipt = engine.submit(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        assert_eq!(receipt, Receipt {
+            tx_id: 1,
+            client_id: 1,
+            outcome: Ok(()),
+            available_after: dec!(10),
+            held_after: dec!(0),
+            locked: false,
+        });
+
+        let receipt = engine.submit(Transaction::new(1, 1, TransactionType::Dispute));
+        assert_eq!((receipt.available_after, receipt.held_after), (dec!(0), dec!(10)));
+
+        // A rejection gives the balances it left untouched
+        let receipt = engine.submit(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(1))));
+        assert_eq!(receipt.outcome, Err(EngineError::InsufficientFunds {
+            client_id: 1,
+            available: dec!(0),
+            required: dec!(1),
+        }));
+        assert_eq!(receipt.held_after, dec!(10));
+
+        assert!(engine.submit(Transaction::new(1, 1, TransactionType::Chargeback)).locked);
+
+        // The balances of the surviving client of a merge
+        engine.submit(Transaction::new(2, 3, TransactionType::Deposit(dec!(4)))).outcome.unwrap();
+        engine.submit(Transaction::new(3, 4, TransactionType::Deposit(dec!(1)))).outcome.unwrap();
+
+        let receipt = engine.submit(Transaction::new(3, 5, TransactionType::Merge(2)));
+        assert_eq!((receipt.client_id, receipt.available_after), (2, dec!(5)));
+
+        let receipt = engine.submit(Transaction::new(9, 6, TransactionType::Dispute));
+        assert_eq!((receipt.client_id, receipt.available_after, receipt.locked), (9, dec!(0), false));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut engine = Engine::new();
+        let recorder = Recorder::default();
+        engine.add_observer(Box::new(recorder.clone()));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(5))));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Dispute));
+
+        assert_eq!(engine.add_transaction(Transaction::new(2, 3, TransactionType::Merge(1))), Ok(()));
+
+        assert_eq!(engine.get_account(2), None);
+        assert_eq!(engine.get_account(1), Some(&Account {
+            client_id: 1,
+            available: dec!(10),
+            held: dec!(5),
+            total: dec!(15),
+            locked: false,
+        }));
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&LifecycleEvent::Closed { client_id: 2 })
+        );
+    }
+
+    #[test]
+    fn test_merge_rekeys_later_transactions() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(5))));
+        let _ = engine.add_transaction(Transaction::new(3, 3, TransactionType::Deposit(dec!(1))));
+        let _ = engine.add_transaction(Transaction::new(2, 4, TransactionType::Merge(3)));
+        let _ = engine.add_transaction(Transaction::new(3, 5, TransactionType::Merge(1)));
+
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Chargeback));
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts, vec![Account {
+            client_id: 1,
+            available: dec!(11),
+            held: dec!(0),
+            total: dec!(11),
+            locked: true,
+        }]);
+    }
+
+    #[test]
+    fn test_merge_propagates_lock() {
+        let mut engine = Engine::new();
+        let recorder = Recorder::default();
+        engine.add_observer(Box::new(recorder.clone()));
+
+        engine.seed_account(Account {
+            client_id: 2,
+            available: dec!(1),
+            held: dec!(0),
+            total: dec!(1),
+            locked: true,
+        });
+
+        let _ = engine.add_transaction(Transaction::new(2, 1, TransactionType::Merge(1)));
+
+        assert!(engine.get_account(1).unwrap().locked);
+        assert_eq!(*recorder.events.lock().unwrap(), vec![
+            LifecycleEvent::Created { client_id: 1 },
+            LifecycleEvent::Locked { client_id: 1, tx_id: 1 },
+            LifecycleEvent::Closed { client_id: 2 }
+        ]);
+    }
+
+    #[test]
+    fn test_merge_unknown_client() {
+        let mut engine = Engine::new();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 2, TransactionType::Merge(1))),
+            Err(EngineError::UnknownClient(2))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Merge(1))),
+            Err(EngineError::UnknownClient(1))
+        );
+        assert!(engine.get_account(2).is_none());
+    }
+
+    #[test]
+    fn test_event_sourced_rebuild_with_merge() {
+        let mut engine = Engine::event_sourced();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(5))));
+        let _ = engine.add_transaction(Transaction::new(2, 3, TransactionType::Merge(1)));
+        let _ = engine.add_transaction(Transaction::new(2, 4, TransactionType::Withdrawal(dec!(3))));
+
+        engine.rebuild();
+
+        assert_eq!(engine.get_account(2), None);
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(12));
+    }
+
+    #[test]
+    fn test_dispute_into_deficit() {
+        let mut engine = Engine::new();
+        engine.allow_negative_balances();
+
+        let _ = engine.add_transaction(
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))).with_timestamp(0)
+        );
+        let _ = engine.add_transaction(
+            Transaction::new(1, 2, TransactionType::Withdrawal(dec!(8))).with_timestamp(0)
+        );
+
+        assert_eq!(
+            engine.add_transaction(
+                Transaction::new(1, 1, TransactionType::Dispute).with_timestamp(SECONDS_PER_DAY)
+            ),
+            Ok(())
+        );
+        let _ = engine.add_transaction(
+            Transaction::new(2, 3, TransactionType::Deposit(dec!(1))).with_timestamp(SECONDS_PER_DAY * 4)
+        );
+
+        assert_eq!(engine.deficits(), vec![DeficitAccount {
+            client_id: 1,
+            deficit: dec!(8),
+            since_tx: Some(1),
+            since: Some(SECONDS_PER_DAY),
+            age_days: Some(3),
+        }]);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let mut engine = Engine::new();
+        engine.allow_negative_balances();
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(8))));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Recovery(dec!(9)))),
+            Err(EngineError::InvalidAmount(dec!(9)))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::Recovery(dec!(5)))),
+            Ok(())
+        );
+        assert_eq!(engine.deficits()[0].deficit, dec!(3));
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 5, TransactionType::Recovery(dec!(3)))),
+            Ok(())
+        );
+        assert!(engine.deficits().is_empty());
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 6, TransactionType::Recovery(dec!(1)))),
+            Err(EngineError::NotInDeficit(1))
+        );
+
+        let account = engine.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(10));
+        assert_eq!(account.total, dec!(10));
+    }
+
+    #[test]
+    fn test_amount_validation() {
+        let mut engine = Engine::builder().max_amount_scale(2).build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(-50.0)))),
+            Err(EngineError::InvalidAmount(dec!(-50.0)))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(0)))),
+            Err(EngineError::InvalidAmount(dec!(0)))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(1.005)))),
+            Err(EngineError::TooPrecise { amount: dec!(1.005), max_scale: 2 })
+        );
+
+        // The receiving account of a transfer is validated too
+        engine.seed_account(Account { available: Decimal::MAX, total: Decimal::MAX, ..Account::new(2) });
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 5, TransactionType::Transfer { to: 2, amount: dec!(1) })),
+            Err(EngineError::AmountOverflow(dec!(1)))
+        );
+
+        engine.add_transaction(Transaction::new(1, 6, TransactionType::Withdrawal(dec!(1.50)))).unwrap();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(8.5));
+    }
+
+    #[test]
+    fn test_adjustments() {
+        let adjustment = |tx_id, amount| {
+            Transaction::new(1, tx_id, TransactionType::Adjustment {
+                amount,
+                reason: "OPS-1".to_string(),
+            })
+        };
+
+        assert_eq!(
+            Engine::new().add_transaction(adjustment(1, dec!(5))),
+            Err(EngineError::NotAllowed { tx_id: 1, tx_type: "adjustment" })
+        );
+
+        let recorder = Recorder::default();
+        let mut engine = Engine::builder()
+            .allow_adjustments()
+            .observer(Box::new(recorder.clone()))
+            .build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(adjustment(2, dec!(-4))).unwrap();
+        engine.add_transaction(adjustment(3, dec!(1.5))).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(adjustment(4, dec!(-8))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(7.5), required: dec!(8) })
+        );
+        assert_eq!(engine.add_transaction(adjustment(4, dec!(0))), Err(EngineError::InvalidAmount(dec!(0))));
+        assert_eq!(engine.add_transaction(adjustment(2, dec!(1))), Err(EngineError::DuplicateTxId(2)));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Dispute)),
+            Err(EngineError::UnknownTransaction(3))
+        );
+
+        // Locked accounts can still be corrected
+        engine.add_transaction(Transaction::new(1, 5, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 5, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 5, TransactionType::Chargeback)).unwrap();
+        engine.add_transaction(adjustment(6, dec!(2.5))).unwrap();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+        assert_eq!(engine.system_accounts().balance(SystemAccount::Adjustments), dec!(0));
+        assert_eq!(
+            recorder.events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|event| matches!(event, LifecycleEvent::Adjusted { .. }))
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_transfer() {
+        let transfer = |tx_id, to, amount| {
+            Transaction::new(1, tx_id, TransactionType::Transfer { to, amount })
+        };
+        let mut engine = Engine::new();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(transfer(2, 2, dec!(4))).unwrap();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(6));
+        assert_eq!(engine.get_account(2).unwrap().available, dec!(4));
+
+        assert_eq!(
+            engine.add_transaction(transfer(3, 2, dec!(7))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(6), required: dec!(7) })
+        );
+        assert_eq!(engine.add_transaction(transfer(3, 1, dec!(1))), Err(EngineError::UnknownClient(1)));
+        assert_eq!(engine.add_transaction(transfer(3, 2, dec!(-1))), Err(EngineError::InvalidAmount(dec!(-1))));
+        assert_eq!(engine.add_transaction(transfer(2, 2, dec!(1))), Err(EngineError::DuplicateTxId(2)));
+
+        // Only the receiver disputes it, a chargeback gives the funds back to the sender
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)),
+            Err(EngineError::ClientMismatch { tx_id: 2, client_id: 1 })
+        );
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Dispute)).unwrap();
+        assert_eq!(engine.get_account(2).unwrap().held, dec!(4));
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Chargeback)).unwrap();
+
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+        assert_eq!(engine.get_account(2).unwrap(), &Account { locked: true, ..Account::new(2) });
+        assert_eq!(engine.system_accounts().balance(SystemAccount::ChargebackLosses), dec!(0));
+
+        assert_eq!(engine.add_transaction(transfer(3, 2, dec!(1))), Err(EngineError::AccountLocked(2)));
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+    }
+
+    #[test]
+    fn test_unlock() {
+        let unlock = |tx_id| Transaction::new(1, tx_id, TransactionType::Unlock);
+        let mut engine = Engine::new();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Chargeback)).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(unlock(3)),
+            Err(EngineError::NotAllowed { tx_id: 3, tx_type: "unlock" })
+        );
+        assert!(engine.get_account(1).unwrap().locked);
+
+        let recorder = Recorder::default();
+        engine.allow_unlocks();
+        engine.add_observer(Box::new(recorder.clone()));
+
+        engine.add_transaction(unlock(3)).unwrap();
+        assert!(!engine.get_account(1).unwrap().locked);
+        assert_eq!(*recorder.events.lock().unwrap(), vec![LifecycleEvent::Unlocked { client_id: 1, tx_id: 3 }]);
+
+        assert_eq!(engine.add_transaction(unlock(4)), Err(EngineError::NotLocked(1)));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 5, TransactionType::Unlock)),
+            Err(EngineError::UnknownClient(2))
+        );
+        engine.add_transaction(Transaction::new(1, 6, TransactionType::Withdrawal(dec!(10)))).unwrap();
+    }
+
+    #[test]
+    fn test_delete() {
+        let delete = |client_id, tx_id| Transaction::new(client_id, tx_id, TransactionType::Delete);
+        let restore = |client_id, tx_id| Transaction::new(client_id, tx_id, TransactionType::Restore);
+        let mut engine = Engine::new();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(5)))).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(delete(1, 3)),
+            Err(EngineError::NotAllowed { tx_id: 3, tx_type: "delete" })
+        );
+
+        let recorder = Recorder::default();
+        engine.allow_deletes();
+        engine.add_observer(Box::new(recorder.clone()));
+
+        engine.add_transaction(delete(1, 3)).unwrap();
+        assert_eq!(engine.get_account(1), None);
+        assert_eq!(engine.client_ids().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(engine.deleted_accounts().collect::<Vec<_>>(), vec![&Account {
+            available: dec!(10),
+            total: dec!(10),
+            ..Account::new(1)
+        }]);
+        assert!(recorder.balances.lock().unwrap().is_empty());
+
+        // Nothing but a restore applies to it, not even transfers from other clients
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::Deposit(dec!(1)))),
+            Err(EngineError::AccountDeleted(1))
+        );
+        assert_eq!(
+            engine.add_transaction(
+                Transaction::new(2, 4, TransactionType::Transfer { to: 1, amount: dec!(1) })
+            ),
+            Err(EngineError::AccountDeleted(1))
+        );
+        assert_eq!(engine.add_transaction(delete(1, 4)), Err(EngineError::AccountDeleted(1)));
+        assert_eq!(engine.add_transaction(restore(2, 4)), Err(EngineError::NotDeleted(2)));
+        assert_eq!(engine.add_transaction(delete(3, 4)), Err(EngineError::UnknownClient(3)));
+
+        engine.add_transaction(restore(1, 5)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+        assert_eq!(*recorder.events.lock().unwrap(), vec![
+            LifecycleEvent::Deleted { client_id: 1, tx_id: 3 },
+            LifecycleEvent::Restored { client_id: 1, tx_id: 5 },
+        ]);
+        assert_eq!(*recorder.balances.lock().unwrap(), vec![(1, dec!(10))]);
+
+        // The history survives, the deposit can still be disputed
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(10));
+    }
+
+    #[test]
+    fn test_withdrawal_holds() {
+        let mut engine = Engine::builder().hold_expiry(Duration::from_secs(100)).build();
+        let at = |tx: Transaction, timestamp| tx.with_timestamp(timestamp);
+
+        engine.add_transaction(at(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))), 0)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::WithdrawHold(dec!(4))), 10)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 3, TransactionType::WithdrawHold(dec!(5))), 20)).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(at(Transaction::new(1, 4, TransactionType::WithdrawHold(dec!(2))), 30)),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(1), required: dec!(2) })
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 2, TransactionType::WithdrawCommit)),
+            Err(EngineError::ClientMismatch { tx_id: 2, client_id: 2 })
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)),
+            Err(EngineError::UnknownTransaction(2))
+        );
+
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::WithdrawCommit), 50)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap(), &Account {
+            client_id: 1,
+            available: dec!(1),
+            held: dec!(5),
+            total: dec!(6),
+            locked: false,
+        });
+
+        // The second hold expires at 120, its commit comes too late
+        assert_eq!(
+            engine.add_transaction(at(Transaction::new(1, 3, TransactionType::WithdrawCommit), 120)),
+            Err(EngineError::UnknownTransaction(3))
+        );
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(6));
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(0));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::WithdrawCommit)),
+            Err(EngineError::UnknownTransaction(2))
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_void() {
+        let mut engine = Engine::builder().hold_expiry(Duration::from_secs(100)).build();
+        let at = |tx: Transaction, timestamp| tx.with_timestamp(timestamp);
+
+        engine.add_transaction(at(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))), 0)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::WithdrawHold(dec!(4))), 10)).unwrap();
+
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 2, TransactionType::WithdrawVoid)),
+            Err(EngineError::ClientMismatch { tx_id: 2, client_id: 2 })
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::WithdrawVoid)),
+            Err(EngineError::UnknownTransaction(1))
+        );
+
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::WithdrawVoid), 20)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(0));
+
+        // A voided hold can't be captured, voided again or expire
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::WithdrawCommit)),
+            Err(EngineError::UnknownTransaction(2))
+        );
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::WithdrawVoid)),
+            Err(EngineError::UnknownTransaction(2))
+        );
+
+        engine.add_transaction(at(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(1))), 200)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(9));
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(0));
+    }
+
+    #[test]
+    fn test_deposit_hold() {
+        let mut engine = Engine::builder().deposit_hold(Duration::from_secs(100)).build();
+        let at = |tx: Transaction, timestamp| tx.with_timestamp(timestamp);
+
+        engine.add_transaction(at(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))), 0)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))), 10)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(15));
+        assert_eq!(
+            engine.add_transaction(at(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(1))), 20)),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(0), required: dec!(1) })
+        );
+
+        // The dispute takes over the hold of the second deposit, its release doesn't apply anymore
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::Dispute), 30)).unwrap();
+
+        engine.add_transaction(at(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(4))), 110)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap(), &Account {
+            client_id: 1,
+            available: dec!(6),
+            held: dec!(5),
+            total: dec!(11),
+            locked: false,
+        });
+        assert_eq!(engine.lifecycle_events, vec![]);
+
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::Resolve), 120)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(11));
+        assert_eq!(engine.scheduled_releases().count(), 0);
+    }
+
+    #[test]
+    fn test_dispute_expiry() {
+        let mut engine = Engine::builder().dispute_expiry(Duration::from_secs(100)).build();
+        let at = |tx: Transaction, timestamp| tx.with_timestamp(timestamp);
+
+        engine.add_transaction(at(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))), 0)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))), 0)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 1, TransactionType::Dispute), 10)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::Dispute), 20)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::Chargeback), 30)).unwrap();
+
+        assert_eq!(engine.scheduled_releases().map(|release| release.tx_id).collect::<Vec<_>>(), vec![1]);
+
+        // The first dispute expires at 110 even if the account is locked, the chargeback of the
+        // second one stands
+        engine.add_transaction(at(Transaction::new(2, 3, TransactionType::Deposit(dec!(1))), 110)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap(), &Account {
+            client_id: 1,
+            available: dec!(10),
+            held: dec!(0),
+            total: dec!(10),
+            locked: true,
+        });
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback)),
+            Err(EngineError::UnknownTransaction(1))
+        );
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let mut engine = Engine::builder().dispute_window(Duration::from_secs(100)).build();
+        let at = |tx: Transaction, timestamp| tx.with_timestamp(timestamp);
+
+        engine.add_transaction(at(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))), 0)).unwrap();
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))), 50)).unwrap();
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(1)))).unwrap();
+
+        // A rejected duplicate doesn't move the time of the transaction
+        assert!(engine.add_transaction(at(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))), 90)).is_err());
+
+        assert_eq!(
+            engine.add_transaction(at(Transaction::new(1, 1, TransactionType::Dispute), 101)),
+            Err(EngineError::DisputeWindowClosed(1))
+        );
+        engine.add_transaction(at(Transaction::new(1, 2, TransactionType::Dispute), 150)).unwrap();
+
+        // Without a time of their own, disputes are filed at the engine clock, 150
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)),
+            Err(EngineError::DisputeWindowClosed(1))
+        );
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Dispute)).unwrap();
+
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(6));
+    }
+
+    #[test]
+    fn test_redisputes() {
+        let mut engine = Engine::builder().allow_redisputes(1).build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Resolve)).unwrap();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+        assert_eq!(engine.tx_status(1), Some(TxStatus::UnderDispute));
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(10));
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Resolve)).unwrap();
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)),
+            Err(EngineError::RedisputeLimit(1))
+        );
+
+        // Without the policy a resolved transaction is done with
+        let mut engine = Engine::new();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Resolve)).unwrap();
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)),
+            Err(EngineError::UnknownTransaction(1))
+        );
+    }
+
+    #[test]
+    fn test_finalize_runs_due_releases() {
+        let clock = ManualClock::at(0);
+        let mut engine = Engine::builder()
+            .dispute_expiry(Duration::from_secs(100))
+            .clock(Box::new(clock.clone()))
+            .build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+
+        clock.advance(100);
+        engine.finalize();
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
 
-        account.total = account.available + account.held;
-    }
+        // Draining the accounts finalizes them too
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)).unwrap();
 
-    pub fn get_accounts(self) -> Vec<Account> {
-        self.accounts.into_values().collect()
+        clock.advance(100);
+        assert_eq!(engine.get_accounts()[0].held, dec!(0));
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rust_decimal_macros::dec;
-    use crate::types::TransactionType;
 
     #[test]
-    fn test_example() {
-        let mut engine = Engine::new();
+    fn test_lock_risky_accounts() {
+        let mut engine = Engine::builder()
+            .event_sourcing()
+            .risk_scoring(RiskScoring::new(Duration::from_secs(60), [RiskThreshold::Cycles(1)]))
+            .lock_risky_accounts()
+            .build();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(1.0)),
-        });
+        for (tx_id, timestamp) in [(1, 0), (3, 10)] {
+            let deposit = Transaction::new(1, tx_id, TransactionType::Deposit(dec!(10))).with_timestamp(timestamp);
+            let withdrawal = Transaction::new(1, tx_id + 1, TransactionType::Withdrawal(dec!(10)))
+                .with_timestamp(timestamp + 5);
 
-        engine.add_transaction(Transaction {
-            client_id: 2,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(2.0)),
-        });
+            engine.add_transaction(deposit).unwrap();
+            engine.add_transaction(withdrawal).unwrap();
+        }
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 3,
-            tx_type: TransactionType::Deposit(dec!(2.0)),
-        });
+        assert!(engine.get_account(1).unwrap().locked);
+        assert_eq!(engine.risk_scoring().unwrap().signals(1).unwrap().cycles, 2);
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 5, TransactionType::Deposit(dec!(1)))),
+            Err(EngineError::AccountLocked(1))
+        );
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 4,
-            tx_type: TransactionType::Withdrawal(dec!(1.5)),
-        });
+        // The replay locks the account again
+        engine.rebuild();
+        assert!(engine.get_account(1).unwrap().locked);
+        assert_eq!(engine.risk_scoring().unwrap().signals(1).unwrap().cycles, 2);
+    }
 
-        engine.add_transaction(Transaction {
-            client_id: 2,
-            tx_id: 5,
-            tx_type: TransactionType::Withdrawal(dec!(3.0)),
-        });
+    #[test]
+    fn test_seeded_releases() {
+        let release = |kind, tx_id, amount| ScheduledRelease { at: 100, kind, tx_id, client_id: 1, amount };
+        let mut engine = Engine::builder()
+            .event_sourcing()
+            .opening_balances([Account { held: dec!(6), total: dec!(6), ..Account::new(1) }])
+            .scheduled_releases([
+                release(ReleaseKind::DepositHold, 1, dec!(1)),
+                release(ReleaseKind::DisputeExpiry, 2, dec!(2)),
+                release(ReleaseKind::WithdrawalHold, 3, dec!(3))
+            ])
+            .build();
 
-        let mut accounts = engine.get_accounts();
-        accounts.sort_by(|a, b| {
-            match a.client_id < b.client_id {
-                true => std::cmp::Ordering::Less,
-                false => std::cmp::Ordering::Greater,
-            }
-        });
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Resolve).with_timestamp(50)).unwrap();
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::WithdrawCommit)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(2));
 
-        assert_eq!(accounts.len(), 2);
+        engine.rebuild();
+        assert_eq!(engine.scheduled_releases().map(|release| release.tx_id).collect::<Vec<_>>(), vec![1]);
 
-        assert_eq!(accounts[0], Account {
+        engine.add_transaction(Transaction::new(1, 4, TransactionType::Deposit(dec!(1))).with_timestamp(100)).unwrap();
+        assert_eq!(engine.get_account(1).unwrap(), &Account {
             client_id: 1,
-            available: dec!(1.5),
-            held: dec!(0),
-            total: dec!(1.5),
-            locked: false,
-        });
-
-        assert_eq!(accounts[1], Account {
-            client_id: 2,
-            available: dec!(2.0),
+            available: dec!(4),
             held: dec!(0),
-            total: dec!(2.0),
+            total: dec!(4),
             locked: false,
         });
     }
 
     #[test]
-    fn test_deposit() {
+    fn test_seeded_deficit() {
         let mut engine = Engine::new();
 
-        engine.add_transaction(Transaction {
+        engine.seed_account(Account {
             client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
+            available: dec!(-4),
+            held: dec!(0),
+            total: dec!(-4),
+            locked: false,
         });
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(10));
+        assert_eq!(engine.deficits(), vec![DeficitAccount {
+            client_id: 1,
+            deficit: dec!(4),
+            since_tx: None,
+            since: None,
+            age_days: None,
+        }]);
     }
 
     #[test]
-    fn test_withdrawal() {
+    fn test_detect_dormant_with_clock() {
+        let clock = ManualClock::at(0);
         let mut engine = Engine::new();
+        engine.set_clock(Box::new(clock.clone()));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+        let _ = engine.add_transaction(
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))).with_timestamp(0)
+        );
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Withdrawal(dec!(5)),
-        });
+        assert!(engine.detect_dormant(10).is_empty());
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(5));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(5));
+        clock.advance(SECONDS_PER_DAY * 10);
+        assert_eq!(engine.detect_dormant(10), vec![DormantAccount {
+            client_id: 1,
+            last_activity: 0,
+            idle_days: 10,
+        }]);
     }
 
     #[test]
-    fn test_withdrawal_not_enough_funds() {
+    fn test_deficit_age_with_clock() {
+        let clock = ManualClock::at(SECONDS_PER_DAY);
         let mut engine = Engine::new();
+        engine.set_clock(Box::new(clock.clone()));
+        engine.allow_negative_balances();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
-
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Withdrawal(dec!(15)),
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(10))));
+        let _ = engine.add_transaction(
+            Transaction::new(1, 1, TransactionType::Dispute).with_timestamp(SECONDS_PER_DAY)
+        );
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(10));
+        clock.advance(SECONDS_PER_DAY * 2);
+        assert_eq!(engine.deficits()[0].age_days, Some(2));
     }
 
     #[test]
-    fn test_dispute() {
+    fn test_open_disputes() {
         let mut engine = Engine::new();
+        let deposit = |client_id, tx_id| Transaction::new(client_id, tx_id, TransactionType::Deposit(dec!(5)));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+        // Disputed before the engine had a time
+        engine.add_transaction(deposit(1, 1)).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(deposit(2, 2).with_timestamp(SECONDS_PER_DAY)).unwrap();
+        engine.add_transaction(deposit(2, 3)).unwrap();
+        engine.add_transaction(deposit(2, 4)).unwrap();
+        engine.add_transaction(Transaction::new(2, 3, TransactionType::Dispute).with_timestamp(SECONDS_PER_DAY * 3)).unwrap();
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Dispute).with_timestamp(SECONDS_PER_DAY * 2)).unwrap();
+        engine.add_transaction(Transaction::new(2, 4, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(2, 4, TransactionType::Resolve)).unwrap();
+        engine.add_transaction(deposit(1, 5).with_timestamp(SECONDS_PER_DAY * 5)).unwrap();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        let open = |tx, client, disputed_at: Option<u64>, age_days| OpenDispute {
+            tx,
+            client,
+            amount: dec!(5),
+            disputed_at: disputed_at.map(|days| days * SECONDS_PER_DAY),
+            age_days,
+        };
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Dispute,
-        });
+        // The clock doesn't go back for a late dispute
+        assert_eq!(engine.open_disputes(), vec![
+            open(2, 2, Some(3), Some(2)),
+            open(3, 2, Some(3), Some(2)),
+            open(1, 1, None, None)
+        ]);
+    }
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(5));
-        assert_eq!(account.total, dec!(15));
+    fn quarantining_engine() -> Engine {
+        let mut engine = Engine::event_sourced();
+        engine.add_policy(Box::new(QuarantineAbove(dec!(100))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(100))));
+
+        engine
     }
 
     #[test]
-    fn test_dispute_not_enough_funds() {
-        let mut engine = Engine::new();
+    fn test_quarantine_withdrawal_holds_funds() {
+        let mut engine = quarantining_engine();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(50))));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(120)))),
+            Ok(())
+        );
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Withdrawal(dec!(5)),
-        });
+        let account = engine.get_account(1).unwrap();
+        assert_eq!(account.available, dec!(30));
+        assert_eq!(account.held, dec!(120));
+        assert_eq!(account.total, dec!(150));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Dispute,
-        });
+        let quarantined: Vec<u32> = engine
+            .quarantined()
+            .map(|tx| tx.tx_id)
+            .collect();
+        assert_eq!(quarantined, vec![3]);
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(5));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(5));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(200)))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(30), required: dec!(200) })
+        );
     }
 
     #[test]
-    fn test_dispute_unknown() {
-        let mut engine = Engine::new();
+    fn test_approve_and_decline() {
+        let mut engine = quarantining_engine();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(500))));
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(100));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        assert_eq!(engine.add_transaction(Transaction::new(1, 2, TransactionType::Approve)), Ok(()));
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(600));
+
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(300))));
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(101))));
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(199));
 
-        engine.add_transaction(Transaction {
+        assert_eq!(engine.add_transaction(Transaction::new(1, 3, TransactionType::Approve)), Ok(()));
+        assert_eq!(engine.add_transaction(Transaction::new(1, 4, TransactionType::Decline)), Ok(()));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::Approve)),
+            Err(EngineError::UnknownTransaction(4))
+        );
+
+        let expected = Account {
             client_id: 1,
-            tx_id: 3,
-            tx_type: TransactionType::Dispute,
-        });
+            available: dec!(300),
+            held: dec!(0),
+            total: dec!(300),
+            locked: false,
+        };
+        assert_eq!(engine.get_account(1), Some(&expected));
+        assert_eq!(engine.quarantined().count(), 0);
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(15));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(15));
+        engine.rebuild();
+        assert_eq!(engine.get_account(1), Some(&expected));
     }
 
     #[test]
-    fn test_resolve() {
-        let mut engine = Engine::new();
+    fn test_release_client_mismatch() {
+        let mut engine = quarantining_engine();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(500))));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 2, TransactionType::Approve)),
+            Err(EngineError::ClientMismatch { tx_id: 2, client_id: 2 })
+        );
+        assert_eq!(engine.quarantined().count(), 1);
+    }
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Dispute,
-        });
+    #[test]
+    fn test_release_approved_deposit_into_locked_account() {
+        let mut engine = quarantining_engine();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Resolve,
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(500))));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback));
+        assert!(engine.get_account(1).unwrap().locked);
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(15));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(15));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Approve)),
+            Err(EngineError::AccountLocked(1))
+        );
+        assert_eq!(engine.get_account(1).unwrap().total, dec!(0));
+        assert_eq!(engine.quarantined().count(), 1);
+        assert_eq!(engine.system_accounts().balance(SystemAccount::Escrow), dec!(500));
+
+        // Declining it still releases the escrow
+        assert_eq!(engine.add_transaction(Transaction::new(1, 2, TransactionType::Decline)), Ok(()));
+        assert_eq!(engine.quarantined().count(), 0);
+        assert_eq!(engine.system_accounts().balance(SystemAccount::Escrow), dec!(0));
     }
 
     #[test]
-    fn test_resolve_not_under_dispute() {
-        let mut engine = Engine::new();
-
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+    fn test_release_execute_failure_keeps_escrow() {
+        let bounds = BalanceBounds {
+            classes: HashMap::from([("capped".to_string(), Bounds { floor: None, ceiling: Some(dec!(300)) })]),
+            accounts: HashMap::new(),
+            default_class: Some("capped".to_string()),
+        };
+        let mut engine = Engine::builder()
+            .balance_bounds(bounds)
+            .policy(Box::new(QuarantineAbove(dec!(100))))
+            .build();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(100)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(250)))).unwrap();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Dispute,
-        });
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Approve)),
+            Err(EngineError::AboveCeiling { client_id: 1, total: dec!(350), ceiling: dec!(300) })
+        );
+        assert_eq!(engine.get_account(1).unwrap().total, dec!(100));
+        assert_eq!(engine.quarantined().map(|tx| tx.tx_id).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(engine.system_accounts().balance(SystemAccount::Escrow), dec!(250));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Resolve,
-        });
+        engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(60)))).unwrap();
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(5));
-        assert_eq!(account.total, dec!(15));
+        assert_eq!(engine.add_transaction(Transaction::new(1, 2, TransactionType::Approve)), Ok(()));
+        assert_eq!(engine.get_account(1).unwrap().total, dec!(290));
+        assert_eq!(engine.system_accounts().balance(SystemAccount::Escrow), dec!(0));
     }
 
     #[test]
-    fn test_resolve_unknown() {
-        let mut engine = Engine::new();
-
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+    fn test_system_accounts() {
+        let mut engine = quarantining_engine();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(500))));
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(700))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Approve));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Dispute,
-        });
+        let system = engine.system_accounts();
+        assert_eq!(system.balance(SystemAccount::ChargebackLosses), dec!(100));
+        assert_eq!(system.balance(SystemAccount::Escrow), dec!(700));
+        assert_eq!(system.balance(SystemAccount::Fees), dec!(0));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 3,
-            tx_type: TransactionType::Resolve,
-        });
+        let deposited = dec!(1300);
+        let client_total = engine.get_account(1).unwrap().total;
+        assert_eq!(
+            client_total +
+                system.balance(SystemAccount::ChargebackLosses) +
+                system.balance(SystemAccount::Escrow),
+            deposited
+        );
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(5));
-        assert_eq!(account.total, dec!(15));
+        let before = system.clone();
+        engine.rebuild();
+        assert_eq!(engine.system_accounts(), &before);
     }
 
     #[test]
-    fn test_chargeback() {
-        let mut engine = Engine::new();
-
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+    fn test_risk_tier_limits_after_chargeback() {
+        let mut engine = Engine::event_sourced();
+        engine.add_policy(Box::new(TierPolicy::new([(RiskTier::High, dec!(5))])));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(20))));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(6)))),
+            Ok(())
+        );
+        assert_eq!(engine.risk_tier(1), RiskTier::Standard);
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Dispute,
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback));
+        assert_eq!(engine.risk_tier(1), RiskTier::High);
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Chargeback,
-        });
+        // The chargeback also locks the account, which takes precedence over the tier limit
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(6)))),
+            Err(EngineError::AccountLocked(1))
+        );
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(10));
-        assert_eq!(account.locked, true);
+        engine.rebuild();
+        assert_eq!(engine.risk_tier(1), RiskTier::High);
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(14));
     }
 
     #[test]
-    fn test_chargeback_not_under_dispute() {
-        let mut engine = Engine::new();
+    fn test_account_limits_after_rebuild() {
+        let mut engine = Engine::event_sourced();
+        engine.add_policy(Box::new(AccountLimits::new(["count=2/day".parse().unwrap()])));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(1)))),
+            Err(EngineError::Policy(ReasonCode::TxCountExceeded))
+        );
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        // Disputes aren't limited
+        assert_eq!(engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)), Ok(()));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Dispute,
-        });
+        engine.rebuild();
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(5));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(1)))),
+            Err(EngineError::Policy(ReasonCode::TxCountExceeded))
+        );
+    }
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Chargeback,
-        });
+    #[test]
+    fn test_merge_keeps_highest_tier() {
+        let mut engine = Engine::new();
+        engine.add_policy(Box::new(TierPolicy::default()));
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(5));
-        assert_eq!(account.total, dec!(15));
-        assert_eq!(account.locked, false);
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(10))));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(2, 2, TransactionType::Chargeback));
+        let _ = engine.add_transaction(Transaction::new(2, 3, TransactionType::Merge(1)));
+
+        assert_eq!(engine.risk_tier(1), RiskTier::High);
+        assert_eq!(engine.risk_tier(2), RiskTier::Standard);
     }
 
     #[test]
-    fn test_chargeback_unknown() {
+    fn test_prune_history_by_age_keeps_disputes() {
+        let clock = ManualClock::at(0);
         let mut engine = Engine::new();
+        engine.set_clock(Box::new(clock.clone()));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
-
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        for tx_id in 1..=3 {
+            let _ = engine.add_transaction(
+                Transaction::new(1, tx_id, TransactionType::Deposit(dec!(10))).with_timestamp(
+                    (tx_id as u64) * SECONDS_PER_DAY
+                )
+            );
+        }
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Dispute,
-        });
+        clock.advance(SECONDS_PER_DAY * 5);
+        let retention = Retention { max_age_days: Some(2), ..Default::default() };
+        assert_eq!(engine.prune_history(&retention), 1);
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 3,
-            tx_type: TransactionType::Chargeback,
-        });
+        assert!(engine.store.has_tx(1));
+        assert!(!engine.store.has_tx(2));
+        assert!(engine.store.has_tx(3));
 
-        let account = engine.accounts.get(&1).unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(5));
-        assert_eq!(account.total, dec!(15));
-        assert_eq!(account.locked, false);
+        assert_eq!(
+            engine.add_transaction(Transaction::new(1, 2, TransactionType::Dispute)),
+            Err(EngineError::UnknownTransaction(2))
+        );
+        assert_eq!(engine.add_transaction(Transaction::new(1, 1, TransactionType::Resolve)), Ok(()));
     }
 
     #[test]
-    fn test_get_accounts() {
+    fn test_prune_history_by_size() {
         let mut engine = Engine::new();
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 1,
-            tx_type: TransactionType::Deposit(dec!(10)),
-        });
+        for tx_id in 1..=5 {
+            let _ = engine.add_transaction(Transaction::new(1, tx_id, TransactionType::Deposit(dec!(1))));
+        }
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 5, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(1, 5, TransactionType::Resolve));
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Deposit(dec!(5)),
-        });
+        let retention = Retention { max_entries: Some(2), ..Default::default() };
+        assert_eq!(engine.prune_history(&retention), 3);
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Dispute,
-        });
+        let kept: Vec<u32> = (1..=5).filter(|tx_id| engine.store.has_tx(*tx_id)).collect();
+        assert_eq!(kept, vec![1, 5]);
+        assert_eq!(engine.store.order().count(), 2);
+    }
 
-        engine.add_transaction(Transaction {
-            client_id: 1,
-            tx_id: 2,
-            tx_type: TransactionType::Chargeback,
-        });
+    #[test]
+    fn test_prune_history_per_client() {
+        let mut engine = Engine::new();
 
-        let account = engine.get_accounts().pop().unwrap();
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total, dec!(10));
+        for tx_id in 1..=4 {
+            let _ = engine.add_transaction(Transaction::new(1, tx_id, TransactionType::Deposit(dec!(1))));
+        }
+        let _ = engine.add_transaction(Transaction::new(2, 5, TransactionType::Deposit(dec!(1))));
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute));
+
+        let retention = Retention { max_per_client: Some(2), ..Default::default() };
+        assert_eq!(engine.prune_history(&retention), 2);
+
+        let kept: Vec<u32> = (1..=5).filter(|tx_id| engine.store.has_tx(*tx_id)).collect();
+        assert_eq!(kept, vec![1, 4, 5]);
     }
 }
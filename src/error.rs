@@ -0,0 +1,226 @@
+use std::error::Error;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{ invariants::InvariantViolation, ordering::OutOfOrder, reason::ReasonCode, types::ClientId };
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum EngineError {
+    #[error("client {client_id} has {available} available but {required} is required")]
+    InsufficientFunds {
+        client_id: ClientId,
+        available: Decimal,
+        required: Decimal,
+    },
+    #[error("transaction {0} is unknown")]
+    UnknownTransaction(u32),
+    #[error("transaction {tx_id} doesn't belong to client {client_id}")]
+    ClientMismatch {
+        tx_id: u32,
+        client_id: ClientId,
+    },
+    #[error("transaction {0} already exists")]
+    DuplicateTxId(u32),
+    #[error("transaction {0} is already under dispute")]
+    AlreadyDisputed(u32),
+    #[error("transaction {0} is not under dispute")]
+    NotUnderDispute(u32),
+    /// Representment of a transaction that isn't charged back
+    #[error("transaction {0} is not charged back")]
+    NotChargedBack(u32),
+    #[error("client {0} is locked")]
+    AccountLocked(ClientId),
+    #[error("amount {0} is invalid")]
+    InvalidAmount(Decimal),
+    /// The amount has more decimal places than the engine accepts
+    #[error("amount {amount} has more than {max_scale} decimal places")]
+    TooPrecise {
+        amount: Decimal,
+        max_scale: u32,
+    },
+    /// Applying the amount would overflow a balance
+    #[error("amount {0} would overflow the balance")]
+    AmountOverflow(Decimal),
+    #[error("client {0} is unknown")]
+    UnknownClient(ClientId),
+    #[error("client {0} is not in deficit")]
+    NotInDeficit(ClientId),
+    #[error("client {0} is not locked")]
+    NotLocked(ClientId),
+    #[error("client {0} is deleted")]
+    AccountDeleted(ClientId),
+    #[error("client {0} is not deleted")]
+    NotDeleted(ClientId),
+    /// The upstream sequence number repeats or goes back
+    #[error("client {client_id} received sequence {received} after {last}")]
+    OutOfSequence {
+        client_id: ClientId,
+        last: u64,
+        received: u64,
+    },
+    /// The timestamp is older than the last one of the client
+    #[error("client {client_id} received timestamp {received} after {last}")]
+    LateTimestamp {
+        client_id: ClientId,
+        last: u64,
+        received: u64,
+    },
+    /// The transaction type isn't enabled in this engine
+    #[error("transaction {tx_id} is a {tx_type}, which is not allowed")]
+    NotAllowed {
+        tx_id: u32,
+        tx_type: &'static str,
+    },
+    /// The deposit would take the total balance over the ceiling of the account class
+    #[error("client {client_id} would hold {total} over its ceiling of {ceiling}")]
+    AboveCeiling {
+        client_id: ClientId,
+        total: Decimal,
+        ceiling: Decimal,
+    },
+    /// The debit would take the available balance under the floor of the account class
+    #[error("client {client_id} would have {available} available under its floor of {floor}")]
+    BelowFloor {
+        client_id: ClientId,
+        available: Decimal,
+        floor: Decimal,
+    },
+    /// Rejected by a policy
+    #[error("rejected by a policy with {0}")]
+    Policy(ReasonCode),
+    /// The worker owning the client panicked on an earlier transaction, it doesn't apply any more
+    #[error("worker {0} is degraded after a panic")]
+    ShardDegraded(usize),
+    /// There's no rate to convert between the currencies
+    #[error("there's no rate from {from} to {to}")]
+    NoRate {
+        from: String,
+        to: String,
+    },
+    /// The dispute came after the dispute window of the transaction
+    #[error("transaction {0} can't be disputed anymore")]
+    DisputeWindowClosed(u32),
+    /// The resolved transaction was already disputed again as many times as allowed
+    #[error("transaction {0} was disputed again too many times")]
+    RedisputeLimit(u32),
+    /// The transaction was applied after a later one of its client
+    #[error("{0}")]
+    OutOfOrder(OutOfOrder),
+    /// The state store failed, on this transaction or an earlier one
+    #[error("the state store failed: {0}")]
+    Storage(String),
+}
+
+impl EngineError {
+    pub fn reason(&self) -> ReasonCode {
+        match self {
+            EngineError::InsufficientFunds { .. } => ReasonCode::InsufficientFunds,
+            EngineError::UnknownTransaction(_) => ReasonCode::UnknownTx,
+            EngineError::ClientMismatch { .. } => ReasonCode::ClientMismatch,
+            EngineError::DuplicateTxId(_) => ReasonCode::DuplicateTx,
+            EngineError::AlreadyDisputed(_) => ReasonCode::AlreadyDisputed,
+            EngineError::NotUnderDispute(_) => ReasonCode::NotUnderDispute,
+            EngineError::NotChargedBack(_) => ReasonCode::NotChargedBack,
+            EngineError::AccountLocked(_) => ReasonCode::AccountLocked,
+            EngineError::InvalidAmount(_) |
+            EngineError::TooPrecise { .. } |
+            EngineError::AmountOverflow(_) => ReasonCode::InvalidAmount,
+            EngineError::UnknownClient(_) => ReasonCode::UnknownClient,
+            EngineError::NotInDeficit(_) => ReasonCode::NotInDeficit,
+            EngineError::NotLocked(_) => ReasonCode::NotLocked,
+            EngineError::AccountDeleted(_) => ReasonCode::AccountDeleted,
+            EngineError::NotDeleted(_) => ReasonCode::NotDeleted,
+            EngineError::OutOfSequence { .. } => ReasonCode::OutOfSequence,
+            EngineError::LateTimestamp { .. } => ReasonCode::LateTimestamp,
+            EngineError::NotAllowed { .. } => ReasonCode::NotAllowed,
+            EngineError::AboveCeiling { .. } => ReasonCode::AboveCeiling,
+            EngineError::BelowFloor { .. } => ReasonCode::BelowFloor,
+            EngineError::Policy(reason) => *reason,
+            EngineError::ShardDegraded(_) => ReasonCode::ShardDegraded,
+            EngineError::NoRate { .. } => ReasonCode::NoRate,
+            EngineError::DisputeWindowClosed(_) => ReasonCode::DisputeWindowClosed,
+            EngineError::RedisputeLimit(_) => ReasonCode::RedisputeLimit,
+            EngineError::OutOfOrder(_) => ReasonCode::OutOfOrder,
+            EngineError::Storage(_) => ReasonCode::StorageFailed,
+        }
+    }
+}
+
+/// Why a run failed, by the stage that failed. Every kind has its own exit code and metrics label.
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    /// The input files, opening balances or flags couldn't be read
+    #[error("{0}: {1}")]
+    Input(String, #[source] Box<dyn Error + Send + Sync>),
+    /// A transaction was rejected, runs count rejections rather than failing on them
+    #[error("{0}")]
+    Engine(#[from] EngineError),
+    /// State kept between runs (approval queue, snapshot) couldn't be saved
+    #[error("{0}: {1}")]
+    Storage(String, #[source] Box<dyn Error + Send + Sync>),
+    /// The accounts or a report couldn't be written
+    #[error("{0}: {1}")]
+    Output(String, #[source] Box<dyn Error + Send + Sync>),
+    /// The state of the engine broke an invariant, with `--check-invariants`
+    #[error("{0}: {1}")]
+    Invariant(String, #[source] Box<InvariantViolation>),
+}
+
+impl PipelineError {
+    pub fn input(context: impl Into<String>, err: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        PipelineError::Input(context.into(), err.into())
+    }
+
+    pub fn storage(context: impl Into<String>, err: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        PipelineError::Storage(context.into(), err.into())
+    }
+
+    pub fn output(context: impl Into<String>, err: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        PipelineError::Output(context.into(), err.into())
+    }
+
+    /// The label of the `pipeline_errors_total` metric.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PipelineError::Input(..) => "input",
+            PipelineError::Engine(_) => "engine",
+            PipelineError::Storage(..) => "storage",
+            PipelineError::Output(..) => "output",
+            PipelineError::Invariant(..) => "invariant",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PipelineError::Input(..) => 2,
+            PipelineError::Engine(_) | PipelineError::Invariant(..) => 3,
+            PipelineError::Storage(..) => 4,
+            PipelineError::Output(..) => 5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn test_pipeline_error() {
+        let err = PipelineError::storage("Failed to write the snapshot", io::Error::other("disk full"));
+
+        assert_eq!(err.to_string(), "Failed to write the snapshot: disk full");
+        assert_eq!((err.kind(), err.exit_code()), ("storage", 4));
+        assert_eq!(err.source().unwrap().to_string(), "disk full");
+
+        let err = PipelineError::from(EngineError::UnknownClient(7));
+
+        assert_eq!((err.kind(), err.exit_code()), ("engine", 3));
+        assert_eq!(err.to_string(), EngineError::UnknownClient(7).to_string());
+
+        assert_eq!(PipelineError::input("", io::Error::other("")).exit_code(), 2);
+        assert_eq!(PipelineError::output("", io::Error::other("")).exit_code(), 5);
+    }
+}
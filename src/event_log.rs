@@ -0,0 +1,161 @@
+use std::any::Any;
+
+use crate::types::{ Account, Transaction };
+
+pub trait Projection: Any + Send {
+    fn apply(&mut self, tx: &Transaction);
+
+    fn reset(&mut self);
+}
+
+pub(crate) struct EventLog {
+    opening_balances: Vec<Account>,
+    opening_quarantined: Vec<Transaction>,
+    events: Vec<Transaction>,
+    projections: Vec<Box<dyn Projection>>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog {
+            opening_balances: vec![],
+            opening_quarantined: vec![],
+            events: vec![],
+            projections: vec![],
+        }
+    }
+
+    pub fn seed(&mut self, account: Account) {
+        self.opening_balances.push(account);
+    }
+
+    pub fn seed_quarantined(&mut self, tx: Transaction) {
+        self.opening_quarantined.push(tx);
+    }
+
+    pub fn append(&mut self, tx: Transaction) {
+        for projection in self.projections.iter_mut() {
+            projection.apply(&tx);
+        }
+
+        self.events.push(tx);
+    }
+
+    pub fn add_projection(&mut self, mut projection: Box<dyn Projection>) {
+        for tx in self.events.iter() {
+            projection.apply(tx);
+        }
+
+        self.projections.push(projection);
+    }
+
+    pub fn projection<P: Projection>(&self) -> Option<&P> {
+        self.projections.iter().find_map(|projection| {
+            let projection: &dyn Any = projection.as_ref();
+            projection.downcast_ref::<P>()
+        })
+    }
+
+    pub fn rebuild_projections(&mut self) {
+        for projection in self.projections.iter_mut() {
+            projection.reset();
+
+            for tx in self.events.iter() {
+                projection.apply(tx);
+            }
+        }
+    }
+
+    pub fn opening_balances(&self) -> &[Account] {
+        &self.opening_balances
+    }
+
+    pub fn opening_quarantined(&self) -> &[Transaction] {
+        &self.opening_quarantined
+    }
+
+    pub fn events(&self) -> &[Transaction] {
+        &self.events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::types::{ ClientId, TransactionType };
+
+    #[derive(Default)]
+    struct DepositTotals {
+        totals: HashMap<ClientId, Decimal>,
+    }
+
+    impl Projection for DepositTotals {
+        fn apply(&mut self, tx: &Transaction) {
+            if let TransactionType::Deposit(amount) = tx.tx_type {
+                *self.totals.entry(tx.client_id).or_default() += amount;
+            }
+        }
+
+        fn reset(&mut self) {
+            self.totals.clear();
+        }
+    }
+
+    fn deposit(client_id: ClientId, tx_id: u32, amount: Decimal) -> Transaction {
+        Transaction::new(client_id, tx_id, TransactionType::Deposit(amount))
+    }
+
+    #[test]
+    fn test_append() {
+        let mut event_log = EventLog::new();
+        event_log.add_projection(Box::new(DepositTotals::default()));
+
+        event_log.append(deposit(1, 1, dec!(10)));
+        event_log.append(deposit(1, 2, dec!(5)));
+        event_log.append(deposit(2, 3, dec!(1)));
+
+        assert_eq!(event_log.events().len(), 3);
+
+        let totals = &event_log.projection::<DepositTotals>().unwrap().totals;
+        assert_eq!(totals.get(&1), Some(&dec!(15)));
+        assert_eq!(totals.get(&2), Some(&dec!(1)));
+    }
+
+    #[test]
+    fn test_add_projection_replays_events() {
+        let mut event_log = EventLog::new();
+
+        event_log.append(deposit(1, 1, dec!(10)));
+        event_log.append(deposit(1, 2, dec!(5)));
+
+        event_log.add_projection(Box::new(DepositTotals::default()));
+
+        let totals = &event_log.projection::<DepositTotals>().unwrap().totals;
+        assert_eq!(totals.get(&1), Some(&dec!(15)));
+    }
+
+    #[test]
+    fn test_rebuild_projections() {
+        let mut event_log = EventLog::new();
+        event_log.add_projection(Box::new(DepositTotals::default()));
+
+        event_log.append(deposit(1, 1, dec!(10)));
+        event_log.rebuild_projections();
+        event_log.rebuild_projections();
+
+        let totals = &event_log.projection::<DepositTotals>().unwrap().totals;
+        assert_eq!(totals.get(&1), Some(&dec!(10)));
+    }
+
+    #[test]
+    fn test_projection_unknown_type() {
+        let event_log = EventLog::new();
+
+        assert!(event_log.projection::<DepositTotals>().is_none());
+    }
+}
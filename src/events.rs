@@ -0,0 +1,159 @@
+use std::{ io, sync::mpsc::Sender };
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{ reason::ReasonCode, types::ClientId };
+
+/// A change the engine made to an account, or a transaction it turned down. Handlers get them in
+/// the order they happen, for audit pipelines and downstream notifications.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineEvent {
+    /// Funds credited, available or held when deposits are held
+    Deposited {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    Withdrawn {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    DisputeOpened {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    /// Resolved by a `resolve` row or because the dispute expired
+    DisputeResolved {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    ChargedBack {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    /// The chargeback was reversed by a representment, the funds are available again
+    Represented {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    AccountLocked {
+        client_id: ClientId,
+        tx_id: u32,
+    },
+    TransactionRejected {
+        client_id: ClientId,
+        tx_id: u32,
+        reason: ReasonCode,
+    },
+    /// The account started matching an alert rule, with its balances then
+    AlertRaised {
+        client_id: ClientId,
+        tx_id: u32,
+        rule: String,
+        available: Decimal,
+        held: Decimal,
+    },
+}
+
+impl EngineEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EngineEvent::Deposited { .. } => "deposited",
+            EngineEvent::Withdrawn { .. } => "withdrawn",
+            EngineEvent::DisputeOpened { .. } => "dispute_opened",
+            EngineEvent::DisputeResolved { .. } => "dispute_resolved",
+            EngineEvent::ChargedBack { .. } => "charged_back",
+            EngineEvent::Represented { .. } => "represented",
+            EngineEvent::AccountLocked { .. } => "account_locked",
+            EngineEvent::TransactionRejected { .. } => "transaction_rejected",
+            EngineEvent::AlertRaised { .. } => "alert_raised",
+        }
+    }
+}
+
+/// Receives the events of an engine, after the transaction that caused them is done.
+pub trait EventHandler: Send {
+    fn on_event(&mut self, event: &EngineEvent);
+}
+
+// Hands the events to another thread, they are dropped once the receiver is gone
+impl EventHandler for Sender<EngineEvent> {
+    fn on_event(&mut self, event: &EngineEvent) {
+        let _ = self.send(event.clone());
+    }
+}
+
+/// Writes every event as a line of JSON.
+pub struct EventWriter<W: io::Write + Send> {
+    writer: W,
+}
+
+impl<W: io::Write + Send> EventWriter<W> {
+    pub fn new(writer: W) -> Self {
+        EventWriter { writer }
+    }
+}
+
+impl<W: io::Write + Send> EventHandler for EventWriter<W> {
+    fn on_event(&mut self, event: &EngineEvent) {
+        let result = serde_json::to_writer(&mut self.writer, event)
+            .map_err(io::Error::from)
+            .and_then(|()| self.writer.write_all(b"\n"));
+
+        if let Err(err) = result {
+            log::error!("Failed to write the {} event: {}", event.name(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_event_writer() {
+        let mut output = vec![];
+        let mut writer = EventWriter::new(&mut output);
+
+        writer.on_event(&EngineEvent::Deposited { client_id: 1, tx_id: 2, amount: dec!(1.5) });
+        writer.on_event(&EngineEvent::TransactionRejected {
+            client_id: 1,
+            tx_id: 3,
+            reason: ReasonCode::InsufficientFunds,
+        });
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"{"event":"deposited","client_id":1,"tx_id":2,"amount":"1.5"}"#,
+                "\n",
+                r#"{"event":"transaction_rejected","client_id":1,"tx_id":3,"reason":"INSUFFICIENT_FUNDS"}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_channel() {
+        let (mut sender, receiver) = mpsc::channel();
+        let event = EngineEvent::AccountLocked { client_id: 1, tx_id: 2 };
+
+        sender.on_event(&event);
+        assert_eq!(receiver.recv().unwrap(), event);
+
+        // The engine isn't stopped by a receiver that's gone
+        drop(receiver);
+        sender.on_event(&event);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{ system::{ SystemAccount, SystemLedger }, types::{ custom_serde, Account } };
+
+// Treasury's position across every client and system account. The engine keeps a single balance
+// per client, so every amount is already in the base currency and the report only labels it;
+// converting other currencies needs the exchange rates, which the engine doesn't have yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExposureSummary {
+    pub currency: String,
+    /// Available funds of the clients, without the deficits
+    pub available: Decimal,
+    pub held: Decimal,
+    /// What clients in deficit owe
+    pub deficits: Decimal,
+    pub system: SystemLedger,
+}
+
+impl ExposureSummary {
+    pub fn new<'a>(
+        currency: &str,
+        accounts: impl IntoIterator<Item = &'a Account>,
+        system: &SystemLedger
+    ) -> Self {
+        let mut summary = ExposureSummary {
+            currency: currency.to_string(),
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            deficits: Decimal::ZERO,
+            system: system.clone(),
+        };
+
+        for account in accounts {
+            match account.available < Decimal::ZERO {
+                true => summary.deficits -= account.available,
+                false => summary.available += account.available,
+            }
+
+            summary.held += account.held;
+        }
+
+        summary
+    }
+
+    /// The client totals, net of the deficits.
+    pub fn client_funds(&self) -> Decimal {
+        self.available + self.held - self.deficits
+    }
+
+    /// What the system holds on behalf of clients: their funds and the deposits in escrow.
+    pub fn exposure(&self) -> Decimal {
+        self.client_funds() + self.system.balance(SystemAccount::Escrow)
+    }
+
+    pub fn write_report<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        // System accounts are named like in the system accounts section of the output
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum Item {
+            Total(&'static str),
+            System(SystemAccount),
+        }
+
+        #[derive(Serialize)]
+        struct Row<'a> {
+            item: Item,
+            currency: &'a str,
+            #[serde(serialize_with = "custom_serde::serialize_decimal")]
+            amount: Decimal,
+        }
+
+        let system = SystemAccount::ALL.map(|account| {
+            (Item::System(account), self.system.balance(account))
+        });
+
+        let items = [
+            (Item::Total("available"), self.available),
+            (Item::Total("held"), self.held),
+            (Item::Total("deficits"), self.deficits),
+            (Item::Total("client_funds"), self.client_funds()),
+        ]
+            .into_iter()
+            .chain(system)
+            .chain([(Item::Total("exposure"), self.exposure())]);
+
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for (item, amount) in items {
+            writer.serialize(Row { item, currency: &self.currency, amount })?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_write_report() {
+        let accounts = [
+            Account { available: dec!(10), held: dec!(2.5), total: dec!(12.5), ..Account::new(1) },
+            Account { available: dec!(-3), total: dec!(-3), ..Account::new(2) },
+            Account { available: dec!(1), total: dec!(1), ..Account::new(3) },
+        ];
+        let mut system = SystemLedger::new();
+        system.credit(SystemAccount::Escrow, dec!(4));
+        system.credit(SystemAccount::ChargebackLosses, dec!(3));
+
+        let summary = ExposureSummary::new("EUR", &accounts, &system);
+        assert_eq!(summary.client_funds(), dec!(10.5));
+        assert_eq!(summary.exposure(), dec!(14.5));
+
+        let mut output = vec![];
+        summary.write_report(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "item,currency,amount\n\
+             available,EUR,11\n\
+             held,EUR,2.5\n\
+             deficits,EUR,3\n\
+             client_funds,EUR,10.5\n\
+             fees,EUR,0\n\
+             chargeback_losses,EUR,3\n\
+             escrow,EUR,4\n\
+             adjustments,EUR,0\n\
+             exposure,EUR,14.5\n"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::{ collections::{ BTreeMap, HashMap }, fs, io, path::Path };
+
+use rust_decimal::Decimal;
+use serde::{ Deserialize, Serialize };
+
+use crate::types::{ custom_serde::{ self, Rounding }, ClientId, TransactionType };
+
+/// The fee of a transaction type, a flat amount plus a percentage of the transaction amount.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fee {
+    #[serde(default)]
+    pub flat: Decimal,
+    /// e.g. 1.5 for 1.5% of the amount
+    #[serde(default)]
+    pub percent: Decimal,
+}
+
+impl Fee {
+    /// Rounded like the balances of the engine charging it.
+    pub fn of(&self, amount: Decimal, rounding: Rounding) -> Decimal {
+        rounding.round(self.flat + amount * self.percent / Decimal::ONE_HUNDRED)
+    }
+}
+
+/// The fees of the transaction types of a client, the types left out are charged the default fee.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientFees {
+    #[serde(default)]
+    pub deposit: Option<Fee>,
+    #[serde(default)]
+    pub withdrawal: Option<Fee>,
+}
+
+/// Fees on deposits and withdrawals, with overrides per client. Loaded from a JSON file, e.g.
+/// `{"withdrawal": {"flat": "0.5", "percent": "1"}, "clients": {"17": {"withdrawal": {}}}}` where
+/// client 17 withdraws for free.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    #[serde(default)]
+    pub deposit: Option<Fee>,
+    #[serde(default)]
+    pub withdrawal: Option<Fee>,
+    #[serde(default)]
+    pub clients: HashMap<ClientId, ClientFees>,
+}
+
+impl FeeSchedule {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let schedule: FeeSchedule = serde_json::from_slice(&fs::read(path)?)?;
+
+        let fees = schedule.clients
+            .values()
+            .flat_map(|client| [client.deposit, client.withdrawal])
+            .chain([schedule.deposit, schedule.withdrawal])
+            .flatten();
+
+        for fee in fees {
+            if fee.flat < Decimal::ZERO || fee.percent < Decimal::ZERO || fee.percent > Decimal::ONE_HUNDRED {
+                let err = format!("invalid fee, flat {} percent {}", fee.flat, fee.percent);
+
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        }
+
+        Ok(schedule)
+    }
+
+    /// The fee of a transaction, zero for the types without fees.
+    pub fn fee(&self, client_id: ClientId, tx_type: &TransactionType, rounding: Rounding) -> Decimal {
+        let client = self.clients.get(&client_id);
+
+        let (fee, amount) = match tx_type {
+            TransactionType::Deposit(amount) => {
+                (client.and_then(|client| client.deposit).or(self.deposit), *amount)
+            }
+            TransactionType::Withdrawal(amount) => {
+                (client.and_then(|client| client.withdrawal).or(self.withdrawal), *amount)
+            }
+            _ => (None, Decimal::ZERO),
+        };
+
+        fee.map_or(Decimal::ZERO, |fee| fee.of(amount, rounding))
+    }
+}
+
+/// The fees charged by transaction type.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeeSummary {
+    fees: BTreeMap<&'static str, (u64, Decimal)>,
+}
+
+impl FeeSummary {
+    pub fn record(&mut self, tx_type: &'static str, fee: Decimal) {
+        let (count, amount) = self.fees.entry(tx_type).or_default();
+
+        *count += 1;
+        *amount += fee;
+    }
+
+    pub fn total(&self) -> Decimal {
+        self.fees.values().map(|(_, amount)| amount).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.fees.clear();
+    }
+
+    /// One `type,count,amount` row per transaction type charged, then the total.
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        #[derive(Serialize)]
+        struct Row<'a> {
+            #[serde(rename = "type")]
+            tx_type: &'a str,
+            count: u64,
+            #[serde(serialize_with = "custom_serde::serialize_decimal")]
+            amount: Decimal,
+        }
+
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for (tx_type, (count, amount)) in &self.fees {
+            writer.serialize(Row { tx_type, count: *count, amount: *amount })?;
+        }
+
+        let count = self.fees.values().map(|(count, _)| count).sum();
+        writer.serialize(Row { tx_type: "total", count, amount: self.total() })?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_fees() {
+        let path = std::env::temp_dir().join("transaction-engine-fees.json");
+
+        let json = r#"{
+            "deposit": {"flat": "0.1"},
+            "withdrawal": {"flat": "0.5", "percent": "1"},
+            "clients": {"17": {"withdrawal": {}}}
+        }"#;
+        fs::write(&path, json).unwrap();
+        let fees = FeeSchedule::load(&path).unwrap();
+
+        assert_eq!(fees.fee(1, &TransactionType::Deposit(dec!(10)), Rounding::default()), dec!(0.1));
+        assert_eq!(fees.fee(1, &TransactionType::Withdrawal(dec!(10)), Rounding::default()), dec!(0.6));
+        assert_eq!(fees.fee(1, &TransactionType::Withdrawal(dec!(0.12345)), Rounding::default()), dec!(0.5012));
+        assert_eq!(fees.fee(17, &TransactionType::Withdrawal(dec!(10)), Rounding::default()), dec!(0));
+        assert_eq!(fees.fee(17, &TransactionType::Deposit(dec!(10)), Rounding::default()), dec!(0.1));
+        assert_eq!(fees.fee(1, &TransactionType::Dispute, Rounding::default()), dec!(0));
+
+        fs::write(&path, r#"{"deposit": {"percent": "-1"}}"#).unwrap();
+        assert!(FeeSchedule::load(&path).is_err());
+
+        fs::write(&path, r#"{"transfer": {"flat": "1"}}"#).unwrap();
+        assert!(FeeSchedule::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_write_summary() {
+        let mut summary = FeeSummary::default();
+        summary.record("withdrawal", dec!(0.6));
+        summary.record("deposit", dec!(0.1));
+        summary.record("withdrawal", dec!(0.5));
+
+        let mut output = vec![];
+        summary.write_csv(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "type,count,amount\ndeposit,1,0.1\nwithdrawal,2,1.1\ntotal,3,1.2\n"
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use std::{ collections::BTreeMap, net::SocketAddr };
+
+use serde_json::{ Map, Value };
+use tonic::{ transport::Server, Request, Response, Status, Streaming };
+
+use crate::{
+    reason::ReasonCode,
+    engine::Receipt,
+    server::{ AccountView, EngineHandle, EngineStopped },
+    types::{ self, ClientId, Transaction, TransactionType },
+};
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/transaction_engine.Engine.rs"));
+}
+
+pub use generated::{ engine_client::EngineClient, engine_server::EngineServer };
+
+// Messages of proto/engine.proto, keep the tags in sync with it
+
+/// A transaction with the same fields as a row of the input, optional ones are left unset.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TransactionRequest {
+    #[prost(string, tag = "1")]
+    pub r#type: String,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    /// Decimal as a string, e.g. "1.5"
+    #[prost(string, optional, tag = "4")]
+    pub amount: Option<String>,
+    #[prost(uint64, optional, tag = "5")]
+    pub timestamp: Option<u64>,
+    #[prost(uint32, optional, tag = "6")]
+    pub into: Option<u32>,
+    #[prost(string, optional, tag = "7")]
+    pub reason: Option<String>,
+    #[prost(uint32, optional, tag = "8")]
+    pub to_client: Option<u32>,
+    #[prost(string, optional, tag = "9")]
+    pub currency: Option<String>,
+    #[prost(uint64, optional, tag = "10")]
+    pub seq: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubmitSummary {
+    #[prost(uint64, tag = "1")]
+    pub applied: u64,
+    #[prost(uint64, tag = "2")]
+    pub rejected: u64,
+    /// Rejected transactions by reason code
+    #[prost(btree_map = "string, uint64", tag = "3")]
+    pub rejections: BTreeMap<String, u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TransactionReceipt {
+    #[prost(uint32, tag = "1")]
+    pub tx: u32,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(bool, tag = "3")]
+    pub applied: bool,
+    /// The reason code and error of a rejected transaction, empty when it was applied
+    #[prost(string, tag = "4")]
+    pub reason: String,
+    #[prost(string, tag = "5")]
+    pub error: String,
+    #[prost(string, tag = "6")]
+    pub available_after: String,
+    #[prost(string, tag = "7")]
+    pub held_after: String,
+    #[prost(bool, tag = "8")]
+    pub locked: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetAccountRequest {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AccountReply {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+    #[prost(string, tag = "2")]
+    pub available: String,
+    #[prost(string, tag = "3")]
+    pub held: String,
+    #[prost(string, tag = "4")]
+    pub total: String,
+    #[prost(bool, tag = "5")]
+    pub locked: bool,
+    #[prost(string, tag = "6")]
+    pub tier: String,
+}
+
+// Goes through the same deserializer as the JSON Lines input, so both accept the same transactions
+impl TryFrom<TransactionRequest> for Transaction {
+    type Error = ReasonCode;
+
+    fn try_from(request: TransactionRequest) -> Result<Self, Self::Error> {
+        if !TransactionType::NAMES.contains(&request.r#type.as_str()) {
+            return Err(ReasonCode::UnknownType);
+        }
+
+        let mut fields = Map::new();
+
+        fields.insert("type".to_string(), request.r#type.into());
+        fields.insert("client".to_string(), request.client.into());
+        fields.insert("tx".to_string(), request.tx.into());
+
+        let optional = [
+            ("amount", request.amount.map(Value::from)),
+            ("timestamp", request.timestamp.map(Value::from)),
+            ("into", request.into.map(Value::from)),
+            ("reason", request.reason.map(Value::from)),
+            ("to_client", request.to_client.map(Value::from)),
+            ("currency", request.currency.map(Value::from)),
+            ("seq", request.seq.map(Value::from)),
+        ];
+
+        for (name, value) in optional {
+            if let Some(value) = value {
+                fields.insert(name.to_string(), value);
+            }
+        }
+
+        serde_json::from_value(Value::Object(fields)).map_err(|_| ReasonCode::Malformed)
+    }
+}
+
+// The client ids of the messages are 32 bits, as wide as `ClientId` or wider
+#[allow(clippy::useless_conversion)]
+fn proto_client(client_id: ClientId) -> u32 {
+    client_id.into()
+}
+
+impl From<AccountView> for AccountReply {
+    fn from(view: AccountView) -> Self {
+        AccountReply {
+            client: proto_client(view.account.client_id),
+            available: view.account.available.to_string(),
+            held: view.account.held.to_string(),
+            total: view.account.total.to_string(),
+            locked: view.account.locked,
+            tier: view.tier.to_string(),
+        }
+    }
+}
+
+impl From<Receipt> for TransactionReceipt {
+    fn from(receipt: Receipt) -> Self {
+        let (reason, error) = match &receipt.outcome {
+            Ok(()) => (String::new(), String::new()),
+            Err(err) => (err.reason().to_string(), err.to_string()),
+        };
+
+        TransactionReceipt {
+            tx: receipt.tx_id,
+            client: proto_client(receipt.client_id),
+            applied: receipt.outcome.is_ok(),
+            reason,
+            error,
+            available_after: receipt.available_after.to_string(),
+            held_after: receipt.held_after.to_string(),
+            locked: receipt.locked,
+        }
+    }
+}
+
+impl From<EngineStopped> for Status {
+    fn from(err: EngineStopped) -> Self {
+        Status::unavailable(err.to_string())
+    }
+}
+
+pub struct EngineService {
+    engine: EngineHandle,
+}
+
+impl EngineService {
+    pub fn new(engine: EngineHandle) -> Self {
+        EngineService { engine }
+    }
+}
+
+#[tonic::async_trait]
+impl generated::engine_server::Engine for EngineService {
+    // The next message is only read once the previous one is applied, so a client streaming faster
+    // than the engine, or than the rate limits, is slowed down by the HTTP/2 flow control.
+    async fn submit_transactions(
+        &self,
+        request: Request<Streaming<TransactionRequest>>
+    ) -> Result<Response<SubmitSummary>, Status> {
+        let mut stream = request.into_inner();
+        let mut summary = SubmitSummary::default();
+
+        while let Some(message) = stream.message().await? {
+            let tx_id = message.tx;
+
+            let result = match Transaction::try_from(message) {
+                Ok(tx) => {
+                    self.engine.throttle(tx.client_id).await;
+
+                    self.engine.submit(tx).await?.outcome.map_err(|err| {
+                        log::info!("Rejected transaction {}: {}", tx_id, err);
+                        err.reason()
+                    })
+                }
+                Err(reason) => {
+                    log::error!("Failed to parse transaction {}: {}", tx_id, reason);
+                    Err(reason)
+                }
+            };
+
+            match result {
+                Ok(()) => summary.applied += 1,
+                Err(reason) => {
+                    summary.rejected += 1;
+                    *summary.rejections.entry(reason.to_string()).or_default() += 1;
+                }
+            }
+        }
+
+        Ok(Response::new(summary))
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<TransactionRequest>
+    ) -> Result<Response<TransactionReceipt>, Status> {
+        let message = request.into_inner();
+        let tx_id = message.tx;
+
+        let tx = Transaction::try_from(message).map_err(|reason| {
+            log::error!("Failed to parse transaction {}: {}", tx_id, reason);
+            Status::invalid_argument(reason.to_string())
+        })?;
+
+        self.engine.throttle(tx.client_id).await;
+
+        let receipt = self.engine.submit(tx).await?;
+
+        if let Err(err) = &receipt.outcome {
+            log::info!("Rejected transaction {}: {}", tx_id, err);
+        }
+
+        Ok(Response::new(receipt.into()))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>
+    ) -> Result<Response<AccountReply>, Status> {
+        let client = request.into_inner().client;
+        let client_id = types::client_id(client.into()).map_err(|err| Status::invalid_argument(err.to_string()))?;
+
+        match self.engine.query_account(client_id).await? {
+            Some(view) => Ok(Response::new(view.into())),
+            None => Err(Status::not_found(format!("client {} is unknown", client))),
+        }
+    }
+}
+
+pub async fn serve(address: SocketAddr, engine: EngineHandle) -> Result<(), tonic::transport::Error> {
+    log::info!("Serving gRPC on {}", address);
+
+    Server::builder()
+        .add_service(EngineServer::new(EngineService::new(engine)))
+        .serve(address).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::engine::Engine;
+
+    use super::*;
+
+    fn request(tx_type: &str, tx: u32, amount: Option<&str>) -> TransactionRequest {
+        TransactionRequest {
+            r#type: tx_type.to_string(),
+            client: 1,
+            tx,
+            amount: amount.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_transaction_request() {
+        let tx = Transaction::try_from(TransactionRequest {
+            timestamp: Some(100),
+            ..request("deposit", 1, Some("1.5"))
+        }).unwrap();
+
+        assert_eq!(tx.tx_type, TransactionType::Deposit(rust_decimal_macros::dec!(1.5)));
+        assert_eq!(tx.timestamp, Some(100));
+        assert_eq!(
+            Transaction::try_from(request("dispute", 1, None)).unwrap().tx_type,
+            TransactionType::Dispute
+        );
+
+        let reject = |request| Transaction::try_from(request).unwrap_err();
+
+        assert_eq!(reject(request("refund", 1, None)), ReasonCode::UnknownType);
+        assert_eq!(reject(request("deposit", 1, None)), ReasonCode::Malformed);
+        assert_eq!(
+            reject(TransactionRequest { client: 70_000, ..request("deposit", 1, Some("1")) }),
+            ReasonCode::Malformed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_submit_transactions() {
+        let address: SocketAddr = "127.0.0.1:50917".parse().unwrap();
+
+        tokio::spawn(serve(address, EngineHandle::spawn(Engine::new())));
+
+        let mut client = loop {
+            match EngineClient::connect(format!("http://{}", address)).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        let messages = vec![
+            request("deposit", 1, Some("2.5")),
+            request("withdrawal", 2, Some("3")),
+            request("withdrawal", 3, Some("1")),
+            request("refund", 4, None)
+        ];
+
+        let summary = client
+            .submit_transactions(tokio_stream::iter(messages)).await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!((summary.applied, summary.rejected), (2, 2));
+        assert_eq!(
+            summary.rejections,
+            [("INSUFFICIENT_FUNDS".to_string(), 1), ("UNKNOWN_TYPE".to_string(), 1)].into_iter().collect()
+        );
+
+        let receipt = client.submit_transaction(request("withdrawal", 5, Some("1"))).await.unwrap().into_inner();
+        assert!(receipt.applied);
+        assert_eq!((receipt.available_after.as_str(), receipt.held_after.as_str()), ("0.5", "0"));
+
+        let receipt = client.submit_transaction(request("withdrawal", 6, Some("1"))).await.unwrap().into_inner();
+        assert_eq!((receipt.applied, receipt.reason.as_str()), (false, "INSUFFICIENT_FUNDS"));
+        assert_eq!(receipt.available_after, "0.5");
+        assert_eq!(
+            client.submit_transaction(request("refund", 7, None)).await.unwrap_err().code(),
+            tonic::Code::InvalidArgument
+        );
+
+        let account = client.get_account(GetAccountRequest { client: 1 }).await.unwrap().into_inner();
+
+        assert_eq!((account.available.as_str(), account.tier.as_str()), ("0.5", "standard"));
+        assert_eq!(
+            client.get_account(GetAccountRequest { client: 2 }).await.unwrap_err().code(),
+            tonic::Code::NotFound
+        );
+    }
+}
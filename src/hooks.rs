@@ -0,0 +1,16 @@
+use crate::{ policy::Decision, types::{ Account, Transaction } };
+
+/// Callbacks around every transaction the engine applies, so embedders can add their own rules
+/// (e.g. blocking withdrawals over a limit) without changing the engine.
+///
+/// `before_apply` sees the transactions that passed validation, right before they change an
+/// account, and the first decision other than `Allow` wins like with a policy. Admin operations
+/// (merges, unlocks, deletes, ...) and the releases of quarantined transactions aren't decided by
+/// hooks. `after_apply` is called once for every account a transaction changed, with its new state.
+pub trait EngineHooks: Send {
+    fn before_apply(&self, _tx: &Transaction) -> Decision {
+        Decision::Allow
+    }
+
+    fn after_apply(&mut self, _tx: &Transaction, _account: &Account) {}
+}
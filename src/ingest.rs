@@ -0,0 +1,1948 @@
+use std::{
+    fmt,
+    mem,
+    fs::File,
+    io::{ self, BufRead, BufReader, Read, Seek, SeekFrom },
+    path::{ Path, PathBuf },
+    str::{ self, FromStr },
+    sync::Arc,
+};
+
+use csv::{ ByteRecord, ReaderBuilder, StringRecord, Trim };
+use flate2::read::MultiGzDecoder;
+use serde::{ Deserialize, Serialize };
+use tokio::{
+    select,
+    spawn,
+    sync::{ mpsc::{ self, error::TrySendError }, oneshot },
+    task::{ spawn_blocking, JoinError, JoinHandle },
+};
+
+#[cfg(feature = "object-store")]
+use crate::object_input::ObjectReader;
+use crate::{
+    ordering::{ Reorder, Sequencer },
+    provenance::{ Provenance, Tagged },
+    reason::ReasonCode,
+    schema::{ self, Schema },
+    shard::{ self, shard_of, TxIndex },
+    spill::SpillQueue,
+    transform::Transform,
+    types::{ custom_serde::CsvColumns, Transaction, TransactionType },
+};
+
+/// The path that reads the transactions from stdin.
+pub const STDIN: &str = "-";
+
+// The bytes of a file `--parse-threads` reads and parses at once, there are at least as many chunks
+// as threads
+const CHUNK_BYTES: u64 = 1 << 20;
+
+/// Whether an input is the URL of an object in a store, e.g. `s3://bucket/key`, instead of a path.
+pub fn is_url(path: &Path) -> bool {
+    path.to_string_lossy().contains("://")
+}
+
+/// What happens to the batches read while the pipeline is full, because the input comes faster
+/// than the engine applies it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// The readers wait for room, so the input is read as fast as the engine applies it
+    #[default]
+    Block,
+    /// The batches are dropped and never applied, only counted
+    Drop,
+    /// The batches are queued in a temporary file until there's room, so the memory stays bounded
+    Spill,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Overflow::Block => write!(f, "block"),
+            Overflow::Drop => write!(f, "drop"),
+            Overflow::Spill => write!(f, "spill"),
+        }
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Overflow::Block),
+            "drop" => Ok(Overflow::Drop),
+            "spill" => Ok(Overflow::Spill),
+            _ => Err(format!("unknown overflow strategy {}, expected block, drop or spill", s)),
+        }
+    }
+}
+
+/// The records read while the pipeline was full, with an overflow strategy other than blocking.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Overflowed {
+    pub dropped: u64,
+    pub spilled: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tuning {
+    pub reader_threads: usize,
+    pub parser_threads: usize,
+    /// Threads reading and parsing whole chunks of each file, in place of the readers and parsers
+    pub parse_threads: Option<usize>,
+    pub shards: usize,
+    pub batch_size: usize,
+    /// Number of batches each channel of the pipeline holds
+    pub buffer_size: usize,
+    pub overflow: Overflow,
+    /// Seconds a record may arrive late by its timestamp and still be applied in time order
+    pub reorder_window: Option<u64>,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning {
+            reader_threads: 1,
+            parser_threads: 1,
+            parse_threads: None,
+            shards: 1,
+            batch_size: 100,
+            buffer_size: 100,
+            overflow: Overflow::Block,
+            reorder_window: None,
+        }
+    }
+}
+
+impl fmt::Display for Tuning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "--reader-threads {} --parser-threads {} --shards {} --batch-size {}",
+            self.reader_threads,
+            self.parser_threads,
+            self.shards,
+            self.batch_size
+        )?;
+
+        if let Some(threads) = self.parse_threads {
+            write!(f, " --parse-threads {}", threads)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Tuning {
+    pub fn grid(max_threads: usize) -> Vec<Tuning> {
+        let threads: Vec<usize> = [1, 2, 4, 8]
+            .into_iter()
+            .filter(|threads| *threads <= max_threads.max(1))
+            .collect();
+
+        let mut grid = vec![];
+
+        for &reader_threads in &threads {
+            for &parser_threads in &threads {
+                for shards in [1, 2, 4] {
+                    for batch_size in [1, 100, 1000] {
+                        grid.push(Tuning { reader_threads, parser_threads, shards, batch_size, ..Tuning::default() });
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    /// JSON Lines, one transaction object per line
+    Json,
+}
+
+impl InputFormat {
+    /// JSON for the `.json`, `.jsonl` and `.ndjson` extensions, CSV otherwise. The extension of a
+    /// compressed file is the one before its compression's, e.g. `.json` for `.json.gz`.
+    pub fn detect(path: &Path) -> Self {
+        let path = match Compression::detect(path) {
+            Compression::None => path,
+            _ => Path::new(path.file_stem().unwrap_or_default()),
+        };
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json" | "jsonl" | "ndjson") => InputFormat::Json,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            _ => Err(format!("unknown input format {}, expected csv or json", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Gzip for the `.gz` extension, zstd for `.zst`, none otherwise.
+    pub fn detect(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Decompresses what's read from the reader. A gzip stream can be several members one after
+    /// the other, like files that were compressed separately and concatenated.
+    pub fn decoder<R: Read + Send + 'static>(&self, reader: R) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(reader))),
+            Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+        })
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression {}, expected none, gzip or zstd", s)),
+        }
+    }
+}
+
+// The fields of a record as read, they are only checked to be UTF-8 when parsed
+struct Raw {
+    provenance: Provenance,
+    record: csv::Result<ByteRecord>,
+}
+
+// How the records of a batch are parsed. A JSON line is kept as the only field of its record, so
+// it goes through the pipeline (and to the rejects) like a CSV row.
+#[derive(Clone)]
+enum Layout {
+    Csv(Arc<StringRecord>),
+    Json,
+}
+
+type RawBatch = (Layout, Vec<Raw>);
+
+// A task of the pipeline, failing when an input couldn't be read to the end or it panicked
+type Stage = JoinHandle<io::Result<()>>;
+
+/// The handle of the pipeline's tasks and the receiver of the batches of transactions.
+pub type Pipeline = (JoinHandle<io::Result<Overflowed>>, mpsc::Receiver<Vec<Tagged>>);
+
+/// How much of the inputs to read: at most `limit` bytes of each, and only the records after the
+/// one at `after`, the last record a checkpointed run applied. The inputs before the one `after`
+/// was read from are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct ReadBounds {
+    pub limit: Option<u64>,
+    pub after: Option<Provenance>,
+}
+
+enum Input {
+    File {
+        path: PathBuf,
+        source: Arc<str>,
+        layout: Layout,
+        header_lines: u64,
+        ranges: Vec<(u64, u64)>,
+    },
+    // Only the records past the offset `after` are sent, a stream can't seek to it
+    Stream {
+        reader: Box<dyn Read + Send>,
+        source: Arc<str>,
+        format: InputFormat,
+        after: Option<u64>,
+    },
+}
+
+// Reads the csv files one after the other through the configured pipeline and sends the
+// transactions in batches. Readers split each file at line boundaries and parsers work on whole
+// batches, both keep the file order. With more than one shard, only the order of each client's
+// transactions is kept. A `-` path reads stdin instead, with a single reader. The bounds limit the
+// number of bytes to read from each file, and where to resume reading. Without a format, each file's is detected from its
+// extension and stdin is CSV. Without a compression, each file's is detected from its extension too
+// and stdin isn't compressed, a compressed file is read by a single reader like stdin and the limit
+// is then on its decompressed bytes. The transform is applied to every record before it's parsed.
+// With strict amounts, JSON records with a number amount are malformed, only strings are accepted.
+//
+// The handle fails when a file couldn't be read to the end or a stage panicked, the transactions
+// received are then only part of the input.
+pub fn spawn_pipeline(
+    paths: Vec<PathBuf>,
+    format: Option<InputFormat>,
+    compression: Option<Compression>,
+    tuning: Tuning,
+    bounds: ReadBounds,
+    transform: Option<Transform>
+) -> io::Result<Pipeline> {
+    let skipped = match &bounds.after {
+        Some(after) => paths
+            .iter()
+            .position(|path| source_name(path) == *after.source)
+            .ok_or_else(|| {
+                let err = format!("{} isn't one of the inputs", after.source);
+
+                io::Error::new(io::ErrorKind::InvalidInput, err)
+            })?,
+        None => 0,
+    };
+
+    let inputs = paths
+        .into_iter()
+        .skip(skipped)
+        .enumerate()
+        .map(|(index, path)| {
+            let after = bounds.after.as_ref().filter(|_| index == 0);
+
+            open_input(path, format, compression, &tuning, bounds.limit, after)
+        })
+        .collect::<io::Result<Vec<Input>>>()?;
+
+    Ok(spawn_inputs(inputs, tuning, transform))
+}
+
+// Replaces the glob patterns by the files they match, in alphabetical order. Patterns that don't
+// match any file are an error, like a missing file would be. URLs are kept as they are.
+pub fn expand_globs(paths: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+    let mut expanded = vec![];
+
+    for path in paths {
+        let pattern = path.to_string_lossy();
+
+        if is_url(&path) || !pattern.contains(['*', '?', '[']) {
+            expanded.push(path);
+            continue;
+        }
+
+        let matches = glob::glob(&pattern)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .map_err(io::Error::from)?;
+
+        if matches.is_empty() {
+            return Err(
+                io::Error::new(io::ErrorKind::NotFound, format!("no file matches {}", pattern))
+            );
+        }
+
+        expanded.extend(matches);
+    }
+
+    Ok(expanded)
+}
+
+fn open_input(
+    path: PathBuf,
+    format: Option<InputFormat>,
+    compression: Option<Compression>,
+    tuning: &Tuning,
+    limit: Option<u64>,
+    after: Option<&Provenance>
+) -> io::Result<Input> {
+    if path.as_os_str() == STDIN {
+        return Ok(Input::Stream {
+            reader: compression.unwrap_or_default().decoder(io::stdin())?,
+            source: source_name(&path).into(),
+            format: format.unwrap_or_default(),
+            after: after.map(|after| after.offset),
+        });
+    }
+
+    let url = is_url(&path);
+    let compression = compression.unwrap_or(Compression::detect(&path));
+
+    // An object or a compressed file can't be split into ranges, it's streamed
+    if url || compression != Compression::None {
+        let reader = match url {
+            true => open_url(&path)?,
+            false => Box::new(File::open(&path)?),
+        };
+        let reader = compression.decoder(reader)?;
+
+        return Ok(Input::Stream {
+            reader: match limit {
+                Some(limit) => Box::new(reader.take(limit)),
+                None => reader,
+            },
+            source: source_name(&path).into(),
+            format: format.unwrap_or(InputFormat::detect(&path)),
+            after: after.map(|after| after.offset),
+        });
+    }
+
+    let (layout, data_start, header_lines) = match format.unwrap_or(InputFormat::detect(&path)) {
+        InputFormat::Csv => {
+            let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(&path)?;
+            let headers = Arc::new(schema::normalize(reader.headers()?));
+
+            log_schema(&path.display().to_string(), &headers);
+
+            (Layout::Csv(headers), reader.position().byte(), reader.position().line() - 1)
+        }
+        InputFormat::Json => (Layout::Json, 0, 0),
+    };
+
+    let (data_start, header_lines) = match after {
+        Some(after) => skip_record(&path, &layout, after)?,
+        None => (data_start, header_lines),
+    };
+
+    let end = match limit {
+        Some(limit) => limit.min(File::open(&path)?.metadata()?.len()),
+        None => File::open(&path)?.metadata()?.len(),
+    };
+
+    // Chunks are parsed by a pool of threads, ranges read by a thread each
+    let count = match tuning.parse_threads {
+        Some(threads) => threads.max((end.saturating_sub(data_start) / CHUNK_BYTES) as usize),
+        None => tuning.reader_threads,
+    };
+    let ranges = split_ranges(&path, data_start, end, count)?;
+
+    Ok(Input::File {
+        source: source_name(&path).into(),
+        path,
+        layout,
+        header_lines,
+        ranges,
+    })
+}
+
+// Where the record after `after` starts and how many lines come before it, reading resumes there.
+fn skip_record(path: &Path, layout: &Layout, after: &Provenance) -> io::Result<(u64, u64)> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(after.offset))?;
+
+    match layout {
+        Layout::Csv(_) => {
+            let mut reader = ReaderBuilder::new().has_headers(false).from_reader(file);
+            reader.read_record(&mut StringRecord::new())?;
+
+            let position = reader.position();
+
+            Ok((after.offset + position.byte(), after.line + position.line() - 2))
+        }
+        Layout::Json => {
+            let read = BufReader::new(file).read_line(&mut String::new())?;
+
+            Ok((after.offset + (read as u64), after.line))
+        }
+    }
+}
+
+// The source of the records of an input, as their provenance shows it
+fn source_name(path: &Path) -> String {
+    match path.as_os_str() == STDIN {
+        true => "stdin".to_string(),
+        false => path.display().to_string(),
+    }
+}
+
+#[cfg(feature = "object-store")]
+fn open_url(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    Ok(Box::new(ObjectReader::open(&path.to_string_lossy())?))
+}
+
+#[cfg(not(feature = "object-store"))]
+fn open_url(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let err = format!("reading {} needs the object-store feature", path.display());
+
+    Err(io::Error::new(io::ErrorKind::Unsupported, err))
+}
+
+fn log_schema(source: &str, headers: &StringRecord) {
+    let schema = Schema::detect(headers);
+
+    match schema.missing.is_empty() {
+        true => log::info!("Schema of {}: {}", source, schema),
+        false => log::error!("Schema of {}: {}, its rows can't be parsed", source, schema),
+    }
+}
+
+fn spawn_inputs(
+    inputs: Vec<Input>,
+    tuning: Tuning,
+    transform: Option<Transform>
+) -> Pipeline {
+    let transform = transform.map(Arc::new);
+
+    if let Some(threads) = tuning.parse_threads {
+        let (split, parse, parsed_rx) = spawn_chunks(inputs, tuning, threads, transform);
+
+        return spawn_stages(split, None, parse, parsed_rx, tuning);
+    }
+
+    let (raw_tx, raw_rx) = mpsc::channel::<RawBatch>(tuning.buffer_size);
+
+    // Without blocking, the readers send to a stage of their own that never waits for the parsers
+    let (read_tx, overflow) = match tuning.overflow {
+        Overflow::Block => (raw_tx, None),
+        Overflow::Drop => {
+            let (read_tx, read_rx) = mpsc::channel::<RawBatch>(tuning.buffer_size);
+
+            (read_tx, Some(spawn(drop_overflow(read_rx, raw_tx))))
+        }
+        Overflow::Spill => {
+            let (read_tx, read_rx) = mpsc::channel::<RawBatch>(tuning.buffer_size);
+
+            (read_tx, Some(spawn(spill_overflow(read_rx, raw_tx))))
+        }
+    };
+
+    let read = spawn(async move {
+        for input in inputs {
+            let read = match input {
+                Input::File { path, source, layout, header_lines, ranges } => {
+                    read_ranges(path, source, layout, ranges, header_lines, tuning, &read_tx).await
+                }
+                Input::Stream { reader, source, format, after } => {
+                    read_stream(reader, source, format, after, tuning, read_tx.clone()).await
+                }
+            };
+
+            if !read? {
+                break;
+            }
+        }
+
+        Ok(())
+    });
+
+    let (parse, parsed_rx) = spawn_parsers(raw_rx, tuning, transform);
+
+    spawn_stages(read, overflow, parse, parsed_rx, tuning)
+}
+
+fn dropped_batch(batch: &RawBatch) {
+    if let Some(Raw { provenance, .. }) = batch.1.first() {
+        log::warn!("Dropped {} records from {}, the pipeline is full", batch.1.len(), provenance);
+    }
+}
+
+async fn drop_overflow(mut rx: mpsc::Receiver<RawBatch>, tx: mpsc::Sender<RawBatch>) -> io::Result<Overflowed> {
+    let mut overflowed = Overflowed::default();
+
+    while let Some(batch) = rx.recv().await {
+        match tx.try_send(batch) {
+            Ok(()) => {}
+            Err(TrySendError::Full(batch)) => {
+                dropped_batch(&batch);
+
+                overflowed.dropped += batch.1.len() as u64;
+            }
+            Err(TrySendError::Closed(_)) => break,
+        }
+    }
+
+    Ok(overflowed)
+}
+
+// Once a batch is spilled, the ones after it are spilled too until the queue is sent, so the
+// parsers get the batches in the order they were read.
+async fn spill_overflow(mut rx: mpsc::Receiver<RawBatch>, tx: mpsc::Sender<RawBatch>) -> io::Result<Overflowed> {
+    let mut overflowed = Overflowed::default();
+    let mut queue = SpillQueue::<Spilled>::new()?;
+
+    loop {
+        if queue.is_empty() {
+            let Some(batch) = rx.recv().await else {
+                break;
+            };
+
+            match tx.try_send(batch) {
+                Ok(()) => {}
+                Err(TrySendError::Full(batch)) => {
+                    overflowed.spilled += batch.1.len() as u64;
+                    queue.push(&Spilled::new(batch))?;
+                }
+                Err(TrySendError::Closed(_)) => break,
+            }
+
+            continue;
+        }
+
+        select! {
+            permit = tx.reserve() => {
+                let Ok(permit) = permit else {
+                    break;
+                };
+
+                if let Some(spilled) = queue.pop()? {
+                    permit.send(spilled.into_batch());
+                }
+            }
+            batch = rx.recv() => {
+                let Some(batch) = batch else {
+                    while let Some(spilled) = queue.pop()? {
+                        if tx.send(spilled.into_batch()).await.is_err() {
+                            break;
+                        }
+                    }
+
+                    break;
+                };
+
+                overflowed.spilled += batch.1.len() as u64;
+                queue.push(&Spilled::new(batch))?;
+            }
+        }
+    }
+
+    Ok(overflowed)
+}
+
+// A batch as it's kept in the spill file, a record that couldn't be read keeps its error message.
+#[derive(Serialize, Deserialize)]
+struct Spilled {
+    headers: Option<Vec<String>>,
+    records: Vec<SpilledRaw>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SpilledRaw {
+    source: String,
+    line: u64,
+    offset: u64,
+    fields: Result<Vec<Vec<u8>>, String>,
+}
+
+impl Spilled {
+    fn new((layout, batch): RawBatch) -> Self {
+        let headers = match layout {
+            Layout::Csv(headers) => Some(headers.iter().map(String::from).collect()),
+            Layout::Json => None,
+        };
+
+        let records = batch
+            .into_iter()
+            .map(|Raw { provenance, record }| SpilledRaw {
+                source: provenance.source.to_string(),
+                line: provenance.line,
+                offset: provenance.offset,
+                fields: record
+                    .map(|record| record.iter().map(<[u8]>::to_vec).collect())
+                    .map_err(|err| err.to_string()),
+            })
+            .collect();
+
+        Spilled { headers, records }
+    }
+
+    fn into_batch(self) -> RawBatch {
+        let layout = match self.headers {
+            Some(headers) => Layout::Csv(Arc::new(StringRecord::from(headers))),
+            None => Layout::Json,
+        };
+
+        let batch = self.records
+            .into_iter()
+            .map(|SpilledRaw { source, line, offset, fields }| Raw {
+                provenance: Provenance { source: source.into(), line, offset },
+                record: fields.map(ByteRecord::from).map_err(|err| io::Error::other(err).into()),
+            })
+            .collect();
+
+        (layout, batch)
+    }
+}
+
+// Parses up to `parser_threads` batches at a time on the blocking pool, and hands them on in the
+// order they were read.
+fn spawn_parsers(
+    mut raw_rx: mpsc::Receiver<RawBatch>,
+    tuning: Tuning,
+    transform: Option<Arc<Transform>>
+) -> (Stage, mpsc::Receiver<Vec<Parsed>>) {
+    let (parsing_tx, mut parsing_rx) = mpsc::channel::<JoinHandle<Vec<Parsed>>>(tuning.parser_threads);
+    let (parsed_tx, parsed_rx) = mpsc::channel::<Vec<Parsed>>(tuning.buffer_size);
+
+    let parse = spawn(async move {
+        while let Some((layout, batch)) = raw_rx.recv().await {
+            let transform = transform.clone();
+            let parsing = spawn_blocking(move || {
+                parse(batch, &layout, transform.as_deref())
+            });
+
+            if parsing_tx.send(parsing).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let hand_off = spawn(async move {
+        let mut result = Ok(());
+
+        while let Some(parsing) = parsing_rx.recv().await {
+            match parsing.await {
+                Ok(batch) => {
+                    if parsed_tx.send(batch).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    result = Err(stage_failed("parser", err));
+                    break;
+                }
+            }
+        }
+
+        drop(parsing_rx);
+
+        result.and(parse.await.map_err(|err| stage_failed("parser", err)))
+    });
+
+    (hand_off, parsed_rx)
+}
+
+// A chunk of a file being read and parsed, with its batches and how many lines it had. Its lines
+// are counted from the start of the chunk until the chunks before it are done.
+struct Chunk {
+    source: Arc<str>,
+    parsing: JoinHandle<io::Result<(Vec<Vec<Parsed>>, u64)>>,
+    // The header lines of the file at its first chunk, the lines are counted again from there
+    header_lines: Option<u64>,
+}
+
+// Splits the files into chunks, each one read and parsed from start to end on the blocking pool, up
+// to `threads` at a time. The chunks are handed on in the file order, so the transactions of each
+// client keep theirs. A stream can't be split, each of its batches is parsed as a chunk of its own.
+fn spawn_chunks(
+    inputs: Vec<Input>,
+    tuning: Tuning,
+    threads: usize,
+    transform: Option<Arc<Transform>>
+) -> (Stage, Stage, mpsc::Receiver<Vec<Parsed>>) {
+    let (chunk_tx, mut chunk_rx) = mpsc::channel::<Chunk>(threads);
+    let (parsed_tx, parsed_rx) = mpsc::channel::<Vec<Parsed>>(tuning.buffer_size);
+
+    let split = spawn(async move {
+        for input in inputs {
+            match input {
+                Input::File { path, source, layout, header_lines, ranges } => {
+                    for (i, range) in ranges.into_iter().enumerate() {
+                        let (path, chunk_source, layout, transform) = (path.clone(), source.clone(), layout.clone(), transform.clone());
+                        let parsing = spawn_blocking(move || {
+                            parse_chunk(&path, chunk_source, &layout, range, tuning.batch_size, transform.as_deref())
+                        });
+                        let chunk = Chunk { source: source.clone(), parsing, header_lines: (i == 0).then_some(header_lines) };
+
+                        if chunk_tx.send(chunk).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+                Input::Stream { reader, source, format, after } => {
+                    let (stream_tx, mut stream_rx) = mpsc::channel::<RawBatch>(tuning.buffer_size);
+                    let read = spawn(read_stream(reader, source.clone(), format, after, tuning, stream_tx));
+
+                    while let Some((layout, batch)) = stream_rx.recv().await {
+                        let transform = transform.clone();
+                        let parsing = spawn_blocking(move || {
+                            Ok((vec![parse(batch, &layout, transform.as_deref())], 0))
+                        });
+
+                        // Its lines are counted from the start of the stream already
+                        if chunk_tx.send(Chunk { source: source.clone(), parsing, header_lines: Some(0) }).await.is_err() {
+                            break;
+                        }
+                    }
+
+                    drop(stream_rx);
+
+                    if !read.await.map_err(|err| stage_failed("reader", err))?? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    });
+
+    let hand_off = spawn(async move {
+        let mut lines_before = 0;
+
+        'chunks: while let Some(Chunk { source, parsing, header_lines }) = chunk_rx.recv().await {
+            let (batches, lines) = read_failed(&source, parsing.await)?;
+
+            if let Some(header_lines) = header_lines {
+                lines_before = header_lines;
+            }
+
+            for mut batch in batches {
+                for (provenance, _) in &mut batch {
+                    provenance.line += lines_before;
+                }
+
+                if parsed_tx.send(batch).await.is_err() {
+                    break 'chunks;
+                }
+            }
+
+            lines_before += lines;
+        }
+
+        Ok(())
+    });
+
+    (split, hand_off, parsed_rx)
+}
+
+// Reads and parses one chunk of a file in batches, returns them with how many lines it had.
+fn parse_chunk(
+    path: &Path,
+    source: Arc<str>,
+    layout: &Layout,
+    range: (u64, u64),
+    batch_size: usize,
+    transform: Option<&Transform>
+) -> io::Result<(Vec<Vec<Parsed>>, u64)> {
+    let mut batches = vec![];
+    let json = matches!(layout, Layout::Json);
+
+    let lines = read_range(path, source, json, range, batch_size, |batch| {
+        batches.push(parse(batch, layout, transform));
+        true
+    })?;
+
+    Ok((batches, lines))
+}
+
+fn spawn_stages(
+    read: Stage,
+    overflow: Option<JoinHandle<io::Result<Overflowed>>>,
+    parse: Stage,
+    mut parsed_rx: mpsc::Receiver<Vec<Parsed>>,
+    tuning: Tuning
+) -> Pipeline {
+    let (tx, rx) = mpsc::channel::<Vec<Tagged>>(tuning.buffer_size);
+    let engine_tx = tx.clone();
+
+    let shards = tuning.shards.max(1);
+    let mut shard_txs = Vec::with_capacity(shards);
+    let mut forwards = Vec::with_capacity(shards);
+
+    for _ in 0..shards {
+        let (shard_tx, mut shard_rx) = mpsc::channel::<Forward>(tuning.buffer_size);
+        let tx = tx.clone();
+
+        shard_txs.push(shard_tx);
+        forwards.push(
+            spawn(async move {
+                while let Some(forward) = shard_rx.recv().await {
+                    let batch = match forward {
+                        Forward::Batch(batch) => batch,
+                        Forward::Barrier(done) => {
+                            let _ = done.send(());
+                            continue;
+                        }
+                    };
+
+                    if tx.send(batch).await.is_err() {
+                        log::error!("Failed to send transaction to engine");
+                        break;
+                    }
+                }
+            })
+        );
+    }
+
+    let sequence = spawn(async move {
+        let mut sequencer = Sequencer::new();
+        let mut reorder = tuning.reorder_window.map(Reorder::new);
+        let mut index = TxIndex::default();
+
+        loop {
+            // Sequence numbers are assigned after reordering, so they follow the order of application
+            let batch = match parsed_rx.recv().await {
+                Some(batch) => match &mut reorder {
+                    Some(reorder) => {
+                        for (provenance, record) in batch {
+                            let timestamp = record.as_ref().ok().and_then(|tx| tx.timestamp);
+                            reorder.push(timestamp, (provenance, record));
+                        }
+
+                        reorder.ready()
+                    }
+                    None => batch,
+                },
+                None => match reorder.take() {
+                    Some(reorder) => reorder.finish(),
+                    None => break,
+                },
+            };
+
+            let mut batches: Vec<Vec<Tagged>> = (0..shards).map(|_| vec![]).collect();
+
+            for (provenance, record) in batch {
+                let (shard, record, raw, error) = match record {
+                    Ok(tx) => (shard_of(tx.client_id, shards), Ok(sequencer.assign(tx)), None, None),
+                    Err((reason, raw, error)) => (0, Err(reason), raw, Some(error)),
+                };
+
+                let tagged = Tagged { provenance, record, raw, error };
+
+                let crossing = match &tagged.record {
+                    Ok(sequenced) if shards > 1 => crosses_shards(&mut index, &sequenced.tx, shard, shards),
+                    _ => false,
+                };
+
+                if !crossing {
+                    batches[shard].push(tagged);
+                    continue;
+                }
+
+                // Everything before it reaches the engine first, and it reaches the engine before
+                // anything after it is handed to a shard
+                for (shard, batch) in batches.iter_mut().enumerate() {
+                    if !batch.is_empty() && shard_txs[shard].send(Forward::Batch(mem::take(batch))).await.is_err() {
+                        return Ok(());
+                    }
+                }
+
+                if !forwarded(&shard_txs).await || engine_tx.send(vec![tagged]).await.is_err() {
+                    return Ok(());
+                }
+            }
+
+            for (shard, batch) in batches.into_iter().enumerate() {
+                if !batch.is_empty() && shard_txs[shard].send(Forward::Batch(batch)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    });
+
+    // Every stage is awaited so none is left running, the first failure is the one reported
+    let handle = spawn(async move {
+        let read = read.await.map_err(|err| stage_failed("reader", err)).and_then(|read| read);
+        let overflowed = match overflow {
+            Some(overflow) => overflow.await.map_err(|err| stage_failed("overflow", err)).and_then(|overflowed| overflowed),
+            None => Ok(Overflowed::default()),
+        };
+        let parse = parse.await.map_err(|err| stage_failed("parser", err)).and_then(|parse| parse);
+        let sequence = sequence.await.map_err(|err| stage_failed("sequencer", err)).and_then(|sequence| sequence);
+        let mut result = read.and(overflowed).and_then(|overflowed| parse.and(sequence).map(|()| overflowed));
+
+        for forward in forwards {
+            let forward = forward.await.map_err(|err| stage_failed("forwarder", err));
+            result = result.and_then(|overflowed| forward.map(|()| overflowed));
+        }
+
+        if let Ok(Overflowed { dropped, spilled }) = result {
+            if dropped > 0 {
+                log::warn!("Dropped {} records while the pipeline was full", dropped);
+            }
+
+            if spilled > 0 {
+                log::info!("Spilled {} records to disk while the pipeline was full", spilled);
+            }
+        }
+
+        result
+    });
+
+    (handle, rx)
+}
+
+// What the sequencer hands a shard: a batch to forward to the engine, or a barrier acknowledged once
+// the batches before it are forwarded
+enum Forward {
+    Batch(Vec<Tagged>),
+    Barrier(oneshot::Sender<()>),
+}
+
+// A transfer or a merge with a client of another shard, or a transaction taking or referring to an
+// id taken on another shard, has to keep its place among the transactions of both shards
+fn crosses_shards(index: &mut TxIndex, tx: &Transaction, shard: usize, shards: usize) -> bool {
+    let other_client = shard::counterparty(tx).is_some_and(|other| shard_of(other, shards) != shard);
+    let other_id = index.other_shard(tx, shard).is_some();
+
+    index.take(tx, shard);
+
+    other_client || other_id
+}
+
+// Waits until every shard forwarded the batches it was given, false when the pipeline stopped
+async fn forwarded(shard_txs: &[mpsc::Sender<Forward>]) -> bool {
+    let mut barriers = Vec::with_capacity(shard_txs.len());
+
+    for shard_tx in shard_txs {
+        let (done, barrier) = oneshot::channel();
+
+        if shard_tx.send(Forward::Barrier(done)).await.is_err() {
+            return false;
+        }
+
+        barriers.push(barrier);
+    }
+
+    for barrier in barriers {
+        if barrier.await.is_err() {
+            return false;
+        }
+    }
+
+    true
+}
+
+fn stage_failed(stage: &str, err: JoinError) -> io::Error {
+    io::Error::other(format!("the {} stage failed: {}", stage, err))
+}
+
+// Splits [start, end) into up to `count` ranges, each ending right after a newline.
+fn split_ranges(path: &Path, start: u64, end: u64, count: usize) -> io::Result<Vec<(u64, u64)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut bounds = vec![start];
+
+    for i in 1..count.max(1) {
+        let target = start + ((end.saturating_sub(start)) * (i as u64)) / (count as u64);
+
+        if target <= *bounds.last().unwrap_or(&start) {
+            continue;
+        }
+
+        reader.seek(SeekFrom::Start(target - 1))?;
+
+        let mut skipped = vec![];
+        let bound = target - 1 + (reader.read_until(b'\n', &mut skipped)? as u64);
+
+        if bound > *bounds.last().unwrap_or(&start) && bound < end {
+            bounds.push(bound);
+        }
+    }
+
+    bounds.push(end);
+
+    Ok(
+        bounds
+            .windows(2)
+            .map(|range| (range[0], range[1]))
+            .collect()
+    )
+}
+
+// Returns false when the receiver is gone, an error when the file couldn't be read to the end.
+async fn read_ranges(
+    path: PathBuf,
+    source: Arc<str>,
+    layout: Layout,
+    ranges: Vec<(u64, u64)>,
+    header_lines: u64,
+    tuning: Tuning,
+    tx: &mpsc::Sender<RawBatch>
+) -> io::Result<bool> {
+    let batch_size = tuning.batch_size;
+    let readers: Vec<_> = ranges
+        .into_iter()
+        .map(|range| {
+            let (range_tx, range_rx) = mpsc::channel::<Vec<Raw>>(tuning.buffer_size);
+            let path = path.clone();
+            let source = source.clone();
+            let json = matches!(layout, Layout::Json);
+
+            let handle = spawn_blocking(move || {
+                read_range(&path, source, json, range, batch_size, |batch| range_tx.blocking_send(batch).is_ok())
+            });
+
+            (handle, range_rx)
+        })
+        .collect();
+
+    // Line numbers are relative to the start of each range until the previous ranges are done.
+    let mut lines_before = header_lines;
+
+    for (handle, mut range_rx) in readers {
+        while let Some(mut batch) = range_rx.recv().await {
+            for raw in &mut batch {
+                raw.provenance.line += lines_before;
+            }
+
+            if tx.send((layout.clone(), batch)).await.is_err() {
+                log::error!("Failed to send transaction to engine");
+                return Ok(false);
+            }
+        }
+
+        lines_before += read_failed(&source, handle.await)?;
+    }
+
+    Ok(true)
+}
+
+fn read_failed<T>(source: &str, result: Result<io::Result<T>, JoinError>) -> io::Result<T> {
+    result
+        .map_err(|err| stage_failed("reader", err))
+        .and_then(|read| read)
+        .map_err(|err| io::Error::new(err.kind(), format!("failed to read {}: {}", source, err)))
+}
+
+// A stream can't be split into ranges, so it's read by a single blocking task, headers included.
+async fn read_stream(
+    reader: Box<dyn Read + Send>,
+    source: Arc<str>,
+    format: InputFormat,
+    after: Option<u64>,
+    tuning: Tuning,
+    tx: mpsc::Sender<RawBatch>
+) -> io::Result<bool> {
+    let batch_size = tuning.batch_size;
+    let (layout_tx, layout_rx) = oneshot::channel::<Layout>();
+    let (stream_tx, mut stream_rx) = mpsc::channel::<Vec<Raw>>(tuning.buffer_size);
+    let stream_source = source.clone();
+
+    let handle = spawn_blocking(move || {
+        match format {
+            InputFormat::Csv => {
+                let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+                let headers = schema::normalize(reader.headers()?);
+
+                log_schema(&stream_source, &headers);
+
+                let _ = layout_tx.send(Layout::Csv(Arc::new(headers)));
+
+                read_records(&mut reader, &stream_source, 0, batch_size, |batch| stream_tx.blocking_send(batch).is_ok()).map(|_| ())
+            }
+            InputFormat::Json => {
+                let _ = layout_tx.send(Layout::Json);
+
+                read_lines(BufReader::new(reader), &stream_source, 0, batch_size, |batch| stream_tx.blocking_send(batch).is_ok()).map(|_| ())
+            }
+        }
+    });
+
+    if let Ok(layout) = layout_rx.await {
+        while let Some(mut batch) = stream_rx.recv().await {
+            if let Some(after) = after {
+                batch.retain(|raw| raw.provenance.offset > after);
+
+                if batch.is_empty() {
+                    continue;
+                }
+            }
+
+            if tx.send((layout.clone(), batch)).await.is_err() {
+                log::error!("Failed to send transaction to engine");
+                return Ok(false);
+            }
+        }
+    }
+
+    read_failed(&source, handle.await).map(|()| true)
+}
+
+// Reads the records of one range and returns how many lines it had.
+fn read_range(
+    path: &Path,
+    source: Arc<str>,
+    json: bool,
+    (start, end): (u64, u64),
+    batch_size: usize,
+    send: impl FnMut(Vec<Raw>) -> bool
+) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+
+    let lines = LineCounter { inner: file.take(end - start), lines: 0 };
+
+    if json {
+        let mut reader = BufReader::new(lines);
+
+        if !read_lines(&mut reader, &source, start, batch_size, send)? {
+            return Ok(0);
+        }
+
+        return Ok(reader.into_inner().lines);
+    }
+
+    let mut reader = ReaderBuilder::new().trim(Trim::All).has_headers(false).from_reader(lines);
+
+    if !read_records(&mut reader, &source, start, batch_size, send)? {
+        return Ok(0);
+    }
+
+    Ok(reader.into_inner().lines)
+}
+
+// Sends the records in batches, returns false when the receiver is gone.
+fn read_records<R: Read>(
+    reader: &mut csv::Reader<R>,
+    source: &Arc<str>,
+    start: u64,
+    batch_size: usize,
+    mut send: impl FnMut(Vec<Raw>) -> bool
+) -> io::Result<bool> {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut record = ByteRecord::new();
+
+    loop {
+        let read = reader.read_byte_record(&mut record);
+        let position = record.position().unwrap_or(reader.position());
+
+        let provenance = Provenance {
+            source: source.clone(),
+            line: position.line(),
+            offset: start + position.byte(),
+        };
+
+        let record = match read {
+            Ok(false) => break,
+            Ok(true) => Ok(record.clone()),
+            Err(err) if err.is_io_error() => return Err(err.into()),
+            Err(err) => Err(err),
+        };
+
+        batch.push(Raw { provenance, record });
+
+        if batch.len() >= batch_size {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+
+            if !send(full) {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(batch.is_empty() || send(batch))
+}
+
+// Sends the non-blank lines in batches, each one as a single field record, returns false when the
+// receiver is gone.
+fn read_lines<R: BufRead>(
+    mut reader: R,
+    source: &Arc<str>,
+    start: u64,
+    batch_size: usize,
+    mut send: impl FnMut(Vec<Raw>) -> bool
+) -> io::Result<bool> {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut line = String::new();
+    let mut offset = start;
+    let mut number = 0;
+
+    loop {
+        line.clear();
+
+        let read = reader.read_line(&mut line)?;
+
+        if read == 0 {
+            break;
+        }
+
+        number += 1;
+
+        let provenance = Provenance { source: source.clone(), line: number, offset };
+
+        offset += read as u64;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        batch.push(Raw { provenance, record: Ok(ByteRecord::from(vec![line.trim()])) });
+
+        if batch.len() >= batch_size {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+
+            if !send(full) {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(batch.is_empty() || send(batch))
+}
+
+// A record that couldn't be parsed keeps its reason, its fields when they could be read and why
+type Parsed = (Provenance, Result<Transaction, (ReasonCode, Option<StringRecord>, String)>);
+
+// CSV rows are parsed straight from their fields, JSON lines and the rows of headers with a column
+// missing or repeated through serde. Rejected rows keep their fields as read, before the transform.
+fn parse(
+    batch: Vec<Raw>,
+    layout: &Layout,
+    transform: Option<&Transform>
+) -> Vec<Parsed> {
+    let headers = match layout {
+        Layout::Csv(headers) => headers,
+        Layout::Json => return parse_json(batch, transform),
+    };
+
+    let type_column = headers.iter().position(|header| header == "type");
+    let columns = CsvColumns::new(headers);
+
+    batch
+        .into_iter()
+        .map(|Raw { provenance, record }| {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    log::error!("Failed to read transaction at {}: {}", provenance, err);
+                    return (provenance, Err((ReasonCode::Malformed, None, err.to_string())));
+                }
+            };
+
+            // A transform works on the text of the fields
+            let mapped = match transform.map(|transform| (transform, StringRecord::from_byte_record(record.clone()))) {
+                Some((transform, Ok(text))) => Some(transform.apply_csv(headers, &text).into_byte_record()),
+                Some((_, Err(err))) => {
+                    log::error!("Failed to parse transaction at {}: {}", provenance, err);
+
+                    let raw = StringRecord::from_byte_record_lossy(record);
+                    return (provenance, Err((ReasonCode::Malformed, Some(raw), err.to_string())));
+                }
+                None => None,
+            };
+            let fields = mapped.as_ref().unwrap_or(&record);
+
+            let parsed = match &columns {
+                Some(columns) => columns.parse(fields),
+                None => {
+                    fields
+                        .deserialize::<Transaction>(Some(headers.as_byte_record()))
+                        .map_err(|err| err.to_string())
+                }
+            };
+
+            match parsed {
+                Ok(transaction) => (provenance, Ok(transaction)),
+                Err(err) => {
+                    let tx_type = type_column
+                        .and_then(|column| fields.get(column))
+                        .and_then(|field| str::from_utf8(field).ok());
+
+                    let reason = match tx_type {
+                        Some(tx_type) if !TransactionType::NAMES.contains(&tx_type) => {
+                            log::warn!("Unknown transaction type {} at {}", tx_type, provenance);
+                            ReasonCode::UnknownType
+                        }
+                        _ => {
+                            log::error!("Failed to parse transaction at {}: {}", provenance, err);
+                            ReasonCode::Malformed
+                        }
+                    };
+
+                    (provenance, Err((reason, Some(StringRecord::from_byte_record_lossy(record)), err.to_string())))
+                }
+            }
+        })
+        .collect()
+}
+
+fn parse_json(batch: Vec<Raw>, transform: Option<&Transform>) -> Vec<Parsed> {
+    batch
+        .into_iter()
+        .map(|Raw { provenance, record }| {
+            // The lines were read as text
+            let record = match record {
+                Ok(record) => StringRecord::from_byte_record_lossy(record),
+                Err(err) => {
+                    log::error!("Failed to read transaction at {}: {}", provenance, err);
+                    return (provenance, Err((ReasonCode::Malformed, None, err.to_string())));
+                }
+            };
+
+            let line = record.get(0).unwrap_or_default();
+            let mapped = transform.map(|transform| transform.apply_json(line));
+            let line = mapped.as_deref().unwrap_or(line);
+
+            match serde_json::from_str::<Transaction>(line) {
+                Ok(transaction) => (provenance, Ok(transaction)),
+                Err(err) => {
+                    let value = serde_json::from_str::<serde_json::Value>(line).ok();
+                    let tx_type = value.as_ref().and_then(|value| value.get("type")?.as_str());
+
+                    let reason = match tx_type {
+                        Some(tx_type) if !TransactionType::NAMES.contains(&tx_type) => {
+                            log::warn!("Unknown transaction type {} at {}", tx_type, provenance);
+                            ReasonCode::UnknownType
+                        }
+                        _ => {
+                            log::error!("Failed to parse transaction at {}: {}", provenance, err);
+                            ReasonCode::Malformed
+                        }
+                    };
+
+                    (provenance, Err((reason, Some(record), err.to_string())))
+                }
+            }
+        })
+        .collect()
+}
+
+struct LineCounter<R> {
+    inner: R,
+    lines: u64,
+}
+
+impl<R: Read> Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        self.lines += buf[..read]
+            .iter()
+            .filter(|byte| **byte == b'\n')
+            .count() as u64;
+
+        Ok(read)
+    }
+}
+
+// Returns how many bytes of the file hold the headers and its first `rows` lines, decompressed
+// when its extension is the one of a compression.
+pub fn sample_len(path: &Path, rows: usize) -> io::Result<u64> {
+    let mut reader = BufReader::new(Compression::detect(path).decoder(File::open(path)?)?);
+    let mut line = vec![];
+    let mut len = 0;
+
+    for _ in 0..=rows {
+        line.clear();
+
+        match reader.read_until(b'\n', &mut line)? {
+            0 => break,
+            read => len += read as u64,
+        }
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ collections::HashMap, env, fs, time::Duration };
+
+    use super::*;
+    use crate::types::ClientId;
+
+    fn write_input(name: &str) -> PathBuf {
+        let mut input = String::from("type,client,tx,amount\n");
+
+        for tx in 1..=500u32 {
+            match tx {
+                250 => input.push_str("deposit,x,250,1.0\n"),
+                _ => input.push_str(&format!("deposit,{},{},1.0\n", tx % 7, tx)),
+            }
+        }
+
+        let path = env::temp_dir().join(name);
+        fs::write(&path, input).unwrap();
+        path
+    }
+
+    async fn collect(path: &Path, tuning: Tuning, limit: Option<u64>) -> Vec<Tagged> {
+        let (handle, mut rx) = spawn_pipeline(vec![path.to_path_buf()], None, None, tuning, ReadBounds { limit, after: None }, None).unwrap();
+        let mut tagged = vec![];
+
+        while let Some(batch) = rx.recv().await {
+            tagged.extend(batch);
+        }
+
+        handle.await.unwrap().unwrap();
+
+        tagged
+    }
+
+    fn per_client(tagged: &[Tagged]) -> HashMap<ClientId, Vec<(u32, u64)>> {
+        let mut clients: HashMap<ClientId, Vec<(u32, u64)>> = HashMap::new();
+
+        for Tagged { provenance, record, .. } in tagged {
+            if let Ok(sequenced) = record {
+                clients
+                    .entry(sequenced.tx.client_id)
+                    .or_default()
+                    .push((sequenced.tx.tx_id, provenance.line));
+            }
+        }
+
+        clients
+    }
+
+    #[tokio::test]
+    async fn test_file_order_with_readers_and_parsers() {
+        let path = write_input("transaction-engine-ingest-order.csv");
+
+        let tuning = Tuning { reader_threads: 4, parser_threads: 3, shards: 1, batch_size: 7, ..Tuning::default() };
+        let tagged = collect(&path, tuning, None).await;
+
+        assert_eq!(tagged.len(), 500);
+
+        for (i, Tagged { provenance, record, .. }) in tagged.iter().enumerate() {
+            let line = (i as u64) + 2;
+
+            assert_eq!(provenance.line, line);
+
+            match line {
+                251 => assert_eq!(record.as_ref().unwrap_err(), &ReasonCode::Malformed),
+                _ => assert_eq!(record.as_ref().unwrap().tx.tx_id, (i as u32) + 1),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_order_with_shards() {
+        let path = write_input("transaction-engine-ingest-shards.csv");
+
+        let expected = per_client(&collect(&path, Tuning::default(), None).await);
+
+        for shards in [2, 4] {
+            let tuning = Tuning { reader_threads: 2, parser_threads: 2, shards, batch_size: 10, ..Tuning::default() };
+
+            assert_eq!(per_client(&collect(&path, tuning, None).await), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_order_with_parse_threads() {
+        let path = write_input("transaction-engine-ingest-chunks.csv");
+        let expected = collect(&path, Tuning::default(), None).await;
+
+        for threads in [1, 3, 8] {
+            let tuning = Tuning { parse_threads: Some(threads), batch_size: 7, ..Tuning::default() };
+            let (handle, mut rx) = spawn_pipeline(vec![path.clone(), path.clone()], None, None, tuning, ReadBounds::default(), None).unwrap();
+            let mut tagged = vec![];
+
+            while let Some(batch) = rx.recv().await {
+                tagged.extend(batch);
+            }
+
+            handle.await.unwrap().unwrap();
+
+            assert_eq!(tagged.len(), 1000);
+
+            // The lines are counted again from the second file
+            for (tagged, expected) in tagged.chunks(500).flat_map(|file| file.iter().zip(&expected)) {
+                assert_eq!(tagged.provenance, expected.provenance);
+                assert_eq!(tagged.record.as_ref().map(|sequenced| sequenced.tx.tx_id), expected.record.as_ref().map(|sequenced| sequenced.tx.tx_id));
+            }
+
+            let tuning = Tuning { parse_threads: Some(threads), shards: 4, batch_size: 10, ..Tuning::default() };
+
+            assert_eq!(per_client(&collect(&path, tuning, None).await), per_client(&expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overflow() {
+        let path = write_input("transaction-engine-ingest-overflow.csv");
+
+        let expected = collect(&path, Tuning::default(), None).await;
+
+        for overflow in [Overflow::Drop, Overflow::Spill] {
+            let tuning = Tuning { batch_size: 10, buffer_size: 1, overflow, ..Tuning::default() };
+            let (handle, mut rx) = spawn_pipeline(vec![path.clone()], None, None, tuning, ReadBounds::default(), None).unwrap();
+
+            // The engine falls behind while the whole file is read
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            let mut tagged = vec![];
+
+            while let Some(batch) = rx.recv().await {
+                tagged.extend(batch);
+            }
+
+            let overflowed = handle.await.unwrap().unwrap();
+
+            match overflow {
+                Overflow::Drop => {
+                    assert!(overflowed.dropped > 0);
+                    assert_eq!((tagged.len() as u64) + overflowed.dropped, 500);
+                }
+                _ => {
+                    assert!(overflowed.spilled > 0);
+                    assert_eq!(
+                        tagged.iter().map(|tagged| (&tagged.provenance, &tagged.record)).collect::<Vec<_>>(),
+                        expected.iter().map(|tagged| (&tagged.provenance, &tagged.record)).collect::<Vec<_>>()
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_inputs() {
+        let path = write_input("transaction-engine-ingest-compressed.csv");
+        let plain = fs::read(&path).unwrap();
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        io::Write::write_all(&mut gzip, &plain).unwrap();
+
+        let compressed = [
+            ("transaction-engine-ingest-compressed.csv.gz", gzip.finish().unwrap()),
+            ("transaction-engine-ingest-compressed.csv.zst", zstd::encode_all(plain.as_slice(), 0).unwrap()),
+        ];
+
+        let expected = collect(&path, Tuning::default(), None).await;
+
+        for (name, bytes) in compressed {
+            let compressed_path = env::temp_dir().join(name);
+            fs::write(&compressed_path, bytes).unwrap();
+
+            assert_eq!(InputFormat::detect(&compressed_path), InputFormat::Csv);
+            assert_eq!(sample_len(&compressed_path, 10).unwrap(), sample_len(&path, 10).unwrap());
+
+            let tuning = Tuning { reader_threads: 3, batch_size: 9, ..Tuning::default() };
+            let tagged = collect(&compressed_path, tuning, None).await;
+
+            assert_eq!(
+                tagged.iter().map(|tagged| (tagged.provenance.line, &tagged.record)).collect::<Vec<_>>(),
+                expected.iter().map(|tagged| (tagged.provenance.line, &tagged.record)).collect::<Vec<_>>()
+            );
+        }
+
+        assert_eq!(InputFormat::detect(Path::new("drop.jsonl.gz")), InputFormat::Json);
+        assert_eq!(Compression::detect(Path::new("drop.jsonl")), Compression::None);
+    }
+
+    #[tokio::test]
+    async fn test_stream_matches_file() {
+        let path = write_input("transaction-engine-ingest-stream.csv");
+
+        let tuning = Tuning { parser_threads: 2, batch_size: 9, ..Tuning::default() };
+        let stream = Input::Stream {
+            reader: Box::new(io::Cursor::new(fs::read(&path).unwrap())),
+            source: "stdin".into(),
+            format: InputFormat::Csv,
+            after: None,
+        };
+        let (handle, mut rx) = spawn_inputs(vec![stream], tuning, None);
+        let mut streamed = vec![];
+
+        while let Some(batch) = rx.recv().await {
+            streamed.extend(batch);
+        }
+
+        handle.await.unwrap().unwrap();
+
+        let expected = collect(&path, Tuning::default(), None).await;
+
+        assert_eq!(streamed.len(), expected.len());
+
+        for (streamed, expected) in streamed.iter().zip(&expected) {
+            assert_eq!(&*streamed.provenance.source, "stdin");
+            assert_eq!(streamed.provenance.line, expected.provenance.line);
+            assert_eq!(streamed.provenance.offset, expected.provenance.offset);
+            assert_eq!(
+                streamed.record.as_ref().map(|sequenced| sequenced.tx.tx_id),
+                expected.record.as_ref().map(|sequenced| sequenced.tx.tx_id)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multiple_files_in_order() {
+        let first = env::temp_dir().join("transaction-engine-ingest-first.csv");
+        let second = env::temp_dir().join("transaction-engine-ingest-second.csv");
+        fs::write(&first, "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,1.0\n").unwrap();
+        fs::write(&second, "client,type,tx,amount\n1,withdrawal,3,0.5\n").unwrap();
+
+        let tuning = Tuning { reader_threads: 2, ..Tuning::default() };
+        let paths = vec![first.clone(), second.clone()];
+        let (handle, mut rx) = spawn_pipeline(paths, None, None, tuning, ReadBounds::default(), None).unwrap();
+        let mut tagged = vec![];
+
+        while let Some(batch) = rx.recv().await {
+            tagged.extend(batch);
+        }
+
+        handle.await.unwrap().unwrap();
+
+        let received: Vec<(String, u64, u32, u64)> = tagged
+            .iter()
+            .map(|Tagged { provenance, record, .. }| {
+                let sequenced = record.as_ref().unwrap();
+                (provenance.source.to_string(), provenance.line, sequenced.tx.tx_id, sequenced.seq)
+            })
+            .collect();
+
+        let first = first.display().to_string();
+        let second = second.display().to_string();
+
+        assert_eq!(received, vec![
+            (first.clone(), 2, 1, 0),
+            (first, 3, 2, 0),
+            (second, 2, 3, 1)
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_after() {
+        let first = env::temp_dir().join("transaction-engine-ingest-resume-first.csv");
+        let second = env::temp_dir().join("transaction-engine-ingest-resume-second.json");
+        fs::write(
+            &first,
+            "type,client,tx,amount,note\ndeposit,1,1,1.0,\"two\nlines\"\ndeposit,2,2,1.0,\ndeposit,3,3,1.0,\n"
+        ).unwrap();
+        fs::write(
+            &second,
+            "{\"type\":\"deposit\",\"client\":4,\"tx\":4,\"amount\":\"1.0\"}\n\n{\"type\":\"deposit\",\"client\":5,\"tx\":5,\"amount\":\"1.0\"}\n"
+        ).unwrap();
+
+        let read = |after: Option<Provenance>| {
+            let paths = vec![first.clone(), second.clone()];
+            let tuning = Tuning { reader_threads: 2, ..Tuning::default() };
+            let bounds = ReadBounds { limit: None, after };
+
+            async move {
+                let (handle, mut rx) = spawn_pipeline(paths, None, None, tuning, bounds, None).unwrap();
+                let mut tagged = vec![];
+
+                while let Some(batch) = rx.recv().await {
+                    tagged.extend(batch.into_iter().map(|tagged| tagged.provenance));
+                }
+
+                handle.await.unwrap().unwrap();
+                tagged
+            }
+        };
+
+        let all = read(None).await;
+        assert_eq!(all.iter().map(|provenance| provenance.line).collect::<Vec<_>>(), vec![2, 4, 5, 1, 3]);
+
+        for (index, after) in all.iter().enumerate() {
+            assert_eq!(read(Some(after.clone())).await, all[index + 1..]);
+        }
+
+        let unknown = Provenance { source: "elsewhere.csv".into(), line: 2, offset: 10 };
+        let bounds = ReadBounds { limit: None, after: Some(unknown) };
+        assert!(spawn_pipeline(vec![first.clone()], None, None, Tuning::default(), bounds, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reorder_window() {
+        let path = env::temp_dir().join("transaction-engine-ingest-reorder.csv");
+        fs::write(
+            &path,
+            "type,client,tx,amount,ts\ndeposit,1,1,1.0,100\ndeposit,1,2,1.0,130\ndeposit,1,3,1.0,110\n\
+             deposit,1,4,1.0,\ndeposit,1,5,1.0,200\ndeposit,1,6,1.0,150\n"
+        ).unwrap();
+
+        let tuning = Tuning { batch_size: 2, reorder_window: Some(30), ..Tuning::default() };
+        let received: Vec<(u32, u64)> = collect(&path, tuning, None)
+            .await
+            .iter()
+            .map(|Tagged { record, .. }| {
+                let sequenced = record.as_ref().unwrap();
+                (sequenced.tx.tx_id, sequenced.seq)
+            })
+            .collect();
+
+        assert_eq!(received, vec![(1, 0), (3, 1), (2, 2), (4, 3), (6, 4), (5, 5)]);
+    }
+
+    #[test]
+    fn test_parse_unknown_type() {
+        let headers = Layout::Csv(Arc::new(StringRecord::from(vec!["type", "client", "tx", "amount"])));
+        let raw = |record: Vec<&str>| Raw {
+            provenance: Provenance { source: "a.csv".into(), line: 2, offset: 0 },
+            record: Ok(ByteRecord::from(record)),
+        };
+
+        let parsed = parse(
+            vec![raw(vec!["refund", "1", "1", "2.0"]), raw(vec!["deposit", "1", "2", ""])],
+            &headers,
+            None
+        );
+
+        let (_, refund) = &parsed[0];
+        let (reason, row, _) = refund.as_ref().unwrap_err();
+        assert_eq!(*reason, ReasonCode::UnknownType);
+        assert_eq!(row.as_ref().unwrap().get(0), Some("refund"));
+
+        let (_, deposit) = &parsed[1];
+        assert_eq!(deposit.as_ref().unwrap_err().0, ReasonCode::Malformed);
+    }
+
+    #[test]
+    fn test_parse_number_amounts() {
+        let raw = |line: &str| Raw {
+            provenance: Provenance { source: "test".into(), line: 1, offset: 0 },
+            record: Ok(ByteRecord::from(vec![line])),
+        };
+        let lines = || vec![
+            raw(r#"{"type":"deposit","client":1,"tx":1,"amount":"0.10"}"#),
+            raw(r#"{"type":"deposit","client":1,"tx":2,"amount":0.10}"#),
+            raw(r#"{"type":"dispute","client":1,"tx":1}"#)
+        ];
+
+        let parsed: Vec<_> = parse(lines(), &Layout::Json, None)
+            .into_iter()
+            .map(|(_, parsed)| parsed.map(|tx| tx.tx_type).map_err(|(reason, _, _)| reason))
+            .collect();
+        assert_eq!(parsed, vec![
+            Ok(TransactionType::Deposit(rust_decimal_macros::dec!(0.10))),
+            Ok(TransactionType::Deposit(rust_decimal_macros::dec!(0.10))),
+            Ok(TransactionType::Dispute)
+        ]);
+        assert!(matches!(&parsed[1], Ok(TransactionType::Deposit(amount)) if amount.scale() == 2));
+    }
+
+    #[tokio::test]
+    async fn test_json_lines() {
+        let path = env::temp_dir().join("transaction-engine-ingest.jsonl");
+        fs::write(
+            &path,
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.5\"}\n\
+             \n\
+             {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":0.1,\"timestamp\":1700000000}\n\
+             {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n\
+             {\"type\":\"refund\",\"client\":1,\"tx\":3}\n\
+             {\"type\":\"deposit\",\"client\":1\n"
+        ).unwrap();
+
+        assert_eq!(InputFormat::detect(&path), InputFormat::Json);
+
+        let tuning = Tuning { reader_threads: 3, batch_size: 2, ..Tuning::default() };
+        let tagged = collect(&path, tuning, None).await;
+
+        let received: Vec<(u64, Result<TransactionType, ReasonCode>)> = tagged
+            .iter()
+            .map(|Tagged { provenance, record, .. }| {
+                (provenance.line, record.clone().map(|sequenced| sequenced.tx.tx_type))
+            })
+            .collect();
+
+        assert_eq!(received, vec![
+            (1, Ok(TransactionType::Deposit(rust_decimal_macros::dec!(2.5)))),
+            (3, Ok(TransactionType::Withdrawal(rust_decimal_macros::dec!(0.1)))),
+            (4, Ok(TransactionType::Dispute)),
+            (5, Err(ReasonCode::UnknownType)),
+            (6, Err(ReasonCode::Malformed))
+        ]);
+        assert_eq!(tagged[1].record.as_ref().unwrap().tx.timestamp, Some(1_700_000_000));
+        assert_eq!(tagged[1].provenance.offset, 53);
+        assert_eq!(tagged[3].raw.as_ref().unwrap().get(0), Some(r#"{"type":"refund","client":1,"tx":3}"#));
+
+        let stream = Input::Stream {
+            reader: Box::new(io::Cursor::new(fs::read(&path).unwrap())),
+            source: "stdin".into(),
+            format: InputFormat::Json,
+            after: None,
+        };
+        let (handle, mut rx) = spawn_inputs(vec![stream], Tuning::default(), None);
+        let mut streamed = vec![];
+
+        while let Some(batch) = rx.recv().await {
+            streamed.extend(batch);
+        }
+
+        handle.await.unwrap().unwrap();
+
+        assert_eq!(
+            streamed.iter().map(|tagged| tagged.provenance.line).collect::<Vec<_>>(),
+            vec![1, 3, 4, 5, 6]
+        );
+    }
+
+    // Reads its data, then fails or panics instead of reaching the end
+    struct Broken {
+        data: io::Cursor<&'static [u8]>,
+        panics: bool,
+    }
+
+    impl Read for Broken {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.data.read(buf)? {
+                0 if self.panics => panic!("reader bug"),
+                0 => Err(io::Error::other("connection reset")),
+                read => Ok(read),
+            }
+        }
+    }
+
+    async fn drain(reader: Broken) -> (usize, io::Result<Overflowed>) {
+        let stream = Input::Stream { reader: Box::new(reader), source: "stdin".into(), format: InputFormat::Json, after: None };
+        let (handle, mut rx) = spawn_inputs(vec![stream], Tuning::default(), None);
+        let mut received = 0;
+
+        while let Some(batch) = rx.recv().await {
+            received += batch.len();
+        }
+
+        (received, handle.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_reader_failures() {
+        let data: &[u8] = b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1\"}\n";
+
+        let (received, result) = drain(Broken { data: io::Cursor::new(data), panics: false }).await;
+        assert_eq!(received, 0);
+        assert!(result.unwrap_err().to_string().contains("connection reset"));
+
+        let (_, result) = drain(Broken { data: io::Cursor::new(data), panics: true }).await;
+        assert!(result.unwrap_err().to_string().contains("reader stage failed"));
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let dir = env::temp_dir().join("transaction-engine-ingest-globs");
+        fs::create_dir_all(&dir).unwrap();
+
+        for name in ["2024-01-02.csv", "2024-01-01.csv", "other.txt"] {
+            fs::write(dir.join(name), "type,client,tx,amount\n").unwrap();
+        }
+
+        let expanded = expand_globs(vec![PathBuf::from(STDIN), dir.join("*.csv")]).unwrap();
+
+        assert_eq!(expanded, vec![
+            PathBuf::from(STDIN),
+            dir.join("2024-01-01.csv"),
+            dir.join("2024-01-02.csv")
+        ]);
+        assert!(expand_globs(vec![dir.join("*.json")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sample() {
+        let path = write_input("transaction-engine-ingest-sample.csv");
+
+        let limit = sample_len(&path, 10).unwrap();
+        let tuning = Tuning { reader_threads: 3, ..Tuning::default() };
+
+        let tagged = collect(&path, tuning, Some(limit)).await;
+
+        assert_eq!(tagged.len(), 10);
+        assert_eq!(tagged[9].provenance.line, 11);
+    }
+}
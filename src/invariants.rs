@@ -0,0 +1,92 @@
+use std::{ error::Error, fmt };
+
+use rust_decimal::Decimal;
+
+use crate::types::{ Account, Transaction };
+
+/// A state the engine must never reach, found by [`Engine::verify`](crate::Engine::verify).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The total balance isn't the available plus the held balance
+    Total(Account),
+    NegativeHeld(Account),
+    /// The held balances don't add up to the funds of the open disputes, holds and quarantined
+    /// withdrawals, plus the held funds of the opening balances
+    Held {
+        held: Decimal,
+        holds: Decimal,
+    },
+    /// A locked account changed by another client's transaction or one it refuses, or lost
+    /// available funds, held more or was unlocked. Only its releases and its disputes from before
+    /// the lock, the deposits allowed into it and admin transactions may change it
+    LockedChanged {
+        before: Account,
+        after: Account,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Total(account) => {
+                write!(
+                    f,
+                    "client {} has a total of {} but {} available and {} held",
+                    account.client_id,
+                    account.total,
+                    account.available,
+                    account.held
+                )
+            }
+            Violation::NegativeHeld(account) => {
+                write!(f, "client {} holds {}", account.client_id, account.held)
+            }
+            Violation::Held { held, holds } => {
+                write!(f, "the accounts hold {} but the open disputes and holds amount to {}", held, holds)
+            }
+            Violation::LockedChanged { before, after } => {
+                write!(
+                    f,
+                    "locked client {} went from {} available and {} held to {} available and {} held",
+                    before.client_id,
+                    before.available,
+                    before.held,
+                    after.available,
+                    after.held
+                )?;
+
+                match after.locked {
+                    true => Ok(()),
+                    false => f.write_str(", unlocked"),
+                }
+            }
+        }
+    }
+}
+
+impl Error for Violation {}
+
+/// The first violation found by an engine checking its invariants after every transaction, see
+/// [`EngineBuilder::check_invariants`](crate::EngineBuilder::check_invariants).
+#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone)]
+pub struct InvariantViolation {
+    /// The transaction after which the state was found broken, applied or not
+    pub tx: Transaction,
+    pub violation: Violation,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "after transaction {} ({} of client {}): {}",
+            self.tx.tx_id,
+            self.tx.tx_type.name(),
+            self.tx.client_id,
+            self.violation
+        )
+    }
+}
+
+impl Error for InvariantViolation {}
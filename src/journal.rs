@@ -0,0 +1,354 @@
+use std::{
+    fs::{ File, OpenOptions },
+    io::{ self, BufRead, BufReader, Write },
+    path::Path,
+    sync::{ Arc, Mutex },
+};
+
+use serde::Serialize;
+
+use crate::{
+    engine::{ EngineCore, Receipt, TxStatus },
+    error::EngineError,
+    policy::RiskTier,
+    snapshot::Snapshot,
+    types::{ Account, ClientId, Transaction, TransactionType },
+};
+
+/// How many records are written between two fsyncs by default.
+pub const DEFAULT_SYNC_EVERY: usize = 64;
+
+// A record is a line of the JSON Lines input, so a journal can also be read as an input file.
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+    client: ClientId,
+    tx: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_client: Option<ClientId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_currency: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    into: Option<ClientId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<&'a str>,
+}
+
+impl<'a> From<&'a Transaction> for Record<'a> {
+    fn from(tx: &'a Transaction) -> Self {
+        let (to_client, into, reason) = match &tx.tx_type {
+            TransactionType::Transfer { to, .. } => (Some(*to), None, None),
+            TransactionType::Merge(into) => (None, Some(*into), None),
+            TransactionType::Adjustment { reason, .. } => (None, None, Some(reason.as_str())),
+            _ => (None, None, None),
+        };
+
+        Record {
+            tx_type: tx.tx_type.name(),
+            client: tx.client_id,
+            tx: tx.tx_id,
+            // As a string, so the amount keeps its exact scale
+            amount: tx.tx_type.amount().map(|amount| amount.to_string()),
+            to_client,
+            to_currency: match &tx.tx_type {
+                TransactionType::Convert { to, .. } => Some(to.as_str()),
+                _ => None,
+            },
+            into,
+            reason,
+            timestamp: tx.timestamp,
+            currency: tx.currency.as_deref(),
+            seq: tx.seq,
+            tenant: tx.tenant.as_deref(),
+        }
+    }
+}
+
+/// Append-only log of the transactions given to an engine, written before the engine applies
+/// them. Replaying it with the same opening balances and configuration rebuilds the state, the
+/// rejected transactions are rejected again.
+///
+/// Every record reaches the operating system before its transaction is applied, so it survives
+/// the process crashing. The file is fsynced every [`DEFAULT_SYNC_EVERY`] records, a machine
+/// crash can lose the records since the last fsync.
+pub struct Journal {
+    file: File,
+    sync_every: usize,
+    unsynced: usize,
+}
+
+impl Journal {
+    /// Opens the journal at `path` to append to it, creating it if needed. Call [`recover`] first
+    /// to apply what it already holds.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Journal { file, sync_every: DEFAULT_SYNC_EVERY, unsynced: 0 })
+    }
+
+    /// How many records are written between two fsyncs, 1 syncs every record.
+    pub fn sync_every(mut self, count: usize) -> Self {
+        self.sync_every = count.max(1);
+        self
+    }
+
+    pub fn append(&mut self, tx: &Transaction) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&Record::from(tx))?;
+        line.push(b'\n');
+
+        self.file.write_all(&line)?;
+        self.unsynced += 1;
+
+        if self.unsynced >= self.sync_every {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.unsynced = 0;
+        self.file.sync_data()
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        if self.unsynced > 0 {
+            if let Err(err) = self.sync() {
+                log::error!("Failed to sync the journal: {}", err);
+            }
+        }
+    }
+}
+
+/// Reads the transactions of the journal at `path` in order and returns how many bytes the
+/// complete records take. A last record without its newline was cut by a crash, it's skipped.
+pub fn replay<P, F>(path: P, mut apply: F) -> io::Result<u64>
+    where P: AsRef<Path>, F: FnMut(Transaction)
+{
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    let mut complete = 0;
+    let mut number = 0;
+
+    loop {
+        line.clear();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(complete);
+        }
+
+        number += 1;
+
+        if !line.ends_with('\n') {
+            log::warn!("Skipping the incomplete last record of the journal, line {}", number);
+            return Ok(complete);
+        }
+
+        let tx = serde_json::from_str(&line).map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {} of the journal: {}", number, err))
+        })?;
+
+        apply(tx);
+        complete += line.len() as u64;
+    }
+}
+
+/// Applies the transactions of the journal at `path` to the engine, when it exists, and opens it
+/// to append the next ones. An incomplete last record is removed.
+pub fn recover<P: AsRef<Path>, E: EngineCore>(path: P, engine: &mut E) -> io::Result<Journal> {
+    recover_with(path, |tx| engine.apply(tx))
+}
+
+/// Like [`recover`], with `apply` applying every transaction of the journal, e.g. to the engine of
+/// its client among several.
+pub fn recover_with<P, F>(path: P, mut apply: F) -> io::Result<Journal>
+    where P: AsRef<Path>, F: FnMut(Transaction) -> Result<(), EngineError>
+{
+    let path = path.as_ref();
+
+    if path.exists() {
+        let mut count = 0;
+
+        let complete = replay(path, |tx| {
+            count += 1;
+
+            if let Err(err) = apply(tx) {
+                log::debug!("Replayed transaction rejected again: {}", err);
+            }
+        })?;
+
+        OpenOptions::new().write(true).open(path)?.set_len(complete)?;
+
+        log::info!("Replayed {} transactions from the journal", count);
+    }
+
+    Journal::open(path)
+}
+
+/// An engine writing every transaction to its journal before applying it.
+pub struct Journaled<E> {
+    engine: E,
+    journal: Arc<Mutex<Journal>>,
+}
+
+impl<E: EngineCore> Journaled<E> {
+    pub fn new(engine: E, journal: Journal) -> Self {
+        Journaled::shared(engine, Arc::new(Mutex::new(journal)))
+    }
+
+    /// Several engines writing to the same journal, their records are interleaved in the order
+    /// they're written.
+    pub fn shared(engine: E, journal: Arc<Mutex<Journal>>) -> Self {
+        Journaled { engine, journal }
+    }
+
+    fn append(&self, tx: &Transaction) {
+        // The lock is released before panicking, it's never poisoned
+        let appended = self.journal.lock().expect("the journal lock is poisoned").append(tx);
+
+        // A transaction that isn't journaled can't be applied, stopping the engine is what's left
+        if let Err(err) = appended {
+            panic!("failed to write the journal: {}", err);
+        }
+    }
+}
+
+impl<E: EngineCore> EngineCore for Journaled<E> {
+    fn apply(&mut self, tx: Transaction) -> Result<(), EngineError> {
+        self.append(&tx);
+        self.engine.apply(tx)
+    }
+
+    // The engine's own receipt has the balances of the client a merged one resolves to
+    fn submit(&mut self, tx: Transaction) -> Receipt {
+        self.append(&tx);
+        self.engine.submit(tx)
+    }
+
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        self.engine.account(client_id)
+    }
+
+    fn accounts_iter(&self) -> Box<dyn Iterator<Item = Account> + '_> {
+        self.engine.accounts_iter()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.engine.snapshot()
+    }
+
+    fn risk_tier(&self, client_id: ClientId) -> RiskTier {
+        self.engine.risk_tier(client_id)
+    }
+
+    fn tx_status(&self, tx_id: u32) -> Option<TxStatus> {
+        self.engine.tx_status(tx_id)
+    }
+
+    fn changed_clients(&self, tx: &Transaction) -> Vec<ClientId> {
+        self.engine.changed_clients(tx)
+    }
+
+    fn pending(&self) -> Vec<Transaction> {
+        self.engine.pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rust_decimal_macros::dec;
+
+    use crate::Engine;
+
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let txs = [
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(1.2300))).with_timestamp(7),
+            Transaction::new(1, 2, TransactionType::Transfer { to: 2, amount: dec!(1) }),
+            Transaction::new(1, 3, TransactionType::Merge(3)),
+            Transaction::new(1, 4, TransactionType::Adjustment { amount: dec!(-1), reason: "fix".into() }),
+            Transaction { seq: Some(9), currency: Some("EUR".into()), ..Transaction::new(1, 4, TransactionType::Dispute) },
+            Transaction { tenant: Some("acme".into()), ..Transaction::new(1, 5, TransactionType::Deposit(dec!(2))) },
+        ];
+
+        for tx in txs {
+            let line = serde_json::to_string(&Record::from(&tx)).unwrap();
+
+            assert_eq!(serde_json::from_str::<Transaction>(&line).unwrap(), tx);
+        }
+
+        let tx = Transaction::new(2, 5, TransactionType::Withdrawal(dec!(0.50)));
+        let line = serde_json::to_string(&Record::from(&tx)).unwrap();
+        assert_eq!(line, r#"{"type":"withdrawal","client":2,"tx":5,"amount":"0.50"}"#);
+    }
+
+    #[test]
+    fn test_recover() {
+        let path = std::env::temp_dir().join("transaction-engine-journal.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let mut engine = Journaled::new(Engine::new(), recover(&path, &mut Engine::new()).unwrap());
+        engine.apply(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        assert!(engine.apply(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(20)))).is_err());
+        engine.apply(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(4)))).unwrap();
+        drop(engine);
+
+        // A crash in the middle of a record
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"type":"deposit","cli"#).unwrap();
+        drop(file);
+
+        let mut recovered = Engine::new();
+        let mut journal = recover(&path, &mut recovered).unwrap();
+        assert_eq!(recovered.get_account(1).unwrap().available, dec!(6));
+
+        journal.append(&Transaction::new(1, 4, TransactionType::Deposit(dec!(1)))).unwrap();
+        drop(journal);
+
+        let mut count = 0;
+        replay(&path, |_| count += 1).unwrap();
+        assert_eq!(count, 4);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_submit_merged_client() {
+        let path = std::env::temp_dir().join("transaction-engine-journal-submit.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let mut engine = Journaled::new(Engine::new(), Journal::open(&path).unwrap());
+        engine.apply(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.apply(Transaction::new(2, 2, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.apply(Transaction::new(1, 3, TransactionType::Merge(2))).unwrap();
+
+        // Client 1 is now client 2, the receipt has its balances
+        let receipt = engine.submit(Transaction::new(1, 4, TransactionType::Deposit(dec!(1))));
+        assert_eq!(receipt.client_id, 2);
+        assert_eq!(receipt.available_after, dec!(16));
+        drop(engine);
+
+        let mut count = 0;
+        replay(&path, |_| count += 1).unwrap();
+        assert_eq!(count, 4);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,318 @@
+use std::{ fmt, fs, path::PathBuf, time::Duration };
+
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{ CommitMode, Consumer, StreamConsumer },
+    error::KafkaError,
+    message::{ BorrowedMessage, Message },
+    producer::{ BaseRecord, DefaultProducerContext, Producer, ThreadedProducer },
+};
+use tokio::time::{ self, MissedTickBehavior };
+
+use crate::{
+    checkpoint::Checkpoint,
+    dead_letter::{ DeadLetter, DeadLetterQueue, DeadLetterSink },
+    engine::{ Engine, EngineBuilder, EngineCore },
+    listener,
+    metrics::PrometheusRecorder,
+    provenance::Provenance,
+    reason::ReasonCode,
+    snapshot::SnapshotError,
+    store::StateStore,
+    throttle::{ RateLimiter, RateLimits },
+    types::Transaction,
+};
+
+// How long a dropped dead letter producer waits for its messages to be delivered
+const DEAD_LETTER_FLUSH: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct KafkaSource {
+    pub brokers: String,
+    pub group: String,
+    pub topic: String,
+    /// Where the accounts and the transaction history are checkpointed, the offsets are then only
+    /// committed along with them
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    /// A message over the limits waits for its turn, a topic can't turn it away
+    pub rate_limits: RateLimits,
+    /// Where the metrics are written every interval, in the Prometheus text format
+    pub metrics: Option<PathBuf>,
+    /// Where the messages that can't be parsed or that the engine rejected go, their offsets are
+    /// committed like the others
+    pub dead_letters: DeadLetterQueue,
+}
+
+#[derive(Debug)]
+pub enum ConsumerError {
+    Kafka(KafkaError),
+    Snapshot(SnapshotError),
+}
+
+impl fmt::Display for ConsumerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsumerError::Kafka(err) => write!(f, "kafka: {}", err),
+            ConsumerError::Snapshot(err) => write!(f, "could not write the checkpoint: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConsumerError {}
+
+impl From<KafkaError> for ConsumerError {
+    fn from(err: KafkaError) -> Self {
+        ConsumerError::Kafka(err)
+    }
+}
+
+impl From<SnapshotError> for ConsumerError {
+    fn from(err: SnapshotError) -> Self {
+        ConsumerError::Snapshot(err)
+    }
+}
+
+/// A message holds one transaction in the JSON form of the JSON Lines input.
+pub fn decode(payload: Option<&[u8]>) -> Result<Transaction, (ReasonCode, String)> {
+    let payload = payload.ok_or((ReasonCode::Malformed, "empty message".to_string()))?;
+
+    serde_json::from_slice::<Transaction>(payload).map_err(|err| {
+        let value = serde_json::from_slice::<serde_json::Value>(payload).ok();
+
+        listener::unparsed(value.as_ref().and_then(|value| value.get("type")?.as_str()), err)
+    })
+}
+
+/// Produces every dead letter as a JSON message to a topic, keyed by its source. The messages are
+/// delivered in the background, the ones still queued are given a few seconds when it's dropped.
+pub struct KafkaDeadLetters {
+    producer: ThreadedProducer<DefaultProducerContext>,
+    topic: String,
+}
+
+impl KafkaDeadLetters {
+    pub fn new(brokers: &str, topic: &str) -> Result<Self, KafkaError> {
+        let producer = ClientConfig::new().set("bootstrap.servers", brokers).create()?;
+
+        Ok(KafkaDeadLetters { producer, topic: topic.to_string() })
+    }
+}
+
+impl DeadLetterSink for KafkaDeadLetters {
+    fn send(&self, letter: &DeadLetter) {
+        let payload = match serde_json::to_vec(letter) {
+            Ok(payload) => payload,
+            Err(err) => {
+                log::error!("Failed to serialize the dead letter from {}: {}", letter.source, err);
+                return;
+            }
+        };
+
+        let record = BaseRecord::to(&self.topic).key(&letter.source).payload(&payload);
+
+        if let Err((err, _)) = self.producer.send(record) {
+            log::error!("Failed to produce the dead letter from {} to {}: {}", letter.source, self.topic, err);
+        }
+    }
+}
+
+impl Drop for KafkaDeadLetters {
+    fn drop(&mut self) {
+        if let Err(err) = self.producer.flush(DEAD_LETTER_FLUSH) {
+            log::error!("Failed to deliver the dead letters to {}: {}", self.topic, err);
+        }
+    }
+}
+
+impl KafkaSource {
+    fn consumer(&self) -> Result<StreamConsumer, KafkaError> {
+        // Offsets are only stored once the engine applied the message, a restart then resumes
+        // with the first message it didn't apply
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &self.brokers)
+            .set("group.id", &self.group)
+            .set("enable.auto.commit", "false")
+            .set("enable.auto.offset.store", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+
+        consumer.subscribe(&[&self.topic])?;
+
+        Ok(consumer)
+    }
+
+    /// The builder with the engine of the last checkpoint, when there's one: its accounts and the
+    /// history the disputes and the duplicate checks of the messages after it need.
+    pub fn restore(&self, builder: EngineBuilder) -> Result<EngineBuilder, SnapshotError> {
+        let Some(dir) = self.checkpoint.as_ref().filter(|dir| Checkpoint::exists(dir)) else {
+            return Ok(builder);
+        };
+
+        let checkpoint = Checkpoint::load(dir)?;
+
+        log::info!("Restoring the engine as of {}", checkpoint.position);
+
+        Ok(
+            builder
+                .opening_balances(checkpoint.snapshot.accounts)
+                .deleted_accounts(checkpoint.snapshot.deleted)
+                .risk_tiers(checkpoint.snapshot.tiers)
+                .scheduled_releases(checkpoint.snapshot.scheduled)
+                .history(checkpoint.history)
+        )
+    }
+
+    /// Applies the messages of the topic as they arrive, until the consumer fails. With a
+    /// checkpoint, the engine and the offsets are flushed together every interval, so after a crash
+    /// the engine restored from the checkpoint resumes from the offsets that match it.
+    pub async fn run<S: StateStore>(&self, mut engine: Engine<S>) -> Result<(), ConsumerError> {
+        let consumer = self.consumer()?;
+        let mut flush = time::interval(self.checkpoint_interval);
+        // The last message applied and not committed yet
+        let mut uncommitted = None;
+        let metrics = PrometheusRecorder::new();
+        let limiter = self.rate_limits
+            .is_enabled()
+            .then(|| RateLimiter::new(self.rate_limits, Box::new(metrics.clone())));
+
+        flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        log::info!("Consuming {} from {}", self.topic, self.brokers);
+
+        loop {
+            tokio::select! {
+                message = consumer.recv() => {
+                    let message = message?;
+
+                    apply(&mut engine, &message, limiter.as_ref(), &self.dead_letters).await;
+
+                    match self.checkpoint {
+                        Some(_) => {
+                            consumer.store_offset_from_message(&message)?;
+                            uncommitted = Some(Provenance {
+                                source: format!("{}:{}", message.topic(), message.partition()).into(),
+                                line: 0,
+                                offset: message.offset().max(0) as u64,
+                            });
+                        }
+                        None => consumer.commit_message(&message, CommitMode::Async)?,
+                    }
+                }
+                _ = flush.tick(), if uncommitted.is_some() || self.metrics.is_some() => {
+                    if let Some(position) = uncommitted.take() {
+                        if let Some(dir) = &self.checkpoint {
+                            Checkpoint::capture(&engine, position).save(dir)?;
+                        }
+
+                        consumer.commit_consumer_state(CommitMode::Sync)?;
+                    }
+
+                    if let Some(path) = &self.metrics {
+                        if let Err(err) = fs::write(path, metrics.render()) {
+                            log::warn!("Failed to write the metrics to {}: {}", path.display(), err);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A message that can't be applied is logged, sent to the dead letters and skipped like a rejected
+// row, it would be rejected again on every retry
+async fn apply<E: EngineCore>(
+    engine: &mut E,
+    message: &BorrowedMessage<'_>,
+    limiter: Option<&RateLimiter>,
+    dead_letters: &DeadLetterQueue
+) {
+    let position = format!("{}:{}@{}", message.topic(), message.partition(), message.offset());
+    let payload = message.payload().unwrap_or_default();
+
+    match decode(message.payload()) {
+        Ok(tx) => {
+            let tx_id = tx.tx_id;
+
+            if let Some(limiter) = limiter {
+                limiter.acquire(tx.client_id).await;
+            }
+
+            if let Err(err) = engine.apply(tx) {
+                log::info!("Rejected transaction {} at {}: {}", tx_id, position, err);
+
+                dead_letters.send(DeadLetter::new(position, err.reason(), &err, payload));
+            }
+        }
+        Err((reason, err)) => {
+            log::error!("Failed to parse the message at {}: {} {}", position, reason, err);
+
+            dead_letters.send(DeadLetter::new(position, reason, err, payload));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{ error::EngineError, types::TransactionType };
+
+    #[test]
+    fn test_decode() {
+        let payload = br#"{"type":"deposit","client":1,"tx":2,"amount":"1.5","timestamp":100}"#;
+        let tx = decode(Some(payload)).unwrap();
+
+        assert_eq!(tx.tx_type, TransactionType::Deposit(dec!(1.5)));
+        assert_eq!((tx.client_id, tx.tx_id, tx.timestamp), (1, 2, Some(100)));
+
+        let reject = |payload| decode(payload).unwrap_err().0;
+
+        assert_eq!(reject(Some(br#"{"type":"refund","client":1,"tx":2}"#)), ReasonCode::UnknownType);
+        assert_eq!(reject(Some(br#"{"type":"deposit","client":1}"#)), ReasonCode::Malformed);
+        assert_eq!(reject(Some(b"deposit,1,2,1.5")), ReasonCode::Malformed);
+        assert_eq!(reject(None), ReasonCode::Malformed);
+    }
+
+    #[test]
+    fn test_restart_with_open_dispute() {
+        let dir = env::temp_dir().join("transaction-engine-kafka-checkpoint");
+        let _ = fs::remove_dir_all(&dir);
+
+        let source = KafkaSource {
+            brokers: "localhost:9092".to_string(),
+            group: "transaction-engine".to_string(),
+            topic: "transactions".to_string(),
+            checkpoint: Some(dir.clone()),
+            checkpoint_interval: Duration::from_secs(60),
+            rate_limits: RateLimits::default(),
+            metrics: None,
+            dead_letters: DeadLetterQueue::default(),
+        };
+
+        // Nothing to restore before the first checkpoint
+        assert!(source.restore(Engine::builder()).unwrap().build().get_account(1).is_none());
+
+        let mut engine = Engine::new();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+
+        let position = Provenance { source: "transactions:0".into(), line: 0, offset: 1 };
+        Checkpoint::capture(&engine, position).save(&dir).unwrap();
+
+        let mut restarted = source.restore(Engine::builder()).unwrap().build();
+
+        assert_eq!(restarted.get_account(1).unwrap().held, dec!(10));
+        assert_eq!(
+            restarted.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(1)))),
+            Err(EngineError::DuplicateTxId(1))
+        );
+        assert_eq!(restarted.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback)), Ok(()));
+
+        let account = restarted.get_account(1).unwrap();
+        assert_eq!((account.held, account.total, account.locked), (dec!(0), dec!(0), true));
+    }
+}
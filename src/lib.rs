@@ -0,0 +1,102 @@
+//! Processes client transactions (deposits, withdrawals, disputes, ...) into account balances.
+//!
+//! The [`Engine`] is configured with an [`EngineBuilder`], fed one [`Transaction`] at a time with
+//! [`Engine::add_transaction`] or a batch at a time with [`Engine::add_transactions`], and drained
+//! into the resulting [`Account`]s with [`Engine::get_accounts`]. [`Engine::get_account`],
+//! [`Engine::accounts_iter`] and [`Engine::account_count`] inspect the accounts without consuming
+//! it:
+//!
+//! ```
+//! use rust_decimal_macros::dec;
+//! use transaction_engine::{ Engine, Transaction, TransactionType };
+//!
+//! let mut engine = Engine::builder().allow_negative_balances().build();
+//!
+//! engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+//! engine.add_transaction(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(4)))).unwrap();
+//! assert!(engine.add_transaction(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(7)))).is_err());
+//!
+//! let accounts = engine.get_accounts();
+//! assert_eq!(accounts[0].available, dec!(6));
+//! ```
+//!
+//! Transactions can also be read from CSV with the `csv` crate, as they implement `Deserialize`
+//! for the `type,client,tx,amount` format used by the command line tool.
+
+pub mod alert;
+pub mod audit;
+pub mod balance_bounds;
+pub mod checkpoint;
+pub mod clock;
+pub mod config;
+pub mod credit;
+pub mod currency;
+pub mod dead_letter;
+pub mod deficit;
+pub mod delivery;
+pub mod disputes;
+pub mod dormancy;
+pub mod engine;
+pub mod error;
+pub mod event_log;
+pub mod events;
+pub mod exposure;
+pub mod fees;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hooks;
+pub mod ingest;
+pub mod invariants;
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod listener;
+pub mod manifest;
+pub mod metrics;
+pub mod normalized;
+#[cfg(feature = "object-store")]
+pub mod object_input;
+pub mod observer;
+pub mod opening_balances;
+pub mod ordering;
+pub mod output;
+pub mod pending;
+pub mod policy;
+pub mod progress;
+pub mod provenance;
+pub mod reason;
+pub mod redis_cache;
+pub mod regress;
+pub mod rejects;
+pub mod retention;
+pub mod risk;
+pub mod rollup;
+pub mod scenario;
+pub mod scheduler;
+pub mod schema;
+pub mod server;
+pub mod shard;
+pub mod shadow;
+pub mod simulation;
+#[cfg(feature = "sled")]
+pub mod sled_store;
+pub mod snapshot;
+pub mod spill;
+pub mod statement;
+pub mod store;
+pub mod system;
+pub mod tenant;
+pub mod throttle;
+pub mod throughput;
+pub mod transform;
+pub mod types;
+pub mod unknown_types;
+pub mod validation;
+pub mod webhook;
+
+pub use engine::{ Engine, EngineBuilder, EngineCore, Receipt, TxStatus };
+pub use error::{ EngineError, PipelineError };
+pub use hooks::EngineHooks;
+pub use reason::ReasonCode;
+pub use store::{ MemoryStore, StateStore };
+pub use types::{ Account, ClientId, Transaction, TransactionType };
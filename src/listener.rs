@@ -0,0 +1,284 @@
+use std::{ fmt, io, path::PathBuf, str::FromStr };
+
+use csv::{ ReaderBuilder, StringRecord, Trim };
+use tokio::{
+    io::{ AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader },
+    net::TcpListener,
+};
+
+use crate::{
+    dead_letter::DeadLetter,
+    ingest::InputFormat,
+    reason::ReasonCode,
+    schema,
+    server::EngineHandle,
+    types::{ Transaction, TransactionType },
+};
+
+/// A TCP address, or a Unix domain socket path prefixed with `unix:`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("missing socket path after unix:".to_string()),
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => Ok(ListenAddress::Tcp(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => f.write_str(address),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Accepts connections until the listener fails, each one is served by its own task. The
+/// transactions of all of them go to the same engine, in the order they are read.
+pub async fn listen(
+    address: &ListenAddress,
+    format: InputFormat,
+    engine: EngineHandle
+) -> io::Result<()> {
+    match address {
+        ListenAddress::Tcp(address) => {
+            let listener = TcpListener::bind(address).await?;
+
+            log::info!("Listening for transactions on {}", listener.local_addr()?);
+
+            loop {
+                let (stream, peer) = listener.accept().await?;
+
+                tokio::spawn(connection(stream, peer.to_string(), format, engine.clone()));
+            }
+        }
+        #[cfg(unix)]
+        ListenAddress::Unix(path) => {
+            let listener = tokio::net::UnixListener::bind(path)?;
+
+            log::info!("Listening for transactions on {}", address);
+
+            let mut connections = 0;
+
+            loop {
+                let (stream, _) = listener.accept().await?;
+                connections += 1;
+
+                let peer = format!("{}#{}", address, connections);
+
+                tokio::spawn(connection(stream, peer, format, engine.clone()));
+            }
+        }
+        #[cfg(not(unix))]
+        ListenAddress::Unix(_) => {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets are not supported"))
+        }
+    }
+}
+
+async fn connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    peer: String,
+    format: InputFormat,
+    engine: EngineHandle
+) {
+    log::info!("Connection from {}", peer);
+
+    match serve(stream, &peer, format, engine).await {
+        Ok(()) => log::info!("Connection from {} closed", peer),
+        Err(err) => log::warn!("Connection from {} failed: {}", peer, err),
+    }
+}
+
+// Answers every transaction line with `ok`, or with its reason code and the error. A CSV
+// connection starts with its header line, like a CSV file, and it isn't answered. The lines that
+// aren't applied go to the dead letters of the engine too, from `<peer> line <n>`.
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    peer: &str,
+    format: InputFormat,
+    engine: EngineHandle
+) -> io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut headers = None;
+    let mut line_number = 0;
+
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let parsed = match (format, &headers) {
+            (InputFormat::Json, _) => parse_json(&line),
+            (InputFormat::Csv, Some(headers)) => parse_csv(&line, headers),
+            (InputFormat::Csv, None) => {
+                headers = Some(schema::normalize(&read_record(&line).map_err(invalid_data)?));
+                continue;
+            }
+        };
+
+        let source = || format!("{} line {}", peer, line_number);
+
+        let reply = match parsed {
+            Ok(tx) => match engine.submit(tx).await.map_err(invalid_data)?.outcome {
+                Ok(_) => "ok".to_string(),
+                Err(err) => {
+                    engine.dead_letter(DeadLetter::new(source(), err.reason(), &err, line.as_bytes()));
+
+                    format!("{} {}", err.reason(), err)
+                }
+            },
+            Err((reason, err)) => {
+                engine.dead_letter(DeadLetter::new(source(), reason, &err, line.as_bytes()));
+
+                format!("{} line {}: {}", reason, line_number, err)
+            }
+        };
+
+        writer.write_all(reply.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+
+    writer.flush().await
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn read_record(line: &str) -> csv::Result<StringRecord> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).has_headers(false).from_reader(line.as_bytes());
+
+    reader.records().next().unwrap_or_else(|| Ok(StringRecord::new()))
+}
+
+type Parsed = Result<Transaction, (ReasonCode, String)>;
+
+fn parse_csv(line: &str, headers: &StringRecord) -> Parsed {
+    let record = read_record(line).map_err(|err| (ReasonCode::Malformed, err.to_string()))?;
+
+    record.deserialize::<Transaction>(Some(headers)).map_err(|err| {
+        let tx_type = headers
+            .iter()
+            .position(|header| header == "type")
+            .and_then(|column| record.get(column));
+
+        // The position in the error is the one within the line, it's useless to the client
+        match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => unparsed(tx_type, err),
+            _ => unparsed(tx_type, err),
+        }
+    })
+}
+
+fn parse_json(line: &str) -> Parsed {
+    serde_json::from_str::<Transaction>(line).map_err(|err| {
+        let value = serde_json::from_str::<serde_json::Value>(line).ok();
+        let tx_type = value.as_ref().and_then(|value| value.get("type")?.as_str());
+
+        unparsed(tx_type, err)
+    })
+}
+
+pub(crate) fn unparsed(tx_type: Option<&str>, err: impl fmt::Display) -> (ReasonCode, String) {
+    match tx_type {
+        Some(tx_type) if !TransactionType::NAMES.contains(&tx_type) => {
+            (ReasonCode::UnknownType, format!("unknown transaction type {}", tx_type))
+        }
+        _ => (ReasonCode::Malformed, err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use tokio::io::{ duplex, AsyncReadExt };
+
+    use crate::{ dead_letter::{ tests::Recorder, DeadLetterQueue }, engine::Engine };
+
+    use super::*;
+
+    async fn exchange(engine: &EngineHandle, format: InputFormat, input: &str) -> String {
+        let (mut client, server) = duplex(1024);
+        let connection = tokio::spawn(serve(server, "peer", format, engine.clone()));
+
+        client.write_all(input.as_bytes()).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        connection.await.unwrap().unwrap();
+
+        output
+    }
+
+    #[tokio::test]
+    async fn test_connections() {
+        let recorder = Recorder::default();
+        let engine = EngineHandle::spawn(Engine::new())
+            .dead_letters(DeadLetterQueue::new(vec![Box::new(recorder.clone())]));
+
+        assert_eq!(
+            exchange(
+                &engine,
+                InputFormat::Csv,
+                "type, client, tx, amount\n\
+                 deposit, 1, 1, 2.5\n\
+                 \n\
+                 withdrawal, 1, 2, 3\n\
+                 refund, 1, 3, 1\n\
+                 deposit, 1\n"
+            ).await,
+            "ok\n\
+             INSUFFICIENT_FUNDS client 1 has 2.5 available but 3 is required\n\
+             UNKNOWN_TYPE line 5: unknown transaction type refund\n\
+             MALFORMED line 6: expected field, but got end of row\n"
+        );
+
+        // A second connection applies to the same engine
+        let output = exchange(
+            &engine,
+            InputFormat::Json,
+            "{\"type\":\"withdrawal\",\"client\":1,\"tx\":4,\"amount\":\"2\"}\n{\"type\":\"deposit\"}\n"
+        ).await;
+
+        assert!(output.starts_with("ok\nMALFORMED line 2: "));
+        assert_eq!(engine.query_account(1).await.unwrap().unwrap().account.available, dec!(0.5));
+
+        let letters = recorder.letters.lock().unwrap();
+        let letters: Vec<_> = letters
+            .iter()
+            .map(|letter| (letter.source.as_str(), letter.reason, letter.payload.as_str()))
+            .collect();
+
+        assert_eq!(letters, vec![
+            ("peer line 4", ReasonCode::InsufficientFunds, "withdrawal, 1, 2, 3"),
+            ("peer line 5", ReasonCode::UnknownType, "refund, 1, 3, 1"),
+            ("peer line 6", ReasonCode::Malformed, "deposit, 1"),
+            ("peer line 2", ReasonCode::Malformed, "{\"type\":\"deposit\"}"),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!("127.0.0.1:7000".parse(), Ok(ListenAddress::Tcp("127.0.0.1:7000".to_string())));
+        assert_eq!(
+            "unix:/tmp/engine.sock".parse(),
+            Ok(ListenAddress::Unix(PathBuf::from("/tmp/engine.sock")))
+        );
+        assert!("unix:".parse::<ListenAddress>().is_err());
+    }
+}
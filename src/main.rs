@@ -1,66 +1,1813 @@
-use std::env;
+use std::{
+    collections::{ BTreeMap, HashMap, HashSet },
+    env,
+    error::Error,
+    ffi::OsString,
+    fs::{ self, File },
+    future::{ self, Future },
+    io::{ self, BufWriter },
+    iter,
+    path::{ Path, PathBuf },
+    pin::Pin,
+    process,
+    sync::{ Arc, Mutex },
+    thread,
+    time::{ Duration, Instant },
+};
 
-use csv::{ ReaderBuilder, Trim };
-use engine::Engine;
-use tokio::{ io::{ stdout, AsyncWriteExt }, join, spawn, sync::mpsc };
-use types::Transaction;
+use clap::{ parser::ValueSource, CommandFactory, Parser };
+use cli::{ Cli, ClockSource, Command, DeadLetterArgs, ListenArgs, PendingAction, ServeArgs };
+#[cfg(feature = "kafka")]
+use cli::ConsumeArgs;
+use settings::EngineSettings;
+use rust_decimal::Decimal;
+use tokio::{ io::{ stdout, AsyncWriteExt }, spawn, sync::mpsc };
+use transaction_engine::{
+    alert,
+    audit::{ AuditSink, AuditWriter, RotatingFile },
+    balance_bounds,
+    checkpoint::Checkpoint,
+    clock::{ Clock, SystemClock },
+    config::Config,
+    credit,
+    currency::{ conversion::ConversionTable, MultiCurrency },
+    dead_letter::{ DeadLetterFile, DeadLetterQueue, DeadLetterSink },
+    deficit,
+    disputes,
+    dormancy,
+    error::PipelineError,
+    events::EventWriter,
+    exposure::ExposureSummary,
+    ingest::{ self, Compression, InputFormat, Overflowed, ReadBounds, Tuning },
+    journal::{ self, Journaled },
+    listener,
+    manifest::{ Manifest, OutputFile },
+    metrics::{ MetricsRecorder, PrometheusRecorder },
+    normalized::NormalizedFile,
+    observer::LogObserver,
+    opening_balances,
+    ordering::{ SequenceGuard, SequenceSummary },
+    output::{ self, ClientSelection, ExtendedColumns, OutputFormat },
+    pending::{ PendingError, PendingQueue, Review },
+    policy::{ QuarantineAbove, RiskTier },
+    progress::{ Progress, RunSummary },
+    provenance::{ Provenance, SourceStats, Tagged },
+    redis_cache::RedisCache,
+    regress,
+    rejects::RejectsFile,
+    retention::Retention,
+    rollup::DailyRollup,
+    scenario::Scenario,
+    server::{ self, EngineHandle },
+    shadow::{ self, Shadow },
+    shard::{ self, ShardedEngine },
+    simulation,
+    snapshot::Snapshot,
+    statement,
+    store::{ MemoryStore, StateStore },
+    tenant::{ self, MultiTenant },
+    throttle::{ Replay, TokenBucket },
+    throughput::Throughput,
+    transform::Transform,
+    types::custom_serde::{ self, Rounding },
+    unknown_types::{ UnknownTypeFile, UnknownTypeHandler },
+    webhook::WebhookObserver,
+    Account,
+    ClientId,
+    Engine,
+    EngineError,
+    ReasonCode,
+    Transaction,
+};
+#[cfg(feature = "sled")]
+use transaction_engine::sled_store::SledStore;
 
-mod engine;
-mod types;
+mod cli;
+mod settings;
 
-const BUFFER_SIZE: usize = 100;
+// A run stopped by SIGINT or SIGTERM, after writing the accounts of the transactions it applied
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+// With --detailed-exit-codes, a complete run without failures that rejected transactions, the code
+// of the engine kind, or that skipped rows it couldn't parse
+const REJECTED_EXIT_CODE: i32 = 3;
+const UNPARSED_EXIT_CODE: i32 = 6;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Failures that don't stop the run, e.g. a report that couldn't be written. They are logged and
+// counted in `pipeline_errors_total`, and the run exits with the code of the first one.
+struct Failures {
+    metrics: PrometheusRecorder,
+    exit_code: i32,
+}
+
+impl Failures {
+    fn new(metrics: PrometheusRecorder) -> Self {
+        Failures { metrics, exit_code: 0 }
+    }
+
+    fn record(&mut self, err: PipelineError) {
+        log::error!("{}", err);
+
+        self.count(&err);
+
+        if self.exit_code == 0 {
+            self.exit_code = err.exit_code();
+        }
+    }
+
+    // Rejections are expected in the input, they are counted but don't change the exit code
+    fn reject(&mut self, err: EngineError) {
+        self.count(&PipelineError::Engine(err));
+    }
+
+    fn count(&mut self, err: &PipelineError) {
+        self.metrics.increment_counter("pipeline_errors_total", &[("kind", err.kind())], 1);
+    }
+}
+
+// The reports every result of the engine goes to
+struct Outcomes {
+    rejected: BTreeMap<ReasonCode, usize>,
+    sources: SourceStats,
+    throughput: Option<Throughput>,
+    rejects: Option<RejectsFile<File>>,
+    normalized: Option<NormalizedFile<File>>,
+}
+
+impl Outcomes {
+    // Only the rejects file, the normalized transactions and the throughput report need the
+    // transaction
+    fn needs_transaction(&self) -> bool {
+        self.rejects.is_some() || self.normalized.is_some() || self.throughput.is_some()
+    }
+
+    fn record(
+        &mut self,
+        failures: &mut Failures,
+        provenance: &Provenance,
+        tx: Option<&Transaction>,
+        result: Result<(), EngineError>
+    ) {
+        let result = result.map_err(|err| {
+            log::info!("Rejected transaction from {}: {}", provenance, err);
+
+            if let (Some(rejects), Some(tx)) = (&mut self.rejects, tx) {
+                rejects.reject_transaction(provenance, tx, &err);
+            }
+
+            let reason = err.reason();
+            failures.reject(err);
+            reason
+        });
+
+        match result {
+            Ok(()) => {
+                if let (Some(normalized), Some(tx)) = (&mut self.normalized, tx) {
+                    normalized.write(provenance, tx);
+                }
+            }
+            Err(reason) => *self.rejected.entry(reason).or_default() += 1,
+        }
+
+        if let (Some(throughput), Some(tx)) = (&mut self.throughput, tx) {
+            throughput.record(tx, result);
+        }
+
+        self.sources.record(provenance, result);
+    }
+
+    // A row that couldn't be parsed, it's counted as malformed whatever its reason code
+    fn record_unparsed(&mut self, provenance: &Provenance, reason: ReasonCode) {
+        *self.rejected.entry(reason).or_default() += 1;
+        self.sources.record_unparsed(provenance);
+    }
+}
+
+fn save_checkpoint<S: StateStore>(engine: &Engine<S>, dir: &Path, position: Provenance, failures: &mut Failures) {
+    match Checkpoint::capture(engine, position).save(dir) {
+        Ok(()) => log::info!("Saved a checkpoint to {}", dir.display()),
+        Err(err) => failures.record(PipelineError::storage("Failed to write the checkpoint", err)),
+    }
+}
+
+// Errors before any transaction is processed, e.g. the input can't be opened, end the run.
+fn fatal(err: PipelineError) -> ! {
+    log::error!("{}", err);
+    std::process::exit(err.exit_code());
+}
+
+// Parses the arguments again with the settings of the config file in front of them, leaving out the
+// ones given on the command line so they take precedence. Returns the arguments parsed too
+fn with_config(path: &Path) -> (Cli, Vec<OsString>) {
+    let config = Config::load(path)
+        .unwrap_or_else(|err| fatal(PipelineError::input("Could not load the config file", err)));
+    let command = Cli::command();
+
+    for key in config.keys() {
+        let flag = key.replace('_', "-");
+        let known = command.get_arguments().any(|arg| arg.get_long() == Some(flag.as_str()));
+
+        if !known || ["config", "help", "version"].contains(&key) {
+            fatal(PipelineError::input("Invalid config file", format!("unknown setting {}", key)));
+        }
+    }
+
+    let matches = command.get_matches();
+    let given = |key: &str| matches.value_source(key) == Some(ValueSource::CommandLine);
+
+    let mut args = env::args_os();
+    let program = args.next().unwrap_or_default();
+    let settings = config.args(given).into_iter().map(OsString::from);
+    let args: Vec<OsString> = iter::once(program).chain(settings).chain(args).collect();
+
+    (Cli::parse_from(&args), args)
+}
 
 #[tokio::main]
 async fn main() {
-    let file = env::args().nth(1).expect("Specify the csv file");
+    let mut cli = Cli::parse();
+    let mut run_args: Vec<OsString> = env::args_os().collect();
 
     env_logger::init();
 
+    if let Some(path) = cli.config.clone() {
+        (cli, run_args) = with_config(&path);
+    }
+
+    match cli.command.take() {
+        Some(command) => run_command(command).await,
+        None => run(cli, run_args).await,
+    }
+}
+
+async fn run_command(command: Command) {
+    match command {
+        Command::Pending { action } => {
+            if let Err(err) = review_pending(action) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::ServeSnapshot { snapshot, listen } => serve_snapshot(snapshot, listen).await,
+        Command::Serve(args) => serve(args).await,
+        #[cfg(feature = "kafka")]
+        Command::Consume(args) => consume(args).await,
+        Command::Listen(args) => listen(args).await,
+        Command::Replay { journal, opening_balances, snapshot } => replay(journal, opening_balances, snapshot).await,
+        Command::Regress { baseline_bin, candidate_bin, input, args } => {
+            match regress(baseline_bin, candidate_bin, input, args) {
+                Ok(true) => (),
+                Ok(false) => std::process::exit(1),
+                Err(err) => fatal(err),
+            }
+        }
+        Command::Reconcile { old, new } => {
+            match reconcile(old, new) {
+                Ok(true) => (),
+                Ok(false) => std::process::exit(1),
+                Err(err) => fatal(err),
+            }
+        }
+        Command::Tune { file, sample } => tune(file, sample).await,
+        Command::Scenario { file } => {
+            if !run_scenario(file).await {
+                std::process::exit(1);
+            }
+        }
+        Command::Simulate { seed, transactions, clients } => {
+            match simulation::simulate(seed, transactions, clients) {
+                Ok(report) => println!("{}", report),
+                Err(divergence) => {
+                    eprintln!("{}", divergence);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+async fn serve_snapshot(snapshot: PathBuf, listen: String) -> ! {
+    let snapshot = Snapshot::load(&snapshot).unwrap_or_else(|err| {
+        eprintln!("Could not load the snapshot: {}", err);
+        std::process::exit(1);
+    });
+
+    if let Err(err) = server::serve(listen, server::snapshot_router(snapshot), shutdown_signal()).await {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    std::process::exit(INTERRUPTED_EXIT_CODE);
+}
+
+async fn serve(args: ServeArgs) -> ! {
+    custom_serde::set_strict_amounts(args.strict_amounts);
+
+    let engine = spawn_live(args.opening_balances, args.journal, args.quarantine_above, args.workers)
+        .limit_rate(args.rate_limits.into())
+        .dead_letters(dead_letter_queue(args.dead_letters, None));
+
+    #[cfg(feature = "grpc")]
+    if let Some(address) = args.grpc_listen {
+        let engine = engine.clone();
+
+        spawn(async move {
+            if let Err(err) = transaction_engine::grpc::serve(address, engine).await {
+                log::error!("gRPC server failed: {}", err);
+                std::process::exit(1);
+            }
+        });
+    }
+
+    if let Err(err) = server::serve(args.listen, server::live_router(engine.clone()), shutdown_signal()).await {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    shut_down(engine, args.snapshot).await;
+}
+
+#[cfg(feature = "kafka")]
+async fn consume(args: ConsumeArgs) {
+    custom_serde::set_strict_amounts(args.strict_amounts);
+
+    let dead_letters = dead_letter_queue(args.dead_letters, Some(&args.brokers));
+
+    let source = transaction_engine::kafka::KafkaSource {
+        brokers: args.brokers,
+        group: args.group,
+        topic: args.topic,
+        checkpoint: args.checkpoint,
+        checkpoint_interval: Duration::from_secs(args.checkpoint_interval),
+        rate_limits: args.rate_limits.into(),
+        metrics: args.metrics,
+        dead_letters,
+    };
+
+    let builder = source
+        .restore(Engine::builder().observer(Box::new(LogObserver)))
+        .unwrap_or_else(|err| fatal(PipelineError::input("Could not load the checkpoint to restore", err)));
+
+    if let Err(err) = source.run(builder.build()).await {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn listen(args: ListenArgs) -> ! {
+    custom_serde::set_strict_amounts(args.strict_amounts);
+
+    let engine = spawn_live(args.opening_balances, args.journal, None, 1)
+        .dead_letters(dead_letter_queue(args.dead_letters, None));
+
+    let result = tokio::select! {
+        result = listener::listen(&args.listen, args.format, engine.clone()) => result,
+        () = shutdown_signal() => Ok(()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    shut_down(engine, args.snapshot).await;
+}
+
+// The combinations of flags a batch run can't honour, checked before anything is read
+fn check_flags(cli: &Cli) {
+    // The system accounts are a second CSV section, there's no room for them in a JSON document
+    if cli.system_accounts && cli.output_format != OutputFormat::Csv {
+        fatal(PipelineError::input("Invalid flags", "--system-accounts requires --output-format csv"));
+    }
+
+    if cli.fee_summary && cli.output_format != OutputFormat::Csv {
+        fatal(PipelineError::input("Invalid flags", "--fee-summary requires --output-format csv"));
+    }
+
+    // A Parquet file is written whole and only has the columns of a single account table
+    #[cfg(feature = "parquet")]
+    if cli.output_format == OutputFormat::Parquet && (cli.multi_currency || cli.multi_tenant || cli.stream_output) {
+        fatal(PipelineError::input(
+            "Invalid flags",
+            "--output-format parquet can't be combined with --multi-currency, --multi-tenant or --stream-output"
+        ));
+    }
+
+    // A checkpoint is only consistent when the records are applied one after the other
+    if (cli.checkpoint.is_some() || cli.resume.is_some()) && (cli.workers > 1 || cli.tuning.shards > 1) {
+        fatal(PipelineError::input("Invalid flags", "--checkpoint and --resume need a single worker and shard"));
+    }
+
+    if cli.audit_log.as_deref() == Some(Path::new("-")) && cli.output.is_none() {
+        fatal(PipelineError::input("Invalid flags", "--audit-log - requires --output, the accounts go to stdout"));
+    }
+}
+
+// A batch run over the input files, or stdin
+async fn run(cli: Cli, run_args: Vec<OsString>) {
     log::info!("Starting...");
 
-    let (tx, mut rx) = mpsc::channel::<Transaction>(BUFFER_SIZE);
+    check_flags(&cli);
+
+    // Before anything is read, the opening balances are rounded too
+    custom_serde::set_rounding(Rounding { precision: cli.precision, mode: cli.rounding });
+    custom_serde::set_normalize(cli.normalize_amounts);
+    custom_serde::set_strict_amounts(cli.strict_amounts);
+
+    let settings = EngineSettings::new(&cli).unwrap_or_else(|err| fatal(err));
+    let shadow_settings = cli.shadow_config
+        .as_ref()
+        .map(|path| EngineSettings::candidate(run_args, path).unwrap_or_else(|err| fatal(err)));
+
+    let files = match cli.files.is_empty() {
+        true => vec![PathBuf::from(ingest::STDIN)],
+        false => ingest::expand_globs(cli.files)
+            .unwrap_or_else(|err| fatal(PipelineError::input("Could not find the csv files", err))),
+    };
 
-    let file_input = spawn(async move {
-        let mut reader = ReaderBuilder::new()
-            .trim(Trim::All)
-            .from_path(file)
-            .expect("Could not open the csv file");
+    let transform = cli.transform.as_ref().map(|path| {
+        Transform::load(path).unwrap_or_else(|err| fatal(PipelineError::input("Could not load the transform", err)))
+    });
+
+    let opening_balances = match &cli.opening_balances {
+        Some(path) => opening_balances::load(path).unwrap_or_else(|err| {
+            fatal(PipelineError::input("Could not load the opening balances", err))
+        }),
+        None => vec![],
+    };
+
+    let restored = cli.restore.as_ref().map(|path| {
+        Snapshot::load(path)
+            .unwrap_or_else(|err| fatal(PipelineError::input("Could not load the snapshot to restore", err)))
+    });
+
+    let checkpoint = cli.resume.as_ref().map(|dir| {
+        Checkpoint::load(dir)
+            .unwrap_or_else(|err| fatal(PipelineError::input("Could not load the checkpoint to resume from", err)))
+    });
+
+    // A resumed run starts from the engine of the checkpoint, right after its last record
+    let (restored, resume_after, opening_history) = match checkpoint {
+        Some(checkpoint) => {
+            log::info!("Resuming after {}", checkpoint.position);
+
+            (Some(checkpoint.snapshot), Some(checkpoint.position), checkpoint.history)
+        }
+        None => (restored, None, vec![]),
+    };
+
+    let (opening_balances, opening_deleted, opening_tiers, opening_releases) = match restored {
+        Some(snapshot) => (snapshot.accounts, snapshot.deleted, snapshot.tiers, snapshot.scheduled),
+        None => (opening_balances, vec![], BTreeMap::new(), vec![]),
+    };
+
+    let shadow_opening_balances = cli.shadow_opening_balances
+        .as_ref()
+        .map(|path| {
+            opening_balances::load(path).unwrap_or_else(|err| {
+                fatal(PipelineError::input("Could not load the shadow opening balances", err))
+            })
+        });
+
+    let webhooks: Vec<WebhookObserver> = cli.lifecycle_webhook
+        .iter()
+        .map(|url| {
+            WebhookObserver::new(url)
+                .unwrap_or_else(|err| fatal(PipelineError::input("Invalid lifecycle webhook", err)))
+        })
+        .collect();
+
+    // The history goes to disk with --state-dir, it stays in memory otherwise
+    #[cfg(feature = "sled")]
+    let store: Box<dyn StateStore + Send> = match &cli.state_dir {
+        Some(dir) => Box::new(SledStore::open(dir).unwrap_or_else(|err| {
+            fatal(PipelineError::storage("Could not open the state directory", err))
+        })),
+        None => Box::new(MemoryStore::with_capacity(cli.expected_clients, cli.expected_txs)),
+    };
+
+    #[cfg(not(feature = "sled"))]
+    let store: Box<dyn StateStore + Send> =
+        Box::new(MemoryStore::with_capacity(cli.expected_clients, cli.expected_txs));
+
+    let redis_cache = cli.redis_url
+        .as_ref()
+        .map(|url| {
+            RedisCache::new(url).unwrap_or_else(|err| fatal(PipelineError::input("Invalid redis url", err)))
+        });
 
-        for record in reader.deserialize::<Transaction>() {
-            let Ok(transaction) = record else {
-                log::error!("Failed to parse transaction");
-                continue;
+    let events = cli.events
+        .as_ref()
+        .map(|path| {
+            let file = File::create(path).unwrap_or_else(|err| {
+                fatal(PipelineError::output("Could not create the events file", err))
+            });
+
+            EventWriter::new(BufWriter::new(file))
+        });
+
+    let audit = cli.audit_log
+        .as_ref()
+        .map(|path| {
+            let sink: io::Result<Box<dyn AuditSink>> = match cli.audit_max_bytes {
+                _ if path == Path::new("-") => Ok(Box::new(AuditWriter::new(io::stdout()))),
+                Some(max_bytes) => {
+                    RotatingFile::open(path, max_bytes, cli.audit_keep).map(|file| Box::new(AuditWriter::new(file)) as _)
+                }
+                None => File::create(path).map(|file| Box::new(AuditWriter::new(BufWriter::new(file))) as _),
             };
 
-            if tx.send(transaction).await.is_err() {
-                log::error!("Failed to send transaction to engine");
-                break;
+            sink.unwrap_or_else(|err| fatal(PipelineError::output("Could not create the audit log", err)))
+        });
+
+    // Every file the run may write besides the accounts, in a fixed order for the manifest
+    let report_paths: Vec<PathBuf> = [
+        &cli.unknown_types,
+        &cli.rejects,
+        &cli.events,
+        &cli.source_stats,
+        &cli.throughput_report,
+        &cli.pending,
+        &cli.shadow_report,
+        &cli.snapshot,
+        &cli.daily_rollup,
+        &cli.deficit_report,
+        &cli.alerts_report,
+        &cli.open_disputes,
+        &cli.overflow_report,
+        &cli.exposure_report,
+        &cli.risk_report,
+        &cli.dormancy_report,
+        &cli.metrics,
+        &cli.emit_normalized,
+    ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let manifest_path = cli.manifest;
+    let args: Vec<String> = env::args_os()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let input_paths = files.clone();
+    // The ETA needs the size of the whole input, so none for stdin. The offsets read in compressed
+    // files are decompressed, so none for them either.
+    let input_size = files
+        .iter()
+        .map(|path| {
+            let compressed = cli.compression.unwrap_or(Compression::detect(path)) != Compression::None;
+
+            match path.as_os_str() == ingest::STDIN || compressed {
+                true => None,
+                false => fs::metadata(path).ok().map(|metadata| metadata.len()),
             }
-        }
+        })
+        .sum::<Option<u64>>();
+    let show_progress = cli.progress;
+
+    let event_sourcing = cli.event_sourcing;
+    let daily_rollup = cli.daily_rollup;
+    let dormant_after = cli.dormant_after;
+    let activity_columns = cli.activity_columns;
+    let dormancy_report = cli.dormancy_report;
+    let shadow_report = cli.shadow_report;
+    let deficit_report = cli.deficit_report;
+    let open_disputes = cli.open_disputes;
+    let alerts_report = cli.alerts_report;
+    let statements = cli.statements;
+    let risk_report = cli.risk_report;
+    let overflow_report = cli.overflow_report;
+    let multi_currency = cli.base_currency.clone().filter(|_| cli.multi_currency);
+    let rates = cli.rates.map(|path| {
+        ConversionTable::load(path)
+            .unwrap_or_else(|err| fatal(PipelineError::input("Could not load the exchange rates", err)))
     });
+    let exposure_report = cli.exposure_report.zip(cli.base_currency);
+    let multi_tenant = cli.multi_tenant.then_some(cli.default_tenant);
+    let tenant_filter = cli.tenant;
+    let split_tenants = cli.split_tenants;
+    let max_apply_rate = cli.max_apply_rate;
+    let replay_speed = cli.replay_speed;
+    let source_stats = cli.source_stats;
+    let throughput_report = cli.throughput_report;
+    let throughput_bucket = cli.throughput_bucket;
+    let metrics_path = cli.metrics;
+    let metrics = PrometheusRecorder::new();
+    let unknown_types_path = cli.unknown_types;
+    let rejects_path = cli.rejects;
+    let emit_normalized = cli.emit_normalized;
+    let clock = cli.clock;
+    let pending_path = cli.pending;
+    let system_accounts = cli.system_accounts;
+    let risk_tiers = cli.risk_tiers;
+    let fee_summary = cli.fee_summary;
+    let credit_limits = settings.credit_limits.clone();
+    let retention = Retention {
+        max_age_days: cli.retain_days,
+        max_entries: cli.retain_max,
+        max_per_client: cli.retain_per_client,
+    };
+    let snapshot_path = cli.snapshot;
+    let checkpoint_dir = cli.checkpoint;
+    let checkpoint_every = cli.checkpoint_every;
+    let alerts = cli.alert;
+    let writer_threads = cli.writer_threads;
+    let workers = cli.workers;
+    let expected_clients = cli.expected_clients;
+    let expected_txs = cli.expected_txs;
+    let output_format = cli.output_format;
+    let only = cli.only;
+    let selection = ClientSelection::new(cli.clients, cli.client_range);
+    let unsorted = cli.unsorted;
+    let stream_output = cli.stream_output;
+    let output_path = cli.output;
+    let input_format = cli.input_format;
+    let compression = cli.compression;
+    let detailed_exit_codes = cli.detailed_exit_codes;
+    let max_errors = match cli.strict {
+        true => Some(0),
+        false => cli.max_errors,
+    };
+    let tuning = Tuning::from(cli.tuning);
+
+    let mut pending = pending_path
+        .as_ref()
+        .map(|path| {
+            PendingQueue::load(path).unwrap_or_else(|err| {
+                fatal(PipelineError::storage("Could not load the approval queue", err))
+            })
+        });
+
+    let bounds = ReadBounds { limit: None, after: resume_after };
+
+    let (file_input, mut rx) = ingest
+        ::spawn_pipeline(files, input_format, compression, tuning, bounds, transform)
+        .unwrap_or_else(|err| fatal(PipelineError::input("Could not open the csv files", err)));
+
+    let mut failures = Failures::new(metrics.clone());
 
     let consume = spawn(async move {
-        let mut engine = Engine::new();
+        let configure = || settings.builder();
 
-        while let Some(transaction) = rx.recv().await {
-            engine.add_transaction(transaction);
-        }
+        // With workers the transactions go to the shards and `engine` stays idle, the flags needing
+        // the state of a single engine conflict with `--workers`
+        let mut sharded = (workers > 1).then(|| {
+            ShardedEngine::new(workers, |shard| {
+                let owned = |client_id: &ClientId| shard::shard_of(*client_id, workers) == shard;
+                let accounts = opening_balances.iter().filter(|account| owned(&account.client_id));
+                let deleted = opening_deleted.iter().filter(|account| owned(&account.client_id));
+                let tiers = opening_tiers.iter().filter(|(client_id, _)| owned(client_id));
+                let releases = opening_releases.iter().filter(|release| owned(&release.client_id));
+
+                let mut builder = configure()
+                    .observer(Box::new(LogObserver))
+                    .opening_balances(accounts.cloned())
+                    .deleted_accounts(deleted.cloned())
+                    .risk_tiers(tiers.map(|(client_id, tier)| (*client_id, *tier)))
+                    .scheduled_releases(releases.cloned());
 
-        let mut writer = csv::Writer::from_writer(vec![]);
+                if clock == ClockSource::System {
+                    builder = builder.clock(Box::new(SystemClock));
+                }
 
-        engine
-            .get_accounts()
-            .into_iter()
-            .for_each(|account| {
-                let _ = writer.serialize(account);
+                if metrics_path.is_some() {
+                    builder = builder.metrics(Box::new(metrics.clone()));
+                }
+
+                builder.build_with_store(MemoryStore::with_capacity(expected_clients / workers, expected_txs / workers))
+            })
+        });
+
+        // With multiple currencies the transactions go to an engine per currency and `engine` stays
+        // idle too, the opening balances are in the base currency
+        let mut currencies = multi_currency.map(|base| {
+            let opening_balances = opening_balances.clone();
+            let metrics = metrics.clone();
+            let with_metrics = metrics_path.is_some();
+
+            let mut currencies = MultiCurrency::new(base.clone(), move |currency: &str| {
+                let mut builder = configure().observer(Box::new(LogObserver));
+
+                if currency == base {
+                    builder = builder.opening_balances(opening_balances.clone());
+                }
+
+                if clock == ClockSource::System {
+                    builder = builder.clock(Box::new(SystemClock));
+                }
+
+                if with_metrics {
+                    builder = builder.metrics(Box::new(metrics.clone()));
+                }
+
+                builder.build()
             });
 
-        if let Ok(bytes) = writer.into_inner() {
-            let _ = stdout().write_all(&bytes).await;
+            if let Some(rates) = rates {
+                currencies.set_rates(rates);
+            }
+
+            currencies
+        });
+
+        // With multiple tenants the transactions go to an engine per tenant and `engine` stays idle
+        // as well, the opening balances are in the default tenant
+        let mut tenants = multi_tenant.map(|default| {
+            let opening_balances = opening_balances.clone();
+            let metrics = metrics.clone();
+            let with_metrics = metrics_path.is_some();
+
+            MultiTenant::new(default.clone(), move |tenant: &str| {
+                let mut builder = configure().observer(Box::new(LogObserver));
+
+                if tenant == default {
+                    builder = builder.opening_balances(opening_balances.clone());
+                }
+
+                if clock == ClockSource::System {
+                    builder = builder.clock(Box::new(SystemClock));
+                }
+
+                if with_metrics {
+                    builder = builder.metrics(Box::new(metrics.clone()));
+                }
+
+                builder.build()
+            })
+        });
+
+        // Without opening balances of its own, the candidate of the shadow mode starts from the primary's
+        let candidate_balances = match (&shadow_settings, shadow_opening_balances) {
+            (_, Some(opening_balances)) => Some(opening_balances),
+            (Some(_), None) => Some(opening_balances.clone()),
+            (None, None) => None,
+        };
+
+        let mut builder = configure()
+            .observer(Box::new(LogObserver))
+            .opening_balances(opening_balances)
+            .deleted_accounts(opening_deleted)
+            .risk_tiers(opening_tiers)
+            .scheduled_releases(opening_releases)
+            .history(opening_history)
+            .retention(retention);
+
+        if clock == ClockSource::System {
+            builder = builder.clock(Box::new(SystemClock));
+        }
+
+        for webhook in webhooks {
+            builder = builder.observer(Box::new(webhook));
+        }
+
+        if let Some(redis_cache) = redis_cache {
+            builder = builder.observer(Box::new(redis_cache));
+        }
+
+        if let Some(events) = events {
+            builder = builder.event_handler(Box::new(events));
+        }
+
+        if let Some(audit) = audit {
+            builder = builder.audit(audit);
+        }
+
+        if statements.is_some() {
+            builder = builder.statements();
+        }
+
+        for rule in alerts {
+            builder = builder.alert(rule);
+        }
+
+        if metrics_path.is_some() {
+            builder = builder.metrics(Box::new(metrics.clone()));
+        }
+
+        if daily_rollup.is_some() {
+            builder = builder.projection(Box::new(DailyRollup::new()));
+        }
+
+        let mut engine = builder.build_with_store(store);
+        let now = SystemClock.now().unwrap_or_default();
+
+        let mut shadow = candidate_balances.map(|opening_balances| {
+            let candidate_settings = shadow_settings.as_ref().unwrap_or(&settings);
+            let mut candidate = candidate_settings.builder().opening_balances(opening_balances).build();
+
+            if let Some(mut pending) = pending.clone() {
+                pending.restore(&mut candidate, now);
+            }
+
+            Shadow::new(candidate)
+        });
+
+        if let Some(pending) = &mut pending {
+            pending.restore(&mut engine, now);
+        }
+
+        let mut guard = SequenceGuard::new();
+        let mut throttle = max_apply_rate.map(TokenBucket::new);
+        let mut replay = replay_speed.map(Replay::new);
+        let mut unknown_types = unknown_types_path.map(|path| {
+            UnknownTypeFile::new(File::create(path).unwrap_or_else(|err| {
+                fatal(PipelineError::output("Could not create the unknown types file", err))
+            }))
+        });
+        let mut outcomes = Outcomes {
+            rejected: ReasonCode::ALL.into_iter().map(|reason| (reason, 0)).collect(),
+            sources: SourceStats::new(),
+            throughput: throughput_report.as_ref().map(|_| Throughput::new(throughput_bucket)),
+            rejects: rejects_path.map(|path| {
+                RejectsFile::new(File::create(path).unwrap_or_else(|err| {
+                    fatal(PipelineError::output("Could not create the rejects file", err))
+                }))
+            }),
+            normalized: emit_normalized.map(|path| {
+                let format = InputFormat::detect(&path);
+
+                NormalizedFile::new(File::create(path).unwrap_or_else(|err| {
+                    fatal(PipelineError::output("Could not create the normalized transactions file", err))
+                }), format)
+            }),
+        };
+        let mut processed = 0;
+        let mut progress = Progress::new(input_size, PROGRESS_INTERVAL);
+        let mut interrupted = false;
+        let mut shutdown: Pin<Box<dyn Future<Output = ()> + Send>> = Box::pin(shutdown_signal());
+        let mut next_checkpoint = checkpoint_every;
+        // The last record handled, a checkpoint resumes after it
+        let mut last_read: Option<Provenance> = None;
+
+        while let Some(batch) = next_batch(&mut rx, &mut shutdown, &mut interrupted).await {
+            if let Some(Tagged { provenance, .. }) = batch.last() {
+                last_read = Some(provenance.clone());
+            }
+
+            for Tagged { provenance, record, raw, error } in batch {
+                processed += 1;
+                progress.read(&provenance);
+
+                let sequenced = match record {
+                    Ok(sequenced) => sequenced,
+                    Err(reason) => {
+                        if let (ReasonCode::UnknownType, Some(handler), Some(raw)) = (
+                            reason,
+                            &mut unknown_types,
+                            &raw,
+                        ) {
+                            handler.on_unknown_type(&provenance, raw);
+                        }
+
+                        if let Some(rejects) = &mut outcomes.rejects {
+                            rejects.reject_row(&provenance, reason, raw.as_ref());
+                        }
+
+                        outcomes.record_unparsed(&provenance, reason);
+
+                        if max_errors.is_some_and(|max_errors| outcomes.sources.total().malformed > max_errors) {
+                            fatal(
+                                PipelineError::input(
+                                    format!("Could not parse the record at {} ({})", provenance, reason),
+                                    error.unwrap_or_default()
+                                )
+                            );
+                        }
+
+                        continue;
+                    }
+                };
+
+                if let Err(err) = guard.check(&sequenced) {
+                    let tx = outcomes.needs_transaction().then_some(&sequenced.tx);
+
+                    outcomes.record(&mut failures, &provenance, tx, Err(EngineError::OutOfOrder(err)));
+                    continue;
+                }
+
+                if let Some(replay) = &mut replay {
+                    replay.wait(sequenced.tx.timestamp).await;
+                }
+
+                if let Some(throttle) = &mut throttle {
+                    throttle.acquire().await;
+                }
+
+                let tx = outcomes.needs_transaction().then(|| sequenced.tx.clone());
+
+                if let Some(currencies) = &mut currencies {
+                    let result = currencies.add_transaction(sequenced.tx);
+
+                    outcomes.record(&mut failures, &provenance, tx.as_ref(), result);
+                    continue;
+                }
+
+                if let Some(tenants) = &mut tenants {
+                    let result = tenants.add_transaction(sequenced.tx);
+
+                    outcomes.record(&mut failures, &provenance, tx.as_ref(), result);
+                    continue;
+                }
+
+                if let Some(sharded) = &mut sharded {
+                    sharded.submit(sequenced.tx, (provenance, tx));
+
+                    for ((provenance, tx), result) in sharded.results() {
+                        outcomes.record(&mut failures, &provenance, tx.as_ref(), result);
+                    }
+
+                    continue;
+                }
+
+                let result = match &mut shadow {
+                    Some(shadow) => shadow.add_transaction(&mut engine, sequenced.tx),
+                    None => engine.add_transaction(sequenced.tx),
+                };
+
+                if let Some(violation) = engine.invariant_violation() {
+                    let context = format!("Invariant violated at {}", provenance);
+
+                    fatal(PipelineError::Invariant(context, Box::new(violation.clone())));
+                }
+
+                outcomes.record(&mut failures, &provenance, tx.as_ref(), result);
+            }
+
+            if show_progress && progress.due() {
+                eprintln!("{}", progress.line(&outcomes.sources.total()));
+            }
+
+            if let (Some(dir), Some(position)) = (&checkpoint_dir, &last_read) {
+                if processed >= next_checkpoint {
+                    save_checkpoint(&engine, dir, position.clone(), &mut failures);
+                    next_checkpoint = processed + checkpoint_every;
+                }
+            }
+        }
+
+        // The channel is drained, but the input is only complete when every stage of the pipeline
+        // finished cleanly. The accounts of a partial input must not look like a complete output,
+        // unless the run was interrupted and exits with a code saying so. A reader may still be
+        // waiting for its input then.
+        if interrupted {
+            log::warn!("Interrupted after {} records, writing the accounts so far", processed);
+
+            if let (Some(dir), Some(position)) = (&checkpoint_dir, last_read) {
+                save_checkpoint(&engine, dir, position, &mut failures);
+            }
+
+            file_input.abort();
+        } else {
+            match file_input.await {
+                Ok(Ok(Overflowed { dropped, spilled })) => {
+                    let mut metrics = metrics.clone();
+
+                    metrics.increment_counter("pipeline_dropped_records_total", &[], dropped);
+                    metrics.increment_counter("pipeline_spilled_records_total", &[], spilled);
+                }
+                Ok(Err(err)) => fatal(PipelineError::input("Failed to read the input", err)),
+                Err(err) => fatal(PipelineError::input("Failed to read the input", err)),
+            }
+        }
+
+        let shards = sharded.map(|sharded| {
+            let (results, mut engines, degraded) = sharded.finish();
+
+            engines.iter_mut().for_each(Engine::finalize);
+
+            for ((provenance, tx), result) in results {
+                outcomes.record(&mut failures, &provenance, tx.as_ref(), result);
+            }
+
+            // The other workers' accounts are still written, the exit code says the output is partial
+            for degraded in degraded {
+                failures.record(PipelineError::Engine(EngineError::ShardDegraded(degraded.shard)));
+            }
+
+            engines
+        });
+
+        let Outcomes { rejected, sources, throughput, mut rejects, mut normalized } = outcomes;
+
+        eprintln!("{}", progress.summary(&sources.total()));
+
+        if let Some(Err(err)) = unknown_types.as_mut().map(UnknownTypeFile::flush) {
+            failures.record(PipelineError::output("Failed to write the unknown types", err));
+        }
+
+        if let Some(Err(err)) = rejects.as_mut().map(RejectsFile::flush) {
+            failures.record(PipelineError::output("Failed to write the rejects", err));
+        }
+
+        if let Some(Err(err)) = normalized.as_mut().map(NormalizedFile::flush) {
+            failures.record(PipelineError::output("Failed to write the normalized transactions", err));
+        }
+
+        for (source, counters) in sources.iter() {
+            log::info!(
+                "Source {}: {} applied, {} rejected, {} malformed",
+                source,
+                counters.applied,
+                counters.rejected,
+                counters.malformed
+            );
+        }
+
+        if let Some(path) = source_stats {
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| sources.write_report(file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the source stats", err));
+            }
+        }
+
+        if let (Some(path), Some(throughput)) = (throughput_report, &throughput) {
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| throughput.write_report(file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the throughput report", err));
+            }
+        }
+
+        for (reason, count) in rejected.iter().filter(|(_, count)| **count > 0) {
+            log::info!("Rejected {} transactions with {}", count, reason);
+        }
+
+        let sequence = engine.sequence_summary();
+
+        if sequence != SequenceSummary::default() {
+            log::warn!("Upstream sequence: {}", sequence);
+        }
+
+        let duplicate_disputes: BTreeMap<ClientId, u32> = engine.duplicate_disputes().collect();
+
+        for (client_id, count) in duplicate_disputes {
+            log::warn!("Client {} disputed transactions already under dispute {} times", client_id, count);
+        }
+
+        for tx in engine.quarantined() {
+            log::warn!("Transaction {} of client {} is pending review", tx.tx_id, tx.client_id);
+        }
+
+        if let (Some(path), Some(pending)) = (pending_path, &mut pending) {
+            pending.capture(&engine, now);
+
+            if let Some(cutoff) = retention.cutoff(Some(now)) {
+                pending.prune_audit(cutoff);
+            }
+
+            if let Err(err) = pending.save(path) {
+                failures.record(PipelineError::storage("Failed to save the approval queue", err));
+            }
+        }
+
+        if let Some(shadow) = shadow {
+            let divergences = shadow.finish(&engine);
+
+            log::info!("Shadow engine diverged {} times", divergences.len());
+
+            if let Some(path) = shadow_report {
+                let result = File::create(path)
+                    .map_err(csv::Error::from)
+                    .and_then(|file| shadow::write_report(&divergences, file));
+
+                if let Err(err) = result {
+                    failures.record(PipelineError::output("Failed to write the shadow report", err));
+                }
+            }
+        }
+
+        if event_sourcing {
+            log::info!("Rebuilding balances from {} events", engine.events().len());
+
+            engine.rebuild();
+        }
+
+        engine.finalize();
+
+        let store_failure = engine.store_failure();
+
+        if let Some(err) = &store_failure {
+            failures.record(PipelineError::storage("The state directory failed", err.clone()));
+        }
+
+        // A state the store failed on isn't carried to the next run
+        if let Some(path) = snapshot_path.filter(|_| store_failure.is_none()) {
+            let snapshot = match &shards {
+                Some(engines) => shard::merge_snapshot(engines),
+                None => Snapshot::capture(&engine),
+            };
+
+            if let Err(err) = snapshot.save(path) {
+                failures.record(PipelineError::storage("Failed to write the snapshot", err));
+            }
+        }
+
+        if let (Some(path), Some(rollup)) = (daily_rollup, engine.projection::<DailyRollup>()) {
+            if let Err(err) = write_rollup(rollup, &path) {
+                failures.record(PipelineError::output("Failed to write the daily rollup", err));
+            }
+        }
+
+        if let Some(path) = deficit_report {
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| deficit::write_report(&engine.deficits(), file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the deficit report", err));
+            }
+        }
+
+        if let Some(path) = alerts_report {
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| alert::write_report(&engine.alerts_report(), file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the alerts report", err));
+            }
+        }
+
+        if let Some(path) = open_disputes {
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| disputes::write_report(&engine.open_disputes(), file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the open disputes", err));
+            }
+        }
+
+        if let (Some(path), Some(ledger)) = (&statements, engine.ledger()) {
+            if let Err(err) = statement::write_statements(ledger, path) {
+                failures.record(PipelineError::output("Failed to write the statements", err));
+            }
+        }
+
+        if let Some(path) = &overflow_report {
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| balance_bounds::write_report(engine.overflows(), file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the overflow report", err));
+            }
+        }
+
+        if let (Some(path), Some(scoring)) = (&risk_report, engine.risk_scoring()) {
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| scoring.write_report(file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the risk report", err));
+            }
+        }
+
+        if let Some((path, currency)) = exposure_report {
+            let accounts = engine.client_ids().filter_map(|client_id| engine.get_account(client_id));
+            let summary = ExposureSummary::new(&currency, accounts, engine.system_accounts());
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| summary.write_report(file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the exposure report", err));
+            }
+        }
+
+        let dormant = dormant_after.map(|days| engine.detect_dormant(days));
+
+        if let (Some(path), Some(dormant)) = (dormancy_report, &dormant) {
+            let result = File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| dormancy::write_report(dormant, file));
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the dormancy report", err));
+            }
+        }
+
+        let dormant: Option<HashSet<ClientId>> = dormant.map(|dormant| {
+            dormant
+                .into_iter()
+                .map(|account| account.client_id)
+                .collect()
+        });
+
+        let tiers: Option<HashMap<ClientId, RiskTier>> = risk_tiers.then(|| {
+            engine
+                .client_ids()
+                .map(|client_id| (client_id, engine.risk_tier(client_id)))
+                .collect()
+        });
+
+        let activity: Option<HashMap<ClientId, (u64, u64)>> = activity_columns.then(|| {
+            engine
+                .client_ids()
+                .filter_map(|client_id| engine.activity_span(client_id).map(|span| (client_id, span)))
+                .collect()
+        });
+
+        let system = system_accounts.then(|| engine.system_accounts().clone());
+        let fees_charged = fee_summary.then(|| engine.fee_summary().clone());
+
+        let mut accounts = match shards {
+            Some(engines) if unsorted => engines.into_iter().flat_map(Engine::into_accounts).collect(),
+            Some(engines) => shard::merge_accounts(engines),
+            None if unsorted => engine.into_accounts(),
+            None => engine.get_accounts(),
+        };
+
+        let mut currency_accounts = currencies.map(|currencies| match unsorted {
+            true => currencies.into_accounts(),
+            false => currencies.get_accounts(),
+        });
+
+        let mut tenant_accounts = tenants.map(|tenants| match unsorted {
+            true => tenants.into_accounts(),
+            false => tenants.get_accounts(),
+        });
+
+        let total = sources.total();
+        let summary = RunSummary {
+            rows: processed as u64,
+            applied: total.applied,
+            rejected: total.rejected,
+            malformed: total.malformed,
+            reasons: rejected,
+            accounts: accounts.len(),
+            locked: accounts.iter().filter(|account| account.locked).count(),
+        };
+
+        eprintln!("{}", summary);
+
+        if !only.is_empty() {
+            let keep = |account: &Account| only.iter().any(|filter| filter.matches(account));
+
+            accounts.retain(keep);
+
+            if let Some(currency_accounts) = &mut currency_accounts {
+                currency_accounts.retain(|account| keep(&account.account()));
+            }
+
+            if let Some(tenant_accounts) = &mut tenant_accounts {
+                tenant_accounts.retain(|account| keep(&account.account()));
+            }
+        }
+
+        if !selection.is_empty() {
+            accounts.retain(|account| selection.matches(account.client_id));
+
+            if let Some(currency_accounts) = &mut currency_accounts {
+                currency_accounts.retain(|account| selection.matches(account.client_id));
+            }
+
+            if let Some(tenant_accounts) = &mut tenant_accounts {
+                tenant_accounts.retain(|account| selection.matches(account.client_id));
+            }
+        }
+
+        if let Some(tenant_accounts) = &mut tenant_accounts {
+            if !tenant_filter.is_empty() {
+                tenant_accounts.retain(|account| tenant_filter.contains(&account.tenant));
+            }
+
+            if let Some(path) = &split_tenants {
+                if let Err(err) = tenant::write_tenants(tenant_accounts, path) {
+                    failures.record(PipelineError::output("Failed to write the accounts per tenant", err));
+                }
+            }
+        }
+
+        let columns = |account: &Account| ExtendedColumns {
+            status: dormant
+                .as_ref()
+                .map(|dormant| dormancy::status(dormant.contains(&account.client_id))),
+            tier: tiers
+                .as_ref()
+                .map(|tiers| tiers.get(&account.client_id).copied().unwrap_or_default()),
+            credit_limit: credit_limits.as_ref().map(|limits| limits.limit(account.client_id)),
+            credit_used: credit_limits.as_ref().map(|_| credit::used(account.available)),
+            first_activity: activity
+                .as_ref()
+                .map(|activity| activity.get(&account.client_id).map(|(first, _)| *first)),
+            last_activity: activity
+                .as_ref()
+                .map(|activity| activity.get(&account.client_id).map(|(_, last)| *last)),
+        };
+
+        let extended = |accounts: Vec<Account>| -> Vec<(Account, ExtendedColumns)> {
+            accounts
+                .into_iter()
+                .map(|account| {
+                    let columns = columns(&account);
+
+                    (account, columns)
+                })
+                .collect()
+        };
+
+        let name = output_path.as_ref().map_or("stdout".to_string(), |path| path.display().to_string());
+        let mut accounts_output = None;
+
+        if stream_output {
+            let mut trailer = vec![];
+
+            if let Some(system) = system {
+                trailer.push(b'\n');
+
+                if let Err(err) = system.write_csv(&mut trailer) {
+                    failures.record(PipelineError::output("Failed to serialize system accounts", err));
+                }
+            }
+
+            if let Some(fees) = &fees_charged {
+                trailer.push(b'\n');
+
+                if let Err(err) = fees.write_csv(&mut trailer) {
+                    failures.record(PipelineError::output("Failed to serialize the fee summary", err));
+                }
+            }
+
+            let result = match &output_path {
+                Some(path) => output::stream_atomic(path, &accounts, columns, output_format, &trailer).await,
+                None => output::stream_rows(&accounts, columns, output_format, &trailer, &mut stdout()).await,
+            };
+
+            match result {
+                Ok(digest) => accounts_output = Some(digest.finish(&name)),
+                Err(err) => failures.record(PipelineError::output("Failed to write the accounts", err)),
+            }
         } else {
-            log::error!("Failed to serialize accounts");
+            let result: Result<Vec<u8>, Box<dyn Error + Send + Sync>> = match output_format {
+                OutputFormat::Csv if currency_accounts.is_some() => {
+                    output::write_csv(&currency_accounts.unwrap_or_default(), writer_threads).map_err(Into::into)
+                }
+                OutputFormat::Json | OutputFormat::Ndjson if currency_accounts.is_some() => {
+                    let lines = output_format == OutputFormat::Ndjson;
+
+                    output::write_json_rows(currency_accounts.unwrap_or_default().iter(), lines).map_err(Into::into)
+                }
+                OutputFormat::Csv if tenant_accounts.is_some() => {
+                    output::write_csv(&tenant_accounts.unwrap_or_default(), writer_threads).map_err(Into::into)
+                }
+                OutputFormat::Json | OutputFormat::Ndjson if tenant_accounts.is_some() => {
+                    let lines = output_format == OutputFormat::Ndjson;
+
+                    output::write_json_rows(tenant_accounts.unwrap_or_default().iter(), lines).map_err(Into::into)
+                }
+                OutputFormat::Csv if dormant.is_none() && tiers.is_none() && credit_limits.is_none() && activity.is_none() => {
+                    output::write_csv(&accounts, writer_threads).map_err(Into::into)
+                }
+                OutputFormat::Csv => output::write_csv(&extended(accounts), writer_threads).map_err(Into::into),
+                OutputFormat::Json | OutputFormat::Ndjson => {
+                    let lines = output_format == OutputFormat::Ndjson;
+
+                    output::write_json(&extended(accounts), lines).map_err(Into::into)
+                }
+                #[cfg(feature = "parquet")]
+                OutputFormat::Parquet => output::write_parquet(&extended(accounts)).map_err(Into::into),
+            };
+
+            match result {
+                Ok(mut bytes) => {
+                    if let Some(system) = system {
+                        bytes.push(b'\n');
+
+                        if let Err(err) = system.write_csv(&mut bytes) {
+                            failures.record(PipelineError::output("Failed to serialize system accounts", err));
+                        }
+                    }
+
+                    if let Some(fees) = &fees_charged {
+                        bytes.push(b'\n');
+
+                        if let Err(err) = fees.write_csv(&mut bytes) {
+                            failures.record(PipelineError::output("Failed to serialize the fee summary", err));
+                        }
+                    }
+
+                    let result = match &output_path {
+                        Some(path) => output::write_atomic(path, &bytes),
+                        None => {
+                            let mut stdout = stdout();
+
+                            stdout.write_all(&bytes).await.and(stdout.flush().await)
+                        }
+                    };
+
+                    match result {
+                        Ok(()) => accounts_output = Some(OutputFile::of_bytes(&name, &bytes)),
+                        Err(err) => {
+                            failures.record(PipelineError::output("Failed to write the accounts", err));
+                        }
+                    }
+                }
+                Err(err) => failures.record(PipelineError::output("Failed to serialize accounts", err)),
+            }
+        }
+
+        // Written last so it counts every failure of the run
+        if let Some(path) = metrics_path {
+            if let Err(err) = fs::write(path, metrics.render()) {
+                failures.record(PipelineError::output("Failed to write the metrics", err));
+            }
+        }
+
+        // After every other output, so it can hash them. Outputs missing at the end are left out.
+        if let Some(path) = manifest_path {
+            let mut manifest = Manifest::new(args);
+            manifest.outputs.extend(accounts_output);
+
+            let result = manifest
+                .add_inputs(&input_paths, &sources, processed as u64)
+                .and_then(|()| {
+                    for path in report_paths.iter().filter(|path| path.exists()) {
+                        manifest.add_output_file(path)?;
+                    }
+
+                    manifest.save(&path)
+                });
+
+            if let Err(err) = result {
+                failures.record(PipelineError::output("Failed to write the manifest", err));
+            }
+        }
+
+        match interrupted {
+            true => INTERRUPTED_EXIT_CODE,
+            false if failures.exit_code != 0 || !detailed_exit_codes => failures.exit_code,
+            false if summary.malformed > 0 => UNPARSED_EXIT_CODE,
+            false if summary.rejected > 0 => REJECTED_EXIT_CODE,
+            false => 0,
         }
     });
 
-    let _ = join!(file_input, consume);
+    match consume.await {
+        Ok(0) => (),
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(err) => fatal(PipelineError::output("Failed to process the transactions", err)),
+    }
+}
+
+fn load_opening_balances(path: Option<PathBuf>) -> Vec<Account> {
+    match path {
+        Some(path) => opening_balances::load(path).unwrap_or_else(|err| {
+            fatal(PipelineError::input("Could not load the opening balances", err))
+        }),
+        None => vec![],
+    }
+}
+
+// Completes on SIGINT (Ctrl-C) or SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            log::error!("Could not listen for SIGINT: {}", err);
+            future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                log::error!("Could not listen for SIGTERM: {}", err);
+                future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+
+    tokio::select! {
+        () = interrupt => log::warn!("Received SIGINT, shutting down"),
+        () = terminate => log::warn!("Received SIGTERM, shutting down"),
+    }
+}
+
+// Waits for the next batch until the run is interrupted. From then on the input isn't read anymore,
+// the batches already in the channel are still handed out.
+async fn next_batch(
+    rx: &mut mpsc::Receiver<Vec<Tagged>>,
+    shutdown: &mut Pin<Box<dyn Future<Output = ()> + Send>>,
+    interrupted: &mut bool
+) -> Option<Vec<Tagged>> {
+    if !*interrupted {
+        tokio::select! {
+            batch = rx.recv() => return batch,
+            () = shutdown.as_mut() => {
+                *interrupted = true;
+                rx.close();
+            }
+        }
+    }
+
+    rx.recv().await
+}
+
+// Prints the accounts of a live engine once it stopped taking transactions, after the ones it
+// already received are applied, and exits with the code of an interrupted run
+async fn shut_down(engine: EngineHandle, snapshot: Option<PathBuf>) -> ! {
+    let state = engine
+        .accounts().await
+        .unwrap_or_else(|err| fatal(PipelineError::output("Could not read the final accounts", err)));
+
+    if let Some(path) = snapshot {
+        if let Err(err) = state.save(path) {
+            fatal(PipelineError::storage("Failed to write the snapshot", err));
+        }
+    }
+
+    let bytes = output::write_csv(&state.accounts, 1)
+        .unwrap_or_else(|err| fatal(PipelineError::output("Failed to serialize the accounts", err)));
+
+    let mut stdout = stdout();
+
+    if let Err(err) = stdout.write_all(&bytes).await.and(stdout.flush().await) {
+        fatal(PipelineError::output("Failed to write the accounts", err));
+    }
+
+    std::process::exit(INTERRUPTED_EXIT_CODE);
+}
+
+// The engine of serve and listen, recovered from its journal when there's one
+fn spawn_live(
+    opening_balances: Option<PathBuf>,
+    journal: Option<PathBuf>,
+    quarantine_above: Option<Decimal>,
+    workers: usize
+) -> EngineHandle {
+    let opening_balances = load_opening_balances(opening_balances);
+
+    let mut engines: Vec<Engine> = (0..workers)
+        .map(|shard| {
+            let accounts = opening_balances
+                .iter()
+                .filter(|account| shard::shard_of(account.client_id, workers) == shard);
+
+            let mut builder = Engine::builder().observer(Box::new(LogObserver)).opening_balances(accounts.cloned());
+
+            if let Some(amount) = quarantine_above {
+                builder = builder.policy(Box::new(QuarantineAbove(amount)));
+            }
+
+            builder.build()
+        })
+        .collect();
+
+    match journal {
+        Some(path) => {
+            let journal = journal::recover_with(&path, |tx| {
+                let shard = shard::route(&tx, workers)?;
+
+                engines[shard].add_transaction(tx)
+            });
+            let journal = Arc::new(Mutex::new(journal.unwrap_or_else(|err| {
+                fatal(PipelineError::storage("Could not recover the journal", err))
+            })));
+
+            EngineHandle::spawn_sharded(
+                engines.into_iter().map(|engine| Journaled::shared(engine, journal.clone())).collect()
+            )
+        }
+        None => EngineHandle::spawn_sharded(engines),
+    }
+}
+
+// The sinks of the dead letters of a streaming mode, `brokers` the ones it consumes from
+#[cfg_attr(not(feature = "kafka"), allow(unused_variables))]
+fn dead_letter_queue(args: DeadLetterArgs, brokers: Option<&str>) -> DeadLetterQueue {
+    let mut sinks: Vec<Box<dyn DeadLetterSink>> = vec![];
+
+    if let Some(path) = args.dead_letter_file {
+        let file = DeadLetterFile::open(&path).unwrap_or_else(|err| {
+            fatal(PipelineError::output("Could not open the dead letter file", err))
+        });
+
+        sinks.push(Box::new(file));
+    }
+
+    #[cfg(feature = "kafka")]
+    if let Some(topic) = args.dead_letter_topic {
+        let brokers = args.dead_letter_brokers.as_deref().or(brokers).unwrap_or("localhost:9092");
+        let producer = transaction_engine::kafka::KafkaDeadLetters::new(brokers, &topic).unwrap_or_else(|err| {
+            fatal(PipelineError::output("Could not create the dead letter producer", err))
+        });
+
+        sinks.push(Box::new(producer));
+    }
+
+    DeadLetterQueue::new(sinks)
+}
+
+async fn replay(journal: PathBuf, opening_balances: Option<PathBuf>, snapshot: Option<PathBuf>) {
+    let mut engine = Engine::builder().opening_balances(load_opening_balances(opening_balances)).build();
+
+    let result = journal::replay(&journal, |tx| {
+        if let Err(err) = engine.add_transaction(tx) {
+            log::debug!("Replayed transaction rejected again: {}", err);
+        }
+    });
+
+    if let Err(err) = result {
+        fatal(PipelineError::input("Could not replay the journal", err));
+    }
+
+    if let Some(path) = snapshot {
+        if let Err(err) = Snapshot::capture(&engine).save(path) {
+            fatal(PipelineError::storage("Failed to write the snapshot", err));
+        }
+    }
+
+    let bytes = output::write_csv(&engine.get_accounts(), 1)
+        .unwrap_or_else(|err| fatal(PipelineError::output("Failed to serialize the accounts", err)));
+
+    let mut stdout = stdout();
+
+    if let Err(err) = stdout.write_all(&bytes).await.and(stdout.flush().await) {
+        fatal(PipelineError::output("Failed to write the accounts", err));
+    }
+}
+
+// Prints the expectations that didn't hold to stderr and the accounts at the end to stdout, returns
+// whether every expectation held
+async fn run_scenario(path: PathBuf) -> bool {
+    let scenario = fs::read_to_string(&path)
+        .map_err(|err| PipelineError::input("Could not read the scenario", err))
+        .and_then(|text| Scenario::parse(&text).map_err(|err| PipelineError::input("Invalid scenario", err)))
+        .unwrap_or_else(|err| fatal(err));
+
+    let mut engine = Engine::new();
+    let failures = scenario.run(&mut engine);
+
+    for failure in &failures {
+        eprintln!("{}", failure);
+    }
+
+    let bytes = output::write_csv(&engine.get_accounts(), 1)
+        .unwrap_or_else(|err| fatal(PipelineError::output("Failed to serialize the accounts", err)));
+
+    let mut stdout = stdout();
+
+    if let Err(err) = stdout.write_all(&bytes).await.and(stdout.flush().await) {
+        fatal(PipelineError::output("Failed to write the accounts", err));
+    }
+
+    failures.is_empty()
+}
+
+fn write_rollup(rollup: &DailyRollup, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let file = File::create(path)?;
+
+    #[cfg(feature = "parquet")]
+    if path.extension().is_some_and(|extension| extension == "parquet") {
+        rollup.write_parquet(file)?;
+        return Ok(());
+    }
+
+    rollup.write_csv(file)?;
+
+    Ok(())
+}
+
+fn review_pending(action: PendingAction) -> Result<(), PendingError> {
+    let now = SystemClock.now().unwrap_or_default();
+
+    let (queue_path, tx_id, review, reviewer) = match action {
+        PendingAction::List { queue } => {
+            return PendingQueue::load(queue)?
+                .write_list(std::io::stdout())
+                .map_err(|err| PendingError::Io(err.into()));
+        }
+        PendingAction::Approve { queue, tx, reviewer } => (queue, tx, Review::Approved, reviewer),
+        PendingAction::Reject { queue, tx, reviewer } => (queue, tx, Review::Rejected, reviewer),
+    };
+
+    let mut queue = PendingQueue::load(&queue_path)?;
+
+    queue.review(tx_id, review, reviewer, now)?;
+    queue.save(&queue_path)
+}
+
+// Returns whether the outputs are the same.
+fn regress(
+    baseline_bin: PathBuf,
+    candidate_bin: Option<PathBuf>,
+    input: PathBuf,
+    args: Vec<String>
+) -> Result<bool, PipelineError> {
+    let candidate_bin = match candidate_bin {
+        Some(path) => path,
+        None => env::current_exe().map_err(|err| PipelineError::input("Could not find this binary", err))?,
+    };
+
+    let run = |bin: &Path| -> Result<Vec<u8>, PipelineError> {
+        let context = || format!("Could not run {}", bin.display());
+        let start = Instant::now();
+
+        let output = process::Command::new(bin)
+            .args(&args)
+            .arg(&input)
+            .stderr(process::Stdio::inherit())
+            .output()
+            .map_err(|err| PipelineError::input(context(), err))?;
+
+        if !output.status.success() {
+            return Err(PipelineError::input(context(), format!("it exited with {}", output.status)));
+        }
+
+        log::info!("{} took {:?}", bin.display(), start.elapsed());
+
+        Ok(output.stdout)
+    };
+
+    let baseline = run(&baseline_bin)?;
+    let candidate = run(&candidate_bin)?;
+
+    let diffs = regress::diff_outputs(&baseline, &candidate)
+        .map_err(|err| PipelineError::input("Could not parse the outputs", err))?;
+
+    for diff in &diffs {
+        println!("{}", diff);
+    }
+
+    log::info!("{} differences between the baseline and the candidate", diffs.len());
+
+    Ok(diffs.is_empty())
+}
+
+// Returns whether the reports match.
+fn reconcile(old: PathBuf, new: PathBuf) -> Result<bool, PipelineError> {
+    let read = |path: &Path| -> Result<Vec<Account>, PipelineError> {
+        let context = || format!("Could not read the account report {}", path.display());
+        let output = fs::read(path).map_err(|err| PipelineError::input(context(), err))?;
+
+        regress::read_accounts(&output).map_err(|err| PipelineError::input(context(), err))
+    };
+
+    let diffs = regress::reconcile(&read(&old)?, &read(&new)?);
+
+    for diff in &diffs {
+        println!("{}", diff);
+    }
+
+    log::info!("{} differences between {} and {}", diffs.len(), old.display(), new.display());
+
+    Ok(diffs.is_empty())
+}
+
+async fn tune(file: PathBuf, sample: usize) {
+    let limit = ingest::sample_len(&file, sample)
+        .unwrap_or_else(|err| fatal(PipelineError::input("Could not read the csv file", err)));
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+
+    let mut fastest: Option<(Tuning, Duration)> = None;
+
+    for tuning in Tuning::grid(threads) {
+        let start = Instant::now();
+
+        let (file_input, mut rx) = ingest
+            ::spawn_pipeline(vec![file.clone()], None, None, tuning, ReadBounds { limit: Some(limit), after: None }, None)
+            .unwrap_or_else(|err| fatal(PipelineError::input("Could not open the csv file", err)));
+
+        let mut engine = Engine::new();
+
+        while let Some(batch) = rx.recv().await {
+            for Tagged { record, .. } in batch {
+                if let Ok(sequenced) = record {
+                    let _ = engine.add_transaction(sequenced.tx);
+                }
+            }
+        }
+
+        let _ = file_input.await;
+
+        let elapsed = start.elapsed();
+
+        log::info!("{}: {:?}", tuning, elapsed);
+
+        if fastest.is_none_or(|(_, fastest)| elapsed < fastest) {
+            fastest = Some((tuning, elapsed));
+        }
+    }
+
+    if let Some((tuning, elapsed)) = fastest {
+        log::info!("Fastest configuration took {:?} for {} records", elapsed, sample);
+
+        println!("{}", tuning);
+    }
 }
@@ -0,0 +1,339 @@
+use std::{ fs::File, io::{ self, Read }, path::Path };
+
+use serde::{ Deserialize, Serialize };
+
+use crate::{ ingest::{ self, STDIN }, output, provenance::{ SourceCounters, SourceStats } };
+
+/// Bumped when a field of the manifest is removed or changes meaning, adding one doesn't.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// What a run read and wrote, so downstream systems can check where outputs come from and notice
+/// the same input being processed twice. It has no timestamps, the same run gives the same
+/// manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub manifest_version: u32,
+    pub engine_version: String,
+    /// The command line arguments, without the program name
+    pub args: Vec<String>,
+    pub inputs: Vec<InputFile>,
+    pub rows: RowCounts,
+    pub outputs: Vec<OutputFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputFile {
+    pub path: String,
+    /// Unknown for stdin, which can't be read again
+    pub bytes: Option<u64>,
+    pub sha256: Option<String>,
+    #[serde(flatten)]
+    pub counters: SourceCounters,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RowCounts {
+    pub read: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    pub malformed: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputFile {
+    /// The path, or `stdout` for the accounts written to it
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+impl OutputFile {
+    pub fn of_bytes(path: &str, bytes: &[u8]) -> Self {
+        let mut digest = OutputDigest::new();
+        digest.update(bytes);
+
+        digest.finish(path)
+    }
+
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let (bytes, sha256) = hash_file(path)?;
+
+        Ok(OutputFile { path: path.display().to_string(), bytes, sha256 })
+    }
+}
+
+/// The size and hash of an output written a piece at a time, see [`OutputFile::of_bytes`] for one
+/// written at once.
+pub struct OutputDigest {
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl OutputDigest {
+    pub fn new() -> Self {
+        OutputDigest { hasher: Sha256::new(), bytes: 0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+        self.bytes += bytes.len() as u64;
+    }
+
+    pub fn finish(self, path: &str) -> OutputFile {
+        OutputFile { path: path.to_string(), bytes: self.bytes, sha256: self.hasher.finish() }
+    }
+}
+
+impl Default for OutputDigest {
+    fn default() -> Self {
+        OutputDigest::new()
+    }
+}
+
+impl Manifest {
+    pub fn new(args: Vec<String>) -> Self {
+        Manifest {
+            manifest_version: MANIFEST_VERSION,
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            args,
+            inputs: vec![],
+            rows: RowCounts::default(),
+            outputs: vec![],
+        }
+    }
+
+    /// Hashes the inputs and takes their counters from `sources`. `read` counts the records read,
+    /// including those skipped before being applied.
+    pub fn add_inputs(
+        &mut self,
+        paths: &[impl AsRef<Path>],
+        sources: &SourceStats,
+        read: u64
+    ) -> io::Result<()> {
+        for path in paths {
+            let path = path.as_ref();
+
+            // An object would have to be read again to be hashed
+            let (source, bytes, sha256) = match path == Path::new(STDIN) {
+                true => ("stdin".to_string(), None, None),
+                false if ingest::is_url(path) => (path.display().to_string(), None, None),
+                false => {
+                    let (bytes, sha256) = hash_file(path)?;
+
+                    (path.display().to_string(), Some(bytes), Some(sha256))
+                }
+            };
+
+            let counters = sources.get(&source).cloned().unwrap_or_default();
+
+            self.rows.applied += counters.applied;
+            self.rows.rejected += counters.rejected;
+            self.rows.malformed += counters.malformed;
+
+            self.inputs.push(InputFile { path: source, bytes, sha256, counters });
+        }
+
+        self.rows.read += read;
+
+        Ok(())
+    }
+
+    pub fn add_output(&mut self, output: OutputFile) {
+        self.outputs.push(output);
+    }
+
+    pub fn add_output_file(&mut self, path: &Path) -> io::Result<()> {
+        self.outputs.push(OutputFile::of_file(path)?);
+
+        Ok(())
+    }
+
+    /// Writes the manifest as pretty JSON, replacing the file at once.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+
+        output::write_atomic(path, &json)
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut hasher = Sha256::new();
+    let mut bytes = 0;
+
+    loop {
+        let read = file.read(&mut buffer)?;
+
+        if read == 0 {
+            return Ok((bytes, hasher.finish()));
+        }
+
+        hasher.update(&buffer[..read]);
+        bytes += read as u64;
+    }
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// SHA-256 (FIPS 180-4), so the hashes can be checked with the usual tools like sha256sum. It's
+// about 60 lines, not worth a dependency.
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    len: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        while !data.is_empty() {
+            let take = (64 - self.block_len).min(data.len());
+
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        let bits = self.len * 8;
+
+        self.update(&[0x80]);
+
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+
+        self.update(&bits.to_be_bytes());
+
+        self.state.iter().map(|word| format!("{:08x}", word)).collect()
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+
+        for (i, word) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::provenance::Provenance;
+
+    use super::*;
+
+    fn sha256(data: &[u8]) -> String {
+        OutputFile::of_bytes("", data).sha256
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(sha256(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn test_manifest() {
+        let dir = std::env::temp_dir().join("transaction-engine-manifest");
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        fs::write(&input, "abc").unwrap();
+
+        let mut sources = SourceStats::new();
+        let provenance = Provenance { source: input.display().to_string().into(), line: 2, offset: 0 };
+        sources.record(&provenance, Ok(()));
+        sources.record_unparsed(&provenance);
+
+        let mut manifest = Manifest::new(vec!["input.csv".to_string()]);
+        manifest.add_inputs(&[input.as_path(), Path::new(STDIN)], &sources, 3).unwrap();
+        manifest.add_output(OutputFile::of_bytes("stdout", b"abc"));
+
+        assert_eq!(manifest.inputs[0].bytes, Some(3));
+        assert_eq!(manifest.inputs[0].sha256, manifest.outputs[0].sha256.clone().into());
+        assert_eq!(manifest.inputs[1].path, "stdin");
+        assert_eq!(manifest.inputs[1].sha256, None);
+        assert_eq!(manifest.rows, RowCounts { read: 3, applied: 1, rejected: 0, malformed: 1 });
+
+        let path = dir.join("manifest.json");
+        manifest.save(&path).unwrap();
+
+        let saved: Manifest = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved, manifest);
+        assert_eq!(OutputFile::of_file(&input).unwrap().sha256, manifest.outputs[0].sha256);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
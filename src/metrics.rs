@@ -0,0 +1,173 @@
+use std::{ collections::BTreeMap, fmt::Write, sync::{ Arc, Mutex } };
+
+pub type Labels<'a> = &'a [(&'static str, &'a str)];
+
+/// Receives the engine metrics, every method does nothing by default.
+pub trait MetricsRecorder: Send {
+    fn increment_counter(&mut self, _name: &'static str, _labels: Labels, _value: u64) {}
+
+    fn set_gauge(&mut self, _name: &'static str, _labels: Labels, _value: f64) {}
+
+    fn record_histogram(&mut self, _name: &'static str, _labels: Labels, _value: f64) {}
+}
+
+pub struct NoopRecorder;
+
+impl MetricsRecorder for NoopRecorder {}
+
+const BUCKETS: [f64; 11] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1,
+];
+
+type Series = (&'static str, String);
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<Series, u64>,
+    gauges: BTreeMap<Series, f64>,
+    histograms: BTreeMap<Series, Histogram>,
+}
+
+// Keeps the metrics in memory and renders them in the Prometheus text format. Clones share the
+// same metrics, so one clone can be given to the engine and another one rendered.
+#[derive(Clone, Default)]
+pub struct PrometheusRecorder {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl PrometheusRecorder {
+    pub fn new() -> Self {
+        PrometheusRecorder::default()
+    }
+
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut output = String::new();
+        let mut last = "";
+
+        for ((name, labels), value) in &registry.counters {
+            write_type(&mut output, &mut last, name, "counter");
+            let _ = writeln!(output, "{}{} {}", name, braced(labels), value);
+        }
+
+        for ((name, labels), value) in &registry.gauges {
+            write_type(&mut output, &mut last, name, "gauge");
+            let _ = writeln!(output, "{}{} {}", name, braced(labels), value);
+        }
+
+        for ((name, labels), histogram) in &registry.histograms {
+            write_type(&mut output, &mut last, name, "histogram");
+
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                let le = join(labels, &format!("le=\"{}\"", bound));
+                let _ = writeln!(output, "{}_bucket{{{}}} {}", name, le, count);
+            }
+
+            let le = join(labels, "le=\"+Inf\"");
+            let _ = writeln!(output, "{}_bucket{{{}}} {}", name, le, histogram.count);
+            let _ = writeln!(output, "{}_sum{} {}", name, braced(labels), histogram.sum);
+            let _ = writeln!(output, "{}_count{} {}", name, braced(labels), histogram.count);
+        }
+
+        output
+    }
+}
+
+impl MetricsRecorder for PrometheusRecorder {
+    fn increment_counter(&mut self, name: &'static str, labels: Labels, value: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.counters.entry((name, format_labels(labels))).or_default() += value;
+    }
+
+    fn set_gauge(&mut self, name: &'static str, labels: Labels, value: f64) {
+        let mut registry = self.registry.lock().unwrap();
+        registry.gauges.insert((name, format_labels(labels)), value);
+    }
+
+    fn record_histogram(&mut self, name: &'static str, labels: Labels, value: f64) {
+        let mut registry = self.registry.lock().unwrap();
+        let histogram = registry.histograms.entry((name, format_labels(labels))).or_default();
+
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+
+        histogram.count += 1;
+        histogram.sum += value;
+    }
+}
+
+// The series are sorted by name, so the type line is only written before the first one of a name.
+fn write_type(output: &mut String, last: &mut &'static str, name: &'static str, kind: &str) {
+    if *last != name {
+        let _ = writeln!(output, "# TYPE {} {}", name, kind);
+        *last = name;
+    }
+}
+
+fn format_labels(labels: Labels) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn braced(labels: &str) -> String {
+    match labels.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", labels),
+    }
+}
+
+fn join(labels: &str, label: &str) -> String {
+    match labels.is_empty() {
+        true => label.to_string(),
+        false => format!("{},{}", labels, label),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let recorder = PrometheusRecorder::new();
+        let mut engine_side = recorder.clone();
+
+        engine_side.increment_counter("applied_total", &[("type", "deposit")], 1);
+        engine_side.increment_counter("applied_total", &[("type", "deposit")], 2);
+        engine_side.increment_counter("applied_total", &[("type", "dispute")], 1);
+        engine_side.set_gauge("accounts", &[], 4.0);
+        engine_side.record_histogram("apply_seconds", &[], 0.000_02);
+        engine_side.record_histogram("apply_seconds", &[], 0.5);
+
+        let rendered = recorder.render();
+
+        assert!(
+            rendered.starts_with(
+                "# TYPE applied_total counter\n\
+                 applied_total{type=\"deposit\"} 3\n\
+                 applied_total{type=\"dispute\"} 1\n\
+                 # TYPE accounts gauge\n\
+                 accounts 4\n\
+                 # TYPE apply_seconds histogram\n"
+            )
+        );
+        assert!(rendered.contains("apply_seconds_bucket{le=\"0.00005\"} 1\n"));
+        assert!(rendered.contains("apply_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(rendered.ends_with("apply_seconds_count 2\n"));
+    }
+}
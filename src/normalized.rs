@@ -0,0 +1,78 @@
+use std::io::{ self, BufWriter, Write };
+
+use crate::{ ingest::InputFormat, provenance::Provenance, types::Transaction };
+
+// The applied transactions written back in the input format, with the canonical columns and
+// amounts whatever the input's aliases and notation, so other tools can read them.
+pub struct NormalizedFile<W: io::Write> {
+    sink: Sink<W>,
+}
+
+enum Sink<W: io::Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json(BufWriter<W>),
+}
+
+impl<W: io::Write> NormalizedFile<W> {
+    pub fn new(writer: W, format: InputFormat) -> Self {
+        let sink = match format {
+            InputFormat::Csv => Sink::Csv(Box::new(csv::Writer::from_writer(writer))),
+            InputFormat::Json => Sink::Json(BufWriter::new(writer)),
+        };
+
+        NormalizedFile { sink }
+    }
+
+    pub fn write(&mut self, provenance: &Provenance, tx: &Transaction) {
+        let result = match &mut self.sink {
+            Sink::Csv(writer) => writer.serialize(tx).map_err(io::Error::from),
+            Sink::Json(writer) => {
+                serde_json::to_writer(&mut *writer, tx)
+                    .map_err(io::Error::from)
+                    .and_then(|()| writer.write_all(b"\n"))
+            }
+        };
+
+        if let Err(err) = result {
+            log::error!("Failed to write the normalized transaction from {}: {}", provenance, err);
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Csv(writer) => writer.flush(),
+            Sink::Json(writer) => writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::types::TransactionType;
+
+    #[test]
+    fn test_json_lines() {
+        let provenance = Provenance { source: "stdin".into(), line: 2, offset: 0 };
+        let mut bytes = vec![];
+        let mut file = NormalizedFile::new(&mut bytes, InputFormat::Json);
+
+        file.write(&provenance, &Transaction::new(1, 1, TransactionType::Deposit(dec!(2.50))));
+        file.write(&provenance, &Transaction::new(1, 1, TransactionType::Dispute));
+        file.flush().unwrap();
+        drop(file);
+
+        let lines: Vec<Transaction> = String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines, vec![
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(2.5))),
+            Transaction::new(1, 1, TransactionType::Dispute)
+        ]);
+    }
+}
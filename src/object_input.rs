@@ -0,0 +1,153 @@
+use std::{ env, io, ops::Range, sync::Arc, time::Duration };
+
+use object_store::{ path::Path, ObjectStore };
+use tokio::runtime::Handle;
+use url::Url;
+
+const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+const RETRIES: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+// The prefixes of the environment variables object_store reads its settings from
+const ENV_PREFIXES: [&str; 3] = ["AWS_", "GOOGLE_", "AZURE_"];
+
+/// Streams an object of a store, e.g. `s3://bucket/drops/2024-01-01.csv`, through range reads of
+/// a few megabytes each, so the object is never held in memory or on disk as a whole. A range read
+/// that fails is retried on its own, on top of the retries of the store's client. Reading blocks
+/// on the runtime it was opened in, so it must happen on a blocking thread of it.
+pub struct ObjectReader {
+    store: Arc<dyn ObjectStore>,
+    location: Path,
+    runtime: Handle,
+    chunk_size: u64,
+    size: Option<u64>,
+    position: u64,
+    chunk: Vec<u8>,
+    consumed: usize,
+}
+
+impl ObjectReader {
+    /// The credentials and settings of the store come from its environment variables, like
+    /// `AWS_ACCESS_KEY_ID`, `AWS_REGION` or `AWS_ENDPOINT`. Nothing is requested before the first read.
+    pub fn open(url: &str) -> io::Result<Self> {
+        let url = Url::parse(url).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let options = env::vars()
+            .filter(|(key, _)| ENV_PREFIXES.iter().any(|prefix| key.starts_with(prefix)))
+            .map(|(key, value)| (key.to_ascii_lowercase(), value));
+
+        let (store, location) = object_store::parse_url_opts(&url, options).map_err(io::Error::other)?;
+
+        Ok(ObjectReader::new(Arc::from(store), location, Handle::current(), CHUNK_SIZE))
+    }
+
+    pub fn new(store: Arc<dyn ObjectStore>, location: Path, runtime: Handle, chunk_size: u64) -> Self {
+        ObjectReader {
+            store,
+            location,
+            runtime,
+            chunk_size: chunk_size.max(1),
+            size: None,
+            position: 0,
+            chunk: vec![],
+            consumed: 0,
+        }
+    }
+
+    fn fetch(&mut self) -> io::Result<bool> {
+        let size = match self.size {
+            Some(size) => size,
+            None => {
+                let meta = self.runtime.block_on(self.store.head(&self.location)).map_err(io::Error::other)?;
+                *self.size.insert(meta.size)
+            }
+        };
+
+        if self.position >= size {
+            return Ok(false);
+        }
+
+        let range = self.position..(self.position + self.chunk_size).min(size);
+        let chunk = self.runtime.block_on(get_range(self.store.as_ref(), &self.location, range))?;
+
+        if chunk.is_empty() {
+            let err = format!("{} ended at byte {} instead of {}", self.location, self.position, size);
+
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, err));
+        }
+
+        self.position += chunk.len() as u64;
+        self.chunk = chunk;
+        self.consumed = 0;
+
+        Ok(true)
+    }
+}
+
+async fn get_range(store: &dyn ObjectStore, location: &Path, range: Range<u64>) -> io::Result<Vec<u8>> {
+    let mut attempt = 0;
+
+    loop {
+        match store.get_range(location, range.clone()).await {
+            Ok(bytes) => {
+                return Ok(bytes.to_vec());
+            }
+            Err(err) if attempt < RETRIES && !matches!(err, object_store::Error::NotFound { .. }) => {
+                attempt += 1;
+
+                log::warn!("Retrying bytes {}..{} of {} ({}): {}", range.start, range.end, location, attempt, err);
+
+                tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+            }
+            Err(err) => {
+                return Err(io::Error::other(err));
+            }
+        }
+    }
+}
+
+impl io::Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.consumed == self.chunk.len() && !self.fetch()? {
+            return Ok(0);
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.consumed);
+
+        buf[..read].copy_from_slice(&self.chunk[self.consumed..self.consumed + read]);
+        self.consumed += read;
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use object_store::{ memory::InMemory, PutPayload };
+    use tokio::runtime::Runtime;
+
+    use super::*;
+
+    #[test]
+    fn test_range_reads() {
+        let runtime = Runtime::new().unwrap();
+        let store = Arc::new(InMemory::new());
+        let location = Path::from("drops/transactions.csv");
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\n".repeat(10);
+
+        runtime
+            .block_on(store.put(&location, PutPayload::from(data.clone().into_bytes())))
+            .unwrap();
+
+        let mut reader = ObjectReader::new(store.clone(), location, runtime.handle().clone(), 7);
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+
+        assert_eq!(read, data);
+
+        let mut missing = ObjectReader::new(store, Path::from("missing.csv"), runtime.handle().clone(), 7);
+
+        assert!(missing.read_to_string(&mut String::new()).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::types::{ Account, ClientId };
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    Created {
+        client_id: ClientId,
+    },
+    FirstDeposit {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    Locked {
+        client_id: ClientId,
+        tx_id: u32,
+    },
+    Unlocked {
+        client_id: ClientId,
+        tx_id: u32,
+    },
+    Closed {
+        client_id: ClientId,
+    },
+    Deleted {
+        client_id: ClientId,
+        tx_id: u32,
+    },
+    Restored {
+        client_id: ClientId,
+        tx_id: u32,
+    },
+    Dormant {
+        client_id: ClientId,
+        last_activity: u64,
+        days: u64,
+    },
+    Alert {
+        client_id: ClientId,
+        tx_id: u32,
+        rule: String,
+        available: Decimal,
+        held: Decimal,
+    },
+    Adjusted {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+        reason: String,
+    },
+    HoldExpired {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    DepositReleased {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    DisputeExpired {
+        client_id: ClientId,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    /// The client disputed transactions already under dispute more times than allowed
+    Escalated {
+        client_id: ClientId,
+        tx_id: u32,
+        duplicate_disputes: u32,
+    },
+}
+
+impl LifecycleEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LifecycleEvent::Created { .. } => "created",
+            LifecycleEvent::FirstDeposit { .. } => "first_deposit",
+            LifecycleEvent::Locked { .. } => "locked",
+            LifecycleEvent::Unlocked { .. } => "unlocked",
+            LifecycleEvent::Closed { .. } => "closed",
+            LifecycleEvent::Deleted { .. } => "deleted",
+            LifecycleEvent::Restored { .. } => "restored",
+            LifecycleEvent::Dormant { .. } => "dormant",
+            LifecycleEvent::Alert { .. } => "alert",
+            LifecycleEvent::Adjusted { .. } => "adjusted",
+            LifecycleEvent::HoldExpired { .. } => "hold_expired",
+            LifecycleEvent::DepositReleased { .. } => "deposit_released",
+            LifecycleEvent::DisputeExpired { .. } => "dispute_expired",
+            LifecycleEvent::Escalated { .. } => "escalated",
+        }
+    }
+}
+
+pub trait AccountObserver: Send {
+    fn on_lifecycle_event(&mut self, event: &LifecycleEvent);
+
+    /// Called with the new balance of an account after a transaction or seed changed it.
+    fn on_balance_change(&mut self, _account: &Account) {}
+}
+
+pub struct LogObserver;
+
+impl AccountObserver for LogObserver {
+    fn on_lifecycle_event(&mut self, event: &LifecycleEvent) {
+        match event {
+            LifecycleEvent::Alert { .. } => log::warn!("Alert {:?}", event),
+            LifecycleEvent::Adjusted { .. } => log::warn!("Adjustment {:?}", event),
+            LifecycleEvent::Escalated { .. } => log::warn!("Escalation {:?}", event),
+            _ => log::info!("Lifecycle event {:?}", event),
+        }
+    }
+}
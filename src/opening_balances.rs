@@ -0,0 +1,288 @@
+use std::{ collections::HashSet, fmt, fs::File, io::{ self, BufRead, BufReader }, path::Path };
+
+use csv::{ ReaderBuilder, Trim };
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::types::{ Account, ClientId };
+
+#[derive(Debug)]
+pub enum OpeningBalanceError {
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    DuplicateClient(ClientId),
+    NegativeHeld(ClientId),
+    Inconsistent {
+        client_id: ClientId,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+    },
+}
+
+impl fmt::Display for OpeningBalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpeningBalanceError::Csv(err) => write!(f, "{}", err),
+            OpeningBalanceError::Json(err) => write!(f, "{}", err),
+            OpeningBalanceError::DuplicateClient(client_id) => {
+                write!(f, "client {} appears more than once", client_id)
+            }
+            OpeningBalanceError::NegativeHeld(client_id) => {
+                write!(f, "client {} has a negative held balance", client_id)
+            }
+            OpeningBalanceError::Inconsistent { client_id, available, held, total } =>
+                write!(
+                    f,
+                    "client {} total {} does not match available {} + held {}",
+                    client_id,
+                    total,
+                    available,
+                    held
+                ),
+        }
+    }
+}
+
+impl std::error::Error for OpeningBalanceError {}
+
+impl From<csv::Error> for OpeningBalanceError {
+    fn from(err: csv::Error) -> Self {
+        OpeningBalanceError::Csv(err)
+    }
+}
+
+impl From<serde_json::Error> for OpeningBalanceError {
+    fn from(err: serde_json::Error) -> Self {
+        OpeningBalanceError::Json(err)
+    }
+}
+
+/// Loads the accounts of an output file, CSV or JSON by the extension: `.json` for an array of
+/// objects and `.ndjson` or `.jsonl` for an object per line.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Account>, OpeningBalanceError> {
+    let file = File::open(&path).map_err(csv::Error::from)?;
+
+    match path.as_ref().extension().and_then(|extension| extension.to_str()) {
+        Some("json") => load_json(BufReader::new(file)),
+        Some("ndjson" | "jsonl") => load_json_lines(BufReader::new(file)),
+        _ => load_from_reader(file),
+    }
+}
+
+/// The extra columns of an output (tiers, credit, activity, ...) are ignored, and the sections
+/// after the accounts (system accounts, fee summary) are ignored too, they start after a blank
+/// line. A row of the accounts with a missing field is an error, with its line.
+pub fn load_from_reader<R: io::Read>(rdr: R) -> Result<Vec<Account>, OpeningBalanceError> {
+    let section = AccountsSection { inner: BufReader::new(rdr), line: vec![], read: 0, ended: false };
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(section);
+    let headers = reader.headers()?.clone();
+    let mut records = vec![];
+
+    for record in reader.records() {
+        let account = record.and_then(|record| record.deserialize::<Account>(Some(&headers)));
+
+        records.push(account.map_err(OpeningBalanceError::from));
+    }
+
+    collect(records)
+}
+
+// The lines of an output up to the blank line ending its accounts
+struct AccountsSection<R> {
+    inner: R,
+    line: Vec<u8>,
+    // What was already read of `line`
+    read: usize,
+    ended: bool,
+}
+
+impl<R: BufRead> io::Read for AccountsSection<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.line.len() {
+            self.line.clear();
+            self.read = 0;
+
+            if self.ended || self.inner.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(0);
+            }
+
+            if self.line.trim_ascii().is_empty() {
+                self.ended = true;
+                self.line.clear();
+                return Ok(0);
+            }
+        }
+
+        let read = (&self.line[self.read..]).read(buf)?;
+        self.read += read;
+
+        Ok(read)
+    }
+}
+
+pub fn load_json<R: io::Read>(rdr: R) -> Result<Vec<Account>, OpeningBalanceError> {
+    let mut deserializer = serde_json::Deserializer::from_reader(rdr);
+
+    // What follows the array is the sections after the accounts, it isn't read
+    let accounts = Vec::<Account>::deserialize(&mut deserializer)?;
+
+    collect(accounts.into_iter().map(Ok))
+}
+
+pub fn load_json_lines<R: BufRead>(rdr: R) -> Result<Vec<Account>, OpeningBalanceError> {
+    let mut accounts = vec![];
+
+    for line in rdr.lines() {
+        let line = line.map_err(csv::Error::from)?;
+
+        // The sections after the accounts start after an empty line
+        if line.trim().is_empty() {
+            break;
+        }
+
+        accounts.push(serde_json::from_str::<Account>(&line).map_err(OpeningBalanceError::from));
+    }
+
+    collect(accounts)
+}
+
+fn collect(
+    accounts: impl IntoIterator<Item = Result<Account, OpeningBalanceError>>
+) -> Result<Vec<Account>, OpeningBalanceError> {
+    let mut seen = HashSet::new();
+    let mut loaded = vec![];
+
+    for account in accounts {
+        let account = account?;
+
+        validate(&account)?;
+
+        if !seen.insert(account.client_id) {
+            return Err(OpeningBalanceError::DuplicateClient(account.client_id));
+        }
+
+        loaded.push(account);
+    }
+
+    Ok(loaded)
+}
+
+fn validate(account: &Account) -> Result<(), OpeningBalanceError> {
+    if account.held < Decimal::ZERO {
+        return Err(OpeningBalanceError::NegativeHeld(account.client_id));
+    }
+
+    if account.available + account.held != account.total {
+        return Err(OpeningBalanceError::Inconsistent {
+            client_id: account.client_id,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let input = "client, available, held, total, locked\n1, 1.5, 0.5, 2.0, false\n2, 0, 0, 0, true\n";
+
+        let accounts = load_from_reader(input.as_bytes()).unwrap();
+        assert_eq!(accounts, vec![
+            Account {
+                client_id: 1,
+                available: dec!(1.5),
+                held: dec!(0.5),
+                total: dec!(2.0),
+                locked: false,
+            },
+            Account {
+                client_id: 2,
+                available: dec!(0),
+                held: dec!(0),
+                total: dec!(0),
+                locked: true,
+            }
+        ]);
+    }
+
+    #[test]
+    fn test_load_inconsistent_total() {
+        let input = "client,available,held,total,locked\n1,1.5,0.5,3.0,false\n";
+
+        let result = load_from_reader(input.as_bytes());
+        assert!(matches!(result, Err(OpeningBalanceError::Inconsistent { client_id: 1, .. })));
+    }
+
+    #[test]
+    fn test_load_negative_held() {
+        let input = "client,available,held,total,locked\n1,2.0,-1.0,1.0,false\n";
+
+        let result = load_from_reader(input.as_bytes());
+        assert!(matches!(result, Err(OpeningBalanceError::NegativeHeld(1))));
+    }
+
+    #[test]
+    fn test_load_duplicate_client() {
+        let input = "client,available,held,total,locked\n1,1,0,1,false\n1,2,0,2,false\n";
+
+        let result = load_from_reader(input.as_bytes());
+        assert!(matches!(result, Err(OpeningBalanceError::DuplicateClient(1))));
+    }
+
+    #[test]
+    fn test_load_malformed() {
+        let input = "client,available,held,total,locked\n1,1,0,1,maybe\n";
+
+        let result = load_from_reader(input.as_bytes());
+        assert!(matches!(result, Err(OpeningBalanceError::Csv(_))));
+    }
+
+    #[test]
+    fn test_load_short_row() {
+        let input = "client,available,held,total,locked\n1,1,0,1,false\n2,1,0\n3,1,0,1,false\n";
+
+        let err = load_from_reader(input.as_bytes()).unwrap_err();
+        assert!(matches!(err, OpeningBalanceError::Csv(_)));
+        assert!(err.to_string().contains("line: 3"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_output_columns_and_sections() {
+        let input = "client,available,held,total,locked,tier\n1,1,0,1,false,high\n\naccount,balance\nfees,0.5\n";
+
+        let accounts = load_from_reader(input.as_bytes()).unwrap();
+        assert_eq!(accounts, vec![Account { available: dec!(1), total: dec!(1), ..Account::new(1) }]);
+    }
+
+    #[test]
+    fn test_load_json() {
+        let array = r#"[{"client":1,"available":"1.5","held":"0","total":"1.5","locked":false,"tier":"low"}]"#;
+        let lines = concat!(
+            r#"{"client":1,"available":"1.5","held":"0","total":"1.5","locked":false}"#,
+            "\n",
+            r#"{"client":2,"available":"0","held":"0","total":"0","locked":true}"#,
+            "\n\naccount,balance\nfees,0\n"
+        );
+
+        let account = Account { available: dec!(1.5), total: dec!(1.5), ..Account::new(1) };
+
+        assert_eq!(load_json(format!("{}\n\naccount,balance\n", array).as_bytes()).unwrap(), vec![account.clone()]);
+        assert_eq!(
+            load_json_lines(lines.as_bytes()).unwrap(),
+            vec![account, Account { locked: true, ..Account::new(2) }]
+        );
+        assert!(matches!(
+            load_json(r#"[{"client":1,"available":"1","held":"0","total":"2","locked":false}]"#.as_bytes()),
+            Err(OpeningBalanceError::Inconsistent { client_id: 1, .. })
+        ));
+    }
+}
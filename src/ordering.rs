@@ -0,0 +1,463 @@
+use std::{ cmp::{ Ordering, Reverse }, collections::{ BinaryHeap, HashMap }, fmt };
+
+use crate::types::{ ClientId, Transaction };
+
+#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone)]
+pub struct Sequenced {
+    pub seq: u64,
+    pub tx: Transaction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfOrder {
+    pub client_id: ClientId,
+    pub expected: u64,
+    pub received: u64,
+}
+
+impl fmt::Display for OutOfOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} expected sequence {} but received {}",
+            self.client_id,
+            self.expected,
+            self.received
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Sequencer {
+    next: HashMap<ClientId, u64>,
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Sequencer::default()
+    }
+
+    pub fn assign(&mut self, tx: Transaction) -> Sequenced {
+        let next = self.next.entry(tx.client_id).or_default();
+        let seq = *next;
+
+        *next += 1;
+
+        Sequenced { seq, tx }
+    }
+}
+
+#[derive(Default)]
+pub struct SequenceGuard {
+    expected: HashMap<ClientId, u64>,
+}
+
+impl SequenceGuard {
+    pub fn new() -> Self {
+        SequenceGuard::default()
+    }
+
+    pub fn check(&mut self, sequenced: &Sequenced) -> Result<(), OutOfOrder> {
+        let client_id = sequenced.tx.client_id;
+        let expected = self.expected.entry(client_id).or_default();
+
+        if sequenced.seq != *expected {
+            return Err(OutOfOrder { client_id, expected: *expected, received: sequenced.seq });
+        }
+
+        *expected += 1;
+
+        Ok(())
+    }
+
+    /// The next sequence number of a client moving to another guard, see [`SequenceGuard::put_client`].
+    pub fn take_client(&mut self, client_id: ClientId) -> Option<u64> {
+        self.expected.remove(&client_id)
+    }
+
+    pub fn put_client(&mut self, client_id: ClientId, expected: u64) {
+        self.expected.insert(client_id, expected);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceConflict {
+    /// Sequence numbers were skipped, the missing records may still arrive late
+    Gap {
+        client_id: ClientId,
+        last: u64,
+        received: u64,
+    },
+    /// The same sequence number as the previous record
+    Duplicate {
+        client_id: ClientId,
+        seq: u64,
+    },
+    /// A sequence number lower than the previous record's
+    Late {
+        client_id: ClientId,
+        last: u64,
+        received: u64,
+    },
+}
+
+impl SequenceConflict {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SequenceConflict::Gap { .. } => "gap",
+            SequenceConflict::Duplicate { .. } => "duplicate",
+            SequenceConflict::Late { .. } => "late",
+        }
+    }
+}
+
+impl fmt::Display for SequenceConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceConflict::Gap { client_id, last, received } => {
+                write!(f, "client {} skipped from sequence {} to {}", client_id, last, received)
+            }
+            SequenceConflict::Duplicate { client_id, seq } => {
+                write!(f, "client {} received sequence {} twice", client_id, seq)
+            }
+            SequenceConflict::Late { client_id, last, received } => {
+                write!(f, "client {} received sequence {} after {}", client_id, received, last)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceSummary {
+    pub gaps: usize,
+    /// Sequence numbers skipped by the gaps
+    pub missing: u64,
+    pub duplicates: usize,
+    pub late: usize,
+}
+
+impl fmt::Display for SequenceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} gaps ({} missing), {} duplicates, {} late",
+            self.gaps,
+            self.missing,
+            self.duplicates,
+            self.late
+        )
+    }
+}
+
+// Checks the `seq` column set by upstream, unlike `Sequencer` which numbers the records itself.
+// Each client's sequence starts at the first number seen for it, so feeds don't have to start at 0.
+#[derive(Default)]
+pub struct UpstreamSequence {
+    last: HashMap<ClientId, u64>,
+    summary: SequenceSummary,
+}
+
+impl UpstreamSequence {
+    pub fn new() -> Self {
+        UpstreamSequence::default()
+    }
+
+    pub fn check(&mut self, client_id: ClientId, seq: u64) -> Option<SequenceConflict> {
+        let Some(last) = self.last.get_mut(&client_id) else {
+            self.last.insert(client_id, seq);
+            return None;
+        };
+
+        let conflict = match seq {
+            seq if seq == *last => SequenceConflict::Duplicate { client_id, seq },
+            seq if seq < *last => SequenceConflict::Late { client_id, last: *last, received: seq },
+            seq if seq > *last + 1 => SequenceConflict::Gap { client_id, last: *last, received: seq },
+            seq => {
+                *last = seq;
+                return None;
+            }
+        };
+
+        match conflict {
+            SequenceConflict::Gap { .. } => {
+                self.summary.gaps += 1;
+                self.summary.missing += seq - *last - 1;
+                *last = seq;
+            }
+            SequenceConflict::Duplicate { .. } => self.summary.duplicates += 1,
+            SequenceConflict::Late { .. } => self.summary.late += 1,
+        }
+
+        Some(conflict)
+    }
+
+    pub fn summary(&self) -> SequenceSummary {
+        self.summary
+    }
+
+    /// Puts back the conflicts counted before a replay, the replayed transactions aren't counted twice.
+    pub fn restore_summary(&mut self, summary: SequenceSummary) {
+        self.summary = summary;
+    }
+
+    /// The last sequence number of a client moving to another engine, its conflicts stay counted here.
+    pub fn take_client(&mut self, client_id: ClientId) -> Option<u64> {
+        self.last.remove(&client_id)
+    }
+
+    pub fn put_client(&mut self, client_id: ClientId, last: u64) {
+        self.last.insert(client_id, last);
+    }
+}
+
+struct Held<T> {
+    timestamp: u64,
+    arrival: u64,
+    item: T,
+}
+
+impl<T> Held<T> {
+    fn key(&self) -> (u64, u64) {
+        (self.timestamp, self.arrival)
+    }
+}
+
+impl<T> PartialEq for Held<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<T> Eq for Held<T> {}
+
+impl<T> PartialOrd for Held<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Held<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+// Holds records back until the latest timestamp seen is `window` seconds past theirs, then releases
+// them in timestamp order, so records arriving up to `window` seconds late take their place.
+// Records without a timestamp keep their place in the arrival order.
+pub struct Reorder<T> {
+    window: u64,
+    latest: u64,
+    arrivals: u64,
+    held: BinaryHeap<Reverse<Held<T>>>,
+}
+
+impl<T> Reorder<T> {
+    pub fn new(window: u64) -> Self {
+        Reorder { window, latest: 0, arrivals: 0, held: BinaryHeap::new() }
+    }
+
+    pub fn push(&mut self, timestamp: Option<u64>, item: T) {
+        let timestamp = timestamp.unwrap_or(self.latest);
+
+        self.latest = self.latest.max(timestamp);
+        self.arrivals += 1;
+        self.held.push(Reverse(Held { timestamp, arrival: self.arrivals, item }));
+    }
+
+    /// The records out of the window, in timestamp order
+    pub fn ready(&mut self) -> Vec<T> {
+        let mut ready = vec![];
+
+        while let Some(Reverse(held)) = self.held.peek() {
+            if held.timestamp.saturating_add(self.window) > self.latest {
+                break;
+            }
+
+            ready.extend(self.held.pop().map(|Reverse(held)| held.item));
+        }
+
+        ready
+    }
+
+    /// Every record still held, in timestamp order
+    pub fn finish(mut self) -> Vec<T> {
+        let mut rest = Vec::with_capacity(self.held.len());
+
+        while let Some(Reverse(held)) = self.held.pop() {
+            rest.push(held.item);
+        }
+
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{ engine::Engine, shard::ShardedEngine, types::TransactionType };
+
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize
+        }
+    }
+
+    // Every client alternates deposits and withdrawals of its whole balance, a withdrawal applied
+    // before its deposit is rejected
+    fn input() -> Vec<Transaction> {
+        let mut count: HashMap<ClientId, usize> = HashMap::new();
+
+        (0..2000u32)
+            .map(|tx_id| {
+                let client_id = ((tx_id * 31) % 97) as ClientId;
+                let count = count.entry(client_id).or_default();
+                let tx_type = match *count % 2 {
+                    0 => TransactionType::Deposit(dec!(1)),
+                    _ => TransactionType::Withdrawal(dec!(1)),
+                };
+
+                *count += 1;
+
+                Transaction::new(client_id, tx_id, tx_type)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sequencer_per_client() {
+        let mut sequencer = Sequencer::new();
+
+        let seqs: Vec<u64> = [1, 2, 1, 1, 2]
+            .into_iter()
+            .enumerate()
+            .map(|(tx_id, client_id)| {
+                sequencer.assign(
+                    Transaction::new(client_id, tx_id as u32, TransactionType::Deposit(dec!(1)))
+                ).seq
+            })
+            .collect();
+
+        assert_eq!(seqs, vec![0, 0, 1, 2, 1]);
+    }
+
+    #[test]
+    fn test_order_preserved_under_any_shard_scheduling() {
+        for seed in 0..20 {
+            let mut rng = Lcg(seed);
+            let workers = 1 + rng.next() % 8;
+            let mut sharded = ShardedEngine::new(workers, |_| Engine::builder().build());
+            let mut results = vec![];
+
+            // Batches reach the workers and results are taken at random points
+            for tx in input() {
+                let tag = (tx.client_id, tx.tx_id);
+
+                sharded.submit(tx, tag);
+
+                match rng.next() % 16 {
+                    0 => sharded.flush(),
+                    1 => results.extend(sharded.results()),
+                    2 => std::thread::yield_now(),
+                    _ => {}
+                }
+            }
+
+            let (rest, _, degraded) = sharded.finish();
+            assert!(degraded.is_empty());
+            results.extend(rest);
+
+            // A client's results come back in the order its worker applied them
+            let mut applied: HashMap<ClientId, Vec<u32>> = HashMap::new();
+            for ((client_id, tx_id), result) in results {
+                assert_eq!(result, Ok(()), "transaction {} with {} workers", tx_id, workers);
+                applied.entry(client_id).or_default().push(tx_id);
+            }
+
+            let mut expected: HashMap<ClientId, Vec<u32>> = HashMap::new();
+            for tx in input() {
+                expected.entry(tx.client_id).or_default().push(tx.tx_id);
+            }
+
+            assert_eq!(applied, expected);
+        }
+    }
+
+    #[test]
+    fn test_upstream_sequence() {
+        let mut upstream = UpstreamSequence::new();
+
+        let input = [(1, 10), (2, 0), (1, 11), (1, 14), (1, 14), (1, 12), (1, 15)];
+        let conflicts: Vec<Option<SequenceConflict>> = input
+            .into_iter()
+            .map(|(client_id, seq)| upstream.check(client_id, seq))
+            .collect();
+
+        assert_eq!(conflicts, vec![
+            None,
+            None,
+            None,
+            Some(SequenceConflict::Gap { client_id: 1, last: 11, received: 14 }),
+            Some(SequenceConflict::Duplicate { client_id: 1, seq: 14 }),
+            Some(SequenceConflict::Late { client_id: 1, last: 14, received: 12 }),
+            None
+        ]);
+        assert_eq!(
+            upstream.summary(),
+            SequenceSummary { gaps: 1, missing: 2, duplicates: 1, late: 1 }
+        );
+    }
+
+    #[test]
+    fn test_guard_rejects_out_of_order() {
+        let mut sequencer = Sequencer::new();
+        let mut guard = SequenceGuard::new();
+
+        let first = sequencer.assign(Transaction::new(1, 1, TransactionType::Deposit(dec!(1))));
+        let second = sequencer.assign(Transaction::new(1, 2, TransactionType::Deposit(dec!(1))));
+        let other = sequencer.assign(Transaction::new(2, 3, TransactionType::Deposit(dec!(1))));
+
+        assert_eq!(guard.check(&other), Ok(()));
+        assert_eq!(
+            guard.check(&second),
+            Err(OutOfOrder { client_id: 1, expected: 0, received: 1 })
+        );
+        assert_eq!(guard.check(&first), Ok(()));
+        assert_eq!(guard.check(&second), Ok(()));
+        assert_eq!(
+            guard.check(&first),
+            Err(OutOfOrder { client_id: 1, expected: 2, received: 0 })
+        );
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut reorder = Reorder::new(10);
+
+        reorder.push(Some(100), 1);
+        reorder.push(Some(95), 2);
+        reorder.push(None, 3);
+        assert!(reorder.ready().is_empty());
+
+        reorder.push(Some(108), 4);
+        assert_eq!(reorder.ready(), vec![2]);
+
+        reorder.push(Some(90), 5);
+        reorder.push(Some(112), 6);
+        assert_eq!(reorder.ready(), vec![5, 1, 3]);
+        assert_eq!(reorder.finish(), vec![4, 6]);
+
+        let mut reorder = Reorder::new(0);
+
+        reorder.push(Some(5), 1);
+        assert_eq!(reorder.ready(), vec![1]);
+        reorder.push(Some(3), 2);
+        assert_eq!(reorder.ready(), vec![2]);
+    }
+}
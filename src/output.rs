@@ -0,0 +1,741 @@
+use std::{ collections::HashSet, fmt, fs::{ self, File }, io::{ self, Write }, path::Path, str::FromStr, thread };
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::io::{ AsyncWrite, AsyncWriteExt };
+
+use crate::{ manifest::OutputDigest, policy::RiskTier, types::{ custom_serde, Account, ClientId } };
+
+// Rows serialized at a time when streaming, only their bytes are buffered
+const STREAM_CHUNK: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A JSON array of account objects
+    Json,
+    /// One account object per line
+    Ndjson,
+    /// A Parquet file with decimal balance columns
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Csv => f.write_str("csv"),
+            OutputFormat::Json => f.write_str("json"),
+            OutputFormat::Ndjson => f.write_str("ndjson"),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => f.write_str("parquet"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("parquet output needs a build with --features parquet".to_string()),
+            _ => Err(format!("unknown output format {}, expected csv, json, ndjson or parquet", s)),
+        }
+    }
+}
+
+/// Keeps only the accounts needing a review in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountFilter {
+    Locked,
+    /// Holding funds, for an open dispute or a hold
+    Disputed,
+    /// A negative available or total balance
+    Negative,
+}
+
+impl AccountFilter {
+    pub fn matches(&self, account: &Account) -> bool {
+        match self {
+            AccountFilter::Locked => account.locked,
+            AccountFilter::Disputed => !account.held.is_zero(),
+            AccountFilter::Negative => account.available < Decimal::ZERO || account.total < Decimal::ZERO,
+        }
+    }
+}
+
+impl fmt::Display for AccountFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountFilter::Locked => f.write_str("locked"),
+            AccountFilter::Disputed => f.write_str("disputed"),
+            AccountFilter::Negative => f.write_str("negative"),
+        }
+    }
+}
+
+impl FromStr for AccountFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "locked" => Ok(AccountFilter::Locked),
+            "disputed" => Ok(AccountFilter::Disputed),
+            "negative" => Ok(AccountFilter::Negative),
+            _ => Err(format!("unknown account filter {}, expected locked, disputed or negative", s)),
+        }
+    }
+}
+
+/// An inclusive range of client ids, e.g. `100-200`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientRange {
+    pub first: ClientId,
+    pub last: ClientId,
+}
+
+impl ClientRange {
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        (self.first..=self.last).contains(&client_id)
+    }
+}
+
+impl fmt::Display for ClientRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+impl FromStr for ClientRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = s.split_once('-').ok_or("expected FIRST-LAST, e.g. 100-200")?;
+        let first = first.trim().parse::<ClientId>().map_err(|err| format!("invalid first client {}: {}", first, err))?;
+        let last = last.trim().parse::<ClientId>().map_err(|err| format!("invalid last client {}: {}", last, err))?;
+
+        if first > last {
+            return Err(format!("the range {}-{} is empty, the first client is after the last", first, last));
+        }
+
+        Ok(ClientRange { first, last })
+    }
+}
+
+/// Keeps only the accounts of some clients in the output, listed or in a range. It keeps every
+/// account when empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientSelection {
+    clients: HashSet<ClientId>,
+    ranges: Vec<ClientRange>,
+}
+
+impl ClientSelection {
+    pub fn new(clients: impl IntoIterator<Item = ClientId>, ranges: Vec<ClientRange>) -> Self {
+        ClientSelection { clients: clients.into_iter().collect(), ranges }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty() && self.ranges.is_empty()
+    }
+
+    pub fn matches(&self, client_id: ClientId) -> bool {
+        self.is_empty() || self.clients.contains(&client_id) || self.ranges.iter().any(|range| range.contains(client_id))
+    }
+}
+
+// Optional columns appended after the account columns, only the enabled ones are written.
+#[derive(Debug, Default, Serialize)]
+pub struct ExtendedColumns {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier: Option<RiskTier>,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "custom_serde::serialize_optional_decimal")]
+    pub credit_limit: Option<Decimal>,
+    /// The part of the credit limit used, how far under zero the available balance is
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "custom_serde::serialize_optional_decimal")]
+    pub credit_used: Option<Decimal>,
+    /// The timestamp of the first transaction of the account, `Some(None)` when none had one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_activity: Option<Option<u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_activity: Option<Option<u64>>,
+}
+
+impl ExtendedColumns {
+    pub fn is_empty(&self) -> bool {
+        self.status.is_none() && self.tier.is_none() && self.credit_limit.is_none() && self.first_activity.is_none()
+    }
+}
+
+// Serializes the rows as one CSV, formatting contiguous chunks of them on this many threads into
+// buffers of their own. The buffers are joined in order, only the first one with the header, so the
+// output is the same whatever the thread count.
+pub fn write_csv<T: Serialize + Sync>(rows: &[T], threads: usize) -> csv::Result<Vec<u8>> {
+    let chunk_size = rows.len().div_ceil(threads.max(1)).max(1);
+
+    let chunks: Vec<csv::Result<Vec<u8>>> = thread::scope(|scope| {
+        let handles: Vec<_> = rows
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| scope.spawn(move || write_chunk(chunk, index == 0)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("output thread panicked"))
+            .collect()
+    });
+
+    let mut bytes = vec![];
+
+    for chunk in chunks {
+        bytes.extend(chunk?);
+    }
+
+    Ok(bytes)
+}
+
+// The extended columns are fields of the account object, only the enabled ones.
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    #[serde(flatten)]
+    account: &'a Account,
+    #[serde(flatten)]
+    columns: &'a ExtendedColumns,
+}
+
+// Serializes the accounts as a JSON array, or as JSON Lines, with the same rounded decimal strings
+// as the CSV output.
+pub fn write_json(rows: &[(Account, ExtendedColumns)], lines: bool) -> serde_json::Result<Vec<u8>> {
+    write_json_rows(rows.iter().map(|(account, columns)| JsonRow { account, columns }), lines)
+}
+
+/// Serializes any rows like `write_json`.
+pub fn write_json_rows<T: Serialize>(rows: impl Iterator<Item = T>, lines: bool) -> serde_json::Result<Vec<u8>> {
+    let mut bytes = vec![];
+
+    if !lines {
+        serde_json::to_writer(&mut bytes, &rows.collect::<Vec<_>>())?;
+        bytes.push(b'\n');
+
+        return Ok(bytes);
+    }
+
+    for row in rows {
+        serde_json::to_writer(&mut bytes, &row)?;
+        bytes.push(b'\n');
+    }
+
+    Ok(bytes)
+}
+
+// Writes a temporary file next to the path and renames it over the path once it's complete, so
+// readers never see a partial output and a failed run leaves the previous one in place.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+
+    let result = File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+/// Writes the accounts with their extended columns to `writer` a chunk at a time as they're
+/// formatted, so the memory used doesn't grow with the number of accounts, followed by `trailer`.
+/// The bytes are the same as with `write_csv` or `write_json`, the digest returned is the one of
+/// what was written.
+pub async fn stream_rows<W: AsyncWrite + Unpin>(
+    accounts: &[Account],
+    columns: impl Fn(&Account) -> ExtendedColumns,
+    format: OutputFormat,
+    trailer: &[u8],
+    writer: &mut W
+) -> io::Result<OutputDigest> {
+    let mut digest = OutputDigest::new();
+    let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+    let mut bytes = vec![];
+    let mut first = true;
+
+    if format == OutputFormat::Json {
+        bytes.push(b'[');
+    }
+
+    for accounts in accounts.chunks(STREAM_CHUNK) {
+        chunk.extend(accounts.iter().map(|account| (account, columns(account))));
+
+        match format {
+            OutputFormat::Csv => bytes.extend(write_chunk(&chunk, first)?),
+            OutputFormat::Json | OutputFormat::Ndjson => {
+                for (index, (account, columns)) in chunk.iter().enumerate() {
+                    if format == OutputFormat::Json && !(first && index == 0) {
+                        bytes.push(b',');
+                    }
+
+                    serde_json::to_writer(&mut bytes, &JsonRow { account, columns })?;
+
+                    if format == OutputFormat::Ndjson {
+                        bytes.push(b'\n');
+                    }
+                }
+            }
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "parquet output can't be streamed"));
+            }
+        }
+
+        digest.update(&bytes);
+        writer.write_all(&bytes).await?;
+
+        bytes.clear();
+        chunk.clear();
+        first = false;
+    }
+
+    if format == OutputFormat::Json {
+        bytes.extend_from_slice(b"]\n");
+    }
+
+    bytes.extend_from_slice(trailer);
+    digest.update(&bytes);
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+
+    Ok(digest)
+}
+
+// Streams the rows into a temporary file renamed over the path once it's complete, like
+// `write_atomic`.
+pub async fn stream_atomic(
+    path: &Path,
+    accounts: &[Account],
+    columns: impl Fn(&Account) -> ExtendedColumns,
+    format: OutputFormat,
+    trailer: &[u8]
+) -> io::Result<OutputDigest> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp).await?;
+        let digest = stream_rows(accounts, columns, format, trailer, &mut file).await?;
+
+        file.sync_all().await?;
+        tokio::fs::rename(&temp, path).await?;
+
+        Ok(digest)
+    }.await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+/// Serializes the accounts as a Parquet file, the balances as `DECIMAL(38, s)` with the scale of
+/// the run's precision, rounded like the CSV columns. The extended columns enabled in the first row
+/// follow the account columns, the activity ones as UTC timestamps.
+#[cfg(feature = "parquet")]
+pub fn write_parquet(rows: &[(Account, ExtendedColumns)]) -> parquet::errors::Result<Vec<u8>> {
+    use std::sync::Arc;
+
+    use parquet::{
+        data_type::{ BoolType, ByteArray, ByteArrayType, FixedLenByteArray, FixedLenByteArrayType, Int32Type, Int64Type },
+        file::{ properties::WriterProperties, writer::SerializedFileWriter },
+        schema::parser::parse_message_type,
+    };
+
+    // A decimal has at most 28 places
+    let scale = custom_serde::rounding().precision.min(28);
+    let decimal = |value: Decimal| -> FixedLenByteArray {
+        let mut value = custom_serde::rounding().round(value);
+        value.rescale(scale);
+
+        FixedLenByteArray::from(value.mantissa().to_be_bytes().to_vec())
+    };
+
+    let enabled = rows.first().map(|(_, columns)| columns);
+    let status = enabled.is_some_and(|columns| columns.status.is_some());
+    let tier = enabled.is_some_and(|columns| columns.tier.is_some());
+    let credit = enabled.is_some_and(|columns| columns.credit_limit.is_some());
+    let activity = enabled.is_some_and(|columns| columns.first_activity.is_some());
+
+    // Unsigned client ids are stored in an int32, annotated with their width
+    let client_bits = ClientId::BITS;
+
+    let mut schema = format!(
+        "message accounts {{
+            required int32 client (INTEGER({client_bits}, false));
+            required fixed_len_byte_array(16) available (DECIMAL(38, {scale}));
+            required fixed_len_byte_array(16) held (DECIMAL(38, {scale}));
+            required fixed_len_byte_array(16) total (DECIMAL(38, {scale}));
+            required boolean locked;"
+    );
+
+    if status {
+        schema.push_str("optional binary status (STRING);");
+    }
+
+    if tier {
+        schema.push_str("optional binary tier (STRING);");
+    }
+
+    if credit {
+        schema.push_str(&format!("optional fixed_len_byte_array(16) credit_limit (DECIMAL(38, {scale}));"));
+        schema.push_str(&format!("optional fixed_len_byte_array(16) credit_used (DECIMAL(38, {scale}));"));
+    }
+
+    if activity {
+        schema.push_str("optional int64 first_activity (TIMESTAMP(MILLIS, true));");
+        schema.push_str("optional int64 last_activity (TIMESTAMP(MILLIS, true));");
+    }
+
+    schema.push('}');
+
+    // The values of an optional column and its definition levels, 0 for a null
+    fn optional<T>(values: impl Iterator<Item = Option<T>>) -> (Vec<T>, Vec<i16>) {
+        let mut present = vec![];
+        let mut levels = vec![];
+
+        for value in values {
+            levels.push(value.is_some() as i16);
+            present.extend(value);
+        }
+
+        (present, levels)
+    }
+
+    let mut writer = SerializedFileWriter::new(
+        vec![],
+        Arc::new(parse_message_type(&schema)?),
+        Arc::new(WriterProperties::builder().build())
+    )?;
+    let mut row_group = writer.next_row_group()?;
+
+    let clients: Vec<i32> = rows.iter().map(|(account, _)| account.client_id as i32).collect();
+    let mut column = row_group.next_column()?.expect("missing client column");
+    column.typed::<Int32Type>().write_batch(&clients, None, None)?;
+    column.close()?;
+
+    let balances: [fn(&Account) -> Decimal; 3] = [
+        |account| account.available,
+        |account| account.held,
+        |account| account.total,
+    ];
+
+    for balance in balances {
+        let values: Vec<FixedLenByteArray> = rows.iter().map(|(account, _)| decimal(balance(account))).collect();
+        let mut column = row_group.next_column()?.expect("missing balance column");
+        column.typed::<FixedLenByteArrayType>().write_batch(&values, None, None)?;
+        column.close()?;
+    }
+
+    let locked: Vec<bool> = rows.iter().map(|(account, _)| account.locked).collect();
+    let mut column = row_group.next_column()?.expect("missing locked column");
+    column.typed::<BoolType>().write_batch(&locked, None, None)?;
+    column.close()?;
+
+    let mut labels = vec![];
+
+    if status {
+        labels.push(optional(rows.iter().map(|(_, columns)| columns.status.map(ByteArray::from))));
+    }
+
+    if tier {
+        labels.push(optional(rows.iter().map(|(_, columns)| {
+            columns.tier.map(|tier| ByteArray::from(tier.to_string().as_str()))
+        })));
+    }
+
+    for (values, levels) in labels {
+        let mut column = row_group.next_column()?.expect("missing label column");
+        column.typed::<ByteArrayType>().write_batch(&values, Some(&levels), None)?;
+        column.close()?;
+    }
+
+    if credit {
+        for (values, levels) in [
+            optional(rows.iter().map(|(_, columns)| columns.credit_limit.map(decimal))),
+            optional(rows.iter().map(|(_, columns)| columns.credit_used.map(decimal))),
+        ] {
+            let mut column = row_group.next_column()?.expect("missing credit column");
+            column.typed::<FixedLenByteArrayType>().write_batch(&values, Some(&levels), None)?;
+            column.close()?;
+        }
+    }
+
+    if activity {
+        let millis = |timestamp: Option<Option<u64>>| timestamp.flatten().map(|seconds| seconds as i64 * 1000);
+
+        for (values, levels) in [
+            optional(rows.iter().map(|(_, columns)| millis(columns.first_activity))),
+            optional(rows.iter().map(|(_, columns)| millis(columns.last_activity))),
+        ] {
+            let mut column = row_group.next_column()?.expect("missing activity column");
+            column.typed::<Int64Type>().write_batch(&values, Some(&levels), None)?;
+            column.close()?;
+        }
+    }
+
+    row_group.close()?;
+
+    writer.into_inner()
+}
+
+fn write_chunk<T: Serialize>(rows: &[T], headers: bool) -> csv::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new().has_headers(headers).from_writer(vec![]);
+
+    for row in rows {
+        writer.serialize(row)?;
+    }
+
+    writer.into_inner().map_err(|err| err.into_error().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::{ manifest::OutputFile, types::Account };
+
+    fn write(columns: ExtendedColumns) -> String {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize((Account::new(1), columns)).unwrap();
+
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_status_column() {
+        let columns = ExtendedColumns { status: Some("dormant"), ..Default::default() };
+
+        assert_eq!(
+            write(columns),
+            "client,available,held,total,locked,status\n1,0,0,0,false,dormant\n"
+        );
+    }
+
+    #[test]
+    fn test_all_columns() {
+        let columns = ExtendedColumns {
+            status: Some("active"),
+            tier: Some(RiskTier::High),
+            credit_limit: Some(Decimal::ONE_HUNDRED),
+            credit_used: Some(Decimal::ZERO),
+            first_activity: Some(Some(100)),
+            last_activity: Some(Some(200)),
+        };
+
+        assert_eq!(
+            write(columns),
+            concat!(
+                "client,available,held,total,locked,status,tier,credit_limit,credit_used,first_activity,last_activity\n",
+                "1,0,0,0,false,active,high,100,0,100,200\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_activity_columns_without_timestamps() {
+        let columns = ExtendedColumns { first_activity: Some(None), last_activity: Some(None), ..Default::default() };
+
+        assert_eq!(
+            write(columns),
+            "client,available,held,total,locked,first_activity,last_activity\n1,0,0,0,false,,\n"
+        );
+    }
+
+    #[test]
+    fn test_account_filters() {
+        let locked = Account { locked: true, ..Account::new(1) };
+        let disputed = Account { held: Decimal::ONE, total: Decimal::ONE, ..Account::new(2) };
+        let negative = Account { available: Decimal::NEGATIVE_ONE, total: Decimal::NEGATIVE_ONE, ..Account::new(3) };
+
+        assert!(AccountFilter::Locked.matches(&locked));
+        assert!(!AccountFilter::Locked.matches(&disputed));
+        assert!(AccountFilter::Disputed.matches(&disputed));
+        assert!(!AccountFilter::Disputed.matches(&negative));
+        assert!(AccountFilter::Negative.matches(&negative));
+        assert!(!AccountFilter::Negative.matches(&Account::new(4)));
+        assert_eq!("disputed".parse(), Ok(AccountFilter::Disputed));
+        assert!("frozen".parse::<AccountFilter>().is_err());
+    }
+
+    #[test]
+    fn test_client_selection() {
+        let range = "100-200".parse::<ClientRange>().unwrap();
+        let selection = ClientSelection::new([1, 5], vec![range]);
+
+        assert!(selection.matches(5));
+        assert!(selection.matches(100));
+        assert!(selection.matches(200));
+        assert!(!selection.matches(2));
+        assert!(!selection.matches(201));
+        assert!(ClientSelection::default().matches(2));
+        assert!("200-100".parse::<ClientRange>().is_err());
+        assert!("100".parse::<ClientRange>().is_err());
+    }
+
+    #[test]
+    fn test_write_csv_threads() {
+        let accounts: Vec<Account> = (0..10)
+            .map(|client_id| Account { available: Decimal::from(client_id), ..Account::new(client_id) })
+            .collect();
+
+        let expected = write_csv(&accounts, 1).unwrap();
+        assert!(expected.starts_with(b"client,available,held,total,locked\n0,0,0,0,false\n"));
+
+        for threads in [2, 3, 16] {
+            assert_eq!(write_csv(&accounts, threads).unwrap(), expected);
+        }
+
+        assert!(write_csv::<Account>(&[], 4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_json() {
+        let rows = vec![
+            (
+                Account { available: Decimal::new(15, 1), total: Decimal::new(15, 1), ..Account::new(1) },
+                ExtendedColumns::default(),
+            ),
+            (Account::new(2), ExtendedColumns { tier: Some(RiskTier::High), ..Default::default() })
+        ];
+
+        assert_eq!(
+            String::from_utf8(write_json(&rows, false).unwrap()).unwrap(),
+            r#"[{"client":1,"available":"1.5","held":"0","total":"1.5","locked":false},"#.to_string() +
+                r#"{"client":2,"available":"0","held":"0","total":"0","locked":false,"tier":"high"}]"# + "\n"
+        );
+        assert_eq!(
+            String::from_utf8(write_json(&rows, true).unwrap()).unwrap(),
+            "{\"client\":1,\"available\":\"1.5\",\"held\":\"0\",\"total\":\"1.5\",\"locked\":false}\n\
+             {\"client\":2,\"available\":\"0\",\"held\":\"0\",\"total\":\"0\",\"locked\":false,\"tier\":\"high\"}\n"
+        );
+        assert_eq!(write_json(&[], false).unwrap(), b"[]\n");
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = std::env::temp_dir().join("transaction-engine-output-atomic");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounts.csv");
+
+        fs::write(&path, "previous").unwrap();
+        write_atomic(&path, b"client\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "client\n");
+        assert!(!dir.join("accounts.csv.tmp").exists());
+
+        // A directory can't be replaced by a file, the temporary file is removed
+        let taken = dir.join("taken");
+        fs::create_dir_all(&taken).unwrap();
+
+        assert!(write_atomic(&taken, b"client\n").is_err());
+        assert!(!dir.join("taken.tmp").exists());
+    }
+
+    #[test]
+    fn test_tier_column() {
+        let columns = ExtendedColumns { tier: Some(RiskTier::Standard), ..Default::default() };
+
+        assert_eq!(
+            write(columns),
+            "client,available,held,total,locked,tier\n1,0,0,0,false,standard\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_rows() {
+        let accounts: Vec<Account> = (0..10_000)
+            .map(|client_id| Account { available: Decimal::new(client_id as i64, 2), ..Account::new(client_id) })
+            .collect();
+        let tier = |account: &Account| ExtendedColumns {
+            tier: Some(if account.client_id.is_multiple_of(3) { RiskTier::High } else { RiskTier::Standard }),
+            ..Default::default()
+        };
+        let extended: Vec<_> = accounts.iter().map(|account| (account.clone(), tier(account))).collect();
+
+        let expected = [
+            (OutputFormat::Csv, write_csv(&extended, 1).unwrap()),
+            (OutputFormat::Json, write_json(&extended, false).unwrap()),
+            (OutputFormat::Ndjson, write_json(&extended, true).unwrap()),
+        ];
+
+        for (format, expected) in expected {
+            let mut bytes = vec![];
+            let digest = stream_rows(&accounts, tier, format, b"", &mut bytes).await.unwrap();
+
+            assert_eq!(bytes, expected, "{}", format);
+            assert_eq!(digest.finish("stdout"), OutputFile::of_bytes("stdout", &expected));
+        }
+
+        let mut bytes = vec![];
+        stream_rows(&[], |_| ExtendedColumns::default(), OutputFormat::Json, b"\nend", &mut bytes).await.unwrap();
+        assert_eq!(bytes, b"[]\n\nend");
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_write_parquet() {
+        use parquet::file::reader::{ FileReader, SerializedFileReader };
+
+        let rows = vec![
+            (
+                Account { available: Decimal::new(15, 1), total: Decimal::new(15, 1), ..Account::new(1) },
+                ExtendedColumns { tier: Some(RiskTier::High), first_activity: Some(Some(60)), last_activity: Some(None), ..Default::default() },
+            ),
+            (
+                Account { available: Decimal::new(-25, 5), held: Decimal::TEN, total: Decimal::new(99975, 4), locked: true, ..Account::new(2) },
+                ExtendedColumns { tier: Some(RiskTier::Low), first_activity: Some(None), last_activity: Some(None), ..Default::default() },
+            ),
+        ];
+
+        let dir = std::env::temp_dir().join("transaction-engine-output-parquet");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounts.parquet");
+        fs::write(&path, write_parquet(&rows).unwrap()).unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        let names: Vec<&str> = schema.columns().iter().map(|column| column.name()).collect();
+
+        assert_eq!(names, vec!["client", "available", "held", "total", "locked", "tier", "first_activity", "last_activity"]);
+        assert_eq!((schema.column(1).type_precision(), schema.column(1).type_scale()), (38, 4));
+
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+
+        assert_eq!(rows, vec![
+            "{client: 1, available: 1.5000, held: 0.0000, total: 1.5000, locked: false, tier: \"high\", \
+             first_activity: 1970-01-01 00:01:00 +00:00, last_activity: null}",
+            "{client: 2, available: -0.0002, held: 10.0000, total: 9.9975, locked: true, tier: \"low\", \
+             first_activity: null, last_activity: null}",
+        ]);
+    }
+}
@@ -0,0 +1,352 @@
+use std::{ collections::HashSet, fmt, fs, io, path::Path };
+
+use rust_decimal::Decimal;
+use serde::{ Deserialize, Serialize };
+
+use crate::{ engine::Engine, store::StateStore, types::{ ClientId, Transaction, TransactionType } };
+
+#[derive(Debug)]
+pub enum PendingError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    NotFound(u32),
+    AlreadyReviewed(u32),
+}
+
+impl fmt::Display for PendingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PendingError::Io(err) => write!(f, "{}", err),
+            PendingError::Json(err) => write!(f, "{}", err),
+            PendingError::NotFound(tx_id) => write!(f, "transaction {} is not pending", tx_id),
+            PendingError::AlreadyReviewed(tx_id) => {
+                write!(f, "transaction {} was already reviewed", tx_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PendingError {}
+
+impl From<io::Error> for PendingError {
+    fn from(err: io::Error) -> Self {
+        PendingError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for PendingError {
+    fn from(err: serde_json::Error) -> Self {
+        PendingError::Json(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Review {
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quarantined,
+    Approved,
+    Rejected,
+    Applied,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub tx: u32,
+    pub client: ClientId,
+    pub action: Action,
+    pub at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reviewer: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingEntry {
+    pub client: ClientId,
+    pub tx: u32,
+    pub tx_type: TransactionType,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub review: Option<Review>,
+}
+
+impl PendingEntry {
+    fn transaction(&self) -> Transaction {
+        Transaction {
+            timestamp: self.timestamp,
+            ..Transaction::new(self.client, self.tx, self.tx_type.clone())
+        }
+    }
+}
+
+// The approval queue survives between runs: transactions still quarantined at the end of a run
+// are saved here, reviewed with the `pending` commands and released by the next run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingQueue {
+    pub pending: Vec<PendingEntry>,
+    pub audit: Vec<AuditEntry>,
+}
+
+impl PendingQueue {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PendingError> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(PendingQueue::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PendingError> {
+        let json = serde_json::to_vec_pretty(self)?;
+
+        fs::write(path, json)?;
+
+        Ok(())
+    }
+
+    pub fn review(
+        &mut self,
+        tx_id: u32,
+        review: Review,
+        reviewer: Option<String>,
+        at: u64
+    ) -> Result<(), PendingError> {
+        let entry = self.pending
+            .iter_mut()
+            .find(|entry| entry.tx == tx_id)
+            .ok_or(PendingError::NotFound(tx_id))?;
+
+        if entry.review.is_some() {
+            return Err(PendingError::AlreadyReviewed(tx_id));
+        }
+
+        entry.review = Some(review);
+
+        self.audit.push(AuditEntry {
+            tx: tx_id,
+            client: entry.client,
+            action: match review {
+                Review::Approved => Action::Approved,
+                Review::Rejected => Action::Rejected,
+            },
+            at,
+            reviewer,
+        });
+
+        Ok(())
+    }
+
+    /// Parks the saved transactions in the engine again and applies the reviewed ones
+    pub fn restore<S: StateStore>(&mut self, engine: &mut Engine<S>, at: u64) {
+        for entry in self.pending.iter() {
+            engine.restore_quarantined(entry.transaction());
+        }
+
+        for entry in self.pending.iter().filter(|entry| entry.review.is_some()) {
+            let tx_type = match entry.review {
+                Some(Review::Approved) => TransactionType::Approve,
+                _ => TransactionType::Decline,
+            };
+
+            let release = Transaction { tx_type, timestamp: None, ..entry.transaction() };
+
+            match engine.add_transaction(release) {
+                Ok(()) => {
+                    self.audit.push(AuditEntry {
+                        tx: entry.tx,
+                        client: entry.client,
+                        action: Action::Applied,
+                        at,
+                        reviewer: None,
+                    });
+                }
+                Err(reason) => {
+                    log::error!("Failed to release pending transaction {}: {}", entry.tx, reason);
+                }
+            }
+        }
+    }
+
+    /// Replaces the queue with what is still quarantined in the engine
+    pub fn capture<S: StateStore>(&mut self, engine: &Engine<S>, at: u64) {
+        let known: HashSet<u32> = self.pending
+            .iter()
+            .map(|entry| entry.tx)
+            .collect();
+
+        self.pending = engine
+            .quarantined()
+            .map(|tx| PendingEntry {
+                client: tx.client_id,
+                tx: tx.tx_id,
+                tx_type: tx.tx_type.clone(),
+                timestamp: tx.timestamp,
+                review: None,
+            })
+            .collect();
+
+        for entry in self.pending.iter().filter(|entry| !known.contains(&entry.tx)) {
+            self.audit.push(AuditEntry {
+                tx: entry.tx,
+                client: entry.client,
+                action: Action::Quarantined,
+                at,
+                reviewer: None,
+            });
+        }
+    }
+
+    /// Drops audit entries older than the cutoff, except those of transactions still pending
+    pub fn prune_audit(&mut self, cutoff: u64) -> usize {
+        let pending: HashSet<u32> = self.pending
+            .iter()
+            .map(|entry| entry.tx)
+            .collect();
+        let before = self.audit.len();
+
+        self.audit.retain(|entry| entry.at >= cutoff || pending.contains(&entry.tx));
+
+        before - self.audit.len()
+    }
+
+    pub fn write_list<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        #[derive(Serialize)]
+        struct Row {
+            tx: u32,
+            client: ClientId,
+            #[serde(rename = "type")]
+            tx_type: &'static str,
+            amount: Option<Decimal>,
+            review: Option<Review>,
+        }
+
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for entry in self.pending.iter() {
+            writer.serialize(Row {
+                tx: entry.tx,
+                client: entry.client,
+                tx_type: entry.tx_type.name(),
+                amount: entry.tx_type.amount(),
+                review: entry.review,
+            })?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{ policy::QuarantineAbove, types::Account };
+
+    fn quarantined_run() -> (Engine, PendingQueue) {
+        let mut engine = Engine::new();
+        engine.add_policy(Box::new(QuarantineAbove(dec!(100))));
+
+        let _ = engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(100))));
+        let _ = engine.add_transaction(Transaction::new(1, 3, TransactionType::Deposit(dec!(500))));
+        let _ = engine.add_transaction(Transaction::new(1, 4, TransactionType::Withdrawal(dec!(50))));
+
+        let mut queue = PendingQueue::default();
+        queue.capture(&engine, 10);
+
+        (engine, queue)
+    }
+
+    #[test]
+    fn test_capture() {
+        let (_, queue) = quarantined_run();
+
+        assert_eq!(queue.pending, vec![PendingEntry {
+            client: 1,
+            tx: 3,
+            tx_type: TransactionType::Deposit(dec!(500)),
+            timestamp: None,
+            review: None,
+        }]);
+        assert_eq!(queue.audit, vec![AuditEntry {
+            tx: 3,
+            client: 1,
+            action: Action::Quarantined,
+            at: 10,
+            reviewer: None,
+        }]);
+    }
+
+    #[test]
+    fn test_review() {
+        let (_, mut queue) = quarantined_run();
+
+        queue.review(3, Review::Approved, Some("alice".to_string()), 20).unwrap();
+
+        assert!(matches!(queue.review(3, Review::Rejected, None, 30), Err(PendingError::AlreadyReviewed(3))));
+        assert!(matches!(queue.review(4, Review::Rejected, None, 30), Err(PendingError::NotFound(4))));
+        assert_eq!(queue.audit.last().unwrap().reviewer.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_restore_in_next_run() {
+        let (engine, mut queue) = quarantined_run();
+        queue.review(3, Review::Approved, None, 20).unwrap();
+
+        let json = serde_json::to_string(&queue).unwrap();
+        let mut queue: PendingQueue = serde_json::from_str(&json).unwrap();
+
+        let mut next = Engine::new();
+        for account in engine.get_accounts() {
+            next.seed_account(account);
+        }
+
+        queue.restore(&mut next, 30);
+        queue.capture(&next, 30);
+
+        assert!(queue.pending.is_empty());
+        assert_eq!(queue.audit.last().unwrap().action, Action::Applied);
+        assert_eq!(next.get_account(1), Some(&Account {
+            client_id: 1,
+            available: dec!(550),
+            held: dec!(0),
+            total: dec!(550),
+            locked: false,
+        }));
+    }
+
+    #[test]
+    fn test_write_list() {
+        let (_, queue) = quarantined_run();
+
+        let mut output = vec![];
+        queue.write_list(&mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "tx,client,type,amount,review\n3,1,deposit,500,\n");
+    }
+
+    #[test]
+    fn test_prune_audit_keeps_pending() {
+        let (_, mut queue) = quarantined_run();
+        queue.audit.push(AuditEntry {
+            tx: 9,
+            client: 2,
+            action: Action::Applied,
+            at: 5,
+            reviewer: None,
+        });
+
+        assert_eq!(queue.prune_audit(20), 1);
+        assert_eq!(queue.audit.len(), 1);
+        assert_eq!(queue.audit[0].tx, 3);
+    }
+}
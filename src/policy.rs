@@ -0,0 +1,428 @@
+use std::{ any::Any, collections::HashMap, fmt, str::FromStr };
+
+use rust_decimal::Decimal;
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+    engine::SECONDS_PER_DAY,
+    reason::ReasonCode,
+    types::{ Account, ClientId, Transaction, TransactionType },
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskTier {
+    Low,
+    #[default]
+    Standard,
+    High,
+}
+
+impl fmt::Display for RiskTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskTier::Low => f.write_str("low"),
+            RiskTier::Standard => f.write_str("standard"),
+            RiskTier::High => f.write_str("high"),
+        }
+    }
+}
+
+impl FromStr for RiskTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(RiskTier::Low),
+            "standard" => Ok(RiskTier::Standard),
+            "high" => Ok(RiskTier::High),
+            _ => Err(format!("unknown risk tier {}, expected low, standard or high", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Reject(ReasonCode),
+    /// Park the transaction until it's approved or declined, holding the funds of a debit
+    Quarantine,
+}
+
+// Policies are evaluated in the order they were added, the first decision other than `Allow` wins.
+pub trait Policy: Send {
+    fn evaluate(&self, tx: &Transaction, account: &Account, tier: RiskTier) -> Decision;
+
+    /// Called after a transaction is applied, returns the client's new tier when it should change
+    fn adjust_tier(&self, _tx: &Transaction, _tier: RiskTier) -> Option<RiskTier> {
+        None
+    }
+
+    /// Called after a transaction passed every policy and was applied
+    fn record(&mut self, _tx: &Transaction) {}
+
+    /// Called before the engine replays its events, state kept by `record` must be cleared
+    fn reset(&mut self) {}
+
+    /// Called when the client moves to another engine, returns the state kept by `record` for it
+    fn take_client(&mut self, _client_id: ClientId) -> Option<Box<dyn Any + Send>> {
+        None
+    }
+
+    /// Called on the engine the client moves to, with what `take_client` returned
+    fn put_client(&mut self, _state: Box<dyn Any + Send>) {}
+}
+
+/// Quarantines deposits and withdrawals above an amount for manual review
+pub struct QuarantineAbove(pub Decimal);
+
+impl Policy for QuarantineAbove {
+    fn evaluate(&self, tx: &Transaction, _account: &Account, _tier: RiskTier) -> Decision {
+        match tx.tx_type {
+            TransactionType::Deposit(amount) | TransactionType::Withdrawal(amount) if
+                amount > self.0
+            => Decision::Quarantine,
+            _ => Decision::Allow,
+        }
+    }
+}
+
+/// Caps the withdrawal amount per risk tier and raises a client to the high tier after a chargeback
+#[derive(Default)]
+pub struct TierPolicy {
+    limits: HashMap<RiskTier, Decimal>,
+}
+
+impl TierPolicy {
+    pub fn new(limits: impl IntoIterator<Item = (RiskTier, Decimal)>) -> Self {
+        TierPolicy { limits: limits.into_iter().collect() }
+    }
+}
+
+impl Policy for TierPolicy {
+    fn evaluate(&self, tx: &Transaction, _account: &Account, tier: RiskTier) -> Decision {
+        match (&tx.tx_type, self.limits.get(&tier)) {
+            (TransactionType::Withdrawal(amount), Some(limit)) if amount > limit => {
+                Decision::Reject(ReasonCode::LimitExceeded)
+            }
+            _ => Decision::Allow,
+        }
+    }
+
+    fn adjust_tier(&self, tx: &Transaction, tier: RiskTier) -> Option<RiskTier> {
+        match tx.tx_type {
+            TransactionType::Chargeback if tier != RiskTier::High => Some(RiskTier::High),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Count(u32),
+    Turnover(Decimal),
+}
+
+/// A cap on the deposits, withdrawals and transfers of an account per period, e.g.
+/// `count=20/day` or `high:turnover=5000/week` for the clients of a risk tier only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountLimit {
+    pub tier: Option<RiskTier>,
+    pub kind: LimitKind,
+    /// Length of the period in seconds, periods start at multiples of it
+    pub period: u64,
+}
+
+impl FromStr for AccountLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tier, limit) = match s.split_once(':') {
+            Some((tier, limit)) => (Some(tier.parse()?), limit),
+            None => (None, s),
+        };
+
+        let (kind, value) = limit.split_once('=').ok_or("expected [TIER:]KIND=VALUE/PERIOD")?;
+        let (value, period) = value.split_once('/').ok_or("expected a period, e.g. 20/day")?;
+
+        let kind = match kind {
+            "count" => LimitKind::Count(value.parse().map_err(|_| format!("invalid count {}", value))?),
+            "turnover" => {
+                LimitKind::Turnover(value.parse().map_err(|_| format!("invalid amount {}", value))?)
+            }
+            _ => {
+                return Err(format!("unknown limit {}, expected count or turnover", kind));
+            }
+        };
+
+        let period = match period {
+            "hour" => SECONDS_PER_DAY / 24,
+            "day" => SECONDS_PER_DAY,
+            "week" => SECONDS_PER_DAY * 7,
+            _ => {
+                return Err(format!("unknown period {}, expected hour, day or week", period));
+            }
+        };
+
+        Ok(AccountLimit { tier, kind, period })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    period: u64,
+    count: u32,
+    turnover: Decimal,
+}
+
+/// Rejects the transactions that would take an account over its count or turnover limits. Periods
+/// follow the transaction timestamps, one without a timestamp falls in the period of the latest
+/// one seen. Usage is kept for every limit whatever the tier, so a client moving to a limited tier
+/// has the transactions it already made in the period counted.
+#[derive(Default)]
+pub struct AccountLimits {
+    limits: Vec<AccountLimit>,
+    usage: HashMap<(ClientId, usize), Usage>,
+    latest: Option<u64>,
+}
+
+impl AccountLimits {
+    pub fn new(limits: impl IntoIterator<Item = AccountLimit>) -> Self {
+        AccountLimits { limits: limits.into_iter().collect(), ..Default::default() }
+    }
+
+    fn limited(tx: &Transaction) -> Option<Decimal> {
+        match tx.tx_type {
+            TransactionType::Deposit(amount) |
+            TransactionType::Withdrawal(amount) |
+            TransactionType::Transfer { amount, .. } => Some(amount),
+            _ => None,
+        }
+    }
+
+    // The usage of the period the transaction falls in, empty when the last one was earlier
+    fn usage(&self, tx: &Transaction, index: usize, limit: &AccountLimit) -> Usage {
+        let period = tx.timestamp.or(self.latest).unwrap_or_default() / limit.period;
+
+        match self.usage.get(&(tx.client_id, index)) {
+            Some(usage) if usage.period == period => *usage,
+            _ => Usage { period, ..Default::default() },
+        }
+    }
+}
+
+impl Policy for AccountLimits {
+    fn evaluate(&self, tx: &Transaction, _account: &Account, tier: RiskTier) -> Decision {
+        let Some(amount) = Self::limited(tx) else {
+            return Decision::Allow;
+        };
+
+        for (index, limit) in self.limits.iter().enumerate() {
+            if limit.tier.is_some_and(|limited| limited != tier) {
+                continue;
+            }
+
+            let usage = self.usage(tx, index, limit);
+
+            match limit.kind {
+                LimitKind::Count(max) if usage.count >= max => {
+                    return Decision::Reject(ReasonCode::TxCountExceeded);
+                }
+                LimitKind::Turnover(max) if usage.turnover + amount > max => {
+                    return Decision::Reject(ReasonCode::TurnoverExceeded);
+                }
+                _ => {}
+            }
+        }
+
+        Decision::Allow
+    }
+
+    fn record(&mut self, tx: &Transaction) {
+        let Some(amount) = Self::limited(tx) else {
+            return;
+        };
+
+        for (index, limit) in self.limits.iter().enumerate() {
+            let mut usage = self.usage(tx, index, limit);
+
+            usage.count += 1;
+            usage.turnover += amount;
+
+            self.usage.insert((tx.client_id, index), usage);
+        }
+
+        self.latest = self.latest.max(tx.timestamp);
+    }
+
+    fn reset(&mut self) {
+        self.usage.clear();
+        self.latest = None;
+    }
+
+    fn take_client(&mut self, client_id: ClientId) -> Option<Box<dyn Any + Send>> {
+        let keys: Vec<(ClientId, usize)> = self.usage
+            .keys()
+            .filter(|(client, _)| *client == client_id)
+            .copied()
+            .collect();
+
+        let usage: Vec<((ClientId, usize), Usage)> = keys
+            .into_iter()
+            .filter_map(|key| self.usage.remove(&key).map(|usage| (key, usage)))
+            .collect();
+
+        Some(Box::new(usage))
+    }
+
+    fn put_client(&mut self, state: Box<dyn Any + Send>) {
+        if let Ok(usage) = state.downcast::<Vec<((ClientId, usize), Usage)>>() {
+            self.usage.extend(*usage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_quarantine_above() {
+        let policy = QuarantineAbove(dec!(100));
+        let account = Account::new(1);
+
+        let decide = |tx_type| {
+            policy.evaluate(&Transaction::new(1, 1, tx_type), &account, RiskTier::default())
+        };
+
+        assert_eq!(decide(TransactionType::Deposit(dec!(100))), Decision::Allow);
+        assert_eq!(decide(TransactionType::Deposit(dec!(100.01))), Decision::Quarantine);
+        assert_eq!(decide(TransactionType::Withdrawal(dec!(500))), Decision::Quarantine);
+        assert_eq!(decide(TransactionType::Dispute), Decision::Allow);
+    }
+
+    #[test]
+    fn test_tier_limits() {
+        let policy = TierPolicy::new([(RiskTier::High, dec!(50))]);
+        let account = Account::new(1);
+        let withdrawal = Transaction::new(1, 1, TransactionType::Withdrawal(dec!(60)));
+
+        assert_eq!(policy.evaluate(&withdrawal, &account, RiskTier::Standard), Decision::Allow);
+        assert_eq!(
+            policy.evaluate(&withdrawal, &account, RiskTier::High),
+            Decision::Reject(ReasonCode::LimitExceeded)
+        );
+    }
+
+    #[test]
+    fn test_chargeback_raises_tier() {
+        let policy = TierPolicy::default();
+        let chargeback = Transaction::new(1, 1, TransactionType::Chargeback);
+
+        assert_eq!(policy.adjust_tier(&chargeback, RiskTier::Low), Some(RiskTier::High));
+        assert_eq!(policy.adjust_tier(&chargeback, RiskTier::High), None);
+        assert_eq!(
+            policy.adjust_tier(&Transaction::new(1, 1, TransactionType::Dispute), RiskTier::Low),
+            None
+        );
+    }
+
+    #[test]
+    fn test_account_limits() {
+        let mut policy = AccountLimits::new([
+            "count=3/day".parse().unwrap(),
+            "high:turnover=100/hour".parse().unwrap(),
+        ]);
+        let account = Account::new(1);
+        let deposit = |tx_id, amount, timestamp| Transaction {
+            timestamp: Some(timestamp),
+            ..Transaction::new(1, tx_id, TransactionType::Deposit(amount))
+        };
+
+        for tx in [deposit(1, dec!(80), 0), deposit(2, dec!(10), 60)] {
+            assert_eq!(policy.evaluate(&tx, &account, RiskTier::High), Decision::Allow);
+            policy.record(&tx);
+        }
+
+        assert_eq!(
+            policy.evaluate(&deposit(3, dec!(20), 120), &account, RiskTier::High),
+            Decision::Reject(ReasonCode::TurnoverExceeded)
+        );
+        assert_eq!(policy.evaluate(&deposit(3, dec!(20), 120), &account, RiskTier::Standard), Decision::Allow);
+        policy.record(&deposit(3, dec!(20), 120));
+
+        assert_eq!(
+            policy.evaluate(&deposit(4, dec!(5), 180), &account, RiskTier::Standard),
+            Decision::Reject(ReasonCode::TxCountExceeded)
+        );
+        assert_eq!(
+            policy.evaluate(&deposit(4, dec!(5), SECONDS_PER_DAY), &account, RiskTier::High),
+            Decision::Allow
+        );
+        assert_eq!(
+            policy.evaluate(&Transaction::new(1, 1, TransactionType::Dispute), &account, RiskTier::High),
+            Decision::Allow
+        );
+
+        let other = Transaction {
+            timestamp: Some(120),
+            ..Transaction::new(2, 4, TransactionType::Withdrawal(dec!(1)))
+        };
+        assert_eq!(policy.evaluate(&other, &Account::new(2), RiskTier::Standard), Decision::Allow);
+
+        policy.reset();
+        assert_eq!(policy.evaluate(&deposit(4, dec!(5), 180), &account, RiskTier::Standard), Decision::Allow);
+    }
+
+    #[test]
+    fn test_account_limits_move_client() {
+        let limits = || AccountLimits::new(["count=1/day".parse().unwrap()]);
+        let (mut from, mut to) = (limits(), limits());
+        let deposit = |client_id, tx_id| Transaction {
+            timestamp: Some(0),
+            ..Transaction::new(client_id, tx_id, TransactionType::Deposit(dec!(1)))
+        };
+
+        from.record(&deposit(1, 1));
+        from.record(&deposit(2, 2));
+        to.put_client(from.take_client(1).unwrap());
+
+        assert_eq!(from.evaluate(&deposit(1, 3), &Account::new(1), RiskTier::Standard), Decision::Allow);
+        assert_eq!(
+            to.evaluate(&deposit(1, 3), &Account::new(1), RiskTier::Standard),
+            Decision::Reject(ReasonCode::TxCountExceeded)
+        );
+        assert_eq!(
+            from.evaluate(&deposit(2, 4), &Account::new(2), RiskTier::Standard),
+            Decision::Reject(ReasonCode::TxCountExceeded)
+        );
+    }
+
+    #[test]
+    fn test_parse_account_limit() {
+        assert_eq!(
+            "high:turnover=5000/week".parse(),
+            Ok(AccountLimit {
+                tier: Some(RiskTier::High),
+                kind: LimitKind::Turnover(dec!(5000)),
+                period: SECONDS_PER_DAY * 7,
+            })
+        );
+        assert_eq!(
+            "count=20/hour".parse(),
+            Ok(AccountLimit { tier: None, kind: LimitKind::Count(20), period: 3600 })
+        );
+        assert!("count=20".parse::<AccountLimit>().is_err());
+        assert!("count=20/month".parse::<AccountLimit>().is_err());
+        assert!("volume=20/day".parse::<AccountLimit>().is_err());
+        assert!("medium:count=20/day".parse::<AccountLimit>().is_err());
+    }
+
+    #[test]
+    fn test_parse_tier() {
+        assert_eq!("high".parse(), Ok(RiskTier::High));
+        assert!("medium".parse::<RiskTier>().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{ collections::{ BTreeMap, HashMap }, fmt, sync::Arc, time::{ Duration, Instant } };
+
+use crate::{ provenance::{ Provenance, SourceCounters }, reason::ReasonCode };
+
+/// Follows how far a run is through its input: the rows read, the rate since the start and, when
+/// the size of the input is known, the time left at that rate. The bytes read are the offsets of
+/// the latest rows of every source, so the sizes are those of the input files.
+pub struct Progress {
+    start: Instant,
+    last_report: Instant,
+    interval: Duration,
+    total_bytes: Option<u64>,
+    offsets: HashMap<Arc<str>, u64>,
+    read: u64,
+}
+
+impl Progress {
+    /// Without a total size there's no ETA, e.g. for stdin.
+    pub fn new(total_bytes: Option<u64>, interval: Duration) -> Self {
+        let start = Instant::now();
+
+        Progress { start, last_report: start, interval, total_bytes, offsets: HashMap::new(), read: 0 }
+    }
+
+    pub fn read(&mut self, provenance: &Provenance) {
+        self.read += 1;
+
+        let offset = self.offsets.entry(provenance.source.clone()).or_default();
+        *offset = (*offset).max(provenance.offset);
+    }
+
+    /// True once per interval.
+    pub fn due(&mut self) -> bool {
+        if self.last_report.elapsed() < self.interval {
+            return false;
+        }
+
+        self.last_report = Instant::now();
+
+        true
+    }
+
+    pub fn line(&self, counters: &SourceCounters) -> String {
+        self.format_line(self.start.elapsed(), counters)
+    }
+
+    /// The line at the end of a run.
+    pub fn summary(&self, counters: &SourceCounters) -> String {
+        self.format_summary(self.start.elapsed(), counters)
+    }
+
+    fn rate(&self, elapsed: Duration) -> u64 {
+        match elapsed.is_zero() {
+            true => 0,
+            false => ((self.read as f64) / elapsed.as_secs_f64()) as u64,
+        }
+    }
+
+    fn format_line(&self, elapsed: Duration, counters: &SourceCounters) -> String {
+        let mut line = format!(
+            "{} rows read, {} applied, {} rejected, {} malformed, {} rows/s",
+            self.read,
+            counters.applied,
+            counters.rejected,
+            counters.malformed,
+            self.rate(elapsed)
+        );
+
+        let bytes: u64 = self.offsets.values().sum();
+
+        if let Some(total) = self.total_bytes.filter(|total| *total > 0 && bytes > 0) {
+            let done = (bytes as f64) / (total as f64);
+            let left = elapsed.as_secs_f64() * (1.0 - done.min(1.0)) / done;
+
+            line.push_str(&format!(", {:.0}% ETA {}s", done.min(1.0) * 100.0, left.ceil() as u64));
+        }
+
+        line
+    }
+
+    fn format_summary(&self, elapsed: Duration, counters: &SourceCounters) -> String {
+        format!(
+            "Processed {} rows in {:.1}s: {} applied, {} rejected, {} malformed, {} rows/s",
+            self.read,
+            elapsed.as_secs_f64(),
+            counters.applied,
+            counters.rejected,
+            counters.malformed,
+            self.rate(elapsed)
+        )
+    }
+}
+
+/// The counts of a whole run, written as a single line of `key=value` pairs for scripts, e.g.
+/// `summary rows=4 applied=2 rejected=1 malformed=1 accounts=2 locked=0 reasons=INSUFFICIENT_FUNDS:1,MALFORMED:1`.
+/// `rejected` counts the transactions the engine rejected, `malformed` the rows that couldn't be
+/// parsed, and `reasons` both by reason code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pub rows: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    pub malformed: u64,
+    pub reasons: BTreeMap<ReasonCode, usize>,
+    /// The accounts in the output, before any filter
+    pub accounts: usize,
+    pub locked: usize,
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "summary rows={} applied={} rejected={} malformed={} accounts={} locked={} reasons=",
+            self.rows,
+            self.applied,
+            self.rejected,
+            self.malformed,
+            self.accounts,
+            self.locked
+        )?;
+
+        let reasons = self.reasons.iter().filter(|(_, count)| **count > 0);
+
+        for (i, (reason, count)) in reasons.enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+
+            write!(f, "{}:{}", reason, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provenance(source: &str, offset: u64) -> Provenance {
+        Provenance { source: source.into(), line: 0, offset }
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut progress = Progress::new(Some(1000), Duration::from_secs(1));
+
+        for offset in [0, 100, 200] {
+            progress.read(&provenance("a.csv", offset));
+        }
+
+        progress.read(&provenance("b.csv", 50));
+
+        let counters = SourceCounters { applied: 2, rejected: 1, malformed: 1 };
+
+        assert_eq!(
+            progress.format_line(Duration::from_secs(2), &counters),
+            "4 rows read, 2 applied, 1 rejected, 1 malformed, 2 rows/s, 25% ETA 6s"
+        );
+        assert_eq!(
+            progress.format_summary(Duration::from_millis(2500), &counters),
+            "Processed 4 rows in 2.5s: 2 applied, 1 rejected, 1 malformed, 1 rows/s"
+        );
+
+        let stdin = Progress::new(None, Duration::from_secs(1));
+
+        let summary = RunSummary {
+            rows: 4,
+            applied: 2,
+            rejected: 1,
+            malformed: 1,
+            reasons: [(ReasonCode::Malformed, 1), (ReasonCode::InsufficientFunds, 1), (ReasonCode::UnknownTx, 0)]
+                .into_iter()
+                .collect(),
+            accounts: 2,
+            locked: 0,
+        };
+
+        assert_eq!(
+            summary.to_string(),
+            "summary rows=4 applied=2 rejected=1 malformed=1 accounts=2 locked=0 reasons=INSUFFICIENT_FUNDS:1,MALFORMED:1"
+        );
+        assert_eq!(
+            RunSummary::default().to_string(),
+            "summary rows=0 applied=0 rejected=0 malformed=0 accounts=0 locked=0 reasons="
+        );
+
+        assert_eq!(
+            stdin.format_line(Duration::ZERO, &SourceCounters::default()),
+            "0 rows read, 0 applied, 0 rejected, 0 malformed, 0 rows/s"
+        );
+    }
+}
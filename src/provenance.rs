@@ -0,0 +1,129 @@
+use std::{ collections::BTreeMap, fmt, io, sync::Arc };
+
+use csv::StringRecord;
+use serde::{ Deserialize, Serialize };
+
+use crate::{ ordering::Sequenced, reason::ReasonCode };
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub source: Arc<str>,
+    pub line: u64,
+    pub offset: u64,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.source, self.line)
+    }
+}
+
+#[derive(Debug)]
+pub struct Tagged {
+    pub provenance: Provenance,
+    pub record: Result<Sequenced, ReasonCode>,
+    /// The fields of a row that couldn't be parsed into a transaction
+    pub raw: Option<StringRecord>,
+    /// Why the row couldn't be parsed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceCounters {
+    pub applied: u64,
+    pub rejected: u64,
+    pub malformed: u64,
+}
+
+#[derive(Default)]
+pub struct SourceStats {
+    sources: BTreeMap<Arc<str>, SourceCounters>,
+}
+
+impl SourceStats {
+    pub fn new() -> Self {
+        SourceStats::default()
+    }
+
+    /// Counts the outcome of a transaction the engine was given.
+    pub fn record(&mut self, provenance: &Provenance, result: Result<(), ReasonCode>) {
+        let counters = self.sources.entry(provenance.source.clone()).or_default();
+
+        match result {
+            Ok(()) => counters.applied += 1,
+            Err(_) => counters.rejected += 1,
+        }
+    }
+
+    /// Counts a row that couldn't be parsed into a transaction, whatever its reason code.
+    pub fn record_unparsed(&mut self, provenance: &Provenance) {
+        self.sources.entry(provenance.source.clone()).or_default().malformed += 1;
+    }
+
+    pub fn get(&self, source: &str) -> Option<&SourceCounters> {
+        self.sources.get(source)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SourceCounters)> {
+        self.sources.iter().map(|(source, counters)| (source.as_ref(), counters))
+    }
+
+    /// The counters of every source added up.
+    pub fn total(&self) -> SourceCounters {
+        self.sources.values().fold(SourceCounters::default(), |total, counters| SourceCounters {
+            applied: total.applied + counters.applied,
+            rejected: total.rejected + counters.rejected,
+            malformed: total.malformed + counters.malformed,
+        })
+    }
+
+    pub fn write_report<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        #[derive(Serialize)]
+        struct Row<'a> {
+            source: &'a str,
+        }
+
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for (source, counters) in self.iter() {
+            writer.serialize((Row { source }, counters))?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provenance(source: &str, line: u64) -> Provenance {
+        Provenance { source: source.into(), line, offset: 0 }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(provenance("partner-a.csv", 12).to_string(), "partner-a.csv:12");
+    }
+
+    #[test]
+    fn test_counters_per_source() {
+        let mut stats = SourceStats::new();
+
+        stats.record(&provenance("b.csv", 2), Ok(()));
+        stats.record(&provenance("a.csv", 2), Err(ReasonCode::InsufficientFunds));
+        stats.record_unparsed(&provenance("b.csv", 3));
+        stats.record(&provenance("b.csv", 4), Ok(()));
+
+        let mut output = vec![];
+        stats.write_report(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "source,applied,rejected,malformed\na.csv,0,1,0\nb.csv,2,0,1\n"
+        );
+        assert_eq!(stats.total(), SourceCounters { applied: 2, rejected: 1, malformed: 1 });
+    }
+}
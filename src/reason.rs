@@ -0,0 +1,168 @@
+use std::fmt;
+
+use serde::Serialize;
+
+// Codes are part of the output contract (rejects file, API responses, metrics labels and audit
+// log), never rename or reuse one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReasonCode {
+    /// Not enough available funds for a withdrawal or to hold a disputed amount
+    InsufficientFunds,
+    /// The referenced transaction is unknown or not disputable
+    UnknownTx,
+    /// The referenced transaction belongs to another client
+    ClientMismatch,
+    /// The transaction id was already used
+    DuplicateTx,
+    /// The transaction is already under dispute
+    AlreadyDisputed,
+    /// Resolve or chargeback of a transaction that isn't under dispute
+    NotUnderDispute,
+    /// The account is locked
+    AccountLocked,
+    /// A configured limit would be exceeded
+    LimitExceeded,
+    /// The amount is negative, zero or otherwise invalid
+    InvalidAmount,
+    /// The transaction type is not supported
+    UnknownType,
+    /// The record could not be parsed
+    Malformed,
+    /// The merge source account doesn't exist or is the merge target itself
+    UnknownClient,
+    /// Recovery for an account whose available balance isn't negative
+    NotInDeficit,
+    /// The transaction type isn't enabled, e.g. adjustments without `--allow-adjustments`
+    NotAllowed,
+    /// Unlock of an account that isn't locked
+    NotLocked,
+    /// The upstream `seq` repeats or goes back, with `--reject-out-of-sequence`
+    OutOfSequence,
+    /// The account already made the most transactions allowed in the period, with `--account-limit`
+    TxCountExceeded,
+    /// The transaction would take the account over its turnover for the period, with `--account-limit`
+    TurnoverExceeded,
+    /// The account was deleted, only a restore applies to it
+    AccountDeleted,
+    /// Restore of an account that isn't deleted
+    NotDeleted,
+    /// The deposit would take the account over the ceiling of its class, with `--balance-bounds`
+    AboveCeiling,
+    /// The debit would take the account under the floor of its class, with `--balance-bounds`
+    BelowFloor,
+    /// The worker owning the account panicked on an earlier transaction, with `--workers`
+    ShardDegraded,
+    /// The conversion table has no rate for the pair of currencies, with `--rates`
+    NoRate,
+    /// The dispute was filed too long after the transaction, with `--dispute-window-days`
+    DisputeWindowClosed,
+    /// The timestamp goes back for the client, with `--reject-late-timestamps`
+    LateTimestamp,
+    /// The resolved transaction was disputed again more times than allowed, with `--allow-redisputes`
+    RedisputeLimit,
+    /// Representment of a transaction that isn't charged back
+    NotChargedBack,
+    /// The transaction reached the engine after a later one of its client, the input order was lost
+    OutOfOrder,
+    /// The state store failed on this transaction or an earlier one, with `--state-dir`
+    StorageFailed,
+}
+
+impl ReasonCode {
+    pub const ALL: [ReasonCode; 30] = [
+        ReasonCode::InsufficientFunds,
+        ReasonCode::UnknownTx,
+        ReasonCode::ClientMismatch,
+        ReasonCode::DuplicateTx,
+        ReasonCode::AlreadyDisputed,
+        ReasonCode::NotUnderDispute,
+        ReasonCode::AccountLocked,
+        ReasonCode::LimitExceeded,
+        ReasonCode::InvalidAmount,
+        ReasonCode::UnknownType,
+        ReasonCode::Malformed,
+        ReasonCode::UnknownClient,
+        ReasonCode::NotInDeficit,
+        ReasonCode::NotAllowed,
+        ReasonCode::NotLocked,
+        ReasonCode::OutOfSequence,
+        ReasonCode::TxCountExceeded,
+        ReasonCode::TurnoverExceeded,
+        ReasonCode::AccountDeleted,
+        ReasonCode::NotDeleted,
+        ReasonCode::AboveCeiling,
+        ReasonCode::BelowFloor,
+        ReasonCode::ShardDegraded,
+        ReasonCode::NoRate,
+        ReasonCode::DisputeWindowClosed,
+        ReasonCode::LateTimestamp,
+        ReasonCode::RedisputeLimit,
+        ReasonCode::NotChargedBack,
+        ReasonCode::OutOfOrder,
+        ReasonCode::StorageFailed,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReasonCode::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            ReasonCode::UnknownTx => "UNKNOWN_TX",
+            ReasonCode::ClientMismatch => "CLIENT_MISMATCH",
+            ReasonCode::DuplicateTx => "DUPLICATE_TX",
+            ReasonCode::AlreadyDisputed => "ALREADY_DISPUTED",
+            ReasonCode::NotUnderDispute => "NOT_UNDER_DISPUTE",
+            ReasonCode::AccountLocked => "ACCOUNT_LOCKED",
+            ReasonCode::LimitExceeded => "LIMIT_EXCEEDED",
+            ReasonCode::InvalidAmount => "INVALID_AMOUNT",
+            ReasonCode::UnknownType => "UNKNOWN_TYPE",
+            ReasonCode::Malformed => "MALFORMED",
+            ReasonCode::UnknownClient => "UNKNOWN_CLIENT",
+            ReasonCode::NotInDeficit => "NOT_IN_DEFICIT",
+            ReasonCode::NotAllowed => "NOT_ALLOWED",
+            ReasonCode::NotLocked => "NOT_LOCKED",
+            ReasonCode::OutOfSequence => "OUT_OF_SEQUENCE",
+            ReasonCode::TxCountExceeded => "TX_COUNT_EXCEEDED",
+            ReasonCode::TurnoverExceeded => "TURNOVER_EXCEEDED",
+            ReasonCode::AccountDeleted => "ACCOUNT_DELETED",
+            ReasonCode::NotDeleted => "NOT_DELETED",
+            ReasonCode::AboveCeiling => "ABOVE_CEILING",
+            ReasonCode::BelowFloor => "BELOW_FLOOR",
+            ReasonCode::ShardDegraded => "SHARD_DEGRADED",
+            ReasonCode::NoRate => "NO_RATE",
+            ReasonCode::DisputeWindowClosed => "DISPUTE_WINDOW_CLOSED",
+            ReasonCode::LateTimestamp => "LATE_TIMESTAMP",
+            ReasonCode::RedisputeLimit => "REDISPUTE_LIMIT",
+            ReasonCode::NotChargedBack => "NOT_CHARGED_BACK",
+            ReasonCode::OutOfOrder => "OUT_OF_ORDER",
+            ReasonCode::StorageFailed => "STORAGE_FAILED",
+        }
+    }
+}
+
+impl fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_unique() {
+        let codes: std::collections::HashSet<&str> = ReasonCode::ALL
+            .iter()
+            .map(|reason| reason.code())
+            .collect();
+
+        assert_eq!(codes.len(), ReasonCode::ALL.len());
+    }
+
+    #[test]
+    fn test_serialize_matches_code() {
+        for reason in ReasonCode::ALL {
+            assert_eq!(serde_json::to_string(&reason).unwrap(), format!("\"{}\"", reason.code()));
+        }
+    }
+}
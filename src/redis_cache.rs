@@ -0,0 +1,240 @@
+use std::{
+    fmt,
+    io::{ self, BufRead, BufReader, Write },
+    net::TcpStream,
+};
+
+use crate::{
+    delivery::{ self, DeliveryLimits, DeliveryWorker },
+    observer::{ AccountObserver, LifecycleEvent },
+    types::{ Account, ClientId },
+};
+
+const KEY_PREFIX: &str = "account:";
+const CHANNEL: &str = "accounts";
+
+#[derive(Debug)]
+pub struct InvalidUrl(String);
+
+impl fmt::Display for InvalidUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid redis url {}, expected redis://host[:port]", self.0)
+    }
+}
+
+impl std::error::Error for InvalidUrl {}
+
+fn parse_url(url: &str) -> Result<(String, u16), InvalidUrl> {
+    let invalid = || InvalidUrl(url.to_string());
+
+    let authority = url.strip_prefix("redis://").ok_or_else(invalid)?.trim_end_matches('/');
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+        None => (authority, 6379),
+    };
+
+    if host.is_empty() || host.contains('/') {
+        return Err(invalid());
+    }
+
+    Ok((host.to_string(), port))
+}
+
+enum Command {
+    Update(ClientId, String),
+    Remove(ClientId),
+}
+
+// Speaks just enough of the Redis protocol to send commands and check their replies.
+struct Connection {
+    reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+    fn open(address: &(String, u16), limits: &DeliveryLimits) -> io::Result<Self> {
+        Ok(Connection { reader: BufReader::new(delivery::connect(&address.0, address.1, limits)?) })
+    }
+
+    fn send(&mut self, commands: &[&[&str]]) -> io::Result<()> {
+        let mut request = vec![];
+
+        for command in commands {
+            write!(request, "*{}\r\n", command.len())?;
+
+            for arg in command.iter() {
+                write!(request, "${}\r\n{}\r\n", arg.len(), arg)?;
+            }
+        }
+
+        self.reader.get_mut().write_all(&request)?;
+
+        for _ in commands {
+            let mut reply = String::new();
+
+            if self.reader.read_line(&mut reply)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+
+            if let Some(err) = reply.strip_prefix('-') {
+                return Err(io::Error::other(err.trim_end().to_string()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Keeps the balance of every account under `account:<client>` and publishes each update to the
+// `accounts` channel, so read-heavy services don't need the engine. Commands are sent from a
+// worker thread, a failed command is logged and the connection opened again for the next one. See
+// `DeliveryWorker` for what happens to the commands when redis is slow or hangs.
+pub struct RedisCache {
+    worker: DeliveryWorker<Command>,
+}
+
+impl RedisCache {
+    pub fn new(url: &str) -> Result<Self, InvalidUrl> {
+        Self::with_limits(url, DeliveryLimits::default())
+    }
+
+    pub fn with_limits(url: &str, limits: DeliveryLimits) -> Result<Self, InvalidUrl> {
+        let address = parse_url(url)?;
+        let mut connection: Option<Connection> = None;
+
+        let worker = DeliveryWorker::spawn("redis cache", &limits, move |command: Command| {
+            let result = match &mut connection {
+                Some(connection) => Ok(connection),
+                None => Connection::open(&address, &limits).map(|opened| connection.insert(opened)),
+            }.and_then(|connection| {
+                match &command {
+                    Command::Update(client_id, json) => {
+                        let key = format!("{}{}", KEY_PREFIX, client_id);
+                        connection.send(&[&["SET", &key, json], &["PUBLISH", CHANNEL, json]])
+                    }
+                    Command::Remove(client_id) => {
+                        let key = format!("{}{}", KEY_PREFIX, client_id);
+                        connection.send(&[&["DEL", &key]])
+                    }
+                }
+            });
+
+            if let Err(err) = result {
+                log::error!("Failed to update the redis cache: {}", err);
+                connection = None;
+            }
+        });
+
+        Ok(RedisCache { worker })
+    }
+
+    /// The updates dropped because redis couldn't keep up.
+    pub fn dropped(&self) -> u64 {
+        self.worker.dropped()
+    }
+
+    fn send(&mut self, command: Command) {
+        self.worker.send(command);
+    }
+}
+
+impl AccountObserver for RedisCache {
+    fn on_lifecycle_event(&mut self, event: &LifecycleEvent) {
+        // A deleted account is cached again by the balance change of its restore
+        if let LifecycleEvent::Closed { client_id } | LifecycleEvent::Deleted { client_id, .. } = event {
+            self.send(Command::Remove(*client_id));
+        }
+    }
+
+    fn on_balance_change(&mut self, account: &Account) {
+        match serde_json::to_string(account) {
+            Ok(json) => self.send(Command::Update(account.client_id, json)),
+            Err(err) => log::error!("Failed to serialize account {:?}: {}", account, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ io::Read, net::TcpListener, thread, time::{ Duration, Instant } };
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(parse_url("redis://cache:6380").unwrap(), ("cache".to_string(), 6380));
+        assert_eq!(parse_url("redis://localhost/").unwrap(), ("localhost".to_string(), 6379));
+        assert!(parse_url("http://localhost").is_err());
+        assert!(parse_url("redis://:6379").is_err());
+        assert!(parse_url("redis://localhost/1").is_err());
+    }
+
+    #[test]
+    fn test_cache_updates() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = vec![];
+            let mut buffer = [0; 1024];
+
+            // SET, PUBLISH and DEL
+            for reply in [&b"+OK\r\n:1\r\n"[..], b":1\r\n"] {
+                let read = stream.read(&mut buffer).unwrap();
+                received.extend_from_slice(&buffer[..read]);
+                stream.write_all(reply).unwrap();
+            }
+
+            String::from_utf8(received).unwrap()
+        });
+
+        let mut cache = RedisCache::new(&format!("redis://127.0.0.1:{}", port)).unwrap();
+        cache.on_balance_change(&Account { available: dec!(1.5), total: dec!(1.5), ..Account::new(7) });
+        thread::sleep(Duration::from_millis(100));
+        cache.on_lifecycle_event(&LifecycleEvent::Closed { client_id: 7 });
+        drop(cache);
+
+        let json = r#"{"client":7,"available":"1.5","held":"0","total":"1.5","locked":false}"#;
+        assert_eq!(
+            server.join().unwrap(),
+            format!(
+                "*3\r\n$3\r\nSET\r\n$9\r\naccount:7\r\n${}\r\n{}\r\n*3\r\n$7\r\nPUBLISH\r\n$8\r\naccounts\r\n${}\r\n{}\r\n*2\r\n$3\r\nDEL\r\n$9\r\naccount:7\r\n",
+                json.len(),
+                json,
+                json.len(),
+                json
+            )
+        );
+    }
+
+    #[test]
+    fn test_hung_server() {
+        // Accepts the connections and never replies
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let _held: Vec<_> = listener.incoming().collect();
+        });
+
+        let limits = DeliveryLimits {
+            io_timeout: Duration::from_millis(100),
+            queue: 1,
+            shutdown: Duration::from_secs(5),
+            ..DeliveryLimits::default()
+        };
+        let mut cache = RedisCache::with_limits(&format!("redis://127.0.0.1:{}", port), limits).unwrap();
+
+        for client_id in 0..10 {
+            cache.on_balance_change(&Account::new(client_id));
+        }
+
+        assert!(cache.dropped() > 0);
+
+        let start = Instant::now();
+        drop(cache);
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
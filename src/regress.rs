@@ -0,0 +1,232 @@
+use std::{ collections::BTreeMap, fmt };
+
+use csv::{ ReaderBuilder, StringRecord, Trim };
+use rust_decimal::Decimal;
+
+use crate::types::{ Account, ClientId };
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputDiff {
+    Header {
+        baseline: String,
+        candidate: String,
+    },
+    /// Only in the baseline output
+    Missing {
+        key: String,
+        row: String,
+    },
+    /// Only in the candidate output
+    Added {
+        key: String,
+        row: String,
+    },
+    Changed {
+        key: String,
+        baseline: String,
+        candidate: String,
+    },
+}
+
+impl fmt::Display for OutputDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputDiff::Header { baseline, candidate } => {
+                write!(f, "header\n- {}\n+ {}", baseline, candidate)
+            }
+            OutputDiff::Missing { key, row } => write!(f, "client {}\n- {}", key, row),
+            OutputDiff::Added { key, row } => write!(f, "client {}\n+ {}", key, row),
+            OutputDiff::Changed { key, baseline, candidate } => {
+                write!(f, "client {}\n- {}\n+ {}", key, baseline, candidate)
+            }
+        }
+    }
+}
+
+// Compares two account outputs row by row, keyed by their first column (the client) so the row
+// order doesn't matter. Only the first CSV section is compared, the system accounts that may follow
+// a blank line are left out.
+pub fn diff_outputs(baseline: &[u8], candidate: &[u8]) -> csv::Result<Vec<OutputDiff>> {
+    let (baseline_header, baseline_rows) = read_rows(baseline)?;
+    let (candidate_header, mut candidate_rows) = read_rows(candidate)?;
+
+    let mut diffs = vec![];
+
+    if baseline_header != candidate_header {
+        diffs.push(OutputDiff::Header { baseline: baseline_header, candidate: candidate_header });
+    }
+
+    for (key, row) in baseline_rows {
+        match candidate_rows.remove(&key) {
+            None => diffs.push(OutputDiff::Missing { key, row }),
+            Some(candidate) if candidate != row => {
+                diffs.push(OutputDiff::Changed { key, baseline: row, candidate });
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (key, row) in candidate_rows {
+        diffs.push(OutputDiff::Added { key, row });
+    }
+
+    Ok(diffs)
+}
+
+/// A difference between two account reports for one client, see [`reconcile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountDiff {
+    /// Only in the old report
+    Missing(ClientId),
+    /// Only in the new report
+    Added(ClientId),
+    Changed {
+        client_id: ClientId,
+        field: &'static str,
+        old: String,
+        new: String,
+    },
+}
+
+impl fmt::Display for AccountDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountDiff::Missing(client_id) => write!(f, "client {}: only in the old report", client_id),
+            AccountDiff::Added(client_id) => write!(f, "client {}: only in the new report", client_id),
+            AccountDiff::Changed { client_id, field, old, new } => {
+                write!(f, "client {}: {} {} -> {}", client_id, field, old, new)
+            }
+        }
+    }
+}
+
+/// Reads the accounts of an account report, the output of a run. Columns after `locked` and the
+/// system accounts that may follow a blank line are left out.
+pub fn read_accounts(output: &[u8]) -> csv::Result<Vec<Account>> {
+    let reader = ReaderBuilder::new().trim(Trim::All).from_reader(first_section(output));
+
+    reader.into_deserialize().collect()
+}
+
+// Compares two account reports client by client, whatever their order. Balances are compared as
+// numbers, so `1.50` and `1.5` are the same balance.
+pub fn reconcile(old: &[Account], new: &[Account]) -> Vec<AccountDiff> {
+    let mut new: BTreeMap<ClientId, &Account> = new.iter().map(|account| (account.client_id, account)).collect();
+    let mut old: Vec<&Account> = old.iter().collect();
+    old.sort_by_key(|account| account.client_id);
+
+    let mut diffs = vec![];
+
+    for old in old {
+        let Some(new) = new.remove(&old.client_id) else {
+            diffs.push(AccountDiff::Missing(old.client_id));
+            continue;
+        };
+
+        let balances: [(&'static str, Decimal, Decimal); 3] = [
+            ("available", old.available, new.available),
+            ("held", old.held, new.held),
+            ("total", old.total, new.total),
+        ];
+
+        for (field, old_balance, new_balance) in balances {
+            if old_balance != new_balance {
+                diffs.push(AccountDiff::Changed {
+                    client_id: old.client_id,
+                    field,
+                    old: old_balance.to_string(),
+                    new: new_balance.to_string(),
+                });
+            }
+        }
+
+        if old.locked != new.locked {
+            diffs.push(AccountDiff::Changed {
+                client_id: old.client_id,
+                field: "locked",
+                old: old.locked.to_string(),
+                new: new.locked.to_string(),
+            });
+        }
+    }
+
+    diffs.extend(new.into_keys().map(AccountDiff::Added));
+
+    diffs
+}
+
+fn first_section(output: &[u8]) -> &[u8] {
+    match output.windows(2).position(|window| window == b"\n\n") {
+        Some(end) => &output[..=end],
+        None => output,
+    }
+}
+
+fn read_rows(output: &[u8]) -> csv::Result<(String, BTreeMap<String, String>)> {
+    let mut reader = ReaderBuilder::new().from_reader(first_section(output));
+    let header = join(reader.headers()?);
+    let mut rows = BTreeMap::new();
+
+    for record in reader.records() {
+        let record = record?;
+
+        rows.insert(record.get(0).unwrap_or_default().to_string(), join(&record));
+    }
+
+    Ok((header, rows))
+}
+
+fn join(record: &StringRecord) -> String {
+    record.iter().collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_outputs() {
+        let baseline = b"client,available,held,total,locked\n1,5,0,5,false\n2,1,0,1,false\n3,0,0,0,true\n";
+        let candidate = b"client,available,held,total,locked\n3,0,0,0,true\n1,4,1,5,false\n4,2,0,2,false\n\n\
+                          account,balance\nfees,0\n";
+
+        assert_eq!(diff_outputs(baseline, baseline).unwrap(), vec![]);
+        assert_eq!(diff_outputs(baseline, candidate).unwrap(), vec![
+            OutputDiff::Changed {
+                key: "1".to_string(),
+                baseline: "1,5,0,5,false".to_string(),
+                candidate: "1,4,1,5,false".to_string(),
+            },
+            OutputDiff::Missing { key: "2".to_string(), row: "2,1,0,1,false".to_string() },
+            OutputDiff::Added { key: "4".to_string(), row: "4,2,0,2,false".to_string() }
+        ]);
+
+        let tiers = b"client,available,held,total,locked,tier\n1,5,0,5,false,standard\n";
+
+        assert_eq!(diff_outputs(baseline, tiers).unwrap()[0], OutputDiff::Header {
+            baseline: "client,available,held,total,locked".to_string(),
+            candidate: "client,available,held,total,locked,tier".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let old = read_accounts(
+            b"client,available,held,total,locked\n1,5,0,5,false\n2,1.50,0,1.5,false\n3,0,0,0,true\n"
+        ).unwrap();
+        let new = read_accounts(
+            b"client,available,held,total,locked,tier\n3,0,0,0,false,high\n2,1.5,0,1.5,false,standard\n\
+              1,4,1,5,false,standard\n4,2,0,2,false,standard\n\naccount,balance\nfees,0\n"
+        ).unwrap();
+
+        assert_eq!(reconcile(&old, &old), vec![]);
+        assert_eq!(reconcile(&old, &new), vec![
+            AccountDiff::Changed { client_id: 1, field: "available", old: "5".to_string(), new: "4".to_string() },
+            AccountDiff::Changed { client_id: 1, field: "held", old: "0".to_string(), new: "1".to_string() },
+            AccountDiff::Changed { client_id: 3, field: "locked", old: "true".to_string(), new: "false".to_string() },
+            AccountDiff::Added(4)
+        ]);
+        assert_eq!(reconcile(&new, &old)[3], AccountDiff::Missing(4));
+        assert_eq!(AccountDiff::Added(4).to_string(), "client 4: only in the new report");
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+
+use csv::StringRecord;
+use serde::Serialize;
+
+use crate::{
+    error::EngineError,
+    provenance::Provenance,
+    reason::ReasonCode,
+    types::{ ClientId, Transaction },
+    unknown_types,
+};
+
+// Every row the engine dropped, so operations can reconcile the input with the accounts. Parsed
+// transactions have their fields and the engine error as detail, rows that couldn't be parsed only
+// have the raw row as detail.
+pub struct RejectsFile<W: io::Write> {
+    writer: csv::Writer<W>,
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    source: &'a str,
+    line: u64,
+    reason: ReasonCode,
+    client: Option<ClientId>,
+    tx: Option<u32>,
+    #[serde(rename = "type")]
+    tx_type: Option<&'static str>,
+    amount: Option<String>,
+    detail: String,
+}
+
+impl<W: io::Write> RejectsFile<W> {
+    pub fn new(writer: W) -> Self {
+        RejectsFile { writer: csv::Writer::from_writer(writer) }
+    }
+
+    pub fn reject_transaction(&mut self, provenance: &Provenance, tx: &Transaction, err: &EngineError) {
+        self.write(Row {
+            source: &provenance.source,
+            line: provenance.line,
+            reason: err.reason(),
+            client: Some(tx.client_id),
+            tx: Some(tx.tx_id),
+            tx_type: Some(tx.tx_type.name()),
+            amount: tx.tx_type.amount().map(|amount| amount.normalize().to_string()),
+            detail: err.to_string(),
+        }, provenance);
+    }
+
+    pub fn reject_row(&mut self, provenance: &Provenance, reason: ReasonCode, raw: Option<&StringRecord>) {
+        let detail = match raw.map(unknown_types::encode).transpose() {
+            Ok(detail) => detail.unwrap_or_default(),
+            Err(err) => {
+                log::error!("Failed to encode the rejected row at {}: {}", provenance, err);
+                String::new()
+            }
+        };
+
+        self.write(Row {
+            source: &provenance.source,
+            line: provenance.line,
+            reason,
+            client: None,
+            tx: None,
+            tx_type: None,
+            amount: None,
+            detail,
+        }, provenance);
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write(&mut self, row: Row, provenance: &Provenance) {
+        if let Err(err) = self.writer.serialize(row) {
+            log::error!("Failed to write the rejected row at {}: {}", provenance, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::types::TransactionType;
+
+    #[test]
+    fn test_rejects_file() {
+        let mut output = vec![];
+        let mut file = RejectsFile::new(&mut output);
+
+        let provenance = Provenance { source: "a.csv".into(), line: 3, offset: 40 };
+        file.reject_transaction(
+            &provenance,
+            &Transaction::new(1, 7, TransactionType::Withdrawal(dec!(2.50))),
+            &EngineError::InsufficientFunds { client_id: 1, available: dec!(1), required: dec!(2.5) }
+        );
+        file.reject_row(
+            &Provenance { line: 4, ..provenance.clone() },
+            ReasonCode::Malformed,
+            Some(&StringRecord::from(vec!["deposit", "x", "8", "1"]))
+        );
+        file.reject_row(&Provenance { line: 5, ..provenance }, ReasonCode::Malformed, None);
+        file.flush().unwrap();
+        drop(file);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "source,line,reason,client,tx,type,amount,detail\n\
+             a.csv,3,INSUFFICIENT_FUNDS,1,7,withdrawal,2.5,client 1 has 1 available but 2.5 is required\n\
+             a.csv,4,MALFORMED,,,,,\"deposit,x,8,1\"\n\
+             a.csv,5,MALFORMED,,,,,\n"
+        );
+    }
+}
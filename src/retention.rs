@@ -0,0 +1,36 @@
+// How long and how much transaction history is kept. Pruning never drops a transaction that is
+// under dispute, it's still needed to resolve or charge it back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub max_age_days: Option<u64>,
+    pub max_entries: Option<usize>,
+    pub max_per_client: Option<usize>,
+}
+
+impl Retention {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some() || self.max_entries.is_some() || self.max_per_client.is_some()
+    }
+
+    /// Unix timestamp before which entries are expired, if retention by age is enabled
+    pub fn cutoff(&self, now: Option<u64>) -> Option<u64> {
+        self.max_age_days
+            .zip(now)
+            .map(|(days, now)| now.saturating_sub(days * crate::engine::SECONDS_PER_DAY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cutoff() {
+        let retention = Retention { max_age_days: Some(2), ..Default::default() };
+
+        assert_eq!(retention.cutoff(Some(86400 * 5)), Some(86400 * 3));
+        assert_eq!(retention.cutoff(Some(10)), Some(0));
+        assert_eq!(retention.cutoff(None), None);
+        assert_eq!(Retention::default().cutoff(Some(10)), None);
+    }
+}
@@ -0,0 +1,293 @@
+use std::{ collections::{ BTreeMap, HashMap, VecDeque }, fmt, io, str::FromStr, time::Duration };
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::types::{ custom_serde, ClientId, Transaction, TransactionType };
+
+/// A signal over its threshold flags the client, e.g. `chargebacks>1` or `dispute_rate>0.5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskThreshold {
+    Chargebacks(u32),
+    DisputeRate(Decimal),
+    Cycles(u32),
+    Velocity(u32),
+}
+
+impl RiskThreshold {
+    pub fn exceeded(&self, signals: &RiskSignals) -> bool {
+        match self {
+            RiskThreshold::Chargebacks(max) => signals.chargebacks > *max,
+            RiskThreshold::DisputeRate(max) => signals.dispute_rate() > *max,
+            RiskThreshold::Cycles(max) => signals.cycles > *max,
+            RiskThreshold::Velocity(max) => signals.velocity > *max,
+        }
+    }
+}
+
+impl fmt::Display for RiskThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskThreshold::Chargebacks(max) => write!(f, "chargebacks>{}", max),
+            RiskThreshold::DisputeRate(max) => write!(f, "dispute_rate>{}", max),
+            RiskThreshold::Cycles(max) => write!(f, "cycles>{}", max),
+            RiskThreshold::Velocity(max) => write!(f, "velocity>{}", max),
+        }
+    }
+}
+
+impl FromStr for RiskThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (signal, value) = s
+            .trim()
+            .split_once('>')
+            .ok_or_else(|| format!("expected SIGNAL>VALUE instead of {}", s))?;
+        let count = || value.trim().parse::<u32>().map_err(|_| format!("invalid count {}", value));
+
+        match signal {
+            "chargebacks" => Ok(RiskThreshold::Chargebacks(count()?)),
+            "dispute_rate" => {
+                value
+                    .trim()
+                    .parse()
+                    .map(RiskThreshold::DisputeRate)
+                    .map_err(|_| format!("invalid rate {}", value))
+            }
+            "cycles" => Ok(RiskThreshold::Cycles(count()?)),
+            "velocity" => Ok(RiskThreshold::Velocity(count()?)),
+            _ => {
+                Err(
+                    format!(
+                        "unknown risk signal {}, expected chargebacks, dispute_rate, cycles or velocity",
+                        signal
+                    )
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RiskSignals {
+    pub deposits: u32,
+    pub disputes: u32,
+    pub chargebacks: u32,
+    /// Withdrawals within the window after a deposit
+    pub cycles: u32,
+    /// The most deposits, withdrawals and transfers the client made within one window
+    pub velocity: u32,
+}
+
+impl RiskSignals {
+    /// Disputes per deposit, rounded like the balances.
+    pub fn dispute_rate(&self) -> Decimal {
+        match self.deposits {
+            0 => Decimal::ZERO,
+            deposits => custom_serde::rounding().round(Decimal::from(self.disputes) / Decimal::from(deposits)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Activity {
+    // Timestamps of the moves of funds within the window of the latest one
+    recent: VecDeque<u64>,
+    // Timestamp of the last deposit that wasn't withdrawn yet
+    deposit: Option<u64>,
+}
+
+/// Computes the risk signals of every client from the transactions the engine applied. The window
+/// follows the transaction timestamps, one without a timestamp is taken as made at the time of the
+/// latest one seen.
+#[derive(Debug, Clone, Default)]
+pub struct RiskScoring {
+    window: u64,
+    thresholds: Vec<RiskThreshold>,
+    signals: BTreeMap<ClientId, RiskSignals>,
+    activity: HashMap<ClientId, Activity>,
+    latest: Option<u64>,
+}
+
+impl RiskScoring {
+    pub fn new(window: Duration, thresholds: impl IntoIterator<Item = RiskThreshold>) -> Self {
+        RiskScoring { window: window.as_secs(), thresholds: thresholds.into_iter().collect(), ..Default::default() }
+    }
+
+    pub fn record(&mut self, tx: &Transaction) {
+        let now = tx.timestamp.or(self.latest).unwrap_or_default();
+        self.latest = self.latest.max(tx.timestamp);
+
+        let signals = self.signals.entry(tx.client_id).or_default();
+        let activity = self.activity.entry(tx.client_id).or_default();
+
+        match tx.tx_type {
+            TransactionType::Dispute => {
+                signals.disputes += 1;
+                return;
+            }
+            TransactionType::Chargeback => {
+                signals.chargebacks += 1;
+                return;
+            }
+            TransactionType::Deposit(_) => {
+                signals.deposits += 1;
+                activity.deposit = Some(now);
+            }
+            TransactionType::Withdrawal(_) => {
+                if activity.deposit.take().is_some_and(|deposit| now.saturating_sub(deposit) <= self.window) {
+                    signals.cycles += 1;
+                }
+            }
+            TransactionType::Transfer { .. } => {}
+            _ => {
+                return;
+            }
+        }
+
+        while activity.recent.front().is_some_and(|time| now.saturating_sub(*time) > self.window) {
+            activity.recent.pop_front();
+        }
+
+        activity.recent.push_back(now);
+        signals.velocity = signals.velocity.max(activity.recent.len() as u32);
+    }
+
+    pub fn signals(&self, client_id: ClientId) -> Option<&RiskSignals> {
+        self.signals.get(&client_id)
+    }
+
+    /// The thresholds the signals of a client are over.
+    pub fn flags(&self, client_id: ClientId) -> Vec<RiskThreshold> {
+        let Some(signals) = self.signals.get(&client_id) else {
+            return vec![];
+        };
+
+        self.thresholds
+            .iter()
+            .filter(|threshold| threshold.exceeded(signals))
+            .copied()
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.signals.clear();
+        self.activity.clear();
+        self.latest = None;
+    }
+
+    /// One row per client with its signals and the thresholds they're over, separated by `;`.
+    pub fn write_report<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        #[derive(Serialize)]
+        struct Row {
+            client: ClientId,
+            deposits: u32,
+            disputes: u32,
+            chargebacks: u32,
+            #[serde(serialize_with = "custom_serde::serialize_decimal")]
+            dispute_rate: Decimal,
+            cycles: u32,
+            velocity: u32,
+            flags: String,
+        }
+
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for (client_id, signals) in &self.signals {
+            let flags: Vec<String> = self
+                .flags(*client_id)
+                .iter()
+                .map(RiskThreshold::to_string)
+                .collect();
+
+            writer.serialize(Row {
+                client: *client_id,
+                deposits: signals.deposits,
+                disputes: signals.disputes,
+                chargebacks: signals.chargebacks,
+                dispute_rate: signals.dispute_rate(),
+                cycles: signals.cycles,
+                velocity: signals.velocity,
+                flags: flags.join(";"),
+            })?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn tx(client_id: ClientId, tx_id: u32, tx_type: TransactionType, timestamp: Option<u64>) -> Transaction {
+        Transaction { timestamp, ..Transaction::new(client_id, tx_id, tx_type) }
+    }
+
+    #[test]
+    fn test_parse_thresholds() {
+        assert_eq!("chargebacks>1".parse(), Ok(RiskThreshold::Chargebacks(1)));
+        assert_eq!("dispute_rate>0.5".parse(), Ok(RiskThreshold::DisputeRate(dec!(0.5))));
+        assert_eq!("cycles>3".parse(), Ok(RiskThreshold::Cycles(3)));
+        assert_eq!("velocity>20".parse(), Ok(RiskThreshold::Velocity(20)));
+        assert!("velocity<20".parse::<RiskThreshold>().is_err());
+        assert!("cycles>x".parse::<RiskThreshold>().is_err());
+        assert!("refunds>1".parse::<RiskThreshold>().is_err());
+
+        assert_eq!(RiskThreshold::DisputeRate(dec!(0.25)).to_string(), "dispute_rate>0.25");
+    }
+
+    #[test]
+    fn test_signals() {
+        let mut scoring = RiskScoring::new(Duration::from_secs(60), [RiskThreshold::Cycles(1)]);
+
+        scoring.record(&tx(1, 1, TransactionType::Deposit(dec!(10)), Some(0)));
+        scoring.record(&tx(1, 2, TransactionType::Withdrawal(dec!(10)), Some(30)));
+        scoring.record(&tx(1, 3, TransactionType::Deposit(dec!(10)), Some(40)));
+        scoring.record(&tx(1, 3, TransactionType::Dispute, None));
+        // Too late for a cycle
+        scoring.record(&tx(1, 4, TransactionType::Withdrawal(dec!(5)), Some(200)));
+        scoring.record(&tx(1, 3, TransactionType::Chargeback, None));
+
+        assert_eq!(
+            scoring.signals(1),
+            Some(&RiskSignals { deposits: 2, disputes: 1, chargebacks: 1, cycles: 1, velocity: 3 })
+        );
+        assert_eq!(scoring.signals(1).unwrap().dispute_rate(), dec!(0.5));
+        assert!(scoring.flags(1).is_empty());
+
+        scoring.record(&tx(1, 5, TransactionType::Deposit(dec!(10)), Some(210)));
+        scoring.record(&tx(1, 6, TransactionType::Withdrawal(dec!(10)), Some(220)));
+
+        assert_eq!(scoring.flags(1), vec![RiskThreshold::Cycles(1)]);
+        assert_eq!(scoring.signals(2), None);
+    }
+
+    #[test]
+    fn test_write_report() {
+        let mut scoring = RiskScoring::new(
+            Duration::from_secs(60),
+            [RiskThreshold::Chargebacks(0), RiskThreshold::DisputeRate(dec!(0.5))]
+        );
+
+        scoring.record(&tx(2, 1, TransactionType::Deposit(dec!(10)), None));
+        scoring.record(&tx(2, 1, TransactionType::Dispute, None));
+        scoring.record(&tx(2, 1, TransactionType::Chargeback, None));
+        scoring.record(&tx(1, 2, TransactionType::Deposit(dec!(10)), None));
+
+        let mut output = vec![];
+        scoring.write_report(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,deposits,disputes,chargebacks,dispute_rate,cycles,velocity,flags\n\
+             1,1,0,0,0,0,1,\n\
+             2,1,1,1,1,0,1,chargebacks>0;dispute_rate>0.5\n"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::{ collections::{ BTreeMap, HashMap }, io };
+
+use chrono::{ DateTime, NaiveDate };
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{ event_log::Projection, types::{ custom_serde, ClientId, Transaction, TransactionType } };
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DailyActivity {
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub disputes: Decimal,
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    client: ClientId,
+    date: String,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    deposits: &'a Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    withdrawals: &'a Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    disputes: &'a Decimal,
+}
+
+#[derive(Default)]
+pub struct DailyRollup {
+    days: BTreeMap<(ClientId, NaiveDate), DailyActivity>,
+    deposits: HashMap<u32, Decimal>,
+    // Withdrawal holds count as withdrawals the day they are committed
+    holds: HashMap<u32, Decimal>,
+}
+
+impl DailyRollup {
+    pub fn new() -> Self {
+        DailyRollup::default()
+    }
+
+    pub fn get(&self, client_id: ClientId, date: NaiveDate) -> Option<&DailyActivity> {
+        self.days.get(&(client_id, date))
+    }
+
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for ((client_id, date), activity) in self.days.iter() {
+            writer.serialize(Row {
+                client: *client_id,
+                date: date.format("%Y-%m-%d").to_string(),
+                deposits: &activity.deposits,
+                withdrawals: &activity.withdrawals,
+                disputes: &activity.disputes,
+            })?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet<W: io::Write + Send>(&self, writer: W) -> parquet::errors::Result<()> {
+        use std::sync::Arc;
+
+        use parquet::{
+            data_type::{ Int32Type, Int64Type },
+            file::{ properties::WriterProperties, writer::SerializedFileWriter },
+            schema::parser::parse_message_type,
+        };
+
+        let schema = parse_message_type(
+            &format!(
+                "message daily_rollup {{
+                    required int32 client (INTEGER({}, false));
+                    required int32 date (DATE);
+                    required int64 deposits (DECIMAL(18, 4));
+                    required int64 withdrawals (DECIMAL(18, 4));
+                    required int64 disputes (DECIMAL(18, 4));
+                }}",
+                ClientId::BITS
+            )
+        )?;
+
+        let epoch = NaiveDate::default();
+        let clients: Vec<i32> = self.days
+            .keys()
+            .map(|(client_id, _)| *client_id as i32)
+            .collect();
+        let dates: Vec<i32> = self.days
+            .keys()
+            .map(|(_, date)| (*date - epoch).num_days() as i32)
+            .collect();
+        let amounts = |f: fn(&DailyActivity) -> Decimal| -> Vec<i64> {
+            self.days
+                .values()
+                .map(|activity| to_scaled(f(activity)))
+                .collect()
+        };
+
+        let mut writer = SerializedFileWriter::new(
+            writer,
+            Arc::new(schema),
+            Arc::new(WriterProperties::builder().build())
+        )?;
+        let mut row_group = writer.next_row_group()?;
+
+        for values in [clients, dates] {
+            let mut column = row_group.next_column()?.expect("missing int32 column");
+            column.typed::<Int32Type>().write_batch(&values, None, None)?;
+            column.close()?;
+        }
+
+        for values in [
+            amounts(|activity| activity.deposits),
+            amounts(|activity| activity.withdrawals),
+            amounts(|activity| activity.disputes),
+        ] {
+            let mut column = row_group.next_column()?.expect("missing decimal column");
+            column.typed::<Int64Type>().write_batch(&values, None, None)?;
+            column.close()?;
+        }
+
+        row_group.close()?;
+        writer.close()?;
+
+        Ok(())
+    }
+}
+
+// Rounded like the CSV columns, then scaled to the 4 decimal places of the schema
+#[cfg(feature = "parquet")]
+fn to_scaled(value: Decimal) -> i64 {
+    let mut value = custom_serde::rounding().round(value);
+    value.rescale(4);
+    value.mantissa() as i64
+}
+
+impl Projection for DailyRollup {
+    fn apply(&mut self, tx: &Transaction) {
+        match tx.tx_type {
+            TransactionType::Deposit(amount) | TransactionType::Transfer { amount, .. } => {
+                self.deposits.insert(tx.tx_id, amount);
+            }
+            TransactionType::WithdrawHold(amount) => {
+                self.holds.insert(tx.tx_id, amount);
+            }
+            TransactionType::WithdrawVoid => {
+                self.holds.remove(&tx.tx_id);
+            }
+            TransactionType::Merge(_) => {
+                return;
+            }
+            _ => {}
+        }
+
+        let Some(date) = tx.timestamp
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp as i64, 0))
+            .map(|datetime| datetime.date_naive()) else {
+            log::debug!("Transaction {} has no timestamp, skipping rollup", tx.tx_id);
+            return;
+        };
+
+        let activity = self.days.entry((tx.client_id, date)).or_default();
+
+        match tx.tx_type {
+            TransactionType::Deposit(amount) => {
+                activity.deposits += amount;
+            }
+            TransactionType::Withdrawal(amount) => {
+                activity.withdrawals += amount;
+            }
+            TransactionType::Transfer { to, amount } => {
+                activity.withdrawals += amount;
+                self.days.entry((to, date)).or_default().deposits += amount;
+            }
+            TransactionType::Dispute => {
+                if let Some(amount) = self.deposits.get(&tx.tx_id) {
+                    activity.disputes += *amount;
+                }
+            }
+            TransactionType::WithdrawCommit => {
+                if let Some(amount) = self.holds.remove(&tx.tx_id) {
+                    activity.withdrawals += amount;
+                }
+            }
+            TransactionType::Resolve |
+            TransactionType::Chargeback |
+            TransactionType::Representment |
+            TransactionType::Merge(_) |
+            TransactionType::Recovery(_) |
+            TransactionType::Adjustment { .. } |
+            TransactionType::Approve |
+            TransactionType::Decline |
+            TransactionType::Unlock |
+            TransactionType::Delete |
+            TransactionType::Restore |
+            TransactionType::WithdrawHold(_) |
+            TransactionType::WithdrawVoid |
+            TransactionType::Convert { .. } => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.days.clear();
+        self.deposits.clear();
+        self.holds.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    const DAY: u64 = 86400;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_rollup() {
+        let mut rollup = DailyRollup::new();
+
+        rollup.apply(&Transaction::new(1, 1, TransactionType::Deposit(dec!(10))).with_timestamp(0));
+        rollup.apply(&Transaction::new(1, 2, TransactionType::Deposit(dec!(5))).with_timestamp(10));
+        rollup.apply(
+            &Transaction::new(1, 3, TransactionType::Withdrawal(dec!(3))).with_timestamp(DAY)
+        );
+        rollup.apply(&Transaction::new(1, 2, TransactionType::Dispute).with_timestamp(DAY + 1));
+        rollup.apply(&Transaction::new(2, 4, TransactionType::Deposit(dec!(1))).with_timestamp(0));
+        rollup.apply(&Transaction::new(1, 5, TransactionType::WithdrawHold(dec!(2))).with_timestamp(20));
+        rollup.apply(&Transaction::new(1, 5, TransactionType::WithdrawCommit).with_timestamp(DAY + 2));
+
+        assert_eq!(rollup.get(1, date(1970, 1, 1)), Some(&DailyActivity {
+            deposits: dec!(15),
+            withdrawals: dec!(0),
+            disputes: dec!(0),
+        }));
+        assert_eq!(rollup.get(1, date(1970, 1, 2)), Some(&DailyActivity {
+            deposits: dec!(0),
+            withdrawals: dec!(5),
+            disputes: dec!(5),
+        }));
+        assert_eq!(rollup.get(2, date(1970, 1, 1)), Some(&DailyActivity {
+            deposits: dec!(1),
+            withdrawals: dec!(0),
+            disputes: dec!(0),
+        }));
+    }
+
+    #[test]
+    fn test_rollup_without_timestamp() {
+        let mut rollup = DailyRollup::new();
+
+        rollup.apply(&Transaction::new(1, 1, TransactionType::Deposit(dec!(10))));
+        rollup.apply(&Transaction::new(1, 1, TransactionType::Dispute).with_timestamp(0));
+
+        assert_eq!(rollup.get(1, date(1970, 1, 1)), Some(&DailyActivity {
+            deposits: dec!(0),
+            withdrawals: dec!(0),
+            disputes: dec!(10),
+        }));
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut rollup = DailyRollup::new();
+
+        rollup.apply(&Transaction::new(2, 1, TransactionType::Deposit(dec!(1.5))).with_timestamp(0));
+        rollup.apply(
+            &Transaction::new(1, 2, TransactionType::Withdrawal(dec!(2))).with_timestamp(DAY)
+        );
+
+        let mut output = vec![];
+        rollup.write_csv(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,date,deposits,withdrawals,disputes\n1,1970-01-02,0,2,0\n2,1970-01-01,1.5,0,0\n"
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_write_parquet() {
+        use parquet::file::reader::{ FileReader, SerializedFileReader };
+
+        let mut rollup = DailyRollup::new();
+
+        rollup.apply(&Transaction::new(1, 1, TransactionType::Deposit(dec!(1.5))).with_timestamp(0));
+
+        let path = std::env::temp_dir().join("transaction-engine-rollup-test.parquet");
+        rollup.write_parquet(std::fs::File::create(&path).unwrap()).unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 1);
+    }
+}
@@ -0,0 +1,281 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+use serde_json::{ Map, Value };
+
+use crate::{ engine::Engine, store::StateStore, types::{ ClientId, Transaction } };
+
+// A tiny text format to write down what happens to a few accounts, for tests, bug reproductions and
+// demos. Every statement is a line, or is separated from the next one by a `/`, and `#` starts a
+// comment:
+//
+//     deposit 1 1 10          # type, client, tx and amount
+//     dispute 1 1 / expect 1 available=0 held=10 total=10 locked=false
+//     transfer 1 2 1 to_client=2     # other fields of the JSON input as key=value
+//     expect tx 1 under_dispute      # applied, under_dispute, resolved, charged_back, rejected or unknown
+//     expect tx 2 rejected=NOT_ALLOWED
+//
+// Rejected transactions don't fail a scenario, `expect tx` checks them.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+/// An expectation that didn't hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: expected {}, got {}", self.line, self.expected, self.actual)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Available(Decimal),
+    Held(Decimal),
+    Total(Decimal),
+    Locked(bool),
+}
+
+#[derive(Debug)]
+enum Step {
+    Apply(Transaction),
+    ExpectAccount {
+        client_id: ClientId,
+        fields: Vec<Field>,
+    },
+    ExpectStatus {
+        tx_id: u32,
+        status: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct Scenario {
+    steps: Vec<(usize, Step)>,
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Self, ScenarioError> {
+        let mut steps = vec![];
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or_default();
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+
+            for statement in tokens.split(|token| *token == "/").filter(|tokens| !tokens.is_empty()) {
+                let step = parse_step(statement)
+                    .map_err(|message| ScenarioError { line: line_number, message })?;
+
+                steps.push((line_number, step));
+            }
+        }
+
+        Ok(Scenario { steps })
+    }
+
+    /// Applies the transactions in order and checks the expectations when they're reached,
+    /// returns the ones that didn't hold.
+    pub fn run<S: StateStore>(&self, engine: &mut Engine<S>) -> Vec<Failure> {
+        let mut failures = vec![];
+
+        for (line, step) in &self.steps {
+            match step {
+                Step::Apply(tx) => {
+                    if let Err(err) = engine.add_transaction(tx.clone()) {
+                        log::info!("line {}: rejected: {}", line, err);
+                    }
+                }
+                Step::ExpectAccount { client_id, fields } => {
+                    let Some(account) = engine.get_account(*client_id) else {
+                        failures.push(Failure {
+                            line: *line,
+                            expected: format!("client {}", client_id),
+                            actual: "no account".to_string(),
+                        });
+                        continue;
+                    };
+
+                    for field in fields {
+                        let actual = match field {
+                            Field::Available(_) => Field::Available(account.available),
+                            Field::Held(_) => Field::Held(account.held),
+                            Field::Total(_) => Field::Total(account.total),
+                            Field::Locked(_) => Field::Locked(account.locked),
+                        };
+
+                        if actual != *field {
+                            failures.push(Failure {
+                                line: *line,
+                                expected: format!("client {} {}", client_id, field),
+                                actual: actual.to_string(),
+                            });
+                        }
+                    }
+                }
+                Step::ExpectStatus { tx_id, status } => {
+                    let actual = match engine.tx_status(*tx_id) {
+                        Some(actual) => match (actual.reason(), status.contains('=')) {
+                            (Some(reason), true) => format!("{}={}", actual.name(), reason),
+                            _ => actual.name().to_string(),
+                        },
+                        None => "unknown".to_string(),
+                    };
+
+                    if actual != *status {
+                        failures.push(Failure {
+                            line: *line,
+                            expected: format!("tx {} {}", tx_id, status),
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+
+        failures
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Available(amount) => write!(f, "available={}", amount.normalize()),
+            Field::Held(amount) => write!(f, "held={}", amount.normalize()),
+            Field::Total(amount) => write!(f, "total={}", amount.normalize()),
+            Field::Locked(locked) => write!(f, "locked={}", locked),
+        }
+    }
+}
+
+fn parse_step(tokens: &[&str]) -> Result<Step, String> {
+    match tokens {
+        ["expect", "tx", tx_id, status] => {
+            Ok(Step::ExpectStatus { tx_id: parse_number(tx_id)?, status: status.to_string() })
+        }
+        ["expect", client_id, fields @ ..] if !fields.is_empty() => {
+            let fields = fields.iter().map(|field| parse_field(field)).collect::<Result<_, _>>()?;
+
+            Ok(Step::ExpectAccount { client_id: parse_number(client_id)?, fields })
+        }
+        ["expect", ..] => Err("expected `expect CLIENT FIELD=VALUE...` or `expect tx TX STATUS`".to_string()),
+        [tx_type, client_id, tx_id, rest @ ..] => {
+            let mut record = Map::new();
+            record.insert("type".to_string(), Value::from(*tx_type));
+            record.insert("client".to_string(), Value::from(parse_number::<u64>(client_id)?));
+            record.insert("tx".to_string(), Value::from(parse_number::<u32>(tx_id)?));
+
+            for (index, token) in rest.iter().enumerate() {
+                let (key, value) = match token.split_once('=') {
+                    Some((key, value)) => (key, value),
+                    None if index == 0 => ("amount", *token),
+                    None => return Err(format!("expected KEY=VALUE instead of {}", token)),
+                };
+
+                let value = match (key, value.parse::<u64>()) {
+                    ("amount" | "reason" | "currency", _) | (_, Err(_)) => Value::from(value),
+                    (_, Ok(number)) => Value::from(number),
+                };
+
+                record.insert(key.to_string(), value);
+            }
+
+            serde_json::from_value(Value::Object(record))
+                .map(Step::Apply)
+                .map_err(|err| err.to_string())
+        }
+        _ => Err("expected `TYPE CLIENT TX [AMOUNT] [KEY=VALUE...]`".to_string()),
+    }
+}
+
+fn parse_field(field: &str) -> Result<Field, String> {
+    let (name, value) = field
+        .split_once('=')
+        .ok_or_else(|| format!("expected FIELD=VALUE instead of {}", field))?;
+    let amount = || value.parse::<Decimal>().map_err(|err| format!("invalid {} {}: {}", name, value, err));
+
+    match name {
+        "available" => Ok(Field::Available(amount()?)),
+        "held" => Ok(Field::Held(amount()?)),
+        "total" => Ok(Field::Total(amount()?)),
+        "locked" => value.parse().map(Field::Locked).map_err(|_| format!("invalid locked {}", value)),
+        _ => Err(format!("unknown field {}, expected available, held, total or locked", name)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(token: &str) -> Result<T, String> {
+    token.parse().map_err(|_| format!("invalid number {}", token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispute_scenario() {
+        let scenario = Scenario::parse(
+            "deposit 1 1 10 / dispute 1 1 / expect 1 available=0 held=10\n\
+             # the funds come back\n\
+             resolve 1 1\n\
+             expect 1 available=10.0 held=0 total=10 locked=false\n\
+             withdrawal 1 2 20   # not enough funds\n\
+             expect tx 1 resolved / expect tx 2 rejected=INSUFFICIENT_FUNDS / expect tx 3 unknown"
+        ).unwrap();
+
+        assert_eq!(scenario.run(&mut Engine::new()), vec![]);
+    }
+
+    #[test]
+    fn test_failures() {
+        let scenario = Scenario::parse(
+            "deposit 1 1 10\nexpect 1 available=5 locked=false\nexpect 2 held=0\nexpect tx 1 rejected"
+        ).unwrap();
+
+        assert_eq!(
+            scenario.run(&mut Engine::new()),
+            vec![
+                Failure { line: 2, expected: "client 1 available=5".to_string(), actual: "available=10".to_string() },
+                Failure { line: 3, expected: "client 2".to_string(), actual: "no account".to_string() },
+                Failure { line: 4, expected: "tx 1 rejected".to_string(), actual: "applied".to_string() }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Scenario::parse("deposit 1 1 10\ndeposit x 2 10").unwrap_err(),
+            ScenarioError { line: 2, message: "invalid number x".to_string() }
+        );
+        assert_eq!(Scenario::parse("expect 1 frozen=true").unwrap_err().line, 1);
+        assert_eq!(Scenario::parse("expect 1").unwrap_err().line, 1);
+        assert_eq!(Scenario::parse("deposit 1 1 10 20").unwrap_err().line, 1);
+        assert!(Scenario::parse("teleport 1 1 10").is_err());
+    }
+
+    #[test]
+    fn test_extra_fields() {
+        let scenario = Scenario::parse(
+            "deposit 1 1 10 / transfer 1 2 4 to_client=2 / expect 2 available=4 / expect 1 available=6"
+        ).unwrap();
+
+        assert_eq!(scenario.run(&mut Engine::new()), vec![]);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{ BTreeMap, HashSet };
+
+use rust_decimal::Decimal;
+use serde::{ Deserialize, Serialize };
+
+use crate::{ engine::SECONDS_PER_DAY, types::{ custom_serde::Rounding, ClientId, Transaction, TransactionType } };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleaseKind {
+    /// The funds of a deposit become available
+    DepositHold,
+    /// A dispute nobody resolved or charged back is resolved
+    DisputeExpiry,
+    /// An uncommitted withdrawal hold gives its funds back
+    WithdrawalHold,
+}
+
+/// A timed obligation on a transaction. It carries the client and amount so it can be restored
+/// from a snapshot without the transaction history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledRelease {
+    pub at: u64,
+    pub kind: ReleaseKind,
+    pub tx_id: u32,
+    pub client_id: ClientId,
+    pub amount: Decimal,
+}
+
+// Releases by time, ties in the order of the transaction ids. A release whose transaction moved on
+// (a hold committed, a dispute resolved) is left in place, whoever pops it checks it still applies.
+#[derive(Debug, Clone, Default)]
+pub struct Scheduler {
+    releases: BTreeMap<(u64, u32, ReleaseKind), ScheduledRelease>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler::default()
+    }
+
+    pub fn schedule(&mut self, release: ScheduledRelease) {
+        self.releases.insert((release.at, release.tx_id, release.kind), release);
+    }
+
+    /// The earliest release due at `now`, if any.
+    pub fn pop_due(&mut self, now: u64) -> Option<ScheduledRelease> {
+        match self.releases.first_key_value() {
+            Some(((at, _, _), _)) if *at <= now => self.releases.pop_first().map(|(_, release)| release),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ScheduledRelease> {
+        self.releases.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.releases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.releases.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.releases.clear();
+    }
+
+    /// Removes the releases of the clients, in time order.
+    pub fn take_clients(&mut self, clients: &HashSet<ClientId>) -> Vec<ScheduledRelease> {
+        let keys: Vec<(u64, u32, ReleaseKind)> = self.releases
+            .iter()
+            .filter(|(_, release)| clients.contains(&release.client_id))
+            .map(|(key, _)| *key)
+            .collect();
+
+        keys.into_iter().filter_map(|key| self.releases.remove(&key)).collect()
+    }
+}
+
+/// An operation run on the available balance of every account at the end of each day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Pays interest at this daily rate on positive available balances, e.g. 0.0001 for 0.01%
+    Interest(Decimal),
+    /// Sweeps the available funds over this threshold out of the account
+    Sweep(Decimal),
+}
+
+impl Operation {
+    /// The reason of the adjustments it makes, for the audit log and the statements
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Interest(_) => "interest",
+            Operation::Sweep(_) => "sweep",
+        }
+    }
+
+    /// The signed amount it adjusts an available balance by, if any, interest rounded like the
+    /// balances
+    pub fn amount(&self, available: Decimal, rounding: Rounding) -> Option<Decimal> {
+        let amount = match *self {
+            Operation::Interest(rate) if available > Decimal::ZERO => rounding.round(available * rate),
+            Operation::Sweep(threshold) if available > threshold => threshold - available,
+            _ => Decimal::ZERO,
+        };
+
+        Some(amount).filter(|amount| !amount.is_zero())
+    }
+}
+
+// The operations run at every day end (midnight UTC) the clock goes past, in the order they were
+// added. The first time seen only starts the count, a day the engine didn't see start isn't run.
+// Their adjustments take the free transaction ids down from `u32::MAX`.
+#[derive(Debug, Clone)]
+pub struct DailyOperations {
+    operations: Vec<Operation>,
+    next_day_end: Option<u64>,
+    next_id: u32,
+}
+
+impl Default for DailyOperations {
+    fn default() -> Self {
+        DailyOperations { operations: vec![], next_day_end: None, next_id: u32::MAX }
+    }
+}
+
+impl DailyOperations {
+    pub fn new(operations: Vec<Operation>) -> Self {
+        DailyOperations { operations, ..Default::default() }
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// The day ends passed since the last call, in order.
+    pub fn due(&mut self, now: u64) -> Vec<u64> {
+        let first = (now / SECONDS_PER_DAY + 1) * SECONDS_PER_DAY;
+        let next = *self.next_day_end.get_or_insert(first);
+
+        if next > now {
+            return vec![];
+        }
+
+        self.next_day_end = Some(first);
+
+        (next..=now).step_by(SECONDS_PER_DAY as usize).collect()
+    }
+
+    /// The next transaction id for an adjustment, skipping the ones `taken`.
+    pub fn next_id(&mut self, taken: impl Fn(u32) -> bool) -> u32 {
+        while taken(self.next_id) {
+            self.next_id -= 1;
+        }
+
+        let id = self.next_id;
+        self.next_id -= 1;
+
+        id
+    }
+
+    /// Whether the transaction is an adjustment made by the operations
+    pub fn made(&self, tx: &Transaction) -> bool {
+        match &tx.tx_type {
+            TransactionType::Adjustment { reason, .. } => {
+                tx.tx_id > self.next_id && self.operations.iter().any(|operation| operation.name() == reason)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn release(at: u64, kind: ReleaseKind, tx_id: u32) -> ScheduledRelease {
+        ScheduledRelease { at, kind, tx_id, client_id: 1, amount: dec!(1) }
+    }
+
+    #[test]
+    fn test_pop_due_in_order() {
+        let mut scheduler = Scheduler::new();
+
+        scheduler.schedule(release(20, ReleaseKind::WithdrawalHold, 1));
+        scheduler.schedule(release(10, ReleaseKind::DisputeExpiry, 3));
+        scheduler.schedule(release(10, ReleaseKind::DepositHold, 2));
+
+        assert_eq!(scheduler.pop_due(5), None);
+        assert_eq!(scheduler.pop_due(15), Some(release(10, ReleaseKind::DepositHold, 2)));
+        assert_eq!(scheduler.pop_due(15), Some(release(10, ReleaseKind::DisputeExpiry, 3)));
+        assert_eq!(scheduler.pop_due(15), None);
+        assert_eq!(scheduler.len(), 1);
+        assert_eq!(scheduler.pop_due(20), Some(release(20, ReleaseKind::WithdrawalHold, 1)));
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn test_day_ends() {
+        let mut operations = DailyOperations::new(vec![Operation::Interest(dec!(0.01))]);
+
+        assert!(operations.due(SECONDS_PER_DAY + 10).is_empty());
+        assert!(operations.due(SECONDS_PER_DAY * 2 - 1).is_empty());
+        assert_eq!(operations.due(SECONDS_PER_DAY * 2), vec![SECONDS_PER_DAY * 2]);
+        assert_eq!(operations.due(SECONDS_PER_DAY * 4 + 5), vec![SECONDS_PER_DAY * 3, SECONDS_PER_DAY * 4]);
+        assert!(operations.due(SECONDS_PER_DAY * 4 + 6).is_empty());
+
+        assert_eq!(operations.next_id(|id| id == u32::MAX), u32::MAX - 1);
+        assert_eq!(operations.next_id(|_| false), u32::MAX - 2);
+    }
+
+    #[test]
+    fn test_operation_amounts() {
+        assert_eq!(Operation::Interest(dec!(0.001)).amount(dec!(150), Rounding::default()), Some(dec!(0.15)));
+        assert_eq!(Operation::Interest(dec!(0.001)).amount(dec!(-150), Rounding::default()), None);
+        // Rounded like the balances
+        assert_eq!(Operation::Interest(dec!(0.001)).amount(dec!(0.01), Rounding::default()), None);
+        assert_eq!(Operation::Sweep(dec!(100)).amount(dec!(150), Rounding::default()), Some(dec!(-50)));
+        assert_eq!(Operation::Sweep(dec!(100)).amount(dec!(100), Rounding::default()), None);
+    }
+}
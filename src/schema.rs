@@ -0,0 +1,136 @@
+use std::fmt;
+
+use csv::StringRecord;
+
+const REQUIRED: [&str; 3] = ["type", "client", "tx"];
+// A file of disputes, resolves and chargebacks only has no amounts
+const OPTIONAL: [&str; 9] = [
+    "amount",
+    "into",
+    "timestamp",
+    "currency",
+    "seq",
+    "reason",
+    "to_client",
+    "to_currency",
+    "tenant",
+];
+
+// Other names upstream systems give the columns
+const ALIASES: [(&str, &str); 4] = [
+    ("client_id", "client"),
+    ("tx_id", "tx"),
+    ("transaction", "tx"),
+    ("ts", "timestamp"),
+];
+
+/// The headers with every column under the name the engine knows it by: names are trimmed and
+/// lowercased and aliases like `client_id` or `tx_id` renamed.
+pub fn normalize(headers: &StringRecord) -> StringRecord {
+    headers
+        .iter()
+        .map(|header| {
+            let header = header.trim().to_lowercase();
+
+            match ALIASES.iter().find(|(alias, _)| *alias == header) {
+                Some((_, column)) => column.to_string(),
+                None => header,
+            }
+        })
+        .collect()
+}
+
+// The columns of an input file. Optional columns turn on the features that need them, columns the
+// engine doesn't know are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    pub missing: Vec<String>,
+    pub timestamp: bool,
+    pub currency: bool,
+    pub seq: bool,
+    pub ignored: Vec<String>,
+}
+
+impl Schema {
+    pub fn detect(headers: &StringRecord) -> Self {
+        let has = |column: &str| headers.iter().any(|header| header == column);
+
+        Schema {
+            missing: REQUIRED.iter()
+                .filter(|column| !has(column))
+                .map(|column| column.to_string())
+                .collect(),
+            timestamp: has("timestamp"),
+            currency: has("currency"),
+            seq: has("seq"),
+            ignored: headers
+                .iter()
+                .filter(|header| !REQUIRED.contains(header) && !OPTIONAL.contains(header))
+                .map(|header| header.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let feature = |present: bool| if present { "present" } else { "absent" };
+
+        write!(
+            f,
+            "timestamp {} (time-based features), currency {}, seq {}",
+            feature(self.timestamp),
+            feature(self.currency),
+            feature(self.seq)
+        )?;
+
+        if !self.ignored.is_empty() {
+            write!(f, ", ignored columns {}", self.ignored.join(" "))?;
+        }
+
+        if !self.missing.is_empty() {
+            write!(f, ", missing required columns {}", self.missing.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let schema = Schema::detect(
+            &StringRecord::from(vec!["type", "client", "tx", "amount", "seq", "channel"])
+        );
+
+        assert_eq!(schema, Schema {
+            missing: vec![],
+            timestamp: false,
+            currency: false,
+            seq: true,
+            ignored: vec!["channel".to_string()],
+        });
+        assert_eq!(
+            schema.to_string(),
+            "timestamp absent (time-based features), currency absent, seq present, ignored columns channel"
+        );
+
+        let schema = Schema::detect(&StringRecord::from(vec!["type", "client", "timestamp"]));
+        assert_eq!(schema.missing, vec!["tx"]);
+        assert!(schema.timestamp);
+
+        let schema = Schema::detect(&StringRecord::from(vec!["tx", "type", "client"]));
+        assert!(schema.missing.is_empty());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(&StringRecord::from(vec!["Client_ID", "transaction", " Type ", "tx_id", "amount", "TS", "note"])),
+            StringRecord::from(vec!["client", "tx", "type", "tx", "amount", "timestamp", "note"])
+        );
+    }
+}
@@ -0,0 +1,995 @@
+use std::{ collections::HashSet, fmt, future::Future, io, sync::Arc };
+
+use axum::{
+    body::Bytes,
+    extract::{ ws::{ Message, WebSocket, WebSocketUpgrade }, Path, Query, State },
+    http::{ header, StatusCode },
+    response::{ IntoResponse, Response },
+    routing::{ get, post },
+    Json,
+    Router,
+};
+use serde::{ Deserialize, Serialize };
+use tokio::{
+    net::{ TcpListener, ToSocketAddrs },
+    sync::{ broadcast::{ self, error::RecvError }, mpsc, oneshot },
+    task,
+};
+
+use crate::{
+    dead_letter::{ DeadLetter, DeadLetterQueue },
+    engine::{ EngineCore, Receipt, TxStatus },
+    error::EngineError,
+    listener,
+    metrics::PrometheusRecorder,
+    policy::RiskTier,
+    reason::ReasonCode,
+    shard::{ self, shard_of },
+    snapshot::Snapshot,
+    throttle::{ RateLimiter, RateLimits, Throttled },
+    types::{ custom_serde, Account, ClientId, Transaction, TransactionType },
+};
+
+// Requests buffered for the engine task before the handlers wait for room
+const LIVE_CAPACITY: usize = 1024;
+// Account updates buffered for every `/ws/accounts` connection, a slower one skips the oldest
+const FEED_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountView {
+    #[serde(flatten)]
+    pub account: Account,
+    pub tier: RiskTier,
+}
+
+// The answer to `POST /transactions`, the reason and error only for a rejected transaction
+#[derive(Serialize)]
+struct ReceiptView {
+    tx: u32,
+    client: ClientId,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<ReasonCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    available_after: rust_decimal::Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    held_after: rust_decimal::Decimal,
+    locked: bool,
+}
+
+impl From<&Receipt> for ReceiptView {
+    fn from(receipt: &Receipt) -> Self {
+        let rejection = receipt.outcome.as_ref().err();
+
+        ReceiptView {
+            tx: receipt.tx_id,
+            client: receipt.client_id,
+            outcome: if rejection.is_some() { "rejected" } else { "applied" },
+            reason: rejection.map(EngineError::reason),
+            error: rejection.map(EngineError::to_string),
+            available_after: receipt.available_after,
+            held_after: receipt.held_after,
+            locked: receipt.locked,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TxStatusView {
+    pub tx: u32,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<ReasonCode>,
+}
+
+impl TxStatusView {
+    fn new(tx: u32, status: TxStatus) -> Self {
+        TxStatusView { tx, status: status.name(), reason: status.reason() }
+    }
+}
+
+// A transaction of `GET /pending`
+#[derive(Serialize)]
+struct PendingView {
+    tx: u32,
+    client: ClientId,
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+    #[serde(serialize_with = "custom_serde::serialize_optional_decimal")]
+    amount: Option<rust_decimal::Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+}
+
+impl From<&Transaction> for PendingView {
+    fn from(tx: &Transaction) -> Self {
+        PendingView {
+            tx: tx.tx_id,
+            client: tx.client_id,
+            tx_type: tx.tx_type.name(),
+            amount: tx.tx_type.amount(),
+            timestamp: tx.timestamp,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FeedFilter {
+    /// Comma separated client ids, every client when missing
+    clients: Option<String>,
+}
+
+// A page of `GET /accounts` sorted by client id, every account when missing
+#[derive(Deserialize)]
+struct Page {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+// The number of accounts of every page, for clients to know when to stop
+const TOTAL_COUNT: &str = "x-total-count";
+
+#[derive(Serialize)]
+struct SnapshotInfo {
+    taken_at: Option<u64>,
+    accounts: usize,
+}
+
+// Query API over a snapshot. There are only GET routes, the snapshot can't be changed through it.
+pub fn snapshot_router(snapshot: Snapshot) -> Router {
+    Router::new()
+        .route("/snapshot", get(snapshot_info))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .with_state(Arc::new(snapshot))
+}
+
+// Every engine is owned by a single task and the handles talk to it through a channel, so the
+// transactions of a client are applied one at a time in the order they are received, like in a
+// batch run.
+enum Request {
+    Apply(Transaction, oneshot::Sender<Receipt>),
+    Accounts(oneshot::Sender<Snapshot>),
+    Account(ClientId, oneshot::Sender<Option<AccountView>>),
+    TxStatus(u32, oneshot::Sender<Option<TxStatus>>),
+    Pending(oneshot::Sender<Vec<Transaction>>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// The engine task stopped, e.g. it panicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineStopped;
+
+impl fmt::Display for EngineStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the engine is not running")
+    }
+}
+
+impl std::error::Error for EngineStopped {}
+
+impl From<EngineStopped> for StatusCode {
+    fn from(_: EngineStopped) -> Self {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// A running engine shared by the APIs that serve it. The engine lives until every clone of the
+/// handle is dropped.
+#[derive(Clone)]
+pub struct EngineHandle {
+    shards: Arc<[mpsc::Sender<Request>]>,
+    updates: broadcast::Sender<AccountView>,
+    limiter: Option<RateLimiter>,
+    metrics: PrometheusRecorder,
+    dead_letters: DeadLetterQueue,
+}
+
+impl EngineHandle {
+    pub fn spawn<E: EngineCore + Send + 'static>(engine: E) -> Self {
+        EngineHandle::spawn_sharded(vec![engine])
+    }
+
+    /// Runs every engine in its own task, each one owning the accounts of the clients
+    /// [`shard_of`] gives it, so the transactions of different clients are applied concurrently.
+    /// With more than one engine transfers and merges are rejected with `NOT_ALLOWED`, see
+    /// [`shard::route`], and transaction ids are only unique per engine.
+    ///
+    /// # Panics
+    ///
+    /// When there's no engine.
+    pub fn spawn_sharded<E: EngineCore + Send + 'static>(engines: Vec<E>) -> Self {
+        assert!(!engines.is_empty(), "a live engine needs at least one shard");
+
+        let (updates, _) = broadcast::channel(FEED_CAPACITY);
+
+        let shards = engines
+            .into_iter()
+            .map(|engine| {
+                let (requests, rx) = mpsc::channel(LIVE_CAPACITY);
+
+                task::spawn(run_engine(engine, rx, updates.clone()));
+
+                requests
+            })
+            .collect();
+
+        EngineHandle {
+            shards,
+            updates,
+            limiter: None,
+            metrics: PrometheusRecorder::new(),
+            dead_letters: DeadLetterQueue::default(),
+        }
+    }
+
+    /// Limits the transactions the APIs take in, before they reach the engine. The throttled ones
+    /// are counted in the metrics of the handle.
+    pub fn limit_rate(mut self, limits: RateLimits) -> Self {
+        self.limiter = limits
+            .is_enabled()
+            .then(|| RateLimiter::new(limits, Box::new(self.metrics.clone())));
+        self
+    }
+
+    /// Sends the records the APIs couldn't parse or the engine rejected to these sinks, with their
+    /// payload as received.
+    pub fn dead_letters(mut self, dead_letters: DeadLetterQueue) -> Self {
+        self.dead_letters = dead_letters;
+        self
+    }
+
+    pub fn dead_letter(&self, letter: DeadLetter) {
+        self.dead_letters.send(letter);
+    }
+
+    /// Takes a share of the rate limits for a transaction of the client, or tells how long to wait
+    /// before trying again
+    pub fn admit(&self, client_id: ClientId) -> Result<(), Throttled> {
+        self.limiter.as_ref().map_or(Ok(()), |limiter| limiter.check(client_id))
+    }
+
+    /// Waits until a transaction of the client is within the rate limits
+    pub async fn throttle(&self, client_id: ClientId) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(client_id).await;
+        }
+    }
+
+    /// The metrics of the APIs, served at `GET /metrics`
+    pub fn metrics(&self) -> &PrometheusRecorder {
+        &self.metrics
+    }
+
+    /// The accounts changed by the transactions applied from now on, as they change. A receiver
+    /// falling more than 1024 updates behind skips the oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<AccountView> {
+        self.updates.subscribe()
+    }
+
+    /// Applies a transaction, the receipt tells whether it was applied and the balances of its
+    /// client afterwards
+    pub async fn submit(&self, tx: Transaction) -> Result<Receipt, EngineStopped> {
+        match shard::route(&tx, self.shards.len()) {
+            Ok(shard) => self.ask(shard, |reply| Request::Apply(tx, reply)).await,
+            Err(err) => {
+                let account = self.query_account(tx.client_id).await?
+                    .map_or_else(|| Account::new(tx.client_id), |view| view.account);
+
+                Ok(Receipt::new(tx.tx_id, Err(err), &account))
+            }
+        }
+    }
+
+    /// Waits until the transactions submitted before through any clone of the handle are applied
+    pub async fn flush(&self) -> Result<(), EngineStopped> {
+        for shard in 0..self.shards.len() {
+            self.ask(shard, Request::Flush).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn accounts(&self) -> Result<Snapshot, EngineStopped> {
+        let mut snapshots = Vec::with_capacity(self.shards.len());
+
+        for shard in 0..self.shards.len() {
+            snapshots.push(self.ask(shard, Request::Accounts).await?);
+        }
+
+        Ok(shard::merged(snapshots))
+    }
+
+    pub async fn query_account(&self, client_id: ClientId) -> Result<Option<AccountView>, EngineStopped> {
+        self.ask(shard_of(client_id, self.shards.len()), |reply| Request::Account(client_id, reply)).await
+    }
+
+    pub async fn tx_status(&self, tx_id: u32) -> Result<Option<TxStatus>, EngineStopped> {
+        let mut statuses = Vec::with_capacity(self.shards.len());
+
+        for shard in 0..self.shards.len() {
+            statuses.extend(self.ask(shard, |reply| Request::TxStatus(tx_id, reply)).await?);
+        }
+
+        Ok(shard::merged_status(statuses))
+    }
+
+    /// The transactions quarantined for a review on every engine, by id
+    pub async fn pending(&self) -> Result<Vec<Transaction>, EngineStopped> {
+        let mut pending = Vec::new();
+
+        for shard in 0..self.shards.len() {
+            pending.extend(self.ask(shard, Request::Pending).await?);
+        }
+
+        pending.sort_unstable_by_key(|tx| tx.tx_id);
+
+        Ok(pending)
+    }
+
+    /// Approves or declines a quarantined transaction like an `approve` or `decline` of its
+    /// client, `None` when it isn't pending
+    pub async fn review(&self, tx_id: u32, approved: bool) -> Result<Option<Receipt>, EngineStopped> {
+        let Some(pending) = self.pending().await?.into_iter().find(|tx| tx.tx_id == tx_id) else {
+            return Ok(None);
+        };
+
+        let tx_type = if approved { TransactionType::Approve } else { TransactionType::Decline };
+
+        self.submit(Transaction::new(pending.client_id, tx_id, tx_type)).await.map(Some)
+    }
+
+    async fn ask<T>(
+        &self,
+        shard: usize,
+        request: impl FnOnce(oneshot::Sender<T>) -> Request
+    ) -> Result<T, EngineStopped> {
+        let (reply, response) = oneshot::channel();
+
+        self.shards[shard].send(request(reply)).await.map_err(|_| EngineStopped)?;
+
+        response.await.map_err(|_| EngineStopped)
+    }
+}
+
+/// API over a running engine, `POST /transactions` applies a transaction (the same JSON as the
+/// JSON Lines input) and the accounts are queried like over a snapshot. `GET /transactions/{id}`
+/// tells where a transaction stands and the `/ws/accounts` WebSocket pushes the accounts as they
+/// change. A transaction over the rate limits is answered with 429 and a `Retry-After`, `GET
+/// /metrics` counts them. `GET /pending` lists the quarantined transactions, `POST
+/// /pending/{tx}/approve` and `POST /pending/{tx}/reject` release them.
+pub fn live_router(engine: EngineHandle) -> Router {
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .route("/transactions", post(post_transaction))
+        .route("/transactions/{id}", get(get_tx_status))
+        .route("/accounts", get(list_live_accounts))
+        .route("/accounts/{client}", get(get_live_account))
+        .route("/ws/accounts", get(account_feed))
+        .route("/pending", get(list_pending))
+        .route("/pending/{id}/approve", post(approve_pending))
+        .route("/pending/{id}/reject", post(reject_pending))
+        .with_state(engine)
+}
+
+async fn run_engine<E: EngineCore>(
+    mut engine: E,
+    mut rx: mpsc::Receiver<Request>,
+    updates: broadcast::Sender<AccountView>
+) {
+    let view = |engine: &E, client_id: ClientId| {
+        engine.account(client_id).map(|account| AccountView { account, tier: engine.risk_tier(client_id) })
+    };
+
+    // A dropped reply means the client went away, there's nobody to tell
+    while let Some(request) = rx.recv().await {
+        match request {
+            Request::Apply(tx, reply) => {
+                // Only kept to tell what changed when somebody listens
+                let watched = (updates.receiver_count() > 0).then(|| tx.clone());
+                let receipt = engine.submit(tx);
+
+                if let Some(tx) = watched.filter(|_| receipt.outcome.is_ok()) {
+                    for changed in engine.changed_clients(&tx) {
+                        if let Some(account) = view(&engine, changed) {
+                            let _ = updates.send(account);
+                        }
+                    }
+                }
+
+                let _ = reply.send(receipt);
+            }
+            Request::Accounts(reply) => {
+                let _ = reply.send(engine.snapshot());
+            }
+            Request::Account(client_id, reply) => {
+                let _ = reply.send(view(&engine, client_id));
+            }
+            Request::TxStatus(tx_id, reply) => {
+                let _ = reply.send(engine.tx_status(tx_id));
+            }
+            Request::Pending(reply) => {
+                let _ = reply.send(engine.pending());
+            }
+            Request::Flush(reply) => {
+                let _ = reply.send(());
+            }
+        }
+    }
+}
+
+// The body is parsed here rather than by the extractor, so a body that can't be parsed still
+// reaches the dead letters as it was sent
+async fn post_transaction(
+    State(engine): State<EngineHandle>,
+    body: Bytes
+) -> Result<Json<ReceiptView>, Response> {
+    let Json(tx) = Json::<Transaction>::from_bytes(&body).map_err(|rejection| {
+        let value = serde_json::from_slice::<serde_json::Value>(&body).ok();
+        let tx_type = value.as_ref().and_then(|value| value.get("type")?.as_str());
+        let (reason, error) = listener::unparsed(tx_type, rejection.body_text());
+
+        engine.dead_letter(DeadLetter::new("http", reason, error, &body));
+
+        rejection.into_response()
+    })?;
+
+    engine.admit(tx.client_id).map_err(|throttled| {
+        // Whole seconds, rounded up so a retry doesn't come too early
+        let retry_after = throttled.retry_after.as_secs() + u64::from(throttled.retry_after.subsec_nanos() > 0);
+
+        (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after.to_string())], throttled.to_string())
+            .into_response()
+    })?;
+
+    let receipt = engine.submit(tx).await.map_err(|err| StatusCode::from(err).into_response())?;
+
+    match &receipt.outcome {
+        Ok(()) => Ok(Json(ReceiptView::from(&receipt))),
+        Err(err) => {
+            engine.dead_letter(DeadLetter::new("http", err.reason(), err, &body));
+
+            Err((StatusCode::UNPROCESSABLE_ENTITY, Json(ReceiptView::from(&receipt))).into_response())
+        }
+    }
+}
+
+async fn get_metrics(State(engine): State<EngineHandle>) -> String {
+    engine.metrics().render()
+}
+
+async fn list_live_accounts(
+    State(engine): State<EngineHandle>,
+    page: Query<Page>
+) -> Result<Response, StatusCode> {
+    let snapshot = Arc::new(engine.accounts().await?);
+
+    Ok(list_accounts(State(snapshot), page).await)
+}
+
+async fn get_live_account(
+    State(engine): State<EngineHandle>,
+    Path(client): Path<ClientId>
+) -> Result<Json<AccountView>, StatusCode> {
+    engine.query_account(client).await?.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_tx_status(
+    State(engine): State<EngineHandle>,
+    Path(id): Path<u32>
+) -> Result<Json<TxStatusView>, StatusCode> {
+    let status = engine.tx_status(id).await?.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(TxStatusView::new(id, status)))
+}
+
+async fn list_pending(State(engine): State<EngineHandle>) -> Result<Json<Vec<PendingView>>, StatusCode> {
+    let pending = engine.pending().await?;
+
+    Ok(Json(pending.iter().map(PendingView::from).collect()))
+}
+
+async fn approve_pending(State(engine): State<EngineHandle>, Path(id): Path<u32>) -> Response {
+    review_pending(engine, id, true).await
+}
+
+async fn reject_pending(State(engine): State<EngineHandle>, Path(id): Path<u32>) -> Response {
+    review_pending(engine, id, false).await
+}
+
+// Answered like `POST /transactions` with the receipt of the approval or decline, 404 when the
+// transaction isn't pending
+async fn review_pending(engine: EngineHandle, id: u32, approved: bool) -> Response {
+    match engine.review(id, approved).await {
+        Ok(Some(receipt)) if receipt.outcome.is_ok() => Json(ReceiptView::from(&receipt)).into_response(),
+        Ok(Some(receipt)) => (StatusCode::UNPROCESSABLE_ENTITY, Json(ReceiptView::from(&receipt))).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => StatusCode::from(err).into_response(),
+    }
+}
+
+async fn account_feed(
+    State(engine): State<EngineHandle>,
+    Query(filter): Query<FeedFilter>,
+    upgrade: WebSocketUpgrade
+) -> Result<Response, StatusCode> {
+    let clients = match filter.clients {
+        Some(clients) => {
+            let clients = clients
+                .split(',')
+                .map(|client| client.trim().parse())
+                .collect::<Result<HashSet<ClientId>, _>>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+            Some(clients)
+        }
+        None => None,
+    };
+
+    let updates = engine.subscribe();
+
+    Ok(upgrade.on_upgrade(move |socket| push_accounts(socket, updates, clients)))
+}
+
+// Sends every update of the watched clients as a JSON text message until the dashboard goes away.
+// Updates it's too slow for are skipped, the next one of the client has its latest balances.
+async fn push_accounts(
+    mut socket: WebSocket,
+    mut updates: broadcast::Receiver<AccountView>,
+    clients: Option<HashSet<ClientId>>
+) {
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                let account = match update {
+                    Ok(account) => account,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Account feed skipped {} updates", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                if clients.as_ref().is_some_and(|clients| !clients.contains(&account.account.client_id)) {
+                    continue;
+                }
+
+                let Ok(text) = serde_json::to_string(&account) else {
+                    continue;
+                };
+
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    return;
+                }
+            }
+            // The dashboard only listens, anything it sends is ignored until it closes
+            message = socket.recv() => {
+                match message {
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+/// Serves the router until `shutdown` completes, then lets the requests in flight finish.
+pub async fn serve<A: ToSocketAddrs>(
+    address: A,
+    router: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+
+    log::info!("Listening on {}", listener.local_addr()?);
+
+    axum::serve(listener, router).with_graceful_shutdown(shutdown).await
+}
+
+async fn snapshot_info(State(snapshot): State<Arc<Snapshot>>) -> Json<SnapshotInfo> {
+    Json(SnapshotInfo { taken_at: snapshot.taken_at, accounts: snapshot.accounts.len() })
+}
+
+async fn list_accounts(State(snapshot): State<Arc<Snapshot>>, Query(page): Query<Page>) -> Response {
+    let accounts: Vec<AccountView> = snapshot.accounts
+        .iter()
+        .skip(page.offset)
+        .take(page.limit.unwrap_or(usize::MAX))
+        .map(|account| AccountView {
+            account: account.clone(),
+            tier: snapshot.risk_tier(account.client_id),
+        })
+        .collect();
+
+    ([(TOTAL_COUNT, snapshot.accounts.len().to_string())], Json(accounts)).into_response()
+}
+
+async fn get_account(
+    State(snapshot): State<Arc<Snapshot>>,
+    Path(client): Path<ClientId>
+) -> Result<Json<AccountView>, StatusCode> {
+    let account = snapshot.account(client).ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(
+        Json(AccountView {
+            account: account.clone(),
+            tier: snapshot.risk_tier(client),
+        })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::IntoFuture;
+
+    use axum::{ body::{ self, Body }, http::{ Method, Request } };
+    use rust_decimal_macros::dec;
+    use tokio::io::{ AsyncRead, AsyncWrite };
+    use tokio_stream::StreamExt;
+    use tokio_tungstenite::{ connect_async, WebSocketStream };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{ dead_letter::tests::Recorder, engine::Engine, policy::QuarantineAbove };
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            taken_at: Some(100),
+            accounts: vec![
+                Account { available: dec!(1.5), total: dec!(1.5), ..Account::new(1) },
+                Account::new(2)
+            ],
+            tiers: [(2, RiskTier::High)].into_iter().collect(),
+            scheduled: vec![],
+            deleted: vec![],
+        }
+    }
+
+    async fn request(method: Method, uri: &str) -> (StatusCode, String) {
+        let request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        let response = snapshot_router(snapshot()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_query_accounts() {
+        assert_eq!(
+            request(Method::GET, "/accounts/2").await,
+            (
+                StatusCode::OK,
+                r#"{"client":2,"available":"0","held":"0","total":"0","locked":false,"tier":"high"}"#.to_string(),
+            )
+        );
+        assert_eq!(request(Method::GET, "/accounts/3").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            request(Method::GET, "/snapshot").await.1,
+            r#"{"taken_at":100,"accounts":2}"#
+        );
+
+        let (status, body) = request(Method::GET, "/accounts").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.starts_with(r#"[{"client":1,"available":"1.5","#));
+    }
+
+    #[tokio::test]
+    async fn test_paginate_accounts() {
+        let page = Request::builder().uri("/accounts?offset=1&limit=5").body(Body::empty()).unwrap();
+        let response = snapshot_router(snapshot()).oneshot(page).await.unwrap();
+
+        assert_eq!(response.headers()[TOTAL_COUNT], "2");
+
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8(body.to_vec()).unwrap(),
+            r#"[{"client":2,"available":"0","held":"0","total":"0","locked":false,"tier":"high"}]"#
+        );
+
+        assert_eq!(request(Method::GET, "/accounts?offset=2").await.1, "[]");
+        assert_eq!(request(Method::GET, "/accounts?limit=0").await.1, "[]");
+        assert_eq!(request(Method::GET, "/accounts?limit=x").await.0, StatusCode::BAD_REQUEST);
+    }
+
+    async fn send(router: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_live_engine() {
+        let router = live_router(EngineHandle::spawn(Engine::new()));
+
+        assert_eq!(
+            send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":1,"tx":1,"amount":"2.5"}"#).await,
+            (
+                StatusCode::OK,
+                r#"{"tx":1,"client":1,"outcome":"applied","available_after":"2.5","held_after":"0","locked":false}"#.to_string(),
+            )
+        );
+        assert_eq!(
+            send(&router, Method::POST, "/transactions", r#"{"type":"withdrawal","client":1,"tx":2,"amount":3}"#).await,
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                concat!(
+                    r#"{"tx":2,"client":1,"outcome":"rejected","reason":"INSUFFICIENT_FUNDS","#,
+                    r#""error":"client 1 has 2.5 available but 3 is required","#,
+                    r#""available_after":"2.5","held_after":"0","locked":false}"#
+                ).to_string(),
+            )
+        );
+        assert_eq!(
+            send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":1}"#).await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":2,"tx":3,"amount":1}"#).await;
+
+        let (status, body) = send(&router, Method::GET, "/accounts", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.starts_with(r#"[{"client":1,"available":"2.5","#));
+        assert!(body.contains(r#"{"client":2,"available":"1","#));
+
+        assert_eq!(send(&router, Method::GET, "/accounts/2", "").await.0, StatusCode::OK);
+        assert_eq!(send(&router, Method::GET, "/accounts/3", "").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters() {
+        let recorder = Recorder::default();
+        let engine = EngineHandle::spawn(Engine::new())
+            .dead_letters(DeadLetterQueue::new(vec![Box::new(recorder.clone())]));
+        let router = live_router(engine);
+
+        send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":1,"tx":1,"amount":1}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"withdrawal","client":1,"tx":2,"amount":3}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"refund","client":1,"tx":3}"#).await;
+        assert_eq!(send(&router, Method::POST, "/transactions", "deposit,1,4,1").await.0, StatusCode::BAD_REQUEST);
+
+        let letters = recorder.letters.lock().unwrap();
+        let letters: Vec<_> = letters
+            .iter()
+            .map(|letter| (letter.source.as_str(), letter.reason, letter.payload.as_str()))
+            .collect();
+
+        assert_eq!(letters, vec![
+            ("http", ReasonCode::InsufficientFunds, r#"{"type":"withdrawal","client":1,"tx":2,"amount":3}"#),
+            ("http", ReasonCode::UnknownType, r#"{"type":"refund","client":1,"tx":3}"#),
+            ("http", ReasonCode::Malformed, "deposit,1,4,1"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_tx_status() {
+        let router = live_router(EngineHandle::spawn(Engine::new()));
+
+        send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":1,"tx":1,"amount":2}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"withdrawal","client":1,"tx":2,"amount":3}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"dispute","client":1,"tx":1}"#).await;
+
+        assert_eq!(
+            send(&router, Method::GET, "/transactions/1", "").await,
+            (StatusCode::OK, r#"{"tx":1,"status":"under_dispute"}"#.to_string())
+        );
+        assert_eq!(
+            send(&router, Method::GET, "/transactions/2", "").await,
+            (StatusCode::OK, r#"{"tx":2,"status":"rejected","reason":"INSUFFICIENT_FUNDS"}"#.to_string())
+        );
+        assert_eq!(send(&router, Method::GET, "/transactions/3", "").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_pending() {
+        let engine = Engine::builder().policy(Box::new(QuarantineAbove(dec!(100)))).build();
+        let router = live_router(EngineHandle::spawn(engine));
+
+        send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":1,"tx":1,"amount":50}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":1,"tx":2,"amount":500}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"withdrawal","client":2,"tx":3,"amount":200}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":2,"tx":4,"amount":300}"#).await;
+
+        assert_eq!(
+            send(&router, Method::GET, "/pending", "").await,
+            (
+                StatusCode::OK,
+                concat!(
+                    r#"[{"tx":2,"client":1,"type":"deposit","amount":"500"},"#,
+                    r#"{"tx":4,"client":2,"type":"deposit","amount":"300"}]"#
+                ).to_string(),
+            )
+        );
+
+        assert_eq!(
+            send(&router, Method::POST, "/pending/2/approve", "").await,
+            (
+                StatusCode::OK,
+                r#"{"tx":2,"client":1,"outcome":"applied","available_after":"550","held_after":"0","locked":false}"#.to_string(),
+            )
+        );
+        assert_eq!(
+            send(&router, Method::POST, "/pending/4/reject", "").await,
+            (
+                StatusCode::OK,
+                r#"{"tx":4,"client":2,"outcome":"applied","available_after":"0","held_after":"0","locked":false}"#.to_string(),
+            )
+        );
+        assert_eq!(send(&router, Method::POST, "/pending/4/approve", "").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(send(&router, Method::POST, "/pending/9/reject", "").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(send(&router, Method::GET, "/pending", "").await, (StatusCode::OK, "[]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_pending_approval_rejected() {
+        let engine = Engine::builder().policy(Box::new(QuarantineAbove(dec!(100)))).build();
+        let router = live_router(EngineHandle::spawn(engine));
+
+        send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":1,"tx":1,"amount":50}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"deposit","client":1,"tx":2,"amount":500}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"dispute","client":1,"tx":1}"#).await;
+        send(&router, Method::POST, "/transactions", r#"{"type":"chargeback","client":1,"tx":1}"#).await;
+
+        let (status, body) = send(&router, Method::POST, "/pending/2/approve", "").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.contains(r#""reason":"ACCOUNT_LOCKED""#));
+
+        // Still pending, it can be rejected
+        assert_eq!(send(&router, Method::POST, "/pending/2/reject", "").await.0, StatusCode::OK);
+        assert_eq!(send(&router, Method::GET, "/pending", "").await.1, "[]");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits() {
+        let limits = RateLimits { global: None, per_client: Some(1) };
+        let router = live_router(EngineHandle::spawn(Engine::new()).limit_rate(limits));
+        let deposit = |client, tx| format!(r#"{{"type":"deposit","client":{},"tx":{},"amount":1}}"#, client, tx);
+
+        assert_eq!(send(&router, Method::POST, "/transactions", &deposit(1, 1)).await.0, StatusCode::OK);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/transactions")
+            .header("content-type", "application/json")
+            .body(Body::from(deposit(1, 2)))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        assert_eq!(send(&router, Method::POST, "/transactions", &deposit(2, 3)).await.0, StatusCode::OK);
+        assert_eq!(send(&router, Method::GET, "/transactions/2", "").await.0, StatusCode::NOT_FOUND);
+
+        let (status, metrics) = send(&router, Method::GET, "/metrics", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(metrics.contains("ingest_throttled_total{scope=\"client\"} 1"));
+    }
+
+    // Only takes deposits, to check the handle doesn't depend on the engine implementation
+    struct DepositsOnly(Vec<Account>);
+
+    impl EngineCore for DepositsOnly {
+        fn apply(&mut self, tx: Transaction) -> Result<(), EngineError> {
+            match tx.tx_type {
+                TransactionType::Deposit(amount) => {
+                    self.0.push(Account { available: amount, total: amount, ..Account::new(tx.client_id) });
+                    Ok(())
+                }
+                _ => Err(EngineError::NotAllowed { tx_id: tx.tx_id, tx_type: tx.tx_type.name() }),
+            }
+        }
+
+        fn account(&self, client_id: ClientId) -> Option<Account> {
+            self.0.iter().find(|account| account.client_id == client_id).cloned()
+        }
+
+        fn accounts_iter(&self) -> Box<dyn Iterator<Item = Account> + '_> {
+            Box::new(self.0.iter().cloned())
+        }
+
+        fn snapshot(&self) -> Snapshot {
+            Snapshot { accounts: self.0.clone(), ..Default::default() }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_engine() {
+        let engine = EngineHandle::spawn(DepositsOnly(vec![]));
+
+        let receipt = engine.submit(Transaction::new(1, 1, TransactionType::Deposit(dec!(2)))).await.unwrap();
+        assert_eq!((receipt.outcome, receipt.available_after), (Ok(()), dec!(2)));
+        assert_eq!(engine.query_account(1).await.unwrap().unwrap().tier, RiskTier::Standard);
+
+        let receipt = engine.submit(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(1)))).await.unwrap();
+        assert_eq!(receipt.outcome, Err(EngineError::NotAllowed { tx_id: 2, tx_type: "withdrawal" }));
+        assert_eq!(receipt.available_after, dec!(2));
+
+        // A client without an account has nothing available
+        let receipt = engine.submit(Transaction::new(3, 3, TransactionType::Dispute)).await.unwrap();
+        assert_eq!((receipt.client_id, receipt.available_after, receipt.locked), (3, dec!(0), false));
+
+        engine.flush().await.unwrap();
+        assert_eq!(engine.accounts().await.unwrap().accounts.len(), 1);
+        assert_eq!(engine.query_account(1).await.unwrap().unwrap().account.available, dec!(2));
+    }
+
+    #[tokio::test]
+    async fn test_sharded_engine() {
+        let engine = EngineHandle::spawn_sharded(vec![Engine::new(), Engine::new()]);
+
+        engine.submit(Transaction::new(1, 1, TransactionType::Deposit(dec!(2)))).await.unwrap().outcome.unwrap();
+        engine.submit(Transaction::new(2, 2, TransactionType::Deposit(dec!(3)))).await.unwrap().outcome.unwrap();
+        engine.submit(Transaction::new(1, 3, TransactionType::Withdrawal(dec!(5)))).await.unwrap().outcome.unwrap_err();
+
+        let receipt = engine
+            .submit(Transaction::new(1, 4, TransactionType::Transfer { to: 2, amount: dec!(1) })).await
+            .unwrap();
+        assert_eq!(receipt.outcome, Err(EngineError::NotAllowed { tx_id: 4, tx_type: "transfer" }));
+        assert_eq!(receipt.available_after, dec!(2));
+        assert_eq!(engine.query_account(2).await.unwrap().unwrap().account.available, dec!(3));
+        assert_eq!(
+            engine.accounts().await.unwrap().accounts.iter().map(|account| account.client_id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(engine.tx_status(2).await.unwrap(), Some(TxStatus::Applied));
+        assert!(engine.tx_status(3).await.unwrap().unwrap().reason().is_some());
+        assert_eq!(engine.tx_status(5).await.unwrap(), None);
+    }
+
+    async fn receive<S: AsyncRead + AsyncWrite + Unpin>(socket: &mut WebSocketStream<S>, count: usize) -> Vec<String> {
+        let mut messages = vec![];
+
+        while messages.len() < count {
+            messages.push(socket.next().await.unwrap().unwrap().into_text().unwrap().to_string());
+        }
+
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_account_feed() {
+        let engine = EngineHandle::spawn(Engine::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(axum::serve(listener, live_router(engine.clone())).into_future());
+
+        let (mut every, _) = connect_async(format!("ws://{}/ws/accounts", address)).await.unwrap();
+        let (mut second, _) = connect_async(format!("ws://{}/ws/accounts?clients=2,3", address)).await.unwrap();
+        assert!(connect_async(format!("ws://{}/ws/accounts?clients=x", address)).await.is_err());
+
+        engine.submit(Transaction::new(1, 1, TransactionType::Deposit(dec!(5)))).await.unwrap();
+        engine.submit(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(9)))).await.unwrap();
+        engine.submit(Transaction::new(1, 3, TransactionType::Transfer { to: 2, amount: dec!(2) })).await.unwrap();
+
+        assert_eq!(receive(&mut every, 3).await, vec![
+            r#"{"client":1,"available":"5","held":"0","total":"5","locked":false,"tier":"standard"}"#,
+            r#"{"client":1,"available":"3","held":"0","total":"3","locked":false,"tier":"standard"}"#,
+            r#"{"client":2,"available":"2","held":"0","total":"2","locked":false,"tier":"standard"}"#
+        ]);
+
+        assert_eq!(receive(&mut second, 1).await, vec![
+            r#"{"client":2,"available":"2","held":"0","total":"2","locked":false,"tier":"standard"}"#
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        assert_eq!(
+            request(Method::POST, "/accounts/1").await.0,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(request(Method::DELETE, "/accounts").await.0, StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
@@ -0,0 +1,383 @@
+use std::{ ffi::OsString, path::Path, time::Duration };
+
+use clap::{ CommandFactory, Parser };
+use rust_decimal::Decimal;
+
+use transaction_engine::{
+    balance_bounds::BalanceBounds,
+    config::Config,
+    credit::CreditLimits,
+    engine::{ Engine, EngineBuilder, SECONDS_PER_DAY },
+    error::PipelineError,
+    fees::FeeSchedule,
+    policy::{ AccountLimit, AccountLimits, QuarantineAbove, RiskTier, TierPolicy },
+    risk::{ RiskScoring, RiskThreshold },
+    scheduler::Operation,
+    types::custom_serde::Rounding,
+};
+
+use crate::cli::Cli;
+
+/// The flags a `--shadow-config` file may set, the ones configuring the engine itself rather than
+/// the inputs, outputs or reports of the run.
+pub const ENGINE_SETTINGS: [&str; 30] = [
+    "allow_negative_balance",
+    "allow_locked_deposits",
+    "idempotent",
+    "allow_adjustments",
+    "reject_out_of_sequence",
+    "reject_late_timestamps",
+    "allow_unlocks",
+    "unlock_on_representment",
+    "allow_deletes",
+    "hold_expiry_days",
+    "max_amount_scale",
+    "deposit_hold_days",
+    "dispute_expiry_days",
+    "dispute_window_days",
+    "escalate_duplicate_disputes",
+    "allow_redisputes",
+    "daily_interest",
+    "sweep_above",
+    "quarantine_above",
+    "risk_tiers",
+    "tier_limit",
+    "account_limit",
+    "balance_bounds",
+    "fees",
+    "limits",
+    "risk_threshold",
+    "risk_lock",
+    "risk_window_minutes",
+    "precision",
+    "rounding",
+];
+
+/// How the engines of a run are built, from its flags. The shadow mode builds its candidate engine
+/// from the flags of the run with the settings of `--shadow-config` in place of the same ones.
+pub struct EngineSettings {
+    event_sourcing: bool,
+    allow_negative_balance: bool,
+    allow_locked_deposits: bool,
+    idempotent: bool,
+    allow_adjustments: bool,
+    reject_out_of_sequence: bool,
+    reject_late_timestamps: bool,
+    check_invariants: bool,
+    allow_unlocks: bool,
+    unlock_on_representment: bool,
+    allow_deletes: bool,
+    hold_expiry: Duration,
+    max_amount_scale: u32,
+    deposit_hold: Option<Duration>,
+    dispute_expiry: Option<Duration>,
+    dispute_window: Option<Duration>,
+    duplicate_dispute_limit: Option<u32>,
+    redispute_limit: Option<u32>,
+    daily_interest: Option<Decimal>,
+    sweep_above: Option<Decimal>,
+    slow_apply: Option<Duration>,
+    quarantine_above: Option<Decimal>,
+    tier_policy: bool,
+    tier_limits: Vec<(RiskTier, Decimal)>,
+    account_limits: Vec<AccountLimit>,
+    pub balance_bounds: Option<BalanceBounds>,
+    pub fees: Option<FeeSchedule>,
+    pub credit_limits: Option<CreditLimits>,
+    route_overflow: bool,
+    risk_scoring: bool,
+    risk_thresholds: Vec<RiskThreshold>,
+    risk_window: Duration,
+    risk_lock: bool,
+    rounding: Rounding,
+}
+
+impl EngineSettings {
+    pub fn new(cli: &Cli) -> Result<Self, PipelineError> {
+        let days = |days: u64| Duration::from_secs(days * SECONDS_PER_DAY);
+
+        let balance_bounds = cli.balance_bounds
+            .as_ref()
+            .map(BalanceBounds::load)
+            .transpose()
+            .map_err(|err| PipelineError::input("Could not load the balance bounds", err))?;
+        let fees = cli.fees
+            .as_ref()
+            .map(FeeSchedule::load)
+            .transpose()
+            .map_err(|err| PipelineError::input("Could not load the fees", err))?;
+        let credit_limits = cli.limits
+            .as_ref()
+            .map(CreditLimits::load)
+            .transpose()
+            .map_err(|err| PipelineError::input("Could not load the credit limits", err))?;
+
+        Ok(EngineSettings {
+            event_sourcing: cli.event_sourcing,
+            allow_negative_balance: cli.allow_negative_balance,
+            allow_locked_deposits: cli.allow_locked_deposits,
+            idempotent: cli.idempotent,
+            allow_adjustments: cli.allow_adjustments,
+            reject_out_of_sequence: cli.reject_out_of_sequence,
+            reject_late_timestamps: cli.reject_late_timestamps,
+            check_invariants: cli.check_invariants,
+            allow_unlocks: cli.allow_unlocks,
+            unlock_on_representment: cli.unlock_on_representment,
+            allow_deletes: cli.allow_deletes,
+            hold_expiry: days(cli.hold_expiry_days),
+            max_amount_scale: cli.max_amount_scale,
+            deposit_hold: cli.deposit_hold_days.map(days),
+            dispute_expiry: cli.dispute_expiry_days.map(days),
+            dispute_window: cli.dispute_window_days.map(days),
+            duplicate_dispute_limit: cli.escalate_duplicate_disputes,
+            redispute_limit: cli.allow_redisputes,
+            daily_interest: cli.daily_interest,
+            sweep_above: cli.sweep_above,
+            slow_apply: cli.slow_apply_micros.map(Duration::from_micros),
+            quarantine_above: cli.quarantine_above,
+            tier_policy: cli.risk_tiers || !cli.tier_limit.is_empty(),
+            tier_limits: cli.tier_limit.clone(),
+            account_limits: cli.account_limit.clone(),
+            balance_bounds,
+            fees,
+            credit_limits,
+            route_overflow: cli.overflow_report.is_some(),
+            risk_scoring: cli.risk_report.is_some() || !cli.risk_threshold.is_empty(),
+            risk_thresholds: cli.risk_threshold.clone(),
+            risk_window: Duration::from_secs(cli.risk_window_minutes * 60),
+            risk_lock: cli.risk_lock,
+            rounding: Rounding { precision: cli.precision, mode: cli.rounding },
+        })
+    }
+
+    /// The settings of the candidate engine of `--shadow-config`: the flags of the run, with the
+    /// settings of the file in place of the same flags.
+    pub fn candidate(args: Vec<OsString>, path: &Path) -> Result<Self, PipelineError> {
+        let config = Config::load(path)
+            .map_err(|err| PipelineError::input("Could not load the shadow config file", err))?;
+
+        if let Some(key) = config.keys().find(|key| !ENGINE_SETTINGS.contains(key)) {
+            return Err(PipelineError::input(
+                "Invalid shadow config file",
+                format!("{} isn't an engine setting", key)
+            ));
+        }
+
+        let mut args = without_flags(args, &config.keys().collect::<Vec<_>>()).into_iter();
+        let program = args.next().unwrap_or_default();
+        let settings = config.args(|_| false).into_iter().map(OsString::from);
+
+        let cli = Cli::try_parse_from(std::iter::once(program).chain(settings).chain(args))
+            .map_err(|err| PipelineError::input("Invalid shadow config file", err.to_string()))?;
+
+        EngineSettings::new(&cli)
+    }
+
+    pub fn builder(&self) -> EngineBuilder {
+        let mut builder = Engine::builder();
+
+        if self.event_sourcing {
+            builder = builder.event_sourcing();
+        }
+
+        if self.allow_negative_balance {
+            builder = builder.allow_negative_balances();
+        }
+
+        if self.allow_locked_deposits {
+            builder = builder.allow_locked_deposits();
+        }
+
+        if self.idempotent {
+            builder = builder.idempotent();
+        }
+
+        if self.allow_adjustments {
+            builder = builder.allow_adjustments();
+        }
+
+        if self.reject_out_of_sequence {
+            builder = builder.reject_out_of_sequence();
+        }
+
+        if self.reject_late_timestamps {
+            builder = builder.reject_late_timestamps();
+        }
+
+        if self.check_invariants {
+            builder = builder.check_invariants();
+        }
+
+        if self.allow_unlocks {
+            builder = builder.allow_unlocks();
+        }
+
+        if self.unlock_on_representment {
+            builder = builder.unlock_on_representment();
+        }
+
+        if self.allow_deletes {
+            builder = builder.allow_deletes();
+        }
+
+        builder = builder
+            .hold_expiry(self.hold_expiry)
+            .max_amount_scale(self.max_amount_scale)
+            .rounding(self.rounding);
+
+        if let Some(hold) = self.deposit_hold {
+            builder = builder.deposit_hold(hold);
+        }
+
+        if let Some(expiry) = self.dispute_expiry {
+            builder = builder.dispute_expiry(expiry);
+        }
+
+        if let Some(window) = self.dispute_window {
+            builder = builder.dispute_window(window);
+        }
+
+        if let Some(limit) = self.duplicate_dispute_limit {
+            builder = builder.escalate_duplicate_disputes(limit);
+        }
+
+        if let Some(limit) = self.redispute_limit {
+            builder = builder.allow_redisputes(limit);
+        }
+
+        if let Some(rate) = self.daily_interest {
+            builder = builder.daily_operation(Operation::Interest(rate));
+        }
+
+        if let Some(threshold) = self.sweep_above {
+            builder = builder.daily_operation(Operation::Sweep(threshold));
+        }
+
+        if let Some(threshold) = self.slow_apply {
+            builder = builder.slow_apply_threshold(threshold);
+        }
+
+        if let Some(amount) = self.quarantine_above {
+            builder = builder.policy(Box::new(QuarantineAbove(amount)));
+        }
+
+        if self.tier_policy {
+            builder = builder.policy(Box::new(TierPolicy::new(self.tier_limits.clone())));
+        }
+
+        if !self.account_limits.is_empty() {
+            builder = builder.policy(Box::new(AccountLimits::new(self.account_limits.clone())));
+        }
+
+        if let Some(bounds) = &self.balance_bounds {
+            builder = builder.balance_bounds(bounds.clone());
+        }
+
+        if self.route_overflow {
+            builder = builder.route_overflow();
+        }
+
+        if let Some(fees) = &self.fees {
+            builder = builder.fees(fees.clone());
+        }
+
+        if let Some(limits) = &self.credit_limits {
+            builder = builder.credit_limits(limits.clone());
+        }
+
+        if self.risk_scoring {
+            builder = builder.risk_scoring(RiskScoring::new(self.risk_window, self.risk_thresholds.clone()));
+        }
+
+        if self.risk_lock {
+            builder = builder.lock_risky_accounts();
+        }
+
+        builder
+    }
+}
+
+// Drops the flags with these keys from the arguments, with their values
+fn without_flags(args: Vec<OsString>, keys: &[&str]) -> Vec<OsString> {
+    let command = Cli::command();
+    let flags: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| arg.get_long().is_some_and(|long| keys.contains(&long.replace('-', "_").as_str())))
+        .collect();
+
+    let mut kept = vec![];
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy().into_owned();
+
+        // Only positional arguments follow
+        if text == "--" {
+            kept.push(arg);
+            kept.extend(args);
+            break;
+        }
+
+        let (name, inline_value) = match text.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (text.as_str(), false),
+        };
+        let flag = flags.iter().find(|flag| {
+            flag.get_long().is_some_and(|long| name == format!("--{}", long)) ||
+                flag.get_short().is_some_and(|short| name == format!("-{}", short))
+        });
+
+        match flag {
+            Some(flag) => {
+                if !inline_value && flag.get_action().takes_values() {
+                    args.next();
+                }
+            }
+            None => kept.push(arg),
+        }
+    }
+
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_engine_settings_are_flags() {
+        let command = Cli::command();
+
+        for key in ENGINE_SETTINGS {
+            let flag = key.replace('_', "-");
+
+            assert!(command.get_arguments().any(|arg| arg.get_long() == Some(flag.as_str())), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_without_flags() {
+        assert_eq!(
+            without_flags(
+                args(&[
+                    "engine",
+                    "--fees",
+                    "fees.csv",
+                    "--allow-unlocks",
+                    "--limits=limits.csv",
+                    "--tier-limit",
+                    "low=10",
+                    "input.csv",
+                    "--",
+                    "--fees",
+                ]),
+                &["fees", "limits", "allow_unlocks"]
+            ),
+            args(&["engine", "--tier-limit", "low=10", "input.csv", "--", "--fees"])
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::{ collections::BTreeSet, fmt, io };
+
+use serde::Serialize;
+
+use crate::{
+    engine::Engine,
+    error::EngineError,
+    reason::ReasonCode,
+    store::StateStore,
+    types::{ Account, ClientId, Transaction },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    Outcome {
+        tx_id: u32,
+        client_id: ClientId,
+        primary: Result<(), ReasonCode>,
+        candidate: Result<(), ReasonCode>,
+    },
+    Balance {
+        tx_id: Option<u32>,
+        client_id: ClientId,
+        primary: Option<Account>,
+        candidate: Option<Account>,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = Row::from(self);
+
+        write!(
+            f,
+            "{} divergence for client {} ({}): primary {} candidate {}",
+            row.kind,
+            row.client,
+            row.tx.map_or("end of run".to_string(), |tx| format!("tx {}", tx)),
+            row.primary,
+            row.candidate
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct Row {
+    tx: Option<u32>,
+    client: ClientId,
+    kind: &'static str,
+    primary: String,
+    candidate: String,
+}
+
+impl From<&Divergence> for Row {
+    fn from(divergence: &Divergence) -> Self {
+        match divergence {
+            Divergence::Outcome { tx_id, client_id, primary, candidate } =>
+                Row {
+                    tx: Some(*tx_id),
+                    client: *client_id,
+                    kind: "outcome",
+                    primary: describe_outcome(primary),
+                    candidate: describe_outcome(candidate),
+                },
+            Divergence::Balance { tx_id, client_id, primary, candidate } =>
+                Row {
+                    tx: *tx_id,
+                    client: *client_id,
+                    kind: "balance",
+                    primary: describe_account(primary.as_ref()),
+                    candidate: describe_account(candidate.as_ref()),
+                },
+        }
+    }
+}
+
+fn describe_outcome(outcome: &Result<(), ReasonCode>) -> String {
+    match outcome {
+        Ok(()) => "OK".to_string(),
+        Err(reason) => reason.to_string(),
+    }
+}
+
+fn describe_account(account: Option<&Account>) -> String {
+    match account {
+        Some(account) =>
+            format!(
+                "available={} held={} total={} locked={}",
+                account.available,
+                account.held,
+                account.total,
+                account.locked
+            ),
+        None => "missing".to_string(),
+    }
+}
+
+pub struct Shadow {
+    candidate: Engine,
+    divergences: Vec<Divergence>,
+}
+
+impl Shadow {
+    pub fn new(candidate: Engine) -> Self {
+        Shadow { candidate, divergences: vec![] }
+    }
+
+    pub fn add_transaction<S: StateStore>(
+        &mut self,
+        primary: &mut Engine<S>,
+        tx: Transaction
+    ) -> Result<(), EngineError> {
+        let (tx_id, client_id) = (tx.tx_id, tx.client_id);
+
+        let candidate_result = self.candidate.add_transaction(tx.clone());
+        let primary_result = primary.add_transaction(tx);
+
+        let primary_outcome = primary_result.as_ref().map_err(EngineError::reason).copied();
+        let candidate_outcome = candidate_result.as_ref().map_err(EngineError::reason).copied();
+
+        if primary_outcome != candidate_outcome {
+            self.record(Divergence::Outcome {
+                tx_id,
+                client_id,
+                primary: primary_outcome,
+                candidate: candidate_outcome,
+            });
+        }
+
+        self.compare_account(primary, client_id, Some(tx_id));
+
+        primary_result
+    }
+
+    pub fn divergences(&self) -> &[Divergence] {
+        &self.divergences
+    }
+
+    pub fn finish<S: StateStore>(mut self, primary: &Engine<S>) -> Vec<Divergence> {
+        let client_ids: BTreeSet<ClientId> = primary
+            .client_ids()
+            .chain(self.candidate.client_ids())
+            .collect();
+
+        for client_id in client_ids {
+            self.compare_account(primary, client_id, None);
+        }
+
+        self.divergences
+    }
+
+    fn compare_account<S: StateStore>(
+        &mut self,
+        primary: &Engine<S>,
+        client_id: ClientId,
+        tx_id: Option<u32>
+    ) {
+        let primary = primary.get_account(client_id);
+        let candidate = self.candidate.get_account(client_id);
+
+        if primary != candidate {
+            let divergence = Divergence::Balance {
+                tx_id,
+                client_id,
+                primary: primary.cloned(),
+                candidate: candidate.cloned(),
+            };
+
+            self.record(divergence);
+        }
+    }
+
+    fn record(&mut self, divergence: Divergence) {
+        log::warn!("Shadow {}", divergence);
+
+        self.divergences.push(divergence);
+    }
+}
+
+pub fn write_report<W: io::Write>(divergences: &[Divergence], writer: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for divergence in divergences {
+        writer.serialize(Row::from(divergence))?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::types::TransactionType;
+
+    fn shadow_with_candidate_seed() -> Shadow {
+        let mut candidate = Engine::new();
+        candidate.seed_account(Account {
+            client_id: 1,
+            available: dec!(5),
+            held: dec!(0),
+            total: dec!(5),
+            locked: false,
+        });
+
+        Shadow::new(candidate)
+    }
+
+    #[test]
+    fn test_no_divergence() {
+        let mut primary = Engine::new();
+        let mut shadow = Shadow::new(Engine::new());
+
+        let _ = shadow.add_transaction(
+            &mut primary,
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))
+        );
+        let _ = shadow.add_transaction(
+            &mut primary,
+            Transaction::new(1, 2, TransactionType::Withdrawal(dec!(20)))
+        );
+
+        assert!(shadow.finish(&primary).is_empty());
+        assert_eq!(primary.get_account(1).unwrap().available, dec!(10));
+    }
+
+    #[test]
+    fn test_outcome_and_balance_divergence() {
+        let mut primary = Engine::new();
+        let mut shadow = shadow_with_candidate_seed();
+
+        assert_eq!(
+            shadow.add_transaction(
+                &mut primary,
+                Transaction::new(1, 1, TransactionType::Withdrawal(dec!(3)))
+            ),
+            Err(EngineError::InsufficientFunds {
+                client_id: 1,
+                available: dec!(0),
+                required: dec!(3),
+            })
+        );
+
+        assert_eq!(shadow.divergences(), &[
+            Divergence::Outcome {
+                tx_id: 1,
+                client_id: 1,
+                primary: Err(ReasonCode::InsufficientFunds),
+                candidate: Ok(()),
+            },
+            Divergence::Balance {
+                tx_id: Some(1),
+                client_id: 1,
+                primary: Some(Account::new(1)),
+                candidate: Some(Account {
+                    client_id: 1,
+                    available: dec!(2),
+                    held: dec!(0),
+                    total: dec!(2),
+                    locked: false,
+                }),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_finish_reports_missing_accounts() {
+        let shadow = shadow_with_candidate_seed();
+
+        let divergences = shadow.finish(&Engine::new());
+        assert_eq!(divergences.len(), 1);
+        assert!(
+            matches!(&divergences[0], Divergence::Balance { tx_id: None, client_id: 1, primary: None, .. })
+        );
+    }
+
+    #[test]
+    fn test_write_report() {
+        let mut primary = Engine::new();
+        let mut shadow = shadow_with_candidate_seed();
+        let _ = shadow.add_transaction(
+            &mut primary,
+            Transaction::new(1, 1, TransactionType::Withdrawal(dec!(3)))
+        );
+
+        let mut output = vec![];
+        write_report(shadow.divergences(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tx,client,kind,primary,candidate\n\
+            1,1,outcome,INSUFFICIENT_FUNDS,OK\n\
+            1,1,balance,available=0 held=0 total=0 locked=false,available=2 held=0 total=2 locked=false\n"
+        );
+    }
+}
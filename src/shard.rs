@@ -0,0 +1,861 @@
+use std::{
+    any::Any,
+    collections::{ HashMap, HashSet },
+    fmt,
+    mem,
+    panic::{ self, AssertUnwindSafe },
+    sync::mpsc::{ self, Receiver, Sender, SyncSender },
+    thread::{ self, JoinHandle },
+};
+
+use crate::{
+    engine::{ Engine, EngineCore, Holding, MovedClients, TxStatus },
+    error::EngineError,
+    ordering::{ SequenceGuard, Sequenced, Sequencer },
+    policy::RiskTier,
+    snapshot::Snapshot,
+    types::{ Account, ClientId, Transaction, TransactionType },
+};
+
+// Batches queued for each worker before `submit` waits for room
+const SHARD_CAPACITY: usize = 16;
+const BATCH_SIZE: usize = 256;
+
+/// The worker owning the account of a client, out of `workers`.
+pub fn shard_of(client_id: ClientId, workers: usize) -> usize {
+    client_id as usize % workers
+}
+
+/// The other client of a transfer or a merge.
+pub fn counterparty(tx: &Transaction) -> Option<ClientId> {
+    match tx.tx_type {
+        TransactionType::Transfer { to, .. } => Some(to),
+        TransactionType::Merge(into) => Some(into),
+        _ => None,
+    }
+}
+
+/// The engine a transaction goes to, out of `workers` engines that can't move clients between them,
+/// like those of `serve --workers`. A transfer or a merge would need the accounts of two engines,
+/// with more than one it's rejected with `NOT_ALLOWED` whether its clients are on the same engine or
+/// not, so the results don't depend on the number of engines. A [`ShardedEngine`] moves the clients
+/// instead.
+pub fn route(tx: &Transaction, workers: usize) -> Result<usize, EngineError> {
+    match counterparty(tx) {
+        Some(_) if workers > 1 => Err(EngineError::NotAllowed { tx_id: tx.tx_id, tx_type: tx.tx_type.name() }),
+        _ => Ok(shard_of(tx.client_id, workers)),
+    }
+}
+
+/// The shard that last took each transaction id. A transaction taking or referring to an id taken
+/// on another shard has to be ordered after the transactions of that shard, and is rejected like
+/// by a single engine when that shard still holds the id.
+#[derive(Debug, Default)]
+pub struct TxIndex {
+    shards: HashMap<u32, usize>,
+}
+
+impl TxIndex {
+    /// The other shard that took the id of the transaction going to `shard`, for the transactions
+    /// taking a new id (deposits, withdrawals, ...) or referring to one (disputes, approvals, ...).
+    pub fn other_shard(&self, tx: &Transaction, shard: usize) -> Option<usize> {
+        if !takes_id(&tx.tx_type) && !refers_to_id(&tx.tx_type) {
+            return None;
+        }
+
+        self.shards.get(&tx.tx_id).copied().filter(|other| *other != shard)
+    }
+
+    /// Records that the shard took the id of the transaction, when it takes a new one.
+    pub fn take(&mut self, tx: &Transaction, shard: usize) {
+        if takes_id(&tx.tx_type) {
+            self.shards.insert(tx.tx_id, shard);
+        }
+    }
+
+    /// Records that the transactions moved to the shard with their clients.
+    pub fn moved(&mut self, tx_ids: impl IntoIterator<Item = u32>, shard: usize) {
+        for tx_id in tx_ids {
+            self.shards.insert(tx_id, shard);
+        }
+    }
+}
+
+fn takes_id(tx_type: &TransactionType) -> bool {
+    matches!(
+        tx_type,
+        TransactionType::Deposit(_) |
+            TransactionType::Withdrawal(_) |
+            TransactionType::Adjustment { .. } |
+            TransactionType::Transfer { .. } |
+            TransactionType::WithdrawHold(_)
+    )
+}
+
+fn refers_to_id(tx_type: &TransactionType) -> bool {
+    matches!(
+        tx_type,
+        TransactionType::Dispute |
+            TransactionType::Resolve |
+            TransactionType::Chargeback |
+            TransactionType::Representment |
+            TransactionType::WithdrawCommit |
+            TransactionType::WithdrawVoid |
+            TransactionType::Approve |
+            TransactionType::Decline
+    )
+}
+
+// The worker of every client. A client is on its `shard_of` worker until a transfer or a merge
+// links it to another client, the clients linked together, directly or not, are then kept on one
+// worker.
+#[derive(Debug)]
+struct Homes {
+    workers: usize,
+    groups: HashMap<ClientId, usize>,
+    // The clients and the worker of every group, by the index in `groups`
+    members: Vec<Vec<ClientId>>,
+    shards: Vec<usize>,
+}
+
+// Clients moving to another worker for a transfer or a merge
+struct Move {
+    clients: HashSet<ClientId>,
+    from: usize,
+    to: usize,
+}
+
+impl Homes {
+    fn new(workers: usize) -> Self {
+        Homes { workers, groups: HashMap::new(), members: vec![], shards: vec![] }
+    }
+
+    fn shard(&self, client_id: ClientId) -> usize {
+        match self.groups.get(&client_id) {
+            Some(group) => self.shards[*group],
+            None => shard_of(client_id, self.workers),
+        }
+    }
+
+    fn members(&self, client_id: ClientId) -> HashSet<ClientId> {
+        match self.groups.get(&client_id) {
+            Some(group) => self.members[*group].iter().copied().collect(),
+            None => HashSet::from([client_id]),
+        }
+    }
+
+    // The clients of the smaller group move to the worker of the other one, none when both are on
+    // the same worker already
+    fn moving(&self, client_id: ClientId, other: ClientId) -> Option<Move> {
+        let (shard, other_shard) = (self.shard(client_id), self.shard(other));
+
+        if shard == other_shard {
+            return None;
+        }
+
+        let (clients, others) = (self.members(client_id), self.members(other));
+
+        Some(match others.len() <= clients.len() {
+            true => Move { clients: others, from: other_shard, to: shard },
+            false => Move { clients, from: shard, to: other_shard },
+        })
+    }
+
+    // Once the clients are on the same worker
+    fn link(&mut self, client_id: ClientId, other: ClientId, shard: usize) {
+        let (group, other_group) = (self.group(client_id), self.group(other));
+
+        if group == other_group {
+            return;
+        }
+
+        let (kept, emptied) = match self.members[group].len() >= self.members[other_group].len() {
+            true => (group, other_group),
+            false => (other_group, group),
+        };
+        let moved = mem::take(&mut self.members[emptied]);
+
+        for client_id in &moved {
+            self.groups.insert(*client_id, kept);
+        }
+
+        self.members[kept].extend(moved);
+        self.shards[kept] = shard;
+    }
+
+    fn group(&mut self, client_id: ClientId) -> usize {
+        if let Some(group) = self.groups.get(&client_id) {
+            return *group;
+        }
+
+        let group = self.members.len();
+
+        self.shards.push(self.shard(client_id));
+        self.members.push(vec![client_id]);
+        self.groups.insert(client_id, group);
+
+        group
+    }
+}
+
+/// A submitted transaction's tag and result
+pub type Outcome<T> = (T, Result<(), EngineError>);
+
+type Call = Box<dyn FnOnce(&mut Worker) + Send>;
+
+// A transaction with how another worker holds its id
+type Queued<T> = (Sequenced, Option<Holding>, T);
+
+enum Work<T> {
+    Apply(Vec<Queued<T>>),
+    Call(Call),
+}
+
+/// Splits the clients across worker threads, each applying the transactions of its clients to its
+/// own [`Engine`]. The transactions of a client are applied in the order they are submitted, but
+/// there's no order between clients of different workers. Each worker checks it with a
+/// [`SequenceGuard`] and rejects a transaction applied after a later one of its client with
+/// `OUT_OF_ORDER`. A client starts on its [`shard_of`] worker. A transfer or a merge between clients
+/// of two workers waits for both of them to apply the transactions submitted before, then the
+/// clients of one side move to the worker of the other with their accounts, history and pending
+/// releases, and it's applied there. Clients linked by a transfer or a merge stay together from
+/// then on, the smaller side moves. The engine clock of a worker only moves with the timestamps of
+/// its own transactions. Transaction ids are unique across the workers: a transaction reusing or
+/// referring to the id of a transaction of another worker waits for that worker, and is rejected
+/// like by a single engine.
+///
+/// A worker that panics applying a transaction is degraded rather than taking the whole run down:
+/// that transaction and every later one of its clients are rejected with `SHARD_DEGRADED`, the
+/// other workers carry on, and [`ShardedEngine::finish`] reports it.
+///
+/// `T` tags each submitted transaction, it's handed back with the result.
+pub struct ShardedEngine<T = ()> {
+    shards: Vec<SyncSender<Work<T>>>,
+    workers: Vec<JoinHandle<Worker>>,
+    batches: Vec<Vec<Queued<T>>>,
+    index: TxIndex,
+    homes: Homes,
+    // Numbers the transactions of each client in the order they reach the workers
+    sequencer: Sequencer,
+    results: Receiver<Outcome<T>>,
+    // Results of the transactions rejected before reaching a worker
+    rejections: Sender<Outcome<T>>,
+}
+
+impl<T: Send + 'static> ShardedEngine<T> {
+    /// Starts `workers` workers, `build` creates the engine of each one from its index.
+    pub fn new(workers: usize, build: impl Fn(usize) -> Engine) -> Self {
+        let workers = workers.max(1);
+        let (results_tx, results) = mpsc::channel();
+        let mut shards = Vec::with_capacity(workers);
+        let mut handles = Vec::with_capacity(workers);
+
+        for index in 0..workers {
+            let (tx, rx) = mpsc::sync_channel(SHARD_CAPACITY);
+            let engine = build(index);
+            let results = results_tx.clone();
+
+            shards.push(tx);
+            handles.push(thread::spawn(move || work(Worker::new(index, engine), rx, results)));
+        }
+
+        ShardedEngine {
+            shards,
+            workers: handles,
+            batches: (0..workers).map(|_| Vec::new()).collect(),
+            index: TxIndex::default(),
+            homes: Homes::new(workers),
+            sequencer: Sequencer::new(),
+            results,
+            rejections: results_tx,
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Queues a transaction, its result is returned by [`ShardedEngine::results`] once it's applied.
+    pub fn submit(&mut self, tx: Transaction, tag: T) {
+        let (shard, holding) = match self.place(&tx) {
+            Ok(placed) => placed,
+            Err(err) => {
+                let _ = self.rejections.send((tag, Err(err)));
+                return;
+            }
+        };
+
+        let sequenced = self.sequencer.assign(tx);
+
+        self.batches[shard].push((sequenced, holding, tag));
+
+        if self.batches[shard].len() >= BATCH_SIZE {
+            self.send_batch(shard);
+        }
+    }
+
+    /// Hands the queued transactions over to the workers.
+    pub fn flush(&mut self) {
+        for shard in 0..self.shards.len() {
+            self.send_batch(shard);
+        }
+    }
+
+    /// The results available so far, without waiting for the workers.
+    pub fn results(&self) -> impl Iterator<Item = Outcome<T>> + '_ {
+        self.results.try_iter()
+    }
+
+    /// Applies the queued transactions and stops the workers, returns the results not taken yet,
+    /// the engine of every worker and the workers that were degraded by a panic. The engine of a
+    /// degraded worker is in the state the panic left it in.
+    pub fn finish(mut self) -> (Vec<Outcome<T>>, Vec<Engine>, Vec<DegradedShard>) {
+        self.flush();
+
+        let ShardedEngine { shards, workers, results, rejections, .. } = self;
+
+        drop(shards);
+        drop(rejections);
+
+        let mut engines = Vec::with_capacity(workers.len());
+        let mut degraded = vec![];
+
+        // Panics while applying a transaction are caught, any other one is a bug in the worker loop
+        for worker in workers {
+            let worker = worker.join().expect("an engine worker panicked");
+
+            engines.push(worker.engine);
+            degraded.extend(worker.degraded);
+        }
+
+        (results.into_iter().collect(), engines, degraded)
+    }
+
+    // The worker of the transaction and how another worker holds its id, asking that worker waits
+    // for the transactions already submitted to it
+    fn place(&mut self, tx: &Transaction) -> Result<(usize, Option<Holding>), EngineError> {
+        if let Some(other) = counterparty(tx) {
+            self.join(tx.client_id, other)?;
+        }
+
+        let shard = self.homes.shard(tx.client_id);
+
+        let holding = match self.index.other_shard(tx, shard) {
+            Some(other) => {
+                let tx_id = tx.tx_id;
+
+                self.send_batch(other);
+                self.call(other, move |worker| worker.engine.holding(tx_id))
+            }
+            None => None,
+        };
+
+        // A transaction reusing an id still held elsewhere is rejected, the id stays there
+        if holding.is_none() {
+            self.index.take(tx, shard);
+        }
+
+        Ok((shard, holding))
+    }
+
+    // Brings the clients of a transfer or a merge to one worker, after both workers applied the
+    // transactions submitted before. A degraded worker keeps its clients, the transaction is rejected.
+    fn join(&mut self, client_id: ClientId, other: ClientId) -> Result<(), EngineError> {
+        let shard = match self.homes.moving(client_id, other) {
+            Some(Move { clients, from, to }) => {
+                self.send_batch(from);
+                self.send_batch(to);
+                self.call(to, |worker| worker.healthy())?;
+
+                let moved = self.call(from, move |worker| worker.take_clients(&clients))?;
+
+                self.index.moved(moved.clients.tx_ids(), to);
+                self.call(to, move |worker| worker.put_clients(moved));
+
+                to
+            }
+            None => self.homes.shard(client_id),
+        };
+
+        self.homes.link(client_id, other, shard);
+
+        Ok(())
+    }
+
+    fn send_batch(&mut self, shard: usize) {
+        if self.batches[shard].is_empty() {
+            return;
+        }
+
+        let batch = mem::take(&mut self.batches[shard]);
+
+        self.shards[shard].send(Work::Apply(batch)).expect("an engine worker stopped");
+    }
+
+    // Runs after the transactions already submitted to the worker
+    fn call<R, F>(&self, shard: usize, f: F) -> R
+        where R: Send + 'static, F: FnOnce(&mut Worker) -> R + Send + 'static
+    {
+        let (reply, response) = mpsc::sync_channel(1);
+
+        let call: Call = Box::new(move |worker| {
+            let _ = reply.send(f(worker));
+        });
+
+        self.shards[shard].send(Work::Call(call)).expect("an engine worker stopped");
+
+        response.recv().expect("an engine worker stopped")
+    }
+}
+
+/// A worker that panicked while applying a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DegradedShard {
+    pub shard: usize,
+    /// The transaction that panicked
+    pub client_id: ClientId,
+    pub tx_id: u32,
+    pub panic: String,
+}
+
+impl fmt::Display for DegradedShard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "worker {} is degraded, it panicked applying transaction {} of client {}: {}",
+            self.shard,
+            self.tx_id,
+            self.client_id,
+            self.panic
+        )
+    }
+}
+
+// Clients taken out of a worker, with the next sequence numbers its guard expected of them
+struct MovedWorkerClients {
+    clients: MovedClients,
+    sequences: Vec<(ClientId, u64)>,
+}
+
+struct Worker {
+    index: usize,
+    engine: Engine,
+    guard: SequenceGuard,
+    degraded: Option<DegradedShard>,
+}
+
+impl Worker {
+    fn new(index: usize, engine: Engine) -> Self {
+        Worker { index, engine, guard: SequenceGuard::new(), degraded: None }
+    }
+
+    // The engine may be halfway through a transaction after a panic, it doesn't apply any other
+    fn healthy(&self) -> Result<(), EngineError> {
+        match self.degraded {
+            Some(_) => Err(EngineError::ShardDegraded(self.index)),
+            None => Ok(()),
+        }
+    }
+
+    fn take_clients(&mut self, clients: &HashSet<ClientId>) -> Result<MovedWorkerClients, EngineError> {
+        self.healthy()?;
+
+        let sequences = clients
+            .iter()
+            .filter_map(|client_id| self.guard.take_client(*client_id).map(|expected| (*client_id, expected)))
+            .collect();
+
+        Ok(MovedWorkerClients { clients: self.engine.take_clients(clients), sequences })
+    }
+
+    fn put_clients(&mut self, moved: MovedWorkerClients) {
+        for (client_id, expected) in moved.sequences {
+            self.guard.put_client(client_id, expected);
+        }
+
+        self.engine.put_clients(moved.clients);
+    }
+
+    fn apply(&mut self, sequenced: Sequenced, holding: Option<Holding>) -> Result<(), EngineError> {
+        self.healthy()?;
+
+        self.guard.check(&sequenced).map_err(EngineError::OutOfOrder)?;
+
+        let Sequenced { tx, .. } = sequenced;
+        let (client_id, tx_id) = (tx.client_id, tx.tx_id);
+
+        let apply = || match holding {
+            Some(holding) => self.engine.add_transaction_held_elsewhere(tx, holding),
+            None => self.engine.add_transaction(tx),
+        };
+
+        match panic::catch_unwind(AssertUnwindSafe(apply)) {
+            Ok(result) => result,
+            Err(payload) => {
+                let degraded = DegradedShard {
+                    shard: self.index,
+                    client_id,
+                    tx_id,
+                    panic: panic_message(payload.as_ref()),
+                };
+
+                log::error!("{}", degraded);
+
+                self.degraded = Some(degraded);
+
+                Err(EngineError::ShardDegraded(self.index))
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+fn work<T>(mut worker: Worker, rx: Receiver<Work<T>>, results: Sender<Outcome<T>>) -> Worker {
+    for work in rx {
+        match work {
+            Work::Apply(batch) => {
+                for (sequenced, holding, tag) in batch {
+                    let result = worker.apply(sequenced, holding);
+
+                    // Nobody waits for the results anymore, the transactions are still applied
+                    let _ = results.send((tag, result));
+                }
+            }
+            Work::Call(call) => call(&mut worker),
+        }
+    }
+
+    worker
+}
+
+// The state of every worker, accounts in the order of the client ids
+pub(crate) fn merged(snapshots: impl IntoIterator<Item = Snapshot>) -> Snapshot {
+    let mut merged = Snapshot::default();
+
+    for snapshot in snapshots {
+        merged.taken_at = merged.taken_at.max(snapshot.taken_at);
+        merged.accounts.extend(snapshot.accounts);
+        merged.tiers.extend(snapshot.tiers);
+        merged.scheduled.extend(snapshot.scheduled);
+        merged.deleted.extend(snapshot.deleted);
+    }
+
+    merged.accounts.sort_unstable_by_key(|account| account.client_id);
+    merged.deleted.sort_unstable_by_key(|account| account.client_id);
+    merged.scheduled.sort_by_key(|release| (release.at, release.tx_id, release.kind));
+
+    merged
+}
+
+// Transactions are routed by client so the id could be on any worker, an applied one wins over a
+// rejected one reusing its id on another worker
+pub(crate) fn merged_status(statuses: impl IntoIterator<Item = TxStatus>) -> Option<TxStatus> {
+    let statuses: Vec<TxStatus> = statuses.into_iter().collect();
+
+    statuses
+        .iter()
+        .find(|status| status.reason().is_none())
+        .or(statuses.first())
+        .copied()
+}
+
+/// The accounts of every worker sorted by client id, like [`Engine::get_accounts`].
+pub fn merge_accounts(engines: Vec<Engine>) -> Vec<Account> {
+    let mut accounts: Vec<Account> = engines.into_iter().flat_map(Engine::get_accounts).collect();
+
+    accounts.sort_unstable_by_key(|account| account.client_id);
+
+    accounts
+}
+
+/// Merges the snapshots of the engines returned by [`ShardedEngine::finish`].
+pub fn merge_snapshot(engines: &[Engine]) -> Snapshot {
+    merged(engines.iter().map(Snapshot::capture))
+}
+
+impl<T: Send + 'static> EngineCore for ShardedEngine<T> {
+    fn apply(&mut self, tx: Transaction) -> Result<(), EngineError> {
+        let (shard, holding) = self.place(&tx)?;
+        let sequenced = self.sequencer.assign(tx);
+
+        self.send_batch(shard);
+        self.call(shard, move |worker| worker.apply(sequenced, holding))
+    }
+
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        let shard = self.homes.shard(client_id);
+
+        self.call(shard, move |worker| worker.engine.get_account(client_id).cloned())
+    }
+
+    fn accounts_iter(&self) -> Box<dyn Iterator<Item = Account> + '_> {
+        Box::new(self.snapshot().accounts.into_iter())
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        merged(
+            (0..self.workers()).map(|shard| self.call(shard, |worker| Snapshot::capture(&worker.engine)))
+        )
+    }
+
+    fn risk_tier(&self, client_id: ClientId) -> RiskTier {
+        let shard = self.homes.shard(client_id);
+
+        self.call(shard, move |worker| worker.engine.risk_tier(client_id))
+    }
+
+    fn tx_status(&self, tx_id: u32) -> Option<TxStatus> {
+        merged_status(
+            (0..self.workers()).filter_map(|shard| self.call(shard, move |worker| worker.engine.tx_status(tx_id)))
+        )
+    }
+
+    fn changed_clients(&self, tx: &Transaction) -> Vec<ClientId> {
+        let shard = self.homes.shard(tx.client_id);
+        let tx = tx.clone();
+
+        self.call(shard, move |worker| worker.engine.changed_clients(&tx))
+    }
+
+    fn pending(&self) -> Vec<Transaction> {
+        let mut pending: Vec<Transaction> = (0..self.workers())
+            .flat_map(|shard| self.call(shard, |worker| worker.engine.pending()))
+            .collect();
+
+        pending.sort_unstable_by_key(|tx| tx.tx_id);
+
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::{ hooks::EngineHooks, ordering::OutOfOrder, policy::Decision };
+
+    use super::*;
+
+    #[test]
+    fn test_sharded_engine() {
+        let opening = [Account { available: dec!(5), total: dec!(5), ..Account::new(4) }];
+        let mut sharded = ShardedEngine::new(3, |shard| {
+            Engine::builder()
+                .opening_balances(
+                    opening.iter().filter(|account| shard_of(account.client_id, 3) == shard).cloned()
+                )
+                .build()
+        });
+
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))),
+            Transaction::new(2, 2, TransactionType::Deposit(dec!(3))),
+            Transaction::new(1, 3, TransactionType::Withdrawal(dec!(4))),
+            Transaction::new(2, 4, TransactionType::Withdrawal(dec!(5))),
+            Transaction::new(1, 1, TransactionType::Dispute),
+            Transaction::new(1, 5, TransactionType::Transfer { to: 4, amount: dec!(1) }),
+            Transaction::new(1, 6, TransactionType::Transfer { to: 2, amount: dec!(1) }),
+        ];
+
+        for (index, tx) in transactions.into_iter().enumerate() {
+            sharded.submit(tx, index);
+        }
+
+        let (mut results, engines, degraded) = sharded.finish();
+        assert!(degraded.is_empty());
+        results.sort_by_key(|(index, _)| *index);
+
+        let results: Vec<Result<(), EngineError>> = results
+            .into_iter()
+            .map(|(_, result)| result)
+            .collect();
+
+        assert_eq!(results[..3], [Ok(()), Ok(()), Ok(())]);
+        assert_eq!(results[3], Err(EngineError::InsufficientFunds {
+            client_id: 2,
+            available: dec!(3),
+            required: dec!(5),
+        }));
+        assert!(results[4].is_err());
+        // Clients 1 and 4 are on the same worker, 2 moves there for the second transfer
+        assert_eq!(results[5..], [Ok(()), Ok(())]);
+
+        let accounts = merge_accounts(engines);
+        assert_eq!(
+            accounts.iter().map(|account| (account.client_id, account.available)).collect::<Vec<_>>(),
+            vec![(1, dec!(4)), (2, dec!(4)), (4, dec!(6))]
+        );
+    }
+
+    #[test]
+    fn test_transfers_and_merges_across_workers() {
+        // Clients 3 and 6 are on worker 0, 1 and 4 on worker 1, 2 and 5 on worker 2
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))),
+            Transaction::new(2, 2, TransactionType::Deposit(dec!(5))),
+            Transaction::new(3, 3, TransactionType::Deposit(dec!(7))),
+            Transaction::new(1, 4, TransactionType::Transfer { to: 2, amount: dec!(3) }),
+            Transaction::new(2, 5, TransactionType::Deposit(dec!(1))),
+            Transaction::new(6, 6, TransactionType::Deposit(dec!(2))),
+            Transaction::new(3, 7, TransactionType::Transfer { to: 1, amount: dec!(2) }),
+            Transaction::new(2, 4, TransactionType::Dispute),
+            Transaction::new(2, 4, TransactionType::Chargeback),
+            Transaction::new(5, 2, TransactionType::Deposit(dec!(3))),
+            Transaction::new(6, 8, TransactionType::Merge(3)),
+            Transaction::new(6, 9, TransactionType::Deposit(dec!(1))),
+            Transaction::new(3, 3, TransactionType::Dispute),
+            Transaction::new(5, 7, TransactionType::Dispute),
+        ];
+
+        let mut single = Engine::new();
+        let expected: Vec<(usize, Result<(), EngineError>)> = transactions
+            .iter()
+            .enumerate()
+            .map(|(index, tx)| (index, single.add_transaction(tx.clone())))
+            .collect();
+
+        let mut sharded = ShardedEngine::new(3, |_| Engine::new());
+
+        for (index, tx) in transactions.into_iter().enumerate() {
+            sharded.submit(tx, index);
+        }
+
+        assert_eq!(sharded.homes.shard(2), 1);
+        assert_eq!(sharded.homes.shard(6), 1);
+
+        let (mut results, engines, degraded) = sharded.finish();
+        assert!(degraded.is_empty());
+        results.sort_by_key(|(index, _)| *index);
+
+        assert_eq!(results, expected);
+        assert_eq!(results[9].1, Err(EngineError::DuplicateTxId(2)));
+        assert_eq!(results[13].1, Err(EngineError::ClientMismatch { tx_id: 7, client_id: 5 }));
+        assert!(engines.iter().all(|engine| engine.verify().is_ok()));
+        assert_eq!(merge_accounts(engines), single.get_accounts());
+    }
+
+    #[test]
+    fn test_tx_ids_across_workers() {
+        // Clients 1 and 3 are on worker 1, 2 on worker 0
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(10))),
+            Transaction::new(2, 1, TransactionType::Deposit(dec!(5))),
+            Transaction::new(2, 1, TransactionType::Dispute),
+            Transaction::new(2, 2, TransactionType::Withdrawal(dec!(1))),
+            Transaction::new(3, 2, TransactionType::Deposit(dec!(3))),
+            Transaction::new(2, 2, TransactionType::Deposit(dec!(4))),
+            Transaction::new(1, 1, TransactionType::Dispute),
+            Transaction::new(2, 3, TransactionType::Deposit(dec!(2))),
+            Transaction::new(1, 3, TransactionType::Chargeback),
+        ];
+
+        let mut single = Engine::new();
+        let expected: Vec<(u32, Result<(), EngineError>)> = transactions
+            .iter()
+            .enumerate()
+            .map(|(index, tx)| (index as u32, single.add_transaction(tx.clone())))
+            .collect();
+
+        let mut sharded = ShardedEngine::new(2, |_| Engine::new());
+
+        for (index, tx) in transactions.into_iter().enumerate() {
+            sharded.submit(tx, index as u32);
+        }
+
+        let (mut results, engines, _) = sharded.finish();
+        results.sort_by_key(|(index, _)| *index);
+
+        assert_eq!(results, expected);
+        assert_eq!(results[1].1, Err(EngineError::DuplicateTxId(1)));
+        assert_eq!(results[2].1, Err(EngineError::ClientMismatch { tx_id: 1, client_id: 2 }));
+        assert_eq!(results[5].1, Err(EngineError::DuplicateTxId(2)));
+        assert_eq!(merge_accounts(engines), single.get_accounts());
+    }
+
+    struct PanicOn(ClientId);
+
+    impl EngineHooks for PanicOn {
+        fn before_apply(&self, tx: &Transaction) -> Decision {
+            assert_ne!(tx.client_id, self.0, "poisoned client");
+            Decision::Allow
+        }
+    }
+
+    #[test]
+    fn test_degraded_shard() {
+        let mut sharded = ShardedEngine::new(2, |_| Engine::builder().hooks(Box::new(PanicOn(3))).build());
+
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(1))),
+            Transaction::new(2, 2, TransactionType::Deposit(dec!(1))),
+            Transaction::new(3, 3, TransactionType::Deposit(dec!(1))),
+            Transaction::new(1, 4, TransactionType::Deposit(dec!(1))),
+            Transaction::new(4, 5, TransactionType::Deposit(dec!(1))),
+        ];
+
+        for tx in transactions {
+            let tag = tx.tx_id;
+            sharded.submit(tx, tag);
+        }
+
+        let (mut results, engines, degraded) = sharded.finish();
+        results.sort_by_key(|(tx_id, _)| *tx_id);
+
+        // Clients 1 and 3 are on worker 1, 2 and 4 on worker 0
+        assert_eq!(results, vec![
+            (1, Ok(())),
+            (2, Ok(())),
+            (3, Err(EngineError::ShardDegraded(1))),
+            (4, Err(EngineError::ShardDegraded(1))),
+            (5, Ok(()))
+        ]);
+        assert_eq!(degraded.len(), 1);
+        assert_eq!((degraded[0].shard, degraded[0].client_id, degraded[0].tx_id), (1, 3, 3));
+        assert!(degraded[0].panic.contains("poisoned client"));
+
+        assert_eq!(
+            merge_accounts(engines).iter().map(|account| account.client_id).collect::<Vec<_>>(),
+            vec![1, 2, 4]
+        );
+    }
+
+    #[test]
+    fn test_engine_core() {
+        let mut sharded: ShardedEngine = ShardedEngine::new(2, |_| Engine::new());
+
+        sharded.apply(Transaction::new(1, 1, TransactionType::Deposit(dec!(2)))).unwrap();
+        sharded.apply(Transaction::new(2, 2, TransactionType::Deposit(dec!(3)))).unwrap();
+        assert_eq!(sharded.apply(Transaction::new(1, 3, TransactionType::Merge(2))), Ok(()));
+
+        assert_eq!(sharded.account(2).unwrap().available, dec!(5));
+        assert_eq!(
+            sharded.accounts_iter().map(|account| account.client_id).collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert_eq!(sharded.snapshot().accounts.len(), 1);
+        assert_eq!(sharded.results().count(), 0);
+    }
+
+    #[test]
+    fn test_worker_rejects_out_of_order() {
+        let mut worker = Worker::new(0, Engine::new());
+        let mut sequencer = Sequencer::new();
+
+        let deposit = sequencer.assign(Transaction::new(1, 1, TransactionType::Deposit(dec!(2))));
+        let withdrawal = sequencer.assign(Transaction::new(1, 2, TransactionType::Withdrawal(dec!(2))));
+
+        assert_eq!(
+            worker.apply(withdrawal, None),
+            Err(EngineError::OutOfOrder(OutOfOrder { client_id: 1, expected: 0, received: 1 }))
+        );
+        assert_eq!(worker.apply(deposit, None), Ok(()));
+        assert_eq!(worker.engine.get_account(1).unwrap().available, dec!(2));
+    }
+}
@@ -0,0 +1,450 @@
+use std::{ collections::HashMap, fmt };
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::Engine,
+    error::EngineError,
+    invariants::Violation,
+    types::{ Account, ClientId, Transaction, TransactionType },
+};
+
+// Soak tests of the engine: a seeded generator of random transactions, valid or not, and a model of
+// what the default engine does with them, small enough to be obviously right. Every transaction is
+// applied to both and their outcomes and balances compared, so a seed that diverges reproduces the
+// divergence on any machine.
+
+/// How often the engine invariants are verified, they walk the whole history
+const VERIFY_INTERVAL: usize = 10_000;
+
+/// A splitmix64 generator, the same seed gives the same transactions on every platform and release.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`, `bound` must not be zero
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+/// An endless stream of random transactions over clients `1..=clients`. Most are valid, the rest
+/// are the mistakes of a real input: withdrawals over the balance, duplicate ids, disputes of
+/// unknown transactions or of another client's, resolves and chargebacks without a dispute.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rng: Rng,
+    clients: ClientId,
+    next_tx: u32,
+    // The ids given to deposits and withdrawals, with their client
+    issued: Vec<(ClientId, u32)>,
+}
+
+impl Generator {
+    pub fn new(seed: u64, clients: ClientId) -> Self {
+        Generator { rng: Rng::new(seed), clients: clients.max(1), next_tx: 1, issued: vec![] }
+    }
+
+    fn client(&mut self) -> ClientId {
+        self.rng.below(self.clients as u64) as ClientId + 1
+    }
+
+    // Up to 1000 with four decimal places
+    fn amount(&mut self) -> Decimal {
+        Decimal::new(self.rng.below(10_000_000) as i64 + 1, 4)
+    }
+
+    // A new id, or now and then one already given
+    fn new_tx(&mut self, client_id: ClientId) -> u32 {
+        if !self.issued.is_empty() && self.rng.below(50) == 0 {
+            return self.issued[self.rng.below(self.issued.len() as u64) as usize].1;
+        }
+
+        let tx_id = self.next_tx;
+        self.next_tx += 1;
+        self.issued.push((client_id, tx_id));
+
+        tx_id
+    }
+
+    // An issued transaction, now and then an unknown one or one claimed by another client
+    fn earlier_tx(&mut self) -> (ClientId, u32) {
+        match self.rng.below(20) {
+            _ if self.issued.is_empty() => (self.client(), self.next_tx),
+            0 => (self.client(), self.next_tx + self.rng.below(100) as u32),
+            1 => {
+                let (_, tx_id) = self.issued[self.rng.below(self.issued.len() as u64) as usize];
+
+                (self.client(), tx_id)
+            }
+            _ => {
+                // The recent transactions are the likely ones to be disputed
+                let recent = self.issued.len().min(1_000) as u64;
+
+                self.issued[self.issued.len() - 1 - self.rng.below(recent) as usize]
+            }
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        // Chargebacks are rare, they lock the account for good
+        let tx = match self.rng.below(1_000) {
+            0..=499 => {
+                let client_id = self.client();
+                let tx_type = TransactionType::Deposit(self.amount());
+
+                Transaction::new(client_id, self.new_tx(client_id), tx_type)
+            }
+            500..=749 => {
+                let client_id = self.client();
+                let tx_type = TransactionType::Withdrawal(self.amount());
+
+                Transaction::new(client_id, self.new_tx(client_id), tx_type)
+            }
+            750..=889 => {
+                let (client_id, tx_id) = self.earlier_tx();
+
+                Transaction::new(client_id, tx_id, TransactionType::Dispute)
+            }
+            890..=997 => {
+                let (client_id, tx_id) = self.earlier_tx();
+
+                Transaction::new(client_id, tx_id, TransactionType::Resolve)
+            }
+            _ => {
+                let (client_id, tx_id) = self.earlier_tx();
+
+                Transaction::new(client_id, tx_id, TransactionType::Chargeback)
+            }
+        };
+
+        Some(tx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModelState {
+    Deposited,
+    Withdrawn,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModelTx {
+    client_id: ClientId,
+    amount: Decimal,
+    state: ModelState,
+}
+
+/// What an engine built with the defaults does with deposits, withdrawals, disputes, resolves and
+/// chargebacks, written from the rules rather than from the engine.
+#[derive(Debug, Default)]
+pub struct Oracle {
+    accounts: HashMap<ClientId, Account>,
+    txs: HashMap<u32, ModelTx>,
+}
+
+impl Oracle {
+    pub fn new() -> Self {
+        Oracle::default()
+    }
+
+    /// The balances of a client, zero when it has none
+    pub fn account(&self, client_id: ClientId) -> Account {
+        self.accounts.get(&client_id).cloned().unwrap_or_else(|| Account::new(client_id))
+    }
+
+    /// Applies a transaction to the model, returns whether the engine must apply it.
+    pub fn apply(&mut self, tx: &Transaction) -> bool {
+        let mut account = self.account(tx.client_id);
+        let known = self.txs.get(&tx.tx_id).copied();
+
+        let applied = match (tx.tx_type.clone(), known) {
+            (TransactionType::Deposit(_) | TransactionType::Withdrawal(_), Some(_)) => false,
+            (TransactionType::Deposit(_) | TransactionType::Withdrawal(_), None) if account.locked => false,
+            (TransactionType::Deposit(amount), None) => {
+                account.available += amount;
+                self.record(tx, amount, ModelState::Deposited);
+
+                true
+            }
+            (TransactionType::Withdrawal(amount), None) if account.available >= amount => {
+                account.available -= amount;
+                self.record(tx, amount, ModelState::Withdrawn);
+
+                true
+            }
+            (TransactionType::Withdrawal(_), None) => false,
+            // Only the client of a transaction can dispute it
+            (_, Some(known)) if known.client_id != tx.client_id => false,
+            (TransactionType::Dispute, _) if account.locked => false,
+            (TransactionType::Dispute, Some(known)) if
+                known.state == ModelState::Deposited && account.available >= known.amount
+            => {
+                account.available -= known.amount;
+                account.held += known.amount;
+                self.record(tx, known.amount, ModelState::Disputed);
+
+                true
+            }
+            (TransactionType::Resolve, Some(known)) if known.state == ModelState::Disputed => {
+                account.available += known.amount;
+                account.held -= known.amount;
+                self.record(tx, known.amount, ModelState::Resolved);
+
+                true
+            }
+            (TransactionType::Chargeback, Some(known)) if known.state == ModelState::Disputed => {
+                account.held -= known.amount;
+                account.locked = true;
+                self.record(tx, known.amount, ModelState::ChargedBack);
+
+                true
+            }
+            _ => false,
+        };
+
+        account.total = account.available + account.held;
+        self.accounts.insert(tx.client_id, account);
+
+        applied
+    }
+
+    fn record(&mut self, tx: &Transaction, amount: Decimal, state: ModelState) {
+        self.txs.insert(tx.tx_id, ModelTx { client_id: tx.client_id, amount, state });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The engine applied a transaction the oracle rejects, or the other way around
+    Outcome {
+        engine: Result<(), EngineError>,
+        oracle: bool,
+    },
+    Balances {
+        engine: Account,
+        oracle: Account,
+    },
+    Invariant(Violation),
+}
+
+/// Where the engine and the oracle first disagreed
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub seed: u64,
+    /// The position of the transaction in the generated stream, from 1
+    pub index: usize,
+    pub tx: Transaction,
+    pub mismatch: Mismatch,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed {}, transaction #{} ({} client {} tx {}",
+            self.seed,
+            self.index,
+            self.tx.tx_type.name(),
+            self.tx.client_id,
+            self.tx.tx_id
+        )?;
+
+        if let Some(amount) = self.tx.tx_type.amount() {
+            write!(f, " amount {}", amount)?;
+        }
+
+        write!(f, "): ")?;
+
+        match &self.mismatch {
+            Mismatch::Outcome { engine: Ok(()), .. } => write!(f, "applied by the engine, rejected by the oracle"),
+            Mismatch::Outcome { engine: Err(err), .. } => {
+                write!(f, "rejected by the engine ({}), applied by the oracle", err)
+            }
+            Mismatch::Balances { engine, oracle } => {
+                write!(
+                    f,
+                    "client {} is {}/{}/{}/{} in the engine, {}/{}/{}/{} in the oracle (available/held/total/locked)",
+                    engine.client_id,
+                    engine.available,
+                    engine.held,
+                    engine.total,
+                    engine.locked,
+                    oracle.available,
+                    oracle.held,
+                    oracle.total,
+                    oracle.locked
+                )
+            }
+            Mismatch::Invariant(violation) => write!(f, "{}", violation),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// The outcome of a simulation the engine went through without diverging
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulationReport {
+    pub seed: u64,
+    pub transactions: usize,
+    pub applied: usize,
+    pub rejected: usize,
+    pub locked_accounts: usize,
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed {}: {} transactions, {} applied, {} rejected, {} accounts locked",
+            self.seed,
+            self.transactions,
+            self.applied,
+            self.rejected,
+            self.locked_accounts
+        )
+    }
+}
+
+/// Runs `transactions` generated transactions over `clients` clients through a default engine and
+/// the oracle, comparing the outcome and the client's balances after each one, and the engine
+/// invariants now and then and at the end.
+pub fn simulate(seed: u64, transactions: usize, clients: ClientId) -> Result<SimulationReport, Box<Divergence>> {
+    let mut engine = Engine::new();
+    let mut oracle = Oracle::new();
+    let mut report = SimulationReport { seed, transactions, applied: 0, rejected: 0, locked_accounts: 0 };
+
+    let diverge = |index, tx: &Transaction, mismatch| Box::new(Divergence { seed, index, tx: tx.clone(), mismatch });
+
+    for (index, tx) in Generator::new(seed, clients).take(transactions).enumerate() {
+        let index = index + 1;
+
+        let expected = oracle.apply(&tx);
+        let result = engine.add_transaction(tx.clone());
+
+        if result.is_ok() != expected {
+            return Err(diverge(index, &tx, Mismatch::Outcome { engine: result, oracle: expected }));
+        }
+
+        match expected {
+            true => report.applied += 1,
+            false => report.rejected += 1,
+        }
+
+        let actual = engine.get_account(tx.client_id).cloned().unwrap_or_else(|| Account::new(tx.client_id));
+        let modeled = oracle.account(tx.client_id);
+
+        if actual != modeled {
+            return Err(diverge(index, &tx, Mismatch::Balances { engine: actual, oracle: modeled }));
+        }
+
+        if index.is_multiple_of(VERIFY_INTERVAL) || index == transactions {
+            engine.verify().map_err(|violation| diverge(index, &tx, Mismatch::Invariant(violation)))?;
+        }
+    }
+
+    report.locked_accounts = engine.accounts_iter().filter(|account| account.locked).count();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_generator_is_deterministic() {
+        let first: Vec<_> = Generator::new(7, 5).take(10_000).collect();
+        let second: Vec<_> = Generator::new(7, 5).take(10_000).collect();
+        let other: Vec<_> = Generator::new(8, 5).take(10_000).collect();
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert!(first.iter().all(|tx| (1..=5).contains(&tx.client_id)));
+
+        // Every kind of transaction shows up
+        for name in ["deposit", "withdrawal", "dispute", "resolve", "chargeback"] {
+            assert!(first.iter().any(|tx| tx.tx_type.name() == name), "no {}", name);
+        }
+    }
+
+    #[test]
+    fn test_oracle() {
+        let mut oracle = Oracle::new();
+
+        assert!(oracle.apply(&Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))));
+        assert!(!oracle.apply(&Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))));
+        assert!(!oracle.apply(&Transaction::new(1, 2, TransactionType::Withdrawal(dec!(11)))));
+        assert!(oracle.apply(&Transaction::new(1, 2, TransactionType::Withdrawal(dec!(4)))));
+        assert!(!oracle.apply(&Transaction::new(1, 1, TransactionType::Dispute)));
+        assert!(!oracle.apply(&Transaction::new(2, 1, TransactionType::Dispute)));
+        assert!(!oracle.apply(&Transaction::new(1, 2, TransactionType::Dispute)));
+
+        assert!(oracle.apply(&Transaction::new(1, 3, TransactionType::Deposit(dec!(5)))));
+        assert!(oracle.apply(&Transaction::new(1, 3, TransactionType::Dispute)));
+        assert!(!oracle.apply(&Transaction::new(1, 3, TransactionType::Dispute)));
+        assert!(oracle.apply(&Transaction::new(1, 3, TransactionType::Chargeback)));
+        assert!(!oracle.apply(&Transaction::new(1, 4, TransactionType::Deposit(dec!(1)))));
+
+        assert_eq!(
+            oracle.account(1),
+            Account { available: dec!(6), held: dec!(0), total: dec!(6), locked: true, ..Account::new(1) }
+        );
+        assert_eq!(oracle.account(2), Account::new(2));
+    }
+
+    #[test]
+    fn test_simulate() {
+        for seed in 0..5 {
+            let report = simulate(seed, 20_000, 20).unwrap();
+
+            assert_eq!(report.applied + report.rejected, 20_000);
+            assert!(report.applied > 0 && report.rejected > 0);
+        }
+    }
+
+    #[test]
+    fn test_divergence_display() {
+        let divergence = Divergence {
+            seed: 3,
+            index: 12,
+            tx: Transaction::new(1, 9, TransactionType::Withdrawal(dec!(2.5))),
+            mismatch: Mismatch::Outcome {
+                engine: Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(1), required: dec!(2.5) }),
+                oracle: true,
+            },
+        };
+
+        assert!(
+            divergence
+                .to_string()
+                .starts_with("seed 3, transaction #12 (withdrawal client 1 tx 9 amount 2.5): rejected by the engine (")
+        );
+    }
+}
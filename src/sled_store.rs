@@ -0,0 +1,518 @@
+use std::{ cell::RefCell, collections::{ BTreeMap, HashMap }, path::Path };
+
+use rust_decimal::Decimal;
+
+use crate::{
+    error::EngineError,
+    store::{ HistoryEntry, StateStore, TransactionInfo },
+    types::{ self, Account, ClientId },
+};
+
+const HISTORY_TREE: &str = "history";
+const ORDER_TREE: &str = "order";
+const DEFAULT_BATCH_SIZE: usize = 4096;
+const DEFAULT_CACHE_SIZE: usize = 65536;
+
+fn encode_info(info: TransactionInfo) -> u8 {
+    match info {
+        TransactionInfo::Regular => 0,
+        TransactionInfo::UnderDispute => 1,
+        TransactionInfo::Resolved => 2,
+        TransactionInfo::Withdrawal => 3,
+        TransactionInfo::Adjustment => 4,
+        TransactionInfo::Hold => 5,
+        TransactionInfo::Expired => 6,
+        TransactionInfo::OnHold => 7,
+        TransactionInfo::ChargedBack => 8,
+        TransactionInfo::Represented => 9,
+    }
+}
+
+fn decode_info(byte: u8) -> Option<TransactionInfo> {
+    let info = match byte {
+        0 => TransactionInfo::Regular,
+        1 => TransactionInfo::UnderDispute,
+        2 => TransactionInfo::Resolved,
+        3 => TransactionInfo::Withdrawal,
+        4 => TransactionInfo::Adjustment,
+        5 => TransactionInfo::Hold,
+        6 => TransactionInfo::Expired,
+        7 => TransactionInfo::OnHold,
+        8 => TransactionInfo::ChargedBack,
+        9 => TransactionInfo::Represented,
+        _ => return None,
+    };
+
+    Some(info)
+}
+
+// info (1 byte), client id (8 bytes, whatever the width of `ClientId`) and the amount as
+// serialized by rust_decimal (16 bytes)
+fn encode_entry(entry: &HistoryEntry) -> [u8; 25] {
+    let mut bytes = [0; 25];
+
+    bytes[0] = encode_info(entry.info);
+    bytes[1..9].copy_from_slice(&u64::from(entry.client_id).to_be_bytes());
+    bytes[9..].copy_from_slice(&entry.amount.serialize());
+
+    bytes
+}
+
+fn decode_entry(bytes: &[u8]) -> Option<HistoryEntry> {
+    if bytes.len() != 25 {
+        return None;
+    }
+
+    Some(HistoryEntry {
+        info: decode_info(bytes[0])?,
+        client_id: types::client_id(u64::from_be_bytes(bytes[1..9].try_into().ok()?)).ok()?,
+        amount: Decimal::deserialize(bytes[9..].try_into().ok()?),
+    })
+}
+
+// tx id (4 bytes), whether there's a timestamp (1 byte) and the timestamp (8 bytes)
+fn encode_order(tx_id: u32, timestamp: Option<u64>) -> [u8; 13] {
+    let mut bytes = [0; 13];
+
+    bytes[..4].copy_from_slice(&tx_id.to_be_bytes());
+    bytes[4] = u8::from(timestamp.is_some());
+    bytes[5..].copy_from_slice(&timestamp.unwrap_or_default().to_be_bytes());
+
+    bytes
+}
+
+fn decode_order(bytes: &[u8]) -> Option<(u32, Option<u64>)> {
+    if bytes.len() != 13 {
+        return None;
+    }
+
+    let tx_id = u32::from_be_bytes(bytes[..4].try_into().ok()?);
+    let timestamp = u64::from_be_bytes(bytes[5..].try_into().ok()?);
+
+    Some((tx_id, (bytes[4] == 1).then_some(timestamp)))
+}
+
+// Least recently used transactions are evicted first. Each use gets a new stamp, the oldest stamp
+// is the one to evict.
+struct Cache {
+    capacity: usize,
+    next_stamp: u64,
+    entries: HashMap<u32, (HistoryEntry, u64)>,
+    order: BTreeMap<u64, u32>,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Cache { capacity, next_stamp: 0, entries: HashMap::new(), order: BTreeMap::new() }
+    }
+
+    fn get(&mut self, tx_id: u32) -> Option<HistoryEntry> {
+        let entry = self.entries.get(&tx_id)?.0;
+
+        self.put(tx_id, entry);
+
+        Some(entry)
+    }
+
+    fn put(&mut self, tx_id: u32, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+
+        if let Some((_, old)) = self.entries.insert(tx_id, (entry, stamp)) {
+            self.order.remove(&old);
+        }
+
+        self.order.insert(stamp, tx_id);
+
+        if self.entries.len() > self.capacity {
+            if let Some((_, evicted)) = self.order.pop_first() {
+                self.entries.remove(&evicted);
+            }
+        }
+    }
+
+    fn remove(&mut self, tx_id: u32) {
+        if let Some((_, stamp)) = self.entries.remove(&tx_id) {
+            self.order.remove(&stamp);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// Keeps the history of transactions in a sled database on disk, for inputs whose history doesn't
+/// fit in memory. The accounts stay in memory, there are at most as many as client ids. The order of
+/// the history is kept in the database too, keyed by the position of each transaction.
+///
+/// Writes are buffered and applied to the database in batches, and the most recently used
+/// transactions are cached in memory. The database only holds the state of the current run, it
+/// is cleared when opened; snapshots are what carry the state from a run to the next.
+///
+/// A failing database (e.g. a full disk) is kept as the [`StateStore::failure`] of the store, the
+/// engine rejects the transaction it failed on and every later one with it.
+pub struct SledStore {
+    accounts: HashMap<ClientId, Account>,
+    history: sled::Tree,
+    order: sled::Tree,
+    next_order: u64,
+    // Writes not applied to the database yet, `None` for removals
+    pending: HashMap<u32, Option<HistoryEntry>>,
+    batch_size: usize,
+    cache: RefCell<Cache>,
+    tx_count: usize,
+    // The first failure of the database, reads fail too
+    failure: RefCell<Option<EngineError>>,
+}
+
+impl SledStore {
+    /// Opens the database in `dir`, creating it if needed, and drops what a previous run left.
+    pub fn open<P: AsRef<Path>>(dir: P) -> sled::Result<Self> {
+        let db = sled::open(dir)?;
+        let history = db.open_tree(HISTORY_TREE)?;
+
+        let order = db.open_tree(ORDER_TREE)?;
+
+        history.clear()?;
+        order.clear()?;
+
+        Ok(SledStore {
+            accounts: HashMap::new(),
+            history,
+            order,
+            next_order: 0,
+            pending: HashMap::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            cache: RefCell::new(Cache::new(DEFAULT_CACHE_SIZE)),
+            tx_count: 0,
+            failure: RefCell::new(None),
+        })
+    }
+
+    /// How many writes are buffered before being applied to the database.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// How many transactions are cached in memory.
+    pub fn cache_size(mut self, cache_size: usize) -> Self {
+        self.cache = RefCell::new(Cache::new(cache_size));
+        self
+    }
+
+    /// Applies the buffered writes to the database.
+    pub fn flush(&mut self) -> sled::Result<()> {
+        let mut batch = sled::Batch::default();
+
+        for (tx_id, entry) in self.pending.drain() {
+            match entry {
+                Some(entry) => batch.insert(&tx_id.to_be_bytes(), &encode_entry(&entry)),
+                None => batch.remove(&tx_id.to_be_bytes()),
+            }
+        }
+
+        self.history.apply_batch(batch)
+    }
+
+    // Keeps the first failure, the state is already untrusted after it
+    fn fail(&self, failure: String) {
+        log::error!("The state store failed: {}", failure);
+
+        self.failure.borrow_mut().get_or_insert(EngineError::Storage(failure));
+    }
+
+    fn read(&self, tx_id: u32) -> Option<HistoryEntry> {
+        let bytes = match self.history.get(tx_id.to_be_bytes()) {
+            Ok(bytes) => bytes?,
+            Err(err) => {
+                self.fail(format!("failed to read transaction {}: {}", tx_id, err));
+                return None;
+            }
+        };
+
+        let entry = decode_entry(&bytes);
+
+        if entry.is_none() {
+            self.fail(format!("transaction {} is corrupted", tx_id));
+        }
+
+        entry
+    }
+
+    fn write(&mut self, tx_id: u32, entry: Option<HistoryEntry>) {
+        self.pending.insert(tx_id, entry);
+
+        if self.pending.len() >= self.batch_size {
+            if let Err(err) = self.flush() {
+                self.fail(format!("failed to write the history: {}", err));
+            }
+        }
+    }
+
+    fn read_order(&self, bytes: sled::Result<sled::IVec>) -> Option<(u32, Option<u64>)> {
+        let order = match bytes {
+            Ok(bytes) => decode_order(&bytes),
+            Err(err) => {
+                self.fail(format!("failed to read the order of the history: {}", err));
+                return None;
+            }
+        };
+
+        if order.is_none() {
+            self.fail("the order of the history is corrupted".to_string());
+        }
+
+        order
+    }
+}
+
+impl StateStore for SledStore {
+    fn get_account(&self, client_id: ClientId) -> Option<&Account> {
+        self.accounts.get(&client_id)
+    }
+
+    fn upsert_account(&mut self, account: Account) {
+        self.accounts.insert(account.client_id, account);
+    }
+
+    fn remove_account(&mut self, client_id: ClientId) -> Option<Account> {
+        self.accounts.remove(&client_id)
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.accounts.values())
+    }
+
+    fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    fn get_tx(&self, tx_id: u32) -> Option<HistoryEntry> {
+        if let Some(entry) = self.pending.get(&tx_id) {
+            return *entry;
+        }
+
+        if let Some(entry) = self.cache.borrow_mut().get(tx_id) {
+            return Some(entry);
+        }
+
+        let entry = self.read(tx_id)?;
+        self.cache.borrow_mut().put(tx_id, entry);
+
+        Some(entry)
+    }
+
+    fn put_tx(&mut self, tx_id: u32, entry: HistoryEntry) {
+        if !self.has_tx(tx_id) {
+            self.tx_count += 1;
+        }
+
+        self.cache.get_mut().put(tx_id, entry);
+        self.write(tx_id, Some(entry));
+    }
+
+    fn remove_tx(&mut self, tx_id: u32) {
+        if self.has_tx(tx_id) {
+            self.tx_count -= 1;
+        }
+
+        self.cache.get_mut().remove(tx_id);
+        self.write(tx_id, None);
+    }
+
+    fn tx_count(&self) -> usize {
+        self.tx_count
+    }
+
+    fn push_order(&mut self, tx_id: u32, timestamp: Option<u64>) {
+        let inserted = self.order.insert(self.next_order.to_be_bytes(), &encode_order(tx_id, timestamp));
+
+        if let Err(err) = inserted {
+            self.fail(format!("failed to write the order of the history: {}", err));
+        }
+
+        self.next_order += 1;
+    }
+
+    // Stops at the first entry that can't be read
+    fn order(&self) -> Box<dyn Iterator<Item = (u32, Option<u64>)> + '_> {
+        Box::new(self.order.iter().values().map_while(|bytes| self.read_order(bytes)))
+    }
+
+    fn compact_order(&mut self) {
+        let mut batch = sled::Batch::default();
+
+        for item in self.order.iter() {
+            let (key, bytes) = match item {
+                Ok(item) => item,
+                Err(err) => return self.fail(format!("failed to read the order of the history: {}", err)),
+            };
+            let Some((tx_id, _)) = self.read_order(Ok(bytes)) else {
+                return;
+            };
+
+            if !self.has_tx(tx_id) {
+                batch.remove(key);
+            }
+        }
+
+        if let Err(err) = self.order.apply_batch(batch) {
+            self.fail(format!("failed to write the order of the history: {}", err));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.accounts.clear();
+        self.pending.clear();
+        self.cache.get_mut().clear();
+        self.next_order = 0;
+        self.tx_count = 0;
+
+        if let Err(err) = self.history.clear().and_then(|()| self.order.clear()) {
+            self.fail(format!("failed to clear the database: {}", err));
+        }
+    }
+
+    fn failure(&self) -> Option<EngineError> {
+        self.failure.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("transaction-engine-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let entry = HistoryEntry { info: TransactionInfo::OnHold, client_id: 513, amount: dec!(-1.2345) };
+
+        assert_eq!(decode_entry(&encode_entry(&entry)), Some(entry));
+        assert_eq!(decode_entry(&[10; 25]), None);
+        assert_eq!(decode_entry(&[0; 3]), None);
+        assert_eq!(decode_entry(&[0; 19]), None);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let entry = HistoryEntry { info: TransactionInfo::Regular, client_id: 1, amount: dec!(1) };
+        let mut cache = Cache::new(2);
+
+        cache.put(1, entry);
+        cache.put(2, entry);
+        cache.get(1);
+        cache.put(3, entry);
+
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(3).is_some());
+    }
+
+    #[test]
+    fn test_sled_store() {
+        let dir = temp_dir("sled-store");
+        let mut store = SledStore::open(&dir).unwrap().batch_size(2).cache_size(1);
+        let entry = HistoryEntry { info: TransactionInfo::Regular, client_id: 1, amount: dec!(2) };
+
+        store.upsert_account(Account { available: dec!(2), total: dec!(2), ..Account::new(1) });
+
+        for tx_id in 1..=5 {
+            store.put_tx(tx_id, entry);
+        }
+
+        store.put_tx(2, HistoryEntry { info: TransactionInfo::UnderDispute, ..entry });
+        store.remove_tx(3);
+        store.remove_tx(9);
+
+        assert_eq!(store.tx_count(), 4);
+        assert_eq!(store.get_tx(1), Some(entry));
+        assert_eq!(store.get_tx(2).unwrap().info, TransactionInfo::UnderDispute);
+        assert!(!store.has_tx(3));
+        assert_eq!(store.get_account(1).unwrap().available, dec!(2));
+
+        for tx_id in [1, 3, 2] {
+            store.push_order(tx_id, Some(u64::from(tx_id) * 10));
+        }
+        store.push_order(4, None);
+        store.compact_order();
+
+        assert_eq!(store.order().collect::<Vec<_>>(), vec![(1, Some(10)), (2, Some(20)), (4, None)]);
+        assert_eq!(decode_order(&encode_order(7, Some(0))), Some((7, Some(0))));
+        assert_eq!(decode_order(&[0; 12]), None);
+
+        store.flush().unwrap();
+        assert_eq!(store.history.len(), 4);
+        drop(store);
+
+        // What a previous run left is dropped
+        let store = SledStore::open(&dir).unwrap();
+        assert_eq!(store.tx_count(), 0);
+        assert!(!store.has_tx(1));
+        assert_eq!(store.order().count(), 0);
+
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_engine_on_sled_store() {
+        use crate::{ Engine, Transaction, TransactionType };
+
+        let dir = temp_dir("sled-engine");
+        let store = SledStore::open(&dir).unwrap().batch_size(1).cache_size(0);
+        let mut engine = Engine::builder().build_with_store(store);
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5)))).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)).unwrap();
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Chargeback)).unwrap();
+        assert!(engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5)))).is_err());
+
+        let account = engine.get_account(1).unwrap();
+        assert_eq!(account.total, dec!(5));
+        assert!(account.locked);
+
+        drop(engine);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failure_rejects_transactions() {
+        use crate::{ Engine, EngineError, Transaction, TransactionType };
+
+        let dir = temp_dir("sled-failure");
+        let store = SledStore::open(&dir).unwrap().batch_size(1).cache_size(0);
+        let history = store.history.clone();
+        let mut engine = Engine::builder().build_with_store(store);
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(10)))).unwrap();
+        history.insert(1u32.to_be_bytes(), &[0; 19]).unwrap();
+
+        let failure = EngineError::Storage("transaction 1 is corrupted".to_string());
+
+        assert_eq!(engine.add_transaction(Transaction::new(1, 1, TransactionType::Dispute)), Err(failure.clone()));
+        assert_eq!(
+            engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(1)))),
+            Err(failure.clone())
+        );
+        assert_eq!(engine.store_failure(), Some(failure));
+        assert!(engine.get_account(2).is_none());
+
+        drop(engine);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
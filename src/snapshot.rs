@@ -0,0 +1,803 @@
+use std::{ collections::BTreeMap, fmt, fs::File, io::{ self, BufReader, BufWriter, Read }, path::Path };
+
+use rust_decimal::Decimal;
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+    engine::Engine,
+    policy::RiskTier,
+    scheduler::{ ReleaseKind, ScheduledRelease },
+    store::StateStore,
+    types::{ self, Account, ClientId, ClientIdOutOfRange },
+};
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    Json(serde_json::Error),
+    DuplicateClient(ClientId),
+    /// The total of the client doesn't match available + held
+    Inconsistent(ClientId),
+    /// Written by a release with a format this one doesn't know, newer or not a snapshot at all
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// A client id wider than the ids of this build, see [`ClientId`]
+    ClientId(ClientIdOutOfRange),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "{}", err),
+            SnapshotError::Encoding(err) => write!(f, "invalid snapshot: {}", err),
+            SnapshotError::Json(err) => write!(f, "invalid json snapshot: {}", err),
+            SnapshotError::DuplicateClient(client_id) => {
+                write!(f, "client {} appears more than once", client_id)
+            }
+            SnapshotError::Inconsistent(client_id) => {
+                write!(f, "client {} total does not match available + held", client_id)
+            }
+            SnapshotError::UnsupportedVersion { found, supported } => {
+                write!(f, "unsupported format version {}, this release reads versions 1 to {}", found, supported)
+            }
+            SnapshotError::ClientId(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(err: bincode::Error) -> Self {
+        SnapshotError::Encoding(err)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(err: serde_json::Error) -> Self {
+        SnapshotError::Json(err)
+    }
+}
+
+impl From<ClientIdOutOfRange> for SnapshotError {
+    fn from(err: ClientIdOutOfRange) -> Self {
+        SnapshotError::ClientId(err)
+    }
+}
+
+// Balances and risk tiers of every client at the end of a run, the clock time it was taken at
+// is kept so readers know how old it is. The pending releases (holds, dispute expiries) are kept
+// so a run restoring it honours them. Deleted accounts are kept apart from the others, they are
+// only restored, never shown.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub taken_at: Option<u64>,
+    pub accounts: Vec<Account>,
+    pub tiers: BTreeMap<ClientId, RiskTier>,
+    pub scheduled: Vec<ScheduledRelease>,
+    pub deleted: Vec<Account>,
+}
+
+/// The version of the binary snapshots written by this release. Older ones are migrated to it
+/// when read, newer ones are refused with [`SnapshotError::UnsupportedVersion`].
+pub const SNAPSHOT_VERSION: u16 = 3;
+
+const MAGIC: &[u8; 8] = b"TXSNAPSH";
+
+// Version 1, the snapshots written before they had a header: the parts added over time were
+// appended after `Encoded`. The releases follow it, snapshots taken before releases were kept end
+// without them, then the deleted accounts as a `Vec<EncodedAccount>` snapshots taken before
+// deletes were kept lack.
+struct SnapshotV1 {
+    encoded: Encoded,
+    releases: Vec<EncodedRelease<u16>>,
+    deleted: Vec<EncodedAccount<u16>>,
+}
+
+// Decimals are stored in their 16 byte binary form, the serde form of `Account` is meant for csv.
+#[derive(Serialize, Deserialize)]
+struct Encoded {
+    taken_at: Option<u64>,
+    accounts: Vec<EncodedAccount<u16>>,
+    tiers: Vec<(u16, RiskTier)>,
+}
+
+// Version 2, after the header: every part in one record, a later version changing the layout of
+// the accounts or releases adds its own record and a migration from this one.
+#[derive(Serialize, Deserialize)]
+struct SnapshotV2 {
+    taken_at: Option<u64>,
+    accounts: Vec<EncodedAccount<u16>>,
+    tiers: Vec<(u16, RiskTier)>,
+    releases: Vec<EncodedRelease<u16>>,
+    deleted: Vec<EncodedAccount<u16>>,
+}
+
+// Version 3, the layout of version 2 with the client ids 64 bits wide whatever the width of
+// `ClientId`, so a build with 16 bit ids reads the snapshots of one with 32 bit ids as long as the
+// ids fit.
+#[derive(Serialize, Deserialize)]
+struct SnapshotV3 {
+    taken_at: Option<u64>,
+    accounts: Vec<EncodedAccount>,
+    tiers: Vec<(u64, RiskTier)>,
+    releases: Vec<EncodedRelease>,
+    deleted: Vec<EncodedAccount>,
+}
+
+impl From<SnapshotV1> for SnapshotV2 {
+    fn from(v1: SnapshotV1) -> Self {
+        SnapshotV2 {
+            taken_at: v1.encoded.taken_at,
+            accounts: v1.encoded.accounts,
+            tiers: v1.encoded.tiers,
+            releases: v1.releases,
+            deleted: v1.deleted,
+        }
+    }
+}
+
+impl From<SnapshotV2> for SnapshotV3 {
+    fn from(v2: SnapshotV2) -> Self {
+        SnapshotV3 {
+            taken_at: v2.taken_at,
+            accounts: v2.accounts.into_iter().map(EncodedAccount::widen).collect(),
+            tiers: v2.tiers
+                .into_iter()
+                .map(|(client_id, tier)| (client_id.into(), tier))
+                .collect(),
+            releases: v2.releases.into_iter().map(EncodedRelease::widen).collect(),
+            deleted: v2.deleted.into_iter().map(EncodedAccount::widen).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedRelease<C = u64> {
+    at: u64,
+    kind: ReleaseKind,
+    tx_id: u32,
+    client_id: C,
+    amount: [u8; 16],
+}
+
+impl EncodedRelease<u16> {
+    fn widen(self) -> EncodedRelease {
+        EncodedRelease {
+            at: self.at,
+            kind: self.kind,
+            tx_id: self.tx_id,
+            client_id: self.client_id.into(),
+            amount: self.amount,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedAccount<C = u64> {
+    client_id: C,
+    available: [u8; 16],
+    held: [u8; 16],
+    total: [u8; 16],
+    locked: bool,
+}
+
+impl EncodedAccount<u16> {
+    fn widen(self) -> EncodedAccount {
+        EncodedAccount {
+            client_id: self.client_id.into(),
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        }
+    }
+}
+
+// The portable form, meant to be read and edited by hand. Decimals are strings with every decimal
+// place kept, unlike the rounded ones of the output.
+#[derive(Serialize, Deserialize)]
+struct JsonSnapshot {
+    // The version of the binary format it matches, files written before it was added are version 1
+    #[serde(default = "json_v1")]
+    version: u16,
+    #[serde(default)]
+    taken_at: Option<u64>,
+    accounts: Vec<JsonAccount>,
+    #[serde(default)]
+    tiers: BTreeMap<ClientId, RiskTier>,
+    #[serde(default)]
+    scheduled: Vec<ScheduledRelease>,
+    #[serde(default)]
+    deleted: Vec<JsonAccount>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonAccount {
+    // Read wide to tell an id out of range from a malformed one
+    client: u64,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    #[serde(default)]
+    locked: bool,
+}
+
+impl Snapshot {
+    pub fn capture<S: StateStore>(engine: &Engine<S>) -> Self {
+        let mut client_ids: Vec<ClientId> = engine.client_ids().collect();
+        client_ids.sort_unstable();
+
+        let mut deleted: Vec<Account> = engine.deleted_accounts().cloned().collect();
+        deleted.sort_unstable_by_key(|account| account.client_id);
+
+        Snapshot {
+            taken_at: engine.now(),
+            accounts: client_ids
+                .iter()
+                .filter_map(|client_id| engine.get_account(*client_id).cloned())
+                .collect(),
+            tiers: client_ids
+                .iter()
+                .chain(deleted.iter().map(|account| &account.client_id))
+                .map(|client_id| (*client_id, engine.risk_tier(*client_id)))
+                .filter(|(_, tier)| *tier != RiskTier::default())
+                .collect(),
+            scheduled: engine.scheduled_releases().cloned().collect(),
+            deleted,
+        }
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Option<&Account> {
+        self.accounts
+            .binary_search_by_key(&client_id, |account| account.client_id)
+            .ok()
+            .map(|index| &self.accounts[index])
+    }
+
+    pub fn risk_tier(&self, client_id: ClientId) -> RiskTier {
+        self.tiers.get(&client_id).copied().unwrap_or_default()
+    }
+
+    pub fn write<W: io::Write>(&self, mut writer: W) -> Result<(), SnapshotError> {
+        let encoded = SnapshotV3 {
+            taken_at: self.taken_at,
+            accounts: self.accounts.iter().map(encode_account).collect(),
+            tiers: self.tiers
+                .iter()
+                .map(|(client_id, tier)| (u64::from(*client_id), *tier))
+                .collect(),
+            releases: self.scheduled
+                .iter()
+                .map(|release| EncodedRelease {
+                    at: release.at,
+                    kind: release.kind,
+                    tx_id: release.tx_id,
+                    client_id: u64::from(release.client_id),
+                    amount: release.amount.serialize(),
+                })
+                .collect(),
+            deleted: self.deleted.iter().map(encode_account).collect(),
+        };
+
+        write_header(&mut writer, MAGIC, SNAPSHOT_VERSION)?;
+        bincode::serialize_into(&mut writer, &encoded)?;
+
+        Ok(())
+    }
+
+    /// Reads a snapshot of any version up to [`SNAPSHOT_VERSION`], migrating the older ones.
+    pub fn read<R: io::Read>(reader: R) -> Result<Self, SnapshotError> {
+        let (version, mut reader) = read_header(reader, MAGIC)?;
+
+        let encoded: SnapshotV3 = match version {
+            1 => SnapshotV2::from(SnapshotV1 {
+                encoded: bincode::deserialize_from(&mut reader)?,
+                releases: read_optional(&mut reader)?,
+                deleted: read_optional(&mut reader)?,
+            }).into(),
+            2 => bincode::deserialize_from::<_, SnapshotV2>(&mut reader)?.into(),
+            3 => bincode::deserialize_from(&mut reader)?,
+            found => return Err(SnapshotError::UnsupportedVersion { found, supported: SNAPSHOT_VERSION }),
+        };
+
+        let mut accounts = encoded.accounts.into_iter().map(decode_account).collect::<Result<Vec<_>, _>>()?;
+        let mut deleted = encoded.deleted.into_iter().map(decode_account).collect::<Result<Vec<_>, _>>()?;
+
+        accounts.sort_unstable_by_key(|account| account.client_id);
+        deleted.sort_unstable_by_key(|account| account.client_id);
+
+        Ok(Snapshot {
+            taken_at: encoded.taken_at,
+            accounts,
+            tiers: encoded.tiers
+                .into_iter()
+                .map(|(client_id, tier)| Ok((types::client_id(client_id)?, tier)))
+                .collect::<Result<_, SnapshotError>>()?,
+            scheduled: encoded.releases
+                .into_iter()
+                .map(|release| {
+                    Ok(ScheduledRelease {
+                        at: release.at,
+                        kind: release.kind,
+                        tx_id: release.tx_id,
+                        client_id: types::client_id(release.client_id)?,
+                        amount: Decimal::deserialize(release.amount),
+                    })
+                })
+                .collect::<Result<_, SnapshotError>>()?,
+            deleted,
+        })
+    }
+
+    pub fn write_json<W: io::Write>(&self, mut writer: W) -> Result<(), SnapshotError> {
+        let json = JsonSnapshot {
+            version: SNAPSHOT_VERSION,
+            taken_at: self.taken_at,
+            accounts: self.accounts.iter().map(to_json).collect(),
+            tiers: self.tiers.clone(),
+            scheduled: self.scheduled.clone(),
+            deleted: self.deleted.iter().map(to_json).collect(),
+        };
+
+        serde_json::to_writer_pretty(&mut writer, &json)?;
+        writer.write_all(b"\n")?;
+
+        Ok(())
+    }
+
+    // Hand-edited files are checked like opening balances: one entry per client, deleted or not,
+    // and consistent totals.
+    pub fn read_json<R: io::Read>(reader: R) -> Result<Self, SnapshotError> {
+        let json: JsonSnapshot = serde_json::from_reader(reader)?;
+
+        if json.version == 0 || json.version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion { found: json.version, supported: SNAPSHOT_VERSION });
+        }
+
+        let mut accounts = json.accounts.into_iter().map(from_json).collect::<Result<Vec<_>, _>>()?;
+        let mut deleted = json.deleted.into_iter().map(from_json).collect::<Result<Vec<_>, _>>()?;
+
+        let mut client_ids: Vec<ClientId> = accounts
+            .iter()
+            .chain(deleted.iter())
+            .map(|account| account.client_id)
+            .collect();
+        client_ids.sort_unstable();
+
+        if let Some(pair) = client_ids.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(SnapshotError::DuplicateClient(pair[0]));
+        }
+
+        accounts.sort_unstable_by_key(|account| account.client_id);
+        deleted.sort_unstable_by_key(|account| account.client_id);
+
+        Ok(Snapshot {
+            taken_at: json.taken_at,
+            accounts,
+            tiers: json.tiers,
+            scheduled: json.scheduled,
+            deleted,
+        })
+    }
+
+    /// Writes the portable JSON form when the path ends in `.json`, the binary one otherwise.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        let writer = BufWriter::new(File::create(&path)?);
+
+        match is_json(path.as_ref()) {
+            true => self.write_json(writer),
+            false => self.write(writer),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        let reader = BufReader::new(File::open(&path)?);
+
+        match is_json(path.as_ref()) {
+            true => Snapshot::read_json(reader),
+            false => Snapshot::read(reader),
+        }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "json")
+}
+
+fn encode_account(account: &Account) -> EncodedAccount {
+    EncodedAccount {
+        client_id: account.client_id.into(),
+        available: account.available.serialize(),
+        held: account.held.serialize(),
+        total: account.total.serialize(),
+        locked: account.locked,
+    }
+}
+
+fn decode_account(account: EncodedAccount) -> Result<Account, SnapshotError> {
+    Ok(Account {
+        client_id: types::client_id(account.client_id)?,
+        available: Decimal::deserialize(account.available),
+        held: Decimal::deserialize(account.held),
+        total: Decimal::deserialize(account.total),
+        locked: account.locked,
+    })
+}
+
+fn to_json(account: &Account) -> JsonAccount {
+    JsonAccount {
+        client: account.client_id.into(),
+        available: account.available,
+        held: account.held,
+        total: account.total,
+        locked: account.locked,
+    }
+}
+
+fn from_json(account: JsonAccount) -> Result<Account, SnapshotError> {
+    let client_id = types::client_id(account.client)?;
+
+    match account.available + account.held == account.total {
+        true => Ok(Account {
+            client_id,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+        }),
+        false => Err(SnapshotError::Inconsistent(client_id)),
+    }
+}
+
+fn json_v1() -> u16 {
+    1
+}
+
+/// Writes the header of a versioned binary file: its magic bytes, then the version of its layout.
+pub(crate) fn write_header<W: io::Write>(mut writer: W, magic: &[u8; 8], version: u16) -> Result<(), SnapshotError> {
+    writer.write_all(magic)?;
+    bincode::serialize_into(writer, &version)?;
+
+    Ok(())
+}
+
+// The rest of a versioned file once its header is read, with the bytes of the header given back
+// to the files that have none
+pub(crate) type Body<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// Reads the header written by [`write_header`]. Files written before they had one start right
+/// with their content, they are version 1 and the bytes read looking for the magic are given back
+/// ahead of the rest.
+pub(crate) fn read_header<R: io::Read>(
+    mut reader: R,
+    magic: &[u8; 8]
+) -> Result<(u16, Body<R>), SnapshotError> {
+    let mut start = Vec::with_capacity(magic.len());
+    reader.by_ref().take(magic.len() as u64).read_to_end(&mut start)?;
+
+    if start != magic {
+        return Ok((1, io::Cursor::new(start).chain(reader)));
+    }
+
+    let version: u16 = bincode::deserialize_from(&mut reader)?;
+
+    Ok((version, io::Cursor::new(vec![]).chain(reader)))
+}
+
+// The parts appended to the version 1 format over time, missing from older snapshots
+fn read_optional<T, R>(reader: R) -> Result<Vec<T>, SnapshotError>
+    where T: serde::de::DeserializeOwned, R: io::Read
+{
+    match bincode::deserialize_from(reader) {
+        Ok(items) => Ok(items),
+        Err(err) if is_eof(&err) => Ok(vec![]),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn is_eof(err: &bincode::Error) -> bool {
+    matches!(err.as_ref(), bincode::ErrorKind::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{ policy::TierPolicy, types::{ Transaction, TransactionType } };
+
+    // An account as versions 1 and 2 stored it, with a 16 bit client id
+    fn encode_v2(account: &Account) -> EncodedAccount<u16> {
+        let EncodedAccount { client_id, available, held, total, locked } = encode_account(account);
+
+        EncodedAccount { client_id: client_id as u16, available, held, total, locked }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut engine = Engine::builder().policy(Box::new(TierPolicy::default())).build();
+
+        let _ = engine.add_transaction(
+            Transaction::new(2, 1, TransactionType::Deposit(dec!(10.1234))).with_timestamp(100)
+        );
+        let _ = engine.add_transaction(Transaction::new(1, 2, TransactionType::Deposit(dec!(5))));
+        let _ = engine.add_transaction(Transaction::new(2, 1, TransactionType::Dispute));
+        let _ = engine.add_transaction(Transaction::new(2, 1, TransactionType::Chargeback));
+
+        let snapshot = Snapshot::capture(&engine);
+        assert_eq!(snapshot.taken_at, Some(100));
+        assert!(snapshot.account(2).unwrap().locked);
+        assert_eq!(snapshot.risk_tier(2), RiskTier::High);
+        assert_eq!(snapshot.risk_tier(1), RiskTier::Standard);
+
+        let mut bytes = vec![];
+        snapshot.write(&mut bytes).unwrap();
+
+        assert_eq!(Snapshot::read(bytes.as_slice()).unwrap(), snapshot);
+        assert_eq!(snapshot.accounts, engine.get_accounts());
+    }
+
+    #[test]
+    fn test_scheduled_round_trip() {
+        let mut engine = Engine::builder().deposit_hold(Duration::from_secs(50)).build();
+
+        engine.add_transaction(
+            Transaction::new(1, 1, TransactionType::Deposit(dec!(3))).with_timestamp(100)
+        ).unwrap();
+
+        let snapshot = Snapshot::capture(&engine);
+        assert_eq!(snapshot.scheduled, vec![ScheduledRelease {
+            at: 150,
+            kind: ReleaseKind::DepositHold,
+            tx_id: 1,
+            client_id: 1,
+            amount: dec!(3),
+        }]);
+
+        let mut bytes = vec![];
+        snapshot.write(&mut bytes).unwrap();
+
+        let restored = Snapshot::read(bytes.as_slice()).unwrap();
+        assert_eq!(restored, snapshot);
+
+        // The hold survives the restart and is released on time
+        let mut engine = Engine::builder()
+            .opening_balances(restored.accounts)
+            .scheduled_releases(restored.scheduled)
+            .build();
+
+        engine.add_transaction(
+            Transaction::new(2, 2, TransactionType::Deposit(dec!(1))).with_timestamp(150)
+        ).unwrap();
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(3));
+
+        // Snapshots written before releases were persisted end with the accounts, without even the
+        // lengths of the releases and the deleted accounts
+        let old = Snapshot { scheduled: vec![], ..snapshot };
+        let bytes = bincode::serialize(&Encoded {
+            taken_at: old.taken_at,
+            accounts: old.accounts.iter().map(encode_v2).collect(),
+            tiers: vec![],
+        }).unwrap();
+
+        assert_eq!(Snapshot::read(bytes.as_slice()).unwrap(), old);
+    }
+
+    #[test]
+    fn test_read_v1() {
+        let snapshot = Snapshot {
+            taken_at: Some(100),
+            accounts: vec![Account { available: dec!(1.5), total: dec!(1.5), ..Account::new(1) }],
+            tiers: BTreeMap::from([(1, RiskTier::High)]),
+            scheduled: vec![ScheduledRelease {
+                at: 500,
+                kind: ReleaseKind::WithdrawalHold,
+                tx_id: 7,
+                client_id: 1,
+                amount: dec!(2),
+            }],
+            deleted: vec![Account { locked: true, ..Account::new(2) }],
+        };
+
+        // The layout before the header, the parts one after the other
+        let mut bytes = bincode::serialize(&Encoded {
+            taken_at: snapshot.taken_at,
+            accounts: snapshot.accounts.iter().map(encode_v2).collect(),
+            tiers: vec![(1, RiskTier::High)],
+        }).unwrap();
+        bincode::serialize_into(&mut bytes, &vec![EncodedRelease::<u16> {
+            at: 500,
+            kind: ReleaseKind::WithdrawalHold,
+            tx_id: 7,
+            client_id: 1,
+            amount: dec!(2).serialize(),
+        }]).unwrap();
+        bincode::serialize_into(&mut bytes, &snapshot.deleted.iter().map(encode_v2).collect::<Vec<_>>()).unwrap();
+
+        assert_eq!(Snapshot::read(bytes.as_slice()).unwrap(), snapshot);
+
+        // Version 2, the same parts with a header
+        let mut v2 = vec![];
+        write_header(&mut v2, MAGIC, 2).unwrap();
+        bincode::serialize_into(&mut v2, &SnapshotV2 {
+            taken_at: snapshot.taken_at,
+            accounts: snapshot.accounts.iter().map(encode_v2).collect(),
+            tiers: vec![(1, RiskTier::High)],
+            releases: vec![EncodedRelease {
+                at: 500,
+                kind: ReleaseKind::WithdrawalHold,
+                tx_id: 7,
+                client_id: 1,
+                amount: dec!(2).serialize(),
+            }],
+            deleted: snapshot.deleted.iter().map(encode_v2).collect(),
+        }).unwrap();
+
+        assert_eq!(Snapshot::read(v2.as_slice()).unwrap(), snapshot);
+
+        // Saved again, it's written in the current version
+        let mut current = vec![];
+        snapshot.write(&mut current).unwrap();
+
+        assert!(current.starts_with(MAGIC));
+        assert_eq!(read_header(current.as_slice(), MAGIC).unwrap().0, SNAPSHOT_VERSION);
+        assert_eq!(Snapshot::read(current.as_slice()).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_read_unsupported_version() {
+        let mut bytes = vec![];
+        Snapshot::default().write(&mut bytes).unwrap();
+        bytes[MAGIC.len()] = 4;
+
+        let err = Snapshot::read(bytes.as_slice()).unwrap_err();
+        assert!(matches!(err, SnapshotError::UnsupportedVersion { found: 4, supported: SNAPSHOT_VERSION }));
+        assert_eq!(err.to_string(), "unsupported format version 4, this release reads versions 1 to 3");
+
+        let json = r#"{"version": 4, "accounts": []}"#;
+        assert!(matches!(
+            Snapshot::read_json(json.as_bytes()),
+            Err(SnapshotError::UnsupportedVersion { found: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn test_client_id_width() {
+        let snapshot = Snapshot {
+            accounts: vec![Account { available: dec!(1), total: dec!(1), ..Account::new(ClientId::MAX) }],
+            ..Snapshot::default()
+        };
+
+        let mut bytes = vec![];
+        snapshot.write(&mut bytes).unwrap();
+        assert_eq!(Snapshot::read(bytes.as_slice()).unwrap(), snapshot);
+
+        let wide = u64::from(ClientId::MAX) + 1;
+        let json = format!(r#"{{"accounts": [{{"client": {}, "available": "0", "held": "0", "total": "0"}}]}}"#, wide);
+
+        let err = Snapshot::read_json(json.as_bytes()).unwrap_err();
+        assert!(matches!(err, SnapshotError::ClientId(ClientIdOutOfRange(id)) if id == wide));
+        assert_eq!(err.to_string(), format!("client id {} is out of range, the largest is {}", wide, ClientId::MAX));
+    }
+
+    #[test]
+    fn test_deleted_round_trip() {
+        let mut engine = Engine::builder().allow_deletes().build();
+
+        engine.add_transaction(Transaction::new(1, 1, TransactionType::Deposit(dec!(3)))).unwrap();
+        engine.add_transaction(Transaction::new(2, 2, TransactionType::Deposit(dec!(4)))).unwrap();
+        engine.add_transaction(Transaction::new(2, 3, TransactionType::Delete)).unwrap();
+
+        let snapshot = Snapshot::capture(&engine);
+        assert_eq!(snapshot.accounts, vec![Account { available: dec!(3), total: dec!(3), ..Account::new(1) }]);
+        assert_eq!(snapshot.deleted, vec![Account { available: dec!(4), total: dec!(4), ..Account::new(2) }]);
+
+        let mut bytes = vec![];
+        snapshot.write(&mut bytes).unwrap();
+
+        let restored = Snapshot::read(bytes.as_slice()).unwrap();
+        assert_eq!(restored, snapshot);
+
+        // The account stays hidden after the restart until it's restored
+        let mut engine = Engine::builder()
+            .allow_deletes()
+            .opening_balances(restored.accounts)
+            .deleted_accounts(restored.deleted)
+            .build();
+
+        assert_eq!(engine.get_account(2), None);
+        engine.add_transaction(Transaction::new(2, 4, TransactionType::Restore)).unwrap();
+        assert_eq!(engine.get_account(2).unwrap().available, dec!(4));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let snapshot = Snapshot {
+            taken_at: Some(100),
+            accounts: vec![
+                Account { available: dec!(1.23456), total: dec!(1.23456), ..Account::new(1) },
+                Account { held: dec!(2), total: dec!(2), locked: true, ..Account::new(3) }
+            ],
+            tiers: BTreeMap::from([(3, RiskTier::High)]),
+            scheduled: vec![ScheduledRelease {
+                at: 500,
+                kind: ReleaseKind::DisputeExpiry,
+                tx_id: 7,
+                client_id: 3,
+                amount: dec!(2),
+            }],
+            deleted: vec![Account { available: dec!(4), total: dec!(4), ..Account::new(5) }],
+        };
+
+        let mut bytes = vec![];
+        snapshot.write_json(&mut bytes).unwrap();
+
+        let json = String::from_utf8(bytes).unwrap();
+        assert!(json.contains("\"available\": \"1.23456\""));
+        assert!(json.contains("\"3\": \"high\""));
+        assert!(json.contains("\"version\": 3"));
+        assert_eq!(Snapshot::read_json(json.as_bytes()).unwrap(), snapshot);
+
+        // A hand-written fixture can leave out what it doesn't need
+        let fixture = r#"{"accounts": [{"client": 2, "available": "5", "held": "1", "total": "6"}]}"#;
+        assert_eq!(Snapshot::read_json(fixture.as_bytes()).unwrap().accounts, vec![Account {
+            available: dec!(5),
+            held: dec!(1),
+            total: dec!(6),
+            ..Account::new(2)
+        }]);
+    }
+
+    #[test]
+    fn test_restore() {
+        let fixture = r#"{
+            "accounts": [{"client": 1, "available": "10", "held": "0", "total": "10"}],
+            "tiers": {"1": "high"}
+        }"#;
+        let snapshot = Snapshot::read_json(fixture.as_bytes()).unwrap();
+
+        let mut engine = Engine::builder()
+            .event_sourcing()
+            .policy(Box::new(TierPolicy::new([(RiskTier::High, dec!(5))])))
+            .opening_balances(snapshot.accounts.clone())
+            .risk_tiers(snapshot.tiers.clone())
+            .build();
+
+        assert_eq!(engine.risk_tier(1), RiskTier::High);
+        assert!(engine.add_transaction(Transaction::new(1, 1, TransactionType::Withdrawal(dec!(6)))).is_err());
+
+        engine.rebuild();
+
+        assert_eq!(Snapshot::capture(&engine), snapshot);
+    }
+
+    #[test]
+    fn test_read_json_invalid() {
+        let account = r#"{"client": 2, "available": "5", "held": "1", "total": "6"}"#;
+        let inconsistent = r#"{"accounts": [{"client": 2, "available": "5", "held": "1", "total": "7"}]}"#;
+
+        assert!(matches!(
+            Snapshot::read_json(format!(r#"{{"accounts": [{}, {}]}}"#, account, account).as_bytes()),
+            Err(SnapshotError::DuplicateClient(2))
+        ));
+        let deleted = format!(r#"{{"accounts": [{}], "deleted": [{}]}}"#, account, account);
+
+        assert!(matches!(Snapshot::read_json(deleted.as_bytes()), Err(SnapshotError::DuplicateClient(2))));
+        assert!(matches!(Snapshot::read_json(inconsistent.as_bytes()), Err(SnapshotError::Inconsistent(2))));
+        assert!(matches!(Snapshot::read_json(&b"{}"[..]), Err(SnapshotError::Json(_))));
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        assert!(matches!(Snapshot::read(&b"\x01"[..]), Err(SnapshotError::Encoding(_))));
+    }
+}
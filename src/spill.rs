@@ -0,0 +1,122 @@
+use std::{
+    env,
+    fs::{ self, File },
+    io::{ self, BufRead, BufReader, Seek, SeekFrom, Write },
+    marker::PhantomData,
+    path::{ Path, PathBuf },
+    process,
+    sync::atomic::{ AtomicU64, Ordering },
+};
+
+use serde::{ de::DeserializeOwned, Serialize };
+
+static NEXT_QUEUE: AtomicU64 = AtomicU64::new(0);
+
+/// A first in, first out queue kept in a file, one line of JSON per item, so only the file grows
+/// with the items queued. The file is emptied every time the queue is and removed with the queue.
+pub struct SpillQueue<T> {
+    path: PathBuf,
+    file: File,
+    read: u64,
+    write: u64,
+    len: usize,
+    items: PhantomData<fn(T) -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> SpillQueue<T> {
+    /// A queue in a new file of the temporary directory.
+    pub fn new() -> io::Result<Self> {
+        let name = format!(
+            "transaction-engine-spill-{}-{}.jsonl",
+            process::id(),
+            NEXT_QUEUE.fetch_add(1, Ordering::Relaxed)
+        );
+
+        SpillQueue::create(env::temp_dir().join(name))
+    }
+
+    /// Replaces the file when it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::options().read(true).write(true).create(true).truncate(true).open(&path)?;
+
+        Ok(SpillQueue { path, file, read: 0, write: 0, len: 0, items: PhantomData })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, item: &T) -> io::Result<()> {
+        let mut line = serde_json::to_vec(item)?;
+        line.push(b'\n');
+
+        self.file.seek(SeekFrom::Start(self.write))?;
+        self.file.write_all(&line)?;
+
+        self.write += line.len() as u64;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> io::Result<Option<T>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+
+        self.file.seek(SeekFrom::Start(self.read))?;
+
+        let mut line = vec![];
+        BufReader::new(&mut self.file).read_until(b'\n', &mut line)?;
+
+        self.read += line.len() as u64;
+        self.len -= 1;
+
+        if self.len == 0 {
+            self.file.set_len(0)?;
+            self.read = 0;
+            self.write = 0;
+        }
+
+        Ok(Some(serde_json::from_slice(&line)?))
+    }
+}
+
+impl<T> Drop for SpillQueue<T> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue() {
+        let path = env::temp_dir().join("transaction-engine-spill-queue.jsonl");
+        let mut queue = SpillQueue::<Vec<String>>::create(&path).unwrap();
+
+        queue.push(&vec!["first".to_string()]).unwrap();
+        queue.push(&vec!["second".to_string(), "line\nbreak".to_string()]).unwrap();
+
+        assert_eq!(queue.pop().unwrap(), Some(vec!["first".to_string()]));
+
+        queue.push(&vec![]).unwrap();
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop().unwrap(), Some(vec!["second".to_string(), "line\nbreak".to_string()]));
+        assert_eq!(queue.pop().unwrap(), Some(vec![]));
+        assert_eq!(queue.pop().unwrap(), None);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        drop(queue);
+
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,143 @@
+use std::{ collections::HashMap, fs::{ self, File }, io, path::Path };
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::types::{ custom_serde, Account, ClientId, Transaction, TransactionType };
+
+/// An applied transaction on a client's statement, with the balances of the client right after it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatementEntry {
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub tx_type: &'static str,
+    #[serde(serialize_with = "custom_serde::serialize_optional_decimal")]
+    pub amount: Option<Decimal>,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub available: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub held: Decimal,
+    /// Where the last dispute of the transaction stands: `under_dispute`, `resolved`,
+    /// `charged_back` or `represented`
+    pub dispute: Option<&'static str>,
+}
+
+/// The applied transactions of every client in order, kept by an engine built with
+/// [`EngineBuilder::statements`](crate::EngineBuilder::statements). It grows with every applied
+/// transaction, so it's only kept when asked for.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    statements: HashMap<ClientId, Vec<StatementEntry>>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Ledger::default()
+    }
+
+    /// Adds an applied transaction to the statement of the account it changed. A dispute, resolve or
+    /// chargeback also annotates the transaction it refers to.
+    pub fn record(&mut self, tx: &Transaction, account: &Account) {
+        let statement = self.statements.entry(account.client_id).or_default();
+
+        let dispute = match tx.tx_type {
+            TransactionType::Dispute => Some("under_dispute"),
+            TransactionType::Resolve => Some("resolved"),
+            TransactionType::Chargeback => Some("charged_back"),
+            TransactionType::Representment => Some("represented"),
+            _ => None,
+        };
+
+        if let Some(dispute) = dispute {
+            let disputed = statement
+                .iter_mut()
+                .rev()
+                .find(|entry| entry.tx == tx.tx_id && entry.amount.is_some());
+
+            if let Some(entry) = disputed {
+                entry.dispute = Some(dispute);
+            }
+        }
+
+        statement.push(StatementEntry {
+            tx: tx.tx_id,
+            tx_type: tx.tx_type.name(),
+            amount: tx.tx_type.amount(),
+            available: account.available,
+            held: account.held,
+            dispute: None,
+        });
+    }
+
+    pub fn statement(&self, client_id: ClientId) -> &[StatementEntry] {
+        self.statements.get(&client_id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The clients with a statement, sorted
+    pub fn client_ids(&self) -> Vec<ClientId> {
+        let mut client_ids: Vec<ClientId> = self.statements.keys().copied().collect();
+        client_ids.sort_unstable();
+
+        client_ids
+    }
+}
+
+pub fn write_statement<W: io::Write>(entries: &[StatementEntry], writer: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for entry in entries {
+        writer.serialize(entry)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the statement of every client to `client-<id>.csv` in `dir`, created when missing.
+pub fn write_statements(ledger: &Ledger, dir: &Path) -> csv::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    for client_id in ledger.client_ids() {
+        let file = File::create(dir.join(format!("client-{}.csv", client_id)))?;
+
+        write_statement(ledger.statement(client_id), file)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_statement() {
+        let mut ledger = Ledger::new();
+        let account = |available, held| Account { available, held, total: available + held, ..Account::new(1) };
+
+        ledger.record(&Transaction::new(1, 1, TransactionType::Deposit(dec!(5))), &account(dec!(5), dec!(0)));
+        ledger.record(&Transaction::new(1, 2, TransactionType::Withdrawal(dec!(1.5))), &account(dec!(3.5), dec!(0)));
+        ledger.record(&Transaction::new(1, 1, TransactionType::Dispute), &account(dec!(-1.5), dec!(5)));
+        ledger.record(&Transaction::new(1, 1, TransactionType::Resolve), &account(dec!(3.5), dec!(0)));
+
+        assert_eq!(ledger.client_ids(), vec![1]);
+        assert!(ledger.statement(2).is_empty());
+
+        let mut output = vec![];
+        write_statement(ledger.statement(1), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "tx,type,amount,available,held,dispute\n",
+                "1,deposit,5,5,0,resolved\n",
+                "2,withdrawal,1.5,3.5,0,\n",
+                "1,dispute,,-1.5,5,\n",
+                "1,resolve,,3.5,0,\n"
+            )
+        );
+    }
+}
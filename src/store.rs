@@ -0,0 +1,298 @@
+use std::collections::{ HashMap, VecDeque };
+#[cfg(not(feature = "fast-hash"))]
+use std::collections::hash_map::RandomState;
+
+use rust_decimal::Decimal;
+use serde::{ Deserialize, Serialize };
+
+use crate::{ error::EngineError, types::{ Account, ClientId } };
+
+/// Where a deposit or withdrawal stands, it decides what can still be done with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionInfo {
+    Regular,
+    UnderDispute,
+    /// Resolved (or its dispute expired), it can only be disputed again with re-disputes allowed
+    Resolved,
+    /// Charged back, it can't be disputed again
+    ChargedBack,
+    /// Charged back and won back by a representment, it can't be disputed again
+    Represented,
+    Withdrawal,
+    Adjustment,
+    /// Withdrawal hold waiting to be committed, it becomes a withdrawal or expires
+    Hold,
+    /// Withdrawal hold whose funds went back to available, it expired or was voided
+    Expired,
+    /// Deposit whose funds are held until its release time, it becomes a regular one then
+    OnHold,
+}
+
+/// A transaction kept in the history. Every deposit and withdrawal is kept until pruned, so their
+/// ids can't be reused. Only deposits can be disputed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub info: TransactionInfo,
+    pub client_id: ClientId,
+    pub amount: Decimal,
+}
+
+/// A transaction of the history with what the engine knows about it besides its entry, so it can
+/// be carried over to another engine, e.g. by a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeptTransaction {
+    pub tx_id: u32,
+    pub entry: HistoryEntry,
+    pub timestamp: Option<u64>,
+    /// The sending client, for a transfer
+    pub sender: Option<ClientId>,
+    /// How many times it was disputed again after being resolved
+    pub redisputes: u32,
+}
+
+/// The state an [`Engine`](crate::Engine) works on: the accounts and the history of transactions.
+/// The engine reads an account or a transaction, changes its copy and writes it back, so a backend
+/// doesn't have to hand out references into its storage for writes. There are at most as many
+/// accounts as client ids, backends can keep them in memory and only persist them, the history is
+/// what grows with the input.
+pub trait StateStore {
+    fn get_account(&self, client_id: ClientId) -> Option<&Account>;
+
+    /// Inserts the account, or replaces the one of the same client.
+    fn upsert_account(&mut self, account: Account);
+
+    fn remove_account(&mut self, client_id: ClientId) -> Option<Account>;
+
+    /// The accounts in no particular order
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_>;
+
+    fn account_count(&self) -> usize;
+
+    fn get_tx(&self, tx_id: u32) -> Option<HistoryEntry>;
+
+    /// Inserts the transaction, or replaces the one with the same id.
+    fn put_tx(&mut self, tx_id: u32, entry: HistoryEntry);
+
+    fn remove_tx(&mut self, tx_id: u32);
+
+    fn has_tx(&self, tx_id: u32) -> bool {
+        self.get_tx(tx_id).is_some()
+    }
+
+    fn tx_count(&self) -> usize;
+
+    /// Appends a transaction to the order of the history, see [`StateStore::order`].
+    fn push_order(&mut self, tx_id: u32, timestamp: Option<u64>);
+
+    /// The ids of the history in the order they were added with their timestamps, the oldest first.
+    /// An id is there as many times as it was added, and stays after it's removed from the history
+    /// until [`StateStore::compact_order`].
+    fn order(&self) -> Box<dyn Iterator<Item = (u32, Option<u64>)> + '_>;
+
+    /// Drops the ids no longer in the history from the order.
+    fn compact_order(&mut self);
+
+    /// Drops every account and transaction and the order, e.g. before a rebuild.
+    fn clear(&mut self);
+
+    /// The first failure of the backend, e.g. a full disk. The state can't be trusted after it,
+    /// the engine rejects every transaction from then on.
+    fn failure(&self) -> Option<EngineError> {
+        None
+    }
+}
+
+// SipHash resists collisions crafted from the input, FxHash is much faster on the integer keys
+#[cfg(feature = "fast-hash")]
+type Hasher = rustc_hash::FxBuildHasher;
+#[cfg(not(feature = "fast-hash"))]
+type Hasher = RandomState;
+
+/// Keeps the whole state in memory, the default store.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    accounts: HashMap<ClientId, Account, Hasher>,
+    history: HashMap<u32, HistoryEntry, Hasher>,
+    order: VecDeque<(u32, Option<u64>)>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    /// A store with room for this many accounts and transactions, so a large input doesn't rehash
+    /// its maps as they grow.
+    pub fn with_capacity(clients: usize, txs: usize) -> Self {
+        MemoryStore {
+            accounts: HashMap::with_capacity_and_hasher(clients, Hasher::default()),
+            history: HashMap::with_capacity_and_hasher(txs, Hasher::default()),
+            order: VecDeque::with_capacity(txs),
+        }
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get_account(&self, client_id: ClientId) -> Option<&Account> {
+        self.accounts.get(&client_id)
+    }
+
+    fn upsert_account(&mut self, account: Account) {
+        self.accounts.insert(account.client_id, account);
+    }
+
+    fn remove_account(&mut self, client_id: ClientId) -> Option<Account> {
+        self.accounts.remove(&client_id)
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.accounts.values())
+    }
+
+    fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    fn get_tx(&self, tx_id: u32) -> Option<HistoryEntry> {
+        self.history.get(&tx_id).copied()
+    }
+
+    fn put_tx(&mut self, tx_id: u32, entry: HistoryEntry) {
+        self.history.insert(tx_id, entry);
+    }
+
+    fn remove_tx(&mut self, tx_id: u32) {
+        self.history.remove(&tx_id);
+    }
+
+    fn has_tx(&self, tx_id: u32) -> bool {
+        self.history.contains_key(&tx_id)
+    }
+
+    fn tx_count(&self) -> usize {
+        self.history.len()
+    }
+
+    fn push_order(&mut self, tx_id: u32, timestamp: Option<u64>) {
+        self.order.push_back((tx_id, timestamp));
+    }
+
+    fn order(&self) -> Box<dyn Iterator<Item = (u32, Option<u64>)> + '_> {
+        Box::new(self.order.iter().copied())
+    }
+
+    fn compact_order(&mut self) {
+        let history = &self.history;
+
+        self.order.retain(|(tx_id, _)| history.contains_key(tx_id));
+    }
+
+    fn clear(&mut self) {
+        self.accounts.clear();
+        self.history.clear();
+        self.order.clear();
+    }
+}
+
+// Lets the backend be picked at run time, e.g. by the command line tool.
+impl<S: StateStore + ?Sized> StateStore for Box<S> {
+    fn get_account(&self, client_id: ClientId) -> Option<&Account> {
+        (**self).get_account(client_id)
+    }
+
+    fn upsert_account(&mut self, account: Account) {
+        (**self).upsert_account(account)
+    }
+
+    fn remove_account(&mut self, client_id: ClientId) -> Option<Account> {
+        (**self).remove_account(client_id)
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        (**self).accounts()
+    }
+
+    fn account_count(&self) -> usize {
+        (**self).account_count()
+    }
+
+    fn get_tx(&self, tx_id: u32) -> Option<HistoryEntry> {
+        (**self).get_tx(tx_id)
+    }
+
+    fn put_tx(&mut self, tx_id: u32, entry: HistoryEntry) {
+        (**self).put_tx(tx_id, entry)
+    }
+
+    fn remove_tx(&mut self, tx_id: u32) {
+        (**self).remove_tx(tx_id)
+    }
+
+    fn has_tx(&self, tx_id: u32) -> bool {
+        (**self).has_tx(tx_id)
+    }
+
+    fn tx_count(&self) -> usize {
+        (**self).tx_count()
+    }
+
+    fn push_order(&mut self, tx_id: u32, timestamp: Option<u64>) {
+        (**self).push_order(tx_id, timestamp)
+    }
+
+    fn order(&self) -> Box<dyn Iterator<Item = (u32, Option<u64>)> + '_> {
+        (**self).order()
+    }
+
+    fn compact_order(&mut self) {
+        (**self).compact_order()
+    }
+
+    fn clear(&mut self) {
+        (**self).clear()
+    }
+
+    fn failure(&self) -> Option<EngineError> {
+        (**self).failure()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_memory_store() {
+        let mut store = MemoryStore::new();
+        let entry = HistoryEntry { info: TransactionInfo::Regular, client_id: 1, amount: dec!(2) };
+
+        store.upsert_account(Account::new(1));
+        store.upsert_account(Account { available: dec!(2), total: dec!(2), ..Account::new(1) });
+        store.put_tx(7, entry);
+
+        assert_eq!(store.account_count(), 1);
+        assert_eq!(store.get_account(1).unwrap().available, dec!(2));
+        assert_eq!(store.get_tx(7), Some(entry));
+        assert!(!store.has_tx(8));
+
+        store.put_tx(7, HistoryEntry { info: TransactionInfo::UnderDispute, ..entry });
+        assert_eq!(store.get_tx(7).unwrap().info, TransactionInfo::UnderDispute);
+
+        store.put_tx(8, entry);
+        store.push_order(7, None);
+        store.push_order(8, Some(10));
+        store.push_order(7, None);
+        store.remove_tx(7);
+        assert_eq!(store.order().count(), 3);
+
+        store.compact_order();
+        assert_eq!(store.order().collect::<Vec<_>>(), vec![(8, Some(10))]);
+
+        store.remove_tx(8);
+        assert_eq!(store.tx_count(), 0);
+        assert_eq!(store.remove_account(1).map(|account| account.client_id), Some(1));
+        assert_eq!(store.accounts().count(), 0);
+    }
+}
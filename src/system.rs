@@ -0,0 +1,95 @@
+use std::{ collections::BTreeMap, io };
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::types::custom_serde;
+
+// Internal accounts on the other side of client movements that don't go to or come from the client
+// itself, so client totals plus system balances reconcile with what was deposited and withdrawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemAccount {
+    /// Fees charged to clients
+    Fees,
+    /// Amounts reversed to the payer by chargebacks
+    ChargebackLosses,
+    /// Received deposits waiting in quarantine, not credited to the client yet
+    Escrow,
+    /// Corrections credited to (negative) or debited from (positive) clients by adjustments
+    Adjustments,
+}
+
+impl SystemAccount {
+    pub const ALL: [SystemAccount; 4] = [
+        SystemAccount::Fees,
+        SystemAccount::ChargebackLosses,
+        SystemAccount::Escrow,
+        SystemAccount::Adjustments,
+    ];
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SystemLedger {
+    balances: BTreeMap<SystemAccount, Decimal>,
+}
+
+impl SystemLedger {
+    pub fn new() -> Self {
+        SystemLedger::default()
+    }
+
+    pub fn credit(&mut self, account: SystemAccount, amount: Decimal) {
+        *self.balances.entry(account).or_default() += amount;
+    }
+
+    pub fn debit(&mut self, account: SystemAccount, amount: Decimal) {
+        *self.balances.entry(account).or_default() -= amount;
+    }
+
+    pub fn balance(&self, account: SystemAccount) -> Decimal {
+        self.balances.get(&account).copied().unwrap_or_default()
+    }
+
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        #[derive(Serialize)]
+        struct Row {
+            account: SystemAccount,
+            #[serde(serialize_with = "custom_serde::serialize_decimal")]
+            balance: Decimal,
+        }
+
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for account in SystemAccount::ALL {
+            writer.serialize(Row { account, balance: self.balance(account) })?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_write_csv() {
+        let mut ledger = SystemLedger::new();
+        ledger.credit(SystemAccount::ChargebackLosses, dec!(10.50));
+        ledger.credit(SystemAccount::Escrow, dec!(3));
+        ledger.debit(SystemAccount::Escrow, dec!(1));
+
+        let mut output = vec![];
+        ledger.write_csv(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "account,balance\nfees,0\nchargeback_losses,10.50\nescrow,2\nadjustments,0\n"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::{ collections::BTreeMap, fs::{ self, File }, io, path::Path };
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{ engine::Engine, error::EngineError, types::{ custom_serde, Account, ClientId, Transaction } };
+
+/// An account of the output of a multi-tenant run, the balances of a client of one tenant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TenantAccount {
+    pub tenant: String,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub available: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub held: Decimal,
+    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl TenantAccount {
+    pub fn new(tenant: &str, account: &Account) -> Self {
+        TenantAccount {
+            tenant: tenant.to_string(),
+            client_id: account.client_id,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+        }
+    }
+
+    pub fn account(&self) -> Account {
+        Account {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            ..Account::new(self.client_id)
+        }
+    }
+}
+
+/// Keeps the accounts and history of every tenant apart, with an [`Engine`] per tenant created the
+/// first time a transaction of it is seen. A transaction goes to the engine of its `tenant`, or of
+/// the default tenant when it has none. Client and transaction ids only have to be unique within a
+/// tenant: client 1 of one merchant and client 1 of another are different accounts.
+pub struct MultiTenant<F> {
+    default: String,
+    engines: BTreeMap<String, Engine>,
+    build: F,
+}
+
+impl<F: FnMut(&str) -> Engine> MultiTenant<F> {
+    /// `build` creates the engine of a tenant from its name.
+    pub fn new(default: impl Into<String>, build: F) -> Self {
+        MultiTenant { default: default.into(), engines: BTreeMap::new(), build }
+    }
+
+    pub fn add_transaction(&mut self, tx: Transaction) -> Result<(), EngineError> {
+        let tenant = tx
+            .tenant
+            .as_deref()
+            .map(str::trim)
+            .filter(|tenant| !tenant.is_empty())
+            .unwrap_or(&self.default)
+            .to_string();
+
+        self.engine_mut(&tenant).add_transaction(tx)
+    }
+
+    /// The engine of a tenant, created when missing. Seeding the default tenant this way gives the
+    /// opening balances of a run to it.
+    pub fn engine_mut(&mut self, tenant: &str) -> &mut Engine {
+        self.engines
+            .entry(tenant.to_string())
+            .or_insert_with(|| (self.build)(tenant))
+    }
+
+    pub fn engine(&self, tenant: &str) -> Option<&Engine> {
+        self.engines.get(tenant)
+    }
+
+    /// The tenants seen so far, sorted
+    pub fn tenants(&self) -> impl Iterator<Item = &str> {
+        self.engines.keys().map(String::as_str)
+    }
+
+    /// Consumes the engines and returns the accounts sorted by tenant, then client id.
+    pub fn get_accounts(self) -> Vec<TenantAccount> {
+        self.engines
+            .into_iter()
+            .flat_map(|(tenant, engine)| {
+                engine
+                    .get_accounts()
+                    .into_iter()
+                    .map(move |account| TenantAccount::new(&tenant, &account))
+            })
+            .collect()
+    }
+
+    /// Consumes the engines and returns the accounts by tenant, in no particular order within one.
+    pub fn into_accounts(self) -> Vec<TenantAccount> {
+        self.engines
+            .into_iter()
+            .flat_map(|(tenant, engine)| {
+                engine
+                    .into_accounts()
+                    .into_iter()
+                    .map(move |account| TenantAccount::new(&tenant, &account))
+            })
+            .collect()
+    }
+}
+
+/// Writes the accounts of every tenant to `<tenant>.csv` in `dir`, created when missing, with the
+/// columns of a single-tenant run. A tenant whose name can't be a file name fails the write.
+pub fn write_tenants(accounts: &[TenantAccount], dir: &Path) -> csv::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    for tenant in accounts.chunk_by(|a, b| a.tenant == b.tenant) {
+        let name = &tenant[0].tenant;
+
+        if matches!(name.as_str(), "" | "." | "..") || name.contains(['/', '\\']) {
+            let message = format!("tenant {:?} can't be a file name", name);
+
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+
+        let mut writer = csv::Writer::from_writer(File::create(dir.join(format!("{}.csv", name)))?);
+
+        for account in tenant {
+            writer.serialize(account.account())?;
+        }
+
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::types::TransactionType;
+
+    fn tx(client_id: ClientId, tx_id: u32, tx_type: TransactionType, tenant: Option<&str>) -> Transaction {
+        Transaction { tenant: tenant.map(String::from), ..Transaction::new(client_id, tx_id, tx_type) }
+    }
+
+    #[test]
+    fn test_accounts_per_tenant() {
+        let mut engines = MultiTenant::new("default", |_: &str| Engine::new());
+
+        engines.add_transaction(tx(1, 1, TransactionType::Deposit(dec!(10)), Some("acme"))).unwrap();
+        // The same client and transaction ids in another tenant
+        engines.add_transaction(tx(1, 1, TransactionType::Deposit(dec!(4)), Some("globex"))).unwrap();
+        engines.add_transaction(tx(2, 2, TransactionType::Deposit(dec!(1)), Some(" "))).unwrap();
+
+        // The funds of one tenant can't pay for another
+        assert_eq!(
+            engines.add_transaction(tx(1, 3, TransactionType::Withdrawal(dec!(5)), Some("globex"))),
+            Err(EngineError::InsufficientFunds { client_id: 1, available: dec!(4), required: dec!(5) })
+        );
+
+        // A chargeback only locks the account of its tenant
+        engines.add_transaction(tx(1, 1, TransactionType::Dispute, Some("globex"))).unwrap();
+        engines.add_transaction(tx(1, 1, TransactionType::Chargeback, Some("globex"))).unwrap();
+
+        assert_eq!(
+            engines.add_transaction(tx(1, 1, TransactionType::Deposit(dec!(1)), Some("acme"))),
+            Err(EngineError::DuplicateTxId(1))
+        );
+        assert_eq!(engines.tenants().collect::<Vec<_>>(), vec!["acme", "default", "globex"]);
+
+        assert_eq!(
+            engines.get_accounts(),
+            vec![
+                TenantAccount::new("acme", &Account { available: dec!(10), total: dec!(10), ..Account::new(1) }),
+                TenantAccount::new("default", &Account { available: dec!(1), total: dec!(1), ..Account::new(2) }),
+                TenantAccount::new("globex", &Account { locked: true, ..Account::new(1) })
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_tenants() {
+        let dir = std::env::temp_dir().join("transaction-engine-tenants");
+        let _ = fs::remove_dir_all(&dir);
+
+        let accounts = vec![
+            TenantAccount::new("acme", &Account { available: dec!(2), total: dec!(2), ..Account::new(1) }),
+            TenantAccount::new("acme", &Account::new(2)),
+            TenantAccount::new("globex", &Account { locked: true, ..Account::new(1) }),
+        ];
+
+        write_tenants(&accounts, &dir).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.join("acme.csv")).unwrap(),
+            "client,available,held,total,locked\n1,2,0,2,false\n2,0,0,0,false\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("globex.csv")).unwrap(),
+            "client,available,held,total,locked\n1,0,0,0,true\n"
+        );
+
+        assert!(write_tenants(&[TenantAccount::new("../acme", &Account::new(1))], &dir).is_err());
+    }
+
+    #[test]
+    fn test_write_tenant_column() {
+        let account = TenantAccount::new("acme", &Account { available: dec!(1.5), total: dec!(1.5), ..Account::new(3) });
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&account).unwrap();
+
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "tenant,client,available,held,total,locked\nacme,3,1.5,0,1.5,false\n"
+        );
+    }
+}
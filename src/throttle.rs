@@ -0,0 +1,336 @@
+use std::{ collections::HashMap, fmt, str::FromStr, sync::{ Arc, Mutex }, time::{ Duration, Instant } };
+
+use crate::{ metrics::MetricsRecorder, types::ClientId };
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32) -> Self {
+        let capacity = rate as f64;
+
+        TokenBucket { rate: rate as f64, capacity, tokens: capacity, last: Instant::now() }
+    }
+
+    pub async fn acquire(&mut self) {
+        while let Err(wait) = self.try_take(Instant::now()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+
+        match self.wait() {
+            Some(wait) => Err(wait),
+            None => {
+                self.tokens -= 1.0;
+                Ok(())
+            }
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now.max(self.last);
+    }
+
+    // How long until a token is there, none when there's one already
+    fn wait(&self) -> Option<Duration> {
+        (self.tokens < 1.0).then(|| Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+}
+
+/// Transactions per second accepted from the sources of a service, overall and for each client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub global: Option<u32>,
+    pub per_client: Option<u32>,
+}
+
+impl RateLimits {
+    pub fn is_enabled(&self) -> bool {
+        self.global.is_some() || self.per_client.is_some()
+    }
+}
+
+/// Which limit a transaction was over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleScope {
+    Global,
+    Client,
+}
+
+impl ThrottleScope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThrottleScope::Global => "global",
+            ThrottleScope::Client => "client",
+        }
+    }
+}
+
+/// A transaction over a rate limit, it can be tried again after `retry_after`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    pub scope: ThrottleScope,
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "over the {} rate limit, retry in {} ms", self.scope.name(), self.retry_after.as_millis())
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+struct Buckets {
+    limits: RateLimits,
+    global: Option<TokenBucket>,
+    clients: HashMap<ClientId, TokenBucket>,
+    metrics: Box<dyn MetricsRecorder>,
+}
+
+// Enforces the rate limits before the engine. A transaction over the limit of its client doesn't
+// use up the global one, so a noisy client is held back without starving the others. Clones share
+// the same buckets. Every throttled transaction is counted in `ingest_throttled_total`, by scope.
+#[derive(Clone)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits, metrics: Box<dyn MetricsRecorder>) -> Self {
+        let buckets = Buckets {
+            limits,
+            global: limits.global.map(TokenBucket::new),
+            clients: HashMap::new(),
+            metrics,
+        };
+
+        RateLimiter { buckets: Arc::new(Mutex::new(buckets)) }
+    }
+
+    /// Takes a token for a transaction of the client, or tells how long to wait for one.
+    pub fn check(&self, client_id: ClientId) -> Result<(), Throttled> {
+        self.check_at(client_id, Instant::now())
+    }
+
+    /// Waits until a transaction of the client is within the limits, for the sources that can't
+    /// turn it away and try again later.
+    pub async fn acquire(&self, client_id: ClientId) {
+        while let Err(throttled) = self.check(client_id) {
+            log::debug!("Throttling client {}: {}", client_id, throttled);
+
+            tokio::time::sleep(throttled.retry_after).await;
+        }
+    }
+
+    fn check_at(&self, client_id: ClientId, now: Instant) -> Result<(), Throttled> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { limits, global, clients, metrics } = &mut *buckets;
+
+        let mut client = limits.per_client.map(|rate| {
+            clients.entry(client_id).or_insert_with(|| TokenBucket { last: now, ..TokenBucket::new(rate) })
+        });
+
+        let over = [(ThrottleScope::Client, client.as_deref_mut()), (ThrottleScope::Global, global.as_mut())]
+            .into_iter()
+            .find_map(|(scope, bucket)| {
+                let bucket = bucket?;
+                bucket.refill(now);
+                bucket.wait().map(|retry_after| Throttled { scope, retry_after })
+            });
+
+        if let Some(throttled) = over {
+            metrics.increment_counter("ingest_throttled_total", &[("scope", throttled.scope.name())], 1);
+
+            return Err(throttled);
+        }
+
+        for bucket in [client, global.as_mut()].into_iter().flatten() {
+            bucket.tokens -= 1.0;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter").field("limits", &self.buckets.lock().unwrap().limits).finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    Realtime,
+    Factor(f64),
+}
+
+impl ReplaySpeed {
+    fn factor(&self) -> f64 {
+        match self {
+            ReplaySpeed::Realtime => 1.0,
+            ReplaySpeed::Factor(factor) => *factor,
+        }
+    }
+}
+
+impl fmt::Display for ReplaySpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplaySpeed::Realtime => f.write_str("realtime"),
+            ReplaySpeed::Factor(factor) => write!(f, "{}x", factor),
+        }
+    }
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "realtime" {
+            return Ok(ReplaySpeed::Realtime);
+        }
+
+        let factor = s
+            .strip_suffix('x')
+            .and_then(|factor| factor.parse::<f64>().ok())
+            .filter(|factor| factor.is_finite() && *factor > 0.0)
+            .ok_or_else(|| format!("invalid replay speed {}, expected realtime or e.g. 10x", s))?;
+
+        Ok(ReplaySpeed::Factor(factor))
+    }
+}
+
+// Paces the transactions by their original timestamps: the first timestamped transaction is
+// applied right away and the next ones when as much time, divided by the speed, has passed.
+// Transactions without a timestamp or older than the latest one don't wait.
+pub struct Replay {
+    speed: ReplaySpeed,
+    start: Option<(u64, Instant)>,
+}
+
+impl Replay {
+    pub fn new(speed: ReplaySpeed) -> Self {
+        Replay { speed, start: None }
+    }
+
+    pub async fn wait(&mut self, timestamp: Option<u64>) {
+        if let Some(wait) = timestamp.and_then(|timestamp| self.delay(timestamp, Instant::now())) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn delay(&mut self, timestamp: u64, now: Instant) -> Option<Duration> {
+        let (first, start) = *self.start.get_or_insert((timestamp, now));
+
+        let offset = (timestamp.saturating_sub(first) as f64) / self.speed.factor();
+        let due = start + Duration::from_secs_f64(offset);
+
+        Some(due.saturating_duration_since(now)).filter(|wait| !wait.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::PrometheusRecorder;
+
+    #[test]
+    fn test_burst_then_throttle() {
+        let mut bucket = TokenBucket::new(10);
+        let start = bucket.last;
+
+        for _ in 0..10 {
+            assert_eq!(bucket.try_take(start), Ok(()));
+        }
+
+        let wait = bucket.try_take(start).unwrap_err();
+        assert_eq!(wait.as_millis(), 100);
+
+        assert!(bucket.try_take(start + Duration::from_millis(50)).is_err());
+        assert_eq!(bucket.try_take(start + Duration::from_millis(100)), Ok(()));
+    }
+
+    #[test]
+    fn test_refill_capped() {
+        let mut bucket = TokenBucket::new(5);
+        let later = bucket.last + Duration::from_secs(60);
+
+        let taken = (0..100)
+            .take_while(|_| bucket.try_take(later).is_ok())
+            .count();
+
+        assert_eq!(taken, 5);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let metrics = PrometheusRecorder::new();
+        let limits = RateLimits { global: Some(4), per_client: Some(2) };
+        let limiter = RateLimiter::new(limits, Box::new(metrics.clone()));
+        let start = Instant::now();
+
+        assert_eq!(limiter.check_at(1, start), Ok(()));
+        assert_eq!(limiter.check_at(1, start), Ok(()));
+
+        // The noisy client is held back, the others still get the rest of the global limit
+        let throttled = limiter.check_at(1, start).unwrap_err();
+        assert_eq!(throttled.scope, ThrottleScope::Client);
+        assert_eq!(throttled.retry_after, Duration::from_millis(500));
+
+        assert_eq!(limiter.check_at(2, start), Ok(()));
+        assert_eq!(limiter.check_at(3, start), Ok(()));
+        assert_eq!(limiter.check_at(4, start).unwrap_err().scope, ThrottleScope::Global);
+
+        assert_eq!(limiter.check_at(1, start + Duration::from_millis(500)), Ok(()));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("ingest_throttled_total{scope=\"client\"} 1"));
+        assert!(rendered.contains("ingest_throttled_total{scope=\"global\"} 1"));
+    }
+
+    #[test]
+    fn test_parse_replay_speed() {
+        assert_eq!("realtime".parse(), Ok(ReplaySpeed::Realtime));
+        assert_eq!("10x".parse(), Ok(ReplaySpeed::Factor(10.0)));
+        assert_eq!("0.5x".parse(), Ok(ReplaySpeed::Factor(0.5)));
+        assert!("10".parse::<ReplaySpeed>().is_err());
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[test]
+    fn test_replay_delay() {
+        let mut replay = Replay::new(ReplaySpeed::Factor(10.0));
+        let start = Instant::now();
+
+        assert_eq!(replay.delay(1000, start), None);
+        assert_eq!(replay.delay(1050, start), Some(Duration::from_secs(5)));
+        let later = start + Duration::from_secs(2);
+        assert_eq!(replay.delay(1050, later), Some(Duration::from_secs(3)));
+        assert_eq!(replay.delay(1010, later), None);
+        assert_eq!(replay.delay(900, start), None);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits() {
+        let mut bucket = TokenBucket::new(100);
+        let start = Instant::now();
+
+        for _ in 0..110 {
+            bucket.acquire().await;
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+}
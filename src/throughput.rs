@@ -0,0 +1,150 @@
+use std::{ collections::BTreeMap, io, str::FromStr };
+
+use chrono::DateTime;
+use serde::Serialize;
+
+use crate::{ engine::SECONDS_PER_DAY, reason::ReasonCode, types::Transaction };
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bucket {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    fn seconds(&self) -> u64 {
+        match self {
+            Bucket::Hour => SECONDS_PER_DAY / 24,
+            Bucket::Day => SECONDS_PER_DAY,
+        }
+    }
+
+    fn format(&self) -> &'static str {
+        match self {
+            Bucket::Hour => "%Y-%m-%d %H:00",
+            Bucket::Day => "%Y-%m-%d",
+        }
+    }
+}
+
+impl FromStr for Bucket {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(Bucket::Hour),
+            "day" => Ok(Bucket::Day),
+            _ => Err(format!("unknown bucket {}, expected hour or day", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ThroughputCounters {
+    pub applied: u64,
+    pub rejected: u64,
+}
+
+/// Transactions processed per input timestamp bucket and type. Transactions without a timestamp
+/// are counted in a bucket of their own, reported with an empty start.
+#[derive(Default)]
+pub struct Throughput {
+    bucket: Bucket,
+    // The start of the bucket in seconds, `None` for transactions without a timestamp
+    counters: BTreeMap<(Option<u64>, &'static str), ThroughputCounters>,
+}
+
+impl Throughput {
+    pub fn new(bucket: Bucket) -> Self {
+        Throughput { bucket, ..Default::default() }
+    }
+
+    pub fn record(&mut self, tx: &Transaction, result: Result<(), ReasonCode>) {
+        let seconds = self.bucket.seconds();
+        let start = tx.timestamp.map(|timestamp| timestamp - timestamp % seconds);
+        let counters = self.counters.entry((start, tx.tx_type.name())).or_default();
+
+        match result {
+            Ok(()) => counters.applied += 1,
+            Err(_) => counters.rejected += 1,
+        }
+    }
+
+    pub fn write_report<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        #[derive(Serialize)]
+        struct Row {
+            bucket: String,
+            #[serde(rename = "type")]
+            tx_type: &'static str,
+        }
+
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for ((start, tx_type), counters) in self.counters.iter() {
+            let bucket = start
+                .and_then(|start| DateTime::from_timestamp(start as i64, 0))
+                .map(|start| start.format(self.bucket.format()).to_string())
+                .unwrap_or_default();
+
+            writer.serialize((Row { bucket, tx_type }, counters))?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::types::TransactionType;
+
+    use super::*;
+
+    fn transaction(tx_type: TransactionType, timestamp: Option<u64>) -> Transaction {
+        Transaction { timestamp, ..Transaction::new(1, 1, tx_type) }
+    }
+
+    #[test]
+    fn test_throughput_report() {
+        let deposit = TransactionType::Deposit(dec!(1));
+        let mut hourly = Throughput::new(Bucket::Hour);
+        let mut daily = Throughput::new("day".parse().unwrap());
+
+        for throughput in [&mut hourly, &mut daily] {
+            throughput.record(&transaction(deposit.clone(), Some(1_700_000_000)), Ok(()));
+            throughput.record(&transaction(deposit.clone(), Some(1_700_003_000)), Ok(()));
+            throughput.record(
+                &transaction(TransactionType::Withdrawal(dec!(5)), Some(1_700_000_100)),
+                Err(ReasonCode::InsufficientFunds)
+            );
+            throughput.record(&transaction(TransactionType::Dispute, None), Ok(()));
+        }
+
+        let report = |throughput: &Throughput| {
+            let mut output = vec![];
+            throughput.write_report(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            report(&hourly),
+            "bucket,type,applied,rejected\n\
+             ,dispute,1,0\n\
+             2023-11-14 22:00,deposit,1,0\n\
+             2023-11-14 22:00,withdrawal,0,1\n\
+             2023-11-14 23:00,deposit,1,0\n"
+        );
+        assert_eq!(
+            report(&daily),
+            "bucket,type,applied,rejected\n\
+             ,dispute,1,0\n\
+             2023-11-14,deposit,2,0\n\
+             2023-11-14,withdrawal,0,1\n"
+        );
+        assert!("week".parse::<Bucket>().is_err());
+    }
+}
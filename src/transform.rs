@@ -0,0 +1,162 @@
+use std::{ borrow::Cow, collections::HashMap, fs, io, path::Path };
+
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::types::{ ClientId, TransactionType };
+
+/// Mappings applied to every input record before it's parsed, so a partner format that differs
+/// in small ways doesn't need its own preprocessing script. Loaded from a JSON file, e.g.
+/// `{"types": {"credit": "deposit"}, "amount_multiplier": "0.01", "clients": {"100017": 17}}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transform {
+    /// Type names replaced by the engine's
+    #[serde(default)]
+    pub types: HashMap<String, String>,
+    /// Amounts are multiplied by it, e.g. 0.01 for amounts in cents
+    #[serde(default)]
+    pub amount_multiplier: Option<Decimal>,
+    /// Client ids replaced by others, in the client, to_client and into fields
+    #[serde(default)]
+    pub clients: HashMap<String, ClientId>,
+}
+
+impl Transform {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let transform: Transform = serde_json::from_slice(&fs::read(path)?)?;
+
+        let unknown = transform.types
+            .values()
+            .find(|name| !TransactionType::NAMES.contains(&name.as_str()));
+
+        if let Some(name) = unknown {
+            let err = format!("unknown transaction type {} in the type mappings", name);
+
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+
+        Ok(transform)
+    }
+
+    /// The record with its fields mapped, the headers name the fields.
+    pub fn apply_csv(&self, headers: &StringRecord, record: &StringRecord) -> StringRecord {
+        record
+            .iter()
+            .enumerate()
+            .map(|(column, value)| match headers.get(column) {
+                Some(field) => self.map(field, value),
+                None => Cow::Borrowed(value),
+            })
+            .collect()
+    }
+
+    /// The JSON line with its fields mapped, or the line unchanged when it isn't a JSON object.
+    pub fn apply_json(&self, line: &str) -> String {
+        let Ok(Value::Object(mut object)) = serde_json::from_str::<Value>(line) else {
+            return line.to_string();
+        };
+
+        for (field, value) in object.iter_mut() {
+            let text = match value {
+                Value::String(text) => text.clone(),
+                Value::Number(number) => number.to_string(),
+                _ => continue,
+            };
+
+            if let Cow::Owned(mapped) = self.map(field, &text) {
+                // Client ids are numbers in JSON, the other fields are read from strings too
+                *value = match mapped.parse::<ClientId>() {
+                    Ok(client_id) if is_client_field(field) => Value::from(client_id),
+                    _ => Value::String(mapped),
+                };
+            }
+        }
+
+        Value::Object(object).to_string()
+    }
+
+    fn map<'a>(&self, field: &str, value: &'a str) -> Cow<'a, str> {
+        let mapped = match field {
+            "type" => self.types.get(value).cloned(),
+            field if is_client_field(field) => self.clients.get(value).map(ClientId::to_string),
+            "amount" => {
+                // An amount that isn't a number is left for the parser to reject
+                self.amount_multiplier
+                    .zip(value.parse::<Decimal>().ok())
+                    .map(|(multiplier, amount)| (amount * multiplier).to_string())
+            }
+            _ => None,
+        };
+
+        match mapped {
+            Some(mapped) => Cow::Owned(mapped),
+            None => Cow::Borrowed(value),
+        }
+    }
+}
+
+fn is_client_field(field: &str) -> bool {
+    matches!(field, "client" | "to_client" | "into")
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn transform() -> Transform {
+        Transform {
+            types: HashMap::from([("credit".to_string(), "deposit".to_string())]),
+            amount_multiplier: Some(dec!(0.01)),
+            clients: HashMap::from([("100017".to_string(), 17)]),
+        }
+    }
+
+    #[test]
+    fn test_apply_csv() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "to_client"]);
+        let record = StringRecord::from(vec!["credit", "100017", "100017", "1250", "100017"]);
+
+        assert_eq!(
+            transform().apply_csv(&headers, &record),
+            StringRecord::from(vec!["deposit", "17", "100017", "12.50", "17"])
+        );
+
+        let record = StringRecord::from(vec!["dispute", "2", "3", ""]);
+        assert_eq!(transform().apply_csv(&headers, &record), record);
+    }
+
+    #[test]
+    fn test_apply_json() {
+        let line = r#"{"type":"credit","client":100017,"tx":5,"amount":"1250"}"#;
+        let mapped: Value = serde_json::from_str(&transform().apply_json(line)).unwrap();
+
+        assert_eq!(
+            mapped,
+            serde_json::json!({ "type": "deposit", "client": 17, "tx": 5, "amount": "12.50" })
+        );
+        assert_eq!(transform().apply_json("not json"), "not json");
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join("transaction-engine-transform.json");
+
+        fs::write(&path, r#"{"types": {"credit": "deposit"}, "amount_multiplier": "0.01"}"#).unwrap();
+        let transform = Transform::load(&path).unwrap();
+        assert_eq!(transform.amount_multiplier, Some(dec!(0.01)));
+        assert!(transform.clients.is_empty());
+
+        fs::write(&path, r#"{"types": {"credit": "refund"}}"#).unwrap();
+        assert!(Transform::load(&path).is_err());
+
+        fs::write(&path, r#"{"type": {"credit": "deposit"}}"#).unwrap();
+        assert!(Transform::load(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
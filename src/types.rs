@@ -1,44 +1,194 @@
+use std::fmt;
+
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::{ de, Deserialize, Serialize };
 
-#[cfg_attr(test, derive(PartialEq, Eq))]
-#[derive(Debug, Deserialize)]
+/// The id of a client, 32 bits wide. Built with the `client-id-u16` feature it's 16 bits wide, as
+/// before, for the deployments whose ids fit and whose memory is tight.
+#[cfg(not(feature = "client-id-u16"))]
+pub type ClientId = u32;
+#[cfg(feature = "client-id-u16")]
+pub type ClientId = u16;
+
+/// A client id wider than [`ClientId`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIdOutOfRange(pub u64);
+
+impl fmt::Display for ClientIdOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client id {} is out of range, the largest is {}", self.0, ClientId::MAX)
+    }
+}
+
+impl std::error::Error for ClientIdOutOfRange {}
+
+/// Narrows an id read from an input or a file to a [`ClientId`].
+pub fn client_id(id: u64) -> Result<ClientId, ClientIdOutOfRange> {
+    ClientId::try_from(id).map_err(|_| ClientIdOutOfRange(id))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransactionType {
     Deposit(Decimal),
     Withdrawal(Decimal),
     Dispute,
     Resolve,
     Chargeback,
+    /// Reverses the chargeback of a transaction, the merchant won it back and the funds return
+    Representment,
+    Merge(ClientId),
+    Recovery(Decimal),
+    Approve,
+    Decline,
+    /// Signed correction of the available balance from an ops file, with the reason for the audit
+    Adjustment {
+        amount: Decimal,
+        reason: String,
+    },
+    /// Moves available funds to another client, disputable by the receiving client like a deposit
+    Transfer {
+        to: ClientId,
+        amount: Decimal,
+    },
+    /// Clears the lock of an account, an admin operation
+    Unlock,
+    /// Hides an account from the output and queries, keeping its state, an admin operation
+    Delete,
+    /// Brings back a deleted account, an admin operation
+    Restore,
+    /// Holds available funds for a withdrawal until it's committed or the hold expires
+    WithdrawHold(Decimal),
+    /// Withdraws the funds held by the hold with the same transaction id
+    WithdrawCommit,
+    /// Gives the funds held by the hold with the same transaction id back to available
+    WithdrawVoid,
+    /// Moves available funds from the balance of the transaction's currency to the balance of
+    /// another currency of the client, only with multiple currencies
+    Convert {
+        to: String,
+        amount: Decimal,
+    },
 }
 
+impl TransactionType {
+    pub const NAMES: [&'static str; 22] = [
+        "deposit",
+        "withdrawal",
+        "dispute",
+        "resolve",
+        "chargeback",
+        "representment",
+        "merge",
+        "recovery",
+        "approve",
+        "decline",
+        "adjustment",
+        "transfer",
+        "unlock",
+        "delete",
+        "restore",
+        "withdraw_hold",
+        "withdraw_commit",
+        "withdraw_void",
+        "convert",
+        // Card processor names of withdraw_hold, withdraw_commit and withdraw_void
+        "authorize",
+        "capture",
+        "void",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit(_) => "deposit",
+            TransactionType::Withdrawal(_) => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Representment => "representment",
+            TransactionType::Merge(_) => "merge",
+            TransactionType::Recovery(_) => "recovery",
+            TransactionType::Approve => "approve",
+            TransactionType::Decline => "decline",
+            TransactionType::Adjustment { .. } => "adjustment",
+            TransactionType::Transfer { .. } => "transfer",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Delete => "delete",
+            TransactionType::Restore => "restore",
+            TransactionType::WithdrawHold(_) => "withdraw_hold",
+            TransactionType::WithdrawCommit => "withdraw_commit",
+            TransactionType::WithdrawVoid => "withdraw_void",
+            TransactionType::Convert { .. } => "convert",
+        }
+    }
+
+    pub fn amount(&self) -> Option<Decimal> {
+        match self {
+            TransactionType::Deposit(amount) |
+            TransactionType::Withdrawal(amount) |
+            TransactionType::Recovery(amount) |
+            TransactionType::WithdrawHold(amount) |
+            TransactionType::Adjustment { amount, .. } |
+            TransactionType::Transfer { amount, .. } |
+            TransactionType::Convert { amount, .. } => Some(*amount),
+            _ => None,
+        }
+    }
+}
+
+/// Deserialized from a flat record (`type,client,tx,amount,...`), the amounts are parsed from the
+/// text of their field, never through a binary float. Serialized back to the same flat record with
+/// every column, the ones its type doesn't use empty, and the amount without trailing zeros.
 #[cfg_attr(test, derive(PartialEq, Eq))]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Transaction {
-    #[serde(rename = "client")]
-    pub client_id: u16,
-    #[serde(rename = "tx")]
+    pub client_id: ClientId,
     pub tx_id: u32,
-    #[serde(flatten, deserialize_with = "custom_serde::deserialize_transaction_type")]
     pub tx_type: TransactionType,
+    pub timestamp: Option<u64>,
+    /// Only read when the input has a `currency` column
+    pub currency: Option<String>,
+    /// Upstream sequence number, only read when the input has a `seq` column
+    pub seq: Option<u64>,
+    /// The merchant the client belongs to, only read when the input has a `tenant` column
+    pub tenant: Option<String>,
 }
 
-#[cfg_attr(test, derive(PartialEq, Eq))]
-#[derive(Debug, Serialize)]
+impl Transaction {
+    pub fn new(client_id: ClientId, tx_id: u32, tx_type: TransactionType) -> Self {
+        Transaction { client_id, tx_id, tx_type, timestamp: None, currency: None, seq: None, tenant: None }
+    }
+
+    pub fn with_timestamp(self, timestamp: u64) -> Self {
+        Transaction { timestamp: Some(timestamp), ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Account {
     #[serde(rename = "client")]
-    pub client_id: u16,
-    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    pub client_id: ClientId,
+    #[serde(
+        serialize_with = "custom_serde::serialize_decimal",
+        deserialize_with = "custom_serde::deserialize_balance"
+    )]
     pub available: Decimal,
-    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    #[serde(
+        serialize_with = "custom_serde::serialize_decimal",
+        deserialize_with = "custom_serde::deserialize_balance"
+    )]
     pub held: Decimal,
-    #[serde(serialize_with = "custom_serde::serialize_decimal")]
+    #[serde(
+        serialize_with = "custom_serde::serialize_decimal",
+        deserialize_with = "custom_serde::deserialize_balance"
+    )]
     pub total: Decimal,
     pub locked: bool,
 }
 
 impl Account {
-    pub fn new(client_id: u16) -> Self {
+    pub fn new(client_id: ClientId) -> Self {
         Account {
             client_id,
             available: dec!(0),
@@ -49,84 +199,603 @@ impl Account {
     }
 }
 
-mod custom_serde {
-    use std::fmt;
+pub mod custom_serde {
+    use std::{ fmt, num::ParseIntError, str::{ self, FromStr }, sync::atomic::{ AtomicBool, AtomicU32, AtomicU8, Ordering } };
 
-    use rust_decimal::prelude::FromPrimitive;
+    use csv::{ ByteRecord, StringRecord };
+    use rust_decimal::RoundingStrategy;
     use serde::{ Deserialize, Deserializer, Serializer };
 
     use super::*;
 
-    const PRECISION: u32 = 4;
+    pub const DEFAULT_PRECISION: u32 = 4;
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum RoundingMode {
+        /// Midpoints away from zero
+        HalfUp,
+        /// Midpoints to the even neighbour, banker's rounding
+        #[default]
+        HalfEven,
+        /// Drops the extra places
+        Truncate,
+    }
+
+    impl RoundingMode {
+        const ALL: [RoundingMode; 3] = [RoundingMode::HalfUp, RoundingMode::HalfEven, RoundingMode::Truncate];
+
+        fn strategy(&self) -> RoundingStrategy {
+            match self {
+                RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+                RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+                RoundingMode::Truncate => RoundingStrategy::ToZero,
+            }
+        }
+    }
+
+    impl fmt::Display for RoundingMode {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                RoundingMode::HalfUp => f.write_str("half-up"),
+                RoundingMode::HalfEven => f.write_str("half-even"),
+                RoundingMode::Truncate => f.write_str("truncate"),
+            }
+        }
+    }
+
+    impl FromStr for RoundingMode {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "half-up" => Ok(RoundingMode::HalfUp),
+                "half-even" => Ok(RoundingMode::HalfEven),
+                "truncate" => Ok(RoundingMode::Truncate),
+                _ => Err(format!("unknown rounding mode {}, expected half-up, half-even or truncate", s)),
+            }
+        }
+    }
+
+    /// How balances are rounded when they're read and written, 4 places with banker's rounding by
+    /// default. Transaction amounts are never rounded, see `--max-amount-scale`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Rounding {
+        pub precision: u32,
+        pub mode: RoundingMode,
+    }
+
+    impl Default for Rounding {
+        fn default() -> Self {
+            Rounding { precision: DEFAULT_PRECISION, mode: RoundingMode::default() }
+        }
+    }
+
+    impl Rounding {
+        pub fn round(&self, value: Decimal) -> Decimal {
+            value.round_dp_with_strategy(self.precision, self.mode.strategy())
+        }
+    }
+
+    // The serde functions can't be given arguments, so the rounding is set once for the process
+    static PRECISION: AtomicU32 = AtomicU32::new(DEFAULT_PRECISION);
+    static MODE: AtomicU8 = AtomicU8::new(RoundingMode::HalfEven as u8);
+    static NORMALIZE: AtomicBool = AtomicBool::new(false);
+    static STRICT_AMOUNTS: AtomicBool = AtomicBool::new(false);
+
+    /// The rounding of every balance serialized or deserialized from now on, set at startup.
+    pub fn set_rounding(rounding: Rounding) {
+        PRECISION.store(rounding.precision, Ordering::Relaxed);
+        MODE.store(rounding.mode as u8, Ordering::Relaxed);
+    }
+
+    /// Whether every balance serialized from now on drops its trailing zeros, set at startup. They
+    /// otherwise keep the scale they were computed with, e.g. `1.50` after a deposit of `1.50`.
+    pub fn set_normalize(normalize: bool) {
+        NORMALIZE.store(normalize, Ordering::Relaxed);
+    }
+
+    /// Whether every transaction deserialized from JSON from now on must have its amount as a
+    /// string, set at startup. A JSON number may have gone through a binary float in the program
+    /// that wrote it, whichever API or file it comes through.
+    pub fn set_strict_amounts(strict: bool) {
+        STRICT_AMOUNTS.store(strict, Ordering::Relaxed);
+    }
+
+    pub fn rounding() -> Rounding {
+        Rounding {
+            precision: PRECISION.load(Ordering::Relaxed),
+            mode: RoundingMode::ALL[MODE.load(Ordering::Relaxed) as usize],
+        }
+    }
 
     pub fn serialize_decimal<S>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
         where S: Serializer
     {
-        serializer.serialize_str(&value.round_dp(PRECISION).to_string())
+        let value = rounding().round(*value);
+
+        match NORMALIZE.load(Ordering::Relaxed) {
+            true => serializer.serialize_str(&value.normalize().to_string()),
+            false => serializer.serialize_str(&value.to_string()),
+        }
+    }
+
+    pub fn serialize_optional_decimal<S>(value: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        match value {
+            Some(value) => serialize_decimal(value, serializer),
+            None => serializer.serialize_none(),
+        }
     }
 
-    fn deserialize_decimal<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+    // serde_json hands the raw text of a value asked for under this name (it's what its `RawValue`
+    // asks for), the other formats see a plain newtype and are asked for a string. Either way the
+    // decimal is parsed from the text as written, csv's inference never turns it into a float.
+    const RAW_VALUE: &str = "$serde_json::private::RawValue";
+
+    // Balances are rounded with the `rounding()`, transaction amounts keep their scale so the
+    // engine can reject the ones with too many decimal places instead of silently changing them.
+    fn deserialize_decimal<'de, D>(
+        deserializer: D,
+        rounding: Option<Rounding>,
+        strict: bool
+    ) -> Result<Option<Decimal>, D::Error>
         where D: Deserializer<'de>
     {
-        struct Visitor;
+        struct Visitor {
+            rounding: Option<Rounding>,
+            // JSON numbers are rejected
+            strict: bool,
+        }
+
+        impl Visitor {
+            fn parse(&self, text: &str) -> Option<Decimal> {
+                let d = parse_decimal(text)?;
+
+                match self.rounding {
+                    Some(rounding) => Some(rounding.round(d)),
+                    None => Some(d),
+                }
+            }
+        }
 
         impl<'de> serde::de::Visitor<'de> for Visitor {
             type Value = Option<Decimal>;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a float as a string")
+                formatter.write_str("a decimal number")
             }
 
-            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> where E: serde::de::Error {
-                match Decimal::from_f64(v) {
-                    Some(d) => Ok(Some(d.round_dp(PRECISION))),
-                    None => Ok(None),
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+                where D: Deserializer<'de>
+            {
+                deserializer.deserialize_str(self)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+                where A: serde::de::MapAccess<'de>
+            {
+                map.next_key::<de::IgnoredAny>()?;
+                let raw: String = map.next_value()?;
+
+                match raw.as_str() {
+                    "null" => Ok(None),
+                    // A JSON string, its escapes are decoded before the text is parsed
+                    quoted if quoted.starts_with('"') => {
+                        let text = serde_json::from_str::<String>(quoted).map_err(de::Error::custom)?;
+                        Ok(self.parse(&text))
+                    }
+                    _ if self.strict => Err(de::Error::custom("amount isn't a string")),
+                    number => Ok(self.parse(number)),
                 }
             }
 
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where E: serde::de::Error {
-                match Decimal::from_str_radix(v, 10) {
-                    Ok(d) => Ok(Some(d.round_dp(PRECISION))),
-                    Err(_) => Ok(None),
-                }
+                Ok(self.parse(v))
+            }
+
+            fn visit_none<E>(self) -> Result<Self::Value, E> where E: serde::de::Error {
+                Ok(None)
+            }
+
+            fn visit_unit<E>(self) -> Result<Self::Value, E> where E: serde::de::Error {
+                Ok(None)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(RAW_VALUE, Visitor { rounding, strict })
+    }
+
+    /// Parses a decimal exactly as written, in plain (`1.50`) or scientific (`1.5e3`) notation.
+    /// Text with more digits than a `Decimal` holds is rejected rather than rounded.
+    pub fn parse_decimal(text: &str) -> Option<Decimal> {
+        let text = text.trim();
+
+        match text.contains(['e', 'E']) {
+            true => Decimal::from_scientific(text).ok(),
+            false => Decimal::from_str_exact(text).ok(),
+        }
+    }
+
+    pub fn deserialize_balance<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserialize_decimal(deserializer, Some(rounding()), false)?
+            .ok_or_else(|| de::Error::custom("invalid balance"))
+    }
+
+    fn deserialize_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserialize_decimal(deserializer, None, STRICT_AMOUNTS.load(Ordering::Relaxed))
+    }
+
+    pub fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserialize_optional_integer(deserializer, "a unix timestamp in seconds")
+    }
+
+    pub fn deserialize_seq<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserialize_optional_integer(deserializer, "a sequence number")
+    }
+
+    pub fn deserialize_optional_string<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let currency = Option::<String>::deserialize(deserializer)?;
+
+        Ok(currency.filter(|currency| !currency.is_empty()))
+    }
+
+    fn deserialize_client_id<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserialize_optional_integer(deserializer, "a client id")
+    }
+
+    // Read wide so an id over the range is told apart from one that isn't a number
+    fn deserialize_client<'de, D>(deserializer: D) -> Result<ClientId, D::Error>
+        where D: Deserializer<'de>
+    {
+        client_id(u64::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+
+    fn deserialize_optional_integer<'de, D>(
+        deserializer: D,
+        expecting: &'static str
+    ) -> Result<Option<u64>, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct Visitor(&'static str);
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Option<u64>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str(self.0)
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> where E: serde::de::Error {
+                Ok(Some(v))
             }
 
-            fn visit_string<E>(self, v: String) -> Result<Self::Value, E> where E: serde::de::Error {
-                match Decimal::from_str_radix(&v, 10) {
-                    Ok(d) => Ok(Some(d.round_dp(PRECISION))),
-                    Err(_) => Ok(None),
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where E: serde::de::Error {
+                match v {
+                    "" => Ok(None),
+                    _ => v.parse().map(Some).map_err(E::custom),
                 }
             }
+
+            fn visit_none<E>(self) -> Result<Self::Value, E> where E: serde::de::Error {
+                Ok(None)
+            }
+
+            fn visit_unit<E>(self) -> Result<Self::Value, E> where E: serde::de::Error {
+                Ok(None)
+            }
         }
 
-        deserializer.deserialize_any(Visitor)
+        deserializer.deserialize_any(Visitor(expecting))
     }
 
-    pub fn deserialize_transaction_type<'a, D>(deserializer: D) -> Result<TransactionType, D::Error>
-        where D: Deserializer<'a>
+    // The columns of every transaction type, the ones a type doesn't use are ignored
+    #[derive(Deserialize)]
+    struct Record {
+        #[serde(rename = "type")]
+        tx_type: String,
+        #[serde(deserialize_with = "deserialize_client")]
+        client: ClientId,
+        tx: u32,
+        #[serde(default, deserialize_with = "deserialize_amount")]
+        amount: Option<Decimal>,
+        #[serde(default, deserialize_with = "deserialize_timestamp")]
+        timestamp: Option<u64>,
+        #[serde(default, deserialize_with = "deserialize_optional_string")]
+        currency: Option<String>,
+        #[serde(default, deserialize_with = "deserialize_seq")]
+        seq: Option<u64>,
+        #[serde(default, deserialize_with = "deserialize_client_id")]
+        into: Option<u64>,
+        #[serde(default, deserialize_with = "deserialize_optional_string")]
+        reason: Option<String>,
+        #[serde(default, deserialize_with = "deserialize_client_id")]
+        to_client: Option<u64>,
+        #[serde(default, deserialize_with = "deserialize_optional_string")]
+        to_currency: Option<String>,
+        #[serde(default, deserialize_with = "deserialize_optional_string")]
+        tenant: Option<String>,
+    }
+
+    fn serialize_amount<S>(value: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
     {
-        #[derive(Deserialize, Debug)]
-        struct Helper {
-            #[serde(rename = "type")]
-            tx_type: String,
-            #[serde(deserialize_with = "deserialize_decimal")]
-            amount: Option<Decimal>,
+        match value {
+            Some(value) => serializer.serialize_str(&value.normalize().to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    // The same columns as `Record`, every one of them is written so CSV rows line up
+    #[derive(Serialize)]
+    struct FlatRecord<'a> {
+        #[serde(rename = "type")]
+        tx_type: &'static str,
+        client: ClientId,
+        tx: u32,
+        #[serde(serialize_with = "serialize_amount")]
+        amount: Option<Decimal>,
+        timestamp: Option<u64>,
+        currency: Option<&'a str>,
+        seq: Option<u64>,
+        into: Option<ClientId>,
+        reason: Option<&'a str>,
+        to_client: Option<ClientId>,
+        to_currency: Option<&'a str>,
+        tenant: Option<&'a str>,
+    }
+
+    impl Serialize for Transaction {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            let (into, reason, to_client, to_currency) = match &self.tx_type {
+                TransactionType::Merge(into) => (Some(*into), None, None, None),
+                TransactionType::Adjustment { reason, .. } => (None, Some(reason.as_str()), None, None),
+                TransactionType::Transfer { to, .. } => (None, None, Some(*to), None),
+                TransactionType::Convert { to, .. } => (None, None, None, Some(to.as_str())),
+                _ => (None, None, None, None),
+            };
+
+            FlatRecord {
+                tx_type: self.tx_type.name(),
+                client: self.client_id,
+                tx: self.tx_id,
+                amount: self.tx_type.amount(),
+                timestamp: self.timestamp,
+                currency: self.currency.as_deref(),
+                seq: self.seq,
+                into,
+                reason,
+                to_client,
+                to_currency,
+                tenant: self.tenant.as_deref(),
+            }.serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Transaction {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            let Record {
+                tx_type,
+                client,
+                tx,
+                amount,
+                timestamp,
+                currency,
+                seq,
+                into,
+                reason,
+                to_client,
+                to_currency,
+                tenant,
+            } = Record::deserialize(deserializer)?;
+
+            Ok(Transaction {
+                client_id: client,
+                tx_id: tx,
+                timestamp,
+                currency,
+                seq,
+                tenant,
+                tx_type: transaction_type(TypeFields { tx_type: &tx_type, amount, into, reason, to_client, to_currency })?,
+            })
+        }
+    }
+
+    /// The positions of the columns of a CSV header, to parse its rows into transactions straight
+    /// from the bytes of the fields, without going through serde and an owned `String` per field. A
+    /// row is parsed like the `Deserialize` of `Transaction` parses it, except that an amount that
+    /// isn't a number is an error rather than a missing amount.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct CsvColumns {
+        tx_type: usize,
+        client: usize,
+        tx: usize,
+        amount: Option<usize>,
+        timestamp: Option<usize>,
+        currency: Option<usize>,
+        seq: Option<usize>,
+        into: Option<usize>,
+        reason: Option<usize>,
+        to_client: Option<usize>,
+        to_currency: Option<usize>,
+        tenant: Option<usize>,
+    }
+
+    impl CsvColumns {
+        /// None when a required column is missing or a column appears more than once, the rows of
+        /// such a header are left to serde, which reports them.
+        pub fn new(headers: &StringRecord) -> Option<Self> {
+            Some(CsvColumns {
+                tx_type: column(headers, "type")??,
+                client: column(headers, "client")??,
+                tx: column(headers, "tx")??,
+                amount: column(headers, "amount")?,
+                timestamp: column(headers, "timestamp")?,
+                currency: column(headers, "currency")?,
+                seq: column(headers, "seq")?,
+                into: column(headers, "into")?,
+                reason: column(headers, "reason")?,
+                to_client: column(headers, "to_client")?,
+                to_currency: column(headers, "to_currency")?,
+                tenant: column(headers, "tenant")?,
+            })
+        }
+
+        pub fn parse(&self, record: &ByteRecord) -> Result<Transaction, String> {
+            let text = |column: usize| {
+                let field = record.get(column)?;
+
+                Some(str::from_utf8(field).map_err(|_| format!("invalid UTF-8 in column {}", column + 1)))
+            };
+            let required = |column: usize, name: &str| {
+                text(column).unwrap_or_else(|| Err(format!("missing field `{}`", name)))
+            };
+            // Like csv's, an empty field is a missing value
+            let optional = |column: Option<usize>| {
+                column
+                    .and_then(text)
+                    .transpose()
+                    .map(|field| field.filter(|field| !field.is_empty()))
+            };
+            let integer = |column: Option<usize>, name: &str| {
+                optional(column)?
+                    .map(|field| field.parse::<u64>().map_err(|err| format!("invalid {}: {}", name, err)))
+                    .transpose()
+            };
+            let amount = match (self.amount, optional(self.amount)?) {
+                (Some(column), Some(field)) => {
+                    Some(parse_decimal(field).ok_or_else(|| format!("invalid amount in column {}", column + 1))?)
+                }
+                _ => None,
+            };
+
+            let client = parse_u64(required(self.client, "client")?)
+                .map_err(|err| err.to_string())
+                .and_then(|client| client_id(client).map_err(|err| err.to_string()))
+                .map_err(|err| format!("invalid client: {}", err))?;
+            let tx = parse_u32(required(self.tx, "tx")?).map_err(|err| format!("invalid tx: {}", err))?;
+
+            let tx_type = transaction_type::<de::value::Error>(TypeFields {
+                tx_type: required(self.tx_type, "type")?,
+                amount,
+                into: integer(self.into, "into")?,
+                reason: optional(self.reason)?.map(String::from),
+                to_client: integer(self.to_client, "to_client")?,
+                to_currency: optional(self.to_currency)?.map(String::from),
+            });
+
+            Ok(Transaction {
+                client_id: client,
+                tx_id: tx,
+                tx_type: tx_type.map_err(|err| err.to_string())?,
+                timestamp: integer(self.timestamp, "timestamp")?,
+                currency: optional(self.currency)?.map(String::from),
+                seq: integer(self.seq, "seq")?,
+                tenant: optional(self.tenant)?.map(String::from),
+            })
+        }
+    }
+
+    // The position of a column, None when it's there more than once
+    fn column(headers: &StringRecord, name: &str) -> Option<Option<usize>> {
+        let mut positions = headers
+            .iter()
+            .enumerate()
+            .filter(|(_, header)| *header == name)
+            .map(|(column, _)| column);
+
+        match (positions.next(), positions.next()) {
+            (position, None) => Some(position),
+            _ => None,
+        }
+    }
+
+    // csv reads integers with a `0x` prefix as hexadecimal
+    fn parse_u32(field: &str) -> Result<u32, ParseIntError> {
+        match field.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => field.parse(),
+        }
+    }
+
+    fn parse_u64(field: &str) -> Result<u64, ParseIntError> {
+        match field.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => field.parse(),
+        }
+    }
+
+    // The fields the type of a transaction is made of
+    struct TypeFields<'a> {
+        tx_type: &'a str,
+        amount: Option<Decimal>,
+        into: Option<u64>,
+        reason: Option<String>,
+        to_client: Option<u64>,
+        to_currency: Option<String>,
+    }
+
+    fn transaction_type<E: de::Error>(record: TypeFields) -> Result<TransactionType, E> {
+        if record.tx_type == "merge" {
+            let into = record.into.ok_or_else(|| de::Error::custom("merge requires a valid into client"))?;
+
+            return client_id(into).map(TransactionType::Merge).map_err(de::Error::custom);
         }
 
-        let helper = Helper::deserialize(deserializer)?;
+        if record.tx_type == "adjustment" {
+            return match (record.amount, record.reason) {
+                (Some(amount), Some(reason)) => Ok(TransactionType::Adjustment { amount, reason }),
+                _ => Err(de::Error::custom("adjustment requires an amount and a reason")),
+            };
+        }
+
+        if record.tx_type == "transfer" {
+            let to = record.to_client.map(client_id).transpose().map_err(de::Error::custom)?;
+
+            return match (to, record.amount) {
+                (Some(to), Some(amount)) => Ok(TransactionType::Transfer { to, amount }),
+                _ => Err(de::Error::custom("transfer requires an amount and a valid to_client")),
+            };
+        }
+
+        if record.tx_type == "convert" {
+            return match (record.to_currency, record.amount) {
+                (Some(to), Some(amount)) => Ok(TransactionType::Convert { to, amount }),
+                _ => Err(de::Error::custom("convert requires an amount and a to_currency")),
+            };
+        }
 
-        match (helper.tx_type.as_str(), helper.amount) {
+        match (record.tx_type, record.amount) {
             ("deposit", Some(amount)) => Ok(TransactionType::Deposit(amount)),
             ("withdrawal", Some(amount)) => Ok(TransactionType::Withdrawal(amount)),
             ("dispute", _) => Ok(TransactionType::Dispute),
             ("resolve", _) => Ok(TransactionType::Resolve),
             ("chargeback", _) => Ok(TransactionType::Chargeback),
+            ("representment", _) => Ok(TransactionType::Representment),
+            ("recovery", Some(amount)) => Ok(TransactionType::Recovery(amount)),
+            ("approve", _) => Ok(TransactionType::Approve),
+            ("decline", _) => Ok(TransactionType::Decline),
+            ("unlock", _) => Ok(TransactionType::Unlock),
+            ("delete", _) => Ok(TransactionType::Delete),
+            ("restore", _) => Ok(TransactionType::Restore),
+            ("withdraw_hold" | "authorize", Some(amount)) => Ok(TransactionType::WithdrawHold(amount)),
+            ("withdraw_commit" | "capture", _) => Ok(TransactionType::WithdrawCommit),
+            ("withdraw_void" | "void", _) => Ok(TransactionType::WithdrawVoid),
             _ =>
                 Err(
-                    de::Error::unknown_variant(
-                        helper.tx_type.as_str(),
-                        &["deposit", "withdrawal", "dispute", "resolve", "chargeback"]
-                    )
+                    de::Error::unknown_variant(record.tx_type, &TransactionType::NAMES)
                 ),
         }
     }
@@ -137,6 +806,7 @@ mod tests {
     use std::vec;
 
     use super::*;
+    use custom_serde::{ Rounding, RoundingMode };
 
     #[test]
     fn deserialize_deposit() {
@@ -144,11 +814,199 @@ mod tests {
         let mut reader = csv::Reader::from_reader(input.as_bytes());
 
         let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
-        assert_eq!(tx, Transaction {
-            client_id: 10,
-            tx_id: 20,
-            tx_type: TransactionType::Deposit(dec!(30.123)),
-        });
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Deposit(dec!(30.123))));
+    }
+
+    #[test]
+    fn parse_csv_columns_like_serde() {
+        let input = "seq, amount,type,client,tx,timestamp,currency,into,reason,to_client,to_currency,tenant\n\
+                     1,1.50,deposit,1,1,,,,,,,acme\n\
+                     2,1e2,withdrawal,0x10,0x20,1700000000,EUR,,,,,\n\
+                     ,,dispute,1,1,,,,,,,\n\
+                     ,,merge,1,2,,,3,,,,\n\
+                     ,-2.5,adjustment,1,3,,,,fix,,,\n\
+                     ,5,transfer,1,4,,,,,2,,\n\
+                     ,5,convert,1,5,,USD,,,,EUR,\n\
+                     ,,deposit,1,6,,,,,,,\n\
+                     ,1,deposit,70000,7,,,,,,,\n\
+                     ,1,deposit,1,8,-1,,,,,,\n\
+                     ,1,refund,1,9,,,,,,,\n\
+                     ,1,transfer,1,10,,,,,70000,,\n";
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        let columns = custom_serde::CsvColumns::new(&headers).unwrap();
+
+        for record in reader.records() {
+            let record = record.unwrap();
+
+            assert_eq!(
+                columns.parse(record.as_byte_record()).ok(),
+                record.deserialize::<Transaction>(Some(&headers)).ok(),
+                "{:?}",
+                record
+            );
+        }
+
+        let repeated = csv::StringRecord::from(vec!["type", "client", "tx", "amount", "amount"]);
+        assert_eq!(custom_serde::CsvColumns::new(&repeated), None);
+
+        let missing = csv::StringRecord::from(vec!["type", "client", "amount"]);
+        assert_eq!(custom_serde::CsvColumns::new(&missing), None);
+    }
+
+    #[test]
+    fn parse_csv_columns_invalid_fields() {
+        let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount", "reason"]);
+        let columns = custom_serde::CsvColumns::new(&headers).unwrap();
+
+        let amount = csv::ByteRecord::from(vec!["deposit", "1", "2", "1.2.3", ""]);
+        assert_eq!(columns.parse(&amount).unwrap_err(), "invalid amount in column 4");
+
+        let reason = csv::ByteRecord::from(vec![&b"adjustment"[..], b"1", b"2", b"-1", b"FX\xff"]);
+        assert_eq!(columns.parse(&reason).unwrap_err(), "invalid UTF-8 in column 5");
+
+        let empty = csv::ByteRecord::from(vec!["dispute", "1", "2", "", ""]);
+        assert_eq!(columns.parse(&empty), Ok(Transaction::new(1, 2, TransactionType::Dispute)));
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let transactions = vec![
+            Transaction { seq: Some(3), ..Transaction::new(1, 1, TransactionType::Deposit(dec!(1.50))) },
+            Transaction::new(1, 1, TransactionType::Dispute).with_timestamp(1_700_000_000),
+            Transaction::new(1, 2, TransactionType::Merge(3)),
+            Transaction::new(1, 3, TransactionType::Adjustment { amount: dec!(-2.5), reason: "fix, ops".to_string() }),
+            Transaction::new(1, 4, TransactionType::Transfer { to: 2, amount: dec!(5) }),
+            Transaction {
+                currency: Some("EUR".to_string()),
+                ..Transaction::new(1, 5, TransactionType::Convert { to: "USD".to_string(), amount: dec!(1e2) })
+            },
+            Transaction { tenant: Some("acme".to_string()), ..Transaction::new(1, 6, TransactionType::Deposit(dec!(2))) }
+        ];
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+
+        for tx in transactions.iter() {
+            writer.serialize(tx).unwrap();
+        }
+
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        assert_eq!(
+            csv.lines().take(2).collect::<Vec<_>>(),
+            vec![
+                "type,client,tx,amount,timestamp,currency,seq,into,reason,to_client,to_currency,tenant",
+                "deposit,1,1,1.5,,,3,,,,,"
+            ]
+        );
+
+        let read: Vec<Transaction> = csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(read, transactions);
+
+        for tx in transactions {
+            assert_eq!(serde_json::from_str::<Transaction>(&serde_json::to_string(&tx).unwrap()).unwrap(), tx);
+        }
+
+        assert_eq!(
+            serde_json::to_string(&Transaction::new(7, 8, TransactionType::Withdrawal(dec!(0.10)))).unwrap(),
+            concat!(
+                r#"{"type":"withdrawal","client":7,"tx":8,"amount":"0.1","timestamp":null,"currency":null,"#,
+                r#""seq":null,"into":null,"reason":null,"to_client":null,"to_currency":null,"tenant":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn deserialize_deposit_keeps_scale() {
+        let input = "type,client,tx,amount\ndeposit,10,20,0.123456\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
+        assert_eq!(tx.tx_type.amount().unwrap().scale(), 6);
+    }
+
+    #[test]
+    fn deserialize_deposit_exact_text() {
+        let input = "type,client,tx,amount\ndeposit,10,20,1.10\ndeposit,10,21,0.1000000000000000055511151231\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let amounts: Vec<Decimal> = reader
+            .deserialize::<Transaction>()
+            .map(|tx| tx.unwrap().tx_type.amount().unwrap())
+            .collect();
+        assert_eq!(amounts[0].to_string(), "1.10");
+        assert_eq!(amounts[1].to_string(), "0.1000000000000000055511151231");
+    }
+
+    #[test]
+    fn deserialize_json_amounts() {
+        let tx = |line: &str| serde_json::from_str::<Transaction>(line).map(|tx| tx.tx_type.amount());
+
+        assert_eq!(
+            tx(r#"{"type":"deposit","client":1,"tx":1,"amount":0.30}"#).unwrap().unwrap().to_string(),
+            "0.30"
+        );
+        assert_eq!(tx(r#"{"type":"deposit","client":1,"tx":1,"amount":"2.5"}"#).unwrap(), Some(dec!(2.5)));
+        assert_eq!(tx(r#"{"type":"deposit","client":1,"tx":1,"amount":1.5e2}"#).unwrap(), Some(dec!(150)));
+        assert_eq!(tx(r#"{"type":"dispute","client":1,"tx":1,"amount":null}"#).unwrap(), None);
+        assert!(tx(r#"{"type":"deposit","client":1,"tx":1,"amount":"abc"}"#).is_err());
+    }
+
+    #[test]
+    fn deserialize_deposit_integer_amount() {
+        let input = "type,client,tx,amount\ndeposit,10,20,30\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Deposit(dec!(30))));
+    }
+
+    #[test]
+    fn deserialize_deposit_with_timestamp() {
+        let input = "type,client,tx,amount,timestamp\ndeposit,10,20,30,1700000000\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
+        assert_eq!(
+            tx,
+            Transaction::new(10, 20, TransactionType::Deposit(dec!(30))).with_timestamp(1700000000)
+        );
+    }
+
+    #[test]
+    fn deserialize_optional_columns() {
+        let input = "type,client,tx,amount,currency,seq,note\ndeposit,1,2,3,EUR,7,x\ndispute,1,2,,,,\n";
+        let mut reader = csv::ReaderBuilder::new().from_reader(input.as_bytes());
+
+        let txs: Vec<Transaction> = reader
+            .deserialize()
+            .map(|tx| tx.unwrap())
+            .collect();
+
+        assert_eq!(txs[0].currency.as_deref(), Some("EUR"));
+        assert_eq!(txs[0].seq, Some(7));
+        assert_eq!(txs[1], Transaction::new(1, 2, TransactionType::Dispute));
+    }
+
+    #[test]
+    fn deserialize_dispute_empty_timestamp() {
+        let input = "type,client,tx,amount,timestamp\ndispute,10,20,,\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Dispute));
+    }
+
+    #[test]
+    fn deserialize_invalid_timestamp() {
+        let input = "type,client,tx,amount,timestamp\ndeposit,10,20,30,yesterday\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        assert!(reader.deserialize::<Transaction>().next().unwrap().is_err());
     }
 
     #[test]
@@ -157,11 +1015,7 @@ mod tests {
         let mut reader = csv::Reader::from_reader(input.as_bytes());
 
         let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
-        assert_eq!(tx, Transaction {
-            client_id: 10,
-            tx_id: 20,
-            tx_type: TransactionType::Withdrawal(dec!(30.123)),
-        });
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Withdrawal(dec!(30.123))));
     }
 
     #[test]
@@ -170,11 +1024,7 @@ mod tests {
         let mut reader = csv::Reader::from_reader(input.as_bytes());
 
         let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
-        assert_eq!(tx, Transaction {
-            client_id: 10,
-            tx_id: 20,
-            tx_type: TransactionType::Dispute,
-        });
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Dispute));
     }
 
     #[test]
@@ -183,11 +1033,7 @@ mod tests {
         let mut reader = csv::Reader::from_reader(input.as_bytes());
 
         let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
-        assert_eq!(tx, Transaction {
-            client_id: 10,
-            tx_id: 20,
-            tx_type: TransactionType::Resolve,
-        });
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Resolve));
     }
 
     #[test]
@@ -196,11 +1042,152 @@ mod tests {
         let mut reader = csv::Reader::from_reader(input.as_bytes());
 
         let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
-        assert_eq!(tx, Transaction {
-            client_id: 10,
-            tx_id: 20,
-            tx_type: TransactionType::Chargeback,
-        });
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Chargeback));
+    }
+
+    #[test]
+    fn deserialize_representment() {
+        let input = "type,client,tx,amount\nrepresentment,10,20,\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Representment));
+    }
+
+    #[test]
+    fn deserialize_recovery() {
+        let input = "type,client,tx,amount\nrecovery,10,20,5.5\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Recovery(dec!(5.5))));
+    }
+
+    #[test]
+    fn deserialize_withdraw_hold_commit() {
+        let input = "type,client,tx,amount\nwithdraw_hold,10,20,5\nwithdraw_commit,10,20,\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let txs: Vec<Transaction> = reader
+            .deserialize()
+            .map(|record| record.unwrap())
+            .collect();
+        assert_eq!(txs, vec![
+            Transaction::new(10, 20, TransactionType::WithdrawHold(dec!(5))),
+            Transaction::new(10, 20, TransactionType::WithdrawCommit)
+        ]);
+    }
+
+    #[test]
+    fn deserialize_authorize_capture_void() {
+        let input = "type,client,tx,amount
+authorize,10,20,5
+capture,10,20,
+authorize,10,21,2
+void,10,21,
+\
+                     withdraw_void,10,22,
+";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let txs: Vec<Transaction> = reader
+            .deserialize()
+            .map(|record| record.unwrap())
+            .collect();
+        assert_eq!(txs, vec![
+            Transaction::new(10, 20, TransactionType::WithdrawHold(dec!(5))),
+            Transaction::new(10, 20, TransactionType::WithdrawCommit),
+            Transaction::new(10, 21, TransactionType::WithdrawHold(dec!(2))),
+            Transaction::new(10, 21, TransactionType::WithdrawVoid),
+            Transaction::new(10, 22, TransactionType::WithdrawVoid)
+        ]);
+    }
+
+    #[test]
+    fn deserialize_approve_decline() {
+        let input = "type,client,tx,amount\napprove,10,20,\ndecline,10,21,\nunlock,10,22,\n\
+                     delete,10,23,\nrestore,10,24,\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let txs: Vec<Transaction> = reader
+            .deserialize()
+            .map(|record| record.unwrap())
+            .collect();
+        assert_eq!(txs, vec![
+            Transaction::new(10, 20, TransactionType::Approve),
+            Transaction::new(10, 21, TransactionType::Decline),
+            Transaction::new(10, 22, TransactionType::Unlock),
+            Transaction::new(10, 23, TransactionType::Delete),
+            Transaction::new(10, 24, TransactionType::Restore)
+        ]);
+    }
+
+    #[test]
+    fn deserialize_merge() {
+        let input = "type,client,tx,amount,into\nmerge,10,20,,11\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let tx = reader.deserialize::<Transaction>().next().unwrap().unwrap();
+        assert_eq!(tx, Transaction::new(10, 20, TransactionType::Merge(11)));
+    }
+
+    #[test]
+    fn deserialize_merge_without_into() {
+        let input = format!("type,client,tx,amount,into\nmerge,10,20,,\nmerge,10,21,,{}\n", u64::from(ClientId::MAX) + 1);
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        assert!(reader.deserialize::<Transaction>().all(|record| record.is_err()));
+    }
+
+    #[test]
+    fn deserialize_client_out_of_range() {
+        let wide = u64::from(ClientId::MAX) + 1;
+        let message = format!("client id {} is out of range, the largest is {}", wide, ClientId::MAX);
+
+        let input = format!("type,client,tx,amount\ndeposit,{},1,1\ndeposit,{},2,1\n", ClientId::MAX, wide);
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut records = reader.deserialize::<Transaction>();
+
+        assert_eq!(records.next().unwrap().unwrap().client_id, ClientId::MAX);
+        assert!(records.next().unwrap().unwrap_err().to_string().ends_with(&message));
+
+        let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let columns = custom_serde::CsvColumns::new(&headers).unwrap();
+        let record = csv::ByteRecord::from(vec!["deposit".to_string(), wide.to_string(), "2".to_string(), "1".to_string()]);
+
+        assert_eq!(columns.parse(&record).unwrap_err(), format!("invalid client: {}", message));
+
+        let json = format!(r#"{{"type":"transfer","client":1,"tx":3,"amount":"1","to_client":{}}}"#, wide);
+        assert!(serde_json::from_str::<Transaction>(&json).unwrap_err().to_string().starts_with(&message));
+    }
+
+    #[test]
+    fn deserialize_adjustment() {
+        let input = "type,client,tx,amount,reason\nadjustment,10,20,-2.5,FX_CORRECTION\nadjustment,10,21,1,\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut records = reader.deserialize::<Transaction>();
+
+        assert_eq!(
+            records.next().unwrap().unwrap(),
+            Transaction::new(10, 20, TransactionType::Adjustment {
+                amount: dec!(-2.5),
+                reason: "FX_CORRECTION".to_string(),
+            })
+        );
+        assert!(records.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn deserialize_transfer() {
+        let input = "type,client,tx,amount,to_client\ntransfer,10,20,2.5,11\ntransfer,10,21,2.5,\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut records = reader.deserialize::<Transaction>();
+
+        assert_eq!(
+            records.next().unwrap().unwrap(),
+            Transaction::new(10, 20, TransactionType::Transfer { to: 11, amount: dec!(2.5) })
+        );
+        assert!(records.next().unwrap().is_err());
     }
 
     #[test]
@@ -226,4 +1213,56 @@ mod tests {
         let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
         assert_eq!(output, "client,available,held,total,locked\n1,0.1235,0,0,true\n");
     }
+
+    #[test]
+    fn rounding_modes() {
+        let round = |precision, mode: &str, value| Rounding { precision, mode: mode.parse().unwrap() }.round(value);
+
+        assert_eq!(round(2, "half-up", dec!(1.005)), dec!(1.01));
+        assert_eq!(round(2, "half-up", dec!(-1.005)), dec!(-1.01));
+        assert_eq!(round(2, "half-even", dec!(1.005)), dec!(1.00));
+        assert_eq!(round(2, "half-even", dec!(1.015)), dec!(1.02));
+        assert_eq!(round(2, "truncate", dec!(1.0199)), dec!(1.01));
+        assert_eq!(round(2, "truncate", dec!(-1.0199)), dec!(-1.01));
+        assert_eq!(round(4, "half-even", dec!(0.123456789)), dec!(0.1235));
+        assert!("ceiling".parse::<RoundingMode>().is_err());
+        assert_eq!(Rounding::default(), Rounding { precision: 4, mode: RoundingMode::HalfEven });
+    }
+
+    #[test]
+    fn serialize_account_keeps_scale() {
+        let mut account = Account::new(1);
+        account.available = dec!(1.50000);
+        account.held = dec!(-0.00001);
+        account.total = dec!(100);
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(account).unwrap();
+
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "client,available,held,total,locked\n1,1.5000,0.0000,100,false\n");
+    }
+
+    #[test]
+    fn deserialize_account() {
+        let input = "client,available,held,total,locked\n1,1.5,0.5,2.0,true\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        let account = reader.deserialize::<Account>().next().unwrap().unwrap();
+        assert_eq!(account, Account {
+            client_id: 1,
+            available: dec!(1.5),
+            held: dec!(0.5),
+            total: dec!(2.0),
+            locked: true,
+        });
+    }
+
+    #[test]
+    fn deserialize_account_invalid_balance() {
+        let input = "client,available,held,total,locked\n1,abc,0,0,false\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+
+        assert!(reader.deserialize::<Account>().next().unwrap().is_err());
+    }
 }
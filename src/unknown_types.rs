@@ -0,0 +1,82 @@
+use std::io;
+
+use csv::StringRecord;
+use serde::Serialize;
+
+use crate::provenance::Provenance;
+
+/// Receives the rows with a transaction type the engine doesn't know, instead of only counting
+/// them as `UNKNOWN_TYPE` rejections.
+pub trait UnknownTypeHandler: Send {
+    fn on_unknown_type(&mut self, provenance: &Provenance, row: &StringRecord);
+}
+
+// Keeps the rows as they were read so they can be replayed once the type is supported. The row is
+// a csv line of its own in the `row` column, as files may have different columns.
+pub struct UnknownTypeFile<W: io::Write> {
+    writer: csv::Writer<W>,
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    source: &'a str,
+    line: u64,
+    row: String,
+}
+
+impl<W: io::Write> UnknownTypeFile<W> {
+    pub fn new(writer: W) -> Self {
+        UnknownTypeFile { writer: csv::Writer::from_writer(writer) }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: io::Write + Send> UnknownTypeHandler for UnknownTypeFile<W> {
+    fn on_unknown_type(&mut self, provenance: &Provenance, row: &StringRecord) {
+        let result = encode(row).and_then(|row| {
+            self.writer.serialize(Row { source: &provenance.source, line: provenance.line, row })
+        });
+
+        if let Err(err) = result {
+            log::error!("Failed to write the unknown type row at {}: {}", provenance, err);
+        }
+    }
+}
+
+pub(crate) fn encode(row: &StringRecord) -> csv::Result<String> {
+    let mut writer = csv::WriterBuilder
+        ::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    writer.write_record(row)?;
+
+    let bytes = writer.into_inner().map_err(|err| err.into_error())?;
+    let line = String::from_utf8_lossy(&bytes);
+
+    Ok(line.trim_end_matches('\n').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_type_file() {
+        let mut output = vec![];
+        let mut file = UnknownTypeFile::new(&mut output);
+
+        let provenance = Provenance { source: "a.csv".into(), line: 3, offset: 40 };
+        file.on_unknown_type(&provenance, &StringRecord::from(vec!["refund", "1", "7", "1.5"]));
+        file.on_unknown_type(&provenance, &StringRecord::from(vec!["note", "1", "8", "a,b"]));
+        file.flush().unwrap();
+        drop(file);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "source,line,row\na.csv,3,\"refund,1,7,1.5\"\na.csv,3,\"note,1,8,\"\"a,b\"\"\"\n"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use rust_decimal::Decimal;
+
+use crate::{ error::EngineError, types::{ Account, Transaction, TransactionType } };
+
+pub const DEFAULT_MAX_SCALE: u32 = 4;
+
+// Validators are checked before anything else looks at the transaction, once for every account it
+// changes (both clients of a transfer), the first error rejects it.
+pub trait Validator: Send {
+    fn validate(&self, tx: &Transaction, account: &Account) -> Result<(), EngineError>;
+}
+
+/// Rejects amounts that are negative or zero, have more decimal places than `max_scale`, or would
+/// overflow a balance. Adjustments are signed, only zero is rejected for them.
+#[derive(Debug, Clone, Copy)]
+pub struct AmountValidator {
+    pub max_scale: u32,
+}
+
+impl Default for AmountValidator {
+    fn default() -> Self {
+        AmountValidator { max_scale: DEFAULT_MAX_SCALE }
+    }
+}
+
+impl Validator for AmountValidator {
+    fn validate(&self, tx: &Transaction, account: &Account) -> Result<(), EngineError> {
+        let Some(amount) = tx.tx_type.amount() else {
+            return Ok(());
+        };
+
+        let signed = matches!(tx.tx_type, TransactionType::Adjustment { .. });
+
+        if amount.is_zero() || (amount < Decimal::ZERO && !signed) {
+            return Err(EngineError::InvalidAmount(amount));
+        }
+
+        if amount.normalize().scale() > self.max_scale {
+            return Err(EngineError::TooPrecise { amount, max_scale: self.max_scale });
+        }
+
+        let overflows = [account.available, account.held, account.total]
+            .into_iter()
+            .any(|balance| balance.checked_add(amount).is_none() || balance.checked_sub(amount).is_none());
+
+        match overflows {
+            true => Err(EngineError::AmountOverflow(amount)),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_amount_validator() {
+        let validator = AmountValidator::default();
+        let account = Account::new(1);
+        let validate = |tx_type| validator.validate(&Transaction::new(1, 1, tx_type), &account);
+
+        assert_eq!(validate(TransactionType::Deposit(dec!(1.2345))), Ok(()));
+        assert_eq!(validate(TransactionType::Deposit(dec!(1.50000))), Ok(()));
+        assert_eq!(validate(TransactionType::Dispute), Ok(()));
+        assert_eq!(
+            validate(TransactionType::Deposit(dec!(-50.0))),
+            Err(EngineError::InvalidAmount(dec!(-50.0)))
+        );
+        assert_eq!(validate(TransactionType::Withdrawal(dec!(0))), Err(EngineError::InvalidAmount(dec!(0))));
+        assert_eq!(
+            validate(TransactionType::Withdrawal(dec!(0.00001))),
+            Err(EngineError::TooPrecise { amount: dec!(0.00001), max_scale: 4 })
+        );
+        assert_eq!(
+            validate(TransactionType::Adjustment { amount: dec!(-2), reason: "fix".to_string() }),
+            Ok(())
+        );
+
+        let rich = Account { available: Decimal::MAX, total: Decimal::MAX, ..Account::new(1) };
+
+        assert_eq!(
+            validator.validate(&Transaction::new(1, 2, TransactionType::Deposit(dec!(1))), &rich),
+            Err(EngineError::AmountOverflow(dec!(1)))
+        );
+        assert_eq!(
+            AmountValidator { max_scale: 6 }.validate(
+                &Transaction::new(1, 3, TransactionType::Deposit(dec!(0.00001))),
+                &account
+            ),
+            Ok(())
+        );
+    }
+}
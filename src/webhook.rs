@@ -0,0 +1,210 @@
+use std::{
+    fmt,
+    io::{ self, Read, Write },
+};
+
+use crate::{
+    delivery::{ self, DeliveryLimits, DeliveryWorker },
+    observer::{ AccountObserver, LifecycleEvent },
+};
+
+#[derive(Debug)]
+pub struct InvalidUrl(String);
+
+impl fmt::Display for InvalidUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid webhook url {}, expected http://host[:port][/path]", self.0)
+    }
+}
+
+impl std::error::Error for InvalidUrl {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self, InvalidUrl> {
+        let invalid = || InvalidUrl(url.to_string());
+
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Endpoint { host: host.to_string(), port, path: path.to_string() })
+    }
+
+    fn post(&self, body: &str, limits: &DeliveryLimits) -> io::Result<()> {
+        let mut stream = delivery::connect(&self.host, self.port, limits)?;
+
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        )?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        match response.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            status => Err(io::Error::other(format!("unexpected response status {:?}", status))),
+        }
+    }
+}
+
+/// Posts the lifecycle events to a webhook from a worker thread, see [`DeliveryWorker`] for what
+/// happens to them when the endpoint is slow or hangs.
+pub struct WebhookObserver {
+    worker: DeliveryWorker<String>,
+}
+
+impl WebhookObserver {
+    pub fn new(url: &str) -> Result<Self, InvalidUrl> {
+        Self::with_limits(url, DeliveryLimits::default())
+    }
+
+    pub fn with_limits(url: &str, limits: DeliveryLimits) -> Result<Self, InvalidUrl> {
+        let endpoint = Endpoint::parse(url)?;
+
+        let worker = DeliveryWorker::spawn("webhook", &limits, move |body: String| {
+            if let Err(err) = endpoint.post(&body, &limits) {
+                log::error!("Failed to deliver webhook {}: {}", body, err);
+            }
+        });
+
+        Ok(WebhookObserver { worker })
+    }
+
+    /// The events dropped because the endpoint couldn't keep up.
+    pub fn dropped(&self) -> u64 {
+        self.worker.dropped()
+    }
+}
+
+impl AccountObserver for WebhookObserver {
+    fn on_lifecycle_event(&mut self, event: &LifecycleEvent) {
+        let Ok(body) = serde_json::to_string(event) else {
+            log::error!("Failed to serialize lifecycle event {:?}", event);
+            return;
+        };
+
+        self.worker.send(body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ io::BufRead, io::BufReader, net::TcpListener, thread, time::{ Duration, Instant } };
+
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(Endpoint::parse("http://localhost:8080/hooks/crm").unwrap(), Endpoint {
+            host: "localhost".to_string(),
+            port: 8080,
+            path: "/hooks/crm".to_string(),
+        });
+        assert_eq!(Endpoint::parse("http://example.com").unwrap(), Endpoint {
+            host: "example.com".to_string(),
+            port: 80,
+            path: "/".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_parse_invalid_endpoint() {
+        assert!(Endpoint::parse("https://example.com").is_err());
+        assert!(Endpoint::parse("http://:80/").is_err());
+        assert!(Endpoint::parse("http://example.com:port/").is_err());
+    }
+
+    #[test]
+    fn test_webhook_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut content_length = 0;
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    content_length = value.trim().parse().unwrap();
+                }
+
+                if line == "\r\n" {
+                    break;
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+
+            (request_line, String::from_utf8(body).unwrap())
+        });
+
+        let mut observer = WebhookObserver::new(&format!("http://127.0.0.1:{}/events", port)).unwrap();
+        observer.on_lifecycle_event(&LifecycleEvent::Created { client_id: 7 });
+        drop(observer);
+
+        let (request_line, body) = server.join().unwrap();
+        assert_eq!(request_line, "POST /events HTTP/1.1\r\n");
+        assert_eq!(body, r#"{"event":"created","client_id":7}"#);
+    }
+
+    #[test]
+    fn test_hung_endpoint() {
+        // Accepts the connections and never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let _held: Vec<_> = listener.incoming().collect();
+        });
+
+        let limits = DeliveryLimits {
+            io_timeout: Duration::from_millis(100),
+            queue: 1,
+            shutdown: Duration::from_secs(5),
+            ..DeliveryLimits::default()
+        };
+        let mut observer = WebhookObserver::with_limits(&format!("http://127.0.0.1:{}/events", port), limits).unwrap();
+
+        for client_id in 0..10 {
+            observer.on_lifecycle_event(&LifecycleEvent::Created { client_id });
+        }
+
+        assert!(observer.dropped() > 0);
+
+        // Every waiting event times out instead of holding the end of the run
+        let start = Instant::now();
+        drop(observer);
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,26 @@
+use std::{ env, fs, process::Command };
+
+#[test]
+fn test_config_file_with_overrides() {
+    let dir = env::temp_dir();
+    let path = dir.join("transaction-engine-config.csv");
+    let config = dir.join("transaction-engine-config.toml");
+    fs::write(&path, "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,0.1234\n").unwrap();
+    fs::write(&config, "precision = 2\nrounding = \"truncate\"\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+            .arg("--config")
+            .arg(&config)
+            .args(args)
+            .arg(&path)
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap();
+
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(run(&[]), "client,available,held,total,locked\n1,9.87,0,9.87,false\n");
+    assert_eq!(run(&["--precision", "3"]), "client,available,held,total,locked\n1,9.876,0,9.876,false\n");
+}
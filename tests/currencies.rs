@@ -0,0 +1,61 @@
+use std::{ env, fs, process::Command };
+
+#[test]
+fn test_multi_currency_output() {
+    let path = env::temp_dir().join("transaction-engine-currencies.csv");
+    fs::write(
+        &path,
+        "type,client,tx,amount,currency\n\
+         deposit,1,1,1.5,\n\
+         deposit,1,2,2,USD\n\
+         withdrawal,1,3,3,USD\n\
+         deposit,2,4,5,USD\n\
+         dispute,2,4,,\n\
+         chargeback,2,4,,\n"
+    ).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(["--multi-currency", "--base-currency", "EUR"])
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,currency,available,held,total,locked\n\
+         1,EUR,1.5,0,1.5,false\n\
+         1,USD,2,0,2,false\n\
+         2,USD,0,0,0,true\n"
+    );
+}
+
+#[test]
+fn test_convert_with_rates() {
+    let dir = env::temp_dir();
+    let path = dir.join("transaction-engine-convert.csv");
+    let rates = dir.join("transaction-engine-convert-rates.csv");
+    fs::write(
+        &path,
+        "type,client,tx,amount,currency,to_currency\n\
+         deposit,1,1,100,,\n\
+         convert,1,2,40,EUR,USD\n\
+         convert,1,3,1,EUR,GBP\n"
+    ).unwrap();
+    fs::write(&rates, "from,to,rate,spread\nEUR,USD,1.0837,0.01\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(["--multi-currency", "--base-currency", "EUR", "--rates"])
+        .arg(&rates)
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,currency,available,held,total,locked\n\
+         1,EUR,60,0,60,false\n\
+         1,USD,42.9145,0,42.9145,false\n"
+    );
+}
@@ -0,0 +1,338 @@
+use std::{ env, fs, path::PathBuf, process::Command };
+
+fn write_input(name: &str) -> PathBuf {
+    let mut input = String::from("type,client,tx,amount,timestamp\n");
+
+    for tx in 1..=2000u32 {
+        let client = (tx * 7919) % 613;
+        let timestamp = 1_700_000_000 + tx * 60;
+
+        match tx % 10 {
+            0 => input.push_str(&format!("withdrawal,{},{},0.3333,{}\n", client, tx, timestamp)),
+            5 => input.push_str(&format!("dispute,{},{},,{}\n", client, tx - 1, timestamp)),
+            _ => input.push_str(&format!("deposit,{},{},{}.1234,{}\n", client, tx, tx % 97, timestamp)),
+        }
+    }
+
+    let path = env::temp_dir().join(name);
+    fs::write(&path, input).unwrap();
+    path
+}
+
+fn run(args: &[&str]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(args)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    output.stdout
+}
+
+#[test]
+fn test_identical_output_across_runs() {
+    let input = write_input("transaction-engine-determinism.csv");
+    let input = input.to_str().unwrap();
+
+    let first = run(&[input]);
+
+    for _ in 0..5 {
+        assert_eq!(run(&[input]), first);
+    }
+
+    let clients: Vec<u32> = String::from_utf8(first)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().parse().unwrap())
+        .collect();
+
+    let mut sorted = clients.clone();
+    sorted.sort_unstable();
+    assert_eq!(clients, sorted);
+}
+
+#[test]
+fn test_identical_reports_across_runs() {
+    let input = write_input("transaction-engine-determinism-reports.csv");
+    let input = input.to_str().unwrap();
+    let rollup = env::temp_dir().join("transaction-engine-determinism-rollup.csv");
+    let rollup = rollup.to_str().unwrap();
+    let dormancy = env::temp_dir().join("transaction-engine-determinism-dormancy.csv");
+    let dormancy = dormancy.to_str().unwrap();
+
+    let args = [
+        "--event-sourcing",
+        "--daily-rollup",
+        rollup,
+        "--dormant-after",
+        "0",
+        "--dormancy-report",
+        dormancy,
+        input,
+    ];
+
+    let first = (run(&args), fs::read(rollup).unwrap(), fs::read(dormancy).unwrap());
+
+    for _ in 0..3 {
+        assert_eq!((run(&args), fs::read(rollup).unwrap(), fs::read(dormancy).unwrap()), first);
+    }
+}
+
+#[test]
+fn test_identical_output_across_tunings() {
+    let input = write_input("transaction-engine-determinism-tunings.csv");
+    let input = input.to_str().unwrap();
+
+    let expected = run(&[input]);
+
+    let tunings = [
+        ["--reader-threads", "4", "--parser-threads", "1", "--shards", "1", "--batch-size", "1"],
+        ["--reader-threads", "1", "--parser-threads", "4", "--shards", "1", "--batch-size", "7"],
+        ["--reader-threads", "3", "--parser-threads", "2", "--shards", "1", "--batch-size", "50"],
+    ];
+
+    for tuning in tunings {
+        let mut args = tuning.to_vec();
+        args.push(input);
+
+        assert_eq!(run(&args), expected);
+    }
+
+    for writer_threads in ["2", "5"] {
+        assert_eq!(run(&["--writer-threads", writer_threads, input]), expected);
+    }
+
+    assert_eq!(run(&["--stream-output", input]), expected);
+
+    for parse_threads in ["1", "4"] {
+        assert_eq!(run(&["--parse-threads", parse_threads, "--batch-size", "7", input]), expected);
+    }
+
+    // Each client's transactions stay on one worker, in order
+    for workers in ["2", "7"] {
+        assert_eq!(run(&["--workers", workers, "--shards", "3", input]), expected);
+    }
+}
+
+// Every withdrawal spends the funds a transfer from a client of another shard just brought in
+fn write_transfers_input(name: &str) -> PathBuf {
+    let mut input = String::from("type,client,tx,amount,to_client\n");
+    let mut tx = 0;
+
+    for round in 0..200u32 {
+        let (from, to) = (round % 13, (round + 1) % 13);
+
+        input.push_str(&format!("deposit,{},{},10,\n", from, tx + 1));
+        input.push_str(&format!("transfer,{},{},10,{}\n", from, tx + 2, to));
+        input.push_str(&format!("withdrawal,{},{},10,\n", to, tx + 3));
+        // A reused id, the first of the two clients to reach the engine takes it
+        input.push_str(&format!("deposit,{},{},1,\n", (round + 5) % 13, tx + 3));
+        tx += 3;
+    }
+
+    let path = env::temp_dir().join(name);
+    fs::write(&path, input).unwrap();
+    path
+}
+
+#[test]
+fn test_identical_output_across_shards() {
+    let input = write_transfers_input("transaction-engine-determinism-shards.csv");
+    let input = input.to_str().unwrap();
+
+    let expected = run(&["--shards", "1", "--batch-size", "3", input]);
+
+    for shards in ["2", "4"] {
+        for _ in 0..3 {
+            assert_eq!(run(&["--shards", shards, "--batch-size", "3", input]), expected);
+        }
+    }
+}
+
+#[test]
+fn test_unsorted_output_has_the_same_accounts() {
+    let input = write_input("transaction-engine-determinism-unsorted.csv");
+    let input = input.to_str().unwrap();
+
+    let lines = |output: Vec<u8>| {
+        let mut lines: Vec<String> = String::from_utf8(output).unwrap().lines().map(String::from).collect();
+        lines.sort_unstable();
+        lines
+    };
+
+    let expected = lines(run(&[input]));
+
+    assert_eq!(lines(run(&["--unsorted", input])), expected);
+    assert_eq!(lines(run(&["--unsorted", "--workers", "3", input])), expected);
+}
+
+#[test]
+fn test_reconcile_command() {
+    let input = write_input("transaction-engine-reconcile.csv");
+    let input = input.to_str().unwrap();
+
+    let old = env::temp_dir().join("transaction-engine-reconcile-old.csv");
+    let new = env::temp_dir().join("transaction-engine-reconcile-new.csv");
+    let output = run(&[input]);
+    fs::write(&old, &output).unwrap();
+    fs::write(&new, run(&["--shards", "4", input])).unwrap();
+
+    let reconcile = |old: &PathBuf, new: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+            .arg("reconcile")
+            .args([old, new])
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap()
+    };
+
+    let matching = reconcile(&old, &new);
+
+    assert_eq!(matching.status.code(), Some(0));
+    assert!(matching.stdout.is_empty());
+
+    // The input has no chargebacks, no account is locked
+    let mut lines: Vec<String> = String::from_utf8(output).unwrap().lines().map(String::from).collect();
+    let client = lines[1].split(',').next().unwrap().to_string();
+    lines[1] = lines[1].replace(",false", ",true");
+    lines.push("9999,1,0,1,false".to_string());
+    fs::write(&new, lines.join("\n")).unwrap();
+
+    let differing = reconcile(&old, &new);
+
+    assert_eq!(differing.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(differing.stdout).unwrap(),
+        format!("client {}: locked false -> true\nclient 9999: only in the new report\n", client)
+    );
+}
+
+#[test]
+fn test_normalized_input_gives_the_same_accounts() {
+    let input = write_input("transaction-engine-normalize.csv");
+    let input = input.to_str().unwrap();
+
+    for name in ["transaction-engine-normalized.csv", "transaction-engine-normalized.jsonl"] {
+        let normalized = env::temp_dir().join(name);
+        let normalized = normalized.to_str().unwrap();
+
+        let accounts = run(&["--emit-normalized", normalized, input]);
+
+        assert_eq!(run(&[normalized]), accounts);
+    }
+}
+
+#[test]
+fn test_check_invariants_keeps_the_output() {
+    let input = write_input("transaction-engine-invariants.csv");
+    let input = input.to_str().unwrap();
+
+    assert_eq!(run(&["--check-invariants", "--allow-negative-balance", input]), run(&["--allow-negative-balance", input]));
+}
+
+#[test]
+fn test_resumed_run_gives_the_same_accounts() {
+    let input = write_input("transaction-engine-resume.csv");
+    let input = input.to_str().unwrap();
+
+    // Disputes deposits of the first file and settles them, the history has to be carried over
+    let deposits: Vec<(u32, u32)> = (3..2000u32).step_by(10).map(|tx| ((tx * 7919) % 613, tx)).collect();
+    let mut settle = String::from("type,client,tx,amount\n");
+
+    for (client, tx) in &deposits {
+        settle.push_str(&format!("dispute,{},{},\n", client, tx));
+    }
+
+    for (client, tx) in &deposits {
+        match tx % 20 {
+            3 => settle.push_str(&format!("chargeback,{},{},\n", client, tx)),
+            _ => settle.push_str(&format!("resolve,{},{},\n", client, tx)),
+        }
+    }
+
+    let second = env::temp_dir().join("transaction-engine-resume-settle.csv");
+    fs::write(&second, settle).unwrap();
+    let second = second.to_str().unwrap();
+
+    let accounts = run(&[input, second]);
+
+    // The last checkpoint is taken in the first file, then in the second one
+    for every in ["1500", "700"] {
+        let dir = env::temp_dir().join(format!("transaction-engine-resume-{}", every));
+        let dir = dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(dir);
+
+        assert_eq!(run(&["--checkpoint", dir, "--checkpoint-every", every, input, second]), accounts);
+        assert_eq!(run(&["--resume", dir, input, second]), accounts);
+    }
+}
+
+// Deposits reusing the id of another client's transaction and disputes of other clients'
+// transactions, the clients spread over the workers
+fn write_shared_ids_input(name: &str) -> PathBuf {
+    let mut input = String::from("type,client,tx,amount\n");
+
+    for tx in 20..=1000u32 {
+        let client = tx % 11;
+
+        match tx % 9 {
+            0 => input.push_str(&format!("deposit,{},{},5\n", client, tx - 4)),
+            3 => input.push_str(&format!("dispute,{},{},\n", client, tx - 1)),
+            6 => input.push_str(&format!("dispute,{},{},\n", client, tx - 11)),
+            7 => input.push_str(&format!("chargeback,{},{},\n", client, tx - 12)),
+            _ => input.push_str(&format!("deposit,{},{},{}.5\n", client, tx, tx % 13)),
+        }
+    }
+
+    let path = env::temp_dir().join(name);
+    fs::write(&path, input).unwrap();
+    path
+}
+
+#[test]
+fn test_identical_output_across_workers() {
+    let input = write_shared_ids_input("transaction-engine-determinism-workers.csv");
+    let input = input.to_str().unwrap();
+
+    let expected = run(&[input]);
+
+    for workers in ["1", "2", "4", "7"] {
+        assert_eq!(run(&["--workers", workers, input]), expected);
+    }
+
+    let transfers = env::temp_dir().join("transaction-engine-determinism-transfers.csv");
+    fs::write(
+        &transfers,
+        "type,client,tx,amount,to_client\n\
+         deposit,1,1,10,\n\
+         deposit,2,1,5,\n\
+         deposit,2,2,5,\n\
+         transfer,1,3,4,2\n\
+         dispute,2,1,,\n"
+    ).unwrap();
+    let transfers = transfers.to_str().unwrap();
+
+    let expected = run(&[transfers]);
+    assert_eq!(
+        String::from_utf8(expected.clone()).unwrap(),
+        "client,available,held,total,locked\n1,6,0,6,false\n2,9,0,9,false\n"
+    );
+    assert_eq!(run(&["--workers", "1", transfers]), expected);
+
+    // The clients of a transfer are moved to one worker first
+    for workers in ["2", "4"] {
+        assert_eq!(run(&["--workers", workers, transfers]), expected);
+    }
+
+    let chained = write_transfers_input("transaction-engine-determinism-workers-transfers.csv");
+    let chained = chained.to_str().unwrap();
+
+    let expected = run(&[chained]);
+
+    for workers in ["2", "4", "7"] {
+        assert_eq!(run(&["--workers", workers, chained]), expected);
+    }
+}
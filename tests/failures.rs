@@ -0,0 +1,80 @@
+use std::{ env, fs, process::Command };
+
+#[test]
+fn test_read_failure_writes_no_accounts() {
+    // A JSON Lines file that can't be read past its first line, it isn't UTF-8
+    let path = env::temp_dir().join("transaction-engine-failures.jsonl");
+    let mut input = b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1\"}\n".to_vec();
+    input.extend_from_slice(b"{\"type\":\"deposit\",\"client\":2,\"tx\":2,\"amount\":\"\xff\"}\n");
+    fs::write(&path, input).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_max_errors() {
+    let path = env::temp_dir().join("transaction-engine-max-errors.csv");
+    fs::write(&path, "type,client,tx,amount\ndeposit,1,1,1\ndeposit,x,2,1\ndeposit,1,3,y\ndeposit,1,4,1\n").unwrap();
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+            .arg(&path)
+            .args(args)
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--strict"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Could not parse the record at"));
+
+    assert_eq!(run(&["--max-errors", "1"]).status.code(), Some(2));
+
+    let output = run(&["--max-errors", "2"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "client,available,held,total,locked\n1,2,0,2,false\n");
+}
+
+#[test]
+fn test_detailed_exit_codes() {
+    let run = |name: &str, input: &str| {
+        let path = env::temp_dir().join(name);
+        fs::write(&path, input).unwrap();
+
+        Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+            .arg(&path)
+            .arg("--detailed-exit-codes")
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap()
+    };
+
+    let clean = run("transaction-engine-exit-clean.csv", "type,client,tx,amount\ndeposit,1,1,5\n");
+    assert_eq!(clean.status.code(), Some(0));
+
+    let rejected = run(
+        "transaction-engine-exit-rejected.csv",
+        "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,9\n"
+    );
+    assert_eq!(rejected.status.code(), Some(3));
+    assert!(
+        String::from_utf8_lossy(&rejected.stderr).contains(
+            "summary rows=2 applied=1 rejected=1 malformed=0 accounts=1 locked=0 reasons=INSUFFICIENT_FUNDS:1\n"
+        )
+    );
+
+    let malformed = run(
+        "transaction-engine-exit-malformed.csv",
+        "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,9\ndeposit,x,3,1\n"
+    );
+    assert_eq!(malformed.status.code(), Some(6));
+}
@@ -0,0 +1,30 @@
+use std::{ env, fs, process::Command };
+
+#[test]
+fn test_fee_summary_section() {
+    let dir = env::temp_dir();
+    let path = dir.join("transaction-engine-fees.csv");
+    let fees = dir.join("transaction-engine-fees-schedule.json");
+    fs::write(&path, "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\nwithdrawal,1,3,4\n").unwrap();
+    fs::write(&fees, r#"{"deposit": {"flat": "0.1"}, "withdrawal": {"flat": "0.5", "percent": "1"}}"#).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(["--fee-summary", "--fees"])
+        .arg(&fees)
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,5.36,0,5.36,false\n\
+         2,4.9,0,4.9,false\n\
+         \n\
+         type,count,amount\n\
+         deposit,2,0.2\n\
+         withdrawal,1,0.54\n\
+         total,3,0.74\n"
+    );
+}
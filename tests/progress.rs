@@ -0,0 +1,27 @@
+use std::{ env, fs, process::Command };
+
+#[test]
+fn test_summary_line() {
+    let path = env::temp_dir().join("transaction-engine-progress.csv");
+    fs::write(&path, "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,20\ndeposit,x,3,1\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .arg("--progress")
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // The malformed row is logged before them
+    let lines: Vec<&str> = stderr.lines().rev().take(2).collect();
+    let (summary, counts) = (lines[1], lines[0]);
+
+    assert!(output.status.success());
+    assert!(summary.starts_with("Processed 3 rows in "), "{}", summary);
+    assert!(summary.contains(": 1 applied, 1 rejected, 1 malformed, "), "{}", summary);
+    assert_eq!(
+        counts,
+        "summary rows=3 applied=1 rejected=1 malformed=1 accounts=1 locked=0 reasons=INSUFFICIENT_FUNDS:1,MALFORMED:1"
+    );
+}
@@ -0,0 +1,34 @@
+use std::{ env, fs, process::Command };
+
+#[test]
+fn test_risk_report_and_lock() {
+    let dir = env::temp_dir();
+    let path = dir.join("transaction-engine-risk.csv");
+    let report = dir.join("transaction-engine-risk-report.csv");
+    fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\ndeposit,2,2,5\ndeposit,1,3,2\ndeposit,2,4,1\n"
+    ).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(["--risk-threshold", "dispute_rate>0.5", "--risk-lock", "--risk-report"])
+        .arg(&report)
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    // The dispute locks client 1, its second deposit is rejected
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,0,10,10,true\n\
+         2,6,0,6,false\n"
+    );
+    assert_eq!(
+        fs::read_to_string(&report).unwrap(),
+        "client,deposits,disputes,chargebacks,dispute_rate,cycles,velocity,flags\n\
+         1,1,1,0,1,0,1,dispute_rate>0.5\n\
+         2,2,0,0,0,0,2,\n"
+    );
+}
@@ -0,0 +1,38 @@
+use std::{ env, fs, process::Command };
+
+#[test]
+fn test_precision_and_rounding() {
+    let path = env::temp_dir().join("transaction-engine-rounding.csv");
+    fs::write(&path, "type,client,tx,amount\ndeposit,1,1,1.005\ndeposit,2,2,1.015\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+            .args(args)
+            .arg(&path)
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(run(&[]), "client,available,held,total,locked\n1,1.005,0,1.005,false\n2,1.015,0,1.015,false\n");
+    assert_eq!(
+        run(&["--precision", "2"]),
+        "client,available,held,total,locked\n1,1.00,0,1.00,false\n2,1.02,0,1.02,false\n"
+    );
+    assert_eq!(
+        run(&["--precision", "2", "--normalize-amounts"]),
+        "client,available,held,total,locked\n1,1,0,1,false\n2,1.02,0,1.02,false\n"
+    );
+    assert_eq!(
+        run(&["--precision", "2", "--rounding", "half-up"]),
+        "client,available,held,total,locked\n1,1.01,0,1.01,false\n2,1.02,0,1.02,false\n"
+    );
+    assert_eq!(
+        run(&["--precision", "2", "--rounding", "truncate"]),
+        "client,available,held,total,locked\n1,1.00,0,1.00,false\n2,1.01,0,1.01,false\n"
+    );
+}
@@ -0,0 +1,35 @@
+use std::{ env, fs, process::Command };
+
+use transaction_engine::{ scenario::Scenario, Engine };
+
+#[test]
+fn test_scenarios() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scenarios");
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let scenario = Scenario::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert_eq!(scenario.run(&mut Engine::new()), vec![], "{}", path.display());
+    }
+}
+
+#[test]
+fn test_scenario_command() {
+    let path = env::temp_dir().join("transaction-engine-scenario.scenario");
+    fs::write(&path, "deposit 1 1 10 / expect 1 available=10\nwithdrawal 1 2 4\nexpect 1 available=5\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .arg("scenario")
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "line 3: expected client 1 available=5, got available=6\n");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,6,0,6,false\n"
+    );
+}
@@ -0,0 +1,62 @@
+use std::{ env, fs, path::PathBuf, process::{ Command, Output } };
+
+fn write(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn run(args: &[&str], input: &PathBuf) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(args)
+        .arg(input)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_shadow_config() {
+    let input = write(
+        "transaction-engine-shadow.csv",
+        "type,client,tx,amount,reason\ndeposit,1,1,10,\nadjustment,1,2,-1,FX-1\ndeposit,2,3,1.234,\n"
+    );
+    let config = write("transaction-engine-shadow.toml", "max_amount_scale = 2\n");
+    let report = env::temp_dir().join("transaction-engine-shadow-report.csv");
+    let config = config.to_str().unwrap();
+
+    // The candidate keeps --allow-adjustments and only differs by the amount scale
+    let output = run(
+        &["--allow-adjustments", "--shadow-config", config, "--shadow-report", report.to_str().unwrap()],
+        &input
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,9,0,9,false\n2,1.234,0,1.234,false\n"
+    );
+    assert_eq!(
+        fs::read_to_string(&report).unwrap(),
+        "tx,client,kind,primary,candidate\n\
+         3,2,outcome,OK,INVALID_AMOUNT\n\
+         3,2,balance,available=1.234 held=0 total=1.234 locked=false,missing\n\
+         ,2,balance,available=1.234 held=0 total=1.234 locked=false,missing\n"
+    );
+}
+
+#[test]
+fn test_shadow_config_invalid() {
+    let input = write("transaction-engine-shadow-invalid.csv", "type,client,tx,amount\ndeposit,1,1,10\n");
+    let config = write("transaction-engine-shadow-invalid.toml", "output = \"accounts.csv\"\n");
+    let valid = write("transaction-engine-shadow-valid.toml", "max_amount_scale = 2\n");
+
+    let output = run(&["--shadow-config", config.to_str().unwrap()], &input);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("output isn't an engine setting"));
+
+    for mode in [&["--workers", "2"][..], &["--multi-tenant"], &["--multi-currency", "--base-currency", "EUR"]] {
+        let output = run(&[mode, &["--shadow-config", valid.to_str().unwrap()]].concat(), &input);
+        assert_eq!(output.status.code(), Some(2), "{:?}", mode);
+    }
+}
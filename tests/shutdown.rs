@@ -0,0 +1,32 @@
+#![cfg(unix)]
+
+use std::{ io::Write, process::{ Command, Stdio }, thread, time::Duration };
+
+#[test]
+fn test_interrupted_run_writes_the_accounts() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(["--batch-size", "1", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .env_remove("RUST_LOG")
+        .spawn()
+        .unwrap();
+
+    // stdin stays open, the run only ends with the signal
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\n").unwrap();
+    stdin.flush().unwrap();
+
+    thread::sleep(Duration::from_millis(500));
+
+    let status = Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(130));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,10,0,10,false\n2,5,0,5,false\n"
+    );
+}
@@ -0,0 +1,82 @@
+#![cfg(unix)]
+
+use std::{
+    env,
+    fs,
+    io::{ BufRead, BufReader, Write },
+    net::TcpStream,
+    process::{ Command, Stdio },
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn test_listen_rejects_number_amounts() {
+    let address = "127.0.0.1:47170";
+    let child = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(["listen", "--listen", address, "--format", "json", "--strict-amounts"])
+        .stdout(Stdio::piped())
+        .env_remove("RUST_LOG")
+        .spawn()
+        .unwrap();
+
+    let stream = (0..50)
+        .find_map(|_| {
+            thread::sleep(Duration::from_millis(100));
+            TcpStream::connect(address).ok()
+        })
+        .expect("the listener didn't start");
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    writer
+        .write_all(
+            b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"0.10\"}\n\
+              {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":0.10}\n"
+        )
+        .unwrap();
+
+    let mut lines = vec![];
+
+    for _ in 0..2 {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        lines.push(line);
+    }
+
+    assert_eq!(lines[0], "ok\n");
+    assert!(lines[1].starts_with("MALFORMED line 2: amount isn't a string"), "{}", lines[1]);
+
+    drop(writer);
+
+    let status = Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "client,available,held,total,locked\n1,0.10,0,0.10,false\n");
+}
+
+#[test]
+fn test_file_rejects_number_amounts() {
+    let path = env::temp_dir().join("transaction-engine-strict-amounts.jsonl");
+    fs::write(
+        &path,
+        "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"0.10\"}\n\
+         {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":0.10}\n"
+    ).unwrap();
+
+    let run = |strict: bool| {
+        let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+            .args(strict.then_some("--strict-amounts"))
+            .arg(&path)
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap();
+
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(run(false), "client,available,held,total,locked\n1,0.20,0,0.20,false\n");
+    assert_eq!(run(true), "client,available,held,total,locked\n1,0.10,0,0.10,false\n");
+}
@@ -0,0 +1,51 @@
+use std::{ env, fs, process::Command };
+
+#[test]
+fn test_multi_tenant_output() {
+    let dir = env::temp_dir();
+    let path = dir.join("transaction-engine-tenants.csv");
+    let split = dir.join("transaction-engine-split-tenants");
+    let _ = fs::remove_dir_all(&split);
+    fs::write(
+        &path,
+        "type,client,tx,amount,tenant\n\
+         deposit,1,1,10,acme\n\
+         deposit,1,1,5,globex\n\
+         dispute,1,1,,globex\n\
+         chargeback,1,1,,globex\n\
+         deposit,2,2,1.5,\n\
+         withdrawal,1,2,3,acme\n"
+    ).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(["--multi-tenant", "--default-tenant", "internal", "--split-tenants"])
+        .arg(&split)
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "tenant,client,available,held,total,locked\n\
+         acme,1,7,0,7,false\n\
+         globex,1,0,0,0,true\n\
+         internal,2,1.5,0,1.5,false\n"
+    );
+    assert_eq!(
+        fs::read_to_string(split.join("acme.csv")).unwrap(),
+        "client,available,held,total,locked\n1,7,0,7,false\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-engine"))
+        .args(["--multi-tenant", "--tenant", "globex"])
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "tenant,client,available,held,total,locked\nglobex,1,0,0,0,true\n"
+    );
+}